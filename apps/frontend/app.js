@@ -47,7 +47,8 @@ const state = {
 const sampleDevices = [
   { id: 'peer-a', name: 'Aarav iPhone', addr: '192.168.1.12', status: 'online' },
   { id: 'peer-b', name: 'Meera MacBook', addr: '192.168.1.34', status: 'busy' },
-  { id: 'peer-c', name: 'Ravi Desktop', addr: '192.168.1.55', status: 'offline' }
+  { id: 'peer-c', name: 'Ravi Desktop', addr: '192.168.1.55', status: 'offline' },
+  { id: 'peer-d', name: 'Kiran Pixel', addr: 'fd00::2a', status: 'online' }
 ];
 
 const backendBase = (window.localStorage.getItem('p2pBackendBase') || 'http://127.0.0.1:8787').replace(/\/$/, '');
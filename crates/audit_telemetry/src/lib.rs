@@ -1,6 +1,8 @@
+use paths::AppPaths;
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AuditEvent {
@@ -21,10 +23,35 @@ impl Default for RetentionPolicy {
     }
 }
 
+/// One counter in a snapshot. Counters are namespaced `<subsystem>.<metric>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CounterSample {
+    pub name: String,
+    pub subsystem: String,
+    pub value: u64,
+    /// When counting started: first increment, or the last reset.
+    pub since_ms: u64,
+    pub updated_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CounterSnapshot {
+    pub taken_ms: u64,
+    /// Sorted by name.
+    pub counters: Vec<CounterSample>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CounterEntry {
+    value: u64,
+    since_ms: u64,
+    updated_ms: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct AuditTelemetry {
     events: Vec<AuditEvent>,
-    counters: HashMap<String, u64>,
+    counters: HashMap<String, CounterEntry>,
     retention: RetentionPolicy,
 }
 
@@ -46,18 +73,91 @@ impl AuditTelemetry {
 
     /// Increment a telemetry counter without payload contents.
     pub fn increment_counter(&mut self, metric: &str) {
-        let value = self.counters.entry(metric.to_string()).or_insert(0);
-        *value += 1;
+        let now = now_ms();
+        let entry = self
+            .counters
+            .entry(metric.to_string())
+            .or_insert(CounterEntry {
+                value: 0,
+                since_ms: now,
+                updated_ms: now,
+            });
+        entry.value += 1;
+        entry.updated_ms = now;
+    }
+
+    /// Increment `<subsystem>.<metric>`.
+    pub fn increment_subsystem_counter(&mut self, subsystem: &str, metric: &str) {
+        self.increment_counter(&format!("{subsystem}.{metric}"));
     }
 
     pub fn counter_value(&self, metric: &str) -> u64 {
-        *self.counters.get(metric).unwrap_or(&0)
+        self.counters.get(metric).map_or(0, |c| c.value)
+    }
+
+    pub fn counter_snapshot(&self) -> CounterSnapshot {
+        let mut counters: Vec<CounterSample> = self
+            .counters
+            .iter()
+            .map(|(name, entry)| CounterSample {
+                name: name.clone(),
+                subsystem: counter_subsystem(name).to_string(),
+                value: entry.value,
+                since_ms: entry.since_ms,
+                updated_ms: entry.updated_ms,
+            })
+            .collect();
+        counters.sort_by(|a, b| a.name.cmp(&b.name));
+        CounterSnapshot {
+            taken_ms: now_ms(),
+            counters,
+        }
+    }
+
+    /// Zero every counter, or only those in `subsystem`, returning how many were reset.
+    ///
+    /// Reset counters stay listed at zero so dashboards keep their series.
+    pub fn reset_counters(&mut self, subsystem: Option<&str>) -> usize {
+        let now = now_ms();
+        let mut reset = 0;
+        for (name, entry) in &mut self.counters {
+            if subsystem.is_some_and(|s| counter_subsystem(name) != s) {
+                continue;
+            }
+            *entry = CounterEntry {
+                value: 0,
+                since_ms: now,
+                updated_ms: now,
+            };
+            reset += 1;
+        }
+        reset
     }
 
     pub fn events(&self) -> &[AuditEvent] {
         &self.events
     }
 
+    /// Events whose comma-separated `tags` metadata includes `tag`, oldest first.
+    pub fn events_tagged(&self, tag: &str) -> Vec<&AuditEvent> {
+        self.events
+            .iter()
+            .filter(|event| {
+                event
+                    .metadata
+                    .get("tags")
+                    .is_some_and(|tags| tags.split(',').any(|t| t == tag))
+            })
+            .collect()
+    }
+
+    /// Export local logs to `audit.log` in the platform audit directory.
+    pub fn export_to_default_location(&self, paths: &AppPaths) -> Result<PathBuf, AuditError> {
+        let path = paths.audit_dir().join("audit.log");
+        self.export_events(&path)?;
+        Ok(path)
+    }
+
     /// Export local logs in line-oriented simple format.
     pub fn export_events(&self, path: impl AsRef<Path>) -> Result<(), AuditError> {
         let path = path.as_ref();
@@ -113,6 +213,19 @@ impl From<std::io::Error> for AuditError {
     }
 }
 
+/// Subsystem namespace of a counter: everything before the first `.`.
+pub fn counter_subsystem(name: &str) -> &str {
+    name.split_once('.')
+        .map_or(name, |(subsystem, _)| subsystem)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 pub fn redact_sensitive_metadata(metadata: &mut HashMap<String, String>) {
     const REDACT_KEYS: &[&str] = &[
         "file_name",
@@ -1,4 +1,4 @@
-use audit_telemetry::{AuditEvent, AuditTelemetry, RetentionPolicy};
+use audit_telemetry::{counter_subsystem, AuditEvent, AuditTelemetry, RetentionPolicy};
 use std::collections::HashMap;
 
 #[test]
@@ -33,6 +33,40 @@ fn counter_increments_without_payload() {
     assert_eq!(telemetry.counter_value("unknown.metric"), 0);
 }
 
+#[test]
+fn counter_snapshot_is_namespaced_and_reset_per_subsystem() {
+    let mut telemetry = AuditTelemetry::new(RetentionPolicy::default());
+    telemetry.increment_subsystem_counter("transfer", "completed");
+    telemetry.increment_counter("api.requests{route=/health,status=200}");
+    telemetry.increment_counter("api.requests{route=/health,status=200}");
+
+    let snapshot = telemetry.counter_snapshot();
+    let names: Vec<&str> = snapshot.counters.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "api.requests{route=/health,status=200}",
+            "transfer.completed"
+        ]
+    );
+    assert_eq!(snapshot.counters[0].subsystem, "api");
+    assert_eq!(snapshot.counters[0].value, 2);
+    assert!(snapshot.counters[0].since_ms <= snapshot.counters[0].updated_ms);
+    assert!(snapshot.counters[0].updated_ms <= snapshot.taken_ms);
+    assert_eq!(counter_subsystem("bare"), "bare");
+
+    assert_eq!(telemetry.reset_counters(Some("api")), 1);
+    assert_eq!(
+        telemetry.counter_value("api.requests{route=/health,status=200}"),
+        0
+    );
+    assert_eq!(telemetry.counter_value("transfer.completed"), 1);
+    assert_eq!(telemetry.counter_snapshot().counters.len(), 2);
+
+    assert_eq!(telemetry.reset_counters(None), 2);
+    assert_eq!(telemetry.counter_value("transfer.completed"), 0);
+}
+
 #[test]
 fn retention_keeps_latest_events_only() {
     let mut telemetry = AuditTelemetry::new(RetentionPolicy { max_events: 2 });
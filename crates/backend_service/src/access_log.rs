@@ -0,0 +1,89 @@
+use audit_telemetry::AuditTelemetry;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessLogConfig {
+    /// Mirror one out of every `sample_every` requests into telemetry counters (1 = all).
+    pub sample_every: u32,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self { sample_every: 1 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessLogEntry {
+    pub method: String,
+    pub path: String,
+    /// Route template the request matched, used as the low-cardinality metric label.
+    pub route: &'static str,
+    pub status: u16,
+    pub latency: Duration,
+    pub client_addr: Option<SocketAddr>,
+    pub principal: String,
+}
+
+impl AccessLogEntry {
+    /// Counter key in `api.requests{route,status}` form.
+    pub fn counter_name(&self) -> String {
+        format!(
+            "api.requests{{route={},status={}}}",
+            self.route, self.status
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct AccessLog {
+    config: AccessLogConfig,
+    seen: u64,
+    last_entry: Option<AccessLogEntry>,
+}
+
+impl AccessLog {
+    pub fn new(config: AccessLogConfig) -> Self {
+        Self {
+            config,
+            seen: 0,
+            last_entry: None,
+        }
+    }
+
+    /// Emits the entry through tracing and mirrors sampled entries into telemetry.
+    ///
+    /// Returns whether this entry was sampled into the counters.
+    pub fn record(&mut self, entry: AccessLogEntry, telemetry: &mut AuditTelemetry) -> bool {
+        let client = entry
+            .client_addr
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        tracing::info!(
+            target: "backend_service::access",
+            method = %entry.method,
+            path = %entry.path,
+            route = entry.route,
+            status = entry.status,
+            latency_us = entry.latency.as_micros() as u64,
+            client = %client,
+            principal = %entry.principal,
+            "request handled"
+        );
+
+        let sample_every = u64::from(self.config.sample_every.max(1));
+        let sampled = self.seen.is_multiple_of(sample_every);
+        self.seen += 1;
+        if sampled {
+            telemetry.increment_counter(&entry.counter_name());
+        }
+
+        self.last_entry = Some(entry);
+        sampled
+    }
+
+    pub fn last_entry(&self) -> Option<&AccessLogEntry> {
+        self.last_entry.as_ref()
+    }
+}
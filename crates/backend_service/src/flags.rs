@@ -0,0 +1,202 @@
+use paths::AppPaths;
+use std::collections::BTreeMap;
+use std::fs;
+
+/// Experimental subsystems that can be gated, with their shipped defaults.
+pub const KNOWN_FLAGS: &[(&str, bool)] = &[
+    ("delta_sync", false),
+    ("quic_transport", false),
+    ("swarm_mode", false),
+];
+
+/// Settings key prefix for flag entries (`flags.swarm_mode = true`).
+const FLAG_PREFIX: &str = "flags.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagSource {
+    Default,
+    Settings,
+    Runtime,
+    /// Set by an administrator profile; locked against settings and runtime changes.
+    AdminProfile,
+}
+
+impl FlagSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FlagSource::Default => "default",
+            FlagSource::Settings => "settings",
+            FlagSource::Runtime => "runtime",
+            FlagSource::AdminProfile => "admin_profile",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlagState {
+    pub name: &'static str,
+    pub enabled: bool,
+    pub source: FlagSource,
+}
+
+impl FlagState {
+    pub fn locked(&self) -> bool {
+        self.source == FlagSource::AdminProfile
+    }
+}
+
+/// A flag whose effective value changed; each one becomes an audit event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlagChange {
+    pub name: &'static str,
+    pub enabled: bool,
+    pub source: FlagSource,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlagError {
+    UnknownFlag,
+    Locked,
+    Io(String),
+}
+
+impl std::fmt::Display for FlagError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FlagError::UnknownFlag => write!(f, "unknown feature flag"),
+            FlagError::Locked => write!(f, "feature flag is locked by an admin profile"),
+            FlagError::Io(m) => write!(f, "io error: {m}"),
+        }
+    }
+}
+
+impl std::error::Error for FlagError {}
+
+impl From<std::io::Error> for FlagError {
+    fn from(value: std::io::Error) -> Self {
+        FlagError::Io(value.to_string())
+    }
+}
+
+/// Runtime registry of feature flags.
+///
+/// Unknown names are always reported as disabled, so a subsystem asking about a flag
+/// from a newer build falls back to the safe behaviour.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureFlags {
+    flags: BTreeMap<&'static str, FlagState>,
+}
+
+impl Default for FeatureFlags {
+    fn default() -> Self {
+        let flags = KNOWN_FLAGS
+            .iter()
+            .map(|&(name, enabled)| {
+                (
+                    name,
+                    FlagState {
+                        name,
+                        enabled,
+                        source: FlagSource::Default,
+                    },
+                )
+            })
+            .collect();
+        Self { flags }
+    }
+}
+
+impl FeatureFlags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defaults overlaid with `settings.conf`, then `admin.conf`, from the config directory.
+    ///
+    /// Missing files are treated as empty.
+    pub fn load(paths: &AppPaths) -> Result<Self, FlagError> {
+        let mut flags = Self::new();
+        for (path, source) in [
+            (paths.settings_file(), FlagSource::Settings),
+            (
+                paths.config_dir.join("admin.conf"),
+                FlagSource::AdminProfile,
+            ),
+        ] {
+            if path.is_file() {
+                flags.apply_profile(&fs::read_to_string(path)?, source);
+            }
+        }
+        Ok(flags)
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.flags.get(name).is_some_and(|f| f.enabled)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FlagState> {
+        self.flags.get(name)
+    }
+
+    /// All known flags in name order.
+    pub fn states(&self) -> impl Iterator<Item = &FlagState> {
+        self.flags.values()
+    }
+
+    /// Apply `flags.<name> = <bool>` lines from a settings or admin profile.
+    ///
+    /// Unknown flags, unparsable values and other settings keys are ignored. Non-admin
+    /// profiles cannot override admin-locked flags.
+    pub fn apply_profile(&mut self, contents: &str, source: FlagSource) -> Vec<FlagChange> {
+        let mut changes = Vec::new();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let Some(name) = key.trim().strip_prefix(FLAG_PREFIX) else {
+                continue;
+            };
+            let Some(enabled) = parse_bool(value.trim()) else {
+                continue;
+            };
+            if let Ok(Some(change)) = self.set(name, enabled, source) {
+                changes.push(change);
+            }
+        }
+        changes
+    }
+
+    /// Set a flag, returning the change if its effective value flipped.
+    pub fn set(
+        &mut self,
+        name: &str,
+        enabled: bool,
+        source: FlagSource,
+    ) -> Result<Option<FlagChange>, FlagError> {
+        let state = self.flags.get_mut(name).ok_or(FlagError::UnknownFlag)?;
+        if state.locked() && source != FlagSource::AdminProfile {
+            return Err(FlagError::Locked);
+        }
+
+        let changed = state.enabled != enabled;
+        state.enabled = enabled;
+        state.source = source;
+        Ok(changed.then_some(FlagChange {
+            name: state.name,
+            enabled,
+            source,
+        }))
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "on" | "yes" | "1" => Some(true),
+        "false" | "off" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
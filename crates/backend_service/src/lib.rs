@@ -1,3 +1,36 @@
+mod access_log;
+mod flags;
+mod offer_limits;
+
+pub use access_log::{AccessLog, AccessLogConfig, AccessLogEntry};
+pub use flags::{FeatureFlags, FlagChange, FlagError, FlagSource, FlagState, KNOWN_FLAGS};
+pub use offer_limits::{OfferAdmission, OfferLimitConfig, OfferRateLimiter};
+
+use audit_telemetry::{AuditEvent, AuditTelemetry, RetentionPolicy};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use crypto_envelope::{crypto_selftest, CryptoEnvelopeError};
+use desktop_ui::{
+    DesktopUiState, IncomingRequestModal, TransferItem, TransferState, UiError, UpdatePanelState,
+};
+use large_file_manager::{plan_mirror, DirectorySignature, MirrorAction, MirrorOptions};
+use paths::AppPaths;
+use profile::{export_profile, import_profile, ConflictPolicy, ExportOptions, ProfileError};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use transfer::{normalize_tags, CompletionReceipt, SessionParams, TransferError, TransferSession};
+
+/// Largest decoded chunk accepted by the upload endpoint unless configured otherwise.
+pub const MAX_UPLOAD_CHUNK_BYTES: usize = 4 * 1024 * 1024;
+
+/// Ids handed to transfers created through the API, shared by every route that creates
+/// them so no two requests in a process get the same id.
+static NEXT_TRANSFER_ID: AtomicU64 = AtomicU64::new(1_000);
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HttpResponse {
     pub status_line: &'static str,
@@ -8,85 +41,1119 @@ pub struct HttpResponse {
 impl HttpResponse {
     pub fn to_http_string(&self) -> String {
         format!(
-            "{}\r\nContent-Type: {}\r\nAccess-Control-Allow-Origin: *\r\nAccess-Control-Allow-Methods: GET, POST, OPTIONS\r\nAccess-Control-Allow-Headers: Content-Type\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            "{}\r\nContent-Type: {}\r\nAccess-Control-Allow-Origin: *\r\nAccess-Control-Allow-Methods: GET, POST, PUT, OPTIONS\r\nAccess-Control-Allow-Headers: Content-Type, Authorization\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
             self.status_line,
             self.content_type,
             self.body.len(),
             self.body
         )
     }
+
+    /// Numeric status code parsed from the status line (0 if malformed).
+    pub fn status_code(&self) -> u16 {
+        self.status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .unwrap_or(0)
+    }
 }
 
-pub fn route_request(request: &str) -> HttpResponse {
-    let (first_line, body) = split_request(request);
+#[derive(Debug, Clone, Default)]
+pub struct BackendConfig {
+    /// Bearer tokens accepted by the API, mapped to the principal they authenticate.
+    pub api_tokens: HashMap<String, String>,
+    /// Principals allowed to call admin endpoints such as the counter reset.
+    pub admin_principals: HashSet<String>,
+    pub access_log: AccessLogConfig,
+    pub feature_flags: FeatureFlags,
+    /// Platform directories; profile export/import is unavailable without them.
+    pub paths: Option<AppPaths>,
+    /// KDF settings for profile exports; `include_history` is taken from each request.
+    pub profile_export: ExportOptions,
+    pub offer_limits: OfferLimitConfig,
+    /// Cap on one uploaded chunk after base64 decoding; `None` uses [`MAX_UPLOAD_CHUNK_BYTES`].
+    pub max_upload_chunk_bytes: Option<usize>,
+    /// Directories the mirror planner may scan; both sides of a plan must resolve inside
+    /// one of them. Empty disables the endpoint.
+    pub mirror_roots: Vec<PathBuf>,
+}
 
-    if first_line.starts_with("OPTIONS ") {
-        return HttpResponse {
-            status_line: "HTTP/1.1 204 No Content",
-            content_type: "text/plain; charset=utf-8",
-            body: String::new(),
+impl BackendConfig {
+    /// Config for the service at `paths`, with feature flags loaded from its settings and
+    /// admin profiles.
+    pub fn from_paths(paths: AppPaths) -> Result<Self, FlagError> {
+        Ok(Self {
+            feature_flags: FeatureFlags::load(&paths)?,
+            paths: Some(paths),
+            ..Self::default()
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct BackendService {
+    config: BackendConfig,
+    access_log: AccessLog,
+    telemetry: AuditTelemetry,
+    ui: DesktopUiState,
+    flags: FeatureFlags,
+    sessions: HashMap<u64, SessionParams>,
+    offer_limiter: OfferRateLimiter,
+    transfers: HashMap<u64, TransferSession>,
+    /// Outcome of [`crypto_selftest`] at startup, reported by `/health`.
+    crypto_selftest: Result<(), CryptoEnvelopeError>,
+}
+
+impl BackendService {
+    pub fn new(config: BackendConfig) -> Self {
+        let selftest = crypto_selftest();
+        if let Err(error) = selftest {
+            tracing::error!(%error, "envelope crypto self-test failed");
+        }
+        Self {
+            access_log: AccessLog::new(config.access_log.clone()),
+            flags: config.feature_flags.clone(),
+            offer_limiter: OfferRateLimiter::new(config.offer_limits.clone()),
+            config,
+            telemetry: AuditTelemetry::new(RetentionPolicy::default()),
+            ui: DesktopUiState::new(),
+            sessions: HashMap::new(),
+            transfers: HashMap::new(),
+            crypto_selftest: selftest,
+        }
+    }
+
+    /// Routes one raw HTTP request and records it in the access log.
+    pub fn handle(&mut self, request: &str, client_addr: Option<SocketAddr>) -> HttpResponse {
+        let started = Instant::now();
+        let (route, response) = self
+            .dispatch_stateful(request)
+            .unwrap_or_else(|| dispatch(request));
+
+        let (first_line, _) = split_request(request);
+        let mut parts = first_line.split_whitespace();
+        let entry = AccessLogEntry {
+            method: parts.next().unwrap_or("-").to_string(),
+            path: parts.next().unwrap_or("-").to_string(),
+            route,
+            status: response.status_code(),
+            latency: started.elapsed(),
+            client_addr,
+            principal: self.principal_for(request),
         };
+        self.access_log.record(entry, &mut self.telemetry);
+
+        response
     }
 
-    if first_line.starts_with("GET /health ") {
-        return HttpResponse {
+    /// Whether the envelope ciphers gave their known answers when the service started.
+    pub fn crypto_selftest(&self) -> Result<(), CryptoEnvelopeError> {
+        self.crypto_selftest
+    }
+
+    pub fn access_log(&self) -> &AccessLog {
+        &self.access_log
+    }
+
+    pub fn telemetry(&self) -> &AuditTelemetry {
+        &self.telemetry
+    }
+
+    pub fn ui(&self) -> &DesktopUiState {
+        &self.ui
+    }
+
+    pub fn ui_mut(&mut self) -> &mut DesktopUiState {
+        &mut self.ui
+    }
+
+    pub fn flags(&self) -> &FeatureFlags {
+        &self.flags
+    }
+
+    /// Toggle a flag at runtime, auditing the change if the value flipped.
+    pub fn set_feature_flag(&mut self, name: &str, enabled: bool) -> Result<(), FlagError> {
+        if let Some(change) = self.flags.set(name, enabled, FlagSource::Runtime)? {
+            self.record_flag_change(change);
+        }
+        Ok(())
+    }
+
+    /// Re-read flags from the settings and admin profiles, auditing every flip.
+    pub fn reload_feature_flags(&mut self, paths: &AppPaths) -> Result<(), FlagError> {
+        let loaded = FeatureFlags::load(paths)?;
+        let changes: Vec<FlagChange> = loaded
+            .states()
+            .filter(|state| self.flags.is_enabled(state.name) != state.enabled)
+            .map(|state| FlagChange {
+                name: state.name,
+                enabled: state.enabled,
+                source: state.source,
+            })
+            .collect();
+        self.flags = loaded;
+        for change in changes {
+            self.record_flag_change(change);
+        }
+        Ok(())
+    }
+
+    fn record_flag_change(&mut self, change: FlagChange) {
+        let metadata = HashMap::from([
+            ("flag".to_string(), change.name.to_string()),
+            ("enabled".to_string(), change.enabled.to_string()),
+            ("source".to_string(), change.source.as_str().to_string()),
+        ]);
+        self.telemetry.record_event(AuditEvent {
+            timestamp_ms: now_ms(),
+            category: "feature_flags".to_string(),
+            action: "toggled".to_string(),
+            metadata,
+        });
+    }
+
+    /// Ingestion point for offers arriving from peers.
+    ///
+    /// Offers within the peer's rate limit open the incoming-request modal; the offer
+    /// that trips the limit mutes the peer and is audited, and later offers from a muted
+    /// peer are only counted.
+    pub fn receive_offer(&mut self, request: IncomingRequestModal, now: Instant) -> OfferAdmission {
+        let admission = self.offer_limiter.admit(&request.from_device_id, now);
+        match admission {
+            OfferAdmission::Accepted => self.ui.show_incoming_request(request),
+            OfferAdmission::Throttled { muted_for, strikes } => {
+                self.telemetry
+                    .increment_subsystem_counter("offers", "throttled");
+                let metadata = HashMap::from([
+                    ("peer_id".to_string(), request.from_device_id.clone()),
+                    ("mute_secs".to_string(), muted_for.as_secs().to_string()),
+                    ("strikes".to_string(), strikes.to_string()),
+                ]);
+                self.telemetry.record_event(AuditEvent {
+                    timestamp_ms: now_ms(),
+                    category: "offers".to_string(),
+                    action: "peer_muted".to_string(),
+                    metadata,
+                });
+            }
+            OfferAdmission::Suppressed => {
+                self.telemetry
+                    .increment_subsystem_counter("offers", "suppressed");
+            }
+        }
+        admission
+    }
+
+    /// Post one UI notification per peer whose offers were dropped since the last summary.
+    ///
+    /// Returns the number of notifications posted.
+    pub fn summarize_suppressed_offers(&mut self) -> usize {
+        let suppressed = self.offer_limiter.take_suppressed();
+        for (peer_id, count) in &suppressed {
+            self.ui.notify_suppressed_offers(peer_id, *count);
+        }
+        suppressed.len()
+    }
+
+    /// Keep a session's negotiated parameters for `GET /api/v1/sessions/{id}` and log
+    /// them as one audit event.
+    pub fn record_session_params(&mut self, session_id: u64, params: SessionParams) {
+        let mut metadata: HashMap<String, String> = params
+            .fields()
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect();
+        metadata.insert("session_id".to_string(), session_id.to_string());
+        self.telemetry.record_event(AuditEvent {
+            timestamp_ms: now_ms(),
+            category: "session".to_string(),
+            action: "negotiated".to_string(),
+            metadata,
+        });
+        self.sessions.insert(session_id, params);
+    }
+
+    pub fn session_params(&self, session_id: u64) -> Option<&SessionParams> {
+        self.sessions.get(&session_id)
+    }
+
+    /// Make a transfer session reachable through the chunk upload endpoints.
+    pub fn register_transfer(&mut self, session: TransferSession) {
+        self.transfers.insert(session.transfer_id(), session);
+    }
+
+    pub fn transfer(&self, transfer_id: u64) -> Option<&TransferSession> {
+        self.transfers.get(&transfer_id)
+    }
+
+    /// Routes backed by service state; `None` falls through to the stateless table.
+    fn dispatch_stateful(&mut self, request: &str) -> Option<(&'static str, HttpResponse)> {
+        let (first_line, body) = split_request(request);
+
+        if first_line.starts_with("GET /health ") {
+            return Some(("/health", health_response(self.crypto_selftest)));
+        }
+
+        if first_line.starts_with("POST /api/v1/transfers ") {
+            return Some(("/api/v1/transfers", self.create_transfer(body)));
+        }
+
+        if first_line.starts_with("POST /api/v1/mirror/plan ") {
+            let response = if self.principal_for(request) == "anonymous" {
+                authentication_required()
+            } else {
+                route_mirror_plan(body, &self.config.mirror_roots, &self.flags)
+            };
+            return Some(("/api/v1/mirror/plan", response));
+        }
+
+        if let Some(rest) = first_line.strip_prefix("GET /api/v1/transfers") {
+            if rest.starts_with(' ') || rest.starts_with('?') {
+                let tag = rest
+                    .split_whitespace()
+                    .next()
+                    .and_then(|query| query.strip_prefix("?tag="))
+                    .map(|tag| tag.trim().to_ascii_lowercase());
+                return Some(("/api/v1/transfers", self.list_transfers(tag.as_deref())));
+            }
+        }
+
+        if let Some((id, index)) = first_line
+            .strip_prefix("PUT /api/v1/transfers/")
+            .and_then(|rest| rest.split_whitespace().next())
+            .and_then(|path| path.split_once("/chunks/"))
+        {
+            let response = if self.principal_for(request) == "anonymous" {
+                authentication_required()
+            } else {
+                self.put_transfer_chunk(id, index, body)
+            };
+            return Some(("/api/v1/transfers/{id}/chunks/{index}", response));
+        }
+
+        if let Some(id) = first_line
+            .strip_prefix("GET /api/v1/transfers/")
+            .and_then(|rest| rest.split_whitespace().next())
+            .and_then(|path| path.strip_suffix("/missing"))
+        {
+            let response = if self.principal_for(request) == "anonymous" {
+                authentication_required()
+            } else {
+                self.missing_chunks(id)
+            };
+            return Some(("/api/v1/transfers/{id}/missing", response));
+        }
+
+        if first_line.starts_with("GET /api/v1/flags ") {
+            return Some(("/api/v1/flags", self.flags_status()));
+        }
+
+        if let Some(rest) = first_line.strip_prefix("GET /api/v1/sessions/") {
+            let id = rest.split_whitespace().next().unwrap_or("");
+            return Some(("/api/v1/sessions/{id}", self.session_status(id)));
+        }
+
+        if first_line.starts_with("GET /api/v1/audit/counters ") {
+            return Some(("/api/v1/audit/counters", self.counter_snapshot()));
+        }
+
+        if first_line.starts_with("POST /api/v1/audit/counters/reset ") {
+            let principal = self.principal_for(request);
+            return Some((
+                "/api/v1/audit/counters/reset",
+                self.reset_counters(&principal, body),
+            ));
+        }
+
+        if first_line.starts_with("POST /api/v1/settings/profile/export ") {
+            let response = if self.principal_for(request) == "anonymous" {
+                authentication_required()
+            } else {
+                self.profile_export(body)
+            };
+            return Some(("/api/v1/settings/profile/export", response));
+        }
+
+        if first_line.starts_with("POST /api/v1/settings/profile/import ") {
+            let response = if self.principal_for(request) == "anonymous" {
+                authentication_required()
+            } else {
+                self.profile_import(body)
+            };
+            return Some(("/api/v1/settings/profile/import", response));
+        }
+
+        if first_line.starts_with("GET /api/v1/update ") {
+            return Some(("/api/v1/update", self.update_status()));
+        }
+
+        if first_line.starts_with("POST /api/v1/update/apply ") {
+            let response = if self.principal_for(request) == "anonymous" {
+                authentication_required()
+            } else {
+                let result = self.ui.apply_update();
+                self.update_result(result)
+            };
+            return Some(("/api/v1/update/apply", response));
+        }
+
+        // Verifying and ready are not accepted here: only the installer's check of the
+        // downloaded package moves the panel into them.
+        if first_line.starts_with("POST /api/v1/update/state ") {
+            if self.principal_for(request) == "anonymous" {
+                return Some(("/api/v1/update/state", authentication_required()));
+            }
+            let Some(state) =
+                extract_json_string(body, "state").and_then(|s| UpdatePanelState::parse(&s))
+            else {
+                return Some((
+                    "/api/v1/update/state",
+                    HttpResponse {
+                        status_line: "HTTP/1.1 400 Bad Request",
+                        content_type: "application/json; charset=utf-8",
+                        body: "{\"error\":\"invalid_state\"}".to_string(),
+                    },
+                ));
+            };
+            let result = self.ui.advance_update(state);
+            return Some(("/api/v1/update/state", self.update_result(result)));
+        }
+
+        None
+    }
+
+    fn session_status(&self, id: &str) -> HttpResponse {
+        let Ok(session_id) = id.parse::<u64>() else {
+            return HttpResponse {
+                status_line: "HTTP/1.1 400 Bad Request",
+                content_type: "application/json; charset=utf-8",
+                body: "{\"error\":\"invalid_session_id\"}".to_string(),
+            };
+        };
+        let Some(params) = self.sessions.get(&session_id) else {
+            return HttpResponse {
+                status_line: "HTTP/1.1 404 Not Found",
+                content_type: "application/json; charset=utf-8",
+                body: "{\"error\":\"session_not_found\"}".to_string(),
+            };
+        };
+
+        let params = params
+            .fields()
+            .into_iter()
+            .map(|(key, value)| match key {
+                "protocol_version" | "chunk_size" => format!("\"{key}\":{value}"),
+                _ => format!("\"{key}\":\"{}\"", escape_json(&value)),
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        HttpResponse {
             status_line: "HTTP/1.1 200 OK",
             content_type: "application/json; charset=utf-8",
-            body: "{\"status\":\"ok\"}".to_string(),
+            body: format!("{{\"session_id\":{session_id},\"params\":{{{params}}}}}"),
+        }
+    }
+
+    /// Queue a transfer on the dashboard and audit its creation, tags included, so
+    /// history and audit events can be correlated by label.
+    fn create_transfer(&mut self, body: &str) -> HttpResponse {
+        // Skip ids already taken by sessions registered directly.
+        let transfer_id = loop {
+            let id = NEXT_TRANSFER_ID.fetch_add(1, Ordering::Relaxed);
+            if !self.transfers.contains_key(&id)
+                && !self.ui.transfers().iter().any(|t| t.transfer_id == id)
+            {
+                break id;
+            }
+        };
+        let transfer = match NewTransfer::parse(body, transfer_id) {
+            Ok(transfer) => transfer,
+            Err(response) => return response,
         };
+        self.ui.add_transfer(TransferItem {
+            transfer_id: transfer.transfer_id,
+            target_device_id: transfer.receiver_ids.join(","),
+            file_name: transfer.file_name.clone(),
+            progress_percent: 0,
+            state: TransferState::Queued,
+            tags: transfer.tags.clone(),
+        });
+        let metadata = HashMap::from([
+            ("transfer_id".to_string(), transfer.transfer_id.to_string()),
+            ("receiver_ids".to_string(), transfer.receiver_ids.join(",")),
+            ("tags".to_string(), transfer.tags.join(",")),
+        ]);
+        self.telemetry.record_event(AuditEvent {
+            timestamp_ms: now_ms(),
+            category: "transfer".to_string(),
+            action: "created".to_string(),
+            metadata,
+        });
+        transfer.created_response()
     }
 
-    if first_line.starts_with("GET /api/v1/discovery/devices ") {
-        return HttpResponse {
+    /// Mark a transfer delivered once the caller has verified its receipt, and audit the
+    /// receipt under the tags the transfer was created with.
+    pub fn record_receipt(&mut self, receipt: &CompletionReceipt) -> Result<(), UiError> {
+        let tags = self
+            .ui
+            .transfers()
+            .into_iter()
+            .find(|t| t.transfer_id == receipt.transfer_id)
+            .map(|t| t.tags.join(","))
+            .ok_or(UiError::TransferNotFound)?;
+        self.ui
+            .set_transfer_state(receipt.transfer_id, TransferState::Completed)?;
+        self.ui.update_transfer_progress(receipt.transfer_id, 100)?;
+        let file_sha256: String = receipt
+            .file_sha256
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        let metadata = HashMap::from([
+            ("transfer_id".to_string(), receipt.transfer_id.to_string()),
+            ("total_chunks".to_string(), receipt.total_chunks.to_string()),
+            ("file_sha256".to_string(), file_sha256),
+            ("tags".to_string(), tags),
+        ]);
+        self.telemetry.record_event(AuditEvent {
+            timestamp_ms: now_ms(),
+            category: "transfer".to_string(),
+            action: "delivered".to_string(),
+            metadata,
+        });
+        Ok(())
+    }
+
+    fn list_transfers(&self, tag: Option<&str>) -> HttpResponse {
+        let items = match tag {
+            Some(tag) => self.ui.transfers_tagged(tag),
+            None => self.ui.transfers(),
+        };
+        let transfers = items
+            .iter()
+            .map(|t| {
+                format!(
+                    "{{\"transfer_id\":{},\"file_name\":\"{}\",\"target_device_id\":\"{}\",\"progress_percent\":{},\"state\":\"{}\",\"tags\":{}}}",
+                    t.transfer_id,
+                    escape_json(&t.file_name),
+                    escape_json(&t.target_device_id),
+                    t.progress_percent,
+                    transfer_state_str(&t.state),
+                    json_string_array(&t.tags)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        HttpResponse {
             status_line: "HTTP/1.1 200 OK",
             content_type: "application/json; charset=utf-8",
-            body: discovery_devices_json(),
+            body: format!("{{\"transfers\":[{transfers}]}}"),
+        }
+    }
+
+    fn put_transfer_chunk(&mut self, id: &str, index: &str, body: &str) -> HttpResponse {
+        let (Ok(transfer_id), Ok(chunk_index)) = (id.parse::<u64>(), index.parse::<u32>()) else {
+            return bad_request("invalid_chunk_path");
         };
+        let limit = self
+            .config
+            .max_upload_chunk_bytes
+            .unwrap_or(MAX_UPLOAD_CHUNK_BYTES);
+        let body = body.trim();
+        // Reject oversized bodies before decoding so a huge upload is never buffered twice.
+        if body.len() / 4 * 3 > limit + 3 {
+            return payload_too_large();
+        }
+        let Ok(payload) = BASE64.decode(body) else {
+            return bad_request("invalid_base64");
+        };
+        if payload.len() > limit {
+            return payload_too_large();
+        }
+        let Some(session) = self.transfers.get_mut(&transfer_id) else {
+            return transfer_not_found();
+        };
+        match session.put_chunk(chunk_index, &payload) {
+            Ok(()) => HttpResponse {
+                status_line: "HTTP/1.1 200 OK",
+                content_type: "application/json; charset=utf-8",
+                body: format!(
+                    "{{\"transfer_id\":{transfer_id},\"chunk_index\":{chunk_index},\"missing_count\":{}}}",
+                    session.missing_chunks().len()
+                ),
+            },
+            Err(TransferError::ChunkOutOfRange) => bad_request("chunk_out_of_range"),
+            Err(TransferError::DuplicateChunk) => HttpResponse {
+                status_line: "HTTP/1.1 409 Conflict",
+                content_type: "application/json; charset=utf-8",
+                body: "{\"error\":\"chunk_already_uploaded\"}".to_string(),
+            },
+            Err(_) => bad_request("chunk_length_mismatch"),
+        }
     }
 
-    if first_line.starts_with("POST /api/v1/transfers ") {
-        return route_create_transfer(body);
+    fn missing_chunks(&self, id: &str) -> HttpResponse {
+        let Ok(transfer_id) = id.parse::<u64>() else {
+            return bad_request("invalid_transfer_id");
+        };
+        let Some(session) = self.transfers.get(&transfer_id) else {
+            return transfer_not_found();
+        };
+        let missing = session
+            .missing_chunks()
+            .iter()
+            .map(u32::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        HttpResponse {
+            status_line: "HTTP/1.1 200 OK",
+            content_type: "application/json; charset=utf-8",
+            body: format!(
+                "{{\"transfer_id\":{transfer_id},\"total_chunks\":{},\"missing\":[{missing}]}}",
+                session.total_chunks()
+            ),
+        }
+    }
+
+    fn counter_snapshot(&self) -> HttpResponse {
+        let snapshot = self.telemetry.counter_snapshot();
+        let counters = snapshot
+            .counters
+            .iter()
+            .map(|c| {
+                format!(
+                    "{{\"name\":\"{}\",\"subsystem\":\"{}\",\"value\":{},\"since_ms\":{},\"updated_ms\":{}}}",
+                    escape_json(&c.name),
+                    escape_json(&c.subsystem),
+                    c.value,
+                    c.since_ms,
+                    c.updated_ms
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        HttpResponse {
+            status_line: "HTTP/1.1 200 OK",
+            content_type: "application/json; charset=utf-8",
+            body: format!(
+                "{{\"taken_ms\":{},\"counters\":[{counters}]}}",
+                snapshot.taken_ms
+            ),
+        }
+    }
+
+    /// Admin-only; an optional `subsystem` limits the reset to one namespace.
+    fn reset_counters(&mut self, principal: &str, body: &str) -> HttpResponse {
+        if principal == "anonymous" {
+            return authentication_required();
+        }
+        if !self.config.admin_principals.contains(principal) {
+            return HttpResponse {
+                status_line: "HTTP/1.1 403 Forbidden",
+                content_type: "application/json; charset=utf-8",
+                body: "{\"error\":\"admin_required\"}".to_string(),
+            };
+        }
+
+        let subsystem = extract_json_string(body, "subsystem").filter(|s| !s.is_empty());
+        let reset = self.telemetry.reset_counters(subsystem.as_deref());
+        let scope = subsystem.as_deref().unwrap_or("all");
+        let metadata = HashMap::from([
+            ("principal".to_string(), principal.to_string()),
+            ("subsystem".to_string(), scope.to_string()),
+            ("counters".to_string(), reset.to_string()),
+        ]);
+        self.telemetry.record_event(AuditEvent {
+            timestamp_ms: now_ms(),
+            category: "audit".to_string(),
+            action: "counters_reset".to_string(),
+            metadata,
+        });
+
+        HttpResponse {
+            status_line: "HTTP/1.1 200 OK",
+            content_type: "application/json; charset=utf-8",
+            body: format!(
+                "{{\"reset\":{reset},\"subsystem\":\"{}\"}}",
+                escape_json(scope)
+            ),
+        }
+    }
+
+    fn profile_export(&self, body: &str) -> HttpResponse {
+        let Some(paths) = &self.config.paths else {
+            return profile_unavailable();
+        };
+        let (Some(passphrase), Some(path)) = (
+            extract_json_string(body, "passphrase").filter(|p| !p.is_empty()),
+            extract_json_string(body, "path"),
+        ) else {
+            return HttpResponse {
+                status_line: "HTTP/1.1 400 Bad Request",
+                content_type: "application/json; charset=utf-8",
+                body: "{\"error\":\"passphrase_and_path_required\"}".to_string(),
+            };
+        };
+        if let Err(e) = std::fs::create_dir_all(paths.profile_exports_dir()) {
+            return profile_error(&e.into());
+        }
+        let Some(bundle) = profile_bundle_path(paths, &path) else {
+            return path_outside_profile_exports();
+        };
+        let options = ExportOptions {
+            include_history: extract_json_bool(body, "include_history").unwrap_or(false),
+            ..self.config.profile_export
+        };
+
+        match export_profile(paths, &passphrase, &bundle, options) {
+            Ok(()) => HttpResponse {
+                status_line: "HTTP/1.1 200 OK",
+                content_type: "application/json; charset=utf-8",
+                body: format!(
+                    "{{\"exported\":true,\"path\":\"{}\",\"include_history\":{}}}",
+                    escape_json(&path),
+                    options.include_history
+                ),
+            },
+            Err(e) => profile_error(&e),
+        }
+    }
+
+    fn profile_import(&self, body: &str) -> HttpResponse {
+        let Some(paths) = &self.config.paths else {
+            return profile_unavailable();
+        };
+        let (Some(passphrase), Some(path)) = (
+            extract_json_string(body, "passphrase"),
+            extract_json_string(body, "path"),
+        ) else {
+            return HttpResponse {
+                status_line: "HTTP/1.1 400 Bad Request",
+                content_type: "application/json; charset=utf-8",
+                body: "{\"error\":\"passphrase_and_path_required\"}".to_string(),
+            };
+        };
+        let Some(bundle) = profile_bundle_path(paths, &path) else {
+            return path_outside_profile_exports();
+        };
+        let policy = if extract_json_bool(body, "replace").unwrap_or(false) {
+            ConflictPolicy::Replace
+        } else {
+            ConflictPolicy::KeepExisting
+        };
+
+        match import_profile(paths, &passphrase, &bundle, policy) {
+            Ok(report) => HttpResponse {
+                status_line: "HTTP/1.1 200 OK",
+                content_type: "application/json; charset=utf-8",
+                body: format!(
+                    "{{\"identity\":\"{}\",\"settings\":\"{}\",\"history\":\"{}\",\"peers_added\":{},\"peer_conflicts\":{}}}",
+                    report.identity.as_str(),
+                    report.settings.as_str(),
+                    report.history.as_str(),
+                    report.peers_added,
+                    report.peer_conflicts
+                ),
+            },
+            Err(e) => profile_error(&e),
+        }
+    }
+
+    fn flags_status(&self) -> HttpResponse {
+        let flags = self
+            .flags
+            .states()
+            .map(|state| {
+                format!(
+                    "{{\"name\":\"{}\",\"enabled\":{},\"source\":\"{}\",\"locked\":{}}}",
+                    state.name,
+                    state.enabled,
+                    state.source.as_str(),
+                    state.locked()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        HttpResponse {
+            status_line: "HTTP/1.1 200 OK",
+            content_type: "application/json; charset=utf-8",
+            body: format!("{{\"flags\":[{flags}]}}"),
+        }
+    }
+
+    fn update_status(&self) -> HttpResponse {
+        let body = match self.ui.update_panel() {
+            Some(panel) => format!(
+                "{{\"state\":\"{}\",\"current_version\":\"{}\",\"candidate_version\":\"{}\",\"changelog_url\":\"{}\"}}",
+                panel.state.as_str(),
+                escape_json(&panel.current_version),
+                escape_json(&panel.candidate_version),
+                escape_json(&panel.changelog_url)
+            ),
+            None => "{\"state\":\"none\"}".to_string(),
+        };
+        HttpResponse {
+            status_line: "HTTP/1.1 200 OK",
+            content_type: "application/json; charset=utf-8",
+            body,
+        }
+    }
+
+    fn update_result(&self, result: Result<(), UiError>) -> HttpResponse {
+        match result {
+            Ok(()) => self.update_status(),
+            Err(UiError::NoUpdateAvailable) => HttpResponse {
+                status_line: "HTTP/1.1 404 Not Found",
+                content_type: "application/json; charset=utf-8",
+                body: "{\"error\":\"no_update_available\"}".to_string(),
+            },
+            Err(_) => HttpResponse {
+                status_line: "HTTP/1.1 409 Conflict",
+                content_type: "application/json; charset=utf-8",
+                body: "{\"error\":\"invalid_transition\"}".to_string(),
+            },
+        }
+    }
+
+    fn principal_for(&self, request: &str) -> String {
+        header_value(request, "authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|token| self.config.api_tokens.get(token.trim()))
+            .cloned()
+            .unwrap_or_else(|| "anonymous".to_string())
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn bad_request(error: &str) -> HttpResponse {
+    HttpResponse {
+        status_line: "HTTP/1.1 400 Bad Request",
+        content_type: "application/json; charset=utf-8",
+        body: format!("{{\"error\":\"{error}\"}}"),
+    }
+}
+
+fn authentication_required() -> HttpResponse {
+    HttpResponse {
+        status_line: "HTTP/1.1 401 Unauthorized",
+        content_type: "application/json; charset=utf-8",
+        body: "{\"error\":\"authentication_required\"}".to_string(),
     }
+}
 
+fn payload_too_large() -> HttpResponse {
+    HttpResponse {
+        status_line: "HTTP/1.1 413 Payload Too Large",
+        content_type: "application/json; charset=utf-8",
+        body: "{\"error\":\"chunk_too_large\"}".to_string(),
+    }
+}
+
+fn transfer_not_found() -> HttpResponse {
     HttpResponse {
         status_line: "HTTP/1.1 404 Not Found",
         content_type: "application/json; charset=utf-8",
-        body: "{\"error\":\"not_found\"}".to_string(),
+        body: "{\"error\":\"transfer_not_found\"}".to_string(),
+    }
+}
+
+fn profile_unavailable() -> HttpResponse {
+    HttpResponse {
+        status_line: "HTTP/1.1 503 Service Unavailable",
+        content_type: "application/json; charset=utf-8",
+        body: "{\"error\":\"profile_storage_unconfigured\"}".to_string(),
+    }
+}
+
+/// 503 when the crypto self-test failed: the service runs, but must not be trusted
+/// to seal transfers.
+fn health_response(selftest: Result<(), CryptoEnvelopeError>) -> HttpResponse {
+    match selftest {
+        Ok(()) => HttpResponse {
+            status_line: "HTTP/1.1 200 OK",
+            content_type: "application/json; charset=utf-8",
+            body: "{\"status\":\"ok\",\"crypto_selftest\":\"ok\"}".to_string(),
+        },
+        Err(error) => HttpResponse {
+            status_line: "HTTP/1.1 503 Service Unavailable",
+            content_type: "application/json; charset=utf-8",
+            body: format!(
+                "{{\"status\":\"degraded\",\"crypto_selftest\":\"{}\"}}",
+                escape_json(&error.to_string())
+            ),
+        },
+    }
+}
+
+/// `path` as a bundle in the profile exports directory: relative paths are taken from
+/// there, and `..` or an absolute path outside it is refused. As with the mirror roots,
+/// the directory the bundle lands in is checked with symlinks followed.
+fn profile_bundle_path(paths: &AppPaths, path: &str) -> Option<PathBuf> {
+    let dir = paths.profile_exports_dir();
+    let bundle = dir.join(path);
+    if bundle.components().any(|c| c == Component::ParentDir) || !bundle.starts_with(&dir) {
+        return None;
+    }
+    within_roots(bundle.parent()?, std::slice::from_ref(&dir)).then_some(bundle)
+}
+
+fn path_outside_profile_exports() -> HttpResponse {
+    HttpResponse {
+        status_line: "HTTP/1.1 403 Forbidden",
+        content_type: "application/json; charset=utf-8",
+        body: "{\"error\":\"path_outside_profile_exports\"}".to_string(),
+    }
+}
+
+fn profile_error(error: &ProfileError) -> HttpResponse {
+    match error {
+        ProfileError::WrongPassphrase => HttpResponse {
+            status_line: "HTTP/1.1 403 Forbidden",
+            content_type: "application/json; charset=utf-8",
+            body: "{\"error\":\"wrong_passphrase\"}".to_string(),
+        },
+        e => HttpResponse {
+            status_line: "HTTP/1.1 422 Unprocessable Entity",
+            content_type: "application/json; charset=utf-8",
+            body: format!("{{\"error\":\"{}\"}}", escape_json(&e.to_string())),
+        },
+    }
+}
+
+/// Stateless routing for callers without a [`BackendService`]; `/health` reports a
+/// self-test run once per process.
+pub fn route_request(request: &str) -> HttpResponse {
+    static SELFTEST: OnceLock<Result<(), CryptoEnvelopeError>> = OnceLock::new();
+    let (first_line, _) = split_request(request);
+    if first_line.starts_with("GET /health ") {
+        return health_response(*SELFTEST.get_or_init(crypto_selftest));
+    }
+    dispatch(request).1
+}
+
+/// Routes a request and returns the matched route template alongside the response.
+///
+/// The template (e.g. `/api/v1/transfers`) is what access logging and metrics key on,
+/// so per-resource ids never explode counter cardinality.
+pub(crate) fn dispatch(request: &str) -> (&'static str, HttpResponse) {
+    let (first_line, body) = split_request(request);
+
+    if first_line.starts_with("OPTIONS ") {
+        return (
+            "OPTIONS *",
+            HttpResponse {
+                status_line: "HTTP/1.1 204 No Content",
+                content_type: "text/plain; charset=utf-8",
+                body: String::new(),
+            },
+        );
+    }
+
+    if first_line.starts_with("GET /api/v1/discovery/devices ") {
+        return (
+            "/api/v1/discovery/devices",
+            HttpResponse {
+                status_line: "HTTP/1.1 200 OK",
+                content_type: "application/json; charset=utf-8",
+                body: discovery_devices_json(),
+            },
+        );
+    }
+
+    if first_line.starts_with("POST /api/v1/transfers ") {
+        return ("/api/v1/transfers", route_create_transfer(body));
+    }
+
+    (
+        "unmatched",
+        HttpResponse {
+            status_line: "HTTP/1.1 404 Not Found",
+            content_type: "application/json; charset=utf-8",
+            body: "{\"error\":\"not_found\"}".to_string(),
+        },
+    )
+}
+
+/// A validated `POST /api/v1/transfers` request body.
+struct NewTransfer {
+    transfer_id: u64,
+    file_name: String,
+    receiver_ids: Vec<String>,
+    tags: Vec<String>,
+}
+
+impl NewTransfer {
+    fn parse(body: &str, transfer_id: u64) -> Result<Self, HttpResponse> {
+        let file_name =
+            extract_json_string(body, "file_name").unwrap_or_else(|| "unknown.bin".to_string());
+        let receiver_ids = extract_json_string_array(body, "receiver_ids").unwrap_or_default();
+
+        if receiver_ids.is_empty() {
+            return Err(bad_request("receiver_ids_required"));
+        }
+        let tags = normalize_tags(extract_json_string_array(body, "tags").unwrap_or_default())
+            .map_err(|_| bad_request("invalid_tags"))?;
+
+        Ok(Self {
+            transfer_id,
+            file_name,
+            receiver_ids,
+            tags,
+        })
+    }
+
+    fn created_response(&self) -> HttpResponse {
+        HttpResponse {
+            status_line: "HTTP/1.1 201 Created",
+            content_type: "application/json; charset=utf-8",
+            body: format!(
+                "{{\"transfer_id\":{},\"status\":\"queued\",\"file_name\":\"{}\",\"receiver_ids\":{},\"tags\":{}}}",
+                self.transfer_id,
+                escape_json(&self.file_name),
+                json_string_array(&self.receiver_ids),
+                json_string_array(&self.tags)
+            ),
+        }
     }
 }
 
 fn route_create_transfer(body: &str) -> HttpResponse {
-    let file_name =
-        extract_json_string(body, "file_name").unwrap_or_else(|| "unknown.bin".to_string());
-    let receiver_ids = extract_json_string_array(body, "receiver_ids").unwrap_or_default();
+    match NewTransfer::parse(body, NEXT_TRANSFER_ID.fetch_add(1, Ordering::Relaxed)) {
+        Ok(transfer) => transfer.created_response(),
+        Err(response) => response,
+    }
+}
+
+fn json_string_array(values: &[String]) -> String {
+    let items = values
+        .iter()
+        .map(|v| format!("\"{}\"", escape_json(v)))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("[{items}]")
+}
+
+fn transfer_state_str(state: &TransferState) -> &'static str {
+    match state {
+        TransferState::Queued => "queued",
+        TransferState::InProgress => "in_progress",
+        TransferState::Completed => "completed",
+        TransferState::Failed => "failed",
+    }
+}
+
+const MIRROR_CHUNK_SIZE: usize = 64 * 1024;
 
-    if receiver_ids.is_empty() {
+/// Dry-run only: reports what mirroring `source` onto `destination` would change.
+///
+/// Both paths must resolve, symlinks followed, inside one of `roots`. The planner sends
+/// only changed chunks, so it is off unless the `delta_sync` flag is on.
+fn route_mirror_plan(body: &str, roots: &[PathBuf], flags: &FeatureFlags) -> HttpResponse {
+    if !flags.is_enabled("delta_sync") {
+        return HttpResponse {
+            status_line: "HTTP/1.1 404 Not Found",
+            content_type: "application/json; charset=utf-8",
+            body: "{\"error\":\"feature_disabled\",\"flag\":\"delta_sync\"}".to_string(),
+        };
+    }
+    let (Some(source), Some(destination)) = (
+        extract_json_string(body, "source"),
+        extract_json_string(body, "destination"),
+    ) else {
         return HttpResponse {
             status_line: "HTTP/1.1 400 Bad Request",
             content_type: "application/json; charset=utf-8",
-            body: "{\"error\":\"receiver_ids_required\"}".to_string(),
+            body: "{\"error\":\"source_and_destination_required\"}".to_string(),
+        };
+    };
+    if !within_roots(Path::new(&source), roots) || !within_roots(Path::new(&destination), roots) {
+        return HttpResponse {
+            status_line: "HTTP/1.1 403 Forbidden",
+            content_type: "application/json; charset=utf-8",
+            body: "{\"error\":\"path_outside_mirror_roots\"}".to_string(),
         };
     }
+    let options = MirrorOptions {
+        delete_extraneous: extract_json_bool(body, "delete_extraneous").unwrap_or(false),
+    };
+
+    let plan = DirectorySignature::scan(&source, MIRROR_CHUNK_SIZE)
+        .and_then(|src| {
+            Ok((
+                src,
+                DirectorySignature::scan(&destination, MIRROR_CHUNK_SIZE)?,
+            ))
+        })
+        .and_then(|(src, dst)| plan_mirror(&src, &dst, options));
+    let plan = match plan {
+        Ok(plan) => plan,
+        Err(e) => {
+            return HttpResponse {
+                status_line: "HTTP/1.1 422 Unprocessable Entity",
+                content_type: "application/json; charset=utf-8",
+                body: format!("{{\"error\":\"{}\"}}", escape_json(&e.to_string())),
+            };
+        }
+    };
 
-    let transfer_id = 1_000 + file_name.len() as u64 + receiver_ids.len() as u64;
-    let receivers_json = receiver_ids
+    let actions = plan
+        .actions
         .iter()
-        .map(|r| format!("\"{}\"", escape_json(r)))
+        .map(|action| match action {
+            MirrorAction::Create { path, size } => format!(
+                "{{\"action\":\"create\",\"path\":\"{}\",\"size\":{size}}}",
+                escape_json(&path.display().to_string())
+            ),
+            MirrorAction::Update {
+                path,
+                size,
+                changed_chunks,
+            } => format!(
+                "{{\"action\":\"update\",\"path\":\"{}\",\"size\":{size},\"changed_chunks\":{}}}",
+                escape_json(&path.display().to_string()),
+                changed_chunks.len()
+            ),
+            MirrorAction::Delete { path } => format!(
+                "{{\"action\":\"delete\",\"path\":\"{}\"}}",
+                escape_json(&path.display().to_string())
+            ),
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    let extraneous = plan
+        .extraneous
+        .iter()
+        .map(|path| format!("\"{}\"", escape_json(&path.display().to_string())))
         .collect::<Vec<_>>()
         .join(",");
 
     HttpResponse {
-        status_line: "HTTP/1.1 201 Created",
+        status_line: "HTTP/1.1 200 OK",
         content_type: "application/json; charset=utf-8",
         body: format!(
-            "{{\"transfer_id\":{},\"status\":\"queued\",\"file_name\":\"{}\",\"receiver_ids\":[{}]}}",
-            transfer_id,
-            escape_json(&file_name),
-            receivers_json
+            "{{\"dry_run\":true,\"actions\":[{actions}],\"extraneous\":[{extraneous}],\"bytes_to_send\":{}}}",
+            plan.bytes_to_send
         ),
     }
 }
 
+fn within_roots(path: &Path, roots: &[PathBuf]) -> bool {
+    let Ok(path) = path.canonicalize() else {
+        return false;
+    };
+    roots
+        .iter()
+        .any(|root| root.canonicalize().is_ok_and(|root| path.starts_with(root)))
+}
+
 fn split_request(request: &str) -> (&str, &str) {
     let mut lines = request.lines();
     let first_line = lines.next().unwrap_or_default();
@@ -100,6 +1167,16 @@ fn split_request(request: &str) -> (&str, &str) {
     }
 }
 
+fn header_value<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+    request
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.trim().is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
 fn extract_json_string(body: &str, key: &str) -> Option<String> {
     let marker = format!("\"{}\"", key);
     let idx = body.find(&marker)?;
@@ -112,6 +1189,21 @@ fn extract_json_string(body: &str, key: &str) -> Option<String> {
     Some(rest[..end_quote].to_string())
 }
 
+fn extract_json_bool(body: &str, key: &str) -> Option<bool> {
+    let marker = format!("\"{}\"", key);
+    let idx = body.find(&marker)?;
+    let after = &body[idx + marker.len()..];
+    let colon = after.find(':')?;
+    let value = after[colon + 1..].trim_start();
+    if value.starts_with("true") {
+        Some(true)
+    } else if value.starts_with("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
 fn extract_json_string_array(body: &str, key: &str) -> Option<Vec<String>> {
     let marker = format!("\"{}\"", key);
     let idx = body.find(&marker)?;
@@ -141,6 +1233,46 @@ fn escape_json(input: &str) -> String {
     input.replace('"', "\\\"")
 }
 
+/// Sample discovery results until the registry is wired in; mixes families so the
+/// listing is exercised on IPv6-only networks too.
+const SAMPLE_DEVICES: &[(&str, &str, IpAddr, &str)] = &[
+    (
+        "peer-a",
+        "Aarav iPhone",
+        IpAddr::V4(Ipv4Addr::new(192, 168, 1, 12)),
+        "online",
+    ),
+    (
+        "peer-b",
+        "Meera MacBook",
+        IpAddr::V4(Ipv4Addr::new(192, 168, 1, 34)),
+        "busy",
+    ),
+    (
+        "peer-c",
+        "Ravi Desktop",
+        IpAddr::V4(Ipv4Addr::new(192, 168, 1, 55)),
+        "offline",
+    ),
+    (
+        "peer-d",
+        "Kiran Pixel",
+        IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x2a)),
+        "online",
+    ),
+];
+
 fn discovery_devices_json() -> String {
-    "{\"devices\":[{\"id\":\"peer-a\",\"name\":\"Aarav iPhone\",\"addr\":\"192.168.1.12\",\"status\":\"online\"},{\"id\":\"peer-b\",\"name\":\"Meera MacBook\",\"addr\":\"192.168.1.34\",\"status\":\"busy\"},{\"id\":\"peer-c\",\"name\":\"Ravi Desktop\",\"addr\":\"192.168.1.55\",\"status\":\"offline\"}]}".to_string()
+    let devices = SAMPLE_DEVICES
+        .iter()
+        .map(|(id, name, addr, status)| {
+            let family = if addr.is_ipv4() { "ipv4" } else { "ipv6" };
+            format!(
+                "{{\"id\":\"{id}\",\"name\":\"{}\",\"addr\":\"{addr}\",\"family\":\"{family}\",\"status\":\"{status}\"}}",
+                escape_json(name)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{{\"devices\":[{devices}]}}")
 }
@@ -1,8 +1,9 @@
-use backend_service::route_request;
+use backend_service::{BackendConfig, BackendService};
+use paths::AppPaths;
 use std::io::{Read, Write};
 use std::net::{TcpListener, TcpStream};
 
-fn handle_connection(mut stream: TcpStream) {
+fn handle_connection(service: &mut BackendService, mut stream: TcpStream) {
     let mut buf = [0u8; 8192];
     let n = match stream.read(&mut buf) {
         Ok(n) => n,
@@ -10,19 +11,32 @@ fn handle_connection(mut stream: TcpStream) {
     };
 
     let request = String::from_utf8_lossy(&buf[..n]);
-    let response = route_request(&request).to_http_string();
+    let response = service
+        .handle(&request, stream.peer_addr().ok())
+        .to_http_string();
     let _ = stream.write_all(response.as_bytes());
 }
 
 fn main() -> std::io::Result<()> {
+    tracing_subscriber::fmt::init();
+
     let addr = "127.0.0.1:8787";
     let listener = TcpListener::bind(addr)?;
     println!("backend_service listening on http://{addr}");
 
-    for stream in listener.incoming() {
-        if let Ok(stream) = stream {
-            handle_connection(stream);
+    let config = match AppPaths::from_env() {
+        Ok(paths) => BackendConfig::from_paths(paths).unwrap_or_else(|error| {
+            tracing::warn!(%error, "feature flags not loaded; using defaults");
+            BackendConfig::default()
+        }),
+        Err(error) => {
+            tracing::warn!(%error, "app directories not resolved; using defaults");
+            BackendConfig::default()
         }
+    };
+    let mut service = BackendService::new(config);
+    for stream in listener.incoming().flatten() {
+        handle_connection(&mut service, stream);
     }
 
     Ok(())
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OfferLimitConfig {
+    /// Sustained offers a peer may send per minute.
+    pub offers_per_minute: u32,
+    /// Offers a peer may send back-to-back before the sustained rate applies.
+    pub burst: u32,
+    /// Mute for a first offence. Each repeat offence doubles it, up to `max_mute`.
+    pub base_mute: Duration,
+    pub max_mute: Duration,
+    /// Peers tracked at once. Idle peers are forgotten to make room; while every slot is
+    /// busy, offers from peers not yet tracked are suppressed.
+    pub max_peers: usize,
+}
+
+impl Default for OfferLimitConfig {
+    fn default() -> Self {
+        Self {
+            offers_per_minute: 6,
+            burst: 5,
+            base_mute: Duration::from_secs(30),
+            max_mute: Duration::from_secs(60 * 60),
+            max_peers: 4096,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OfferAdmission {
+    Accepted,
+    /// This offer exceeded the limit; the peer is now muted for `muted_for`.
+    Throttled {
+        muted_for: Duration,
+        strikes: u32,
+    },
+    /// The peer is still muted; the offer is dropped without reaching the UI.
+    Suppressed,
+}
+
+#[derive(Debug, Clone)]
+struct PeerBucket {
+    tokens: f64,
+    refilled_at: Instant,
+    strikes: u32,
+    muted_until: Option<Instant>,
+    last_mute_end: Option<Instant>,
+    suppressed: u32,
+    last_offer: Instant,
+}
+
+impl PeerBucket {
+    /// Nothing left to remember: not muted, no unreported suppressions, and quiet long
+    /// enough that its strikes would have reset and its bucket refilled.
+    fn is_idle(&self, config: &OfferLimitConfig, now: Instant) -> bool {
+        let refill = Duration::from_secs_f64(
+            f64::from(config.burst) * 60.0 / f64::from(config.offers_per_minute.max(1)),
+        );
+        let quiet_since = self
+            .muted_until
+            .map_or(self.last_offer, |until| until.max(self.last_offer));
+        self.suppressed == 0
+            && now.saturating_duration_since(quiet_since) >= config.max_mute.max(refill)
+    }
+}
+
+/// Per-peer token bucket for incoming offers, with escalating mutes for offenders.
+///
+/// A peer that sends no offending offer for `max_mute` after its last mute ends is
+/// treated as a first offender again. At most `max_peers` peers are tracked.
+#[derive(Debug)]
+pub struct OfferRateLimiter {
+    config: OfferLimitConfig,
+    peers: HashMap<String, PeerBucket>,
+}
+
+impl OfferRateLimiter {
+    pub fn new(config: OfferLimitConfig) -> Self {
+        Self {
+            config,
+            peers: HashMap::new(),
+        }
+    }
+
+    pub fn admit(&mut self, peer_id: &str, now: Instant) -> OfferAdmission {
+        if !self.peers.contains_key(peer_id) && self.peers.len() >= self.config.max_peers {
+            self.prune(now);
+            if self.peers.len() >= self.config.max_peers {
+                return OfferAdmission::Suppressed;
+            }
+        }
+        let config = &self.config;
+        let peer = self
+            .peers
+            .entry(peer_id.to_string())
+            .or_insert_with(|| PeerBucket {
+                tokens: f64::from(config.burst),
+                refilled_at: now,
+                strikes: 0,
+                muted_until: None,
+                last_mute_end: None,
+                suppressed: 0,
+                last_offer: now,
+            });
+        peer.last_offer = now;
+
+        if let Some(until) = peer.muted_until {
+            if now < until {
+                peer.suppressed += 1;
+                return OfferAdmission::Suppressed;
+            }
+            peer.muted_until = None;
+            peer.last_mute_end = Some(until);
+            // Start the next window from a clean bucket rather than the pre-mute debt.
+            peer.tokens = f64::from(config.burst);
+            peer.refilled_at = until;
+        }
+        if peer
+            .last_mute_end
+            .is_some_and(|end| now.saturating_duration_since(end) >= config.max_mute)
+        {
+            peer.strikes = 0;
+            peer.last_mute_end = None;
+        }
+
+        let elapsed = now
+            .saturating_duration_since(peer.refilled_at)
+            .as_secs_f64();
+        peer.tokens = (peer.tokens + elapsed * f64::from(config.offers_per_minute) / 60.0)
+            .min(f64::from(config.burst));
+        peer.refilled_at = now;
+
+        if peer.tokens >= 1.0 {
+            peer.tokens -= 1.0;
+            return OfferAdmission::Accepted;
+        }
+
+        peer.strikes += 1;
+        let factor = 1u32 << (peer.strikes - 1).min(16);
+        let muted_for = config.base_mute.saturating_mul(factor).min(config.max_mute);
+        peer.muted_until = Some(now + muted_for);
+        peer.suppressed += 1;
+        OfferAdmission::Throttled {
+            muted_for,
+            strikes: peer.strikes,
+        }
+    }
+
+    pub fn is_muted(&self, peer_id: &str, now: Instant) -> bool {
+        self.peers
+            .get(peer_id)
+            .and_then(|p| p.muted_until)
+            .is_some_and(|until| now < until)
+    }
+
+    /// Forget idle peers; the next offer from one starts from a full bucket and no
+    /// strikes, exactly as if it had been kept.
+    pub fn prune(&mut self, now: Instant) {
+        let config = &self.config;
+        self.peers.retain(|_, peer| !peer.is_idle(config, now));
+    }
+
+    /// Peers currently tracked.
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Suppressed-offer counts per peer since the last call, sorted by peer id.
+    pub fn take_suppressed(&mut self) -> Vec<(String, u32)> {
+        let mut counts: Vec<(String, u32)> = self
+            .peers
+            .iter_mut()
+            .filter(|(_, p)| p.suppressed > 0)
+            .map(|(id, p)| (id.clone(), std::mem::take(&mut p.suppressed)))
+            .collect();
+        counts.sort();
+        counts
+    }
+}
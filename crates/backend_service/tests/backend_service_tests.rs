@@ -1,4 +1,11 @@
-use backend_service::route_request;
+use backend_service::{
+    route_request, AccessLogConfig, BackendConfig, BackendService, FeatureFlags, FlagError,
+    FlagSource, HttpResponse, OfferAdmission, OfferLimitConfig, OfferRateLimiter,
+};
+use desktop_ui::{IncomingDecision, IncomingRequestModal, NotificationKind, UiError};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use transfer::{CompletionReceipt, CompressionPlan, SessionParams, TransferSession};
 
 #[test]
 fn health_endpoint_works() {
@@ -7,12 +14,24 @@ fn health_endpoint_works() {
     assert!(resp.body.contains("ok"));
 }
 
+#[test]
+fn health_reports_the_startup_crypto_selftest() {
+    let mut service = BackendService::new(BackendConfig::default());
+    assert_eq!(service.crypto_selftest(), Ok(()));
+    let resp = service.handle("GET /health HTTP/1.1\r\n\r\n", None);
+    assert_eq!(resp.status_code(), 200);
+    assert_eq!(resp.body, "{\"status\":\"ok\",\"crypto_selftest\":\"ok\"}");
+}
+
 #[test]
 fn devices_endpoint_returns_payload() {
     let resp = route_request("GET /api/v1/discovery/devices HTTP/1.1\r\nHost: localhost\r\n\r\n");
     assert_eq!(resp.status_line, "HTTP/1.1 200 OK");
     assert!(resp.body.contains("\"devices\""));
     assert!(resp.body.contains("peer-a"));
+    assert!(resp
+        .body
+        .contains("\"addr\":\"fd00::2a\",\"family\":\"ipv6\""));
 }
 
 #[test]
@@ -34,8 +53,762 @@ fn create_transfer_requires_receiver_ids() {
     assert!(resp.body.contains("receiver_ids_required"));
 }
 
+#[test]
+fn transfer_tags_flow_to_dashboard_list_filter_and_audit() {
+    let mut service = BackendService::new(BackendConfig::default());
+    let create = |service: &mut BackendService, body: &str| {
+        service.handle(
+            &format!(
+                "POST /api/v1/transfers HTTP/1.1\r\nContent-Type: application/json\r\n\r\n{body}"
+            ),
+            None,
+        )
+    };
+
+    let resp = create(
+        &mut service,
+        "{\"file_name\":\"a.tar\",\"receiver_ids\":[\"peer-a\"],\"tags\":[\"Backup\",\"project-x\"]}",
+    );
+    assert_eq!(resp.status_code(), 201);
+    assert!(resp.body.contains("\"tags\":[\"backup\",\"project-x\"]"));
+    let resp = create(
+        &mut service,
+        "{\"file_name\":\"notes.md\",\"receiver_ids\":[\"peer-a\",\"peer-b\"]}",
+    );
+    assert!(resp.body.contains("\"tags\":[]"));
+    let resp = create(
+        &mut service,
+        "{\"file_name\":\"x\",\"receiver_ids\":[\"peer-a\"],\"tags\":[\"no spaces\"]}",
+    );
+    assert_eq!(resp.status_code(), 400);
+    assert!(resp.body.contains("invalid_tags"));
+
+    let resp = service.handle("GET /api/v1/transfers HTTP/1.1\r\n\r\n", None);
+    assert_eq!(resp.status_code(), 200);
+    assert_eq!(resp.body.matches("\"transfer_id\"").count(), 2);
+
+    let resp = service.handle("GET /api/v1/transfers?tag=backup HTTP/1.1\r\n\r\n", None);
+    assert_eq!(resp.status_code(), 200);
+    assert_eq!(resp.body.matches("\"transfer_id\"").count(), 1);
+    assert!(resp.body.contains("\"file_name\":\"a.tar\""));
+    assert!(resp.body.contains("\"state\":\"queued\""));
+
+    let audited = service.telemetry().events_tagged("project-x");
+    assert_eq!(audited.len(), 1);
+    assert_eq!(audited[0].category, "transfer");
+    assert_eq!(audited[0].action, "created");
+    assert_eq!(audited[0].metadata["receiver_ids"], "peer-a");
+
+    // Ids do not depend on the request, so equal-looking transfers stay distinct.
+    let first = create(
+        &mut service,
+        "{\"file_name\":\"b\",\"receiver_ids\":[\"peer-a\"]}",
+    );
+    let second = create(
+        &mut service,
+        "{\"file_name\":\"c\",\"receiver_ids\":[\"peer-a\"]}",
+    );
+    let id_of = |resp: &HttpResponse| -> u64 {
+        let rest = resp.body.split("\"transfer_id\":").nth(1).expect("id");
+        rest[..rest.find(',').expect("end")]
+            .parse()
+            .expect("number")
+    };
+    assert_ne!(id_of(&first), id_of(&second));
+
+    // The receipt for a tagged transfer is audited under the same tags.
+    let tagged_id = service
+        .telemetry()
+        .events_tagged("backup")
+        .first()
+        .map(|event| event.metadata["transfer_id"].parse::<u64>().expect("id"))
+        .expect("created");
+    let identity = identity::DeviceIdentity::generate();
+    let receipt = CompletionReceipt::sign(tagged_id, 1, [7u8; 32], &identity);
+    service.record_receipt(&receipt).expect("known transfer");
+    let audited = service.telemetry().events_tagged("project-x");
+    assert_eq!(audited.len(), 2);
+    assert_eq!(audited[1].action, "delivered");
+    assert_eq!(audited[1].metadata["total_chunks"], "1");
+    let resp = service.handle("GET /api/v1/transfers?tag=backup HTTP/1.1\r\n\r\n", None);
+    assert!(resp.body.contains("\"state\":\"completed\""));
+    assert!(service
+        .record_receipt(&CompletionReceipt::sign(1, 1, [7u8; 32], &identity))
+        .is_err());
+}
+
 #[test]
 fn unknown_route_returns_404() {
     let resp = route_request("GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n");
     assert_eq!(resp.status_line, "HTTP/1.1 404 Not Found");
 }
+
+#[test]
+fn access_log_captures_request_metadata_and_principal() {
+    let mut config = BackendConfig::default();
+    config
+        .api_tokens
+        .insert("token-123".to_string(), "desktop-ui".to_string());
+    let mut service = BackendService::new(config);
+    let client: SocketAddr = "127.0.0.1:50123".parse().expect("addr");
+
+    let resp = service.handle(
+        "GET /health HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer token-123\r\n\r\n",
+        Some(client),
+    );
+    assert_eq!(resp.status_code(), 200);
+
+    let entry = service.access_log().last_entry().expect("entry");
+    assert_eq!(entry.method, "GET");
+    assert_eq!(entry.path, "/health");
+    assert_eq!(entry.status, 200);
+    assert_eq!(entry.client_addr, Some(client));
+    assert_eq!(entry.principal, "desktop-ui");
+
+    service.handle("GET /missing HTTP/1.1\r\n\r\n", None);
+    let entry = service.access_log().last_entry().expect("entry");
+    assert_eq!(entry.principal, "anonymous");
+    assert_eq!(entry.route, "unmatched");
+}
+
+#[test]
+fn access_log_mirrors_sampled_counters_by_route_and_status() {
+    let mut service = BackendService::new(BackendConfig {
+        access_log: AccessLogConfig { sample_every: 2 },
+        ..BackendConfig::default()
+    });
+
+    for _ in 0..4 {
+        service.handle("GET /health HTTP/1.1\r\n\r\n", None);
+    }
+    service.handle("GET /missing HTTP/1.1\r\n\r\n", None);
+
+    let telemetry = service.telemetry();
+    assert_eq!(
+        telemetry.counter_value("api.requests{route=/health,status=200}"),
+        2
+    );
+    assert_eq!(
+        telemetry.counter_value("api.requests{route=unmatched,status=404}"),
+        1
+    );
+}
+
+#[test]
+fn counter_endpoints_snapshot_and_admin_reset_is_audited() {
+    let mut config = BackendConfig::default();
+    config
+        .api_tokens
+        .insert("admin-token".to_string(), "ops".to_string());
+    config
+        .api_tokens
+        .insert("ui-token".to_string(), "desktop-ui".to_string());
+    config.admin_principals.insert("ops".to_string());
+    let mut service = BackendService::new(config);
+
+    service.handle("GET /health HTTP/1.1\r\n\r\n", None);
+    let resp = service.handle("GET /api/v1/audit/counters HTTP/1.1\r\n\r\n", None);
+    assert_eq!(resp.status_code(), 200);
+    assert!(resp.body.starts_with("{\"taken_ms\":"));
+    assert!(resp.body.contains(
+        "\"name\":\"api.requests{route=/health,status=200}\",\"subsystem\":\"api\",\"value\":1,"
+    ));
+    assert!(resp.body.contains("\"updated_ms\":"));
+
+    let reset = "POST /api/v1/audit/counters/reset HTTP/1.1\r\n";
+    let body = "\r\n{\"subsystem\":\"api\"}";
+    let resp = service.handle(&format!("{reset}{body}"), None);
+    assert_eq!(resp.status_code(), 401);
+    let resp = service.handle(
+        &format!("{reset}Authorization: Bearer ui-token\r\n{body}"),
+        None,
+    );
+    assert_eq!(resp.status_code(), 403);
+    assert!(service.telemetry().events().is_empty());
+
+    let resp = service.handle(
+        &format!("{reset}Authorization: Bearer admin-token\r\n{body}"),
+        None,
+    );
+    assert_eq!(resp.status_code(), 200);
+    assert!(resp.body.contains("\"subsystem\":\"api\""));
+    assert_eq!(
+        service
+            .telemetry()
+            .counter_value("api.requests{route=/health,status=200}"),
+        0
+    );
+
+    let event = &service.telemetry().events()[0];
+    assert_eq!(event.category, "audit");
+    assert_eq!(event.action, "counters_reset");
+    assert_eq!(event.metadata["principal"], "ops");
+    assert_eq!(event.metadata["subsystem"], "api");
+}
+
+#[test]
+fn offer_flood_mutes_peer_with_backoff_and_summarizes_suppressed_offers() {
+    let config = BackendConfig {
+        offer_limits: OfferLimitConfig {
+            offers_per_minute: 6,
+            burst: 2,
+            base_mute: Duration::from_secs(30),
+            max_mute: Duration::from_secs(600),
+            max_peers: 16,
+        },
+        ..Default::default()
+    };
+    let mut service = BackendService::new(config);
+    let offer = |peer: &str| IncomingRequestModal {
+        from_device_id: peer.to_string(),
+        file_name: "spam.bin".to_string(),
+        size_bytes: 1,
+        decision: IncomingDecision::Pending,
+    };
+    let start = Instant::now();
+
+    assert_eq!(
+        service.receive_offer(offer("spammer"), start),
+        OfferAdmission::Accepted
+    );
+    assert_eq!(
+        service.receive_offer(offer("spammer"), start),
+        OfferAdmission::Accepted
+    );
+    assert_eq!(
+        service.receive_offer(offer("spammer"), start),
+        OfferAdmission::Throttled {
+            muted_for: Duration::from_secs(30),
+            strikes: 1
+        }
+    );
+    assert_eq!(
+        service.receive_offer(offer("spammer"), start + Duration::from_secs(10)),
+        OfferAdmission::Suppressed
+    );
+    // Other peers are unaffected by the mute.
+    assert_eq!(
+        service.receive_offer(offer("friend"), start),
+        OfferAdmission::Accepted
+    );
+
+    // Second offence after the mute lifts doubles the mute.
+    let after = start + Duration::from_secs(31);
+    service.receive_offer(offer("spammer"), after);
+    service.receive_offer(offer("spammer"), after);
+    assert_eq!(
+        service.receive_offer(offer("spammer"), after),
+        OfferAdmission::Throttled {
+            muted_for: Duration::from_secs(60),
+            strikes: 2
+        }
+    );
+
+    let muted: Vec<_> = service
+        .telemetry()
+        .events()
+        .iter()
+        .filter(|e| e.category == "offers" && e.action == "peer_muted")
+        .collect();
+    assert_eq!(muted.len(), 2);
+    assert_eq!(muted[1].metadata["peer_id"], "spammer");
+    assert_eq!(muted[1].metadata["mute_secs"], "60");
+    assert_eq!(muted[1].metadata["strikes"], "2");
+    assert_eq!(service.telemetry().counter_value("offers.throttled"), 2);
+    assert_eq!(service.telemetry().counter_value("offers.suppressed"), 1);
+
+    assert_eq!(service.summarize_suppressed_offers(), 1);
+    let pending = service.ui().notifications().pending();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(
+        pending[0].kind,
+        NotificationKind::OffersSuppressed {
+            device_id: "spammer".into(),
+            count: 3
+        }
+    );
+    assert_eq!(service.summarize_suppressed_offers(), 0);
+}
+
+#[test]
+fn offer_limiter_caps_tracked_peers_and_forgets_idle_ones() {
+    let mut limiter = OfferRateLimiter::new(OfferLimitConfig {
+        max_peers: 2,
+        ..Default::default()
+    });
+    let start = Instant::now();
+    assert_eq!(limiter.admit("a", start), OfferAdmission::Accepted);
+    assert_eq!(limiter.admit("b", start), OfferAdmission::Accepted);
+    // Full, and nobody has been quiet long enough to forget.
+    assert_eq!(limiter.admit("c", start), OfferAdmission::Suppressed);
+    assert_eq!(limiter.len(), 2);
+    assert_eq!(limiter.admit("a", start), OfferAdmission::Accepted);
+
+    let later = start + Duration::from_secs(60 * 60);
+    assert_eq!(limiter.admit("c", later), OfferAdmission::Accepted);
+    assert_eq!(limiter.len(), 1);
+    limiter.prune(later + Duration::from_secs(60 * 60));
+    assert!(limiter.is_empty());
+}
+
+#[test]
+fn thin_client_uploads_chunks_resumably_with_auth_and_size_limits() {
+    let mut config = BackendConfig {
+        max_upload_chunk_bytes: Some(8),
+        ..Default::default()
+    };
+    config
+        .api_tokens
+        .insert("web-token".to_string(), "web-client".to_string());
+    let mut service = BackendService::new(config);
+    service.register_transfer(
+        TransferSession::for_upload(77, 10, 4, vec!["peer-b".to_string()]).expect("session"),
+    );
+    let auth = "Authorization: Bearer web-token\r\n";
+
+    let resp = service.handle("GET /api/v1/transfers/77/missing HTTP/1.1\r\n\r\n", None);
+    assert_eq!(resp.status_code(), 401);
+    let resp = service.handle(
+        "PUT /api/v1/transfers/77/chunks/0 HTTP/1.1\r\n\r\nYWJjZA==",
+        None,
+    );
+    assert_eq!(resp.status_code(), 401);
+
+    let put = |service: &mut BackendService, path: &str, body: &str| {
+        service.handle(
+            &format!("PUT /api/v1/transfers/{path} HTTP/1.1\r\n{auth}\r\n{body}"),
+            None,
+        )
+    };
+    // "abcd" and the short tail "ij".
+    assert_eq!(
+        put(&mut service, "77/chunks/0", "YWJjZA==").status_code(),
+        200
+    );
+    let resp = put(&mut service, "77/chunks/2", "aWo=");
+    assert_eq!(resp.status_code(), 200);
+    assert!(resp.body.contains("\"missing_count\":1"));
+
+    let resp = service.handle(
+        &format!("GET /api/v1/transfers/77/missing HTTP/1.1\r\n{auth}\r\n"),
+        None,
+    );
+    assert_eq!(resp.status_code(), 200);
+    assert_eq!(
+        resp.body,
+        "{\"transfer_id\":77,\"total_chunks\":3,\"missing\":[1]}"
+    );
+
+    // 12 bytes decoded exceeds the configured 8-byte cap.
+    let resp = put(&mut service, "77/chunks/1", "MDEyMzQ1Njc4OWFi");
+    assert_eq!(resp.status_code(), 413);
+    assert_eq!(put(&mut service, "77/chunks/1", "ZWY=").status_code(), 400);
+    assert_eq!(
+        put(&mut service, "77/chunks/1", "not base64!").status_code(),
+        400
+    );
+    assert_eq!(
+        put(&mut service, "77/chunks/9", "ZWZnaA==").status_code(),
+        400
+    );
+    assert_eq!(
+        put(&mut service, "78/chunks/1", "ZWZnaA==").status_code(),
+        404
+    );
+
+    assert_eq!(
+        put(&mut service, "77/chunks/1", "ZWZnaA==").status_code(),
+        200
+    );
+    // "EFGH" cannot replace the stored chunk.
+    assert_eq!(
+        put(&mut service, "77/chunks/1", "RUZHSA==").status_code(),
+        409
+    );
+    let session = service.transfer(77).expect("registered");
+    assert!(session.missing_chunks().is_empty());
+    assert_eq!(session.chunk_for(1).expect("chunk").payload, b"efgh");
+    assert_eq!(
+        service.access_log().last_entry().map(|e| e.route),
+        Some("/api/v1/transfers/{id}/chunks/{index}")
+    );
+}
+
+#[test]
+fn session_endpoint_serves_negotiated_params_and_audits_once() {
+    let mut service = BackendService::new(BackendConfig::default());
+    service.record_session_params(
+        41,
+        SessionParams {
+            protocol_version: 2,
+            encryption_mode: "required".to_string(),
+            cipher: "chacha20poly1305".to_string(),
+            chunk_size: 65536,
+            compression: CompressionPlan::Zstd,
+            route: "relay".to_string(),
+        },
+    );
+
+    let resp = service.handle("GET /api/v1/sessions/41 HTTP/1.1\r\n\r\n", None);
+    assert_eq!(resp.status_code(), 200);
+    assert_eq!(
+        resp.body,
+        "{\"session_id\":41,\"params\":{\"protocol_version\":2,\"encryption_mode\":\"required\",\"cipher\":\"chacha20poly1305\",\"chunk_size\":65536,\"compression\":\"zstd\",\"route\":\"relay\"}}"
+    );
+    assert_eq!(
+        service.access_log().last_entry().expect("entry").route,
+        "/api/v1/sessions/{id}"
+    );
+
+    let resp = service.handle("GET /api/v1/sessions/42 HTTP/1.1\r\n\r\n", None);
+    assert_eq!(resp.status_code(), 404);
+    let resp = service.handle("GET /api/v1/sessions/abc HTTP/1.1\r\n\r\n", None);
+    assert_eq!(resp.status_code(), 400);
+
+    let events = service.telemetry().events();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].category, "session");
+    assert_eq!(events[0].action, "negotiated");
+    assert_eq!(events[0].metadata["session_id"], "41");
+    assert_eq!(events[0].metadata["route"], "relay");
+}
+
+#[test]
+fn mirror_plan_endpoint_reports_dry_run_without_touching_destination() {
+    let src = tempfile::tempdir().expect("src");
+    let dst = tempfile::tempdir().expect("dst");
+    std::fs::write(src.path().join("new.txt"), b"hello").expect("write src");
+    std::fs::write(dst.path().join("stale.txt"), b"old").expect("write dst");
+
+    let body = format!(
+        "{{\"source\":\"{}\",\"destination\":\"{}\",\"delete_extraneous\":true}}",
+        src.path().display(),
+        dst.path().display()
+    );
+    let request = |auth: &str, body: &str| {
+        format!(
+            "POST /api/v1/mirror/plan HTTP/1.1\r\nHost: localhost\r\n{auth}Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    };
+    let mut config = BackendConfig {
+        mirror_roots: vec![src.path().to_path_buf(), dst.path().to_path_buf()],
+        ..Default::default()
+    };
+    config
+        .api_tokens
+        .insert("token-123".to_string(), "desktop-ui".to_string());
+    let mut service = BackendService::new(config);
+    let auth = "Authorization: Bearer token-123\r\n";
+
+    // The planner is gated behind delta_sync, which ships off.
+    let resp = service.handle(&request(auth, &body), None);
+    assert_eq!(resp.status_code(), 404);
+    assert!(resp.body.contains("\"flag\":\"delta_sync\""));
+    service.set_feature_flag("delta_sync", true).unwrap();
+
+    // Scanning the filesystem needs a caller and stays inside the configured roots.
+    assert_eq!(route_request(&request(auth, &body)).status_code(), 404);
+    assert_eq!(service.handle(&request("", &body), None).status_code(), 401);
+    let outside = format!(
+        "{{\"source\":\"{}\",\"destination\":\"{}\"}}",
+        src.path().join("..").display(),
+        dst.path().display()
+    );
+    let resp = service.handle(&request(auth, &outside), None);
+    assert_eq!(resp.status_code(), 403);
+    assert!(resp.body.contains("path_outside_mirror_roots"));
+
+    let resp = service.handle(&request(auth, &body), None);
+
+    assert_eq!(resp.status_line, "HTTP/1.1 200 OK");
+    assert!(resp.body.contains("\"dry_run\":true"));
+    assert!(resp
+        .body
+        .contains("{\"action\":\"create\",\"path\":\"new.txt\",\"size\":5}"));
+    assert!(resp
+        .body
+        .contains("{\"action\":\"delete\",\"path\":\"stale.txt\"}"));
+    assert!(dst.path().join("stale.txt").exists());
+}
+
+#[test]
+fn update_endpoints_drive_panel_state() {
+    let mut config = BackendConfig::default();
+    config
+        .api_tokens
+        .insert("token-123".to_string(), "desktop-ui".to_string());
+    let mut service = BackendService::new(config);
+    let get = "GET /api/v1/update HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    let apply = "POST /api/v1/update/apply HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer token-123\r\n\r\n";
+    let state = |body: &str, auth: &str| {
+        format!(
+            "POST /api/v1/update/state HTTP/1.1\r\n{auth}Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    };
+    let auth = "Authorization: Bearer token-123\r\n";
+
+    assert_eq!(service.handle(get, None).body, "{\"state\":\"none\"}");
+    assert_eq!(service.handle(apply, None).status_code(), 404);
+    let anonymous = "POST /api/v1/update/apply HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    assert_eq!(service.handle(anonymous, None).status_code(), 401);
+
+    service.ui_mut().notify_update_available(
+        "1.2.0",
+        &installer_update::FeedEntry {
+            manifest: installer_update::PackageManifest {
+                version: "1.3.0".to_string(),
+                channel: installer_update::UpdateChannel::Stable,
+                platform: "linux-x86_64".to_string(),
+                package_url: "https://example.com/p2p-1.3.0.tar.gz".to_string(),
+                sha256: "c".repeat(64),
+                rollback_from: None,
+            },
+            changelog_url: "https://example.com/changelog/1.3.0".to_string(),
+        },
+    );
+
+    assert_eq!(service.handle(anonymous, None).status_code(), 401);
+    let resp = service.handle(apply, None);
+    assert_eq!(resp.status_code(), 200);
+    assert!(resp.body.contains("\"state\":\"downloading\""));
+    assert!(resp.body.contains("\"candidate_version\":\"1.3.0\""));
+
+    let skip = state("{\"state\":\"installing\"}", auth);
+    assert_eq!(service.handle(&skip, None).status_code(), 409);
+
+    // Only the installer's check of the package reaches verifying and ready.
+    for claimed in ["verifying", "ready"] {
+        let body = format!("{{\"state\":\"{claimed}\"}}");
+        assert_eq!(service.handle(&state(&body, auth), None).status_code(), 409);
+        assert_eq!(service.handle(&state(&body, ""), None).status_code(), 401);
+    }
+    assert!(service
+        .handle(get, None)
+        .body
+        .contains("\"state\":\"downloading\""));
+    assert_eq!(
+        service.ui_mut().verify_update(b"not the package"),
+        Err(UiError::UpdatePackageMismatch)
+    );
+    assert!(service
+        .handle(&state("{\"state\":\"downloading\"}", auth), None)
+        .body
+        .contains("\"state\":\"downloading\""));
+    assert_eq!(
+        service.access_log().last_entry().map(|e| e.route),
+        Some("/api/v1/update/state")
+    );
+}
+
+#[test]
+fn feature_flags_load_from_profiles_and_audit_toggles() {
+    let root = tempfile::tempdir().unwrap();
+    let paths = paths::AppPaths::rooted(root.path());
+    paths.ensure_dirs().unwrap();
+    std::fs::write(
+        paths.settings_file(),
+        "theme = dark\nflags.swarm_mode = on\nflags.delta_sync = true\nflags.teleport = true\n",
+    )
+    .unwrap();
+    std::fs::write(
+        paths.config_dir.join("admin.conf"),
+        "flags.delta_sync = false\n",
+    )
+    .unwrap();
+
+    let flags = FeatureFlags::load(&paths).unwrap();
+    assert!(flags.is_enabled("swarm_mode"));
+    assert!(!flags.is_enabled("delta_sync"));
+    assert!(!flags.is_enabled("teleport"));
+    assert_eq!(
+        flags.get("delta_sync").unwrap().source,
+        FlagSource::AdminProfile
+    );
+    let config = BackendConfig::from_paths(paths.clone()).unwrap();
+    assert_eq!(config.feature_flags, flags);
+    assert_eq!(config.paths.as_ref(), Some(&paths));
+
+    let mut service = BackendService::new(config);
+    assert_eq!(
+        service.set_feature_flag("delta_sync", true),
+        Err(FlagError::Locked)
+    );
+    assert_eq!(
+        service.set_feature_flag("teleport", true),
+        Err(FlagError::UnknownFlag)
+    );
+    service.set_feature_flag("quic_transport", true).unwrap();
+    service.set_feature_flag("quic_transport", true).unwrap();
+
+    let toggles: Vec<_> = service
+        .telemetry()
+        .events()
+        .iter()
+        .filter(|e| e.category == "feature_flags")
+        .collect();
+    assert_eq!(toggles.len(), 1);
+    assert_eq!(toggles[0].metadata["flag"], "quic_transport");
+    assert_eq!(toggles[0].metadata["source"], "runtime");
+
+    let resp = service.handle(
+        "GET /api/v1/flags HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        None,
+    );
+    assert_eq!(resp.status_code(), 200);
+    assert!(resp.body.contains(
+        "{\"name\":\"delta_sync\",\"enabled\":false,\"source\":\"admin_profile\",\"locked\":true}"
+    ));
+    assert!(resp.body.contains(
+        "{\"name\":\"quic_transport\",\"enabled\":true,\"source\":\"runtime\",\"locked\":false}"
+    ));
+    assert!(!resp.body.contains("teleport"));
+
+    std::fs::write(paths.settings_file(), "flags.swarm_mode = off\n").unwrap();
+    service.reload_feature_flags(&paths).unwrap();
+    assert!(!service.flags().is_enabled("swarm_mode"));
+    assert!(!service.flags().is_enabled("quic_transport"));
+    assert_eq!(
+        service
+            .telemetry()
+            .events()
+            .iter()
+            .filter(|e| e.category == "feature_flags")
+            .count(),
+        3
+    );
+}
+
+#[test]
+fn profile_endpoints_export_and_import_between_devices() {
+    let old = tempfile::tempdir().unwrap();
+    let new = tempfile::tempdir().unwrap();
+    let old_paths = paths::AppPaths::rooted(old.path());
+    let new_paths = paths::AppPaths::rooted(new.path());
+    old_paths.ensure_dirs().unwrap();
+    identity::DeviceIdentity::load_or_generate(&old_paths).unwrap();
+    std::fs::write(old_paths.settings_file(), "flags.delta_sync = on\n").unwrap();
+    let bundle = "profile.p2pf";
+
+    let post = |service: &mut BackendService, route: &str, body: &str| {
+        service.handle(
+            &format!(
+                "POST {route} HTTP/1.1\r\nAuthorization: Bearer owner-token\r\n\
+                 Content-Length: {}\r\n\r\n{body}",
+                body.len()
+            ),
+            None,
+        )
+    };
+    let tokens: std::collections::HashMap<String, String> =
+        [("owner-token".to_string(), "owner".to_string())].into();
+
+    // The bundle holds the identity key, so neither direction is open to anonymous callers.
+    let mut anonymous = BackendService::new(BackendConfig {
+        paths: Some(old_paths.clone()),
+        ..BackendConfig::default()
+    });
+    let resp = anonymous.handle(
+        "POST /api/v1/settings/profile/export HTTP/1.1\r\n\r\n{\"passphrase\":\"pw\"}",
+        None,
+    );
+    assert_eq!(resp.status_code(), 401);
+    let resp = anonymous.handle(
+        "POST /api/v1/settings/profile/import HTTP/1.1\r\n\r\n{\"passphrase\":\"pw\"}",
+        None,
+    );
+    assert_eq!(resp.status_code(), 401);
+
+    let mut unconfigured = BackendService::new(BackendConfig {
+        api_tokens: tokens.clone(),
+        ..BackendConfig::default()
+    });
+    let resp = post(
+        &mut unconfigured,
+        "/api/v1/settings/profile/export",
+        "{\"passphrase\":\"pw\",\"path\":\"/tmp/x\"}",
+    );
+    assert_eq!(resp.status_code(), 503);
+
+    let mut exporter = BackendService::new(BackendConfig {
+        paths: Some(old_paths.clone()),
+        profile_export: profile::ExportOptions {
+            include_history: false,
+            iterations: 1_000,
+        },
+        api_tokens: tokens.clone(),
+        ..BackendConfig::default()
+    });
+    let resp = post(
+        &mut exporter,
+        "/api/v1/settings/profile/export",
+        &format!("{{\"passphrase\":\"s3cret\",\"path\":\"{bundle}\"}}"),
+    );
+    assert_eq!(resp.status_code(), 200, "{}", resp.body);
+    assert!(resp.body.contains("\"include_history\":false"));
+    let exported = old_paths.profile_exports_dir().join(bundle);
+    assert!(exported.is_file());
+
+    // Bundles stay inside the exports directory.
+    let outside = old.path().join("profile.p2pf").display().to_string();
+    for path in [
+        "../profile.p2pf",
+        "nested/../../profile.p2pf",
+        outside.as_str(),
+    ] {
+        let resp = post(
+            &mut exporter,
+            "/api/v1/settings/profile/export",
+            &format!("{{\"passphrase\":\"s3cret\",\"path\":\"{path}\"}}"),
+        );
+        assert_eq!(resp.status_code(), 403, "{path}");
+        assert!(resp.body.contains("path_outside_profile_exports"));
+    }
+    assert!(!old.path().join("profile.p2pf").exists());
+    let inside = exported.display().to_string();
+    let resp = post(
+        &mut exporter,
+        "/api/v1/settings/profile/export",
+        &format!("{{\"passphrase\":\"s3cret\",\"path\":\"{inside}\"}}"),
+    );
+    assert_eq!(resp.status_code(), 200, "{}", resp.body);
+    std::fs::create_dir_all(new_paths.profile_exports_dir()).unwrap();
+    std::fs::copy(&exported, new_paths.profile_exports_dir().join(bundle)).unwrap();
+
+    let mut importer = BackendService::new(BackendConfig {
+        paths: Some(new_paths.clone()),
+        api_tokens: tokens,
+        ..BackendConfig::default()
+    });
+    let resp = post(
+        &mut importer,
+        "/api/v1/settings/profile/import",
+        &format!("{{\"passphrase\":\"nope\",\"path\":\"{bundle}\"}}"),
+    );
+    assert_eq!(resp.status_code(), 403);
+    assert!(!new_paths.identity_key_file().exists());
+    let resp = post(
+        &mut importer,
+        "/api/v1/settings/profile/import",
+        &format!(
+            "{{\"passphrase\":\"s3cret\",\"path\":\"{}\"}}",
+            exported.display()
+        ),
+    );
+    assert_eq!(resp.status_code(), 403);
+    assert!(resp.body.contains("path_outside_profile_exports"));
+
+    let resp = post(
+        &mut importer,
+        "/api/v1/settings/profile/import",
+        &format!("{{\"passphrase\":\"s3cret\",\"path\":\"{bundle}\"}}"),
+    );
+    assert_eq!(resp.status_code(), 200, "{}", resp.body);
+    assert!(resp.body.contains("\"identity\":\"imported\""));
+    assert!(resp.body.contains("\"history\":\"absent\""));
+    assert_eq!(
+        std::fs::read_to_string(new_paths.settings_file()).unwrap(),
+        "flags.delta_sync = on\n"
+    );
+}
@@ -0,0 +1,162 @@
+use crate::{CipherSuite, CryptoEnvelopeError};
+
+/// First byte of every structured AAD.
+const AAD_TAG: u8 = 0xa1;
+/// tag | protocol_version | cipher_suite | flags | transfer_id | chunk_index |
+/// total_chunks | file_index
+pub const CHUNK_AAD_HEADER_LEN: usize = 1 + 1 + 1 + 1 + 8 + 4 + 4 + 4;
+const HASH_LEN: usize = 32;
+const FLAG_CHUNK_DIGEST: u8 = 0x01;
+const FLAG_FILE_HASH: u8 = 0x02;
+const FLAG_PADDED: u8 = 0x04;
+/// The high nibble of the flags byte holds the compression codec id.
+const COMPRESSION_SHIFT: u8 = 4;
+
+/// The authenticated header of one chunk, as [`AadBuilder`] encodes it.
+///
+/// Sealing a chunk with this as its AAD binds every field to the ciphertext, so none
+/// can be changed in the frame header without the tag failing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkAad {
+    pub protocol_version: u8,
+    pub cipher_suite: CipherSuite,
+    pub transfer_id: u64,
+    pub chunk_index: u32,
+    pub total_chunks: u32,
+    pub file_index: u32,
+    /// SHA-256 of the chunk's plaintext.
+    pub chunk_digest: Option<[u8; HASH_LEN]>,
+    /// SHA-256 of the whole file, when the sender knows it up front.
+    pub file_hash: Option<[u8; HASH_LEN]>,
+    /// The plaintext was padded before sealing; see [`crate::PaddingScheme`].
+    pub padded: bool,
+    /// Id of the codec the plaintext was compressed with before sealing, 0 for none.
+    /// The ids belong to the transfer crate; only four bits are carried.
+    pub compression: u8,
+}
+
+impl ChunkAad {
+    /// header, then the chunk digest and the file hash when present, in that order.
+    pub fn encode(&self) -> Vec<u8> {
+        let flags = self.chunk_digest.map_or(0, |_| FLAG_CHUNK_DIGEST)
+            | self.file_hash.map_or(0, |_| FLAG_FILE_HASH)
+            | if self.padded { FLAG_PADDED } else { 0 }
+            | (self.compression & 0x0f) << COMPRESSION_SHIFT;
+        let mut out = Vec::with_capacity(CHUNK_AAD_HEADER_LEN + 2 * HASH_LEN);
+        out.push(AAD_TAG);
+        out.push(self.protocol_version);
+        out.push(self.cipher_suite.as_u8());
+        out.push(flags);
+        out.extend_from_slice(&self.transfer_id.to_be_bytes());
+        out.extend_from_slice(&self.chunk_index.to_be_bytes());
+        out.extend_from_slice(&self.total_chunks.to_be_bytes());
+        out.extend_from_slice(&self.file_index.to_be_bytes());
+        out.extend(self.chunk_digest.iter().flatten());
+        out.extend(self.file_hash.iter().flatten());
+        out
+    }
+
+    /// Inverse of [`Self::encode`]; anything else, including trailing bytes, is refused.
+    pub fn parse(bytes: &[u8]) -> Result<Self, CryptoEnvelopeError> {
+        let malformed = CryptoEnvelopeError::MalformedAad;
+        if bytes.len() < CHUNK_AAD_HEADER_LEN || bytes[0] != AAD_TAG {
+            return Err(malformed);
+        }
+        let flags = bytes[3] & 0x0f;
+        if flags & !(FLAG_CHUNK_DIGEST | FLAG_FILE_HASH | FLAG_PADDED) != 0 {
+            return Err(malformed);
+        }
+        let hashes = (flags & (FLAG_CHUNK_DIGEST | FLAG_FILE_HASH)).count_ones() as usize;
+        if bytes.len() != CHUNK_AAD_HEADER_LEN + hashes * HASH_LEN {
+            return Err(malformed);
+        }
+        let mut hashes = bytes[CHUNK_AAD_HEADER_LEN..]
+            .chunks_exact(HASH_LEN)
+            .map(|hash| <[u8; HASH_LEN]>::try_from(hash).expect("chunk len"));
+        let chunk_digest = (flags & FLAG_CHUNK_DIGEST != 0).then(|| hashes.next());
+        let file_hash = (flags & FLAG_FILE_HASH != 0).then(|| hashes.next());
+        Ok(Self {
+            protocol_version: bytes[1],
+            cipher_suite: CipherSuite::from_u8(bytes[2]).ok_or(malformed)?,
+            transfer_id: u64::from_be_bytes(bytes[4..12].try_into().expect("slice len")),
+            chunk_index: u32::from_be_bytes(bytes[12..16].try_into().expect("slice len")),
+            total_chunks: u32::from_be_bytes(bytes[16..20].try_into().expect("slice len")),
+            file_index: u32::from_be_bytes(bytes[20..24].try_into().expect("slice len")),
+            chunk_digest: chunk_digest.flatten(),
+            file_hash: file_hash.flatten(),
+            padded: flags & FLAG_PADDED != 0,
+            compression: bytes[3] >> COMPRESSION_SHIFT,
+        })
+    }
+}
+
+/// Builds the AAD of a chunk frame, so every crate that seals or opens one encodes
+/// the same fields the same way.
+#[derive(Debug, Clone, Copy)]
+pub struct AadBuilder {
+    aad: ChunkAad,
+}
+
+impl AadBuilder {
+    /// File 0, the default cipher suite, no hashes, padding or compression until set.
+    pub fn new(
+        protocol_version: u8,
+        transfer_id: u64,
+        chunk_index: u32,
+        total_chunks: u32,
+    ) -> Self {
+        Self {
+            aad: ChunkAad {
+                protocol_version,
+                cipher_suite: CipherSuite::default(),
+                transfer_id,
+                chunk_index,
+                total_chunks,
+                file_index: 0,
+                chunk_digest: None,
+                file_hash: None,
+                padded: false,
+                compression: 0,
+            },
+        }
+    }
+
+    pub fn with_cipher_suite(mut self, suite: CipherSuite) -> Self {
+        self.aad.cipher_suite = suite;
+        self
+    }
+
+    pub fn with_file_index(mut self, file_index: u32) -> Self {
+        self.aad.file_index = file_index;
+        self
+    }
+
+    pub fn with_chunk_digest(mut self, digest: [u8; HASH_LEN]) -> Self {
+        self.aad.chunk_digest = Some(digest);
+        self
+    }
+
+    pub fn with_file_hash(mut self, hash: [u8; HASH_LEN]) -> Self {
+        self.aad.file_hash = Some(hash);
+        self
+    }
+
+    pub fn with_padded_payload(mut self) -> Self {
+        self.aad.padded = true;
+        self
+    }
+
+    /// Record the codec the payload was compressed with, so the tag covers it.
+    pub fn with_compression(mut self, codec_id: u8) -> Self {
+        self.aad.compression = codec_id;
+        self
+    }
+
+    pub fn fields(&self) -> &ChunkAad {
+        &self.aad
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        self.aad.encode()
+    }
+}
@@ -0,0 +1,51 @@
+use crate::{ChunkCipher, CipherSuite, CryptoEnvelopeError, SecretKeyBytes};
+
+/// One chunk of a batch: its nonce, plaintext and AAD.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchChunk<'a> {
+    pub nonce: [u8; 12],
+    pub plaintext: &'a [u8],
+    pub aad: &'a [u8],
+}
+
+/// [`crate::encrypt_with_suite`] over a batch of one transfer's chunks, keying the
+/// cipher once under the default suite.
+///
+/// `transfer_key` is the transfer's [`crate::derive_transfer_key`], never the session
+/// key itself. Ciphertexts come back in the batch's order; the first failure stops
+/// the batch.
+pub fn encrypt_chunks<'a>(
+    transfer_key: &SecretKeyBytes,
+    chunks: impl IntoIterator<Item = BatchChunk<'a>>,
+) -> Result<Vec<Vec<u8>>, CryptoEnvelopeError> {
+    encrypt_chunks_with_suite(CipherSuite::ChaCha20Poly1305, transfer_key, chunks)
+}
+
+pub fn encrypt_chunks_with_suite<'a>(
+    suite: CipherSuite,
+    transfer_key: &SecretKeyBytes,
+    chunks: impl IntoIterator<Item = BatchChunk<'a>>,
+) -> Result<Vec<Vec<u8>>, CryptoEnvelopeError> {
+    let cipher = ChunkCipher::new(suite, transfer_key.expose());
+    chunks
+        .into_iter()
+        .map(|chunk| cipher.encrypt(chunk.nonce, chunk.plaintext, chunk.aad))
+        .collect()
+}
+
+/// [`encrypt_chunks_with_suite`] spread over the rayon thread pool, for a sender that
+/// a single core cannot keep up with. Ciphertexts keep the batch's order.
+#[cfg(feature = "rayon")]
+pub fn par_encrypt_chunks(
+    suite: CipherSuite,
+    transfer_key: &SecretKeyBytes,
+    chunks: &[BatchChunk<'_>],
+) -> Result<Vec<Vec<u8>>, CryptoEnvelopeError> {
+    use rayon::prelude::*;
+
+    let cipher = ChunkCipher::new(suite, transfer_key.expose());
+    chunks
+        .par_iter()
+        .map(|chunk| cipher.encrypt(chunk.nonce, chunk.plaintext, chunk.aad))
+        .collect()
+}
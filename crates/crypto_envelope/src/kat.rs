@@ -0,0 +1,119 @@
+use crate::{
+    decrypt_with_suite, encrypt_with_suite, CipherSuite, CryptoEnvelopeError, EncryptStream,
+    TAG_LEN,
+};
+
+/// A published test vector: the ciphertext is followed by the tag.
+struct KnownAnswer {
+    suite: CipherSuite,
+    key: &'static str,
+    nonce: &'static str,
+    aad: &'static str,
+    plaintext: &'static str,
+    ciphertext: &'static str,
+}
+
+const KNOWN_ANSWERS: [KnownAnswer; 2] = [
+    // RFC 8439, section 2.8.2.
+    KnownAnswer {
+        suite: CipherSuite::ChaCha20Poly1305,
+        key: "808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f",
+        nonce: "070000004041424344454647",
+        aad: "50515253c0c1c2c3c4c5c6c7",
+        plaintext: "4c616469657320616e642047656e746c656d656e206f662074686520636c6173\
+                    73206f66202739393a204966204920636f756c64206f6666657220796f75206f\
+                    6e6c79206f6e652074697020666f7220746865206675747572652c2073756e73\
+                    637265656e20776f756c642062652069742e",
+        ciphertext: "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6\
+                     3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36\
+                     92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc\
+                     3ff4def08e4b7a9de576d26586cec64b6116\
+                     1ae10b594f09e26a7e902ecbd0600691",
+    },
+    // McGrew and Viega, "The Galois/Counter Mode of Operation", test case 16.
+    KnownAnswer {
+        suite: CipherSuite::Aes256Gcm,
+        key: "feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308",
+        nonce: "cafebabefacedbaddecaf888",
+        aad: "feedfacedeadbeeffeedfacedeadbeefabaddad2",
+        plaintext: "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+                    1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39",
+        ciphertext: "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa\
+                     8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662\
+                     76fc6ece0f4e1768cddf8853bb2d551b",
+    },
+];
+
+/// Run every suite against its published vector: seal, open, and refuse a flipped tag.
+///
+/// Meant for startup, so a miscompiled cipher or a build missing a suite is caught
+/// before it seals a transfer; the error names the first suite that failed.
+pub fn crypto_selftest() -> Result<(), CryptoEnvelopeError> {
+    for suite in CipherSuite::ALL {
+        let answer = KNOWN_ANSWERS
+            .iter()
+            .find(|answer| answer.suite == suite)
+            .ok_or(CryptoEnvelopeError::SelfTestFailed(suite))?;
+        if !check(answer) {
+            return Err(CryptoEnvelopeError::SelfTestFailed(suite));
+        }
+    }
+    Ok(())
+}
+
+fn check(answer: &KnownAnswer) -> bool {
+    let (Some(key), Some(nonce)) = (
+        hex(answer.key).and_then(|key| <[u8; 32]>::try_from(key).ok()),
+        hex(answer.nonce).and_then(|nonce| <[u8; 12]>::try_from(nonce).ok()),
+    ) else {
+        return false;
+    };
+    let (Some(aad), Some(plaintext), Some(expected)) = (
+        hex(answer.aad),
+        hex(answer.plaintext),
+        hex(answer.ciphertext),
+    ) else {
+        return false;
+    };
+
+    if expected.len() != plaintext.len() + TAG_LEN {
+        return false;
+    }
+
+    let sealed = encrypt_with_suite(answer.suite, &key, nonce, &plaintext, &aad);
+    let opened = decrypt_with_suite(answer.suite, &key, nonce, &expected, &aad);
+    let mut flipped = expected.clone();
+    flipped[plaintext.len()] ^= 0x01;
+    let refused = decrypt_with_suite(answer.suite, &key, nonce, &flipped, &aad);
+
+    let streamed = {
+        let mut body = plaintext.clone();
+        let mut stream = EncryptStream::new(answer.suite, &key, nonce, &aad);
+        stream.update(&mut body).is_ok() && {
+            body.extend_from_slice(&stream.finalize());
+            body == expected
+        }
+    };
+
+    sealed.as_deref() == Ok(expected.as_slice())
+        && opened.as_deref() == Ok(plaintext.as_slice())
+        && refused == Err(CryptoEnvelopeError::TagMismatch)
+        && streamed
+}
+
+fn hex(text: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = text
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace())
+        .map(|b| (b as char).to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    Some(
+        digits
+            .chunks(2)
+            .map(|pair| pair[0] << 4 | pair[1])
+            .collect(),
+    )
+}
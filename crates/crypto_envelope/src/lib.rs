@@ -1,9 +1,45 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+mod aad;
+mod batch;
+mod ct;
+mod kat;
+mod nonce;
+mod padding;
+#[cfg(feature = "probe")]
+pub mod probe;
+mod secret;
+mod stream;
+mod subkey;
+mod suite;
+
+pub use aad::{AadBuilder, ChunkAad, CHUNK_AAD_HEADER_LEN};
+#[cfg(feature = "rayon")]
+pub use batch::par_encrypt_chunks;
+pub use batch::{encrypt_chunks, encrypt_chunks_with_suite, BatchChunk};
+pub use ct::ct_eq;
+pub use kat::crypto_selftest;
+pub use nonce::{
+    manifest_nonce, nonce_with_epoch, try_derive_nonce, NonceGuard, NonceSequencer,
+    MAX_NONCE_COUNTER, MAX_NONCE_EPOCH,
+};
+pub use padding::{unpad, PaddingScheme, MIN_PADDING_BUCKET};
+pub use secret::{SecretKeyBytes, SessionKeySecret};
+pub use stream::{DecryptStream, EncryptStream};
+pub use subkey::{derive_transfer_key, LABEL_TRANSFER_KEY};
+pub use suite::{
+    decrypt_with_suite, encrypt_in_place_with_suite, encrypt_with_suite, ChunkCipher, CipherSuite,
+};
+
+/// Bytes the authentication tag adds to every ciphertext.
+pub const TAG_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Direction {
     SenderToReceiver,
     ReceiverToSender,
 }
 
+/// Nonce for a chunk. Only the low three bytes of `chunk_index` fit, so indices past
+/// [`MAX_NONCE_COUNTER`] repeat earlier nonces; [`try_derive_nonce`] refuses them.
 pub fn derive_nonce(transfer_id: u64, chunk_index: u32, direction: Direction) -> [u8; 12] {
     let mut nonce = [0u8; 12];
     nonce[..8].copy_from_slice(&transfer_id.to_be_bytes());
@@ -31,22 +67,22 @@ pub fn decrypt_chunk(
     decrypt_chunk_with_aad(session_rx_key, nonce, ciphertext, &[])
 }
 
+/// ChaCha20-Poly1305: the ciphertext is the plaintext length plus a [`TAG_LEN`]-byte tag.
+///
+/// A key must never encrypt twice under the same nonce.
 pub fn encrypt_chunk_with_aad(
     session_tx_key: &[u8; 32],
     nonce: [u8; 12],
     plaintext: &[u8],
     aad: &[u8],
 ) -> Result<Vec<u8>, CryptoEnvelopeError> {
-    if plaintext.is_empty() {
-        return Ok(vec![compute_tag(session_tx_key, &nonce, aad, plaintext)]);
-    }
-
-    let mut out = Vec::with_capacity(plaintext.len() + 1);
-    for (idx, byte) in plaintext.iter().enumerate() {
-        out.push(*byte ^ keystream_byte(session_tx_key, &nonce, idx));
-    }
-    out.push(compute_tag(session_tx_key, &nonce, aad, plaintext));
-    Ok(out)
+    encrypt_with_suite(
+        CipherSuite::ChaCha20Poly1305,
+        session_tx_key,
+        nonce,
+        plaintext,
+        aad,
+    )
 }
 
 pub fn decrypt_chunk_with_aad(
@@ -55,59 +91,53 @@ pub fn decrypt_chunk_with_aad(
     ciphertext: &[u8],
     aad: &[u8],
 ) -> Result<Vec<u8>, CryptoEnvelopeError> {
-    if ciphertext.is_empty() {
-        return Err(CryptoEnvelopeError::DecryptionFailure);
-    }
-
-    let (cipher_payload, tag) = ciphertext.split_at(ciphertext.len() - 1);
-    let mut plaintext = Vec::with_capacity(cipher_payload.len());
-    for (idx, byte) in cipher_payload.iter().enumerate() {
-        plaintext.push(*byte ^ keystream_byte(session_rx_key, &nonce, idx));
-    }
-
-    let expected_tag = compute_tag(session_rx_key, &nonce, aad, &plaintext);
-    if tag[0] != expected_tag {
-        return Err(CryptoEnvelopeError::DecryptionFailure);
-    }
-
-    Ok(plaintext)
-}
-
-fn keystream_byte(key: &[u8; 32], nonce: &[u8; 12], index: usize) -> u8 {
-    let k = key[index % key.len()];
-    let n = nonce[index % nonce.len()];
-    let i = (index as u8).wrapping_mul(31);
-    k.rotate_left(1) ^ n.rotate_right(1) ^ i
-}
-
-fn compute_tag(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> u8 {
-    let mut tag = 0u8;
-
-    for (idx, b) in key.iter().enumerate() {
-        tag ^= b.wrapping_add((idx as u8).wrapping_mul(3));
-    }
-    for (idx, b) in nonce.iter().enumerate() {
-        tag ^= b.rotate_left((idx % 8) as u32);
-    }
-    for (idx, b) in aad.iter().enumerate() {
-        tag = tag.wrapping_add(b.wrapping_mul(((idx as u8) % 17).max(1)));
-    }
-    for (idx, b) in plaintext.iter().enumerate() {
-        tag ^= b.wrapping_add((idx as u8).wrapping_mul(7));
-    }
-
-    tag
+    decrypt_with_suite(
+        CipherSuite::ChaCha20Poly1305,
+        session_rx_key,
+        nonce,
+        ciphertext,
+        aad,
+    )
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CryptoEnvelopeError {
-    DecryptionFailure,
+    /// More data under one key and nonce than the suite's keystream covers (about
+    /// 256 GiB for ChaCha20-Poly1305, 64 GiB for AES-256-GCM), whether sealed at once
+    /// or streamed.
+    MessageTooLong,
+    /// The tag did not verify: wrong key, wrong nonce or AAD, or altered bytes. Checked
+    /// in constant time, so the error does not say which byte was off.
+    TagMismatch,
+    /// Too short to hold a tag.
+    TruncatedCiphertext,
+    /// A second ciphertext under a (key, nonce) a [`NonceGuard`] already opened.
+    NonceReuse,
+    /// [`crypto_selftest`] got a wrong answer from this suite, or has no vector for it.
+    SelfTestFailed(CipherSuite),
+    /// No unused nonce is left under the key; rekey or start a new transfer.
+    NonceExhausted,
+    /// AAD bytes that are not a [`ChunkAad`].
+    MalformedAad,
+    /// A padded payload without its padding marker.
+    MalformedPadding,
 }
 
 impl std::fmt::Display for CryptoEnvelopeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            CryptoEnvelopeError::DecryptionFailure => write!(f, "decryption failed"),
+            CryptoEnvelopeError::MessageTooLong => {
+                write!(f, "message too long for one nonce")
+            }
+            CryptoEnvelopeError::TagMismatch => write!(f, "authentication tag mismatch"),
+            CryptoEnvelopeError::TruncatedCiphertext => write!(f, "ciphertext truncated"),
+            CryptoEnvelopeError::NonceReuse => write!(f, "nonce reuse"),
+            CryptoEnvelopeError::SelfTestFailed(suite) => {
+                write!(f, "self-test failed for {}", suite.as_str())
+            }
+            CryptoEnvelopeError::NonceExhausted => write!(f, "nonce space exhausted"),
+            CryptoEnvelopeError::MalformedAad => write!(f, "malformed chunk aad"),
+            CryptoEnvelopeError::MalformedPadding => write!(f, "malformed payload padding"),
         }
     }
 }
@@ -0,0 +1,216 @@
+use crate::{ChunkCipher, CipherSuite, CryptoEnvelopeError, Direction};
+use std::collections::{HashMap, HashSet};
+
+/// Largest counter a nonce can hold: it has three bytes for it.
+pub const MAX_NONCE_COUNTER: u32 = 0x00ff_ffff;
+/// Largest key epoch a nonce can hold: six bits beside the direction.
+pub const MAX_NONCE_EPOCH: u32 = 0x3f;
+
+/// transfer_id(8) | counter(3) | epoch(6 bits) << 2 | direction(2 bits)
+///
+/// Epoch 0 gives the same bytes as [`crate::derive_nonce`], so frames sealed before
+/// epochs existed still match. Unlike that function, a counter or epoch that does not
+/// fit is an error instead of wrapping onto a nonce already used.
+pub fn nonce_with_epoch(
+    transfer_id: u64,
+    epoch: u32,
+    counter: u32,
+    direction: Direction,
+) -> Result<[u8; 12], CryptoEnvelopeError> {
+    if counter > MAX_NONCE_COUNTER || epoch > MAX_NONCE_EPOCH {
+        return Err(CryptoEnvelopeError::NonceExhausted);
+    }
+    let mut nonce = [0u8; 12];
+    nonce[..8].copy_from_slice(&transfer_id.to_be_bytes());
+    nonce[8..11].copy_from_slice(&counter.to_be_bytes()[1..]);
+    nonce[11] = (epoch as u8) << 2
+        | match direction {
+            Direction::SenderToReceiver => 0x01,
+            Direction::ReceiverToSender => 0x02,
+        };
+    Ok(nonce)
+}
+
+/// Nonce of the manifest of `transfer_id`: transfer_id(8) | 0(3) | 0x03.
+///
+/// Chunk nonces end in direction bits 01 or 10, so 11 is a domain no chunk of any
+/// epoch can reach, and a manifest sealed under the session key never shares a nonce
+/// with a chunk. There is one per transfer, so a transfer id must not carry two
+/// different manifests under the same key.
+pub fn manifest_nonce(transfer_id: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..8].copy_from_slice(&transfer_id.to_be_bytes());
+    nonce[11] = 0x03;
+    nonce
+}
+
+/// [`crate::derive_nonce`] that refuses chunk indices past [`MAX_NONCE_COUNTER`].
+pub fn try_derive_nonce(
+    transfer_id: u64,
+    chunk_index: u32,
+    direction: Direction,
+) -> Result<[u8; 12], CryptoEnvelopeError> {
+    nonce_with_epoch(transfer_id, 0, chunk_index, direction)
+}
+
+/// Hands out each nonce of a (transfer, direction) once, in counter order.
+///
+/// When a counter space runs out, [`Self::next_nonce`] fails until [`Self::rekey`]
+/// moves to the next epoch, which must come with a new key; when the epochs run out
+/// too, the transfer needs a new id.
+///
+/// Chunk senders use [`Self::nonce_for`] instead, since a chunk's counter is its index:
+/// the receiver rebuilds the nonce from the frame header, and a resent chunk seals the
+/// same plaintext under the nonce it had before.
+#[derive(Debug, Clone, Default)]
+pub struct NonceSequencer {
+    streams: HashMap<(u64, Direction), NonceCounter>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct NonceCounter {
+    epoch: u32,
+    next: u32,
+}
+
+impl NonceSequencer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn next_nonce(
+        &mut self,
+        transfer_id: u64,
+        direction: Direction,
+    ) -> Result<[u8; 12], CryptoEnvelopeError> {
+        let counter = self.streams.entry((transfer_id, direction)).or_default();
+        let nonce = nonce_with_epoch(transfer_id, counter.epoch, counter.next, direction)?;
+        counter.next += 1;
+        Ok(nonce)
+    }
+
+    /// Nonce of `counter` in the current epoch, marking every counter up to it used.
+    pub fn nonce_for(
+        &mut self,
+        transfer_id: u64,
+        direction: Direction,
+        counter: u32,
+    ) -> Result<[u8; 12], CryptoEnvelopeError> {
+        let stream = self.streams.entry((transfer_id, direction)).or_default();
+        let nonce = nonce_with_epoch(transfer_id, stream.epoch, counter, direction)?;
+        stream.next = stream.next.max(counter + 1);
+        Ok(nonce)
+    }
+
+    /// Start the next epoch with a fresh counter, and return it.
+    pub fn rekey(
+        &mut self,
+        transfer_id: u64,
+        direction: Direction,
+    ) -> Result<u32, CryptoEnvelopeError> {
+        let counter = self.streams.entry((transfer_id, direction)).or_default();
+        if counter.epoch >= MAX_NONCE_EPOCH {
+            return Err(CryptoEnvelopeError::NonceExhausted);
+        }
+        *counter = NonceCounter {
+            epoch: counter.epoch + 1,
+            next: 0,
+        };
+        Ok(counter.epoch)
+    }
+
+    /// Pick up counters saved with a transfer, so a restarted sender never hands out a
+    /// nonce it used before the restart.
+    pub fn restore(
+        &mut self,
+        transfer_id: u64,
+        direction: Direction,
+        epoch: u32,
+        used: u32,
+    ) -> Result<(), CryptoEnvelopeError> {
+        if epoch > MAX_NONCE_EPOCH || used > MAX_NONCE_COUNTER + 1 {
+            return Err(CryptoEnvelopeError::NonceExhausted);
+        }
+        self.streams
+            .insert((transfer_id, direction), NonceCounter { epoch, next: used });
+        Ok(())
+    }
+
+    pub fn epoch(&self, transfer_id: u64, direction: Direction) -> u32 {
+        self.counter(transfer_id, direction).epoch
+    }
+
+    /// Nonces handed out in the current epoch.
+    pub fn used(&self, transfer_id: u64, direction: Direction) -> u32 {
+        self.counter(transfer_id, direction).next
+    }
+
+    /// Nonces left before the current epoch needs a rekey.
+    pub fn remaining(&self, transfer_id: u64, direction: Direction) -> u32 {
+        (MAX_NONCE_COUNTER + 1) - self.used(transfer_id, direction)
+    }
+
+    /// Drop the counters of a finished transfer. Its id must not be reused under the
+    /// same key, since the counters would start over.
+    pub fn forget(&mut self, transfer_id: u64) {
+        self.streams.retain(|(id, _), _| *id != transfer_id);
+    }
+
+    fn counter(&self, transfer_id: u64, direction: Direction) -> NonceCounter {
+        self.streams
+            .get(&(transfer_id, direction))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Receive side of a [`NonceSequencer`]: opens ciphertexts under one key, each nonce once.
+///
+/// A nonce already opened fails with [`CryptoEnvelopeError::NonceReuse`] before any
+/// decryption is spent on it. A nonce counts as opened only once its tag verifies, so a
+/// forged ciphertext cannot use up the nonce of the genuine one.
+#[derive(Debug, Clone)]
+pub struct NonceGuard {
+    cipher: ChunkCipher,
+    opened: HashSet<[u8; 12]>,
+}
+
+impl NonceGuard {
+    pub fn new(suite: CipherSuite, key: &[u8; 32]) -> Self {
+        Self {
+            cipher: ChunkCipher::new(suite, key),
+            opened: HashSet::new(),
+        }
+    }
+
+    pub fn check(&self, nonce: &[u8; 12]) -> Result<(), CryptoEnvelopeError> {
+        if self.opened.contains(nonce) {
+            return Err(CryptoEnvelopeError::NonceReuse);
+        }
+        Ok(())
+    }
+
+    pub fn decrypt(
+        &mut self,
+        nonce: [u8; 12],
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptoEnvelopeError> {
+        self.check(&nonce)?;
+        let plaintext = self.cipher.decrypt(nonce, ciphertext, aad)?;
+        self.opened.insert(nonce);
+        Ok(plaintext)
+    }
+
+    /// Nonces opened so far.
+    pub fn opened(&self) -> usize {
+        self.opened.len()
+    }
+
+    /// Drop the nonces of a finished transfer, which lead with its id. Its id must not be
+    /// reused under the same key, since its nonces would open again.
+    pub fn forget(&mut self, transfer_id: u64) {
+        self.opened
+            .retain(|nonce| nonce[..8] != transfer_id.to_be_bytes());
+    }
+}
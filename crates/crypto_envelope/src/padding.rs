@@ -0,0 +1,99 @@
+use crate::CryptoEnvelopeError;
+
+/// Smallest padded size under [`PaddingScheme::Bucket`].
+pub const MIN_PADDING_BUCKET: usize = 256;
+/// First byte of the padding; everything after it is zero.
+const PADDING_MARKER: u8 = 0x80;
+
+/// How a payload is padded before encryption, so the ciphertext length says less
+/// about which file is being sent.
+///
+/// Padding is a `0x80` marker then zeros, so a receiver strips it without knowing
+/// which scheme the sender used.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PaddingScheme {
+    #[default]
+    None,
+    /// Up to the next power of two, at least [`MIN_PADDING_BUCKET`] bytes: few
+    /// distinct sizes, at up to twice the bytes.
+    Bucket,
+    /// PADMÉ: at most about 12% overhead, and a length leaks O(log log n) bits.
+    Padme,
+}
+
+impl PaddingScheme {
+    /// Every scheme, most preferred first.
+    pub const ALL: [PaddingScheme; 3] = [
+        PaddingScheme::Padme,
+        PaddingScheme::Bucket,
+        PaddingScheme::None,
+    ];
+
+    pub fn as_u8(self) -> u8 {
+        match self {
+            PaddingScheme::None => 0,
+            PaddingScheme::Bucket => 1,
+            PaddingScheme::Padme => 2,
+        }
+    }
+
+    /// `None` for ids this build does not implement.
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(PaddingScheme::None),
+            1 => Some(PaddingScheme::Bucket),
+            2 => Some(PaddingScheme::Padme),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PaddingScheme::None => "none",
+            PaddingScheme::Bucket => "bucket",
+            PaddingScheme::Padme => "padme",
+        }
+    }
+
+    /// Length of a `len`-byte payload once padded; the marker always takes a byte.
+    pub fn padded_len(self, len: usize) -> usize {
+        match self {
+            PaddingScheme::None => len,
+            PaddingScheme::Bucket => (len + 1).next_power_of_two().max(MIN_PADDING_BUCKET),
+            PaddingScheme::Padme => padme(len + 1),
+        }
+    }
+
+    /// Pad `payload` in place to [`Self::padded_len`]; a no-op for `None`.
+    pub fn pad(self, payload: &mut Vec<u8>) {
+        if self == PaddingScheme::None {
+            return;
+        }
+        let len = self.padded_len(payload.len());
+        payload.push(PADDING_MARKER);
+        payload.resize(len, 0);
+    }
+}
+
+/// Strip what [`PaddingScheme::pad`] added, under whichever scheme.
+pub fn unpad(payload: &mut Vec<u8>) -> Result<(), CryptoEnvelopeError> {
+    match payload.iter().rposition(|&b| b != 0) {
+        Some(marker) if payload[marker] == PADDING_MARKER => {
+            payload.truncate(marker);
+            Ok(())
+        }
+        _ => Err(CryptoEnvelopeError::MalformedPadding),
+    }
+}
+
+/// Round up, keeping only the top log2(log2(len)) + 1 bits of the length.
+fn padme(len: usize) -> usize {
+    if len < 2 {
+        return len;
+    }
+    let exponent = usize::BITS - 1 - len.leading_zeros();
+    let significant = u32::BITS - exponent.leading_zeros();
+    let mask = (1usize << (exponent - significant)) - 1;
+    (len + mask) & !mask
+}
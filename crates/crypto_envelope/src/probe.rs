@@ -0,0 +1,48 @@
+//! Hooks that let tests check verification never exits early. Only built with the
+//! `probe` feature, which release builds must leave off.
+
+use crate::ct::ct_eq_counted;
+use crate::{CipherSuite, CryptoEnvelopeError, DecryptStream, TAG_LEN};
+
+/// [`crate::ct_eq`], with how many byte pairs it compared.
+pub fn ct_eq_trace(a: &[u8], b: &[u8]) -> (bool, usize) {
+    let mut examined = 0;
+    let equal = ct_eq_counted(a, b, &mut examined);
+    (equal, examined)
+}
+
+/// What [`decrypt_trace`] saw.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecryptTrace {
+    pub result: Result<Vec<u8>, CryptoEnvelopeError>,
+    /// Ciphertext bytes run through the MAC before the tag was checked.
+    pub bytes_authenticated: u64,
+}
+
+/// [`crate::decrypt_chunk_with_aad`] through [`DecryptStream`], reporting how much of
+/// the ciphertext was authenticated before the verdict.
+pub fn decrypt_trace(
+    session_rx_key: &[u8; 32],
+    nonce: [u8; 12],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> DecryptTrace {
+    let Some(body_len) = ciphertext.len().checked_sub(TAG_LEN) else {
+        return DecryptTrace {
+            result: Err(CryptoEnvelopeError::TruncatedCiphertext),
+            bytes_authenticated: 0,
+        };
+    };
+    let (body, tag) = ciphertext.split_at(body_len);
+    let mut plaintext = body.to_vec();
+    let mut stream = DecryptStream::new(CipherSuite::ChaCha20Poly1305, session_rx_key, nonce, aad);
+    let result = stream.update(&mut plaintext);
+    let bytes_authenticated = stream.authenticated_len();
+    let result = result
+        .and_then(|()| stream.finalize(tag))
+        .map(|()| plaintext);
+    DecryptTrace {
+        result,
+        bytes_authenticated,
+    }
+}
@@ -0,0 +1,88 @@
+use crate::ct_eq;
+use std::fmt;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// A 32-byte key, wiped from memory when dropped.
+///
+/// Debug output never shows the bytes, and equality runs in constant time.
+#[derive(Clone)]
+pub struct SecretKeyBytes([u8; 32]);
+
+impl SecretKeyBytes {
+    pub fn new(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn expose(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for SecretKeyBytes {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl PartialEq for SecretKeyBytes {
+    fn eq(&self, other: &Self) -> bool {
+        ct_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SecretKeyBytes {}
+
+impl fmt::Debug for SecretKeyBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretKeyBytes(..)")
+    }
+}
+
+impl Zeroize for SecretKeyBytes {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl Drop for SecretKeyBytes {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for SecretKeyBytes {}
+
+/// The two directional keys of a session, for holders that keep them for the whole
+/// session and should not leave copies behind.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SessionKeySecret {
+    tx: SecretKeyBytes,
+    rx: SecretKeyBytes,
+}
+
+impl SessionKeySecret {
+    pub fn new(tx_key: [u8; 32], rx_key: [u8; 32]) -> Self {
+        Self {
+            tx: SecretKeyBytes(tx_key),
+            rx: SecretKeyBytes(rx_key),
+        }
+    }
+
+    /// Key this side encrypts with.
+    pub fn tx_key(&self) -> &[u8; 32] {
+        self.tx.expose()
+    }
+
+    /// Key this side decrypts with.
+    pub fn rx_key(&self) -> &[u8; 32] {
+        self.rx.expose()
+    }
+}
+
+impl fmt::Debug for SessionKeySecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SessionKeySecret(..)")
+    }
+}
+
+impl ZeroizeOnDrop for SessionKeySecret {}
@@ -0,0 +1,227 @@
+use crate::{ct_eq, CipherSuite, CryptoEnvelopeError, TAG_LEN};
+use aes::Aes256;
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use ctr::Ctr32BE;
+use ghash::GHash;
+use poly1305::universal_hash::{KeyInit, UniversalHash};
+use poly1305::Poly1305;
+use std::fmt;
+use zeroize::Zeroize;
+
+/// Incremental [`crate::encrypt_with_suite`]: encrypts in place slice by slice, so
+/// a large chunk is held once instead of as plaintext and ciphertext.
+///
+/// The ciphertext followed by [`Self::finalize`]'s tag is byte for byte what the
+/// one-shot function returns under the same suite.
+pub struct EncryptStream {
+    cipher: Keystream,
+    mac: StreamMac,
+}
+
+impl EncryptStream {
+    pub fn new(suite: CipherSuite, key: &[u8; 32], nonce: [u8; 12], aad: &[u8]) -> Self {
+        let (cipher, mac) = start(suite, key, nonce, aad);
+        Self { cipher, mac }
+    }
+
+    /// Encrypt the next slice of plaintext in place.
+    pub fn update(&mut self, data: &mut [u8]) -> Result<(), CryptoEnvelopeError> {
+        self.cipher.apply(data)?;
+        self.mac.absorb(data);
+        Ok(())
+    }
+
+    pub fn finalize(self) -> [u8; TAG_LEN] {
+        self.mac.tag()
+    }
+}
+
+impl fmt::Debug for EncryptStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptStream(..)")
+    }
+}
+
+/// Incremental [`crate::decrypt_with_suite`], decrypting in place.
+///
+/// Plaintext from [`Self::update`] is unauthenticated until [`Self::finalize`] accepts
+/// the tag; nothing may act on it before then.
+pub struct DecryptStream {
+    cipher: Keystream,
+    mac: StreamMac,
+}
+
+impl DecryptStream {
+    pub fn new(suite: CipherSuite, key: &[u8; 32], nonce: [u8; 12], aad: &[u8]) -> Self {
+        let (cipher, mac) = start(suite, key, nonce, aad);
+        Self { cipher, mac }
+    }
+
+    /// Decrypt the next slice of ciphertext in place.
+    pub fn update(&mut self, data: &mut [u8]) -> Result<(), CryptoEnvelopeError> {
+        self.mac.absorb(data);
+        self.cipher.apply(data)
+    }
+
+    /// Check the tag that followed the ciphertext, in constant time.
+    pub fn finalize(self, tag: &[u8]) -> Result<(), CryptoEnvelopeError> {
+        if tag.len() != TAG_LEN {
+            return Err(CryptoEnvelopeError::TruncatedCiphertext);
+        }
+        if ct_eq(&self.mac.tag(), tag) {
+            Ok(())
+        } else {
+            Err(CryptoEnvelopeError::TagMismatch)
+        }
+    }
+
+    /// Ciphertext bytes run through the MAC so far.
+    #[cfg(feature = "probe")]
+    pub(crate) fn authenticated_len(&self) -> u64 {
+        self.mac.ciphertext_len
+    }
+}
+
+impl fmt::Debug for DecryptStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DecryptStream(..)")
+    }
+}
+
+/// Keys the cipher and MAC for `suite`. Both AEADs run a counter-mode keystream and
+/// MAC the zero-padded AAD, the zero-padded ciphertext and a block of their lengths;
+/// they differ in how the MAC is keyed and how the tag is finished.
+fn start(
+    suite: CipherSuite,
+    key: &[u8; 32],
+    nonce: [u8; 12],
+    aad: &[u8],
+) -> (Keystream, StreamMac) {
+    let (cipher, mac) = match suite {
+        // RFC 8439: the Poly1305 key is the first keystream block and the payload
+        // starts at block 1.
+        CipherSuite::ChaCha20Poly1305 => {
+            let mut cipher = ChaCha20::new(key.into(), &nonce.into());
+            let mut block = [0u8; 64];
+            cipher.apply_keystream(&mut block);
+            let mac = Poly1305::new(poly1305::Key::from_slice(&block[..32]));
+            block.zeroize();
+            (Keystream::ChaCha20(cipher), Mac::Poly1305(Box::new(mac)))
+        }
+        // NIST SP 800-38D: GHASH is keyed with the encrypted zero block, the payload
+        // starts at counter 2, and the tag is GHASH masked with counter block 1.
+        CipherSuite::Aes256Gcm => {
+            let mut hash_key = [0u8; 16];
+            Ctr32BE::<Aes256>::new(key.into(), &[0u8; 16].into()).apply_keystream(&mut hash_key);
+            let mac = GHash::new(&hash_key.into());
+            hash_key.zeroize();
+            let mut counter = [0u8; 16];
+            counter[..12].copy_from_slice(&nonce);
+            counter[15] = 1;
+            let mut cipher = Ctr32BE::<Aes256>::new(key.into(), &counter.into());
+            let mut mask = [0u8; 16];
+            cipher.apply_keystream(&mut mask);
+            (
+                Keystream::Aes256Gcm(Box::new(cipher)),
+                Mac::Ghash(mac, mask),
+            )
+        }
+    };
+    let mut mac = StreamMac {
+        mac,
+        partial: [0u8; 16],
+        partial_len: 0,
+        aad_len: aad.len() as u64,
+        ciphertext_len: 0,
+    };
+    mac.mac.update_padded(aad);
+    (cipher, mac)
+}
+
+enum Keystream {
+    ChaCha20(ChaCha20),
+    Aes256Gcm(Box<Ctr32BE<Aes256>>),
+}
+
+impl Keystream {
+    /// Fails rather than wrap the block counter into keystream already used.
+    fn apply(&mut self, data: &mut [u8]) -> Result<(), CryptoEnvelopeError> {
+        match self {
+            Keystream::ChaCha20(cipher) => cipher.try_apply_keystream(data),
+            Keystream::Aes256Gcm(cipher) => cipher.try_apply_keystream(data),
+        }
+        .map_err(|_| CryptoEnvelopeError::MessageTooLong)
+    }
+}
+
+enum Mac {
+    Poly1305(Box<Poly1305>),
+    /// GHASH and the keystream block that masks its output.
+    Ghash(GHash, [u8; 16]),
+}
+
+impl Mac {
+    fn update_padded(&mut self, data: &[u8]) {
+        match self {
+            Mac::Poly1305(mac) => mac.update_padded(data),
+            Mac::Ghash(mac, _) => mac.update_padded(data),
+        }
+    }
+}
+
+/// The suite's MAC over ciphertext arriving in slices of any length.
+struct StreamMac {
+    mac: Mac,
+    partial: [u8; 16],
+    partial_len: usize,
+    aad_len: u64,
+    ciphertext_len: u64,
+}
+
+impl StreamMac {
+    fn absorb(&mut self, mut data: &[u8]) {
+        self.ciphertext_len += data.len() as u64;
+        if self.partial_len > 0 {
+            let take = data.len().min(16 - self.partial_len);
+            self.partial[self.partial_len..self.partial_len + take].copy_from_slice(&data[..take]);
+            self.partial_len += take;
+            data = &data[take..];
+            if self.partial_len < 16 {
+                return;
+            }
+            self.mac.update_padded(&self.partial);
+            self.partial_len = 0;
+        }
+        let whole = data.len() - data.len() % 16;
+        self.mac.update_padded(&data[..whole]);
+        let rest = &data[whole..];
+        self.partial[..rest.len()].copy_from_slice(rest);
+        self.partial_len = rest.len();
+    }
+
+    fn tag(mut self) -> [u8; TAG_LEN] {
+        self.mac.update_padded(&self.partial[..self.partial_len]);
+        match self.mac {
+            Mac::Poly1305(mut mac) => {
+                let mut lengths = [0u8; 16];
+                lengths[..8].copy_from_slice(&self.aad_len.to_le_bytes());
+                lengths[8..].copy_from_slice(&self.ciphertext_len.to_le_bytes());
+                mac.update_padded(&lengths);
+                mac.finalize().into()
+            }
+            Mac::Ghash(mut mac, mut mask) => {
+                let mut lengths = [0u8; 16];
+                lengths[..8].copy_from_slice(&(self.aad_len * 8).to_be_bytes());
+                lengths[8..].copy_from_slice(&(self.ciphertext_len * 8).to_be_bytes());
+                mac.update_padded(&lengths);
+                let mut tag: [u8; TAG_LEN] = mac.finalize().into();
+                for (byte, mask) in tag.iter_mut().zip(mask) {
+                    *byte ^= mask;
+                }
+                mask.zeroize();
+                tag
+            }
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use crate::SecretKeyBytes;
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+/// Expand label, under a session key, for the key of one transfer.
+pub const LABEL_TRANSFER_KEY: &[u8] = b"p2p/hkdf/v1/transfer-key";
+
+/// Key that seals the chunks of `transfer_id`, expanded from a session key.
+///
+/// Every transfer gets an independent key, so a nonce reused within one transfer
+/// exposes that transfer's chunks only, never another's under the same session.
+pub fn derive_transfer_key(session_key: &[u8; 32], transfer_id: u64) -> SecretKeyBytes {
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::from_prk(session_key)
+        .expect("32 bytes is a valid HKDF-SHA256 PRK")
+        .expand_multi_info(&[LABEL_TRANSFER_KEY, &transfer_id.to_be_bytes()], &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    SecretKeyBytes::new(key)
+}
@@ -0,0 +1,175 @@
+use crate::{CryptoEnvelopeError, TAG_LEN};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::aead::{Aead, AeadInPlace, KeyInit, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use std::fmt;
+
+/// AEAD a frame is sealed with. Every suite takes a 32-byte key and a 12-byte nonce
+/// and adds a [`crate::TAG_LEN`]-byte tag, so frames differ only in the id.
+///
+/// Id 1 is what frames meant by "encrypted" before suites were named, so those frames
+/// still decode as ChaCha20-Poly1305.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CipherSuite {
+    #[default]
+    ChaCha20Poly1305,
+    /// Faster where the CPU has AES instructions.
+    Aes256Gcm,
+}
+
+impl CipherSuite {
+    /// Every suite, most preferred first.
+    pub const ALL: [CipherSuite; 2] = [CipherSuite::ChaCha20Poly1305, CipherSuite::Aes256Gcm];
+
+    pub fn as_u8(self) -> u8 {
+        match self {
+            CipherSuite::ChaCha20Poly1305 => 1,
+            CipherSuite::Aes256Gcm => 2,
+        }
+    }
+
+    /// `None` for ids this build does not implement.
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(CipherSuite::ChaCha20Poly1305),
+            2 => Some(CipherSuite::Aes256Gcm),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CipherSuite::ChaCha20Poly1305 => "chacha20poly1305",
+            CipherSuite::Aes256Gcm => "aes256gcm",
+        }
+    }
+}
+
+/// An AEAD keyed once under one suite, for sealing many chunks with the same key.
+///
+/// Keying is the fixed cost of every chunk, and the larger one for AES-256-GCM, which
+/// expands its round keys and GHASH key; a sender sealing a run of chunks pays it once.
+#[derive(Clone)]
+pub struct ChunkCipher {
+    cipher: SuiteCipher,
+}
+
+#[derive(Clone)]
+enum SuiteCipher {
+    ChaCha20Poly1305(ChaCha20Poly1305),
+    Aes256Gcm(Box<Aes256Gcm>),
+}
+
+impl ChunkCipher {
+    pub fn new(suite: CipherSuite, key: &[u8; 32]) -> Self {
+        let cipher = match suite {
+            CipherSuite::ChaCha20Poly1305 => {
+                SuiteCipher::ChaCha20Poly1305(ChaCha20Poly1305::new(key.into()))
+            }
+            CipherSuite::Aes256Gcm => SuiteCipher::Aes256Gcm(Box::new(Aes256Gcm::new(key.into()))),
+        };
+        Self { cipher }
+    }
+
+    pub fn suite(&self) -> CipherSuite {
+        match self.cipher {
+            SuiteCipher::ChaCha20Poly1305(_) => CipherSuite::ChaCha20Poly1305,
+            SuiteCipher::Aes256Gcm(_) => CipherSuite::Aes256Gcm,
+        }
+    }
+
+    /// The plaintext plus the tag.
+    pub fn encrypt(
+        &self,
+        nonce: [u8; 12],
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptoEnvelopeError> {
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        match &self.cipher {
+            SuiteCipher::ChaCha20Poly1305(cipher) => cipher.encrypt(&nonce.into(), payload),
+            SuiteCipher::Aes256Gcm(cipher) => cipher.encrypt(&nonce.into(), payload),
+        }
+        .map_err(|_| CryptoEnvelopeError::MessageTooLong)
+    }
+
+    pub fn decrypt(
+        &self,
+        nonce: [u8; 12],
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptoEnvelopeError> {
+        if ciphertext.len() < TAG_LEN {
+            return Err(CryptoEnvelopeError::TruncatedCiphertext);
+        }
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        match &self.cipher {
+            SuiteCipher::ChaCha20Poly1305(cipher) => cipher.decrypt(&nonce.into(), payload),
+            SuiteCipher::Aes256Gcm(cipher) => cipher.decrypt(&nonce.into(), payload),
+        }
+        .map_err(|_| CryptoEnvelopeError::TagMismatch)
+    }
+
+    /// [`Self::encrypt`] over `buffer` itself, with the tag appended to it.
+    pub fn encrypt_in_place(
+        &self,
+        nonce: [u8; 12],
+        aad: &[u8],
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), CryptoEnvelopeError> {
+        match &self.cipher {
+            SuiteCipher::ChaCha20Poly1305(cipher) => {
+                cipher.encrypt_in_place(&nonce.into(), aad, buffer)
+            }
+            SuiteCipher::Aes256Gcm(cipher) => cipher.encrypt_in_place(&nonce.into(), aad, buffer),
+        }
+        .map_err(|_| CryptoEnvelopeError::MessageTooLong)
+    }
+}
+
+impl fmt::Debug for ChunkCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChunkCipher")
+            .field("suite", &self.suite())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Encrypt under `suite`: the ciphertext is the plaintext plus the tag.
+pub fn encrypt_with_suite(
+    suite: CipherSuite,
+    key: &[u8; 32],
+    nonce: [u8; 12],
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, CryptoEnvelopeError> {
+    ChunkCipher::new(suite, key).encrypt(nonce, plaintext, aad)
+}
+
+pub fn decrypt_with_suite(
+    suite: CipherSuite,
+    key: &[u8; 32],
+    nonce: [u8; 12],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, CryptoEnvelopeError> {
+    ChunkCipher::new(suite, key).decrypt(nonce, ciphertext, aad)
+}
+
+/// [`encrypt_with_suite`] over `buffer` itself, with the tag appended to it.
+pub fn encrypt_in_place_with_suite(
+    suite: CipherSuite,
+    key: &[u8; 32],
+    nonce: [u8; 12],
+    aad: &[u8],
+    buffer: &mut Vec<u8>,
+) -> Result<(), CryptoEnvelopeError> {
+    ChunkCipher::new(suite, key).encrypt_in_place(nonce, aad, buffer)
+}
@@ -1,7 +1,13 @@
 use crypto_envelope::{
-    decrypt_chunk, decrypt_chunk_with_aad, derive_nonce, encrypt_chunk, encrypt_chunk_with_aad,
-    Direction,
+    crypto_selftest, ct_eq, decrypt_chunk, decrypt_chunk_with_aad, decrypt_with_suite,
+    derive_nonce, derive_transfer_key, encrypt_chunk, encrypt_chunk_with_aad, encrypt_chunks,
+    encrypt_chunks_with_suite, encrypt_in_place_with_suite, encrypt_with_suite, manifest_nonce,
+    nonce_with_epoch, try_derive_nonce, unpad, AadBuilder, BatchChunk, ChunkAad, ChunkCipher,
+    CipherSuite, CryptoEnvelopeError, DecryptStream, Direction, EncryptStream, NonceGuard,
+    NonceSequencer, PaddingScheme, SecretKeyBytes, SessionKeySecret, CHUNK_AAD_HEADER_LEN,
+    MAX_NONCE_COUNTER, MAX_NONCE_EPOCH, MIN_PADDING_BUCKET, TAG_LEN,
 };
+use zeroize::Zeroize;
 
 #[test]
 fn encrypt_then_decrypt_round_trip() {
@@ -50,3 +56,401 @@ fn nonce_derivation_changes_with_direction_and_index() {
     assert_ne!(n1, n3);
     assert_eq!(n1.len(), 12);
 }
+
+#[test]
+fn every_ciphertext_byte_is_authenticated() {
+    let key = [3u8; 32];
+    let nonce = derive_nonce(77, 4, Direction::SenderToReceiver);
+    let plaintext = b"authenticated payload";
+
+    let ciphertext = encrypt_chunk_with_aad(&key, nonce, plaintext, b"aad").expect("encrypt");
+    assert_eq!(ciphertext.len(), plaintext.len() + TAG_LEN);
+    for index in 0..ciphertext.len() {
+        let mut tampered = ciphertext.clone();
+        tampered[index] ^= 0x01;
+        assert!(decrypt_chunk_with_aad(&key, nonce, &tampered, b"aad").is_err());
+    }
+    assert!(decrypt_chunk_with_aad(&key, nonce, &ciphertext[..TAG_LEN - 1], b"aad").is_err());
+
+    // An empty chunk still carries a full tag.
+    let empty = encrypt_chunk(&key, nonce, b"").expect("encrypt");
+    assert_eq!(empty.len(), TAG_LEN);
+    assert_eq!(decrypt_chunk(&key, nonce, &empty).expect("decrypt"), b"");
+    assert!(decrypt_chunk(&[4u8; 32], nonce, &empty).is_err());
+}
+
+#[test]
+fn streaming_matches_one_shot_for_any_slicing() {
+    let key = [5u8; 32];
+    let nonce = derive_nonce(9, 1, Direction::SenderToReceiver);
+    let aad = b"frame header";
+    let plaintext: Vec<u8> = (0..1_000u32).map(|i| (i * 7 % 256) as u8).collect();
+
+    for suite in CipherSuite::ALL {
+        let one_shot = encrypt_with_suite(suite, &key, nonce, &plaintext, aad).expect("encrypt");
+        for step in [1, 15, 16, 17, 64, 999, 1_000] {
+            let mut buffer = plaintext.clone();
+            let mut stream = EncryptStream::new(suite, &key, nonce, aad);
+            for slice in buffer.chunks_mut(step) {
+                stream.update(slice).expect("update");
+            }
+            buffer.extend_from_slice(&stream.finalize());
+            assert_eq!(buffer, one_shot, "{suite:?} step {step}");
+
+            let (body, tag) = buffer.split_at_mut(plaintext.len());
+            let mut stream = DecryptStream::new(suite, &key, nonce, aad);
+            for slice in body.chunks_mut(step) {
+                stream.update(slice).expect("update");
+            }
+            stream.finalize(tag).expect("tag");
+            assert_eq!(body, plaintext.as_slice());
+        }
+
+        let mut tampered = one_shot.clone();
+        tampered[500] ^= 1;
+        let (body, tag) = tampered.split_at_mut(plaintext.len());
+        let mut stream = DecryptStream::new(suite, &key, nonce, aad);
+        stream.update(body).expect("update");
+        assert!(stream.finalize(tag).is_err());
+        let mut stream = DecryptStream::new(suite, &key, nonce, b"other header");
+        stream
+            .update(&mut one_shot.clone()[..plaintext.len()])
+            .expect("update");
+        assert!(stream.finalize(&one_shot[plaintext.len()..]).is_err());
+    }
+
+    let mut body = plaintext.clone();
+    let mut stream = EncryptStream::new(CipherSuite::Aes256Gcm, &key, nonce, aad);
+    stream.update(&mut body).expect("update");
+    body.extend_from_slice(&stream.finalize());
+    let (body, tag) = body.split_at_mut(plaintext.len());
+    let mut stream = DecryptStream::new(CipherSuite::ChaCha20Poly1305, &key, nonce, aad);
+    stream.update(body).expect("update");
+    assert!(stream.finalize(tag).is_err());
+}
+
+#[test]
+fn each_suite_round_trips_and_rejects_the_other_suites_ciphertext() {
+    let key = [5u8; 32];
+    let nonce = derive_nonce(77, 1, Direction::SenderToReceiver);
+    let plaintext = b"agile payload";
+
+    for suite in CipherSuite::ALL {
+        assert_eq!(CipherSuite::from_u8(suite.as_u8()), Some(suite));
+        let ciphertext = encrypt_with_suite(suite, &key, nonce, plaintext, b"aad").unwrap();
+        assert_eq!(ciphertext.len(), plaintext.len() + TAG_LEN);
+
+        let mut in_place = plaintext.to_vec();
+        encrypt_in_place_with_suite(suite, &key, nonce, b"aad", &mut in_place).unwrap();
+        assert_eq!(in_place, ciphertext);
+
+        let opened = decrypt_with_suite(suite, &key, nonce, &ciphertext, b"aad").unwrap();
+        assert_eq!(opened, plaintext);
+        for other in CipherSuite::ALL.into_iter().filter(|other| *other != suite) {
+            assert!(decrypt_with_suite(other, &key, nonce, &ciphertext, b"aad").is_err());
+        }
+    }
+    // The default suite is what the unsuffixed functions use.
+    assert_eq!(
+        encrypt_chunk_with_aad(&key, nonce, plaintext, b"aad").unwrap(),
+        encrypt_with_suite(CipherSuite::default(), &key, nonce, plaintext, b"aad").unwrap()
+    );
+    assert_eq!(CipherSuite::from_u8(0), None);
+}
+
+#[test]
+fn nonce_sequencer_refuses_to_wrap_and_rekeys_into_a_new_epoch() {
+    let dir = Direction::SenderToReceiver;
+    // Epoch 0 is the layout frames have always used.
+    assert_eq!(
+        try_derive_nonce(5, MAX_NONCE_COUNTER, dir),
+        Ok(derive_nonce(5, MAX_NONCE_COUNTER, dir))
+    );
+    // Past three bytes the old derivation collides; the checked one refuses.
+    assert_eq!(
+        derive_nonce(5, MAX_NONCE_COUNTER + 1, dir),
+        derive_nonce(5, 0, dir)
+    );
+    assert_eq!(
+        try_derive_nonce(5, MAX_NONCE_COUNTER + 1, dir),
+        Err(CryptoEnvelopeError::NonceExhausted)
+    );
+    assert_ne!(
+        nonce_with_epoch(5, 1, 0, dir).unwrap(),
+        nonce_with_epoch(5, 0, 0, dir).unwrap()
+    );
+    assert_ne!(
+        nonce_with_epoch(5, 1, 0, dir).unwrap(),
+        nonce_with_epoch(5, 0, 0, Direction::ReceiverToSender).unwrap()
+    );
+
+    let mut sequencer = NonceSequencer::new();
+    let first = sequencer.next_nonce(5, dir).unwrap();
+    assert_eq!(first, derive_nonce(5, 0, dir));
+    assert_eq!(
+        sequencer.next_nonce(5, dir).unwrap(),
+        derive_nonce(5, 1, dir)
+    );
+    // Each (transfer, direction) counts on its own.
+    assert_eq!(
+        sequencer
+            .next_nonce(5, Direction::ReceiverToSender)
+            .unwrap(),
+        derive_nonce(5, 0, Direction::ReceiverToSender)
+    );
+    assert_eq!(sequencer.used(5, dir), 2);
+    // A chunk index is its counter; sealing it again gives the same nonce.
+    assert_eq!(
+        sequencer.nonce_for(5, dir, 7).unwrap(),
+        derive_nonce(5, 7, dir)
+    );
+    assert_eq!(
+        sequencer.nonce_for(5, dir, 3).unwrap(),
+        derive_nonce(5, 3, dir)
+    );
+    assert_eq!(sequencer.used(5, dir), 8);
+    assert_eq!(
+        sequencer.nonce_for(5, dir, MAX_NONCE_COUNTER + 1),
+        Err(CryptoEnvelopeError::NonceExhausted)
+    );
+    assert_eq!(sequencer.rekey(5, dir), Ok(1));
+    assert_eq!(
+        sequencer.nonce_for(5, dir, 3).unwrap(),
+        nonce_with_epoch(5, 1, 3, dir).unwrap()
+    );
+
+    let mut sequencer = NonceSequencer::new();
+    sequencer.restore(6, dir, 0, MAX_NONCE_COUNTER).unwrap();
+    assert_eq!(sequencer.remaining(6, dir), 1);
+    let last = sequencer.next_nonce(6, dir).unwrap();
+    assert_eq!(last, derive_nonce(6, MAX_NONCE_COUNTER, dir));
+    assert_eq!(
+        sequencer.next_nonce(6, dir),
+        Err(CryptoEnvelopeError::NonceExhausted)
+    );
+    assert_eq!(sequencer.rekey(6, dir), Ok(1));
+    let next = sequencer.next_nonce(6, dir).unwrap();
+    assert_ne!(next, last);
+    assert_ne!(next, derive_nonce(6, 0, dir));
+
+    for epoch in 2..=MAX_NONCE_EPOCH {
+        assert_eq!(sequencer.rekey(6, dir), Ok(epoch));
+    }
+    assert_eq!(
+        sequencer.rekey(6, dir),
+        Err(CryptoEnvelopeError::NonceExhausted)
+    );
+    assert_eq!(
+        sequencer.restore(6, dir, 0, MAX_NONCE_COUNTER + 2),
+        Err(CryptoEnvelopeError::NonceExhausted)
+    );
+    sequencer.forget(6);
+    assert_eq!(sequencer.epoch(6, dir), 0);
+}
+
+#[test]
+fn aad_builder_round_trips_every_field_and_parses_nothing_else() {
+    let plain = AadBuilder::new(2, 9, 3, 8);
+    let full = plain
+        .with_cipher_suite(CipherSuite::Aes256Gcm)
+        .with_file_index(4)
+        .with_chunk_digest([1; 32])
+        .with_file_hash([2; 32])
+        .with_compression(2);
+    assert_eq!(plain.build().len(), CHUNK_AAD_HEADER_LEN);
+    for builder in [plain, full, plain.with_file_hash([3; 32])] {
+        assert_eq!(ChunkAad::parse(&builder.build()), Ok(*builder.fields()));
+    }
+    let parsed = ChunkAad::parse(&full.build()).unwrap();
+    assert_eq!(parsed.cipher_suite, CipherSuite::Aes256Gcm);
+    assert_eq!(parsed.compression, 2);
+    assert_eq!(
+        (parsed.chunk_digest, parsed.file_hash),
+        (Some([1; 32]), Some([2; 32]))
+    );
+
+    let bytes = full.build();
+    let malformed = Err(CryptoEnvelopeError::MalformedAad);
+    assert_eq!(ChunkAad::parse(&bytes[..bytes.len() - 1]), malformed);
+    assert_eq!(
+        ChunkAad::parse(&[bytes.as_slice(), &[0]].concat()),
+        malformed
+    );
+    let mut unknown_suite = bytes.clone();
+    unknown_suite[2] = 0xee;
+    assert_eq!(ChunkAad::parse(&unknown_suite), malformed);
+    // The old bare header (transfer_id | chunk_index | total_chunks) is not this format.
+    assert_eq!(ChunkAad::parse(&[0; 16]), malformed);
+}
+
+#[test]
+fn secret_keys_are_redacted_compared_and_wiped() {
+    let mut key = SecretKeyBytes::new([7; 32]);
+    assert_eq!(key, SecretKeyBytes::from([7; 32]));
+    assert_ne!(key, SecretKeyBytes::new([8; 32]));
+    assert!(!format!("{key:?}").contains('7'));
+
+    key.zeroize();
+    assert_eq!(key.expose(), &[0; 32]);
+
+    let session = SessionKeySecret::new([1; 32], [2; 32]);
+    assert_eq!((session.tx_key(), session.rx_key()), (&[1; 32], &[2; 32]));
+    assert_eq!(format!("{session:?}"), "SessionKeySecret(..)");
+}
+
+#[test]
+fn transfer_keys_are_independent_per_transfer() {
+    let session_key = [6u8; 32];
+    let key = derive_transfer_key(&session_key, 41);
+    assert_eq!(key, derive_transfer_key(&session_key, 41));
+    assert_ne!(key, derive_transfer_key(&session_key, 42));
+    assert_ne!(key, derive_transfer_key(&[7u8; 32], 41));
+    assert_ne!(key.expose(), &session_key);
+}
+
+#[test]
+fn padding_rounds_lengths_up_and_strips_back() {
+    assert_eq!(PaddingScheme::None.padded_len(1000), 1000);
+    assert_eq!(PaddingScheme::Bucket.padded_len(0), MIN_PADDING_BUCKET);
+    assert_eq!(PaddingScheme::Bucket.padded_len(1000), 1024);
+    assert_eq!(PaddingScheme::Bucket.padded_len(1024), 2048);
+    // PADMÉ keeps the top bits of the length: 1_000_001 has exponent 19, so its low
+    // 19 - 5 = 14 bits are rounded away.
+    assert_eq!(PaddingScheme::Padme.padded_len(1_000_000), 1_015_808);
+    for len in [1usize, 100, 65_535, 1 << 20] {
+        let padded = PaddingScheme::Padme.padded_len(len);
+        assert!(padded > len && padded - len <= len / 8 + 1);
+    }
+
+    for scheme in PaddingScheme::ALL {
+        assert_eq!(PaddingScheme::from_u8(scheme.as_u8()), Some(scheme));
+        for payload in [Vec::new(), vec![0; 37], vec![0x80; 300]] {
+            let mut padded = payload.clone();
+            scheme.pad(&mut padded);
+            assert_eq!(padded.len(), scheme.padded_len(payload.len()));
+            if scheme != PaddingScheme::None {
+                unpad(&mut padded).expect("unpad");
+                assert_eq!(padded, payload);
+            }
+        }
+    }
+    assert_eq!(
+        unpad(&mut vec![1, 0, 0]),
+        Err(CryptoEnvelopeError::MalformedPadding)
+    );
+    assert_eq!(
+        unpad(&mut vec![0; 4]),
+        Err(CryptoEnvelopeError::MalformedPadding)
+    );
+}
+
+#[test]
+fn batch_encryption_matches_chunk_by_chunk() {
+    let key = derive_transfer_key(&[0x61u8; 32], 5);
+    let payloads: Vec<Vec<u8>> = (0..8u8).map(|i| vec![i; 100 * i as usize]).collect();
+    let batch: Vec<BatchChunk> = payloads
+        .iter()
+        .enumerate()
+        .map(|(index, payload)| BatchChunk {
+            nonce: derive_nonce(5, index as u32, Direction::SenderToReceiver),
+            plaintext: payload,
+            aad: b"batch",
+        })
+        .collect();
+
+    let sealed = encrypt_chunks(&key, batch.iter().copied()).expect("batch");
+    for (chunk, ciphertext) in batch.iter().zip(&sealed) {
+        let one =
+            encrypt_chunk_with_aad(key.expose(), chunk.nonce, chunk.plaintext, chunk.aad).unwrap();
+        assert_eq!(*ciphertext, one);
+    }
+
+    let cipher = ChunkCipher::new(CipherSuite::Aes256Gcm, key.expose());
+    let sealed = encrypt_chunks_with_suite(CipherSuite::Aes256Gcm, &key, batch.iter().copied())
+        .expect("aes batch");
+    for (chunk, ciphertext) in batch.iter().zip(&sealed) {
+        assert_eq!(
+            cipher.decrypt(chunk.nonce, ciphertext, chunk.aad).unwrap(),
+            chunk.plaintext
+        );
+    }
+    #[cfg(feature = "rayon")]
+    assert_eq!(
+        crypto_envelope::par_encrypt_chunks(CipherSuite::Aes256Gcm, &key, &batch).unwrap(),
+        sealed
+    );
+}
+
+#[test]
+fn manifest_nonce_is_outside_every_chunk_nonce_domain() {
+    let nonce = manifest_nonce(12);
+    assert_eq!(nonce[..8], 12u64.to_be_bytes());
+    for direction in [Direction::SenderToReceiver, Direction::ReceiverToSender] {
+        assert_ne!(nonce, derive_nonce(12, 0, direction));
+        for epoch in 0..=MAX_NONCE_EPOCH {
+            assert_ne!(Ok(nonce), nonce_with_epoch(12, epoch, 0, direction));
+        }
+    }
+}
+
+#[test]
+fn decryption_errors_say_what_failed() {
+    let key = [0x33u8; 32];
+    let nonce = derive_nonce(3, 1, Direction::SenderToReceiver);
+    let ciphertext = encrypt_chunk_with_aad(&key, nonce, b"payload", b"aad").unwrap();
+
+    for suite in CipherSuite::ALL {
+        assert_eq!(
+            decrypt_with_suite(suite, &key, nonce, &ciphertext[..TAG_LEN - 1], b"aad"),
+            Err(CryptoEnvelopeError::TruncatedCiphertext)
+        );
+    }
+    assert_eq!(
+        decrypt_chunk_with_aad(&key, nonce, &ciphertext, b"other"),
+        Err(CryptoEnvelopeError::TagMismatch)
+    );
+    let (body, tag) = ciphertext.split_at(ciphertext.len() - TAG_LEN);
+    let mut stream = DecryptStream::new(CipherSuite::ChaCha20Poly1305, &key, nonce, b"aad");
+    stream.update(&mut body.to_vec()).unwrap();
+    assert_eq!(
+        stream.finalize(&tag[1..]),
+        Err(CryptoEnvelopeError::TruncatedCiphertext)
+    );
+
+    // A guard opens each nonce once, and only a genuine ciphertext uses it up.
+    let mut guard = NonceGuard::new(CipherSuite::ChaCha20Poly1305, &key);
+    assert_eq!(
+        guard.decrypt(nonce, &ciphertext, b"other"),
+        Err(CryptoEnvelopeError::TagMismatch)
+    );
+    assert_eq!(
+        guard.decrypt(nonce, &ciphertext, b"aad").unwrap(),
+        b"payload"
+    );
+    assert_eq!(
+        guard.decrypt(nonce, &ciphertext, b"aad"),
+        Err(CryptoEnvelopeError::NonceReuse)
+    );
+    let reused = encrypt_chunk_with_aad(&key, nonce, b"another", b"aad").unwrap();
+    assert_eq!(guard.check(&nonce), Err(CryptoEnvelopeError::NonceReuse));
+    assert_eq!(
+        guard.decrypt(nonce, &reused, b"aad"),
+        Err(CryptoEnvelopeError::NonceReuse)
+    );
+    assert_eq!(guard.opened(), 1);
+    guard.forget(3);
+    assert_eq!(guard.opened(), 0);
+
+    assert!(ct_eq(b"same tag", b"same tag"));
+    assert!(!ct_eq(b"same tag", b"same taG"));
+    assert!(!ct_eq(b"short", b"longer"));
+}
+
+#[test]
+fn selftest_passes_every_suite_against_its_published_vector() {
+    assert_eq!(crypto_selftest(), Ok(()));
+    assert_eq!(
+        CryptoEnvelopeError::SelfTestFailed(CipherSuite::Aes256Gcm).to_string(),
+        "self-test failed for aes256gcm"
+    );
+}
@@ -1,3 +1,11 @@
+mod notifications;
+mod update_panel;
+
+pub use notifications::{Notification, NotificationKind, NotificationQueue};
+pub use update_panel::{UpdatePanel, UpdatePanelState};
+
+use discovery::{Announcement, FreeSpaceHint, PeerStatus};
+use installer_update::{FeedEntry, InstallPolicy, ManifestFeed, PackageManifest, UpdateChannel};
 use std::collections::HashMap;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -14,6 +22,29 @@ pub struct DeviceCard {
     pub status: DeviceStatus,
 }
 
+impl From<PeerStatus> for DeviceStatus {
+    fn from(status: PeerStatus) -> Self {
+        match status {
+            PeerStatus::Online => DeviceStatus::Online,
+            PeerStatus::Busy => DeviceStatus::Busy,
+        }
+    }
+}
+
+impl DeviceCard {
+    /// Card for a peer that is announcing; without metadata it shows as online.
+    pub fn from_announcement(announcement: &Announcement) -> Self {
+        Self {
+            device_id: announcement.device_id.clone(),
+            display_name: announcement.display_name.clone(),
+            status: announcement
+                .metadata
+                .as_ref()
+                .map_or(DeviceStatus::Online, |m| m.status.into()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum IncomingDecision {
     Pending,
@@ -44,6 +75,36 @@ pub struct TransferItem {
     pub file_name: String,
     pub progress_percent: u8,
     pub state: TransferState,
+    /// Normalized labels from transfer creation, used to filter the dashboard.
+    pub tags: Vec<String>,
+}
+
+/// Sender-side view of an outgoing offer, ordered by how far it has progressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OfferState {
+    Sent,
+    Delivered,
+    Seen,
+    Accepted,
+    Declined,
+    Expired,
+}
+
+impl OfferState {
+    pub fn is_final(self) -> bool {
+        matches!(
+            self,
+            OfferState::Accepted | OfferState::Declined | OfferState::Expired
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingOffer {
+    pub transfer_id: u64,
+    pub target_device_id: String,
+    pub file_name: String,
+    pub state: OfferState,
 }
 
 #[derive(Debug, Default)]
@@ -51,6 +112,12 @@ pub struct DesktopUiState {
     devices: HashMap<String, DeviceCard>,
     incoming_modal: Option<IncomingRequestModal>,
     transfers: HashMap<u64, TransferItem>,
+    outgoing_offers: HashMap<u64, OutgoingOffer>,
+    peer_free_space: HashMap<String, FreeSpaceHint>,
+    notifications: NotificationQueue,
+    update_panel: Option<UpdatePanel>,
+    /// Manifest of the update the panel offers, whose hash [`Self::verify_update`] checks.
+    update_manifest: Option<PackageManifest>,
 }
 
 impl DesktopUiState {
@@ -63,6 +130,12 @@ impl DesktopUiState {
         self.devices.insert(card.device_id.clone(), card);
     }
 
+    /// Refresh a peer's card and free-space hint from its latest announcement.
+    pub fn apply_announcement(&mut self, announcement: &Announcement) {
+        self.upsert_device_card(DeviceCard::from_announcement(announcement));
+        self.record_peer_free_space(&announcement.device_id, announcement.free_space);
+    }
+
     pub fn remove_device_card(&mut self, device_id: &str) {
         self.devices.remove(device_id);
     }
@@ -127,12 +200,233 @@ impl DesktopUiState {
         items.sort_by_key(|t| t.transfer_id);
         items
     }
+
+    pub fn transfers_tagged(&self, tag: &str) -> Vec<&TransferItem> {
+        let mut items = self.transfers();
+        items.retain(|t| t.tags.iter().any(|t| t == tag));
+        items
+    }
+
+    /// Outgoing offer status (sent/delivered/seen/accepted/declined/expired).
+    pub fn track_outgoing_offer(&mut self, offer: OutgoingOffer) {
+        self.outgoing_offers.insert(offer.transfer_id, offer);
+    }
+
+    /// Advance an offer's state; receipts arriving out of order never move it backwards.
+    pub fn advance_offer_state(
+        &mut self,
+        transfer_id: u64,
+        state: OfferState,
+    ) -> Result<(), UiError> {
+        let offer = self
+            .outgoing_offers
+            .get_mut(&transfer_id)
+            .ok_or(UiError::OfferNotFound)?;
+
+        if offer.state.is_final() {
+            return if offer.state == state {
+                Ok(())
+            } else {
+                Err(UiError::OfferAlreadyFinal)
+            };
+        }
+        if state > offer.state {
+            offer.state = state;
+        }
+        Ok(())
+    }
+
+    pub fn outgoing_offer(&self, transfer_id: u64) -> Option<&OutgoingOffer> {
+        self.outgoing_offers.get(&transfer_id)
+    }
+
+    pub fn outgoing_offers(&self) -> Vec<&OutgoingOffer> {
+        let mut items: Vec<&OutgoingOffer> = self.outgoing_offers.values().collect();
+        items.sort_by_key(|o| o.transfer_id);
+        items
+    }
+
+    /// Remember the free-space bucket a peer advertised (announcement or offer acceptance).
+    ///
+    /// `None` clears it, since a peer may stop advertising.
+    pub fn record_peer_free_space(&mut self, device_id: &str, hint: Option<FreeSpaceHint>) {
+        match hint {
+            Some(hint) => {
+                self.peer_free_space.insert(device_id.to_string(), hint);
+            }
+            None => {
+                self.peer_free_space.remove(device_id);
+            }
+        }
+    }
+
+    pub fn peer_free_space(&self, device_id: &str) -> Option<FreeSpaceHint> {
+        self.peer_free_space.get(device_id).copied()
+    }
+
+    /// Peers that never advertised space are given the benefit of the doubt.
+    pub fn peer_likely_lacks_space(&self, device_id: &str, size_bytes: u64) -> bool {
+        self.peer_free_space(device_id)
+            .is_some_and(|hint| hint.likely_lacks_space(size_bytes))
+    }
+
+    /// Warn before a transfer starts if the peer's advertised space cannot hold it.
+    pub fn warn_if_peer_lacks_space(
+        &mut self,
+        device_id: &str,
+        file_name: &str,
+        size_bytes: u64,
+    ) -> bool {
+        if !self.peer_likely_lacks_space(device_id, size_bytes) {
+            return false;
+        }
+        let body = format!(
+            "{} reports less free space than {file_name} needs.",
+            self.device_name(device_id)
+        );
+        self.notifications.push(
+            NotificationKind::PeerLowSpace {
+                device_id: device_id.to_string(),
+            },
+            "Peer likely lacks space",
+            &body,
+        );
+        true
+    }
+
+    /// Summarize offers dropped from a muted peer instead of showing each one.
+    pub fn notify_suppressed_offers(&mut self, device_id: &str, count: u32) {
+        let noun = if count == 1 { "offer" } else { "offers" };
+        let body = format!(
+            "{count} {noun} from {} were suppressed while it was muted for spamming.",
+            self.device_name(device_id)
+        );
+        self.notifications.push(
+            NotificationKind::OffersSuppressed {
+                device_id: device_id.to_string(),
+                count,
+            },
+            "Offers suppressed",
+            &body,
+        );
+    }
+
+    /// Display name from the device card, falling back to the raw id.
+    fn device_name<'a>(&'a self, device_id: &'a str) -> &'a str {
+        self.devices
+            .get(device_id)
+            .map_or(device_id, |card| card.display_name.as_str())
+    }
+
+    pub fn notifications(&self) -> &NotificationQueue {
+        &self.notifications
+    }
+
+    pub fn notifications_mut(&mut self) -> &mut NotificationQueue {
+        &mut self.notifications
+    }
+
+    /// Run a feed check and surface the best candidate, if any.
+    pub fn check_for_update(
+        &mut self,
+        feed: &ManifestFeed,
+        current_version: &str,
+        channel: UpdateChannel,
+        platform: &str,
+        policy: &InstallPolicy,
+    ) -> bool {
+        match feed.check(current_version, channel, platform, policy) {
+            Some(entry) => {
+                self.notify_update_available(current_version, entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Update panel: surface a feed candidate and notify once per candidate version.
+    pub fn notify_update_available(&mut self, current_version: &str, entry: &FeedEntry) {
+        let version = &entry.manifest.version;
+        if let Some(panel) = &self.update_panel {
+            if &panel.candidate_version == version && panel.state != UpdatePanelState::Failed {
+                return;
+            }
+        }
+
+        self.update_panel = Some(UpdatePanel {
+            current_version: current_version.to_string(),
+            candidate_version: version.clone(),
+            changelog_url: entry.changelog_url.clone(),
+            state: UpdatePanelState::Available,
+        });
+        self.update_manifest = Some(entry.manifest.clone());
+        self.notifications.push(
+            NotificationKind::UpdateAvailable {
+                version: version.clone(),
+            },
+            "Update available",
+            &format!("Version {version} is ready to download."),
+        );
+    }
+
+    /// Move the panel to `state`. Verifying and ready are refused: only
+    /// [`Self::verify_update`] reaches them, once the installer has checked the package.
+    pub fn advance_update(&mut self, state: UpdatePanelState) -> Result<(), UiError> {
+        if matches!(state, UpdatePanelState::Verifying | UpdatePanelState::Ready) {
+            return Err(UiError::InvalidUpdateTransition);
+        }
+        self.set_update_state(state)
+    }
+
+    /// Check the downloaded package against the offered manifest: the panel moves
+    /// through verifying to ready, or to failed if the package does not match.
+    pub fn verify_update(&mut self, package: &[u8]) -> Result<(), UiError> {
+        let manifest = self
+            .update_manifest
+            .clone()
+            .ok_or(UiError::NoUpdateAvailable)?;
+        self.set_update_state(UpdatePanelState::Verifying)?;
+        match installer_update::verify_package(&manifest, package) {
+            Ok(()) => self.set_update_state(UpdatePanelState::Ready),
+            Err(_) => {
+                self.set_update_state(UpdatePanelState::Failed)?;
+                Err(UiError::UpdatePackageMismatch)
+            }
+        }
+    }
+
+    fn set_update_state(&mut self, state: UpdatePanelState) -> Result<(), UiError> {
+        let panel = self
+            .update_panel
+            .as_mut()
+            .ok_or(UiError::NoUpdateAvailable)?;
+        if !panel.state.can_transition_to(state) {
+            return Err(UiError::InvalidUpdateTransition);
+        }
+        panel.state = state;
+        Ok(())
+    }
+
+    /// One-click apply: start downloading the offered update.
+    pub fn apply_update(&mut self) -> Result<(), UiError> {
+        self.advance_update(UpdatePanelState::Downloading)
+    }
+
+    pub fn update_panel(&self) -> Option<&UpdatePanel> {
+        self.update_panel.as_ref()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum UiError {
     NoIncomingRequest,
     TransferNotFound,
+    OfferNotFound,
+    OfferAlreadyFinal,
+    NoUpdateAvailable,
+    InvalidUpdateTransition,
+    /// The downloaded update does not match its manifest; the panel is marked failed.
+    UpdatePackageMismatch,
 }
 
 impl std::fmt::Display for UiError {
@@ -140,6 +434,11 @@ impl std::fmt::Display for UiError {
         match self {
             UiError::NoIncomingRequest => write!(f, "no incoming request modal is open"),
             UiError::TransferNotFound => write!(f, "transfer not found"),
+            UiError::OfferNotFound => write!(f, "offer not found"),
+            UiError::OfferAlreadyFinal => write!(f, "offer already reached a final state"),
+            UiError::NoUpdateAvailable => write!(f, "no update available"),
+            UiError::InvalidUpdateTransition => write!(f, "invalid update state transition"),
+            UiError::UpdatePackageMismatch => write!(f, "update package failed verification"),
         }
     }
 }
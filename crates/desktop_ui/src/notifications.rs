@@ -0,0 +1,60 @@
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotificationKind {
+    Info,
+    UpdateAvailable { version: String },
+    PeerLowSpace { device_id: String },
+    OffersSuppressed { device_id: String, count: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub id: u64,
+    pub kind: NotificationKind,
+    pub title: String,
+    pub body: String,
+}
+
+/// Pending toasts shown to the user, oldest first.
+#[derive(Debug, Default)]
+pub struct NotificationQueue {
+    next_id: u64,
+    items: VecDeque<Notification>,
+}
+
+impl NotificationQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, kind: NotificationKind, title: &str, body: &str) -> u64 {
+        self.next_id += 1;
+        self.items.push_back(Notification {
+            id: self.next_id,
+            kind,
+            title: title.to_string(),
+            body: body.to_string(),
+        });
+        self.next_id
+    }
+
+    pub fn pending(&self) -> Vec<&Notification> {
+        self.items.iter().collect()
+    }
+
+    /// Remove a notification; returns false if it was already gone.
+    pub fn dismiss(&mut self, id: u64) -> bool {
+        let before = self.items.len();
+        self.items.retain(|n| n.id != id);
+        self.items.len() != before
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
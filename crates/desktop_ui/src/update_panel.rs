@@ -0,0 +1,57 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdatePanelState {
+    Available,
+    Downloading,
+    Verifying,
+    Ready,
+    Installing,
+    Failed,
+}
+
+impl UpdatePanelState {
+    /// Forward steps of the update flow; a failed step can be retried from the download.
+    pub fn can_transition_to(self, next: UpdatePanelState) -> bool {
+        use UpdatePanelState::*;
+        matches!(
+            (self, next),
+            (Available, Downloading)
+                | (Downloading, Verifying)
+                | (Verifying, Ready)
+                | (Ready, Installing)
+                | (Downloading | Verifying | Installing, Failed)
+                | (Failed, Downloading)
+        )
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UpdatePanelState::Available => "available",
+            UpdatePanelState::Downloading => "downloading",
+            UpdatePanelState::Verifying => "verifying",
+            UpdatePanelState::Ready => "ready",
+            UpdatePanelState::Installing => "installing",
+            UpdatePanelState::Failed => "failed",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        [
+            UpdatePanelState::Available,
+            UpdatePanelState::Downloading,
+            UpdatePanelState::Verifying,
+            UpdatePanelState::Ready,
+            UpdatePanelState::Installing,
+            UpdatePanelState::Failed,
+        ]
+        .into_iter()
+        .find(|s| s.as_str() == value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdatePanel {
+    pub current_version: String,
+    pub candidate_version: String,
+    pub changelog_url: String,
+    pub state: UpdatePanelState,
+}
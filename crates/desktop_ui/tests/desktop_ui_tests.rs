@@ -1,6 +1,8 @@
+use discovery::{Announcement, FreeSpaceHint, PeerMetadata, PeerStatus};
 use desktop_ui::{
-    DesktopUiState, DeviceCard, DeviceStatus, IncomingDecision, IncomingRequestModal, TransferItem,
-    TransferState,
+    DesktopUiState, DeviceCard, DeviceStatus, IncomingDecision, IncomingRequestModal,
+    NotificationKind, OfferState, OutgoingOffer, TransferItem, TransferState, UiError,
+    UpdatePanelState,
 };
 
 #[test]
@@ -52,7 +54,23 @@ fn transfer_dashboard_progress_completion_and_failure() {
         file_name: "video.mp4".into(),
         progress_percent: 0,
         state: TransferState::InProgress,
+        tags: vec!["project-x".into()],
     });
+    ui.add_transfer(TransferItem {
+        transfer_id: 11,
+        target_device_id: "peer-2".into(),
+        file_name: "photos.zip".into(),
+        progress_percent: 0,
+        state: TransferState::Queued,
+        tags: vec!["backup".into(), "project-x".into()],
+    });
+    let tagged: Vec<u64> = ui
+        .transfers_tagged("backup")
+        .iter()
+        .map(|t| t.transfer_id)
+        .collect();
+    assert_eq!(tagged, vec![11]);
+    assert_eq!(ui.transfers_tagged("project-x").len(), 2);
 
     ui.update_transfer_progress(10, 60).expect("progress update");
     assert_eq!(ui.transfers()[0].progress_percent, 60);
@@ -75,3 +93,163 @@ fn updating_unknown_transfer_fails() {
         .expect_err("unknown transfer should fail");
     assert_eq!(err.to_string(), "transfer not found");
 }
+
+#[test]
+fn outgoing_offer_states_only_move_forward() {
+    let mut ui = DesktopUiState::new();
+    ui.track_outgoing_offer(OutgoingOffer {
+        transfer_id: 5,
+        target_device_id: "peer-3".into(),
+        file_name: "notes.txt".into(),
+        state: OfferState::Sent,
+    });
+
+    ui.advance_offer_state(5, OfferState::Seen).expect("seen");
+    // A late delivery receipt must not regress the state.
+    ui.advance_offer_state(5, OfferState::Delivered)
+        .expect("late receipt");
+    assert_eq!(ui.outgoing_offer(5).expect("offer").state, OfferState::Seen);
+
+    ui.advance_offer_state(5, OfferState::Declined)
+        .expect("declined");
+    let err = ui
+        .advance_offer_state(5, OfferState::Accepted)
+        .expect_err("final state is sticky");
+    assert_eq!(err, UiError::OfferAlreadyFinal);
+}
+
+#[test]
+fn sender_is_warned_when_peer_advertises_too_little_space() {
+    const GIB: u64 = 1024 * 1024 * 1024;
+    let mut ui = DesktopUiState::new();
+    ui.upsert_device_card(DeviceCard {
+        device_id: "peer-b".into(),
+        display_name: "Priya Phone".into(),
+        status: DeviceStatus::Online,
+    });
+
+    // No advertisement: nothing to warn about.
+    assert!(!ui.warn_if_peer_lacks_space("peer-b", "movie.mkv", 40 * GIB));
+
+    ui.record_peer_free_space("peer-b", Some(FreeSpaceHint::from_free_bytes(2 * GIB)));
+    assert!(!ui.warn_if_peer_lacks_space("peer-b", "notes.txt", GIB));
+    assert!(ui.warn_if_peer_lacks_space("peer-b", "movie.mkv", 40 * GIB));
+
+    let pending = ui.notifications().pending();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(
+        pending[0].kind,
+        NotificationKind::PeerLowSpace {
+            device_id: "peer-b".into()
+        }
+    );
+    assert_eq!(pending[0].title, "Peer likely lacks space");
+    assert!(pending[0].body.starts_with("Priya Phone"));
+
+    ui.record_peer_free_space("peer-b", None);
+    assert!(!ui.peer_likely_lacks_space("peer-b", 40 * GIB));
+}
+
+#[test]
+fn update_feed_check_notifies_once_and_drives_panel_state_machine() {
+    let feed = installer_update::ManifestFeed::new(vec![installer_update::FeedEntry {
+        manifest: installer_update::PackageManifest {
+            version: "1.3.0".to_string(),
+            channel: installer_update::UpdateChannel::Stable,
+            platform: "linux-x86_64".to_string(),
+            package_url: "https://example.com/p2p-1.3.0.tar.gz".to_string(),
+            // SHA-256 of b"abc".
+            sha256: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string(),
+            rollback_from: None,
+        },
+        changelog_url: "https://example.com/changelog/1.3.0".to_string(),
+    }]);
+    let policy = installer_update::InstallPolicy::default();
+
+    let mut ui = DesktopUiState::new();
+    assert_eq!(ui.apply_update(), Err(UiError::NoUpdateAvailable));
+    for _ in 0..2 {
+        assert!(ui.check_for_update(
+            &feed,
+            "1.2.0",
+            installer_update::UpdateChannel::Stable,
+            "linux-x86_64",
+            &policy
+        ));
+    }
+    let pending = ui.notifications().pending();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(
+        pending[0].kind,
+        NotificationKind::UpdateAvailable {
+            version: "1.3.0".into()
+        }
+    );
+
+    let panel = ui.update_panel().expect("panel");
+    assert_eq!(panel.current_version, "1.2.0");
+    assert_eq!(panel.state, UpdatePanelState::Available);
+
+    assert_eq!(
+        ui.advance_update(UpdatePanelState::Ready),
+        Err(UiError::InvalidUpdateTransition)
+    );
+    assert_eq!(
+        ui.verify_update(b"abc"),
+        Err(UiError::InvalidUpdateTransition)
+    );
+    ui.apply_update().expect("apply");
+    // Verifying and ready come only from the installer's check of the package.
+    for state in [UpdatePanelState::Verifying, UpdatePanelState::Ready] {
+        assert_eq!(
+            ui.advance_update(state),
+            Err(UiError::InvalidUpdateTransition)
+        );
+    }
+    assert_eq!(
+        ui.verify_update(b"abd"),
+        Err(UiError::UpdatePackageMismatch)
+    );
+    assert_eq!(
+        ui.update_panel().map(|p| p.state),
+        Some(UpdatePanelState::Failed)
+    );
+    ui.apply_update().expect("retry");
+    ui.verify_update(b"abc").expect("verify");
+    assert_eq!(
+        ui.update_panel().map(|p| p.state),
+        Some(UpdatePanelState::Ready)
+    );
+    ui.advance_update(UpdatePanelState::Installing)
+        .expect("install");
+    assert_eq!(
+        ui.update_panel().map(|p| p.state),
+        Some(UpdatePanelState::Installing)
+    );
+}
+
+#[test]
+fn announcement_metadata_drives_device_status() {
+    let mut announcement = Announcement {
+        device_id: "peer-c".into(),
+        public_key_b64: "PUBKEYBASE64".into(),
+        display_name: "Office Desktop".into(),
+        port: 7000,
+        free_space: Some(FreeSpaceHint::from_free_bytes(1 << 40)),
+        metadata: None,
+    };
+    let mut ui = DesktopUiState::new();
+    ui.apply_announcement(&announcement);
+    assert_eq!(ui.device_cards()[0].status, DeviceStatus::Online);
+    assert!(ui.peer_free_space("peer-c").is_some());
+
+    announcement.metadata = Some(PeerMetadata {
+        status: PeerStatus::Busy,
+        ..PeerMetadata::default()
+    });
+    announcement.free_space = None;
+    ui.apply_announcement(&announcement);
+    assert_eq!(ui.device_cards().len(), 1);
+    assert_eq!(ui.device_cards()[0].status, DeviceStatus::Busy);
+    assert_eq!(ui.peer_free_space("peer-c"), None);
+}
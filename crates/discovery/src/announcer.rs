@@ -0,0 +1,250 @@
+use crate::{random_u64, Announcement, DiscoveryError, LeaveMessage, SignedAnnouncement};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Shortest delay between announcements whatever the config asks for, so a zero or
+/// near-zero interval cannot spin the announcer thread and flood the network.
+pub const MIN_ANNOUNCE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Where and how often an [`Announcer`] sends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnouncerConfig {
+    /// Local address the sending socket binds to; port 0 picks a fresh one on each bind.
+    pub bind_addr: SocketAddr,
+    /// Multicast group, broadcast address or single peer to announce to.
+    pub target: SocketAddr,
+    pub interval: Duration,
+    /// Each delay is `interval` moved by up to this much either way, so devices that
+    /// start together do not keep announcing in lockstep. Capped at `interval`.
+    pub jitter: Duration,
+}
+
+impl AnnouncerConfig {
+    pub fn new(bind_addr: SocketAddr, target: SocketAddr, interval: Duration) -> Self {
+        Self {
+            bind_addr,
+            target,
+            interval,
+            jitter: interval / 4,
+        }
+    }
+
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Delay before the next announcement for a random `sample`, within
+    /// `interval ± jitter` but never below [`MIN_ANNOUNCE_INTERVAL`].
+    pub fn delay_for(&self, sample: u64) -> Duration {
+        let jitter = self.jitter.min(self.interval).as_nanos() as u64;
+        let delay = if jitter == 0 {
+            self.interval
+        } else {
+            let offset = sample % (2 * jitter + 1);
+            (self.interval + Duration::from_nanos(offset)) - Duration::from_nanos(jitter)
+        };
+        delay.max(MIN_ANNOUNCE_INTERVAL)
+    }
+}
+
+enum Command {
+    Update(Vec<u8>),
+    Rebind,
+    Leave(Vec<u8>),
+    Stop,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    sent: AtomicU64,
+    rebinds: AtomicU64,
+}
+
+/// Sends an announcement periodically from a background thread.
+///
+/// A failed send usually means the interface the socket was bound through went away,
+/// so the socket is dropped and bound again before the next attempt; the same happens
+/// on [`AnnouncerHandle::rebind`] when the caller learns of a network change itself.
+pub struct Announcer;
+
+impl Announcer {
+    /// Bind the socket and send the first announcement right away, then keep going
+    /// until the returned handle is stopped or dropped.
+    pub fn start(
+        config: AnnouncerConfig,
+        announcement: &Announcement,
+    ) -> Result<AnnouncerHandle, DiscoveryError> {
+        Self::spawn(config, announcement.encode()?)
+    }
+
+    /// [`Self::start`] for a signed announcement.
+    pub fn start_signed(
+        config: AnnouncerConfig,
+        signed: &SignedAnnouncement,
+    ) -> Result<AnnouncerHandle, DiscoveryError> {
+        Self::spawn(config, signed.encode()?)
+    }
+
+    fn spawn(
+        config: AnnouncerConfig,
+        mut packet: Vec<u8>,
+    ) -> Result<AnnouncerHandle, DiscoveryError> {
+        let mut socket = Some(bind_socket(&config)?);
+        let counters = Arc::new(Counters::default());
+        let (commands, inbox) = mpsc::channel();
+        let thread_counters = Arc::clone(&counters);
+        let thread = thread::spawn(move || loop {
+            if socket.is_none() {
+                socket = bind_socket(&config).ok();
+                if socket.is_some() {
+                    thread_counters.rebinds.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            if let Some(bound) = &socket {
+                match bound.send_to(&packet, config.target) {
+                    Ok(_) => {
+                        thread_counters.sent.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(_) => socket = None,
+                }
+            }
+
+            match inbox.recv_timeout(config.delay_for(random_u64())) {
+                Ok(Command::Update(next)) => packet = next,
+                Ok(Command::Rebind) => socket = None,
+                Ok(Command::Leave(leave)) => {
+                    if let Some(bound) = socket.or_else(|| bind_socket(&config).ok()) {
+                        let _ = bound.send_to(&leave, config.target);
+                    }
+                    break;
+                }
+                Ok(Command::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+        });
+        Ok(AnnouncerHandle {
+            commands,
+            thread: Some(thread),
+            counters,
+        })
+    }
+}
+
+/// Controls a running [`Announcer`]; dropping it stops the announcer too.
+///
+/// Commands other than stop also trigger an announcement straight away, so peers see
+/// an update or the new socket without waiting out the interval.
+#[derive(Debug)]
+pub struct AnnouncerHandle {
+    commands: Sender<Command>,
+    thread: Option<JoinHandle<()>>,
+    counters: Arc<Counters>,
+}
+
+impl AnnouncerHandle {
+    /// Announce `announcement` from now on, e.g. after the free space changed.
+    pub fn update(&self, announcement: &Announcement) -> Result<(), DiscoveryError> {
+        let _ = self.commands.send(Command::Update(announcement.encode()?));
+        Ok(())
+    }
+
+    /// [`Self::update`] for a signed announcement.
+    pub fn update_signed(&self, signed: &SignedAnnouncement) -> Result<(), DiscoveryError> {
+        let _ = self.commands.send(Command::Update(signed.encode()?));
+        Ok(())
+    }
+
+    /// Bind a new socket, for when the caller knows the network changed.
+    pub fn rebind(&self) {
+        let _ = self.commands.send(Command::Rebind);
+    }
+
+    /// Announcements sent so far.
+    pub fn sent(&self) -> u64 {
+        self.counters.sent.load(Ordering::Relaxed)
+    }
+
+    /// Times the socket was bound again after the first bind.
+    pub fn rebinds(&self) -> u64 {
+        self.counters.rebinds.load(Ordering::Relaxed)
+    }
+
+    /// Stop announcing and wait for the background thread to exit.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    /// Send `leave` to the target in place of the next announcement, then stop, so peers
+    /// drop this device without waiting for it to expire.
+    pub fn leave(mut self, leave: &LeaveMessage) -> Result<(), DiscoveryError> {
+        let _ = self.commands.send(Command::Leave(leave.encode()?));
+        self.shutdown();
+        Ok(())
+    }
+
+    fn shutdown(&mut self) {
+        let _ = self.commands.send(Command::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for AnnouncerHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn bind_socket(config: &AnnouncerConfig) -> Result<UdpSocket, DiscoveryError> {
+    let socket = UdpSocket::bind(config.bind_addr)?;
+    if let IpAddr::V4(target) = config.target.ip() {
+        if !target.is_multicast() {
+            socket.set_broadcast(true)?;
+        } else if let IpAddr::V4(local) = config.bind_addr.ip() {
+            if !local.is_unspecified() {
+                set_multicast_if_v4(&socket, local)?;
+            }
+        }
+    }
+    Ok(socket)
+}
+
+/// Send IPv4 multicast out of the interface that owns `local`. Binding to the address
+/// alone does not do it: the kernel picks the egress for a group from its routing
+/// table unless `IP_MULTICAST_IF` says otherwise.
+#[cfg(unix)]
+fn set_multicast_if_v4(socket: &UdpSocket, local: Ipv4Addr) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let addr = libc::in_addr {
+        s_addr: u32::from_ne_bytes(local.octets()),
+    };
+    // SAFETY: the descriptor stays open for the call, and `addr` is an `in_addr`
+    // living on the stack with exactly the length passed.
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_MULTICAST_IF,
+            (&addr as *const libc::in_addr).cast(),
+            std::mem::size_of::<libc::in_addr>() as libc::socklen_t,
+        )
+    };
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Elsewhere the bound address already selects the multicast interface.
+#[cfg(not(unix))]
+fn set_multicast_if_v4(_socket: &UdpSocket, _local: Ipv4Addr) -> std::io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use crate::{
+    Announcement, Announcer, AnnouncerConfig, AnnouncerHandle, DiscoveryError, MULTICAST_GROUP_V4,
+    MULTICAST_GROUP_V6,
+};
+use if_addrs::IfAddr;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// One address of a local network interface, e.g. the Wi-Fi adapter's IPv4 address.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LocalInterface {
+    pub name: String,
+    /// OS interface index; the scope id of link-local IPv6 addresses on this interface.
+    pub index: u32,
+    pub addr: IpAddr,
+    pub prefix_len: u8,
+}
+
+impl LocalInterface {
+    /// Whether a packet from `source` most likely arrived over this interface: a
+    /// link-local IPv6 source by its scope id, anything else by this address's subnet.
+    pub fn contains(&self, source: SocketAddr) -> bool {
+        match (self.addr, source) {
+            (IpAddr::V4(own), SocketAddr::V4(peer)) => {
+                prefix_matches(&own.octets(), &peer.ip().octets(), self.prefix_len)
+            }
+            (IpAddr::V6(own), SocketAddr::V6(peer)) if peer.scope_id() != 0 => {
+                peer.scope_id() == self.index && own.segments()[0] & 0xffc0 == 0xfe80
+            }
+            (IpAddr::V6(own), SocketAddr::V6(peer)) => {
+                prefix_matches(&own.octets(), &peer.ip().octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+
+    /// The announcement group, reached through this interface.
+    pub fn multicast_target(&self, port: u16) -> SocketAddr {
+        match self.addr {
+            IpAddr::V4(_) => SocketAddr::new(IpAddr::V4(MULTICAST_GROUP_V4), port),
+            IpAddr::V6(_) => {
+                SocketAddr::V6(SocketAddrV6::new(MULTICAST_GROUP_V6, port, 0, self.index))
+            }
+        }
+    }
+}
+
+/// An address block such as `192.168.1.0/24` or `fd00::/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    pub addr: IpAddr,
+    pub prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(own), IpAddr::V4(ip)) => {
+                prefix_matches(&own.octets(), &ip.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(own), IpAddr::V6(ip)) => {
+                prefix_matches(&own.octets(), &ip.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = DiscoveryError;
+
+    /// `addr/prefix`; a bare address is a single-host block.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix)) => (
+                addr,
+                Some(
+                    prefix
+                        .parse::<u8>()
+                        .map_err(|_| DiscoveryError::InvalidSubnet)?,
+                ),
+            ),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| DiscoveryError::InvalidSubnet)?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = prefix_len.unwrap_or(max);
+        if prefix_len > max {
+            return Err(DiscoveryError::InvalidSubnet);
+        }
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+/// Addresses of every interface that can reach other devices, loopback excluded.
+pub fn local_interfaces() -> Result<Vec<LocalInterface>, DiscoveryError> {
+    Ok(if_addrs::get_if_addrs()?
+        .into_iter()
+        .filter(|iface| !iface.is_loopback())
+        .map(|iface| {
+            let prefix_len = match &iface.addr {
+                IfAddr::V4(v4) => v4.prefixlen,
+                IfAddr::V6(v6) => v6.prefixlen,
+            };
+            LocalInterface {
+                addr: iface.ip(),
+                index: iface.index.unwrap_or(0),
+                name: iface.name,
+                prefix_len,
+            }
+        })
+        .collect())
+}
+
+/// The interface a packet from `source` most likely came in on.
+pub fn interface_for(interfaces: &[LocalInterface], source: SocketAddr) -> Option<&LocalInterface> {
+    interfaces.iter().find(|iface| iface.contains(source))
+}
+
+impl AnnouncerConfig {
+    /// Announce to the group from `interface`'s own address, so the announcement leaves
+    /// through that interface rather than whichever one the default route uses: IPv6 by
+    /// the scope id, IPv4 by setting the socket's multicast interface to that address.
+    pub fn for_interface(interface: &LocalInterface, port: u16, interval: Duration) -> Self {
+        let bind_addr = match interface.addr {
+            IpAddr::V6(ip) => SocketAddr::V6(SocketAddrV6::new(ip, 0, 0, interface.index)),
+            ip => SocketAddr::new(ip, 0),
+        };
+        Self::new(bind_addr, interface.multicast_target(port), interval)
+    }
+}
+
+impl Announcer {
+    /// Start one announcer per interface, so every network this device is on hears it.
+    ///
+    /// Stops the ones already started and fails if any interface cannot be bound.
+    pub fn start_per_interface(
+        interfaces: &[LocalInterface],
+        port: u16,
+        interval: Duration,
+        announcement: &Announcement,
+    ) -> Result<Vec<(LocalInterface, AnnouncerHandle)>, DiscoveryError> {
+        interfaces
+            .iter()
+            .map(|iface| {
+                let config = AnnouncerConfig::for_interface(iface, port, interval);
+                Ok((iface.clone(), Announcer::start(config, announcement)?))
+            })
+            .collect()
+    }
+}
+
+fn prefix_matches(own: &[u8], peer: &[u8], prefix_len: u8) -> bool {
+    let full = usize::from(prefix_len / 8).min(own.len());
+    if own[..full] != peer[..full] {
+        return false;
+    }
+    let rest = prefix_len % 8;
+    rest == 0 || full == own.len() || (own[full] ^ peer[full]) >> (8 - rest) == 0
+}
@@ -0,0 +1,90 @@
+use crate::{push_str, read_str, DiscoveryError, MAGIC, MAX_DEVICE_ID_LEN, MAX_PUBLIC_KEY_LEN};
+use identity::{verify_signature, DeviceIdentity};
+
+/// Wire version of a leave packet.
+pub const LEAVE_VERSION: u8 = 4;
+const SIGNATURE_CONTEXT: &[u8] = b"p2p/discovery/v1/leave";
+const SIGNATURE_LEN: usize = 64;
+
+/// Sent by a device shutting down so peers drop it now rather than at the TTL.
+///
+/// Like [`crate::SignedAnnouncement`], it is always signed by `public_key_b64`:
+/// [`Self::sign`] and [`Self::decode`] are the only ways to get one. It carries no
+/// timestamp, so a replayed leave removes a peer only until its next announcement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeaveMessage {
+    device_id: String,
+    public_key_b64: String,
+    signature: [u8; SIGNATURE_LEN],
+}
+
+impl LeaveMessage {
+    pub fn sign(
+        device_id: impl Into<String>,
+        identity: &DeviceIdentity,
+    ) -> Result<Self, DiscoveryError> {
+        let device_id = device_id.into();
+        let public_key_b64 = identity.public_key_b64();
+        let signature = identity.sign(&signed_message(&encode_body(&device_id, &public_key_b64)?));
+        Ok(Self {
+            device_id,
+            public_key_b64,
+            signature,
+        })
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    pub fn public_key_b64(&self) -> &str {
+        &self.public_key_b64
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, DiscoveryError> {
+        // MAGIC | version(u8) | len+device_id | len+public_key | signature(64)
+        let body = encode_body(&self.device_id, &self.public_key_b64)?;
+        let mut out = Vec::with_capacity(4 + 1 + body.len() + SIGNATURE_LEN);
+        out.extend_from_slice(MAGIC);
+        out.push(LEAVE_VERSION);
+        out.extend_from_slice(&body);
+        out.extend_from_slice(&self.signature);
+        Ok(out)
+    }
+
+    /// Decode and verify against the public key the leave carries.
+    pub fn decode(input: &[u8]) -> Result<Self, DiscoveryError> {
+        if input.len() < 5 + SIGNATURE_LEN || &input[..4] != MAGIC || input[4] != LEAVE_VERSION {
+            return Err(DiscoveryError::InvalidPacket("bad magic/header"));
+        }
+        let body = &input[5..input.len() - SIGNATURE_LEN];
+        let mut idx = 0;
+        let device_id = read_str(body, &mut idx, MAX_DEVICE_ID_LEN, "device_id")?;
+        let public_key_b64 = read_str(body, &mut idx, MAX_PUBLIC_KEY_LEN, "public_key")?;
+        if idx != body.len() {
+            return Err(DiscoveryError::InvalidPacket("trailing bytes"));
+        }
+        let signature: [u8; SIGNATURE_LEN] = input[input.len() - SIGNATURE_LEN..]
+            .try_into()
+            .expect("slice len");
+        match verify_signature(&public_key_b64, &signed_message(body), &signature) {
+            Ok(true) => Ok(Self {
+                device_id,
+                public_key_b64,
+                signature,
+            }),
+            _ => Err(DiscoveryError::InvalidSignature),
+        }
+    }
+}
+
+fn encode_body(device_id: &str, public_key_b64: &str) -> Result<Vec<u8>, DiscoveryError> {
+    let mut body = Vec::with_capacity(2 + device_id.len() + 2 + public_key_b64.len());
+    push_str(&mut body, device_id, MAX_DEVICE_ID_LEN, "device_id")?;
+    push_str(&mut body, public_key_b64, MAX_PUBLIC_KEY_LEN, "public_key")?;
+    Ok(body)
+}
+
+fn signed_message(body: &[u8]) -> Vec<u8> {
+    [SIGNATURE_CONTEXT, body].concat()
+}
@@ -1,8 +1,77 @@
-use std::collections::HashMap;
-use std::net::{SocketAddr, UdpSocket};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
 use std::time::{Duration, Instant};
 
+mod announcer;
+mod interfaces;
+mod leave;
+mod metadata;
+mod probe;
+mod query;
+mod signed;
+
+pub use announcer::{Announcer, AnnouncerConfig, AnnouncerHandle, MIN_ANNOUNCE_INTERVAL};
+pub use interfaces::{interface_for, local_interfaces, Cidr, LocalInterface};
+pub use leave::{LeaveMessage, LEAVE_VERSION};
+pub use metadata::{PeerMetadata, PeerStatus, MAX_CAPABILITIES, MAX_PLATFORM_LEN, MAX_TRANSFER_VERSIONS};
+pub use probe::{LatencyProber, Ping, Pong, PING_VERSION, PONG_VERSION};
+pub use query::{DiscoveryQuery, DiscoveryResponse, QueryTarget, MAX_CAPABILITY_LEN, QUERY_VERSION, RESPONSE_VERSION};
+pub use signed::{SignedAnnouncement, ANNOUNCEMENT_VERSION_SIGNED};
+
 const MAGIC: &[u8; 4] = b"P2PD";
+/// Wire version written after MAGIC. Packets without it are the legacy (v0) layout.
+pub const ANNOUNCEMENT_VERSION: u8 = 1;
+/// Version 1 plus a trailing free-space hint byte; only written when a hint is advertised,
+/// so peers that do not advertise one stay readable by version 1 decoders.
+pub const ANNOUNCEMENT_VERSION_SPACE_HINT: u8 = 2;
+/// Version 1 plus TLV fields carrying [`PeerMetadata`] and the free-space hint; only
+/// written when metadata is advertised. Versions 3 to 6 are the other packet kinds.
+pub const ANNOUNCEMENT_VERSION_METADATA: u8 = 7;
+
+/// Administratively scoped IPv4 group for LAN announcements.
+pub const MULTICAST_GROUP_V4: Ipv4Addr = Ipv4Addr::new(239, 255, 77, 77);
+/// Link-local scoped IPv6 group, used on IPv6-only networks.
+pub const MULTICAST_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x7777);
+
+pub const MAX_DEVICE_ID_LEN: usize = 128;
+pub const MAX_PUBLIC_KEY_LEN: usize = 128;
+pub const MAX_DISPLAY_NAME_LEN: usize = 256;
+/// Largest packet decoded by default; well above the largest one this crate encodes.
+pub const MAX_PACKET_LEN: usize = 4096;
+
+/// Bounds applied while decoding packets from the network.
+///
+/// The defaults match what encoders accept. Tighter limits make hostile traffic cheaper to
+/// reject; a packet or field over its limit fails before anything is allocated for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    pub max_packet_len: usize,
+    pub max_device_id_len: usize,
+    pub max_public_key_len: usize,
+    pub max_display_name_len: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_packet_len: MAX_PACKET_LEN,
+            max_device_id_len: MAX_DEVICE_ID_LEN,
+            max_public_key_len: MAX_PUBLIC_KEY_LEN,
+            max_display_name_len: MAX_DISPLAY_NAME_LEN,
+        }
+    }
+}
+
+impl DecodeLimits {
+    fn check_packet(&self, input: &[u8]) -> Result<(), DiscoveryError> {
+        if input.len() > self.max_packet_len {
+            return Err(DiscoveryError::PacketTooLarge { len: input.len(), max: self.max_packet_len });
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Announcement {
@@ -10,31 +79,151 @@ pub struct Announcement {
     pub public_key_b64: String,
     pub display_name: String,
     pub port: u16,
+    pub free_space: Option<FreeSpaceHint>,
+    pub metadata: Option<PeerMetadata>,
+}
+
+/// What follows the strings of a versioned announcement.
+#[derive(Clone, Copy)]
+enum Trailer {
+    None,
+    SpaceHint,
+    Tlv,
+}
+
+/// Bucketed free space a device is willing to advertise to peers.
+///
+/// Only the bucket crosses the wire, so peers can tell whether an offer is likely to fit
+/// without learning the exact byte count. Bucket 0 is below 256 MiB; each following bucket
+/// is four times larger, and the last one is open-ended (256 GiB and up).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FreeSpaceHint {
+    bucket: u8,
+}
+
+impl FreeSpaceHint {
+    pub const BUCKETS: u8 = 7;
+    const FIRST_BOUNDARY: u64 = 256 * 1024 * 1024;
+
+    pub fn from_free_bytes(bytes: u64) -> Self {
+        let mut bucket = 0;
+        while bucket + 1 < Self::BUCKETS && bytes >= Self::boundary(bucket + 1) {
+            bucket += 1;
+        }
+        Self { bucket }
+    }
+
+    pub fn from_bucket(bucket: u8) -> Option<Self> {
+        (bucket < Self::BUCKETS).then_some(Self { bucket })
+    }
+
+    pub fn bucket(self) -> u8 {
+        self.bucket
+    }
+
+    /// Smallest free-space value that maps to this bucket.
+    pub fn lower_bound(self) -> u64 {
+        Self::boundary(self.bucket)
+    }
+
+    /// First value past this bucket; `None` for the open-ended top bucket.
+    pub fn upper_bound(self) -> Option<u64> {
+        (self.bucket + 1 < Self::BUCKETS).then(|| Self::boundary(self.bucket + 1))
+    }
+
+    /// Whether a payload of `size_bytes` exceeds everything this bucket could hold.
+    pub fn likely_lacks_space(self, size_bytes: u64) -> bool {
+        self.upper_bound().is_some_and(|upper| size_bytes >= upper)
+    }
+
+    fn boundary(bucket: u8) -> u64 {
+        match bucket {
+            0 => 0,
+            n => Self::FIRST_BOUNDARY << (2 * u32::from(n - 1)),
+        }
+    }
 }
 
 impl Announcement {
-    pub fn encode(&self) -> Vec<u8> {
-        // Simple length-prefixed binary format:
-        // MAGIC | port(u16 be) | len+device_id | len+public_key | len+display_name
-        let mut out = Vec::with_capacity(4 + 2 + 2 + self.device_id.len() + 2 + self.public_key_b64.len() + 2 + self.display_name.len());
+    /// Encode in the versioned layout, failing instead of truncating oversized fields.
+    pub fn encode(&self) -> Result<Vec<u8>, DiscoveryError> {
+        // Length-prefixed binary format:
+        // MAGIC | version(u8) | port(u16 be) | len+device_id | len+public_key | len+display_name
+        // [| free_space bucket(u8), version 2 only]
+        // [| type(u8) + len(u16 be) + value ..., version 7 only]
+        let mut out = Vec::with_capacity(4 + 1 + 2 + 2 + self.device_id.len() + 2 + self.public_key_b64.len() + 2 + self.display_name.len() + 1);
         out.extend_from_slice(MAGIC);
+        out.push(match (&self.metadata, self.free_space) {
+            (Some(_), _) => ANNOUNCEMENT_VERSION_METADATA,
+            (None, Some(_)) => ANNOUNCEMENT_VERSION_SPACE_HINT,
+            (None, None) => ANNOUNCEMENT_VERSION,
+        });
         out.extend_from_slice(&self.port.to_be_bytes());
-        push_str(&mut out, &self.device_id);
-        push_str(&mut out, &self.public_key_b64);
-        push_str(&mut out, &self.display_name);
-        out
+        push_str(&mut out, &self.device_id, MAX_DEVICE_ID_LEN, "device_id")?;
+        push_str(&mut out, &self.public_key_b64, MAX_PUBLIC_KEY_LEN, "public_key")?;
+        push_str(&mut out, &self.display_name, MAX_DISPLAY_NAME_LEN, "display_name")?;
+        match (&self.metadata, self.free_space) {
+            (Some(metadata), free_space) => metadata::push_tlvs(&mut out, free_space, metadata)?,
+            (None, Some(hint)) => out.push(hint.bucket()),
+            (None, None) => {}
+        }
+        Ok(out)
     }
 
+    /// Decode a versioned packet, falling back to the legacy unversioned layout.
+    ///
+    /// A legacy packet whose port high byte equals the version byte is ambiguous, so the
+    /// versioned parse is tried first and the legacy parse only if it fails.
     pub fn decode(input: &[u8]) -> Result<Self, DiscoveryError> {
+        Self::decode_with_limits(input, &DecodeLimits::default())
+    }
+
+    /// [`Self::decode`] under `limits` rather than the defaults.
+    pub fn decode_with_limits(input: &[u8], limits: &DecodeLimits) -> Result<Self, DiscoveryError> {
+        limits.check_packet(input)?;
         if input.len() < 6 || &input[..4] != MAGIC {
             return Err(DiscoveryError::InvalidPacket("bad magic/header"));
         }
 
-        let port = u16::from_be_bytes([input[4], input[5]]);
-        let mut idx = 6;
-        let device_id = read_str(input, &mut idx)?;
-        let public_key_b64 = read_str(input, &mut idx)?;
-        let display_name = read_str(input, &mut idx)?;
+        let trailer = match input[4] {
+            ANNOUNCEMENT_VERSION => Some(Trailer::None),
+            ANNOUNCEMENT_VERSION_SPACE_HINT => Some(Trailer::SpaceHint),
+            ANNOUNCEMENT_VERSION_METADATA => Some(Trailer::Tlv),
+            _ => None,
+        };
+        if let Some(trailer) = trailer {
+            match Self::decode_body(input, 5, trailer, limits) {
+                Ok(announcement) => return Ok(announcement),
+                Err(err) => return Self::decode_body(input, 4, Trailer::None, limits).map_err(|_| err),
+            }
+        }
+        Self::decode_body(input, 4, Trailer::None, limits)
+    }
+
+    fn decode_body(input: &[u8], port_offset: usize, trailer: Trailer, limits: &DecodeLimits) -> Result<Self, DiscoveryError> {
+        if input.len() < port_offset + 2 {
+            return Err(DiscoveryError::InvalidPacket("bad magic/header"));
+        }
+
+        let port = u16::from_be_bytes([input[port_offset], input[port_offset + 1]]);
+        let mut idx = port_offset + 2;
+        let device_id = read_str(input, &mut idx, limits.max_device_id_len, "device_id")?;
+        let public_key_b64 = read_str(input, &mut idx, limits.max_public_key_len, "public_key")?;
+        let display_name = read_str(input, &mut idx, limits.max_display_name_len, "display_name")?;
+        let (free_space, metadata) = match trailer {
+            Trailer::None => (None, None),
+            Trailer::SpaceHint => {
+                let bucket = *input.get(idx).ok_or(DiscoveryError::InvalidLength)?;
+                idx += 1;
+                let hint = FreeSpaceHint::from_bucket(bucket).ok_or(DiscoveryError::InvalidPacket("unknown free space bucket"))?;
+                (Some(hint), None)
+            }
+            Trailer::Tlv => {
+                let (free_space, metadata) = metadata::read_tlvs(&input[idx..])?;
+                idx = input.len();
+                (free_space, Some(metadata))
+            }
+        };
 
         if idx != input.len() {
             return Err(DiscoveryError::InvalidPacket("trailing bytes"));
@@ -45,82 +234,589 @@ impl Announcement {
             public_key_b64,
             display_name,
             port,
+            free_space,
+            metadata,
         })
     }
 }
 
+/// Any packet a discovery socket may receive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiscoveryPacket {
+    Announcement(Announcement),
+    Signed(SignedAnnouncement),
+    Leave(LeaveMessage),
+    Query(DiscoveryQuery),
+    Response(DiscoveryResponse),
+    Ping(Ping),
+    Pong(Pong),
+}
+
+impl DiscoveryPacket {
+    /// Dispatch on the version byte. A packet that fails to parse as the kind its version
+    /// names is still tried as a legacy announcement, whose port high byte may match it.
+    pub fn decode(input: &[u8]) -> Result<Self, DiscoveryError> {
+        Self::decode_with_limits(input, &DecodeLimits::default())
+    }
+
+    /// [`Self::decode`] under `limits`; they apply to the whole packet and to any
+    /// announcement in it.
+    pub fn decode_with_limits(input: &[u8], limits: &DecodeLimits) -> Result<Self, DiscoveryError> {
+        limits.check_packet(input)?;
+        let parsed = match input.get(4) {
+            Some(&ANNOUNCEMENT_VERSION_SIGNED) => SignedAnnouncement::decode_with_limits(input, limits).map(Self::Signed),
+            Some(&LEAVE_VERSION) => LeaveMessage::decode(input).map(Self::Leave),
+            Some(&QUERY_VERSION) => DiscoveryQuery::decode(input).map(Self::Query),
+            Some(&RESPONSE_VERSION) => DiscoveryResponse::decode_with_limits(input, limits).map(Self::Response),
+            Some(&PING_VERSION) => Ping::decode(input).map(Self::Ping),
+            Some(&PONG_VERSION) => Pong::decode(input).map(Self::Pong),
+            _ => return Announcement::decode_with_limits(input, limits).map(Self::Announcement),
+        };
+        parsed.or_else(|err| Announcement::decode_with_limits(input, limits).map(Self::Announcement).map_err(|_| err))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PeerEntry {
     pub announcement: Announcement,
     pub source: SocketAddr,
     pub last_seen: Instant,
+    /// The announcement came signed by `announcement.public_key_b64`.
+    pub verified: bool,
+    /// [`SignedAnnouncement::sequence`] of the newest signed announcement taken.
+    pub sequence: Option<u64>,
+    /// Local interface the peer was heard on, when the registry knows the interfaces.
+    pub interface: Option<LocalInterface>,
+    /// Smoothed round trip time from [`PeerRegistry::record_rtt`], kept across announcements.
+    pub rtt: Option<Duration>,
+    /// Another key has claimed the same device id; one of them may be spoofing the other.
+    pub conflicting: bool,
+}
+
+impl PeerEntry {
+    /// Address of the peer's transfer service: the announcement source with the advertised port.
+    ///
+    /// The IPv6 scope id is kept, so link-local peers stay reachable on the right interface.
+    pub fn service_addr(&self) -> SocketAddr {
+        let mut addr = self.source;
+        addr.set_port(self.announcement.port);
+        addr
+    }
+
+    /// Whether the peer's metadata lists `capability`.
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.announcement.metadata.as_ref().is_some_and(|m| m.capabilities.iter().any(|c| c == capability))
+    }
+
+    /// Advertised status; peers that send no metadata are taken to be online.
+    pub fn status(&self) -> PeerStatus {
+        self.announcement.metadata.as_ref().map_or(PeerStatus::Online, |m| m.status)
+    }
+}
+
+/// Bounds a [`PeerRegistry`] holds to while a device floods it with announcements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegistryLimits {
+    /// Devices tracked at once, and source addresses rate-limited at once. Announcements
+    /// for new devices are dropped while the registry is full.
+    pub max_peers: usize,
+    /// Updates taken from one source IP per `rate_window`, whatever device ids it claims.
+    pub max_updates_per_source: u32,
+    pub rate_window: Duration,
+}
+
+impl Default for RegistryLimits {
+    fn default() -> Self {
+        Self {
+            max_peers: 256,
+            max_updates_per_source: 20,
+            rate_window: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Announcements a [`PeerRegistry`] dropped under its [`RegistryLimits`], by reason.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DropCounters {
+    pub rate_limited: u64,
+    pub registry_full: u64,
+}
+
+/// Something a [`PeerRegistry`] noticed that the user may need to hear about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryEvent {
+    /// Announcements under different keys claim `device_id`, heard from `sources`.
+    DeviceIdConflict { device_id: String, sources: Vec<SocketAddr> },
+}
+
+/// Events kept until [`PeerRegistry::drain_events`]; the oldest are dropped first.
+const MAX_EVENTS: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+struct RateWindow {
+    start: Instant,
+    updates: u32,
 }
 
 #[derive(Debug)]
 pub struct PeerRegistry {
-    peers: HashMap<String, PeerEntry>,
+    /// Keyed by device id and announced key, so two keys claiming one id both stay visible.
+    peers: HashMap<(String, String), PeerEntry>,
     ttl: Duration,
+    interfaces: Vec<LocalInterface>,
+    limits: RegistryLimits,
+    rate: HashMap<IpAddr, RateWindow>,
+    dropped: DropCounters,
+    events: VecDeque<RegistryEvent>,
 }
 
 impl PeerRegistry {
     pub fn new(ttl: Duration) -> Self {
+        Self::with_limits(ttl, RegistryLimits::default())
+    }
+
+    pub fn with_limits(ttl: Duration, limits: RegistryLimits) -> Self {
         Self {
             peers: HashMap::new(),
             ttl,
+            interfaces: Vec::new(),
+            limits,
+            rate: HashMap::new(),
+            dropped: DropCounters::default(),
+            events: VecDeque::new(),
         }
     }
 
+    pub fn dropped(&self) -> DropCounters {
+        self.dropped
+    }
+
+    /// Events since the last call, oldest first.
+    pub fn drain_events(&mut self) -> Vec<RegistryEvent> {
+        self.events.drain(..).collect()
+    }
+
+    /// Local interfaces to attribute peers to, e.g. from [`local_interfaces`]. Call again
+    /// after a network change; entries are attributed as they are next updated.
+    pub fn set_interfaces(&mut self, interfaces: Vec<LocalInterface>) {
+        self.interfaces = interfaces;
+    }
+
+    /// Record an unsigned announcement. It is dropped when the device already has a verified
+    /// entry, so an unsigned packet cannot replace what a signed one established, and when
+    /// the registry's limits refuse it; see [`Self::dropped`].
+    ///
+    /// An announcement under a new key for a known device id is kept beside the existing
+    /// entry, and both are flagged [`PeerEntry::conflicting`] with a
+    /// [`RegistryEvent::DeviceIdConflict`] queued. Against a verified entry it is dropped,
+    /// but the flag and event are raised all the same.
     pub fn upsert(&mut self, announcement: Announcement, source: SocketAddr, now: Instant) {
+        if self.check_rate(source, now).is_err() {
+            return;
+        }
+        if let Some(pinned) = self.verified_key(&announcement.device_id) {
+            if pinned != announcement.public_key_b64 {
+                self.flag_conflict(&announcement.device_id, vec![source], false);
+            }
+            return;
+        }
+        let _ = self.insert(announcement, source, now, None);
+    }
+
+    /// Record a signed announcement, pinning the device to the first key it was verified with
+    /// for as long as its entry lives.
+    ///
+    /// A later announcement for the same device under another key is refused with
+    /// [`DiscoveryError::KeyMismatch`], and one with a lower sequence than the entry holds
+    /// with [`DiscoveryError::StaleAnnouncement`]. Unverified entries under other keys are replaced.
+    ///
+    /// The pin is first come, first served: anyone on the link can claim a device id it has
+    /// not seen yet, or once its entry expired, and lock the real owner out until then. It
+    /// only keeps a known device from being taken over; check keys against the identity
+    /// trust store before acting on who a peer is.
+    /// Either way the entry is flagged as conflicting and an event queued, as in
+    /// [`Self::upsert`]. Limits are enforced as for [`Self::upsert`], failing with
+    /// [`DiscoveryError::RateLimited`] or [`DiscoveryError::RegistryFull`].
+    pub fn upsert_signed(&mut self, signed: SignedAnnouncement, source: SocketAddr, now: Instant) -> Result<(), DiscoveryError> {
+        self.check_rate(source, now)?;
+        let sequence = signed.sequence();
+        let announcement = signed.into_announcement();
+        if let Some(pinned) = self.verified_key(&announcement.device_id) {
+            if pinned != announcement.public_key_b64 {
+                self.flag_conflict(&announcement.device_id, vec![source], false);
+                return Err(DiscoveryError::KeyMismatch);
+            }
+        }
+        let key = (announcement.device_id.clone(), announcement.public_key_b64.clone());
+        if self.peers.get(&key).and_then(|p| p.sequence).is_some_and(|newest| sequence < newest) {
+            return Err(DiscoveryError::StaleAnnouncement);
+        }
+        let mut replaced = Vec::new();
+        self.peers.retain(|(id, public_key), p| {
+            let other = *id == announcement.device_id && *public_key != announcement.public_key_b64;
+            if other {
+                replaced.push(p.source);
+            }
+            !other
+        });
+        let device_id = announcement.device_id.clone();
+        self.insert(announcement, source, now, Some(sequence))?;
+        if !replaced.is_empty() {
+            self.flag_conflict(&device_id, replaced, true);
+        }
+        Ok(())
+    }
+
+    /// Decode, verify and record a signed packet; anything that fails leaves the registry as it was.
+    pub fn upsert_signed_packet(&mut self, packet: &[u8], source: SocketAddr, now: Instant) -> Result<(), DiscoveryError> {
+        self.upsert_signed(SignedAnnouncement::decode(packet)?, source, now)
+    }
+
+    /// Record the announcement a query response carries, under the same rules as an
+    /// announcement heard on its own.
+    pub fn upsert_response(&mut self, response: DiscoveryResponse, source: SocketAddr, now: Instant) -> Result<(), DiscoveryError> {
+        match response.signed_announcement() {
+            Some(signed) => self.upsert_signed(signed.clone(), source, now),
+            None => {
+                self.upsert(response.announcement().clone(), source, now);
+                Ok(())
+            }
+        }
+    }
+
+    /// Drop the device a verified leave names, returning whether it was registered.
+    ///
+    /// The leave must be signed by a key the device announced; otherwise nothing is
+    /// removed and [`DiscoveryError::KeyMismatch`] is returned. Of conflicting entries, only
+    /// the one under the leave's key goes.
+    pub fn remove_on_leave(&mut self, leave: &LeaveMessage) -> Result<bool, DiscoveryError> {
+        let key = (leave.device_id().to_string(), leave.public_key_b64().to_string());
+        if self.peers.remove(&key).is_some() {
+            self.settle_conflict(leave.device_id());
+            return Ok(true);
+        }
+        if self.peers.keys().any(|(id, _)| id == leave.device_id()) {
+            return Err(DiscoveryError::KeyMismatch);
+        }
+        Ok(false)
+    }
+
+    fn check_rate(&mut self, source: SocketAddr, now: Instant) -> Result<(), DiscoveryError> {
+        let limits = self.limits;
+        let ip = source.ip();
+        if self.rate.len() >= limits.max_peers && !self.rate.contains_key(&ip) {
+            self.rate.retain(|_, w| now.duration_since(w.start) < limits.rate_window);
+            if self.rate.len() >= limits.max_peers {
+                self.dropped.rate_limited += 1;
+                return Err(DiscoveryError::RateLimited);
+            }
+        }
+        let window = self.rate.entry(ip).or_insert(RateWindow { start: now, updates: 0 });
+        if now.duration_since(window.start) >= limits.rate_window {
+            *window = RateWindow { start: now, updates: 0 };
+        }
+        if window.updates >= limits.max_updates_per_source {
+            self.dropped.rate_limited += 1;
+            return Err(DiscoveryError::RateLimited);
+        }
+        window.updates += 1;
+        Ok(())
+    }
+
+    /// `sequence` is that of the signed announcement, `None` for an unverified one.
+    fn insert(&mut self, announcement: Announcement, source: SocketAddr, now: Instant, sequence: Option<u64>) -> Result<(), DiscoveryError> {
+        let key = (announcement.device_id.clone(), announcement.public_key_b64.clone());
+        let previous = self.peers.get(&key);
+        if self.peers.len() >= self.limits.max_peers && previous.is_none() {
+            self.dropped.registry_full += 1;
+            return Err(DiscoveryError::RegistryFull);
+        }
+        let is_new = previous.is_none();
+        let rtt = previous.and_then(|p| p.rtt);
+        let conflicting = previous.is_some_and(|p| p.conflicting);
         self.peers.insert(
-            announcement.device_id.clone(),
+            key.clone(),
             PeerEntry {
                 announcement,
                 source,
                 last_seen: now,
+                verified: sequence.is_some(),
+                sequence,
+                interface: interface_for(&self.interfaces, source).cloned(),
+                rtt,
+                conflicting,
             },
         );
+        if is_new && self.peers.keys().any(|(id, public_key)| *id == key.0 && *public_key != key.1) {
+            self.flag_conflict(&key.0, Vec::new(), true);
+        }
+        Ok(())
+    }
+
+    fn verified_key(&self, device_id: &str) -> Option<String> {
+        self.peers.iter().find(|((id, _), p)| id == device_id && p.verified).map(|((_, public_key), _)| public_key.clone())
+    }
+
+    /// Flag every entry for `device_id` and queue an event, unless they were all flagged
+    /// already and no new key turned up. `sources` start with those of announcements that
+    /// were not kept.
+    fn flag_conflict(&mut self, device_id: &str, mut sources: Vec<SocketAddr>, new_key: bool) {
+        let mut changed = new_key;
+        for ((id, _), peer) in self.peers.iter_mut() {
+            if id == device_id {
+                changed |= !peer.conflicting;
+                peer.conflicting = true;
+                sources.push(peer.source);
+            }
+        }
+        if !changed {
+            return;
+        }
+        sources.sort();
+        sources.dedup();
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(RegistryEvent::DeviceIdConflict {
+            device_id: device_id.to_string(),
+            sources,
+        });
+    }
+
+    /// Clear the flag once removals leave `device_id` with a single entry.
+    fn settle_conflict(&mut self, device_id: &str) {
+        let mut remaining = self.peers.iter_mut().filter(|((id, _), _)| id == device_id);
+        if let (Some((_, peer)), None) = (remaining.next(), remaining.next()) {
+            peer.conflicting = false;
+        }
+    }
+
+    /// Fold a measured round trip into the RTT of the entry for `device_id` under
+    /// `public_key_b64`, returning false if there is none.
+    ///
+    /// Only the entry that was pinged is updated: another key claiming the same device id
+    /// keeps its own measurement. Like TCP's smoothed RTT, each sample moves the estimate
+    /// an eighth of the way, so one slow echo does not reorder [`Self::peers_by_latency`].
+    pub fn record_rtt(&mut self, device_id: &str, public_key_b64: &str, sample: Duration) -> bool {
+        let Some(peer) = self.peers.get_mut(&(device_id.to_string(), public_key_b64.to_string())) else {
+            return false;
+        };
+        peer.rtt = Some(match peer.rtt {
+            Some(rtt) => rtt - rtt / 8 + sample / 8,
+            None => sample,
+        });
+        true
+    }
+
+    /// Peers fastest first; those never measured come last, by device id.
+    pub fn peers_by_latency(&self) -> Vec<&PeerEntry> {
+        let mut peers = self.peers();
+        peers.sort_by(|a, b| match (a.rtt, b.rtt) {
+            (Some(a_rtt), Some(b_rtt)) => a_rtt.cmp(&b_rtt),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => (&a.announcement.device_id, &a.announcement.public_key_b64).cmp(&(&b.announcement.device_id, &b.announcement.public_key_b64)),
+        });
+        peers
     }
 
     pub fn expire(&mut self, now: Instant) {
         let ttl = self.ttl;
-        self.peers.retain(|_, p| now.duration_since(p.last_seen) <= ttl);
+        let window = self.limits.rate_window;
+        let mut expired = Vec::new();
+        self.peers.retain(|(id, _), p| {
+            let live = now.duration_since(p.last_seen) <= ttl;
+            if !live {
+                expired.push(id.clone());
+            }
+            live
+        });
+        for device_id in expired {
+            self.settle_conflict(&device_id);
+        }
+        self.rate.retain(|_, w| now.duration_since(w.start) < window);
     }
 
     pub fn peers(&self) -> Vec<&PeerEntry> {
         self.peers.values().collect()
     }
 
+    /// Copies of the peers whose display name contains `name_substring`, ignoring case.
+    pub fn peers_matching(&self, name_substring: &str) -> Vec<PeerEntry> {
+        let needle = name_substring.to_lowercase();
+        self.snapshot(|p| p.announcement.display_name.to_lowercase().contains(&needle))
+    }
+
+    /// Copies of the peers advertising `capability` in their metadata.
+    pub fn peers_with_capability(&self, capability: &str) -> Vec<PeerEntry> {
+        self.snapshot(|p| p.has_capability(capability))
+    }
+
+    /// Copies of the peers whose announcements came from inside `subnet`.
+    pub fn peers_on_subnet(&self, subnet: &Cidr) -> Vec<PeerEntry> {
+        self.snapshot(|p| subnet.contains(p.source.ip()))
+    }
+
+    /// Owned copies, so a caller can serve them after releasing the registry; sorted by
+    /// display name, then device id.
+    fn snapshot(&self, keep: impl Fn(&PeerEntry) -> bool) -> Vec<PeerEntry> {
+        let mut peers: Vec<PeerEntry> = self.peers.values().filter(|p| keep(p)).cloned().collect();
+        peers.sort_by(|a, b| {
+            (&a.announcement.display_name, &a.announcement.device_id).cmp(&(&b.announcement.display_name, &b.announcement.device_id))
+        });
+        peers
+    }
+
     pub fn len(&self) -> usize {
         self.peers.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
 }
 
 #[derive(Debug)]
 pub struct DiscoveryService {
     socket: UdpSocket,
+    limits: DecodeLimits,
 }
 
 impl DiscoveryService {
     pub fn bind(bind_addr: SocketAddr) -> Result<Self, DiscoveryError> {
         let socket = UdpSocket::bind(bind_addr)?;
         socket.set_nonblocking(false)?;
-        Ok(Self { socket })
+        Ok(Self {
+            socket,
+            limits: DecodeLimits::default(),
+        })
+    }
+
+    /// Limits for every packet received from now on.
+    pub fn set_decode_limits(&mut self, limits: DecodeLimits) {
+        self.limits = limits;
     }
 
     pub fn local_addr(&self) -> Result<SocketAddr, DiscoveryError> {
         Ok(self.socket.local_addr()?)
     }
 
+    /// Bound how long [`Self::recv_announcement`] blocks; `None` waits indefinitely.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), DiscoveryError> {
+        Ok(self.socket.set_read_timeout(timeout)?)
+    }
+
+    /// Join the announcement group matching this socket's address family.
+    ///
+    /// `interface` is the IPv6 interface index (0 lets the OS pick); IPv4 uses any interface.
+    pub fn join_multicast(&self, interface: u32) -> Result<(), DiscoveryError> {
+        match self.socket.local_addr()?.ip() {
+            IpAddr::V4(_) => self
+                .socket
+                .join_multicast_v4(&MULTICAST_GROUP_V4, &Ipv4Addr::UNSPECIFIED)?,
+            IpAddr::V6(_) => self.socket.join_multicast_v6(&MULTICAST_GROUP_V6, interface)?,
+        }
+        Ok(())
+    }
+
+    /// Group destination for this socket's family; link-local IPv6 needs the interface index.
+    pub fn multicast_target(&self, port: u16, interface: u32) -> Result<SocketAddr, DiscoveryError> {
+        Ok(match self.socket.local_addr()?.ip() {
+            IpAddr::V4(_) => SocketAddr::new(IpAddr::V4(MULTICAST_GROUP_V4), port),
+            IpAddr::V6(_) => SocketAddr::V6(SocketAddrV6::new(MULTICAST_GROUP_V6, port, 0, interface)),
+        })
+    }
+
+    pub fn announce_multicast(&self, port: u16, interface: u32, announcement: &Announcement) -> Result<usize, DiscoveryError> {
+        self.send_announcement(self.multicast_target(port, interface)?, announcement)
+    }
+
     pub fn send_announcement(&self, target: SocketAddr, announcement: &Announcement) -> Result<usize, DiscoveryError> {
-        Ok(self.socket.send_to(&announcement.encode(), target)?)
+        Ok(self.socket.send_to(&announcement.encode()?, target)?)
+    }
+
+    pub fn send_signed_announcement(&self, target: SocketAddr, signed: &SignedAnnouncement) -> Result<usize, DiscoveryError> {
+        Ok(self.socket.send_to(&signed.encode()?, target)?)
+    }
+
+    pub fn send_leave(&self, target: SocketAddr, leave: &LeaveMessage) -> Result<usize, DiscoveryError> {
+        Ok(self.socket.send_to(&leave.encode()?, target)?)
+    }
+
+    pub fn send_query(&self, target: SocketAddr, query: &DiscoveryQuery) -> Result<usize, DiscoveryError> {
+        Ok(self.socket.send_to(&query.encode()?, target)?)
+    }
+
+    /// Probe the latency to `target`; its pong comes back to this socket.
+    pub fn send_ping(&self, target: SocketAddr, ping: &Ping) -> Result<usize, DiscoveryError> {
+        Ok(self.socket.send_to(&ping.encode(), target)?)
+    }
+
+    /// Echo `ping` back to `target`, the address it came from.
+    pub fn send_pong(&self, target: SocketAddr, ping: &Ping) -> Result<usize, DiscoveryError> {
+        Ok(self.socket.send_to(&ping.pong().encode(), target)?)
+    }
+
+    /// Answer a query; `target` is the address the query came from.
+    pub fn send_response(&self, target: SocketAddr, response: &DiscoveryResponse) -> Result<usize, DiscoveryError> {
+        Ok(self.socket.send_to(&response.encode()?, target)?)
+    }
+
+    /// Send `query` to `target` and collect the responses to it that arrive within `wait`.
+    ///
+    /// Other packets received meanwhile, including responses to other queries, are
+    /// dropped, so this suits a socket used for nothing else during the wait. The
+    /// socket's read timeout is restored afterwards.
+    pub fn query(&self, target: SocketAddr, query: &DiscoveryQuery, wait: Duration) -> Result<Vec<(DiscoveryResponse, SocketAddr)>, DiscoveryError> {
+        self.send_query(target, query)?;
+        let previous_timeout = self.socket.read_timeout()?;
+        let deadline = Instant::now() + wait;
+        let mut responses = Vec::new();
+        let mut buf = vec![0u8; 2048];
+        let result = loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break Ok(responses);
+            }
+            if let Err(err) = self.socket.set_read_timeout(Some(left)) {
+                break Err(err.into());
+            }
+            match self.socket.recv_from(&mut buf) {
+                Ok((n, src)) => {
+                    if let Ok(DiscoveryPacket::Response(response)) = DiscoveryPacket::decode_with_limits(&buf[..n], &self.limits) {
+                        if response.query_id() == query.query_id {
+                            responses.push((response, src));
+                        }
+                    }
+                }
+                Err(err) if matches!(err.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {}
+                Err(err) => break Err(err.into()),
+            }
+        };
+        self.socket.set_read_timeout(previous_timeout)?;
+        result
     }
 
     pub fn recv_announcement(&self, max_size: usize) -> Result<(Announcement, SocketAddr), DiscoveryError> {
         let mut buf = vec![0u8; max_size];
         let (n, src) = self.socket.recv_from(&mut buf)?;
-        let ann = Announcement::decode(&buf[..n])?;
+        let ann = Announcement::decode_with_limits(&buf[..n], &self.limits)?;
         Ok((ann, src))
     }
+
+    /// Receive one packet of any kind; signed ones are verified.
+    pub fn recv_packet(&self, max_size: usize) -> Result<(DiscoveryPacket, SocketAddr), DiscoveryError> {
+        let mut buf = vec![0u8; max_size];
+        let (n, src) = self.socket.recv_from(&mut buf)?;
+        Ok((DiscoveryPacket::decode_with_limits(&buf[..n], &self.limits)?, src))
+    }
+
+    /// Receive one packet and verify it as a [`SignedAnnouncement`].
+    pub fn recv_signed_announcement(&self, max_size: usize) -> Result<(SignedAnnouncement, SocketAddr), DiscoveryError> {
+        let mut buf = vec![0u8; max_size];
+        let (n, src) = self.socket.recv_from(&mut buf)?;
+        Ok((SignedAnnouncement::decode_with_limits(&buf[..n], &self.limits)?, src))
+    }
 }
 
 #[derive(Debug)]
@@ -128,6 +824,20 @@ pub enum DiscoveryError {
     Io(std::io::Error),
     InvalidPacket(&'static str),
     InvalidLength,
+    FieldTooLong(&'static str),
+    /// The packet is longer than the decoder's [`DecodeLimits::max_packet_len`].
+    PacketTooLarge { len: usize, max: usize },
+    InvalidSignature,
+    /// The announcement's key is not the one expected for its device.
+    KeyMismatch,
+    /// A signed announcement older than one already taken from the same key.
+    StaleAnnouncement,
+    /// The source sent more announcements than the registry takes from one address.
+    RateLimited,
+    /// The registry tracks as many devices as it allows.
+    RegistryFull,
+    /// Not an `addr/prefix` block.
+    InvalidSubnet,
 }
 
 impl std::fmt::Display for DiscoveryError {
@@ -136,6 +846,14 @@ impl std::fmt::Display for DiscoveryError {
             DiscoveryError::Io(e) => write!(f, "I/O error: {e}"),
             DiscoveryError::InvalidPacket(msg) => write!(f, "invalid packet: {msg}"),
             DiscoveryError::InvalidLength => write!(f, "invalid string length"),
+            DiscoveryError::FieldTooLong(field) => write!(f, "field too long: {field}"),
+            DiscoveryError::PacketTooLarge { len, max } => write!(f, "packet too large: {len} bytes, limit {max}"),
+            DiscoveryError::InvalidSignature => write!(f, "invalid announcement signature"),
+            DiscoveryError::KeyMismatch => write!(f, "announcement key does not match the device's key"),
+            DiscoveryError::StaleAnnouncement => write!(f, "announcement is older than one already seen"),
+            DiscoveryError::RateLimited => write!(f, "announcement source is rate limited"),
+            DiscoveryError::RegistryFull => write!(f, "peer registry is full"),
+            DiscoveryError::InvalidSubnet => write!(f, "invalid subnet"),
         }
     }
 }
@@ -148,19 +866,32 @@ impl From<std::io::Error> for DiscoveryError {
     }
 }
 
-fn push_str(out: &mut Vec<u8>, value: &str) {
+/// Random enough for query ids and jitter, without pulling in an RNG crate.
+fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    hasher.finish()
+}
+
+fn push_str(out: &mut Vec<u8>, value: &str, max_len: usize, field: &'static str) -> Result<(), DiscoveryError> {
     let bytes = value.as_bytes();
-    let len = u16::try_from(bytes.len()).unwrap_or(u16::MAX);
-    out.extend_from_slice(&len.to_be_bytes());
-    out.extend_from_slice(&bytes[..usize::from(len)]);
+    if bytes.len() > max_len {
+        return Err(DiscoveryError::FieldTooLong(field));
+    }
+    out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    out.extend_from_slice(bytes);
+    Ok(())
 }
 
-fn read_str(input: &[u8], idx: &mut usize) -> Result<String, DiscoveryError> {
+fn read_str(input: &[u8], idx: &mut usize, max_len: usize, field: &'static str) -> Result<String, DiscoveryError> {
     if *idx + 2 > input.len() {
         return Err(DiscoveryError::InvalidLength);
     }
     let len = u16::from_be_bytes([input[*idx], input[*idx + 1]]) as usize;
     *idx += 2;
+    if len > max_len {
+        return Err(DiscoveryError::FieldTooLong(field));
+    }
     if *idx + len > input.len() {
         return Err(DiscoveryError::InvalidLength);
     }
@@ -0,0 +1,170 @@
+use crate::{push_str, read_str, DiscoveryError, FreeSpaceHint, MAX_CAPABILITY_LEN};
+
+pub const MAX_PLATFORM_LEN: usize = 64;
+pub const MAX_TRANSFER_VERSIONS: usize = 16;
+pub const MAX_CAPABILITIES: usize = 32;
+
+const TLV_FREE_SPACE: u8 = 1;
+const TLV_STATUS: u8 = 2;
+const TLV_TRANSFER_VERSIONS: u8 = 3;
+const TLV_ENCRYPTION_REQUIRED: u8 = 4;
+const TLV_PLATFORM: u8 = 5;
+const TLV_CAPABILITIES: u8 = 6;
+
+/// Whether a device is taking new transfers right now.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PeerStatus {
+    #[default]
+    Online,
+    Busy,
+}
+
+impl PeerStatus {
+    pub fn as_u8(self) -> u8 {
+        match self {
+            PeerStatus::Online => 0,
+            PeerStatus::Busy => 1,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(PeerStatus::Online),
+            1 => Some(PeerStatus::Busy),
+            _ => None,
+        }
+    }
+}
+
+/// Capability and status fields of a metadata announcement.
+///
+/// Each goes on the wire as its own type | len(u16 be) | value field, and decoders skip
+/// types they do not know, so fields can be added without a new version.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerMetadata {
+    pub status: PeerStatus,
+    /// Transfer protocol versions the device speaks, most preferred first.
+    pub transfer_versions: Vec<u8>,
+    /// The device refuses unencrypted transfers.
+    pub encryption_required: bool,
+    /// Free-form platform name, e.g. "linux" or "android".
+    pub platform: String,
+    /// Named features, matched by capability queries, e.g. "resume" or "folder-sync".
+    pub capabilities: Vec<String>,
+}
+
+/// Append the TLV fields; the free-space hint rides along as one of them.
+pub(crate) fn push_tlvs(
+    out: &mut Vec<u8>,
+    free_space: Option<FreeSpaceHint>,
+    metadata: &PeerMetadata,
+) -> Result<(), DiscoveryError> {
+    if metadata.transfer_versions.len() > MAX_TRANSFER_VERSIONS {
+        return Err(DiscoveryError::FieldTooLong("transfer_versions"));
+    }
+    if metadata.platform.len() > MAX_PLATFORM_LEN {
+        return Err(DiscoveryError::FieldTooLong("platform"));
+    }
+    if metadata.capabilities.len() > MAX_CAPABILITIES {
+        return Err(DiscoveryError::FieldTooLong("capabilities"));
+    }
+    let mut capabilities = Vec::new();
+    for capability in &metadata.capabilities {
+        push_str(
+            &mut capabilities,
+            capability,
+            MAX_CAPABILITY_LEN,
+            "capability",
+        )?;
+    }
+    if let Some(hint) = free_space {
+        push_tlv(out, TLV_FREE_SPACE, &[hint.bucket()]);
+    }
+    push_tlv(out, TLV_STATUS, &[metadata.status.as_u8()]);
+    push_tlv(out, TLV_TRANSFER_VERSIONS, &metadata.transfer_versions);
+    push_tlv(
+        out,
+        TLV_ENCRYPTION_REQUIRED,
+        &[u8::from(metadata.encryption_required)],
+    );
+    push_tlv(out, TLV_PLATFORM, metadata.platform.as_bytes());
+    if !capabilities.is_empty() {
+        push_tlv(out, TLV_CAPABILITIES, &capabilities);
+    }
+    Ok(())
+}
+
+/// Read TLV fields up to the end of `input`. Missing fields keep their defaults.
+pub(crate) fn read_tlvs(
+    input: &[u8],
+) -> Result<(Option<FreeSpaceHint>, PeerMetadata), DiscoveryError> {
+    let mut free_space = None;
+    let mut metadata = PeerMetadata::default();
+    let mut idx = 0;
+    while idx < input.len() {
+        if idx + 3 > input.len() {
+            return Err(DiscoveryError::InvalidLength);
+        }
+        let kind = input[idx];
+        let len = u16::from_be_bytes([input[idx + 1], input[idx + 2]]) as usize;
+        idx += 3;
+        let value = input
+            .get(idx..idx + len)
+            .ok_or(DiscoveryError::InvalidLength)?;
+        idx += len;
+        match kind {
+            TLV_FREE_SPACE => {
+                let bucket = single_byte(value)?;
+                free_space = Some(
+                    FreeSpaceHint::from_bucket(bucket)
+                        .ok_or(DiscoveryError::InvalidPacket("unknown free space bucket"))?,
+                );
+            }
+            TLV_STATUS => {
+                metadata.status = PeerStatus::from_u8(single_byte(value)?)
+                    .ok_or(DiscoveryError::InvalidPacket("unknown device status"))?;
+            }
+            TLV_TRANSFER_VERSIONS => {
+                if value.len() > MAX_TRANSFER_VERSIONS {
+                    return Err(DiscoveryError::FieldTooLong("transfer_versions"));
+                }
+                metadata.transfer_versions = value.to_vec();
+            }
+            TLV_ENCRYPTION_REQUIRED => metadata.encryption_required = single_byte(value)? != 0,
+            TLV_PLATFORM => {
+                if value.len() > MAX_PLATFORM_LEN {
+                    return Err(DiscoveryError::FieldTooLong("platform"));
+                }
+                metadata.platform = std::str::from_utf8(value)
+                    .map_err(|_| DiscoveryError::InvalidPacket("utf8 error"))?
+                    .to_string();
+            }
+            TLV_CAPABILITIES => {
+                let mut at = 0;
+                metadata.capabilities.clear();
+                while at < value.len() {
+                    if metadata.capabilities.len() == MAX_CAPABILITIES {
+                        return Err(DiscoveryError::FieldTooLong("capabilities"));
+                    }
+                    let capability = read_str(value, &mut at, MAX_CAPABILITY_LEN, "capability")?;
+                    metadata.capabilities.push(capability);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok((free_space, metadata))
+}
+
+fn push_tlv(out: &mut Vec<u8>, kind: u8, value: &[u8]) {
+    out.push(kind);
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value);
+}
+
+fn single_byte(value: &[u8]) -> Result<u8, DiscoveryError> {
+    match value {
+        [byte] => Ok(*byte),
+        _ => Err(DiscoveryError::InvalidLength),
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{random_u64, DiscoveryError, MAGIC};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Wire version of a latency probe.
+pub const PING_VERSION: u8 = 8;
+/// Wire version of the echo to a [`Ping`].
+pub const PONG_VERSION: u8 = 9;
+
+/// Latency probe; the peer echoes its nonce back in a [`Pong`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ping {
+    pub nonce: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pong {
+    pub nonce: u64,
+}
+
+impl Ping {
+    /// The reply a peer sends back.
+    pub fn pong(&self) -> Pong {
+        Pong { nonce: self.nonce }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        encode_probe(PING_VERSION, self.nonce)
+    }
+
+    pub fn decode(input: &[u8]) -> Result<Self, DiscoveryError> {
+        decode_probe(input, PING_VERSION).map(|nonce| Self { nonce })
+    }
+}
+
+impl Pong {
+    pub fn encode(&self) -> Vec<u8> {
+        encode_probe(PONG_VERSION, self.nonce)
+    }
+
+    pub fn decode(input: &[u8]) -> Result<Self, DiscoveryError> {
+        decode_probe(input, PONG_VERSION).map(|nonce| Self { nonce })
+    }
+}
+
+/// Matches pongs to the pings that asked for them and turns each into a round trip time.
+///
+/// Pings that get no answer within `timeout` are forgotten, so a silent peer does not
+/// make the prober grow.
+#[derive(Debug)]
+pub struct LatencyProber {
+    timeout: Duration,
+    pending: HashMap<u64, (String, Instant)>,
+}
+
+impl LatencyProber {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// A ping to send to `device_id` now.
+    pub fn ping(&mut self, device_id: &str, now: Instant) -> Ping {
+        self.forget_stale(now);
+        let nonce = random_u64();
+        self.pending.insert(nonce, (device_id.to_string(), now));
+        Ping { nonce }
+    }
+
+    /// The device and round trip time a pong answers, or `None` for a pong to no
+    /// outstanding ping, including a late or repeated one.
+    pub fn on_pong(&mut self, pong: Pong, now: Instant) -> Option<(String, Duration)> {
+        self.forget_stale(now);
+        let (device_id, sent) = self.pending.remove(&pong.nonce)?;
+        Some((device_id, now.duration_since(sent)))
+    }
+
+    /// Pings still waiting for an answer.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn forget_stale(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        self.pending
+            .retain(|_, (_, sent)| now.duration_since(*sent) <= timeout);
+    }
+}
+
+fn encode_probe(version: u8, nonce: u64) -> Vec<u8> {
+    // MAGIC | version(u8) | nonce(u64 be)
+    let mut out = Vec::with_capacity(4 + 1 + 8);
+    out.extend_from_slice(MAGIC);
+    out.push(version);
+    out.extend_from_slice(&nonce.to_be_bytes());
+    out
+}
+
+fn decode_probe(input: &[u8], version: u8) -> Result<u64, DiscoveryError> {
+    if input.len() != 13 || &input[..4] != MAGIC || input[4] != version {
+        return Err(DiscoveryError::InvalidPacket("bad magic/header"));
+    }
+    Ok(u64::from_be_bytes(
+        input[5..].try_into().expect("slice len"),
+    ))
+}
@@ -0,0 +1,184 @@
+use crate::{
+    push_str, random_u64, read_str, Announcement, DecodeLimits, DiscoveryError, DiscoveryPacket,
+    SignedAnnouncement, MAGIC, MAX_DEVICE_ID_LEN,
+};
+
+/// Wire version of a query packet.
+pub const QUERY_VERSION: u8 = 5;
+/// Wire version of a response packet.
+pub const RESPONSE_VERSION: u8 = 6;
+pub const MAX_CAPABILITY_LEN: usize = 64;
+
+const KIND_DEVICE_ID: u8 = 1;
+const KIND_CAPABILITY: u8 = 2;
+
+/// What a [`DiscoveryQuery`] asks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryTarget {
+    DeviceId(String),
+    Capability(String),
+}
+
+/// "Who is device X?" or "who supports capability Y?", sent to the group or straight to
+/// a stale peer's last address, so it can be refreshed before the next announcement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveryQuery {
+    pub query_id: u64,
+    pub target: QueryTarget,
+}
+
+impl DiscoveryQuery {
+    /// Query a device by id, under a fresh random query id.
+    pub fn for_device(device_id: impl Into<String>) -> Self {
+        Self {
+            query_id: random_u64(),
+            target: QueryTarget::DeviceId(device_id.into()),
+        }
+    }
+
+    /// Query every device supporting `capability`, under a fresh random query id.
+    pub fn for_capability(capability: impl Into<String>) -> Self {
+        Self {
+            query_id: random_u64(),
+            target: QueryTarget::Capability(capability.into()),
+        }
+    }
+
+    /// Whether a device announcing `announcement` with `capabilities` should respond.
+    pub fn matches(&self, announcement: &Announcement, capabilities: &[&str]) -> bool {
+        match &self.target {
+            QueryTarget::DeviceId(id) => *id == announcement.device_id,
+            QueryTarget::Capability(wanted) => capabilities.contains(&wanted.as_str()),
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, DiscoveryError> {
+        // MAGIC | version(u8) | query_id(u64 be) | kind(u8) | len+value
+        let (kind, value, max_len, field) = match &self.target {
+            QueryTarget::DeviceId(id) => (KIND_DEVICE_ID, id, MAX_DEVICE_ID_LEN, "device_id"),
+            QueryTarget::Capability(cap) => {
+                (KIND_CAPABILITY, cap, MAX_CAPABILITY_LEN, "capability")
+            }
+        };
+        let mut out = Vec::with_capacity(4 + 1 + 8 + 1 + 2 + value.len());
+        out.extend_from_slice(MAGIC);
+        out.push(QUERY_VERSION);
+        out.extend_from_slice(&self.query_id.to_be_bytes());
+        out.push(kind);
+        push_str(&mut out, value, max_len, field)?;
+        Ok(out)
+    }
+
+    pub fn decode(input: &[u8]) -> Result<Self, DiscoveryError> {
+        if input.len() < 14 || &input[..4] != MAGIC || input[4] != QUERY_VERSION {
+            return Err(DiscoveryError::InvalidPacket("bad magic/header"));
+        }
+        let query_id = u64::from_be_bytes(input[5..13].try_into().expect("slice len"));
+        let mut idx = 14;
+        let target = match input[13] {
+            KIND_DEVICE_ID => {
+                QueryTarget::DeviceId(read_str(input, &mut idx, MAX_DEVICE_ID_LEN, "device_id")?)
+            }
+            KIND_CAPABILITY => QueryTarget::Capability(read_str(
+                input,
+                &mut idx,
+                MAX_CAPABILITY_LEN,
+                "capability",
+            )?),
+            _ => return Err(DiscoveryError::InvalidPacket("unknown query kind")),
+        };
+        if idx != input.len() {
+            return Err(DiscoveryError::InvalidPacket("trailing bytes"));
+        }
+        Ok(Self { query_id, target })
+    }
+}
+
+/// A device's answer to a [`DiscoveryQuery`], sent back to the querier only.
+///
+/// It echoes the query id, so the querier can ignore responses it did not ask for, and
+/// carries the responder's current announcement, signed when the responder signs its
+/// broadcasts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveryResponse {
+    query_id: u64,
+    body: ResponseBody,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ResponseBody {
+    Plain(Announcement),
+    Signed(SignedAnnouncement),
+}
+
+impl DiscoveryResponse {
+    pub fn new(query_id: u64, announcement: Announcement) -> Self {
+        Self {
+            query_id,
+            body: ResponseBody::Plain(announcement),
+        }
+    }
+
+    pub fn signed(query_id: u64, signed: SignedAnnouncement) -> Self {
+        Self {
+            query_id,
+            body: ResponseBody::Signed(signed),
+        }
+    }
+
+    pub fn query_id(&self) -> u64 {
+        self.query_id
+    }
+
+    pub fn announcement(&self) -> &Announcement {
+        match &self.body {
+            ResponseBody::Plain(announcement) => announcement,
+            ResponseBody::Signed(signed) => signed.announcement(),
+        }
+    }
+
+    /// The verified announcement, when the responder signed it.
+    pub fn signed_announcement(&self) -> Option<&SignedAnnouncement> {
+        match &self.body {
+            ResponseBody::Plain(_) => None,
+            ResponseBody::Signed(signed) => Some(signed),
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, DiscoveryError> {
+        // MAGIC | version(u8) | query_id(u64 be) | announcement or signed announcement packet
+        let inner = match &self.body {
+            ResponseBody::Plain(announcement) => announcement.encode()?,
+            ResponseBody::Signed(signed) => signed.encode()?,
+        };
+        let mut out = Vec::with_capacity(4 + 1 + 8 + inner.len());
+        out.extend_from_slice(MAGIC);
+        out.push(RESPONSE_VERSION);
+        out.extend_from_slice(&self.query_id.to_be_bytes());
+        out.extend_from_slice(&inner);
+        Ok(out)
+    }
+
+    pub fn decode(input: &[u8]) -> Result<Self, DiscoveryError> {
+        Self::decode_with_limits(input, &DecodeLimits::default())
+    }
+
+    /// [`Self::decode`] with `limits` applied to the packet and the carried announcement.
+    pub fn decode_with_limits(input: &[u8], limits: &DecodeLimits) -> Result<Self, DiscoveryError> {
+        limits.check_packet(input)?;
+        if input.len() < 13 || &input[..4] != MAGIC || input[4] != RESPONSE_VERSION {
+            return Err(DiscoveryError::InvalidPacket("bad magic/header"));
+        }
+        let query_id = u64::from_be_bytes(input[5..13].try_into().expect("slice len"));
+        let body = match DiscoveryPacket::decode_with_limits(&input[13..], limits)? {
+            DiscoveryPacket::Announcement(announcement) => ResponseBody::Plain(announcement),
+            DiscoveryPacket::Signed(signed) => ResponseBody::Signed(signed),
+            _ => {
+                return Err(DiscoveryError::InvalidPacket(
+                    "response must carry an announcement",
+                ))
+            }
+        };
+        Ok(Self { query_id, body })
+    }
+}
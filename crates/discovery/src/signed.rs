@@ -0,0 +1,126 @@
+use crate::{Announcement, DecodeLimits, DiscoveryError, MAGIC};
+use identity::{verify_signature, DeviceIdentity};
+
+/// Wire version of a signed announcement; never written by [`Announcement::encode`].
+pub const ANNOUNCEMENT_VERSION_SIGNED: u8 = 3;
+/// Prepended to the announcement bytes before signing, so the signature cannot be
+/// replayed as one over some other protocol message.
+const SIGNATURE_CONTEXT: &[u8] = b"p2p/discovery/v1/announcement";
+const SIGNATURE_LEN: usize = 64;
+/// MAGIC | version | sequence | announcement length
+const HEADER_LEN: usize = 4 + 1 + 8 + 2;
+
+/// An [`Announcement`] signed by the identity key it advertises.
+///
+/// The only ways to get one are [`Self::sign`] and [`Self::decode`], and both check the
+/// signature against `public_key_b64`, so holding one means the sender owns that key.
+/// It says nothing about whether the key belongs to `device_id`; that is for
+/// [`crate::PeerRegistry::upsert_signed`] to pin.
+///
+/// The signature also covers a `sequence` the sender raises whenever it re-signs, so a
+/// captured announcement cannot be replayed over a newer one.
+///
+/// The signed bytes are kept as received, so an announcement carrying metadata fields
+/// this build skips still encodes to what its sender signed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedAnnouncement {
+    announcement: Announcement,
+    sequence: u64,
+    payload: Vec<u8>,
+    signature: [u8; SIGNATURE_LEN],
+}
+
+impl SignedAnnouncement {
+    /// Sign `announcement`, which must advertise `identity`'s own public key.
+    ///
+    /// `sequence` must grow each time the device signs a new announcement; wall-clock
+    /// milliseconds work and survive a restart.
+    pub fn sign(
+        announcement: Announcement,
+        identity: &DeviceIdentity,
+        sequence: u64,
+    ) -> Result<Self, DiscoveryError> {
+        if announcement.public_key_b64 != identity.public_key_b64() {
+            return Err(DiscoveryError::KeyMismatch);
+        }
+        let payload = announcement.encode()?;
+        let signature = identity.sign(&signed_message(sequence, &payload));
+        Ok(Self {
+            announcement,
+            sequence,
+            payload,
+            signature,
+        })
+    }
+
+    pub fn announcement(&self) -> &Announcement {
+        &self.announcement
+    }
+
+    pub fn into_announcement(self) -> Announcement {
+        self.announcement
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn signature(&self) -> &[u8; SIGNATURE_LEN] {
+        &self.signature
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, DiscoveryError> {
+        // MAGIC | version(u8) | sequence(u64 be) | len(u16 be) + announcement | signature(64)
+        let inner = &self.payload;
+        let mut out = Vec::with_capacity(HEADER_LEN + inner.len() + SIGNATURE_LEN);
+        out.extend_from_slice(MAGIC);
+        out.push(ANNOUNCEMENT_VERSION_SIGNED);
+        out.extend_from_slice(&self.sequence.to_be_bytes());
+        out.extend_from_slice(&(inner.len() as u16).to_be_bytes());
+        out.extend_from_slice(inner);
+        out.extend_from_slice(&self.signature);
+        Ok(out)
+    }
+
+    /// Decode and verify against the public key the announcement carries.
+    pub fn decode(input: &[u8]) -> Result<Self, DiscoveryError> {
+        Self::decode_with_limits(input, &DecodeLimits::default())
+    }
+
+    /// [`Self::decode`] under `limits` rather than the defaults.
+    pub fn decode_with_limits(input: &[u8], limits: &DecodeLimits) -> Result<Self, DiscoveryError> {
+        limits.check_packet(input)?;
+        if input.len() < HEADER_LEN
+            || &input[..4] != MAGIC
+            || input[4] != ANNOUNCEMENT_VERSION_SIGNED
+        {
+            return Err(DiscoveryError::InvalidPacket("bad magic/header"));
+        }
+        let sequence = u64::from_be_bytes(input[5..13].try_into().expect("slice len"));
+        let len = u16::from_be_bytes([input[13], input[14]]) as usize;
+        if input.len() != HEADER_LEN + len + SIGNATURE_LEN {
+            return Err(DiscoveryError::InvalidLength);
+        }
+        let inner = &input[HEADER_LEN..HEADER_LEN + len];
+        let announcement = Announcement::decode_with_limits(inner, limits)?;
+        let signature: [u8; SIGNATURE_LEN] =
+            input[HEADER_LEN + len..].try_into().expect("slice len");
+        match verify_signature(
+            &announcement.public_key_b64,
+            &signed_message(sequence, inner),
+            &signature,
+        ) {
+            Ok(true) => Ok(Self {
+                announcement,
+                sequence,
+                payload: inner.to_vec(),
+                signature,
+            }),
+            _ => Err(DiscoveryError::InvalidSignature),
+        }
+    }
+}
+
+fn signed_message(sequence: u64, announcement: &[u8]) -> Vec<u8> {
+    [SIGNATURE_CONTEXT, &sequence.to_be_bytes(), announcement].concat()
+}
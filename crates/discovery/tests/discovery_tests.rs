@@ -1,4 +1,14 @@
-use discovery::{Announcement, DiscoveryService, PeerRegistry};
+use discovery::{
+    interface_for, local_interfaces, Announcement, Announcer, AnnouncerConfig, Cidr, DecodeLimits,
+    DiscoveryError, DiscoveryPacket, DiscoveryQuery, DiscoveryResponse, DiscoveryService,
+    DropCounters, FreeSpaceHint, LatencyProber, LeaveMessage, LocalInterface, PeerEntry,
+    PeerMetadata, PeerRegistry, PeerStatus, Ping, QueryTarget, RegistryEvent, RegistryLimits,
+    SignedAnnouncement, ANNOUNCEMENT_VERSION, ANNOUNCEMENT_VERSION_METADATA,
+    ANNOUNCEMENT_VERSION_SIGNED, ANNOUNCEMENT_VERSION_SPACE_HINT, MAX_CAPABILITIES,
+    MAX_CAPABILITY_LEN, MAX_DEVICE_ID_LEN, MAX_DISPLAY_NAME_LEN, MAX_PACKET_LEN, MAX_PLATFORM_LEN,
+    MAX_TRANSFER_VERSIONS, MIN_ANNOUNCE_INTERVAL, MULTICAST_GROUP_V4, MULTICAST_GROUP_V6,
+};
+use identity::DeviceIdentity;
 use std::net::{SocketAddr, UdpSocket};
 use std::thread;
 use std::time::{Duration, Instant};
@@ -9,13 +19,15 @@ fn sample_announcement(port: u16) -> Announcement {
         public_key_b64: "PUBKEYBASE64".to_string(),
         display_name: "Alice Laptop".to_string(),
         port,
+        free_space: None,
+        metadata: None,
     }
 }
 
 #[test]
 fn announcement_round_trip_encode_decode() {
     let a = sample_announcement(5000);
-    let b = Announcement::decode(&a.encode()).expect("decode works");
+    let b = Announcement::decode(&a.encode().expect("encode works")).expect("decode works");
     assert_eq!(a, b);
 }
 
@@ -25,6 +37,77 @@ fn invalid_packet_is_rejected() {
     assert!(Announcement::decode(bad).is_err());
 }
 
+#[test]
+fn announcement_carries_version_and_accepts_legacy_packets() {
+    let a = sample_announcement(5000);
+    let encoded = a.encode().expect("encode works");
+    assert_eq!(&encoded[..4], b"P2PD");
+    assert_eq!(encoded[4], ANNOUNCEMENT_VERSION);
+
+    // Legacy layout: MAGIC | port | strings, no version byte.
+    let mut legacy = encoded.clone();
+    legacy.remove(4);
+    assert_eq!(Announcement::decode(&legacy).expect("legacy decode"), a);
+
+    // Legacy port whose high byte collides with the version byte.
+    let mut ambiguous = sample_announcement(0x01aa).encode().expect("encode works");
+    ambiguous.remove(4);
+    assert_eq!(
+        Announcement::decode(&ambiguous)
+            .expect("ambiguous decode")
+            .port,
+        0x01aa
+    );
+}
+
+#[test]
+fn free_space_hint_is_bucketed_and_only_sent_when_advertised() {
+    const GIB: u64 = 1024 * 1024 * 1024;
+    assert_eq!(FreeSpaceHint::from_free_bytes(0).bucket(), 0);
+    assert_eq!(FreeSpaceHint::from_free_bytes(GIB - 1).bucket(), 1);
+    assert_eq!(FreeSpaceHint::from_free_bytes(GIB).bucket(), 2);
+    assert_eq!(
+        FreeSpaceHint::from_free_bytes(3 * GIB),
+        FreeSpaceHint::from_free_bytes(GIB + 7)
+    );
+    let top = FreeSpaceHint::from_free_bytes(u64::MAX);
+    assert_eq!(top.bucket(), FreeSpaceHint::BUCKETS - 1);
+    assert_eq!(top.upper_bound(), None);
+    assert!(!top.likely_lacks_space(u64::MAX));
+
+    let small = FreeSpaceHint::from_free_bytes(2 * GIB);
+    assert!(small.likely_lacks_space(4 * GIB));
+    assert!(!small.likely_lacks_space(3 * GIB));
+
+    let mut a = sample_announcement(5000);
+    a.free_space = Some(small);
+    let encoded = a.encode().expect("encode works");
+    assert_eq!(encoded[4], ANNOUNCEMENT_VERSION_SPACE_HINT);
+    assert_eq!(Announcement::decode(&encoded).expect("decode works"), a);
+
+    let mut bad_bucket = encoded.clone();
+    *bad_bucket.last_mut().unwrap() = FreeSpaceHint::BUCKETS;
+    assert!(Announcement::decode(&bad_bucket).is_err());
+}
+
+#[test]
+fn oversized_fields_are_rejected_not_truncated() {
+    let mut a = sample_announcement(5000);
+    a.display_name = "x".repeat(MAX_DISPLAY_NAME_LEN + 1);
+    assert!(matches!(
+        a.encode(),
+        Err(DiscoveryError::FieldTooLong("display_name"))
+    ));
+
+    // A hand-built packet declaring an oversized field fails on decode too.
+    let mut packet = b"P2PD".to_vec();
+    packet.push(ANNOUNCEMENT_VERSION);
+    packet.extend_from_slice(&5000u16.to_be_bytes());
+    packet.extend_from_slice(&1000u16.to_be_bytes());
+    packet.extend_from_slice(&[b'a'; 1000]);
+    assert!(Announcement::decode(&packet).is_err());
+}
+
 #[test]
 fn peer_registry_expires_stale_entries() {
     let mut registry = PeerRegistry::new(Duration::from_secs(1));
@@ -50,7 +133,10 @@ fn local_announce_discover_cycle_over_udp() {
     // Sender uses raw socket to simulate another peer process.
     let sender = UdpSocket::bind("127.0.0.1:0").expect("bind sender");
     let sent = sender
-        .send_to(&sample_announcement(7777).encode(), recv_addr)
+        .send_to(
+            &sample_announcement(7777).encode().expect("encode"),
+            recv_addr,
+        )
         .expect("send announcement");
     assert!(sent > 0);
 
@@ -59,3 +145,918 @@ fn local_announce_discover_cycle_over_udp() {
     assert_eq!(received.display_name, "Alice Laptop");
     assert_eq!(received.port, 7777);
 }
+
+#[test]
+fn ipv6_loopback_announce_keeps_scope_and_targets_v6_group() {
+    let receiver =
+        DiscoveryService::bind("[::1]:0".parse().expect("bind recv")).expect("receiver bind");
+    let sender =
+        DiscoveryService::bind("[::1]:0".parse().expect("bind send")).expect("sender bind");
+    sender
+        .send_announcement(
+            receiver.local_addr().expect("local addr"),
+            &sample_announcement(7777),
+        )
+        .expect("send");
+
+    let (announcement, source) = receiver.recv_announcement(2048).expect("recv");
+    let mut registry = PeerRegistry::new(Duration::from_secs(5));
+    registry.upsert(announcement, source, Instant::now());
+    let service = registry.peers()[0].service_addr();
+    assert_eq!(service, "[::1]:7777".parse::<SocketAddr>().expect("addr"));
+
+    let target = sender.multicast_target(47_000, 3).expect("target");
+    match target {
+        SocketAddr::V6(v6) => {
+            assert_eq!(*v6.ip(), MULTICAST_GROUP_V6);
+            assert_eq!(v6.scope_id(), 3);
+        }
+        SocketAddr::V4(_) => panic!("v6 socket must target the v6 group"),
+    }
+
+    let v4 = DiscoveryService::bind("127.0.0.1:0".parse().expect("bind v4")).expect("v4 bind");
+    assert_eq!(
+        v4.multicast_target(47_000, 3).expect("target").ip(),
+        MULTICAST_GROUP_V4
+    );
+}
+
+#[test]
+fn announcer_delay_stays_within_jitter() {
+    let any: SocketAddr = "127.0.0.1:0".parse().expect("addr");
+    let config =
+        AnnouncerConfig::new(any, any, Duration::from_secs(10)).with_jitter(Duration::from_secs(2));
+    for sample in [0, 1, 2_000_000_000, 4_000_000_000, u64::MAX] {
+        let delay = config.delay_for(sample);
+        assert!(delay >= Duration::from_secs(8) && delay <= Duration::from_secs(12));
+    }
+    assert_eq!(config.delay_for(0), Duration::from_secs(8));
+    assert_eq!(config.delay_for(4_000_000_000), Duration::from_secs(12));
+
+    let oversized = config.clone().with_jitter(Duration::from_secs(60));
+    assert!(oversized.delay_for(u64::MAX) <= Duration::from_secs(20));
+    let steady = config.with_jitter(Duration::ZERO);
+    assert_eq!(steady.delay_for(12345), Duration::from_secs(10));
+
+    // A zero interval, or jitter reaching down to zero, still waits between sends.
+    let zero = AnnouncerConfig::new(any, any, Duration::ZERO);
+    assert_eq!(zero.delay_for(12345), MIN_ANNOUNCE_INTERVAL);
+    let full_jitter = AnnouncerConfig::new(any, any, Duration::from_millis(150))
+        .with_jitter(Duration::from_millis(150));
+    assert_eq!(full_jitter.delay_for(0), MIN_ANNOUNCE_INTERVAL);
+}
+
+#[test]
+fn announcer_repeats_rebinds_and_stops() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").expect("bind recv");
+    receiver
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .expect("timeout");
+    let recv = || {
+        let mut buf = [0u8; 2048];
+        let (n, src) = receiver.recv_from(&mut buf)?;
+        Ok::<_, std::io::Error>((Announcement::decode(&buf[..n]).expect("decode"), src))
+    };
+    let config = AnnouncerConfig::new(
+        "127.0.0.1:0".parse().expect("bind addr"),
+        receiver.local_addr().expect("local addr"),
+        Duration::from_millis(20),
+    );
+    let handle = Announcer::start(config, &sample_announcement(7000)).expect("start");
+
+    let (first, first_src) = recv().expect("first");
+    let (second, _) = recv().expect("second");
+    assert_eq!(first, second);
+
+    handle.update(&sample_announcement(7001)).expect("update");
+    handle.rebind();
+    loop {
+        let (announcement, src) = recv().expect("announcement after rebind");
+        if announcement.port == 7001 && src != first_src {
+            break;
+        }
+    }
+    assert_eq!(handle.rebinds(), 1);
+    assert!(handle.sent() >= 3);
+
+    handle.stop();
+    receiver
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .expect("timeout");
+    while recv().is_ok() {}
+    thread::sleep(Duration::from_millis(100));
+    assert!(recv().is_err(), "announcer kept sending after stop");
+}
+
+fn signed_announcement(identity: &DeviceIdentity, device_id: &str) -> SignedAnnouncement {
+    signed_announcement_at(identity, device_id, 7000, 1)
+}
+
+fn signed_announcement_at(
+    identity: &DeviceIdentity,
+    device_id: &str,
+    port: u16,
+    sequence: u64,
+) -> SignedAnnouncement {
+    let announcement = Announcement {
+        device_id: device_id.to_string(),
+        public_key_b64: identity.public_key_b64(),
+        ..sample_announcement(port)
+    };
+    SignedAnnouncement::sign(announcement, identity, sequence).expect("sign")
+}
+
+#[test]
+fn signed_announcement_round_trips_and_rejects_tampering() {
+    let identity = DeviceIdentity::generate();
+    let signed = signed_announcement(&identity, "device-123");
+    let encoded = signed.encode().expect("encode");
+    assert_eq!(encoded[4], ANNOUNCEMENT_VERSION_SIGNED);
+    assert_eq!(
+        SignedAnnouncement::decode(&encoded).expect("decode"),
+        signed
+    );
+    assert!(Announcement::decode(&encoded).is_err());
+
+    // Renaming the device inside a valid packet breaks the signature.
+    let mut renamed = encoded.clone();
+    let at = renamed
+        .windows(10)
+        .position(|w| w == b"device-123")
+        .expect("device id in packet");
+    renamed[at + 9] = b'4';
+    assert!(matches!(
+        SignedAnnouncement::decode(&renamed),
+        Err(DiscoveryError::InvalidSignature)
+    ));
+
+    // Re-signing with another key while claiming the original key fails too.
+    let forger = DeviceIdentity::generate();
+    assert!(matches!(
+        SignedAnnouncement::sign(signed.announcement().clone(), &forger, 1),
+        Err(DiscoveryError::KeyMismatch)
+    ));
+    let mut forged = encoded[..encoded.len() - 64].to_vec();
+    forged.extend_from_slice(&forger.sign(b"anything"));
+    assert!(SignedAnnouncement::decode(&forged).is_err());
+}
+
+#[test]
+fn peer_registry_pins_signed_keys() {
+    let owner = DeviceIdentity::generate();
+    let spoofer = DeviceIdentity::generate();
+    let src: SocketAddr = "127.0.0.1:12345".parse().expect("socket addr");
+    let now = Instant::now();
+    let mut registry = PeerRegistry::new(Duration::from_secs(30));
+
+    // A signed announcement replaces an unverified entry, then pins its key.
+    registry.upsert(sample_announcement(9999), src, now);
+    assert!(!registry.peers()[0].verified);
+    registry
+        .upsert_signed(signed_announcement(&owner, "device-123"), src, now)
+        .expect("first signed");
+    assert!(registry.peers()[0].verified);
+
+    let spoofed = signed_announcement(&spoofer, "device-123");
+    assert!(matches!(
+        registry.upsert_signed_packet(&spoofed.encode().expect("encode"), src, now),
+        Err(DiscoveryError::KeyMismatch)
+    ));
+    let mut tampered = signed_announcement(&owner, "device-123")
+        .encode()
+        .expect("encode");
+    *tampered.last_mut().expect("signature") ^= 1;
+    assert!(registry.upsert_signed_packet(&tampered, src, now).is_err());
+
+    // A newer announcement moves the device; replaying the older one cannot move it back.
+    registry
+        .upsert_signed(
+            signed_announcement_at(&owner, "device-123", 7001, 2),
+            src,
+            now,
+        )
+        .expect("newer");
+    assert!(matches!(
+        registry.upsert_signed(signed_announcement(&owner, "device-123"), src, now),
+        Err(DiscoveryError::StaleAnnouncement)
+    ));
+    registry
+        .upsert_signed(
+            signed_announcement_at(&owner, "device-123", 7001, 2),
+            src,
+            now,
+        )
+        .expect("repeated");
+    assert_eq!(registry.peers()[0].sequence, Some(2));
+    // The sequence is signed, so it cannot be raised on a captured packet.
+    let mut bumped = signed_announcement(&owner, "device-123")
+        .encode()
+        .expect("encode");
+    bumped[12] = 9;
+    assert!(matches!(
+        SignedAnnouncement::decode(&bumped),
+        Err(DiscoveryError::InvalidSignature)
+    ));
+
+    let unsigned = Announcement {
+        public_key_b64: spoofer.public_key_b64(),
+        ..sample_announcement(1)
+    };
+    registry.upsert(unsigned, src, now);
+    assert_eq!(registry.len(), 1);
+    let entry = registry.peers()[0];
+    assert_eq!(entry.announcement.public_key_b64, owner.public_key_b64());
+    assert_eq!(entry.announcement.port, 7001);
+
+    // Once the entry expires the device can be claimed again.
+    registry.expire(now + Duration::from_secs(60));
+    registry
+        .upsert_signed(spoofed, src, now + Duration::from_secs(60))
+        .expect("after expiry");
+}
+
+#[test]
+fn leave_removes_peer_only_when_signed_by_announced_key() {
+    let owner = DeviceIdentity::generate();
+    let stranger = DeviceIdentity::generate();
+    let src: SocketAddr = "127.0.0.1:12345".parse().expect("socket addr");
+    let now = Instant::now();
+    let mut registry = PeerRegistry::new(Duration::from_secs(30));
+    registry
+        .upsert_signed(signed_announcement(&owner, "device-123"), src, now)
+        .expect("announce");
+
+    let forged = LeaveMessage::sign("device-123", &stranger).expect("sign");
+    assert!(matches!(
+        registry.remove_on_leave(&forged),
+        Err(DiscoveryError::KeyMismatch)
+    ));
+    assert_eq!(registry.len(), 1);
+
+    let leave = LeaveMessage::sign("device-123", &owner).expect("sign");
+    let encoded = leave.encode().expect("encode");
+    let mut tampered = encoded.clone();
+    tampered[7] ^= 1;
+    assert!(matches!(
+        LeaveMessage::decode(&tampered),
+        Err(DiscoveryError::InvalidSignature)
+    ));
+
+    match DiscoveryPacket::decode(&encoded).expect("decode") {
+        DiscoveryPacket::Leave(decoded) => {
+            assert_eq!(decoded, leave);
+            assert!(registry.remove_on_leave(&decoded).expect("leave"));
+        }
+        other => panic!("expected a leave, got {other:?}"),
+    }
+    assert!(registry.is_empty());
+    assert!(!registry.remove_on_leave(&leave).expect("unknown device"));
+}
+
+#[test]
+fn discovery_packet_dispatches_on_version() {
+    let identity = DeviceIdentity::generate();
+    let plain = sample_announcement(7000);
+    let signed = signed_announcement(&identity, "device-123");
+    assert_eq!(
+        DiscoveryPacket::decode(&plain.encode().expect("encode")).expect("plain"),
+        DiscoveryPacket::Announcement(plain.clone())
+    );
+    assert_eq!(
+        DiscoveryPacket::decode(&signed.encode().expect("encode")).expect("signed"),
+        DiscoveryPacket::Signed(signed)
+    );
+
+    // Legacy ports 0x03xx/0x04xx put the signed/leave version byte in the same place.
+    for port in [0x0350, 0x0450] {
+        let mut legacy = sample_announcement(port).encode().expect("encode");
+        legacy.remove(4);
+        assert_eq!(
+            DiscoveryPacket::decode(&legacy).expect("legacy"),
+            DiscoveryPacket::Announcement(sample_announcement(port))
+        );
+    }
+}
+
+#[test]
+fn announcer_sends_leave_on_shutdown() {
+    let identity = DeviceIdentity::generate();
+    let receiver =
+        DiscoveryService::bind("127.0.0.1:0".parse().expect("bind recv")).expect("receiver bind");
+    let config = AnnouncerConfig::new(
+        "127.0.0.1:0".parse().expect("bind addr"),
+        receiver.local_addr().expect("local addr"),
+        Duration::from_secs(60),
+    );
+    let handle = Announcer::start_signed(config, &signed_announcement(&identity, "device-123"))
+        .expect("start");
+    let mut registry = PeerRegistry::new(Duration::from_secs(300));
+    match receiver.recv_packet(2048).expect("announcement") {
+        (DiscoveryPacket::Signed(signed), src) => registry
+            .upsert_signed(signed, src, Instant::now())
+            .expect("upsert"),
+        other => panic!("expected a signed announcement, got {other:?}"),
+    }
+
+    handle
+        .leave(&LeaveMessage::sign("device-123", &identity).expect("sign"))
+        .expect("leave");
+    match receiver.recv_packet(2048).expect("leave") {
+        (DiscoveryPacket::Leave(leave), _) => {
+            assert!(registry.remove_on_leave(&leave).expect("remove"))
+        }
+        other => panic!("expected a leave, got {other:?}"),
+    }
+    assert!(registry.is_empty());
+}
+
+#[test]
+fn query_and_response_round_trip() {
+    let query = DiscoveryQuery::for_device("device-123");
+    assert_ne!(
+        query.query_id,
+        DiscoveryQuery::for_device("device-123").query_id
+    );
+    let encoded = query.encode().expect("encode");
+    assert_eq!(
+        DiscoveryPacket::decode(&encoded).expect("decode"),
+        DiscoveryPacket::Query(query.clone())
+    );
+    assert!(query.matches(&sample_announcement(7000), &[]));
+
+    let by_capability = DiscoveryQuery::for_capability("transfer-v3");
+    assert_eq!(
+        by_capability.target,
+        QueryTarget::Capability("transfer-v3".into())
+    );
+    assert!(by_capability.matches(&sample_announcement(7000), &["resume", "transfer-v3"]));
+    assert!(!by_capability.matches(&sample_announcement(7000), &["resume"]));
+
+    let identity = DeviceIdentity::generate();
+    let response =
+        DiscoveryResponse::signed(query.query_id, signed_announcement(&identity, "device-123"));
+    let decoded = DiscoveryResponse::decode(&response.encode().expect("encode")).expect("decode");
+    assert_eq!(decoded, response);
+    assert!(decoded.signed_announcement().is_some());
+
+    // A response wrapping a leave is not a response.
+    let mut wrapped_leave = response.encode().expect("encode")[..13].to_vec();
+    wrapped_leave.extend_from_slice(
+        &LeaveMessage::sign("device-123", &identity)
+            .expect("sign")
+            .encode()
+            .expect("encode"),
+    );
+    assert!(DiscoveryResponse::decode(&wrapped_leave).is_err());
+}
+
+#[test]
+fn query_refreshes_a_single_peer_over_udp() {
+    let responder =
+        DiscoveryService::bind("127.0.0.1:0".parse().expect("bind responder")).expect("bind");
+    let responder_addr = responder.local_addr().expect("local addr");
+    let handle = thread::spawn(move || {
+        let ours = sample_announcement(7100);
+        loop {
+            let (packet, src) = responder.recv_packet(2048).expect("recv query");
+            if let DiscoveryPacket::Query(query) = packet {
+                // A reply to someone else's query must be ignored by the querier.
+                responder
+                    .send_response(
+                        src,
+                        &DiscoveryResponse::new(query.query_id ^ 1, ours.clone()),
+                    )
+                    .expect("send stray");
+                if query.matches(&ours, &[]) {
+                    responder
+                        .send_response(src, &DiscoveryResponse::new(query.query_id, ours))
+                        .expect("send response");
+                    return;
+                }
+            }
+        }
+    });
+
+    let client = DiscoveryService::bind("127.0.0.1:0".parse().expect("bind client")).expect("bind");
+    client
+        .set_read_timeout(Some(Duration::from_millis(50)))
+        .expect("timeout");
+    let mut registry = PeerRegistry::new(Duration::from_secs(30));
+    let stale = Instant::now();
+    registry.upsert(sample_announcement(7000), responder_addr, stale);
+
+    let responses = client
+        .query(
+            responder_addr,
+            &DiscoveryQuery::for_device("device-123"),
+            Duration::from_millis(300),
+        )
+        .expect("query");
+    handle.join().expect("responder");
+    assert_eq!(responses.len(), 1);
+    // The caller's read timeout survives the query instead of being cleared.
+    assert!(client.recv_announcement(2048).is_err());
+    let (response, source) = responses.into_iter().next().expect("response");
+    registry
+        .upsert_response(response, source, stale + Duration::from_secs(1))
+        .expect("upsert");
+    assert_eq!(registry.peers()[0].service_addr().port(), 7100);
+}
+
+#[test]
+fn metadata_announcement_round_trips_and_skips_unknown_fields() {
+    let identity = DeviceIdentity::generate();
+    let mut announcement = Announcement {
+        public_key_b64: identity.public_key_b64(),
+        free_space: Some(FreeSpaceHint::from_free_bytes(1 << 33)),
+        metadata: Some(PeerMetadata {
+            status: PeerStatus::Busy,
+            transfer_versions: vec![3, 2],
+            encryption_required: true,
+            platform: "linux".into(),
+            capabilities: vec!["resume".into(), "folder-sync".into()],
+        }),
+        ..sample_announcement(7000)
+    };
+    let encoded = announcement.encode().expect("encode");
+    assert_eq!(encoded[4], ANNOUNCEMENT_VERSION_METADATA);
+    assert_eq!(
+        Announcement::decode(&encoded).expect("decode"),
+        announcement
+    );
+
+    // A field from a newer peer is skipped, and its signature still holds.
+    let mut newer = encoded.clone();
+    newer.extend_from_slice(&[0xee, 0, 2, 0xab, 0xcd]);
+    assert_eq!(
+        Announcement::decode(&newer).expect("decode newer"),
+        announcement
+    );
+    let mut wire = b"P2PD".to_vec();
+    wire.push(ANNOUNCEMENT_VERSION_SIGNED);
+    wire.extend_from_slice(&1u64.to_be_bytes());
+    wire.extend_from_slice(&(newer.len() as u16).to_be_bytes());
+    wire.extend_from_slice(&newer);
+    wire.extend_from_slice(
+        &identity.sign(
+            &[
+                b"p2p/discovery/v1/announcement".as_slice(),
+                &1u64.to_be_bytes(),
+                &newer,
+            ]
+            .concat(),
+        ),
+    );
+    let from_newer = SignedAnnouncement::decode(&wire).expect("signed newer");
+    assert_eq!(from_newer.announcement(), &announcement);
+    assert_eq!(from_newer.encode().expect("encode"), wire);
+
+    let signed = SignedAnnouncement::sign(announcement.clone(), &identity, 1).expect("sign");
+    let mut busy = PeerRegistry::new(Duration::from_secs(30));
+    let src: SocketAddr = "127.0.0.1:12345".parse().expect("socket addr");
+    busy.upsert_signed(signed, src, Instant::now())
+        .expect("upsert");
+    assert_eq!(busy.peers()[0].status(), PeerStatus::Busy);
+
+    announcement.metadata = Some(PeerMetadata {
+        platform: "x".repeat(65),
+        ..PeerMetadata::default()
+    });
+    assert!(matches!(
+        announcement.encode(),
+        Err(DiscoveryError::FieldTooLong("platform"))
+    ));
+
+    let mut bad_status = encoded.clone();
+    let at = bad_status
+        .windows(4)
+        .position(|w| w == [2, 0, 1, 1])
+        .expect("status field");
+    bad_status[at + 3] = 9;
+    assert!(Announcement::decode(&bad_status).is_err());
+}
+
+fn interface(name: &str, index: u32, addr: &str, prefix_len: u8) -> LocalInterface {
+    LocalInterface {
+        name: name.into(),
+        index,
+        addr: addr.parse().expect("ip"),
+        prefix_len,
+    }
+}
+
+#[test]
+fn peers_are_attributed_to_the_interface_they_were_heard_on() {
+    let wifi = interface("wlan0", 3, "192.168.1.20", 24);
+    let ethernet = interface("eth0", 2, "10.0.0.5", 8);
+    let wifi_v6 = interface("wlan0", 3, "fe80::1", 64);
+    let interfaces = vec![wifi.clone(), ethernet.clone(), wifi_v6.clone()];
+
+    let addr = |s: &str| s.parse::<SocketAddr>().expect("addr");
+    assert_eq!(
+        interface_for(&interfaces, addr("192.168.1.77:5000")),
+        Some(&wifi)
+    );
+    assert_eq!(
+        interface_for(&interfaces, addr("10.200.3.4:5000")),
+        Some(&ethernet)
+    );
+    assert_eq!(
+        interface_for(&interfaces, addr("[fe80::99%3]:5000")),
+        Some(&wifi_v6)
+    );
+    assert_eq!(interface_for(&interfaces, addr("[fe80::99%7]:5000")), None);
+    assert_eq!(interface_for(&interfaces, addr("192.168.2.1:5000")), None);
+    assert!(interface("odd", 1, "172.16.0.1", 12).contains(addr("172.31.255.255:1")));
+    assert!(!interface("odd", 1, "172.16.0.1", 12).contains(addr("172.32.0.1:1")));
+
+    let mut registry = PeerRegistry::new(Duration::from_secs(30));
+    registry.set_interfaces(interfaces);
+    let now = Instant::now();
+    registry.upsert(sample_announcement(7000), addr("10.1.1.1:7000"), now);
+    assert_eq!(registry.peers()[0].interface.as_ref(), Some(&ethernet));
+    registry.upsert(sample_announcement(7000), addr("192.168.1.9:7000"), now);
+    assert_eq!(
+        registry.peers()[0]
+            .interface
+            .as_ref()
+            .map(|i| i.name.as_str()),
+        Some("wlan0")
+    );
+
+    let config = AnnouncerConfig::for_interface(&wifi_v6, 47_000, Duration::from_secs(5));
+    match config.target {
+        SocketAddr::V6(target) => {
+            assert_eq!(*target.ip(), MULTICAST_GROUP_V6);
+            assert_eq!(target.scope_id(), 3);
+        }
+        SocketAddr::V4(_) => panic!("v6 interface must target the v6 group"),
+    }
+    assert_eq!(
+        AnnouncerConfig::for_interface(&wifi, 47_000, Duration::from_secs(5)).bind_addr,
+        addr("192.168.1.20:0")
+    );
+}
+
+#[test]
+fn announcer_runs_on_each_interface() {
+    let interfaces = local_interfaces().expect("enumerate interfaces");
+    assert!(interfaces.iter().all(|iface| !iface.addr.is_loopback()));
+
+    // Loopback stands in for two NICs: each interface gets its own socket and target.
+    let receiver = UdpSocket::bind("127.0.0.1:0").expect("bind recv");
+    receiver
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .expect("timeout");
+    let receiver_addr = receiver.local_addr().expect("local addr");
+    let lo = [
+        interface("lo-a", 1, "127.0.0.1", 8),
+        interface("lo-b", 1, "127.0.0.2", 8),
+    ];
+    let handles = lo
+        .iter()
+        .map(|iface| {
+            let mut config = AnnouncerConfig::for_interface(iface, 47_000, Duration::from_secs(60));
+            config.target = receiver_addr;
+            Announcer::start(config, &sample_announcement(7000)).expect("start")
+        })
+        .collect::<Vec<_>>();
+    let mut sources = Vec::new();
+    let mut buf = [0u8; 2048];
+    for _ in 0..2 {
+        let (_, src) = receiver.recv_from(&mut buf).expect("recv");
+        sources.push(src.ip());
+    }
+    sources.sort();
+    assert_eq!(
+        sources,
+        [[127, 0, 0, 1], [127, 0, 0, 2]].map(std::net::IpAddr::from)
+    );
+    drop(handles);
+
+    // Aimed at the group, the socket takes the interface's address as its multicast egress.
+    let config = AnnouncerConfig::for_interface(&lo[0], 47_000, Duration::from_secs(60));
+    Announcer::start(config, &sample_announcement(7000))
+        .expect("multicast interface")
+        .stop();
+}
+
+#[test]
+fn registry_rate_limits_sources_and_caps_peers() {
+    let limits = RegistryLimits {
+        max_peers: 4,
+        max_updates_per_source: 3,
+        rate_window: Duration::from_secs(10),
+    };
+    let mut registry = PeerRegistry::with_limits(Duration::from_secs(30), limits);
+    let flooder: SocketAddr = "192.168.1.66:5000".parse().expect("addr");
+    let now = Instant::now();
+    let spam = |n: u32| Announcement {
+        device_id: format!("spam-{n}"),
+        ..sample_announcement(7000)
+    };
+
+    // One source spamming unique device ids only lands its first few.
+    for n in 0..100 {
+        registry.upsert(spam(n), flooder, now);
+    }
+    assert_eq!(registry.len(), 3);
+    assert_eq!(registry.dropped().rate_limited, 97);
+
+    // Other sources still get in until the cap, then new devices are refused.
+    for n in 0..2u8 {
+        let source = SocketAddr::from(([192, 168, 1, 100 + n], 5000));
+        registry.upsert(spam(1000 + u32::from(n)), source, now);
+    }
+    assert_eq!(registry.len(), 4);
+    let identity = DeviceIdentity::generate();
+    assert!(matches!(
+        registry.upsert_signed(
+            signed_announcement(&identity, "late"),
+            "192.168.1.200:5000".parse().expect("addr"),
+            now
+        ),
+        Err(DiscoveryError::RegistryFull)
+    ));
+    assert_eq!(
+        registry.dropped(),
+        DropCounters {
+            rate_limited: 97,
+            registry_full: 2,
+        }
+    );
+
+    // A known device keeps updating while full, and the flooder's window reopens.
+    registry.upsert(spam(0), flooder, now + Duration::from_secs(10));
+    assert_eq!(registry.dropped().rate_limited, 97);
+    let refreshed = registry
+        .peers()
+        .into_iter()
+        .find(|p| p.announcement.device_id == "spam-0")
+        .expect("known device")
+        .last_seen;
+    assert_eq!(refreshed, now + Duration::from_secs(10));
+}
+
+#[test]
+fn ping_pong_measures_rtt_and_orders_peers() {
+    let pinger = DiscoveryService::bind("127.0.0.1:0".parse().expect("bind")).expect("bind");
+    let peer = DiscoveryService::bind("127.0.0.1:0".parse().expect("bind")).expect("bind");
+    let peer_addr = peer.local_addr().expect("local addr");
+    let echo = thread::spawn(move || match peer.recv_packet(64).expect("recv ping") {
+        (DiscoveryPacket::Ping(ping), src) => {
+            peer.send_pong(src, &ping).expect("send pong");
+        }
+        other => panic!("expected a ping, got {other:?}"),
+    });
+
+    let mut registry = PeerRegistry::new(Duration::from_secs(30));
+    let now = Instant::now();
+    for (id, port) in [("near", 7001), ("far", 7002), ("quiet", 7003)] {
+        let announcement = Announcement {
+            device_id: id.into(),
+            ..sample_announcement(port)
+        };
+        registry.upsert(announcement, peer_addr, now);
+    }
+
+    let mut prober = LatencyProber::new(Duration::from_secs(5));
+    let ping = prober.ping("near", Instant::now());
+    pinger.send_ping(peer_addr, &ping).expect("send ping");
+    let pong = match pinger.recv_packet(64).expect("recv pong") {
+        (DiscoveryPacket::Pong(pong), _) => pong,
+        other => panic!("expected a pong, got {other:?}"),
+    };
+    echo.join().expect("echo");
+    let (device, rtt) = prober.on_pong(pong, Instant::now()).expect("matched");
+    assert_eq!(device, "near");
+    assert!(
+        prober.on_pong(pong, Instant::now()).is_none(),
+        "repeated pong"
+    );
+    assert!(registry.record_rtt(&device, "PUBKEYBASE64", rtt));
+    assert!(registry.record_rtt("far", "PUBKEYBASE64", Duration::from_secs(1)));
+    assert!(!registry.record_rtt("unknown", "PUBKEYBASE64", rtt));
+    // A second key claiming "near" neither gets nor disturbs the first key's RTT.
+    let mut contested = PeerRegistry::new(Duration::from_secs(30));
+    for key in ["PUBKEYBASE64", "OTHERKEY"] {
+        let claim = Announcement {
+            device_id: "near".into(),
+            public_key_b64: key.into(),
+            ..sample_announcement(7001)
+        };
+        contested.upsert(claim, peer_addr, now);
+    }
+    assert!(contested.record_rtt("near", "PUBKEYBASE64", rtt));
+    assert!(!contested.record_rtt("near", "NOKEY", rtt));
+    let measured: Vec<_> = contested
+        .peers()
+        .iter()
+        .map(|p| (p.announcement.public_key_b64.clone(), p.rtt))
+        .collect();
+    assert!(measured.contains(&("PUBKEYBASE64".to_string(), Some(rtt))));
+    assert!(measured.contains(&("OTHERKEY".to_string(), None)));
+
+    // A re-announcement keeps the measurement.
+    registry.upsert(sample_announcement(7001), peer_addr, now);
+    let near = Announcement {
+        device_id: "near".into(),
+        ..sample_announcement(7001)
+    };
+    registry.upsert(near, peer_addr, now);
+    let order: Vec<_> = registry
+        .peers_by_latency()
+        .iter()
+        .map(|p| p.announcement.device_id.clone())
+        .collect();
+    assert_eq!(order, ["near", "far", "device-123", "quiet"]);
+
+    // One slow sample only nudges the smoothed value.
+    registry.record_rtt("far", "PUBKEYBASE64", Duration::from_secs(9));
+    let far = registry
+        .peers()
+        .into_iter()
+        .find(|p| p.announcement.device_id == "far")
+        .and_then(|p| p.rtt);
+    assert_eq!(far, Some(Duration::from_secs(2)));
+
+    // Unanswered pings are forgotten after the timeout.
+    let start = Instant::now();
+    prober.ping("quiet", start);
+    assert_eq!(prober.pending(), 1);
+    assert!(prober
+        .on_pong(Ping { nonce: 1 }.pong(), start + Duration::from_secs(6))
+        .is_none());
+    assert_eq!(prober.pending(), 0);
+}
+
+#[test]
+fn registry_filters_return_sorted_snapshots() {
+    let mut registry = PeerRegistry::new(Duration::from_secs(30));
+    let now = Instant::now();
+    let peer = |id: &str, name: &str, capabilities: &[&str]| Announcement {
+        device_id: id.into(),
+        display_name: name.into(),
+        metadata: Some(PeerMetadata {
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+            ..PeerMetadata::default()
+        }),
+        ..sample_announcement(7000)
+    };
+    let addr = |s: &str| s.parse::<SocketAddr>().expect("addr");
+    registry.upsert(
+        peer("a", "Meera MacBook", &["resume"]),
+        addr("192.168.1.34:1"),
+        now,
+    );
+    registry.upsert(peer("b", "Aarav iPhone", &[]), addr("192.168.1.12:1"), now);
+    registry.upsert(
+        peer("c", "meera pixel", &["resume", "folder-sync"]),
+        addr("[fd00::2a]:1"),
+        now,
+    );
+
+    let names = |peers: Vec<PeerEntry>| {
+        peers
+            .into_iter()
+            .map(|p| p.announcement.display_name)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        names(registry.peers_matching("MEERA")),
+        ["Meera MacBook", "meera pixel"]
+    );
+    assert_eq!(
+        names(registry.peers_with_capability("folder-sync")),
+        ["meera pixel"]
+    );
+    assert_eq!(names(registry.peers_with_capability("resume")).len(), 2);
+
+    let lan: Cidr = "192.168.1.0/24".parse().expect("cidr");
+    assert_eq!(
+        names(registry.peers_on_subnet(&lan)),
+        ["Aarav iPhone", "Meera MacBook"]
+    );
+    let ula: Cidr = "fd00::/8".parse().expect("cidr");
+    assert_eq!(names(registry.peers_on_subnet(&ula)), ["meera pixel"]);
+    let host: Cidr = "192.168.1.12".parse().expect("cidr");
+    assert_eq!(registry.peers_on_subnet(&host).len(), 1);
+    for bad in ["192.168.1.0/33", "nonsense/8", "10.0.0.0/x"] {
+        assert!(matches!(
+            bad.parse::<Cidr>(),
+            Err(DiscoveryError::InvalidSubnet)
+        ));
+    }
+
+    // Snapshots are owned: the registry can change underneath them.
+    let snapshot = registry.peers_matching("aarav");
+    registry.expire(now + Duration::from_secs(60));
+    assert!(registry.is_empty());
+    assert_eq!(snapshot[0].announcement.device_id, "b");
+}
+
+#[test]
+fn registry_flags_device_id_conflicts_and_reports_them() {
+    let owner = DeviceIdentity::generate();
+    let spoofer = DeviceIdentity::generate();
+    let first: SocketAddr = "192.168.1.10:7000".parse().expect("socket addr");
+    let second: SocketAddr = "192.168.1.66:7000".parse().expect("socket addr");
+    let now = Instant::now();
+    let mut registry = PeerRegistry::new(Duration::from_secs(30));
+
+    // The same key from a new address is a move, not a conflict.
+    registry.upsert(sample_announcement(7000), first, now);
+    registry.upsert(sample_announcement(7000), second, now);
+    assert_eq!(registry.len(), 1);
+    assert!(registry.drain_events().is_empty());
+
+    // Another key for the same id keeps both entries, flagged.
+    let other_key = Announcement {
+        public_key_b64: "OTHERKEY".to_string(),
+        ..sample_announcement(7000)
+    };
+    registry.upsert(other_key.clone(), first, now + Duration::from_secs(20));
+    assert_eq!(registry.len(), 2);
+    assert!(registry.peers().iter().all(|p| p.conflicting));
+    assert_eq!(
+        registry.drain_events(),
+        vec![RegistryEvent::DeviceIdConflict {
+            device_id: "device-123".to_string(),
+            sources: vec![first, second],
+        }]
+    );
+    registry.upsert(other_key, first, now + Duration::from_secs(20));
+    assert!(registry.drain_events().is_empty());
+
+    // Once one side expires the survivor is no longer flagged.
+    registry.expire(now + Duration::from_secs(40));
+    assert_eq!(registry.len(), 1);
+    assert!(!registry.peers()[0].conflicting);
+
+    // A verified entry wins, but spoofing attempts against it are still reported.
+    let later = now + Duration::from_secs(40);
+    registry
+        .upsert_signed(signed_announcement(&owner, "device-123"), first, later)
+        .expect("signed");
+    assert_eq!(registry.len(), 1);
+    assert!(registry.peers()[0].verified && registry.peers()[0].conflicting);
+    assert_eq!(registry.drain_events().len(), 1);
+    assert!(matches!(
+        registry.upsert_signed(signed_announcement(&spoofer, "device-123"), second, later),
+        Err(DiscoveryError::KeyMismatch)
+    ));
+    assert_eq!(registry.len(), 1);
+
+    let leave = LeaveMessage::sign("device-123", &owner).expect("sign");
+    assert!(registry.remove_on_leave(&leave).expect("leave"));
+    assert!(registry.is_empty());
+}
+
+#[test]
+fn decoder_limits_bound_packets_and_fields() {
+    // The largest packet the crate can build still fits the default limit.
+    let identity = DeviceIdentity::generate();
+    let largest = Announcement {
+        device_id: "d".repeat(MAX_DEVICE_ID_LEN),
+        public_key_b64: identity.public_key_b64(),
+        display_name: "n".repeat(MAX_DISPLAY_NAME_LEN),
+        port: 7000,
+        free_space: Some(FreeSpaceHint::from_bucket(0).expect("bucket")),
+        metadata: Some(PeerMetadata {
+            transfer_versions: vec![1; MAX_TRANSFER_VERSIONS],
+            platform: "p".repeat(MAX_PLATFORM_LEN),
+            capabilities: vec!["c".repeat(MAX_CAPABILITY_LEN); MAX_CAPABILITIES],
+            ..PeerMetadata::default()
+        }),
+    };
+    let signed = SignedAnnouncement::sign(largest, &identity, u64::MAX).expect("sign");
+    let response = DiscoveryResponse::signed(7, signed)
+        .encode()
+        .expect("encode");
+    assert!(response.len() <= MAX_PACKET_LEN);
+    assert!(DiscoveryPacket::decode(&response).is_ok());
+
+    let mut oversized = sample_announcement(5000).encode().expect("encode");
+    oversized.resize(MAX_PACKET_LEN + 1, 0);
+    assert!(matches!(
+        DiscoveryPacket::decode(&oversized),
+        Err(DiscoveryError::PacketTooLarge { len, max: MAX_PACKET_LEN }) if len == MAX_PACKET_LEN + 1
+    ));
+
+    // Tighter limits reject what the defaults accept, before reading the field.
+    let strict = DecodeLimits {
+        max_packet_len: 512,
+        max_display_name_len: 8,
+        ..DecodeLimits::default()
+    };
+    let packet = sample_announcement(5000).encode().expect("encode");
+    assert!(Announcement::decode(&packet).is_ok());
+    assert!(matches!(
+        Announcement::decode_with_limits(&packet, &strict),
+        Err(DiscoveryError::FieldTooLong("display_name"))
+    ));
+    assert!(matches!(
+        DiscoveryPacket::decode_with_limits(&response, &strict),
+        Err(DiscoveryError::PacketTooLarge { max: 512, .. })
+    ));
+}
@@ -0,0 +1,152 @@
+use crate::HandshakeError;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Timestamp of a hello sent without a usable wall clock.
+pub const UNSTAMPED: u64 = 0;
+
+/// Earliest reading [`SystemClock`] believes: 2020-01-01T00:00:00Z.
+const MIN_PLAUSIBLE_UNIX_SECS: u64 = 1_577_836_800;
+
+/// Wall-clock source for hello timestamps and skew checks.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Unix seconds, or `None` while the time is not known.
+    fn now_unix(&self) -> Option<u64>;
+}
+
+/// The operating system clock.
+///
+/// A clock that reads before 2020, such as an RTC reset to the epoch on an embedded
+/// board, counts as unknown rather than as a real time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_unix(&self) -> Option<u64> {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|elapsed| elapsed.as_secs())
+            .filter(|&secs| secs >= MIN_PLAUSIBLE_UNIX_SECS)
+    }
+}
+
+/// A clock set by hand, for tests and simulations.
+#[derive(Debug, Default)]
+pub struct FixedClock {
+    now: Mutex<Option<u64>>,
+}
+
+impl FixedClock {
+    pub fn new(unix_secs: u64) -> Self {
+        Self {
+            now: Mutex::new(Some(unix_secs)),
+        }
+    }
+
+    /// A clock that does not know the time.
+    pub fn unset() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, unix_secs: Option<u64>) {
+        *self.now.lock().expect("clock lock") = unix_secs;
+    }
+
+    /// Move the clock forward; an unset clock stays unset.
+    pub fn advance(&self, secs: u64) {
+        if let Some(now) = self.now.lock().expect("clock lock").as_mut() {
+            *now += secs;
+        }
+    }
+}
+
+impl Clock for FixedClock {
+    fn now_unix(&self) -> Option<u64> {
+        *self.now.lock().expect("clock lock")
+    }
+}
+
+/// Wraps a clock and, while it does not know the time, carries on from its last
+/// reading with the monotonic timer.
+#[derive(Debug)]
+pub struct MonotonicFallback {
+    clock: Arc<dyn Clock>,
+    anchor: Mutex<Option<(u64, Instant)>>,
+}
+
+impl MonotonicFallback {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            anchor: Mutex::new(None),
+        }
+    }
+
+    /// Start from a time learned elsewhere, e.g. from a paired phone, for a device that
+    /// boots without a wall clock.
+    pub fn anchored(clock: Arc<dyn Clock>, unix_secs: u64) -> Self {
+        Self {
+            clock,
+            anchor: Mutex::new(Some((unix_secs, Instant::now()))),
+        }
+    }
+}
+
+impl Clock for MonotonicFallback {
+    fn now_unix(&self) -> Option<u64> {
+        let mut anchor = self.anchor.lock().expect("clock lock");
+        match self.clock.now_unix() {
+            Some(now) => {
+                *anchor = Some((now, Instant::now()));
+                Some(now)
+            }
+            None => anchor.map(|(secs, at)| secs + at.elapsed().as_secs()),
+        }
+    }
+}
+
+/// How the peer's hello timestamp is checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SkewPolicy {
+    /// Largest clock difference accepted on the peer's hello timestamp.
+    pub max_skew_secs: u64,
+    /// Without a clock of our own, skip the check instead of failing the handshake. The
+    /// hello nonce and the Finished messages still stop replays.
+    pub allow_without_clock: bool,
+    /// Accept [`UNSTAMPED`] hellos from peers without a clock.
+    pub accept_unstamped: bool,
+}
+
+impl Default for SkewPolicy {
+    fn default() -> Self {
+        Self::new(30)
+    }
+}
+
+impl SkewPolicy {
+    /// A strict policy: both clocks must be known and within `max_skew_secs`.
+    pub fn new(max_skew_secs: u64) -> Self {
+        Self {
+            max_skew_secs,
+            allow_without_clock: false,
+            accept_unstamped: false,
+        }
+    }
+
+    /// Check a peer's `timestamp_secs` against our time, `None` when unknown.
+    pub fn check(&self, timestamp_secs: u64, now: Option<u64>) -> Result<(), HandshakeError> {
+        if timestamp_secs == UNSTAMPED && self.accept_unstamped {
+            return Ok(());
+        }
+        match now {
+            Some(now) if now.abs_diff(timestamp_secs) > self.max_skew_secs => {
+                Err(HandshakeError::TimestampSkew)
+            }
+            Some(_) => Ok(()),
+            None if self.allow_without_clock => Ok(()),
+            None => Err(HandshakeError::ClockUnavailable),
+        }
+    }
+}
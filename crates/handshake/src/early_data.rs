@@ -0,0 +1,211 @@
+//! 0-RTT early data: a client holding a resumption ticket from an earlier handshake with
+//! the same peer can send a small payload, such as a transfer manifest, encrypted inside
+//! its ClientHello.
+//!
+//! Early data is not protected by the handshake's fresh keys, so the responder accepts
+//! it only when every anti-replay check passes:
+//! - the hello is signed and its nonce went through the [`crate::ReplayGuard`];
+//! - the ticket is single-use and is consumed on first presentation;
+//! - the ticket is within its lifetime;
+//! - the hello timestamp is within [`MAX_EARLY_DATA_SKEW_SECS`], tighter than the
+//!   handshake's own skew allowance.
+//!
+//! Anything else is rejected without failing the handshake; the client then sends the
+//! data again once the handshake completes. Early data should still only carry requests
+//! that are safe to process twice.
+
+use crate::{
+    ClientHello, HandshakeError, HandshakeOutcome, HelloExtensions, LABEL_EARLY_DATA,
+    LABEL_RESUMPTION, LABEL_TICKET_ID,
+};
+use crypto_envelope::{decrypt_chunk_with_aad, encrypt_chunk_with_aad, SecretKeyBytes};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Early data in a ClientHello: ticket_id[16] | nonce[12] | ciphertext. In a
+/// ServerHello, an empty value saying the early data was accepted.
+pub const EXT_EARLY_DATA: u16 = 3;
+/// Largest early-data payload; anything bigger waits for the handshake.
+pub const MAX_EARLY_DATA_LEN: usize = 16 * 1024;
+/// Largest clock difference on a hello whose early data is accepted.
+pub const MAX_EARLY_DATA_SKEW_SECS: u64 = 10;
+/// How long a ticket can be used after the handshake that issued it.
+pub const DEFAULT_TICKET_LIFETIME_SECS: u64 = 24 * 60 * 60;
+
+/// Secret left over from a completed handshake, for early data in the next one.
+///
+/// Both peers derive the same ticket from the handshake's key schedule, so no message
+/// carries it; the client keeps it and the responder puts it in its [`TicketStore`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct ResumptionTicket {
+    pub id: [u8; 16],
+    pub peer_device_id: String,
+    pub peer_public_key_b64: String,
+    pub issued_at_secs: u64,
+    pub lifetime_secs: u64,
+    secret: SecretKeyBytes,
+}
+
+impl ResumptionTicket {
+    pub fn issue(outcome: &HandshakeOutcome, now_secs: u64, lifetime_secs: u64) -> Self {
+        let mut id = [0u8; 16];
+        id.copy_from_slice(&outcome.schedule.expand(LABEL_TICKET_ID)[..16]);
+        Self {
+            id,
+            peer_device_id: outcome.peer_device_id.clone(),
+            peer_public_key_b64: outcome.peer_public_key_b64.clone(),
+            issued_at_secs: now_secs,
+            lifetime_secs,
+            secret: SecretKeyBytes::new(outcome.schedule.expand(LABEL_RESUMPTION)),
+        }
+    }
+
+    pub fn is_valid_at(&self, now_secs: u64) -> bool {
+        now_secs >= self.issued_at_secs && now_secs - self.issued_at_secs < self.lifetime_secs
+    }
+
+    fn early_key(&self) -> SecretKeyBytes {
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::from_prk(self.secret.expose())
+            .expect("32 bytes is a valid HKDF-SHA256 PRK")
+            .expand(LABEL_EARLY_DATA, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        SecretKeyBytes::new(key)
+    }
+}
+
+impl fmt::Debug for ResumptionTicket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResumptionTicket")
+            .field("id", &self.id)
+            .field("peer_device_id", &self.peer_device_id)
+            .field("issued_at_secs", &self.issued_at_secs)
+            .field("lifetime_secs", &self.lifetime_secs)
+            .field("secret", &"<redacted>")
+            .finish()
+    }
+}
+
+/// What the responder made of a hello's early data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EarlyData {
+    NotOffered,
+    Accepted(Vec<u8>),
+    /// Refused for the given reason; the handshake itself goes on.
+    Rejected(&'static str),
+}
+
+/// Responder side of resumption: the unused tickets, at most one per peer.
+#[derive(Debug, Clone, Default)]
+pub struct TicketStore {
+    tickets: HashMap<[u8; 16], ResumptionTicket>,
+}
+
+impl TicketStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep `ticket`, replacing any earlier ticket for the same peer.
+    pub fn insert(&mut self, ticket: ResumptionTicket) {
+        self.tickets
+            .retain(|_, held| held.peer_device_id != ticket.peer_device_id);
+        self.tickets.insert(ticket.id, ticket);
+    }
+
+    /// Drop tickets past their lifetime.
+    pub fn expire(&mut self, now_secs: u64) {
+        self.tickets
+            .retain(|_, ticket| ticket.is_valid_at(now_secs));
+    }
+
+    pub fn len(&self) -> usize {
+        self.tickets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tickets.is_empty()
+    }
+
+    /// Open the early data of `hello`, consuming its ticket.
+    ///
+    /// Call only once the hello's signature verified and its nonce passed the replay
+    /// guard; [`crate::HandshakeResponder::on_client_hello_with_early_data`] does both.
+    pub fn accept_early_data(&mut self, hello: &ClientHello, now_secs: u64) -> EarlyData {
+        let Some(value) = hello.extensions.get(EXT_EARLY_DATA) else {
+            return EarlyData::NotOffered;
+        };
+        if value.len() < 16 + 12 {
+            return EarlyData::Rejected("malformed early data");
+        }
+        let (id, rest) = value.split_at(16);
+        let (nonce, ciphertext) = rest.split_at(12);
+        let id: [u8; 16] = id.try_into().expect("split at 16");
+        match self.tickets.get(&id) {
+            None => return EarlyData::Rejected("unknown or already used ticket"),
+            // Not consumed: a peer cannot burn another peer's ticket.
+            Some(ticket)
+                if ticket.peer_device_id != hello.device_id
+                    || ticket.peer_public_key_b64 != hello.public_key_b64 =>
+            {
+                return EarlyData::Rejected("ticket issued to another peer");
+            }
+            Some(_) => {}
+        }
+        let ticket = self.tickets.remove(&id).expect("looked up above");
+        if !ticket.is_valid_at(now_secs) {
+            return EarlyData::Rejected("ticket expired");
+        }
+        if now_secs.abs_diff(hello.timestamp_secs) > MAX_EARLY_DATA_SKEW_SECS {
+            return EarlyData::Rejected("hello too old for early data");
+        }
+        let nonce = nonce.try_into().expect("split at 12");
+        match decrypt_chunk_with_aad(ticket.early_key().expose(), nonce, ciphertext, &id) {
+            Ok(data) => EarlyData::Accepted(data),
+            Err(_) => EarlyData::Rejected("early data did not decrypt"),
+        }
+    }
+}
+
+impl HelloExtensions {
+    /// Carry `data` in the hello, encrypted for the peer that shares `ticket`.
+    pub fn with_early_data(
+        mut self,
+        ticket: &ResumptionTicket,
+        data: &[u8],
+    ) -> Result<Self, HandshakeError> {
+        if data.len() > MAX_EARLY_DATA_LEN {
+            return Err(HandshakeError::InvalidMessage("early data too long"));
+        }
+        // Random nonces, since a client may retry with the same ticket.
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext =
+            encrypt_chunk_with_aad(ticket.early_key().expose(), nonce, data, &ticket.id)
+                .map_err(|_| HandshakeError::InvalidMessage("early data did not encrypt"))?;
+        let mut value = Vec::with_capacity(16 + 12 + ciphertext.len());
+        value.extend_from_slice(&ticket.id);
+        value.extend_from_slice(&nonce);
+        value.extend_from_slice(&ciphertext);
+        self.insert(EXT_EARLY_DATA, value)?;
+        Ok(self)
+    }
+
+    pub fn offers_early_data(&self) -> bool {
+        self.get(EXT_EARLY_DATA)
+            .is_some_and(|value| !value.is_empty())
+    }
+
+    pub(crate) fn with_early_data_accepted(mut self) -> Result<Self, HandshakeError> {
+        self.insert(EXT_EARLY_DATA, Vec::new())?;
+        Ok(self)
+    }
+
+    pub fn early_data_accepted(&self) -> bool {
+        self.get(EXT_EARLY_DATA).is_some_and(<[u8]>::is_empty)
+    }
+}
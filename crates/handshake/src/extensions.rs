@@ -0,0 +1,202 @@
+use crate::HandshakeError;
+use crypto_envelope::{CipherSuite, PaddingScheme};
+use std::collections::BTreeMap;
+
+/// The peer can resume an interrupted transfer from a checkpoint. Empty value.
+pub const EXT_RESUMABLE_TRANSFER: u16 = 1;
+/// Largest chunk payload the peer accepts, as a big-endian u32.
+pub const EXT_MAX_CHUNK_SIZE: u16 = 2;
+/// Cipher suite ids the peer encrypts with, one byte each, most preferred first.
+pub const EXT_CIPHER_SUITES: u16 = 4;
+/// Payload padding scheme ids the peer can send, one byte each, most preferred first.
+pub const EXT_PADDING_SCHEMES: u16 = 5;
+
+/// Most extensions one hello may carry.
+pub const MAX_HELLO_EXTENSIONS: usize = 64;
+
+/// Optional features advertised in a hello as type/length/value entries.
+///
+/// Entries are kept sorted by type, so the signed and encoded forms are canonical.
+/// Types a peer does not know are carried and signed but otherwise ignored, which lets
+/// new features be advertised without breaking older peers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HelloExtensions {
+    entries: BTreeMap<u16, Vec<u8>>,
+}
+
+impl HelloExtensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or replace the extension of type `kind`.
+    pub fn insert(&mut self, kind: u16, value: Vec<u8>) -> Result<(), HandshakeError> {
+        if value.len() > u16::MAX as usize {
+            return Err(HandshakeError::InvalidMessage("hello extension too long"));
+        }
+        if !self.entries.contains_key(&kind) && self.entries.len() >= MAX_HELLO_EXTENSIONS {
+            return Err(HandshakeError::InvalidMessage("too many hello extensions"));
+        }
+        self.entries.insert(kind, value);
+        Ok(())
+    }
+
+    pub fn get(&self, kind: u16) -> Option<&[u8]> {
+        self.entries.get(&kind).map(Vec::as_slice)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// (type, value) pairs in type order.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        self.entries
+            .iter()
+            .map(|(&kind, value)| (kind, value.as_slice()))
+    }
+
+    pub fn with_resumable_transfer(mut self) -> Self {
+        self.entries.insert(EXT_RESUMABLE_TRANSFER, Vec::new());
+        self
+    }
+
+    pub fn supports_resumable_transfer(&self) -> bool {
+        self.entries.contains_key(&EXT_RESUMABLE_TRANSFER)
+    }
+
+    pub fn with_max_chunk_size(mut self, bytes: u32) -> Self {
+        self.entries
+            .insert(EXT_MAX_CHUNK_SIZE, bytes.to_be_bytes().to_vec());
+        self
+    }
+
+    /// `None` when the peer did not advertise a limit.
+    pub fn max_chunk_size(&self) -> Result<Option<u32>, HandshakeError> {
+        self.get(EXT_MAX_CHUNK_SIZE)
+            .map(|value| {
+                value
+                    .try_into()
+                    .map(u32::from_be_bytes)
+                    .map_err(|_| HandshakeError::InvalidCapabilities)
+            })
+            .transpose()
+    }
+
+    /// Commit to the client's short authentication string secret; see
+    /// [`crate::sas_commitment`].
+    pub fn with_sas_commitment(mut self, commitment: [u8; 32]) -> Self {
+        self.entries
+            .insert(crate::EXT_SAS_COMMITMENT, commitment.to_vec());
+        self
+    }
+
+    /// Advertise `suites` in preference order; duplicates after the first are dropped.
+    pub fn with_cipher_suites(mut self, suites: &[CipherSuite]) -> Self {
+        let mut ids = Vec::with_capacity(suites.len());
+        for suite in suites {
+            if !ids.contains(&suite.as_u8()) {
+                ids.push(suite.as_u8());
+            }
+        }
+        self.entries.insert(EXT_CIPHER_SUITES, ids);
+        self
+    }
+
+    /// Suites the peer advertised that this build implements, in the peer's order.
+    ///
+    /// A peer that advertised none only speaks ChaCha20-Poly1305, the suite every
+    /// build has had since frames were first encrypted.
+    pub fn cipher_suites(&self) -> Result<Vec<CipherSuite>, HandshakeError> {
+        match self.get(EXT_CIPHER_SUITES) {
+            None => Ok(vec![CipherSuite::ChaCha20Poly1305]),
+            Some([]) => Err(HandshakeError::InvalidCapabilities),
+            // Ids from newer builds are skipped, not rejected.
+            Some(ids) => Ok(ids
+                .iter()
+                .copied()
+                .filter_map(CipherSuite::from_u8)
+                .collect()),
+        }
+    }
+
+    /// Advertise `schemes` in preference order; duplicates after the first are dropped.
+    pub fn with_padding_schemes(mut self, schemes: &[PaddingScheme]) -> Self {
+        let mut ids = Vec::with_capacity(schemes.len());
+        for scheme in schemes {
+            if !ids.contains(&scheme.as_u8()) {
+                ids.push(scheme.as_u8());
+            }
+        }
+        self.entries.insert(EXT_PADDING_SCHEMES, ids);
+        self
+    }
+
+    /// Padding schemes the peer advertised that this build implements, in the peer's
+    /// order. A peer that advertised none does not pad.
+    pub fn padding_schemes(&self) -> Result<Vec<PaddingScheme>, HandshakeError> {
+        match self.get(EXT_PADDING_SCHEMES) {
+            None => Ok(vec![PaddingScheme::None]),
+            Some([]) => Err(HandshakeError::InvalidCapabilities),
+            Some(ids) => Ok(ids
+                .iter()
+                .copied()
+                .filter_map(PaddingScheme::from_u8)
+                .collect()),
+        }
+    }
+
+    /// count(u16) | (type(u16) | len(u16) | value)*
+    pub(crate) fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.entries.len() as u16).to_be_bytes());
+        for (kind, value) in &self.entries {
+            out.extend_from_slice(&kind.to_be_bytes());
+            out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            out.extend_from_slice(value);
+        }
+    }
+}
+
+/// Chunk size limit both peers accept: the smaller advertised limit, or `None` when
+/// neither advertised one.
+pub fn negotiate_max_chunk_size(
+    client: &HelloExtensions,
+    server: &HelloExtensions,
+) -> Result<Option<u32>, HandshakeError> {
+    Ok(match (client.max_chunk_size()?, server.max_chunk_size()?) {
+        (Some(client), Some(server)) => Some(client.min(server)),
+        (limit, None) | (None, limit) => limit,
+    })
+}
+
+/// Cipher suite for the session: the client's most preferred suite that the server
+/// also supports.
+pub fn negotiate_cipher_suite(
+    client: &HelloExtensions,
+    server: &HelloExtensions,
+) -> Result<CipherSuite, HandshakeError> {
+    let server = server.cipher_suites()?;
+    client
+        .cipher_suites()?
+        .into_iter()
+        .find(|suite| server.contains(suite))
+        .ok_or(HandshakeError::NoCommonCipherSuite)
+}
+
+/// Padding for the session: the client's most preferred scheme that the server also
+/// supports, or no padding when they share none.
+pub fn negotiate_padding(
+    client: &HelloExtensions,
+    server: &HelloExtensions,
+) -> Result<PaddingScheme, HandshakeError> {
+    let server = server.padding_schemes()?;
+    Ok(client
+        .padding_schemes()?
+        .into_iter()
+        .find(|scheme| server.contains(scheme))
+        .unwrap_or_default())
+}
@@ -0,0 +1,107 @@
+use crate::{
+    handshake_transcript, key_confirmation_tag, verify_key_confirmation, ClientHello,
+    HandshakeError, PskBinder, ServerHello, SessionKeys,
+};
+use sha2::{Digest, Sha256};
+
+const SERVER_FINISHED_LABEL: &[u8] = b"p2p/server-finished/v1";
+
+/// Last handshake message from the client: proves it derived the same keys as the
+/// server over the same pair of hellos.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientFinished {
+    /// The client's [`key_confirmation_tag`] over the handshake transcript.
+    pub verify_data: [u8; 32],
+    /// The client's PSK binder, when it holds a key for the network. The verify data is
+    /// computed before the PSK is mixed in, since only the server knows yet whether it
+    /// will be.
+    pub psk_binder: Option<PskBinder>,
+    /// The secret behind the hello's SAS commitment, revealed now that the server's
+    /// key share is fixed.
+    pub sas_secret: Option<[u8; 32]>,
+}
+
+/// The server's counterpart to [`ClientFinished`], sent once the client's verifies.
+///
+/// Its MAC also covers the client's verify data, so it confirms the server saw that
+/// exact message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerFinished {
+    /// The server's [`key_confirmation_tag`] over the transcript extended with the
+    /// client's verify data, under the final keys (PSK mixed in if one was accepted).
+    pub verify_data: [u8; 32],
+    /// The server's binder, present exactly when it accepted the client's PSK.
+    pub psk_binder: Option<PskBinder>,
+}
+
+impl ClientFinished {
+    /// Built by the client from its own keys.
+    pub fn new(keys: &SessionKeys, client: &ClientHello, server: &ServerHello) -> Self {
+        Self {
+            verify_data: key_confirmation_tag(keys, &handshake_transcript(client, server)),
+            psk_binder: None,
+            sas_secret: None,
+        }
+    }
+
+    /// Checked by the server under its receive key. A peer that derived different keys
+    /// fails here with [`HandshakeError::KeyConfirmationFailed`] instead of at the first
+    /// chunk.
+    pub fn verify(
+        &self,
+        keys: &SessionKeys,
+        client: &ClientHello,
+        server: &ServerHello,
+    ) -> Result<(), HandshakeError> {
+        verify_key_confirmation(
+            keys,
+            &handshake_transcript(client, server),
+            &self.verify_data,
+        )
+    }
+}
+
+impl ServerFinished {
+    /// Built by the server after verifying `client_finished`.
+    pub fn new(
+        keys: &SessionKeys,
+        client: &ClientHello,
+        server: &ServerHello,
+        client_finished: &ClientFinished,
+    ) -> Self {
+        Self {
+            verify_data: key_confirmation_tag(
+                keys,
+                &server_finished_transcript(client, server, client_finished),
+            ),
+            psk_binder: None,
+        }
+    }
+
+    /// Checked by the client under its receive key against the [`ClientFinished`] it sent.
+    pub fn verify(
+        &self,
+        keys: &SessionKeys,
+        client: &ClientHello,
+        server: &ServerHello,
+        client_finished: &ClientFinished,
+    ) -> Result<(), HandshakeError> {
+        verify_key_confirmation(
+            keys,
+            &server_finished_transcript(client, server, client_finished),
+            &self.verify_data,
+        )
+    }
+}
+
+fn server_finished_transcript(
+    client: &ClientHello,
+    server: &ServerHello,
+    client_finished: &ClientFinished,
+) -> [u8; 32] {
+    let mut hash = Sha256::new();
+    hash.update(SERVER_FINISHED_LABEL);
+    hash.update(handshake_transcript(client, server));
+    hash.update(client_finished.verify_data);
+    hash.finalize().into()
+}
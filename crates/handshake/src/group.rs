@@ -0,0 +1,155 @@
+use crate::{
+    ClientFinished, ClientHello, HandshakeConfig, HandshakeError, HandshakeInitiator,
+    HandshakeOutcome, ServerFinished, ServerHello, SessionKeys,
+};
+use identity::DeviceIdentity;
+use std::collections::BTreeMap;
+
+/// Sender side of a multi-receiver transfer: one pairwise handshake per receiver,
+/// keyed by the receiver's device id.
+///
+/// Every receiver gets its own keys, as the transfer crate's encrypted fan-out expects;
+/// hand each [`Self::outcome`]'s keys, cipher suite and padding to
+/// `TransferSession::set_receiver_key_with_suite` under the same ids. A failed handshake affects only its receiver, which can be started again.
+#[derive(Debug)]
+pub struct GroupSession {
+    config: HandshakeConfig,
+    members: BTreeMap<String, Member>,
+}
+
+#[derive(Debug)]
+enum Member {
+    Handshaking(Box<HandshakeInitiator>),
+    Ready(Box<HandshakeOutcome>),
+    Failed,
+}
+
+impl GroupSession {
+    /// `config` is used for the handshake with every receiver.
+    pub fn new(config: HandshakeConfig) -> Self {
+        Self {
+            config,
+            members: BTreeMap::new(),
+        }
+    }
+
+    /// Start, or restart, the handshake with `device_id` and return the hello to send
+    /// it. Keys from an earlier handshake with the device are dropped.
+    pub fn start(&mut self, identity: &DeviceIdentity, device_id: &str) -> ClientHello {
+        let (initiator, hello) = HandshakeInitiator::start(identity, self.config.clone());
+        self.members.insert(
+            device_id.to_string(),
+            Member::Handshaking(Box::new(initiator)),
+        );
+        hello
+    }
+
+    /// Pass on the server hello from `device_id`, which must be signed as that device.
+    pub fn on_server_hello(
+        &mut self,
+        device_id: &str,
+        hello: &ServerHello,
+    ) -> Result<ClientFinished, HandshakeError> {
+        let initiator = self.handshaking(device_id)?;
+        let result = if hello.device_id == device_id {
+            initiator.on_server_hello(hello)
+        } else {
+            Err(HandshakeError::PeerMismatch)
+        };
+        if result.is_err() {
+            self.members.insert(device_id.to_string(), Member::Failed);
+        }
+        result
+    }
+
+    /// Pass on the Finished from `device_id`; its keys are ready once it verifies.
+    pub fn on_server_finished(
+        &mut self,
+        device_id: &str,
+        finished: &ServerFinished,
+    ) -> Result<&HandshakeOutcome, HandshakeError> {
+        let result = self.handshaking(device_id)?.on_server_finished(finished);
+        let member = self
+            .members
+            .get_mut(device_id)
+            .expect("checked by handshaking");
+        match result {
+            Ok(outcome) => {
+                *member = Member::Ready(Box::new(outcome));
+                match member {
+                    Member::Ready(outcome) => Ok(outcome),
+                    _ => unreachable!("set above"),
+                }
+            }
+            Err(err) => {
+                *member = Member::Failed;
+                Err(err)
+            }
+        }
+    }
+
+    pub fn outcome(&self, device_id: &str) -> Option<&HandshakeOutcome> {
+        match self.members.get(device_id)? {
+            Member::Ready(outcome) => Some(outcome),
+            _ => None,
+        }
+    }
+
+    /// Keys of every receiver whose handshake completed, by device id.
+    pub fn session_keys(&self) -> BTreeMap<String, SessionKeys> {
+        self.members
+            .iter()
+            .filter_map(|(id, member)| match member {
+                Member::Ready(outcome) => Some((id.clone(), outcome.keys.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Receivers with keys, sorted.
+    pub fn ready(&self) -> Vec<&str> {
+        self.ids_where(|member| matches!(member, Member::Ready(_)))
+    }
+
+    /// Receivers still handshaking, sorted.
+    pub fn pending(&self) -> Vec<&str> {
+        self.ids_where(|member| matches!(member, Member::Handshaking(_)))
+    }
+
+    /// Receivers whose handshake failed and must be started again, sorted.
+    pub fn failed(&self) -> Vec<&str> {
+        self.ids_where(|member| matches!(member, Member::Failed))
+    }
+
+    /// Whether every receiver started so far has keys.
+    pub fn is_complete(&self) -> bool {
+        self.members
+            .values()
+            .all(|member| matches!(member, Member::Ready(_)))
+    }
+
+    /// Drop a receiver, e.g. one removed from the transfer.
+    pub fn remove(&mut self, device_id: &str) -> bool {
+        self.members.remove(device_id).is_some()
+    }
+
+    fn handshaking(&mut self, device_id: &str) -> Result<&mut HandshakeInitiator, HandshakeError> {
+        match self.members.get_mut(device_id) {
+            Some(Member::Handshaking(initiator)) => Ok(initiator),
+            Some(_) => Err(HandshakeError::UnexpectedMessage(
+                "no handshake in progress with this device",
+            )),
+            None => Err(HandshakeError::UnexpectedMessage(
+                "no handshake started with this device",
+            )),
+        }
+    }
+
+    fn ids_where(&self, keep: impl Fn(&Member) -> bool) -> Vec<&str> {
+        self.members
+            .iter()
+            .filter(|(_, member)| keep(member))
+            .map(|(id, _)| id.as_str())
+            .collect()
+    }
+}
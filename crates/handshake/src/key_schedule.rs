@@ -0,0 +1,103 @@
+use crate::SessionKeys;
+use crypto_envelope::SecretKeyBytes;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::fmt;
+use zeroize::Zeroize;
+
+/// Expand label for the client-to-server key.
+pub const LABEL_C2S: &[u8] = b"p2p/hkdf/v1/c2s";
+/// Expand label for the server-to-client key.
+pub const LABEL_S2C: &[u8] = b"p2p/hkdf/v1/s2c";
+/// Expand label for the secret later key rotations start from.
+pub const LABEL_REKEY: &[u8] = b"p2p/hkdf/v1/rekey";
+/// Expand label for the secret of the resumption ticket a handshake leaves behind.
+pub const LABEL_RESUMPTION: &[u8] = b"p2p/hkdf/v1/resumption";
+/// Expand label for the public id of that ticket.
+pub const LABEL_TICKET_ID: &[u8] = b"p2p/hkdf/v1/ticket-id";
+/// Expand label, under the resumption secret, for the early-data key.
+pub const LABEL_EARLY_DATA: &[u8] = b"p2p/hkdf/v1/early-data";
+
+/// HKDF key schedule of one handshake.
+///
+/// Extracted once from the X25519 secret, salted with the transcript hash, then
+/// expanded under a distinct label per key, so keys for different purposes never
+/// coincide and new ones can be added without touching the existing ones. Only the
+/// extracted PRK is kept, and it is wiped when the schedule is dropped.
+#[derive(Clone)]
+pub struct KeySchedule {
+    prk: SecretKeyBytes,
+}
+
+impl fmt::Debug for KeySchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("KeySchedule(..)")
+    }
+}
+
+impl KeySchedule {
+    pub fn new(shared_secret: &[u8; 32], transcript: &[u8; 32]) -> Self {
+        let (mut prk, _) = Hkdf::<Sha256>::extract(Some(transcript), shared_secret);
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&prk);
+        prk.as_mut_slice().zeroize();
+        Self {
+            prk: SecretKeyBytes::new(bytes),
+        }
+    }
+
+    /// A 32-byte key for `label`.
+    pub fn expand(&self, label: &[u8]) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        Hkdf::<Sha256>::from_prk(self.prk.expose())
+            .expect("32 bytes is a valid HKDF-SHA256 PRK")
+            .expand(label, &mut out)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        out
+    }
+
+    /// Directional keys, assigned tx/rx by role.
+    pub fn session_keys(&self, is_client: bool) -> SessionKeys {
+        directional(self.expand(LABEL_C2S), self.expand(LABEL_S2C), is_client)
+    }
+
+    /// Secret the first rekey derives from; never used to encrypt directly.
+    pub fn rekey_secret(&self) -> [u8; 32] {
+        self.expand(LABEL_REKEY)
+    }
+
+    /// Directional keys for key epoch `epoch`; epoch 0 is [`Self::session_keys`].
+    ///
+    /// Later epochs are expanded from [`Self::rekey_secret`] with the epoch in the info,
+    /// so every epoch gets independent keys and a fresh nonce space.
+    pub fn derive_next_keys(&self, epoch: u32, is_client: bool) -> SessionKeys {
+        if epoch == 0 {
+            return self.session_keys(is_client);
+        }
+        let secret = SecretKeyBytes::new(self.rekey_secret());
+        let rekey =
+            Hkdf::<Sha256>::from_prk(secret.expose()).expect("32 bytes is a valid HKDF-SHA256 PRK");
+        let expand = |label: &[u8]| {
+            let mut out = [0u8; 32];
+            rekey
+                .expand_multi_info(&[label, &epoch.to_be_bytes()], &mut out)
+                .expect("32 bytes is a valid HKDF-SHA256 output length");
+            out
+        };
+        directional(expand(LABEL_C2S), expand(LABEL_S2C), is_client)
+    }
+}
+
+fn directional(c2s: [u8; 32], s2c: [u8; 32], is_client: bool) -> SessionKeys {
+    if is_client {
+        SessionKeys {
+            tx_key: c2s,
+            rx_key: s2c,
+        }
+    } else {
+        SessionKeys {
+            tx_key: s2c,
+            rx_key: c2s,
+        }
+    }
+}
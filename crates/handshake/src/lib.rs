@@ -1,10 +1,64 @@
+mod clock;
+mod early_data;
+mod extensions;
+mod finished;
+mod group;
+mod key_schedule;
+mod pairing;
+mod psk;
+mod rekey;
+mod replay;
+mod retry;
+mod sas;
+mod state_machine;
+mod tofu;
+mod transcript;
+mod trust;
+mod wire;
+
+pub use clock::{Clock, FixedClock, MonotonicFallback, SkewPolicy, SystemClock, UNSTAMPED};
+pub use crypto_envelope::{CipherSuite, PaddingScheme, SecretKeyBytes, SessionKeySecret};
+pub use early_data::{
+    EarlyData, ResumptionTicket, TicketStore, DEFAULT_TICKET_LIFETIME_SECS, EXT_EARLY_DATA,
+    MAX_EARLY_DATA_LEN, MAX_EARLY_DATA_SKEW_SECS,
+};
+pub use extensions::{
+    negotiate_cipher_suite, negotiate_max_chunk_size, negotiate_padding, HelloExtensions,
+    EXT_CIPHER_SUITES, EXT_MAX_CHUNK_SIZE, EXT_PADDING_SCHEMES, EXT_RESUMABLE_TRANSFER,
+    MAX_HELLO_EXTENSIONS,
+};
+pub use finished::{ClientFinished, ServerFinished};
+pub use group::GroupSession;
+pub use key_schedule::{
+    KeySchedule, LABEL_C2S, LABEL_EARLY_DATA, LABEL_REKEY, LABEL_RESUMPTION, LABEL_S2C,
+    LABEL_TICKET_ID,
+};
+pub use pairing::{PairingCode, PairingKey, PairingShare, PairingState, MIN_PAIRING_CODE_LEN};
+pub use psk::{
+    accept_client_psk, accept_server_psk, client_psk_binder, mix_psk_into_keys, server_psk_binder,
+    PreSharedKey, PskBinder, PskProfile, PskProfiles, MIN_PSK_LEN,
+};
+pub use rekey::{KeyRotation, Rekey, RekeyPolicy, DEFAULT_REKEY_GRACE};
+pub use replay::{ReplayGuard, ReplayGuardStats, DEFAULT_REPLAY_GUARD_ENTRIES};
+pub use retry::{AttemptAction, HandshakeAttempt, RetryPolicy};
+pub use sas::{sas_commitment, verify_sas_reveal, ShortAuthString, EXT_SAS_COMMITMENT};
+pub use state_machine::{
+    HandshakeConfig, HandshakeInitiator, HandshakeOutcome, HandshakeResponder, InitiatorState,
+    ResponderState,
+};
+pub use tofu::{KeyChangedError, PeerTrustState, PinCheck};
+pub use transcript::TranscriptHash;
+pub use trust::{TrustStore, TrustedPeer};
+
+use hmac::{Hmac, Mac};
 use identity::{verify_signature, DeviceIdentity, IdentityError};
 use rand::rngs::OsRng;
 use rand::RngCore;
-use sha2::{Digest, Sha256};
-use std::collections::HashMap;
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use sha2::Sha256;
+use std::fmt;
 use thiserror::Error;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroize;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EncryptionMode {
@@ -30,12 +84,70 @@ impl EncryptionMode {
             _ => Err(HandshakeError::InvalidCapabilities),
         }
     }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EncryptionMode::Off => "off",
+            EncryptionMode::Optional => "optional",
+            EncryptionMode::Required => "required",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(EncryptionMode::Off),
+            "optional" => Some(EncryptionMode::Optional),
+            "required" => Some(EncryptionMode::Required),
+            _ => None,
+        }
+    }
+
+    /// The more demanding of two modes (`Required` > `Optional` > `Off`).
+    pub fn stricter(self, other: Self) -> Self {
+        if other.as_u8() > self.as_u8() {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+/// Chunk payload codecs a peer can decode, advertised in its hello.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionSupport {
+    pub zstd: bool,
+    pub lz4: bool,
+}
+
+impl CompressionSupport {
+    fn as_u8(self) -> u8 {
+        self.zstd as u8 | (self.lz4 as u8) << 1
+    }
+}
+
+/// Codec both peers decode; senders may still skip it for chunks that do not shrink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NegotiatedCompression {
+    None,
+    Zstd,
+    Lz4,
+}
+
+impl NegotiatedCompression {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NegotiatedCompression::None => "none",
+            NegotiatedCompression::Zstd => "zstd",
+            NegotiatedCompression::Lz4 => "lz4",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct HandshakeCapabilities {
     pub supports_encryption: bool,
     pub preferred_encryption_mode: EncryptionMode,
+    pub compression: CompressionSupport,
 }
 
 impl Default for HandshakeCapabilities {
@@ -43,6 +155,7 @@ impl Default for HandshakeCapabilities {
         Self {
             supports_encryption: false,
             preferred_encryption_mode: EncryptionMode::Off,
+            compression: CompressionSupport::default(),
         }
     }
 }
@@ -53,64 +166,92 @@ pub struct NegotiatedEncryption {
     pub mode: EncryptionMode,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ClientHello {
     pub device_id: String,
     pub public_key_b64: String,
+    /// X25519 share for this handshake only.
+    pub ephemeral_public: [u8; 32],
     pub nonce: [u8; 32],
     pub timestamp_secs: u64,
     pub capabilities: HandshakeCapabilities,
+    /// Optional features beyond the fixed capabilities; covered by the signature.
+    pub extensions: HelloExtensions,
     pub signature: [u8; 64],
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ServerHello {
     pub device_id: String,
     pub public_key_b64: String,
+    pub ephemeral_public: [u8; 32],
     pub client_nonce: [u8; 32],
     pub server_nonce: [u8; 32],
+    /// [`TranscriptHash`] after the client hello as the server received it. Signed, so
+    /// a client hello altered in flight (e.g. stripped of its encryption capability)
+    /// fails verification on the client.
+    pub client_hello_hash: [u8; 32],
     pub timestamp_secs: u64,
     pub capabilities: HandshakeCapabilities,
+    /// Optional features beyond the fixed capabilities; covered by the signature.
+    pub extensions: HelloExtensions,
     pub signature: [u8; 64],
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct SessionKeys {
-    pub tx_key: [u8; 32],
-    pub rx_key: [u8; 32],
-}
-
-#[derive(Debug)]
-pub struct ReplayGuard {
-    seen: HashMap<[u8; 32], Instant>,
-    ttl: Duration,
+/// X25519 secret for one handshake; its public half travels in the signed hello.
+///
+/// Made fresh for every hello and dropped once the keys are derived, so a later leak
+/// of the long-term identity key does not expose past sessions.
+pub struct EphemeralKey {
+    secret: StaticSecret,
 }
 
-impl ReplayGuard {
-    pub fn new(ttl: Duration) -> Self {
+impl EphemeralKey {
+    pub fn generate() -> Self {
         Self {
-            seen: HashMap::new(),
-            ttl,
+            secret: StaticSecret::random_from_rng(OsRng),
         }
     }
 
-    pub fn check_and_remember(&mut self, nonce: [u8; 32], now: Instant) -> bool {
-        self.expire(now);
-        if self.seen.contains_key(&nonce) {
-            return false;
-        }
-        self.seen.insert(nonce, now);
-        true
+    pub fn public_bytes(&self) -> [u8; 32] {
+        PublicKey::from(&self.secret).to_bytes()
+    }
+}
+
+impl fmt::Debug for EphemeralKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EphemeralKey")
+            .field("public", &self.public_bytes())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Both keys are wiped when the value is dropped; copies taken out of the fields are
+/// not, so long-lived holders should keep a [`SessionKeySecret`] instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionKeys {
+    pub tx_key: [u8; 32],
+    pub rx_key: [u8; 32],
+}
+
+impl SessionKeys {
+    pub fn to_secret(&self) -> SessionKeySecret {
+        SessionKeySecret::new(self.tx_key, self.rx_key)
     }
+}
 
-    pub fn expire(&mut self, now: Instant) {
-        let ttl = self.ttl;
-        self.seen
-            .retain(|_, seen_at| now.duration_since(*seen_at) <= ttl);
+impl Drop for SessionKeys {
+    fn drop(&mut self) {
+        self.tx_key.zeroize();
+        self.rx_key.zeroize();
     }
 }
 
-pub fn create_client_hello(device_id: &str, identity: &DeviceIdentity) -> ClientHello {
+/// Signed hello plus the ephemeral secret to keep for [`derive_session_keys`].
+pub fn create_client_hello(
+    device_id: &str,
+    identity: &DeviceIdentity,
+) -> (ClientHello, EphemeralKey) {
     create_client_hello_with_capabilities(device_id, identity, HandshakeCapabilities::default())
 }
 
@@ -118,27 +259,73 @@ pub fn create_client_hello_with_capabilities(
     device_id: &str,
     identity: &DeviceIdentity,
     capabilities: HandshakeCapabilities,
-) -> ClientHello {
-    let nonce = random_nonce();
-    let timestamp_secs = now_unix();
-    let public_key_b64 = identity.public_key_b64();
-    let to_sign = client_hello_signing_bytes(
+) -> (ClientHello, EphemeralKey) {
+    create_client_hello_with_extensions(
         device_id,
-        &public_key_b64,
-        nonce,
-        timestamp_secs,
+        identity,
         capabilities,
+        HelloExtensions::default(),
+    )
+}
+
+pub fn create_client_hello_with_extensions(
+    device_id: &str,
+    identity: &DeviceIdentity,
+    capabilities: HandshakeCapabilities,
+    extensions: HelloExtensions,
+) -> (ClientHello, EphemeralKey) {
+    client_hello_at(
+        device_id,
+        identity,
+        capabilities,
+        extensions,
+        SystemClock.now_unix().unwrap_or(UNSTAMPED),
+    )
+}
+
+pub(crate) fn client_hello_at(
+    device_id: &str,
+    identity: &DeviceIdentity,
+    capabilities: HandshakeCapabilities,
+    extensions: HelloExtensions,
+    timestamp_secs: u64,
+) -> (ClientHello, EphemeralKey) {
+    let ephemeral = EphemeralKey::generate();
+    let hello = client_hello_with_ephemeral(
+        device_id,
+        identity,
+        capabilities,
+        extensions,
+        timestamp_secs,
+        &ephemeral,
     );
-    let signature = identity.sign(&to_sign);
+    (hello, ephemeral)
+}
 
-    ClientHello {
+/// [`client_hello_at`] for an ephemeral key made beforehand, e.g. so the extensions can
+/// commit to it.
+pub(crate) fn client_hello_with_ephemeral(
+    device_id: &str,
+    identity: &DeviceIdentity,
+    capabilities: HandshakeCapabilities,
+    extensions: HelloExtensions,
+    timestamp_secs: u64,
+    ephemeral: &EphemeralKey,
+) -> ClientHello {
+    let ephemeral_public = ephemeral.public_bytes();
+    let nonce = random_nonce();
+    let mut hello = ClientHello {
         device_id: device_id.to_string(),
-        public_key_b64,
+        public_key_b64: identity.public_key_b64(),
+        ephemeral_public,
         nonce,
         timestamp_secs,
         capabilities,
-        signature,
-    }
+        extensions,
+        signature: [0u8; 64],
+    };
+    hello.signature = identity.sign(&hello.signing_bytes());
+    hello
 }
 
 pub fn verify_client_hello(
@@ -146,20 +333,23 @@ pub fn verify_client_hello(
     max_skew_secs: u64,
     now_secs: u64,
 ) -> Result<(), HandshakeError> {
-    if is_skewed(hello.timestamp_secs, now_secs, max_skew_secs) {
-        return Err(HandshakeError::TimestampSkew);
-    }
+    verify_client_hello_with(hello, &SkewPolicy::new(max_skew_secs), Some(now_secs))
+}
 
-    let data = client_hello_signing_bytes(
-        &hello.device_id,
-        &hello.public_key_b64,
-        hello.nonce,
-        hello.timestamp_secs,
-        hello.capabilities,
-    );
+/// [`verify_client_hello`] under a [`SkewPolicy`], with `now` from a [`Clock`].
+pub fn verify_client_hello_with(
+    hello: &ClientHello,
+    skew: &SkewPolicy,
+    now: Option<u64>,
+) -> Result<(), HandshakeError> {
+    skew.check(hello.timestamp_secs, now)?;
 
-    let valid = verify_signature(&hello.public_key_b64, &data, &hello.signature)
-        .map_err(HandshakeError::Identity)?;
+    let valid = verify_signature(
+        &hello.public_key_b64,
+        &hello.signing_bytes(),
+        &hello.signature,
+    )
+    .map_err(HandshakeError::Identity)?;
     if !valid {
         return Err(HandshakeError::InvalidSignature);
     }
@@ -171,7 +361,7 @@ pub fn create_server_hello(
     device_id: &str,
     server_identity: &DeviceIdentity,
     client_hello: &ClientHello,
-) -> ServerHello {
+) -> (ServerHello, EphemeralKey) {
     create_server_hello_with_capabilities(
         device_id,
         server_identity,
@@ -185,63 +375,132 @@ pub fn create_server_hello_with_capabilities(
     server_identity: &DeviceIdentity,
     client_hello: &ClientHello,
     capabilities: HandshakeCapabilities,
-) -> ServerHello {
-    let server_nonce = random_nonce();
-    let timestamp_secs = now_unix();
-    let public_key_b64 = server_identity.public_key_b64();
-    let data = server_hello_signing_bytes(
+) -> (ServerHello, EphemeralKey) {
+    create_server_hello_with_extensions(
         device_id,
-        &public_key_b64,
-        client_hello.nonce,
-        server_nonce,
-        timestamp_secs,
+        server_identity,
+        client_hello,
         capabilities,
-    );
-    let signature = server_identity.sign(&data);
+        HelloExtensions::default(),
+    )
+}
 
-    ServerHello {
+pub fn create_server_hello_with_extensions(
+    device_id: &str,
+    server_identity: &DeviceIdentity,
+    client_hello: &ClientHello,
+    capabilities: HandshakeCapabilities,
+    extensions: HelloExtensions,
+) -> (ServerHello, EphemeralKey) {
+    server_hello_at(
+        device_id,
+        server_identity,
+        client_hello,
+        capabilities,
+        extensions,
+        SystemClock.now_unix().unwrap_or(UNSTAMPED),
+    )
+}
+
+pub(crate) fn server_hello_at(
+    device_id: &str,
+    server_identity: &DeviceIdentity,
+    client_hello: &ClientHello,
+    capabilities: HandshakeCapabilities,
+    extensions: HelloExtensions,
+    timestamp_secs: u64,
+) -> (ServerHello, EphemeralKey) {
+    let ephemeral = EphemeralKey::generate();
+    let ephemeral_public = ephemeral.public_bytes();
+    let server_nonce = random_nonce();
+    let mut hello = ServerHello {
         device_id: device_id.to_string(),
-        public_key_b64,
+        public_key_b64: server_identity.public_key_b64(),
+        ephemeral_public,
         client_nonce: client_hello.nonce,
         server_nonce,
+        client_hello_hash: TranscriptHash::after_client_hello(client_hello).current(),
         timestamp_secs,
         capabilities,
-        signature,
-    }
+        extensions,
+        signature: [0u8; 64],
+    };
+    hello.signature = server_identity.sign(&hello.signing_bytes());
+    (hello, ephemeral)
 }
 
+/// Check the server's answer to `client_hello`, the hello this side sent.
+///
+/// Fails with [`HandshakeError::TranscriptMismatch`] when the server signed a
+/// different client hello than the one sent, which is how a downgrade in flight shows.
 pub fn verify_server_hello(
-    expected_client_nonce: [u8; 32],
+    client_hello: &ClientHello,
     hello: &ServerHello,
     max_skew_secs: u64,
     now_secs: u64,
 ) -> Result<(), HandshakeError> {
-    if hello.client_nonce != expected_client_nonce {
+    verify_server_hello_with(
+        client_hello,
+        hello,
+        &SkewPolicy::new(max_skew_secs),
+        Some(now_secs),
+    )
+}
+
+/// [`verify_server_hello`] under a [`SkewPolicy`], with `now` from a [`Clock`].
+pub fn verify_server_hello_with(
+    client_hello: &ClientHello,
+    hello: &ServerHello,
+    skew: &SkewPolicy,
+    now: Option<u64>,
+) -> Result<(), HandshakeError> {
+    if hello.client_nonce != client_hello.nonce {
         return Err(HandshakeError::NonceMismatch);
     }
 
-    if is_skewed(hello.timestamp_secs, now_secs, max_skew_secs) {
-        return Err(HandshakeError::TimestampSkew);
-    }
+    skew.check(hello.timestamp_secs, now)?;
 
-    let data = server_hello_signing_bytes(
-        &hello.device_id,
+    let valid = verify_signature(
         &hello.public_key_b64,
-        hello.client_nonce,
-        hello.server_nonce,
-        hello.timestamp_secs,
-        hello.capabilities,
-    );
-
-    let valid = verify_signature(&hello.public_key_b64, &data, &hello.signature)
-        .map_err(HandshakeError::Identity)?;
+        &hello.signing_bytes(),
+        &hello.signature,
+    )
+    .map_err(HandshakeError::Identity)?;
     if !valid {
         return Err(HandshakeError::InvalidSignature);
     }
+    if hello.client_hello_hash != TranscriptHash::after_client_hello(client_hello).current() {
+        return Err(HandshakeError::TranscriptMismatch);
+    }
 
     Ok(())
 }
 
+/// [`verify_client_hello`], then check the client's key against its pin, pinning it on
+/// first contact. A known device with a new key fails with
+/// [`HandshakeError::KeyChanged`].
+pub fn verify_client_hello_pinned(
+    hello: &ClientHello,
+    pins: &mut TrustStore,
+    max_skew_secs: u64,
+    now_secs: u64,
+) -> Result<PinCheck, HandshakeError> {
+    verify_client_hello(hello, max_skew_secs, now_secs)?;
+    pins.check(&hello.device_id, &hello.public_key_b64, now_secs)
+}
+
+/// [`verify_server_hello`], then the same pin check as [`verify_client_hello_pinned`].
+pub fn verify_server_hello_pinned(
+    client_hello: &ClientHello,
+    hello: &ServerHello,
+    pins: &mut TrustStore,
+    max_skew_secs: u64,
+    now_secs: u64,
+) -> Result<PinCheck, HandshakeError> {
+    verify_server_hello(client_hello, hello, max_skew_secs, now_secs)?;
+    pins.check(&hello.device_id, &hello.public_key_b64, now_secs)
+}
+
 pub fn negotiate_encryption(
     client: HandshakeCapabilities,
     server: HandshakeCapabilities,
@@ -286,6 +545,22 @@ pub fn negotiate_encryption(
     })
 }
 
+/// Pick the payload codec: zstd when both sides decode it (better ratio on text and
+/// logs), else lz4, else none.
+pub fn negotiate_compression(
+    client: HandshakeCapabilities,
+    server: HandshakeCapabilities,
+) -> NegotiatedCompression {
+    let (client, server) = (client.compression, server.compression);
+    if client.zstd && server.zstd {
+        NegotiatedCompression::Zstd
+    } else if client.lz4 && server.lz4 {
+        NegotiatedCompression::Lz4
+    } else {
+        NegotiatedCompression::None
+    }
+}
+
 fn validate_capabilities(capabilities: HandshakeCapabilities) -> Result<(), HandshakeError> {
     // Roundtrip check so invalid discriminants are rejected if structs were built via unchecked paths.
     let _ = EncryptionMode::from_u8(capabilities.preferred_encryption_mode.as_u8())?;
@@ -300,45 +575,88 @@ fn validate_capabilities(capabilities: HandshakeCapabilities) -> Result<(), Hand
 }
 
 /// Derive directional keys so each side gets tx/rx based on role.
+///
+/// Shorthand for [`derive_key_schedule`] followed by [`KeySchedule::session_keys`].
 pub fn derive_session_keys(
-    client_public_key_b64: &str,
-    server_public_key_b64: &str,
-    client_nonce: [u8; 32],
-    server_nonce: [u8; 32],
+    own: &EphemeralKey,
+    client: &ClientHello,
+    server: &ServerHello,
     is_client: bool,
-) -> SessionKeys {
-    let c2s = derive_key_material(
-        b"p2p/c2s",
-        client_public_key_b64,
-        server_public_key_b64,
-        client_nonce,
-        server_nonce,
-    );
-    let s2c = derive_key_material(
-        b"p2p/s2c",
-        client_public_key_b64,
-        server_public_key_b64,
-        client_nonce,
-        server_nonce,
-    );
+) -> Result<SessionKeys, HandshakeError> {
+    Ok(derive_key_schedule(own, client, server, is_client)?.session_keys(is_client))
+}
 
-    if is_client {
-        SessionKeys {
-            tx_key: c2s,
-            rx_key: s2c,
-        }
+/// Key schedule from the X25519 exchange between the two signed hellos.
+///
+/// `own` is the ephemeral secret from this side's hello; the peer's share comes from
+/// its hello. The schedule is salted with the transcript of both hellos, so its keys
+/// belong to this exchange and cannot be computed from the public values.
+pub fn derive_key_schedule(
+    own: &EphemeralKey,
+    client: &ClientHello,
+    server: &ServerHello,
+    is_client: bool,
+) -> Result<KeySchedule, HandshakeError> {
+    let peer_share = if is_client {
+        server.ephemeral_public
     } else {
-        SessionKeys {
-            tx_key: s2c,
-            rx_key: c2s,
-        }
+        client.ephemeral_public
+    };
+    let shared = own.secret.diffie_hellman(&PublicKey::from(peer_share));
+    // A low-order share forces a known secret regardless of our key.
+    if !shared.was_contributory() {
+        return Err(HandshakeError::InvalidKeyShare);
     }
+    Ok(KeySchedule::new(
+        shared.as_bytes(),
+        &handshake_transcript(client, server),
+    ))
+}
+
+/// Hash of both signed hellos, binding key confirmation and the key schedule to this
+/// exact exchange.
+pub fn handshake_transcript(client: &ClientHello, server: &ServerHello) -> [u8; 32] {
+    let mut transcript = TranscriptHash::after_client_hello(client);
+    transcript.add_server_hello(server);
+    transcript.current()
+}
+
+/// Key-confirmation tag to send right after derivation: a MAC over the transcript
+/// under our transmit key.
+pub fn key_confirmation_tag(keys: &SessionKeys, transcript: &[u8; 32]) -> [u8; 32] {
+    confirmation_mac(&keys.tx_key, transcript)
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+/// Check the peer's tag under our receive key, which must equal the peer's transmit key.
+///
+/// Run in both directions so a derivation mismatch fails the handshake here rather
+/// than surfacing later as chunk decryption errors.
+pub fn verify_key_confirmation(
+    keys: &SessionKeys,
+    transcript: &[u8; 32],
+    peer_tag: &[u8; 32],
+) -> Result<(), HandshakeError> {
+    confirmation_mac(&keys.rx_key, transcript)
+        .verify_slice(peer_tag)
+        .map_err(|_| HandshakeError::KeyConfirmationFailed)
+}
+
+fn confirmation_mac(key: &[u8; 32], transcript: &[u8; 32]) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(b"p2p/key-confirm/v1");
+    mac.update(transcript);
+    mac
 }
 
 #[derive(Debug, Error)]
 pub enum HandshakeError {
     #[error("timestamp skew exceeded")]
     TimestampSkew,
+    #[error("no wall clock to check the peer's timestamp against")]
+    ClockUnavailable,
     #[error("invalid signature")]
     InvalidSignature,
     #[error("client/server nonce mismatch")]
@@ -349,83 +667,101 @@ pub enum HandshakeError {
     EncryptionRequiredButUnsupported,
     #[error("invalid handshake capabilities")]
     InvalidCapabilities,
+    #[error("peers share no cipher suite")]
+    NoCommonCipherSuite,
+    #[error("peer is not in the trust store")]
+    PeerNotTrusted,
+    #[error(transparent)]
+    KeyChanged(KeyChangedError),
+    #[error("peer is blocked")]
+    PeerBlocked,
+    #[error("invalid peer identity: {0}")]
+    InvalidPeerId(&'static str),
+    #[error("peer answered as a different device than the one addressed")]
+    PeerMismatch,
+    #[error("plaintext frame rejected by encryption policy")]
+    PlaintextFrameRejected,
+    #[error("client hello nonce was already seen")]
+    ReplayedNonce,
+    #[error("too many recent handshakes: replay guard is full")]
+    ReplayGuardFull,
+    #[error("handshake message out of order: {0}")]
+    UnexpectedMessage(&'static str),
+    #[error("server signed a different client hello than the one sent")]
+    TranscriptMismatch,
+    #[error("peer key share is not a valid X25519 public key")]
+    InvalidKeyShare,
+    #[error("session key confirmation failed: peers derived different keys")]
+    KeyConfirmationFailed,
+    #[error("client did not commit to its short authentication string, or broke the commitment")]
+    SasCommitmentMismatch,
+    #[error("network requires a pre-shared key the peer did not present")]
+    PskRequired,
+    #[error("peer presented an unknown pre-shared key id")]
+    UnknownPskId,
+    #[error("pre-shared key binder did not verify")]
+    PskBinderMismatch,
+    #[error("invalid pre-shared key: {0}")]
+    InvalidPsk(&'static str),
+    #[error("invalid pairing code: {0}")]
+    InvalidPairingCode(&'static str),
+    #[error("pairing failed: peer does not hold the same pairing code")]
+    PairingFailed,
+    #[error("rekey rejected: {0}")]
+    RekeyRejected(&'static str),
+    #[error("frame sealed under key epoch {0}, which is neither current nor in its grace window")]
+    StaleKeyEpoch(u32),
+    #[error("malformed handshake message: {0}")]
+    InvalidMessage(&'static str),
+    #[error("malformed replay cache: {0}")]
+    InvalidReplayCache(&'static str),
+    #[error("malformed trust store: {0}")]
+    InvalidTrustStore(&'static str),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
 }
 
-fn client_hello_signing_bytes(
-    device_id: &str,
-    public_key_b64: &str,
-    nonce: [u8; 32],
-    timestamp_secs: u64,
-    capabilities: HandshakeCapabilities,
-) -> Vec<u8> {
-    let mut out = Vec::new();
-    out.extend_from_slice(b"p2p/client-hello/v1");
-    out.extend_from_slice(device_id.as_bytes());
-    out.extend_from_slice(public_key_b64.as_bytes());
-    out.extend_from_slice(&nonce);
-    out.extend_from_slice(&timestamp_secs.to_be_bytes());
-    out.push(capabilities.supports_encryption as u8);
-    out.push(capabilities.preferred_encryption_mode.as_u8());
-    out
+impl ClientHello {
+    fn signing_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(b"p2p/client-hello/v3");
+        out.extend_from_slice(self.device_id.as_bytes());
+        out.extend_from_slice(self.public_key_b64.as_bytes());
+        out.extend_from_slice(&self.ephemeral_public);
+        out.extend_from_slice(&self.nonce);
+        out.extend_from_slice(&self.timestamp_secs.to_be_bytes());
+        push_capabilities(&mut out, self.capabilities);
+        self.extensions.write(&mut out);
+        out
+    }
 }
 
-fn server_hello_signing_bytes(
-    device_id: &str,
-    public_key_b64: &str,
-    client_nonce: [u8; 32],
-    server_nonce: [u8; 32],
-    timestamp_secs: u64,
-    capabilities: HandshakeCapabilities,
-) -> Vec<u8> {
-    let mut out = Vec::new();
-    out.extend_from_slice(b"p2p/server-hello/v1");
-    out.extend_from_slice(device_id.as_bytes());
-    out.extend_from_slice(public_key_b64.as_bytes());
-    out.extend_from_slice(&client_nonce);
-    out.extend_from_slice(&server_nonce);
-    out.extend_from_slice(&timestamp_secs.to_be_bytes());
+impl ServerHello {
+    fn signing_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(b"p2p/server-hello/v4");
+        out.extend_from_slice(self.device_id.as_bytes());
+        out.extend_from_slice(self.public_key_b64.as_bytes());
+        out.extend_from_slice(&self.ephemeral_public);
+        out.extend_from_slice(&self.client_nonce);
+        out.extend_from_slice(&self.server_nonce);
+        out.extend_from_slice(&self.client_hello_hash);
+        out.extend_from_slice(&self.timestamp_secs.to_be_bytes());
+        push_capabilities(&mut out, self.capabilities);
+        self.extensions.write(&mut out);
+        out
+    }
+}
+
+/// Capabilities as three bytes, the same under the signature and on the wire.
+fn push_capabilities(out: &mut Vec<u8>, capabilities: HandshakeCapabilities) {
     out.push(capabilities.supports_encryption as u8);
     out.push(capabilities.preferred_encryption_mode.as_u8());
-    out
-}
-
-fn derive_key_material(
-    label: &[u8],
-    client_public_key_b64: &str,
-    server_public_key_b64: &str,
-    client_nonce: [u8; 32],
-    server_nonce: [u8; 32],
-) -> [u8; 32] {
-    let mut hasher = Sha256::new();
-    hasher.update(label);
-    hasher.update(client_public_key_b64.as_bytes());
-    hasher.update(server_public_key_b64.as_bytes());
-    hasher.update(client_nonce);
-    hasher.update(server_nonce);
-    let digest = hasher.finalize();
-
-    let mut out = [0u8; 32];
-    out.copy_from_slice(&digest);
-    out
-}
-
-fn random_nonce() -> [u8; 32] {
+    out.push(capabilities.compression.as_u8());
+}
+
+pub(crate) fn random_nonce() -> [u8; 32] {
     let mut nonce = [0u8; 32];
     OsRng.fill_bytes(&mut nonce);
     nonce
 }
-
-fn now_unix() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs()
-}
-
-fn is_skewed(msg_ts: u64, now: u64, max_skew: u64) -> bool {
-    if msg_ts > now {
-        msg_ts - now > max_skew
-    } else {
-        now - msg_ts > max_skew
-    }
-}
@@ -0,0 +1,202 @@
+use crate::{handshake_transcript, ClientHello, HandshakeError, ServerHello, SessionKeys};
+use crypto_envelope::SecretKeyBytes;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::IsIdentity;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256, Sha512};
+use std::fmt;
+
+/// Shortest code accepted; the exchange allows one guess per handshake attempt, so this
+/// bounds an online attacker's odds at one in a million per try.
+pub const MIN_PAIRING_CODE_LEN: usize = 6;
+
+/// A short code both users type in to pair two devices that have never met.
+///
+/// Spaces and dashes are ignored, so `123-456` and `123456` pair.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PairingCode {
+    code: String,
+}
+
+impl PairingCode {
+    pub fn new(code: &str) -> Result<Self, HandshakeError> {
+        let code: String = code
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .collect();
+        if code.chars().count() < MIN_PAIRING_CODE_LEN {
+            return Err(HandshakeError::InvalidPairingCode(
+                "pairing code shorter than 6 characters",
+            ));
+        }
+        Ok(Self { code })
+    }
+
+    /// Group generator for this code, hashed onto the curve so that nobody knows its
+    /// discrete log relative to another code's generator.
+    fn generator(&self) -> RistrettoPoint {
+        let mut hasher = Sha512::new();
+        hasher.update(b"p2p/pairing/v1/generator");
+        hasher.update(self.code.as_bytes());
+        RistrettoPoint::from_uniform_bytes(&hasher.finalize().into())
+    }
+}
+
+impl fmt::Debug for PairingCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PairingCode(<redacted>)")
+    }
+}
+
+/// Pairing extension carried next to a hello: this side's share of the code exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PairingShare {
+    pub share: [u8; 32],
+}
+
+/// One side of a pairing-code exchange (CPace over Ristretto255).
+///
+/// Each side sends a share computed from a generator derived from the code. A peer
+/// with a different code ends up with an unrelated key, and the shares reveal nothing
+/// to test guesses against offline, so an attacker gets one guess per handshake.
+pub struct PairingState {
+    secret: Scalar,
+    share: PairingShare,
+    is_client: bool,
+}
+
+impl fmt::Debug for PairingState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PairingState")
+            .field("share", &self.share)
+            .field("is_client", &self.is_client)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PairingState {
+    /// Start pairing; send the share along with this side's hello.
+    pub fn start(code: &PairingCode, is_client: bool) -> (Self, PairingShare) {
+        let mut wide = [0u8; 64];
+        OsRng.fill_bytes(&mut wide);
+        let secret = Scalar::from_bytes_mod_order_wide(&wide);
+        let share = PairingShare {
+            share: (secret * code.generator()).compress().to_bytes(),
+        };
+        (
+            Self {
+                secret,
+                share,
+                is_client,
+            },
+            share,
+        )
+    }
+
+    /// Combine with the peer's share into a key bound to both hellos.
+    ///
+    /// The key only matches the peer's if both typed the same code; check that with
+    /// [`PairingKey::confirmation`] before trusting the peer's identity key.
+    pub fn finish(
+        self,
+        peer: &PairingShare,
+        client: &ClientHello,
+        server: &ServerHello,
+    ) -> Result<PairingKey, HandshakeError> {
+        let peer_point = CompressedRistretto(peer.share)
+            .decompress()
+            .filter(|point| !point.is_identity())
+            .ok_or(HandshakeError::PairingFailed)?;
+        let shared = self.secret * peer_point;
+        if shared.is_identity() {
+            return Err(HandshakeError::PairingFailed);
+        }
+        let (client_share, server_share) = if self.is_client {
+            (self.share.share, peer.share)
+        } else {
+            (peer.share, self.share.share)
+        };
+        let hkdf = Hkdf::<Sha256>::new(
+            Some(&handshake_transcript(client, server)),
+            shared.compress().as_bytes(),
+        );
+        let mut key = [0u8; 32];
+        hkdf.expand_multi_info(
+            &[b"p2p/pairing/v1/key", &client_share, &server_share],
+            &mut key,
+        )
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Ok(PairingKey {
+            key: SecretKeyBytes::new(key),
+            is_client: self.is_client,
+        })
+    }
+}
+
+/// Outcome of a pairing exchange, confirmed by trading tags.
+///
+/// Once the peer's tag verifies, its hello's identity key belongs to someone who knew
+/// the code and can be added to the trust store.
+#[derive(Clone)]
+pub struct PairingKey {
+    key: SecretKeyBytes,
+    is_client: bool,
+}
+
+impl fmt::Debug for PairingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PairingKey")
+            .field("is_client", &self.is_client)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PairingKey {
+    /// Tag to send to the peer.
+    pub fn confirmation(&self) -> [u8; 32] {
+        self.mac(role_label(self.is_client))
+            .finalize()
+            .into_bytes()
+            .into()
+    }
+
+    /// Check the peer's tag; a mismatch means the codes differed.
+    pub fn verify_peer(&self, peer_tag: &[u8; 32]) -> Result<(), HandshakeError> {
+        self.mac(role_label(!self.is_client))
+            .verify_slice(peer_tag)
+            .map_err(|_| HandshakeError::PairingFailed)
+    }
+
+    /// Mix the pairing key into keys from [`crate::derive_session_keys`], so the session
+    /// depends on the code as well as the key exchange.
+    pub fn mix_into_keys(&self, keys: &SessionKeys) -> SessionKeys {
+        let mix = |key: &[u8; 32]| -> [u8; 32] {
+            let mut mac = self.mac(b"p2p/pairing-mix/v1");
+            mac.update(key);
+            mac.finalize().into_bytes().into()
+        };
+        SessionKeys {
+            tx_key: mix(&keys.tx_key),
+            rx_key: mix(&keys.rx_key),
+        }
+    }
+
+    fn mac(&self, label: &[u8]) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(self.key.expose())
+            .expect("HMAC accepts any key length");
+        mac.update(label);
+        mac
+    }
+}
+
+fn role_label(is_client: bool) -> &'static [u8] {
+    if is_client {
+        b"p2p/pairing-confirm/client/v1"
+    } else {
+        b"p2p/pairing-confirm/server/v1"
+    }
+}
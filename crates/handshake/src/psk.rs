@@ -0,0 +1,267 @@
+use crate::{handshake_transcript, ClientHello, HandshakeError, ServerHello, SessionKeys};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// Shortest secret accepted; anything below this is guessable offline from a binder.
+pub const MIN_PSK_LEN: usize = 16;
+
+/// Settings key prefix for per-network PSK entries (`psk.<network>.id`, `.secret`, `.strict`).
+const SETTINGS_PREFIX: &str = "psk.";
+
+/// A static secret shared out of band, named by an id that travels in the clear.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PreSharedKey {
+    pub id: String,
+    secret: Zeroizing<Vec<u8>>,
+}
+
+impl PreSharedKey {
+    pub fn new(id: &str, secret: &[u8]) -> Result<Self, HandshakeError> {
+        if id.is_empty() {
+            return Err(HandshakeError::InvalidPsk("empty psk id"));
+        }
+        if secret.len() < MIN_PSK_LEN {
+            return Err(HandshakeError::InvalidPsk(
+                "psk secret shorter than 16 bytes",
+            ));
+        }
+        Ok(Self {
+            id: id.to_string(),
+            secret: Zeroizing::new(secret.to_vec()),
+        })
+    }
+
+    /// Parse a hex-encoded secret as written in settings files.
+    pub fn from_hex(id: &str, secret_hex: &str) -> Result<Self, HandshakeError> {
+        Self::new(id, &Zeroizing::new(decode_hex(secret_hex)?))
+    }
+
+    fn mac(&self, label: &[u8]) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.secret)
+            .expect("HMAC accepts any key length");
+        mac.update(label);
+        mac.update(self.id.as_bytes());
+        mac
+    }
+}
+
+impl fmt::Debug for PreSharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PreSharedKey")
+            .field("id", &self.id)
+            .field("secret", &"<redacted>")
+            .finish()
+    }
+}
+
+/// PSK extension carried next to a hello: the key id and a MAC proving possession.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PskBinder {
+    pub psk_id: String,
+    pub binder: [u8; 32],
+}
+
+/// PSK settings for one network profile.
+///
+/// With `strict` set the network is PSK-only: peers that do not present the key are
+/// refused instead of falling back to the identity handshake.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PskProfile {
+    pub key: Option<PreSharedKey>,
+    pub strict: bool,
+}
+
+/// PSK profiles keyed by network id (SSID or interface profile).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PskProfiles {
+    profiles: HashMap<String, PskProfile>,
+}
+
+impl PskProfiles {
+    pub fn set(&mut self, network: &str, profile: PskProfile) {
+        self.profiles.insert(network.to_string(), profile);
+    }
+
+    /// Profile for `network`; unknown or unidentified networks get the default (no PSK).
+    pub fn for_network(&self, network: Option<&str>) -> PskProfile {
+        network
+            .and_then(|n| self.profiles.get(n))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Read `psk.<network>.id`, `psk.<network>.secret` (hex) and `psk.<network>.strict`
+    /// from a settings file.
+    ///
+    /// Unknown keys are ignored; a secret that is not valid hex or too short is an error,
+    /// since silently dropping it would downgrade the network to the identity handshake.
+    pub fn apply_settings(&mut self, contents: &str) -> Result<(), HandshakeError> {
+        let mut ids: HashMap<String, String> = HashMap::new();
+        let mut secrets: HashMap<String, String> = HashMap::new();
+        for line in contents.lines() {
+            let Some((key, value)) = line.trim().split_once('=') else {
+                continue;
+            };
+            let Some(rest) = key.trim().strip_prefix(SETTINGS_PREFIX) else {
+                continue;
+            };
+            let Some((network, field)) = rest.rsplit_once('.') else {
+                continue;
+            };
+            let value = value.trim().to_string();
+            match field {
+                "id" => {
+                    ids.insert(network.to_string(), value);
+                }
+                "secret" => {
+                    secrets.insert(network.to_string(), value);
+                }
+                "strict" => {
+                    if let Some(strict) = parse_bool(&value) {
+                        self.profiles.entry(network.to_string()).or_default().strict = strict;
+                    }
+                }
+                _ => {}
+            }
+        }
+        for (network, secret) in secrets {
+            let id = ids.get(&network).map_or(network.as_str(), String::as_str);
+            let key = PreSharedKey::from_hex(id, &secret)?;
+            self.profiles.entry(network).or_default().key = Some(key);
+        }
+        Ok(())
+    }
+}
+
+/// Client side: bind the PSK to this exact hello so it cannot be replayed onto another.
+pub fn client_psk_binder(psk: &PreSharedKey, hello: &ClientHello) -> PskBinder {
+    PskBinder {
+        psk_id: psk.id.clone(),
+        binder: client_binder_mac(psk, hello).finalize().into_bytes().into(),
+    }
+}
+
+/// Server side: check the client's PSK extension against the network profile.
+///
+/// Returns the key to mix into the session, or `None` for a non-strict network where
+/// neither side uses a PSK and the identity handshake applies unchanged.
+pub fn accept_client_psk<'a>(
+    profile: &'a PskProfile,
+    hello: &ClientHello,
+    binder: Option<&PskBinder>,
+) -> Result<Option<&'a PreSharedKey>, HandshakeError> {
+    let (key, binder) = match (&profile.key, binder) {
+        (Some(key), Some(binder)) => (key, binder),
+        (_, None) if profile.strict => return Err(HandshakeError::PskRequired),
+        (None, Some(_)) if profile.strict => return Err(HandshakeError::UnknownPskId),
+        _ => return Ok(None),
+    };
+    if binder.psk_id != key.id {
+        return Err(HandshakeError::UnknownPskId);
+    }
+    client_binder_mac(key, hello)
+        .verify_slice(&binder.binder)
+        .map_err(|_| HandshakeError::PskBinderMismatch)?;
+    Ok(Some(key))
+}
+
+/// Server's binder over the full transcript, proving it holds the same key.
+pub fn server_psk_binder(
+    psk: &PreSharedKey,
+    client: &ClientHello,
+    server: &ServerHello,
+) -> PskBinder {
+    PskBinder {
+        psk_id: psk.id.clone(),
+        binder: server_binder_mac(psk, client, server)
+            .finalize()
+            .into_bytes()
+            .into(),
+    }
+}
+
+/// Client side: check the server's answer under our profile.
+pub fn accept_server_psk<'a>(
+    profile: &'a PskProfile,
+    client: &ClientHello,
+    server: &ServerHello,
+    binder: Option<&PskBinder>,
+) -> Result<Option<&'a PreSharedKey>, HandshakeError> {
+    let (key, binder) = match (&profile.key, binder) {
+        (Some(key), Some(binder)) => (key, binder),
+        (_, None) if profile.strict => return Err(HandshakeError::PskRequired),
+        (_, None) => return Ok(None),
+        (None, Some(_)) => return Err(HandshakeError::UnknownPskId),
+    };
+    if binder.psk_id != key.id {
+        return Err(HandshakeError::UnknownPskId);
+    }
+    server_binder_mac(key, client, server)
+        .verify_slice(&binder.binder)
+        .map_err(|_| HandshakeError::PskBinderMismatch)?;
+    Ok(Some(key))
+}
+
+/// Mix the PSK and transcript into keys from [`crate::derive_session_keys`].
+///
+/// Each key is mixed independently, so the client's tx still equals the server's rx.
+/// Without the secret an attacker who saw both hellos cannot recompute the keys.
+pub fn mix_psk_into_keys(
+    keys: &SessionKeys,
+    psk: &PreSharedKey,
+    transcript: &[u8; 32],
+) -> SessionKeys {
+    let mix = |key: &[u8; 32]| -> [u8; 32] {
+        let mut mac = psk.mac(b"p2p/psk-mix/v1");
+        mac.update(key);
+        mac.update(transcript);
+        mac.finalize().into_bytes().into()
+    };
+    SessionKeys {
+        tx_key: mix(&keys.tx_key),
+        rx_key: mix(&keys.rx_key),
+    }
+}
+
+fn client_binder_mac(psk: &PreSharedKey, hello: &ClientHello) -> Hmac<Sha256> {
+    let mut mac = psk.mac(b"p2p/psk-binder/client/v1");
+    mac.update(&hello.signing_bytes());
+    mac
+}
+
+fn server_binder_mac(
+    psk: &PreSharedKey,
+    client: &ClientHello,
+    server: &ServerHello,
+) -> Hmac<Sha256> {
+    let mut mac = psk.mac(b"p2p/psk-binder/server/v1");
+    mac.update(&handshake_transcript(client, server));
+    mac
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, HandshakeError> {
+    let value = value.trim();
+    if !value.len().is_multiple_of(2) {
+        return Err(HandshakeError::InvalidPsk("psk secret is not valid hex"));
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| {
+            value
+                .get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or(HandshakeError::InvalidPsk("psk secret is not valid hex"))
+        })
+        .collect()
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "on" | "yes" | "1" => Some(true),
+        "false" | "off" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
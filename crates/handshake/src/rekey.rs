@@ -0,0 +1,207 @@
+use crate::{HandshakeError, KeySchedule, SessionKeys};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{Duration, Instant};
+
+const REKEY_LABEL: &[u8] = b"p2p/rekey/v1";
+/// How long frames sealed under the previous epoch still open after a rotation.
+pub const DEFAULT_REKEY_GRACE: Duration = Duration::from_secs(10);
+
+/// When a session should move to the next key epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RekeyPolicy {
+    /// Chunks sent under one epoch before rotating.
+    pub max_chunks: u64,
+    /// Age of an epoch before rotating, however little was sent.
+    pub max_age: Duration,
+}
+
+impl Default for RekeyPolicy {
+    fn default() -> Self {
+        Self {
+            max_chunks: 1 << 20,
+            max_age: Duration::from_secs(30 * 60),
+        }
+    }
+}
+
+/// Announces that the sender has moved to `epoch`.
+///
+/// The tag is a MAC under the sender's transmit key of the epoch it leaves, so only the
+/// peer holding the current keys can trigger a rotation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rekey {
+    pub epoch: u32,
+    pub tag: [u8; 32],
+}
+
+/// Current key epoch of one side of a session.
+///
+/// Both sides start at epoch 0 with the handshake's session keys. The side that rotates
+/// sends the [`Rekey`] from [`Self::start_rekey`]; the peer applies it with
+/// [`Self::apply_rekey`]. Frames carry [`Self::epoch_aad`] in their AAD and are opened
+/// with [`Self::rx_key_for`] that epoch, so a frame sealed in one epoch never opens in
+/// another.
+///
+/// The receive key of the epoch just left stays usable for a grace window, so frames the
+/// peer sealed before it saw the [`Rekey`] still open. If both sides rotate at once, each
+/// receives a [`Rekey`] for the epoch it already moved to; since every epoch's keys
+/// derive from the schedule alone, both already agree and the crossed message is
+/// accepted as a no-op.
+#[derive(Debug, Clone)]
+pub struct KeyRotation {
+    schedule: KeySchedule,
+    is_client: bool,
+    epoch: u32,
+    keys: SessionKeys,
+    /// This side sent the [`Rekey`] that started the current epoch.
+    initiated: bool,
+    previous: Option<RetiredEpoch>,
+    grace: Duration,
+    chunks_in_epoch: u64,
+    epoch_started: Instant,
+}
+
+/// The receive key of the epoch before the current one.
+#[derive(Debug, Clone)]
+struct RetiredEpoch {
+    epoch: u32,
+    rx_key: [u8; 32],
+    retired_at: Instant,
+}
+
+impl KeyRotation {
+    pub fn new(schedule: KeySchedule, is_client: bool, now: Instant) -> Self {
+        let keys = schedule.derive_next_keys(0, is_client);
+        Self {
+            schedule,
+            is_client,
+            epoch: 0,
+            keys,
+            initiated: false,
+            previous: None,
+            grace: DEFAULT_REKEY_GRACE,
+            chunks_in_epoch: 0,
+            epoch_started: now,
+        }
+    }
+
+    /// Keep the previous epoch's receive key for `grace` after each rotation instead of
+    /// [`DEFAULT_REKEY_GRACE`].
+    pub fn with_grace(mut self, grace: Duration) -> Self {
+        self.grace = grace;
+        self
+    }
+
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    pub fn keys(&self) -> &SessionKeys {
+        &self.keys
+    }
+
+    /// Bytes to append to a chunk's AAD under the current epoch.
+    pub fn epoch_aad(&self) -> [u8; 4] {
+        self.epoch.to_be_bytes()
+    }
+
+    /// Receive key for a frame whose AAD carries `epoch_aad`: the current epoch's, or
+    /// the previous one's while its grace window lasts. Anything else is refused.
+    pub fn rx_key_for(
+        &self,
+        epoch_aad: [u8; 4],
+        now: Instant,
+    ) -> Result<&[u8; 32], HandshakeError> {
+        let epoch = u32::from_be_bytes(epoch_aad);
+        if epoch == self.epoch {
+            return Ok(&self.keys.rx_key);
+        }
+        match &self.previous {
+            Some(previous)
+                if previous.epoch == epoch
+                    && now.saturating_duration_since(previous.retired_at) < self.grace =>
+            {
+                Ok(&previous.rx_key)
+            }
+            _ => Err(HandshakeError::StaleKeyEpoch(epoch)),
+        }
+    }
+
+    /// Count a chunk sealed under the current keys.
+    pub fn record_chunk(&mut self) {
+        self.chunks_in_epoch += 1;
+    }
+
+    /// Whether `policy` calls for a rotation now.
+    pub fn is_due(&self, policy: &RekeyPolicy, now: Instant) -> bool {
+        self.chunks_in_epoch >= policy.max_chunks
+            || now.saturating_duration_since(self.epoch_started) >= policy.max_age
+    }
+
+    /// Move to the next epoch and return the message announcing it. Send it before any
+    /// frame sealed under the new keys.
+    pub fn start_rekey(&mut self, now: Instant) -> Result<Rekey, HandshakeError> {
+        let epoch = self.next_epoch()?;
+        let tag = rekey_mac(&self.keys.tx_key, epoch)
+            .finalize()
+            .into_bytes()
+            .into();
+        self.advance(epoch, now, true);
+        Ok(Rekey { epoch, tag })
+    }
+
+    /// Follow the peer to the epoch in `rekey`. It must be the next epoch and carry a
+    /// tag under the current keys, or be the peer's half of a rotation both sides started
+    /// at once: the epoch this side just moved to, tagged under the key it left.
+    pub fn apply_rekey(&mut self, rekey: &Rekey, now: Instant) -> Result<(), HandshakeError> {
+        if self.initiated && rekey.epoch == self.epoch {
+            let previous = self
+                .previous
+                .as_ref()
+                .filter(|previous| previous.epoch + 1 == self.epoch)
+                .ok_or(HandshakeError::RekeyRejected("epoch is not the next one"))?;
+            rekey_mac(&previous.rx_key, rekey.epoch)
+                .verify_slice(&rekey.tag)
+                .map_err(|_| HandshakeError::RekeyRejected("tag did not verify"))?;
+            // Crossed rotations: both sides are already on this epoch's keys.
+            self.initiated = false;
+            return Ok(());
+        }
+        let epoch = self.next_epoch()?;
+        if rekey.epoch != epoch {
+            return Err(HandshakeError::RekeyRejected("epoch is not the next one"));
+        }
+        rekey_mac(&self.keys.rx_key, epoch)
+            .verify_slice(&rekey.tag)
+            .map_err(|_| HandshakeError::RekeyRejected("tag did not verify"))?;
+        self.advance(epoch, now, false);
+        Ok(())
+    }
+
+    fn next_epoch(&self) -> Result<u32, HandshakeError> {
+        self.epoch
+            .checked_add(1)
+            .ok_or(HandshakeError::RekeyRejected("key epochs exhausted"))
+    }
+
+    fn advance(&mut self, epoch: u32, now: Instant, initiated: bool) {
+        self.previous = Some(RetiredEpoch {
+            epoch: self.epoch,
+            rx_key: self.keys.rx_key,
+            retired_at: now,
+        });
+        self.initiated = initiated;
+        self.epoch = epoch;
+        self.keys = self.schedule.derive_next_keys(epoch, self.is_client);
+        self.chunks_in_epoch = 0;
+        self.epoch_started = now;
+    }
+}
+
+fn rekey_mac(key: &[u8; 32], epoch: u32) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(REKEY_LABEL);
+    mac.update(&epoch.to_be_bytes());
+    mac
+}
@@ -0,0 +1,254 @@
+use crate::HandshakeError;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Nonces a guard holds unless configured otherwise: a few MiB, far above any honest
+/// handshake rate within a skew window.
+pub const DEFAULT_REPLAY_GUARD_ENTRIES: usize = 65_536;
+
+/// Counters since the guard was created or [`ReplayGuard::take_stats`] last ran.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplayGuardStats {
+    pub accepted: u64,
+    pub replays_rejected: u64,
+    /// Fresh nonces refused because the guard was full of live ones.
+    pub rejected_full: u64,
+    pub expired: u64,
+}
+
+impl ReplayGuardStats {
+    /// `(metric, value)` pairs named for audit telemetry counters.
+    pub fn counters(&self) -> [(&'static str, u64); 4] {
+        [
+            ("handshake.replay_accepted", self.accepted),
+            ("handshake.replay_rejected", self.replays_rejected),
+            ("handshake.replay_rejected_full", self.rejected_full),
+            ("handshake.replay_expired", self.expired),
+        ]
+    }
+}
+
+/// Hello nonces seen within the last `ttl`, so a captured hello cannot be replayed.
+///
+/// Holds at most a fixed number of nonces. Once that many are live it refuses new
+/// hellos rather than forget one early, so a flood of fresh nonces can stall handshakes
+/// until the ttl passes but never reopen a replay. Expired nonces are dropped on every
+/// insert.
+#[derive(Debug)]
+pub struct ReplayGuard {
+    seen: HashMap<[u8; 32], Instant>,
+    /// Nonces in insertion order, oldest first.
+    order: VecDeque<([u8; 32], Instant)>,
+    ttl: Duration,
+    max_entries: usize,
+    stats: ReplayGuardStats,
+}
+
+impl ReplayGuard {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+            ttl,
+            max_entries: DEFAULT_REPLAY_GUARD_ENTRIES,
+            stats: ReplayGuardStats::default(),
+        }
+    }
+
+    /// Bound the guard to `max_entries` nonces (at least one). A guard already holding
+    /// more keeps them and refuses new nonces until enough expire.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+
+    /// Remember `nonce` unless it was already seen within the ttl
+    /// ([`HandshakeError::ReplayedNonce`]) or the guard is full of live nonces
+    /// ([`HandshakeError::ReplayGuardFull`]).
+    pub fn check_and_remember(
+        &mut self,
+        nonce: [u8; 32],
+        now: Instant,
+    ) -> Result<(), HandshakeError> {
+        self.expire(now);
+        if self
+            .seen
+            .get(&nonce)
+            .is_some_and(|seen_at| !self.is_expired(*seen_at, now))
+        {
+            self.stats.replays_rejected += 1;
+            return Err(HandshakeError::ReplayedNonce);
+        }
+        if self.seen.len() >= self.max_entries {
+            self.stats.rejected_full += 1;
+            return Err(HandshakeError::ReplayGuardFull);
+        }
+        self.remember(nonce, now);
+        self.stats.accepted += 1;
+        Ok(())
+    }
+
+    /// Drop nonces older than the ttl.
+    pub fn expire(&mut self, now: Instant) {
+        while let Some(&(nonce, seen_at)) = self.order.front() {
+            if !self.is_expired(seen_at, now) {
+                break;
+            }
+            self.order.pop_front();
+            if self.seen.get(&nonce) == Some(&seen_at) {
+                self.seen.remove(&nonce);
+                self.stats.expired += 1;
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    pub fn stats(&self) -> ReplayGuardStats {
+        self.stats
+    }
+
+    /// Counters since the last call, for pushing into telemetry as deltas.
+    pub fn take_stats(&mut self) -> ReplayGuardStats {
+        std::mem::take(&mut self.stats)
+    }
+
+    fn remember(&mut self, nonce: [u8; 32], seen_at: Instant) {
+        self.seen.insert(nonce, seen_at);
+        self.order.push_back((nonce, seen_at));
+    }
+
+    fn is_expired(&self, seen_at: Instant, now: Instant) -> bool {
+        now.saturating_duration_since(seen_at) > self.ttl
+    }
+}
+
+/// Persistence, so a restart inside the skew window does not forget which hello nonces
+/// were already used.
+///
+/// `Instant`s do not survive a restart, so entries are stored with the wall-clock
+/// second they were seen; `now` and `now_unix_secs` must describe the same moment.
+impl ReplayGuard {
+    /// One `nonce-hex<TAB>unix-secs` line per nonce still inside the ttl.
+    pub fn encode(&self, now: Instant, now_unix_secs: u64) -> String {
+        let mut entries: Vec<(&[u8; 32], u64)> = self
+            .seen
+            .iter()
+            .filter(|(_, seen_at)| !self.is_expired(**seen_at, now))
+            .map(|(nonce, seen_at)| {
+                let age = now.saturating_duration_since(*seen_at).as_secs();
+                (nonce, now_unix_secs.saturating_sub(age))
+            })
+            .collect();
+        entries.sort();
+        entries
+            .into_iter()
+            .map(|(nonce, seen_secs)| format!("{}\t{seen_secs}\n", encode_hex(nonce)))
+            .collect()
+    }
+
+    /// Rebuild a guard from [`Self::encode`] output, dropping entries older than `ttl`.
+    ///
+    /// A cache with more live nonces than [`DEFAULT_REPLAY_GUARD_ENTRIES`] is refused
+    /// rather than trimmed, since any nonce left out could be replayed.
+    pub fn decode(
+        text: &str,
+        ttl: Duration,
+        now: Instant,
+        now_unix_secs: u64,
+    ) -> Result<Self, HandshakeError> {
+        let mut entries = Vec::new();
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let (nonce, seen_secs) = line
+                .split_once('\t')
+                .ok_or(HandshakeError::InvalidReplayCache("unrecognised line"))?;
+            let nonce = decode_nonce(nonce)?;
+            let seen_secs: u64 = seen_secs
+                .parse()
+                .map_err(|_| HandshakeError::InvalidReplayCache("bad timestamp"))?;
+            // A timestamp ahead of the clock (it was set back) counts as seen just now.
+            let age = Duration::from_secs(now_unix_secs.saturating_sub(seen_secs));
+            if age > ttl {
+                continue;
+            }
+            if entries.len() == DEFAULT_REPLAY_GUARD_ENTRIES {
+                return Err(HandshakeError::InvalidReplayCache(
+                    "more nonces than the guard holds",
+                ));
+            }
+            entries.push((now.checked_sub(age).unwrap_or(now), nonce));
+        }
+        entries.sort();
+        let mut guard = Self::new(ttl);
+        for (seen_at, nonce) in entries {
+            guard.remember(nonce, seen_at);
+        }
+        Ok(guard)
+    }
+
+    /// Load a saved guard; a missing file yields an empty one.
+    pub fn load(
+        path: impl AsRef<Path>,
+        ttl: Duration,
+        now: Instant,
+        now_unix_secs: u64,
+    ) -> Result<Self, HandshakeError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::new(ttl));
+        }
+        Self::decode(&fs::read_to_string(path)?, ttl, now, now_unix_secs)
+    }
+
+    /// Drop expired nonces, then write the rest through a temporary file so a crash
+    /// mid-save leaves the previous file intact.
+    pub fn save(
+        &mut self,
+        path: impl AsRef<Path>,
+        now: Instant,
+        now_unix_secs: u64,
+    ) -> Result<(), HandshakeError> {
+        self.expire(now);
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, self.encode(now, now_unix_secs))?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_nonce(value: &str) -> Result<[u8; 32], HandshakeError> {
+    let invalid = HandshakeError::InvalidReplayCache("nonce is not 32 hex bytes");
+    if value.len() != 64 {
+        return Err(invalid);
+    }
+    let mut nonce = [0u8; 32];
+    for (i, byte) in nonce.iter_mut().enumerate() {
+        *byte = value
+            .get(2 * i..2 * i + 2)
+            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+            .ok_or(HandshakeError::InvalidReplayCache(
+                "nonce is not 32 hex bytes",
+            ))?;
+    }
+    Ok(nonce)
+}
@@ -0,0 +1,111 @@
+use std::time::{Duration, Instant};
+
+/// How long to wait for a server hello and how often to try again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Wait for a reply to each hello.
+    pub timeout: Duration,
+    /// Hellos sent after the first before giving up.
+    pub max_retries: u32,
+    /// Pause after the first timeout; doubles after each further one.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+        }
+    }
+}
+
+/// What the transport should do next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttemptAction {
+    /// Send a freshly created client hello now.
+    SendHello,
+    /// Nothing to do until this much time has passed or a reply arrives.
+    Wait(Duration),
+    /// Every attempt timed out.
+    GiveUp,
+    /// A reply arrived; the attempt is over.
+    Complete,
+}
+
+/// Retry timing for one handshake, shared by every transport.
+///
+/// Each [`AttemptAction::SendHello`] calls for a new hello with a new nonce, such as a
+/// fresh [`crate::HandshakeInitiator::start`]; resending the same bytes would be refused
+/// by the responder's [`crate::ReplayGuard`] as a replay. A late reply to an earlier
+/// hello fails its nonce check and can be dropped.
+#[derive(Debug, Clone)]
+pub struct HandshakeAttempt {
+    policy: RetryPolicy,
+    sent: u32,
+    next_send: Option<Instant>,
+    deadline: Option<Instant>,
+    outcome: Option<AttemptAction>,
+}
+
+impl HandshakeAttempt {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            sent: 0,
+            next_send: None,
+            deadline: None,
+            outcome: None,
+        }
+    }
+
+    /// Decide what to do at `now`. A returned [`AttemptAction::SendHello`] counts as
+    /// sent at `now`.
+    pub fn poll(&mut self, now: Instant) -> AttemptAction {
+        if let Some(outcome) = self.outcome {
+            return outcome;
+        }
+        if let Some(deadline) = self.deadline {
+            if now < deadline {
+                return AttemptAction::Wait(deadline - now);
+            }
+            self.deadline = None;
+            if self.sent > self.policy.max_retries {
+                self.outcome = Some(AttemptAction::GiveUp);
+                return AttemptAction::GiveUp;
+            }
+            self.next_send = Some(deadline + self.backoff());
+        }
+        match self.next_send {
+            Some(at) if now < at => AttemptAction::Wait(at - now),
+            _ => {
+                self.sent += 1;
+                self.next_send = None;
+                self.deadline = Some(now + self.policy.timeout);
+                AttemptAction::SendHello
+            }
+        }
+    }
+
+    /// The server answered one of the hellos.
+    pub fn complete(&mut self) {
+        self.outcome = Some(AttemptAction::Complete);
+    }
+
+    /// Hellos sent so far.
+    pub fn hellos_sent(&self) -> u32 {
+        self.sent
+    }
+
+    /// Pause before the next hello after `sent` timed-out ones.
+    fn backoff(&self) -> Duration {
+        let doublings = self.sent.saturating_sub(1).min(31);
+        self.policy
+            .initial_backoff
+            .saturating_mul(1 << doublings)
+            .min(self.policy.max_backoff)
+    }
+}
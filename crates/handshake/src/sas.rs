@@ -0,0 +1,115 @@
+use crate::{ClientHello, HandshakeError};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// The client's commitment to its SAS secret, 32 bytes; see [`sas_commitment`].
+pub const EXT_SAS_COMMITMENT: u16 = 6;
+
+/// Short authentication string both users compare before trusting a new peer.
+///
+/// Derived from the handshake transcript, so a relayed handshake shows different codes
+/// on the two screens. The client also mixes in a secret it committed to in its hello
+/// and reveals only in its Finished, after the server's key is fixed. An attacker in the
+/// middle therefore has to pick its own hellos before it can know either side's code, and
+/// cannot search for hellos that make the two collide. The words carry 32 bits and the
+/// digits about 20, so compare the words where possible.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShortAuthString {
+    /// Six decimal digits, zero-padded.
+    pub digits: String,
+    pub words: [&'static str; 4],
+}
+
+impl ShortAuthString {
+    /// The code for a handshake without a commitment, from the transcript alone.
+    pub fn from_transcript(transcript: &[u8; 32]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"p2p/sas/v1");
+        hasher.update(transcript);
+        Self::from_digest(&hasher.finalize().into())
+    }
+
+    /// The code once the client revealed the secret its hello committed to.
+    pub fn from_revealed(transcript: &[u8; 32], secret: &[u8; 32]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"p2p/sas/v2");
+        hasher.update(transcript);
+        hasher.update(secret);
+        Self::from_digest(&hasher.finalize().into())
+    }
+
+    fn from_digest(digest: &[u8; 32]) -> Self {
+        let number = u32::from_be_bytes(digest[4..8].try_into().expect("slice len"));
+        Self {
+            digits: format!("{:06}", number % 1_000_000),
+            words: [0, 1, 2, 3].map(|i| SAS_WORDS[digest[i] as usize]),
+        }
+    }
+
+    /// Words separated by spaces.
+    pub fn phrase(&self) -> String {
+        self.words.join(" ")
+    }
+}
+
+impl fmt::Display for ShortAuthString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} ({})",
+            &self.digits[..3],
+            &self.digits[3..],
+            self.phrase()
+        )
+    }
+}
+
+/// Commitment to `secret` for the hello carrying `ephemeral_public`, so it cannot be
+/// lifted into another handshake.
+pub fn sas_commitment(ephemeral_public: &[u8; 32], secret: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"p2p/sas-commit/v1");
+    hasher.update(ephemeral_public);
+    hasher.update(secret);
+    hasher.finalize().into()
+}
+
+/// Check the secret revealed in the client's Finished against its hello's commitment.
+pub fn verify_sas_reveal(hello: &ClientHello, secret: &[u8; 32]) -> Result<(), HandshakeError> {
+    let expected = sas_commitment(&hello.ephemeral_public, secret);
+    match hello.extensions.get(EXT_SAS_COMMITMENT) {
+        Some(commitment) if commitment == expected => Ok(()),
+        _ => Err(HandshakeError::SasCommitmentMismatch),
+    }
+}
+
+/// One word per byte value; short, common and distinct when read aloud.
+const SAS_WORDS: [&str; 256] = [
+    "acorn", "actor", "adobe", "alarm", "album", "alley", "amber", "angle", "ankle", "apple",
+    "apron", "arena", "armor", "arrow", "atlas", "attic", "audio", "badge", "bagel", "baker",
+    "bamboo", "banjo", "barn", "basil", "beach", "beard", "bench", "berry", "bison", "blade",
+    "blimp", "bloom", "board", "boots", "brass", "bread", "brick", "broom", "brush", "bucket",
+    "bugle", "cabin", "cable", "cactus", "camel", "canoe", "canyon", "cargo", "carrot", "castle",
+    "cedar", "chalk", "cherry", "chess", "chimney", "cider", "cinema", "circus", "citrus", "clock",
+    "cloud", "clover", "cobra", "cocoa", "comet", "coral", "cotton", "cougar", "crane", "crater",
+    "crayon", "cricket", "crown", "crystal", "daisy", "dancer", "delta", "denim", "desert",
+    "dingo", "dolphin", "donkey", "dragon", "drum", "eagle", "easel", "echo", "eclipse", "elbow",
+    "ember", "emerald", "engine", "falcon", "fabric", "ferry", "fiddle", "flame", "flute",
+    "forest", "fossil", "fox", "frost", "galaxy", "garden", "garlic", "gecko", "ginger", "glacier",
+    "glove", "goose", "granite", "grape", "gravel", "guitar", "hammer", "harbor", "hazel",
+    "helmet", "heron", "hippo", "honey", "hornet", "husky", "igloo", "iguana", "island", "ivory",
+    "jacket", "jaguar", "jasmine", "jelly", "jewel", "jigsaw", "jungle", "kayak", "kettle", "kiwi",
+    "koala", "ladder", "lagoon", "lantern", "lemon", "lily", "lizard", "llama", "lobster",
+    "locket", "lotus", "lunar", "magnet", "mango", "maple", "marble", "meadow", "melon", "meteor",
+    "mint", "mirror", "monkey", "mosaic", "moss", "muffin", "nectar", "needle", "nickel", "noodle",
+    "nutmeg", "oasis", "ocean", "olive", "onion", "opal", "orbit", "orchid", "otter", "oyster",
+    "paddle", "panda", "papaya", "parrot", "peach", "pebble", "pepper", "piano", "pickle", "pilot",
+    "pine", "pirate", "planet", "plum", "pony", "poppy", "potato", "prism", "pumpkin", "puzzle",
+    "quartz", "quill", "rabbit", "radar", "radish", "raven", "reef", "ribbon", "robot", "rocket",
+    "rose", "ruby", "saddle", "salmon", "sandal", "satin", "scarf", "shadow", "shell", "silver",
+    "sketch", "sloth", "snail", "spider", "spruce", "squid", "stamp", "statue", "sugar", "summit",
+    "swan", "temple", "thunder", "tiger", "timber", "tomato", "topaz", "tornado", "tractor",
+    "trumpet", "tulip", "tunnel", "turtle", "umbrella", "valley", "velvet", "violet", "violin",
+    "volcano", "waffle", "walnut", "walrus", "wasp", "willow", "window", "wizard", "yogurt",
+    "zebra", "zigzag", "zipper",
+];
@@ -0,0 +1,561 @@
+use crate::{
+    accept_client_psk, accept_server_psk, client_hello_with_ephemeral, client_psk_binder,
+    derive_key_schedule, handshake_transcript, mix_psk_into_keys, negotiate_cipher_suite,
+    negotiate_compression, negotiate_encryption, negotiate_padding, random_nonce, sas_commitment,
+    server_hello_at, server_psk_binder, verify_client_hello_with, verify_sas_reveal,
+    verify_server_hello_with, CipherSuite, ClientFinished, ClientHello, Clock, EarlyData,
+    EncryptionMode, EphemeralKey, HandshakeCapabilities, HandshakeError, HelloExtensions,
+    KeySchedule, NegotiatedCompression, NegotiatedEncryption, PaddingScheme, PinCheck, PskProfile,
+    ReplayGuard, ServerFinished, ServerHello, SessionKeys, ShortAuthString, SkewPolicy,
+    SystemClock, TicketStore, TrustStore, EXT_SAS_COMMITMENT, UNSTAMPED,
+};
+use identity::DeviceIdentity;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// What this side offers in its hello and how strictly it checks the peer's.
+#[derive(Debug, Clone)]
+pub struct HandshakeConfig {
+    pub device_id: String,
+    pub capabilities: HandshakeCapabilities,
+    pub extensions: HelloExtensions,
+    /// Stamps our hellos and is the time the peer's are checked against.
+    pub clock: Arc<dyn Clock>,
+    pub skew: SkewPolicy,
+    /// Pins and per-peer encryption policy, as for [`crate::verify_client_hello_pinned`];
+    /// shared with whatever else handshakes or pairs.
+    pub trust: Option<Arc<Mutex<TrustStore>>>,
+    /// Pre-shared key for the network; a strict profile refuses peers without it.
+    pub psk: PskProfile,
+}
+
+impl HandshakeConfig {
+    /// The system clock and a strict 30-second skew policy.
+    pub fn new(device_id: impl Into<String>) -> Self {
+        Self {
+            device_id: device_id.into(),
+            capabilities: HandshakeCapabilities::default(),
+            extensions: HelloExtensions::default(),
+            clock: Arc::new(SystemClock),
+            skew: SkewPolicy::default(),
+            trust: None,
+            psk: PskProfile::default(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_skew(mut self, skew: SkewPolicy) -> Self {
+        self.skew = skew;
+        self
+    }
+
+    pub fn with_trust_store(mut self, trust: Arc<Mutex<TrustStore>>) -> Self {
+        self.trust = Some(trust);
+        self
+    }
+
+    pub fn with_psk(mut self, psk: PskProfile) -> Self {
+        self.psk = psk;
+        self
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.clock.now_unix().unwrap_or(UNSTAMPED)
+    }
+}
+
+/// Everything a finished handshake settled on.
+#[derive(Debug, Clone)]
+pub struct HandshakeOutcome {
+    pub keys: SessionKeys,
+    /// For [`crate::KeyRotation`] and any further keys.
+    pub schedule: KeySchedule,
+    pub encryption: NegotiatedEncryption,
+    /// AEAD for encrypted frames; see [`crate::negotiate_cipher_suite`].
+    pub cipher_suite: CipherSuite,
+    /// Padding for frame payloads; see [`crate::negotiate_padding`].
+    pub padding: PaddingScheme,
+    pub compression: NegotiatedCompression,
+    pub peer_device_id: String,
+    pub peer_public_key_b64: String,
+    /// The peer's advertised extensions.
+    pub peer_extensions: HelloExtensions,
+    pub transcript: [u8; 32],
+    /// Code to show the user when the peer is new, for comparison with the peer's screen.
+    pub sas: ShortAuthString,
+    /// The client's early data was accepted; when false the client sends it again.
+    pub early_data_accepted: bool,
+    /// How the peer's key compared with its pin; `None` without a trust store.
+    pub pin: Option<PinCheck>,
+    /// A pre-shared key was mixed into [`Self::keys`].
+    pub psk_used: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitiatorState {
+    AwaitingServerHello,
+    AwaitingServerFinished,
+    Complete,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponderState {
+    AwaitingClientHello,
+    AwaitingClientFinished,
+    Complete,
+    Failed,
+}
+
+/// Client side of a handshake: ClientHello, then ServerHello in, ClientFinished out,
+/// then ServerFinished in.
+///
+/// Any error moves the machine to [`InitiatorState::Failed`], after which every call
+/// fails; start a new handshake instead.
+#[derive(Debug)]
+pub struct HandshakeInitiator {
+    config: HandshakeConfig,
+    state: InitiatorState,
+    hello: ClientHello,
+    ephemeral: Option<EphemeralKey>,
+    /// Committed to in the hello, revealed in our Finished.
+    sas_secret: [u8; 32],
+    confirmed: Option<Confirmed>,
+}
+
+impl HandshakeInitiator {
+    /// Start a handshake; send the returned hello to the responder.
+    pub fn start(identity: &DeviceIdentity, config: HandshakeConfig) -> (Self, ClientHello) {
+        let ephemeral = EphemeralKey::generate();
+        let sas_secret = random_nonce();
+        let extensions = config
+            .extensions
+            .clone()
+            .with_sas_commitment(sas_commitment(&ephemeral.public_bytes(), &sas_secret));
+        let hello = client_hello_with_ephemeral(
+            &config.device_id,
+            identity,
+            config.capabilities,
+            extensions,
+            config.timestamp(),
+            &ephemeral,
+        );
+        let initiator = Self {
+            config,
+            state: InitiatorState::AwaitingServerHello,
+            hello: hello.clone(),
+            ephemeral: Some(ephemeral),
+            sas_secret,
+            confirmed: None,
+        };
+        (initiator, hello)
+    }
+
+    pub fn state(&self) -> InitiatorState {
+        self.state
+    }
+
+    /// Verify the server's hello against ours, negotiate, derive keys and return the
+    /// Finished message to send.
+    pub fn on_server_hello(
+        &mut self,
+        hello: &ServerHello,
+    ) -> Result<ClientFinished, HandshakeError> {
+        self.expect(
+            InitiatorState::AwaitingServerHello,
+            "expected a server hello",
+        )?;
+        let result = self.process_server_hello(hello);
+        self.advance(result, InitiatorState::AwaitingServerFinished)
+    }
+
+    /// Check the server's Finished; the handshake is complete once it verifies.
+    pub fn on_server_finished(
+        &mut self,
+        finished: &ServerFinished,
+    ) -> Result<HandshakeOutcome, HandshakeError> {
+        self.expect(
+            InitiatorState::AwaitingServerFinished,
+            "expected a server finished",
+        )?;
+        let confirmed = self.confirmed.take().expect("set with the server hello");
+        let result = self.process_server_finished(finished, confirmed);
+        self.advance(result, InitiatorState::Complete)
+    }
+
+    fn process_server_finished(
+        &self,
+        finished: &ServerFinished,
+        confirmed: Confirmed,
+    ) -> Result<HandshakeOutcome, HandshakeError> {
+        let mut outcome = confirmed.outcome;
+        let psk = accept_server_psk(
+            &self.config.psk,
+            &self.hello,
+            &confirmed.server,
+            finished.psk_binder.as_ref(),
+        )?;
+        if let Some(psk) = psk {
+            outcome.keys = mix_psk_into_keys(&outcome.keys, psk, &outcome.transcript);
+            outcome.psk_used = true;
+        }
+        finished.verify(
+            &outcome.keys,
+            &self.hello,
+            &confirmed.server,
+            &confirmed.client_finished,
+        )?;
+        Ok(outcome)
+    }
+
+    fn process_server_hello(
+        &mut self,
+        hello: &ServerHello,
+    ) -> Result<ClientFinished, HandshakeError> {
+        verify_server_hello_with(
+            &self.hello,
+            hello,
+            &self.config.skew,
+            self.config.clock.now_unix(),
+        )?;
+        let (pin, policy) = check_peer(&self.config, &hello.device_id, &hello.public_key_b64)?;
+        let ephemeral = self.ephemeral.take().expect("held until the server hello");
+        let mut outcome = settle(&ephemeral, &self.hello, hello, true)?;
+        enforce_policy(policy, &outcome)?;
+        outcome.pin = pin;
+        outcome.sas = ShortAuthString::from_revealed(&outcome.transcript, &self.sas_secret);
+        let mut client_finished = ClientFinished::new(&outcome.keys, &self.hello, hello);
+        client_finished.sas_secret = Some(self.sas_secret);
+        client_finished.psk_binder = self
+            .config
+            .psk
+            .key
+            .as_ref()
+            .map(|psk| client_psk_binder(psk, &self.hello));
+        self.confirmed = Some(Confirmed {
+            server: hello.clone(),
+            client_finished: client_finished.clone(),
+            outcome,
+        });
+        Ok(client_finished)
+    }
+
+    fn expect(
+        &mut self,
+        state: InitiatorState,
+        message: &'static str,
+    ) -> Result<(), HandshakeError> {
+        if self.state != state {
+            self.state = InitiatorState::Failed;
+            return Err(HandshakeError::UnexpectedMessage(message));
+        }
+        Ok(())
+    }
+
+    fn advance<T>(
+        &mut self,
+        result: Result<T, HandshakeError>,
+        next: InitiatorState,
+    ) -> Result<T, HandshakeError> {
+        self.state = if result.is_ok() {
+            next
+        } else {
+            InitiatorState::Failed
+        };
+        result
+    }
+}
+
+/// Server side of a handshake: ClientHello in, ServerHello out, then ClientFinished
+/// in, ServerFinished out.
+///
+/// Hello nonces go through a [`ReplayGuard`] shared by every responder of the process,
+/// so a hello replayed onto another connection is refused.
+#[derive(Debug)]
+pub struct HandshakeResponder<'a> {
+    identity: &'a DeviceIdentity,
+    config: HandshakeConfig,
+    state: ResponderState,
+    pending: Option<Pending>,
+}
+
+impl<'a> HandshakeResponder<'a> {
+    pub fn new(identity: &'a DeviceIdentity, config: HandshakeConfig) -> Self {
+        Self {
+            identity,
+            config,
+            state: ResponderState::AwaitingClientHello,
+            pending: None,
+        }
+    }
+
+    pub fn state(&self) -> ResponderState {
+        self.state
+    }
+
+    /// Verify the client's hello, check its nonce is fresh and answer it.
+    ///
+    /// The config's clock drives the skew check; `now` drives the replay guard.
+    pub fn on_client_hello(
+        &mut self,
+        hello: &ClientHello,
+        replay_guard: &mut ReplayGuard,
+        now: Instant,
+    ) -> Result<ServerHello, HandshakeError> {
+        self.expect(
+            ResponderState::AwaitingClientHello,
+            "expected a client hello",
+        )?;
+        let result = self
+            .process_client_hello(hello, replay_guard, None, now)
+            .map(|(server_hello, _)| server_hello);
+        self.advance(result, ResponderState::AwaitingClientFinished)
+    }
+
+    /// [`Self::on_client_hello`] that also opens the hello's early data with `tickets`.
+    ///
+    /// The server hello tells the client whether its early data was accepted; rejected
+    /// early data does not fail the handshake.
+    pub fn on_client_hello_with_early_data(
+        &mut self,
+        hello: &ClientHello,
+        replay_guard: &mut ReplayGuard,
+        tickets: &mut TicketStore,
+        now: Instant,
+    ) -> Result<(ServerHello, EarlyData), HandshakeError> {
+        self.expect(
+            ResponderState::AwaitingClientHello,
+            "expected a client hello",
+        )?;
+        let result = self.process_client_hello(hello, replay_guard, Some(tickets), now);
+        self.advance(result, ResponderState::AwaitingClientFinished)
+    }
+
+    /// Check the client's Finished and return ours with the settled session.
+    pub fn on_client_finished(
+        &mut self,
+        finished: &ClientFinished,
+    ) -> Result<(ServerFinished, HandshakeOutcome), HandshakeError> {
+        self.expect(
+            ResponderState::AwaitingClientFinished,
+            "expected a client finished",
+        )?;
+        let pending = self.pending.take().expect("set with the client hello");
+        let result = self.process_client_finished(finished, pending);
+        self.advance(result, ResponderState::Complete)
+    }
+
+    /// The client's Finished is checked under the identity keys; ours goes out under
+    /// the final ones, with the PSK mixed in when we accepted it.
+    fn process_client_finished(
+        &self,
+        finished: &ClientFinished,
+        pending: Pending,
+    ) -> Result<(ServerFinished, HandshakeOutcome), HandshakeError> {
+        finished.verify(&pending.outcome.keys, &pending.client, &pending.server)?;
+        let sas_secret = finished
+            .sas_secret
+            .ok_or(HandshakeError::SasCommitmentMismatch)?;
+        verify_sas_reveal(&pending.client, &sas_secret)?;
+        let mut outcome = pending.outcome;
+        outcome.sas = ShortAuthString::from_revealed(&outcome.transcript, &sas_secret);
+        let psk = accept_client_psk(
+            &self.config.psk,
+            &pending.client,
+            finished.psk_binder.as_ref(),
+        )?;
+        let mut psk_binder = None;
+        if let Some(psk) = psk {
+            outcome.keys = mix_psk_into_keys(&outcome.keys, psk, &outcome.transcript);
+            outcome.psk_used = true;
+            psk_binder = Some(server_psk_binder(psk, &pending.client, &pending.server));
+        }
+        let mut server_finished =
+            ServerFinished::new(&outcome.keys, &pending.client, &pending.server, finished);
+        server_finished.psk_binder = psk_binder;
+        Ok((server_finished, outcome))
+    }
+
+    fn process_client_hello(
+        &mut self,
+        hello: &ClientHello,
+        replay_guard: &mut ReplayGuard,
+        tickets: Option<&mut TicketStore>,
+        now: Instant,
+    ) -> Result<(ServerHello, EarlyData), HandshakeError> {
+        let now_secs = self.config.clock.now_unix();
+        verify_client_hello_with(hello, &self.config.skew, now_secs)?;
+        replay_guard.check_and_remember(hello.nonce, now)?;
+        let (pin, policy) = check_peer(&self.config, &hello.device_id, &hello.public_key_b64)?;
+        // Without a commitment the server's key could be chosen to steer the SAS.
+        if hello
+            .extensions
+            .get(EXT_SAS_COMMITMENT)
+            .is_none_or(|commitment| commitment.len() != 32)
+        {
+            return Err(HandshakeError::SasCommitmentMismatch);
+        }
+        let mut capabilities = self.config.capabilities;
+        if let Some(trust) = &self.config.trust {
+            capabilities = lock(trust).capabilities_for(
+                capabilities,
+                &hello.device_id,
+                &hello.public_key_b64,
+            )?;
+        }
+        let early_data = match (tickets, now_secs) {
+            (Some(tickets), Some(now_secs)) => tickets.accept_early_data(hello, now_secs),
+            (Some(_), None) => EarlyData::Rejected("no wall clock to check the ticket against"),
+            (None, _) if hello.extensions.offers_early_data() => {
+                EarlyData::Rejected("early data not enabled")
+            }
+            (None, _) => EarlyData::NotOffered,
+        };
+        let mut extensions = self.config.extensions.clone();
+        if let EarlyData::Accepted(_) = early_data {
+            extensions = extensions.with_early_data_accepted()?;
+        }
+        let (server_hello, ephemeral) = server_hello_at(
+            &self.config.device_id,
+            self.identity,
+            hello,
+            capabilities,
+            extensions,
+            self.config.timestamp(),
+        );
+        let mut outcome = settle(&ephemeral, hello, &server_hello, false)?;
+        enforce_policy(policy, &outcome)?;
+        outcome.pin = pin;
+        self.pending = Some(Pending {
+            client: hello.clone(),
+            server: server_hello.clone(),
+            outcome,
+        });
+        Ok((server_hello, early_data))
+    }
+
+    fn expect(
+        &mut self,
+        state: ResponderState,
+        message: &'static str,
+    ) -> Result<(), HandshakeError> {
+        if self.state != state {
+            self.state = ResponderState::Failed;
+            return Err(HandshakeError::UnexpectedMessage(message));
+        }
+        Ok(())
+    }
+
+    fn advance<T>(
+        &mut self,
+        result: Result<T, HandshakeError>,
+        next: ResponderState,
+    ) -> Result<T, HandshakeError> {
+        self.state = if result.is_ok() {
+            next
+        } else {
+            ResponderState::Failed
+        };
+        result
+    }
+}
+
+#[derive(Debug)]
+struct Confirmed {
+    server: ServerHello,
+    client_finished: ClientFinished,
+    outcome: HandshakeOutcome,
+}
+
+#[derive(Debug)]
+struct Pending {
+    client: ClientHello,
+    server: ServerHello,
+    outcome: HandshakeOutcome,
+}
+
+/// Pin check for the peer's verified key, with the encryption policy its trust state
+/// calls for. Without a trust store every peer gets the config's own capabilities.
+fn check_peer(
+    config: &HandshakeConfig,
+    device_id: &str,
+    public_key_b64: &str,
+) -> Result<(Option<PinCheck>, EncryptionMode), HandshakeError> {
+    let Some(trust) = &config.trust else {
+        return Ok((None, EncryptionMode::Optional));
+    };
+    let mut store = lock(trust);
+    let pin = store.check(device_id, public_key_b64, config.timestamp())?;
+    Ok((
+        Some(pin),
+        store.encryption_policy_for(device_id, public_key_b64),
+    ))
+}
+
+/// A peer whose policy requires encryption cannot settle on a plaintext session, even
+/// when the peer's hello did not ask for one.
+fn enforce_policy(
+    policy: EncryptionMode,
+    outcome: &HandshakeOutcome,
+) -> Result<(), HandshakeError> {
+    if policy == EncryptionMode::Required && !outcome.encryption.enabled {
+        return Err(HandshakeError::EncryptionRequiredButUnsupported);
+    }
+    Ok(())
+}
+
+/// A poisoned lock only means another handshake panicked mid-update; the pins it holds
+/// are still the ones on disk.
+fn lock(trust: &Mutex<TrustStore>) -> std::sync::MutexGuard<'_, TrustStore> {
+    trust
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Negotiation and key derivation shared by both roles once both hellos are known.
+fn settle(
+    own: &EphemeralKey,
+    client: &ClientHello,
+    server: &ServerHello,
+    is_client: bool,
+) -> Result<HandshakeOutcome, HandshakeError> {
+    let encryption = negotiate_encryption(client.capabilities, server.capabilities)?;
+    let cipher_suite = negotiate_cipher_suite(&client.extensions, &server.extensions)?;
+    let padding = negotiate_padding(&client.extensions, &server.extensions)?;
+    let schedule = derive_key_schedule(own, client, server, is_client)?;
+    let transcript = handshake_transcript(client, server);
+    let peer = if is_client {
+        (
+            &server.device_id,
+            &server.public_key_b64,
+            &server.extensions,
+        )
+    } else {
+        (
+            &client.device_id,
+            &client.public_key_b64,
+            &client.extensions,
+        )
+    };
+    Ok(HandshakeOutcome {
+        keys: schedule.session_keys(is_client),
+        encryption,
+        cipher_suite,
+        padding,
+        compression: negotiate_compression(client.capabilities, server.capabilities),
+        peer_device_id: peer.0.clone(),
+        peer_public_key_b64: peer.1.clone(),
+        peer_extensions: peer.2.clone(),
+        transcript,
+        sas: ShortAuthString::from_transcript(&transcript),
+        early_data_accepted: client.extensions.offers_early_data()
+            && server.extensions.early_data_accepted(),
+        schedule,
+        pin: None,
+        psk_used: false,
+    })
+}
@@ -0,0 +1,131 @@
+use crate::trust::validate_peer_field;
+use crate::{HandshakeError, TrustStore, TrustedPeer};
+use std::fmt;
+
+/// How far a pinned key is trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerTrustState {
+    /// Pinned on first contact and never confirmed by the user.
+    FirstUse,
+    /// The user compared fingerprints or paired with a code.
+    Verified,
+    /// The user refused this device; its handshakes fail.
+    Blocked,
+}
+
+impl PeerTrustState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PeerTrustState::FirstUse => "first-use",
+            PeerTrustState::Verified => "verified",
+            PeerTrustState::Blocked => "blocked",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "first-use" => Some(PeerTrustState::FirstUse),
+            "verified" => Some(PeerTrustState::Verified),
+            "blocked" => Some(PeerTrustState::Blocked),
+            _ => None,
+        }
+    }
+}
+
+/// A known device presented a different key than the one pinned for it.
+///
+/// Either the device was reinstalled or someone is impersonating it; only the user can
+/// tell, so the handshake stops until [`TrustStore::replace_key`] is called.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyChangedError {
+    pub device_id: String,
+    pub pinned_public_key_b64: String,
+    pub presented_public_key_b64: String,
+}
+
+impl fmt::Display for KeyChangedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "public key of device {} changed", self.device_id)
+    }
+}
+
+impl std::error::Error for KeyChangedError {}
+
+/// Result of checking a peer against its pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinCheck {
+    /// Never seen before; its key is now pinned as [`PeerTrustState::FirstUse`].
+    FirstSeen,
+    /// Matches the pinned key.
+    Known(PeerTrustState),
+}
+
+/// Trust on first use: the first key seen for a device id is pinned in the trust store
+/// and every later handshake must present the same one.
+impl TrustStore {
+    /// Check `public_key_b64` against the pin for `device_id`, pinning it if the device
+    /// is new. Call only after the hello's signature verified.
+    pub fn check(
+        &mut self,
+        device_id: &str,
+        public_key_b64: &str,
+        now_secs: u64,
+    ) -> Result<PinCheck, HandshakeError> {
+        validate_peer_field(device_id)?;
+        validate_peer_field(public_key_b64)?;
+        let Some(peer) = self.get(device_id) else {
+            self.pin(
+                device_id,
+                public_key_b64,
+                PeerTrustState::FirstUse,
+                now_secs,
+            )?;
+            return Ok(PinCheck::FirstSeen);
+        };
+        if peer.public_key_b64 != public_key_b64 {
+            return Err(HandshakeError::KeyChanged(KeyChangedError {
+                device_id: device_id.to_string(),
+                pinned_public_key_b64: peer.public_key_b64.clone(),
+                presented_public_key_b64: public_key_b64.to_string(),
+            }));
+        }
+        if peer.state == PeerTrustState::Blocked {
+            return Err(HandshakeError::PeerBlocked);
+        }
+        Ok(PinCheck::Known(peer.state))
+    }
+
+    /// Pin a key directly, e.g. after pairing; replaces any earlier pin but keeps the
+    /// peer's encryption policy override.
+    pub fn pin(
+        &mut self,
+        device_id: &str,
+        public_key_b64: &str,
+        state: PeerTrustState,
+        now_secs: u64,
+    ) -> Result<(), HandshakeError> {
+        let encryption_policy = self.get(device_id).and_then(|peer| peer.encryption_policy);
+        self.trust(TrustedPeer {
+            device_id: device_id.to_string(),
+            public_key_b64: public_key_b64.to_string(),
+            encryption_policy,
+            state,
+            pinned_at_secs: now_secs,
+        })
+    }
+
+    /// Accept the new key from a [`KeyChangedError`] once the user confirmed the change.
+    /// The device starts over at [`PeerTrustState::FirstUse`].
+    pub fn replace_key(
+        &mut self,
+        changed: &KeyChangedError,
+        now_secs: u64,
+    ) -> Result<(), HandshakeError> {
+        self.pin(
+            &changed.device_id,
+            &changed.presented_public_key_b64,
+            PeerTrustState::FirstUse,
+            now_secs,
+        )
+    }
+}
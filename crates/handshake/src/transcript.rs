@@ -0,0 +1,64 @@
+use crate::{ClientHello, ServerHello};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Running hash over every handshake message exchanged so far.
+///
+/// Each message enters as its signed bytes plus signature, length-prefixed, so the hash
+/// changes if any capability bit, nonce or key share differs between the two views.
+/// The server signs the hash after the client hello, and the hash after both hellos
+/// salts the key schedule.
+#[derive(Clone)]
+pub struct TranscriptHash {
+    hasher: Sha256,
+}
+
+impl fmt::Debug for TranscriptHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TranscriptHash")
+            .field(&self.current())
+            .finish()
+    }
+}
+
+impl Default for TranscriptHash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TranscriptHash {
+    pub fn new() -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"p2p/transcript/v2");
+        Self { hasher }
+    }
+
+    /// Transcript as it stands when the server answers `hello`.
+    pub fn after_client_hello(hello: &ClientHello) -> Self {
+        let mut transcript = Self::new();
+        transcript.add_client_hello(hello);
+        transcript
+    }
+
+    pub fn add_client_hello(&mut self, hello: &ClientHello) {
+        self.add_message(&hello.signing_bytes());
+        self.add_message(&hello.signature);
+    }
+
+    pub fn add_server_hello(&mut self, hello: &ServerHello) {
+        self.add_message(&hello.signing_bytes());
+        self.add_message(&hello.signature);
+    }
+
+    /// Append any other handshake message in its encoded form.
+    pub fn add_message(&mut self, message: &[u8]) {
+        self.hasher.update((message.len() as u64).to_be_bytes());
+        self.hasher.update(message);
+    }
+
+    /// Hash of everything added so far; more messages can still be added.
+    pub fn current(&self) -> [u8; 32] {
+        self.hasher.clone().finalize().into()
+    }
+}
@@ -0,0 +1,290 @@
+use crate::{
+    EncryptionMode, HandshakeCapabilities, HandshakeError, NegotiatedEncryption, PeerTrustState,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrustedPeer {
+    pub device_id: String,
+    pub public_key_b64: String,
+    /// Per-peer override; `None` uses the store's default for the peer's trust state.
+    pub encryption_policy: Option<EncryptionMode>,
+    pub state: PeerTrustState,
+    /// Unix seconds when the key was pinned; 0 for peers added by hand.
+    pub pinned_at_secs: u64,
+}
+
+impl TrustedPeer {
+    /// A peer the user vouched for, under the store's default policy.
+    pub fn verified(device_id: &str, public_key_b64: &str) -> Self {
+        Self {
+            device_id: device_id.to_string(),
+            public_key_b64: public_key_b64.to_string(),
+            encryption_policy: None,
+            state: PeerTrustState::Verified,
+            pinned_at_secs: 0,
+        }
+    }
+}
+
+/// Known peers, the key pinned for each and the encryption policy applied to it.
+///
+/// A peer only counts as trusted when both its device id and public key match and the
+/// user verified it, so a reused id with a different key, or a key only pinned on first
+/// use, falls back to the unknown-peer policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrustStore {
+    pub trusted_peer_policy: EncryptionMode,
+    pub unknown_peer_policy: EncryptionMode,
+    peers: HashMap<String, TrustedPeer>,
+}
+
+impl Default for TrustStore {
+    fn default() -> Self {
+        Self::new(EncryptionMode::Off, EncryptionMode::Off)
+    }
+}
+
+impl TrustStore {
+    pub fn new(trusted_peer_policy: EncryptionMode, unknown_peer_policy: EncryptionMode) -> Self {
+        Self {
+            trusted_peer_policy,
+            unknown_peer_policy,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Add or replace a peer. Ids and keys must be non-empty and free of control
+    /// characters, which would break the line-based file format.
+    pub fn trust(&mut self, peer: TrustedPeer) -> Result<(), HandshakeError> {
+        validate_peer_field(&peer.device_id)?;
+        validate_peer_field(&peer.public_key_b64)?;
+        self.peers.insert(peer.device_id.clone(), peer);
+        Ok(())
+    }
+
+    pub fn revoke(&mut self, device_id: &str) -> Option<TrustedPeer> {
+        self.peers.remove(device_id)
+    }
+
+    pub fn get(&self, device_id: &str) -> Option<&TrustedPeer> {
+        self.peers.get(device_id)
+    }
+
+    /// Trusted peers sorted by device id.
+    pub fn peers(&self) -> Vec<&TrustedPeer> {
+        let mut peers: Vec<&TrustedPeer> = self.peers.values().collect();
+        peers.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+        peers
+    }
+
+    /// Tab-separated text form: two policy lines, then one `peer` line per entry with
+    /// its policy override, trust state and pin time.
+    pub fn encode(&self) -> String {
+        let mut out = format!(
+            "trusted_peer_policy\t{}\nunknown_peer_policy\t{}\n",
+            self.trusted_peer_policy.as_str(),
+            self.unknown_peer_policy.as_str()
+        );
+        for peer in self.peers() {
+            out.push_str(&format!(
+                "peer\t{}\t{}\t{}\t{}\t{}\n",
+                peer.device_id,
+                peer.public_key_b64,
+                peer.encryption_policy
+                    .map_or("default", EncryptionMode::as_str),
+                peer.state.as_str(),
+                peer.pinned_at_secs
+            ));
+        }
+        out
+    }
+
+    /// Parse [`Self::encode`] output. Peer lines without a trust state, from before pins
+    /// were kept here, load as verified. A device id listed twice is an error rather
+    /// than letting the later line win.
+    pub fn decode(text: &str) -> Result<Self, HandshakeError> {
+        let mut store = Self::default();
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let (device_id, public_key_b64, policy, state, pinned_at) = match fields.as_slice() {
+                ["trusted_peer_policy", mode] => {
+                    store.trusted_peer_policy = parse_mode(mode)?;
+                    continue;
+                }
+                ["unknown_peer_policy", mode] => {
+                    store.unknown_peer_policy = parse_mode(mode)?;
+                    continue;
+                }
+                ["peer", device_id, public_key_b64, policy] => {
+                    (device_id, public_key_b64, policy, "verified", "0")
+                }
+                ["peer", device_id, public_key_b64, policy, state, pinned_at] => {
+                    (device_id, public_key_b64, policy, *state, *pinned_at)
+                }
+                _ => return Err(HandshakeError::InvalidTrustStore("unrecognised line")),
+            };
+            if device_id.is_empty() || public_key_b64.is_empty() {
+                return Err(HandshakeError::InvalidTrustStore("empty peer field"));
+            }
+            if store.peers.contains_key(*device_id) {
+                return Err(HandshakeError::InvalidTrustStore("duplicate device id"));
+            }
+            let encryption_policy = match *policy {
+                "default" => None,
+                mode => Some(parse_mode(mode)?),
+            };
+            let state = PeerTrustState::parse(state)
+                .ok_or(HandshakeError::InvalidTrustStore("unknown trust state"))?;
+            let pinned_at_secs = pinned_at
+                .parse()
+                .map_err(|_| HandshakeError::InvalidTrustStore("bad pin timestamp"))?;
+            store.trust(TrustedPeer {
+                device_id: device_id.to_string(),
+                public_key_b64: public_key_b64.to_string(),
+                encryption_policy,
+                state,
+                pinned_at_secs,
+            })?;
+        }
+        Ok(store)
+    }
+
+    /// Load a saved store; a missing file yields the default (empty) store.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, HandshakeError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Self::decode(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), HandshakeError> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, self.encode())?;
+        Ok(())
+    }
+
+    pub fn set_encryption_policy(
+        &mut self,
+        device_id: &str,
+        policy: Option<EncryptionMode>,
+    ) -> Result<(), HandshakeError> {
+        let peer = self
+            .peers
+            .get_mut(device_id)
+            .ok_or(HandshakeError::PeerNotTrusted)?;
+        peer.encryption_policy = policy;
+        Ok(())
+    }
+
+    /// Raise or lower how far a peer's pinned key is trusted, e.g. to block it.
+    pub fn set_state(
+        &mut self,
+        device_id: &str,
+        state: PeerTrustState,
+    ) -> Result<(), HandshakeError> {
+        let peer = self
+            .peers
+            .get_mut(device_id)
+            .ok_or(HandshakeError::PeerNotTrusted)?;
+        peer.state = state;
+        Ok(())
+    }
+
+    /// Policy for a peer presenting `public_key_b64` under `device_id`.
+    ///
+    /// A per-peer override applies to any matching peer that is not blocked; otherwise
+    /// only verified peers get the trusted-peer default.
+    pub fn encryption_policy_for(&self, device_id: &str, public_key_b64: &str) -> EncryptionMode {
+        match self.peers.get(device_id) {
+            Some(peer)
+                if peer.public_key_b64 == public_key_b64
+                    && peer.state != PeerTrustState::Blocked =>
+            {
+                let default = match peer.state {
+                    PeerTrustState::Verified => self.trusted_peer_policy,
+                    _ => self.unknown_peer_policy,
+                };
+                peer.encryption_policy.unwrap_or(default)
+            }
+            _ => self.unknown_peer_policy,
+        }
+    }
+
+    /// Raise the local capabilities to what this peer's policy demands.
+    ///
+    /// The stricter of the global preference and the peer policy wins.
+    pub fn capabilities_for(
+        &self,
+        local: HandshakeCapabilities,
+        device_id: &str,
+        public_key_b64: &str,
+    ) -> Result<HandshakeCapabilities, HandshakeError> {
+        let policy = self.encryption_policy_for(device_id, public_key_b64);
+        let mode = local.preferred_encryption_mode.stricter(policy);
+        if !local.supports_encryption {
+            return match mode {
+                EncryptionMode::Required => Err(HandshakeError::EncryptionRequiredButUnsupported),
+                _ => Ok(local),
+            };
+        }
+        Ok(HandshakeCapabilities {
+            supports_encryption: true,
+            preferred_encryption_mode: mode,
+            ..local
+        })
+    }
+
+    /// Capability negotiation with the peer's policy applied to our side.
+    pub fn negotiate_for_peer(
+        &self,
+        local: HandshakeCapabilities,
+        remote: HandshakeCapabilities,
+        device_id: &str,
+        public_key_b64: &str,
+    ) -> Result<NegotiatedEncryption, HandshakeError> {
+        let local = self.capabilities_for(local, device_id, public_key_b64)?;
+        crate::negotiate_encryption(local, remote)
+    }
+
+    /// Reject plaintext frames from a peer whose session or policy requires encryption.
+    pub fn check_frame(
+        &self,
+        device_id: &str,
+        public_key_b64: &str,
+        negotiated: NegotiatedEncryption,
+        frame_encrypted: bool,
+    ) -> Result<(), HandshakeError> {
+        let required = negotiated
+            .mode
+            .stricter(self.encryption_policy_for(device_id, public_key_b64));
+        if required == EncryptionMode::Required && !frame_encrypted {
+            return Err(HandshakeError::PlaintextFrameRejected);
+        }
+        Ok(())
+    }
+}
+
+/// Device ids and keys end up as tab-separated fields, so a tab or newline in one
+/// could forge extra lines; every control character is refused.
+pub(crate) fn validate_peer_field(value: &str) -> Result<(), HandshakeError> {
+    if value.is_empty() {
+        return Err(HandshakeError::InvalidPeerId("empty device id or key"));
+    }
+    if value.chars().any(char::is_control) {
+        return Err(HandshakeError::InvalidPeerId(
+            "control character in device id or key",
+        ));
+    }
+    Ok(())
+}
+
+fn parse_mode(value: &str) -> Result<EncryptionMode, HandshakeError> {
+    EncryptionMode::parse(value).ok_or(HandshakeError::InvalidTrustStore("unknown encryption mode"))
+}
@@ -0,0 +1,301 @@
+use crate::{
+    push_capabilities, ClientFinished, ClientHello, CompressionSupport, EncryptionMode,
+    HandshakeCapabilities, HandshakeError, HelloExtensions, PskBinder, Rekey, ServerFinished,
+    ServerHello, MAX_HELLO_EXTENSIONS,
+};
+
+const MAGIC_HANDSHAKE: &[u8; 4] = b"P2PH";
+const WIRE_VERSION: u8 = 1;
+const KIND_CLIENT_HELLO: u8 = 1;
+const KIND_SERVER_HELLO: u8 = 2;
+const KIND_CLIENT_FINISHED: u8 = 3;
+const KIND_SERVER_FINISHED: u8 = 4;
+const KIND_REKEY: u8 = 5;
+
+impl ClientHello {
+    /// MAGIC | version | kind | device_id | public_key_b64 | ephemeral_public[32]
+    ///   | nonce[32] | timestamp(u64) | capabilities[3] | extensions | signature[64]
+    ///
+    /// Fields follow the signing-bytes order; strings are u16-length-prefixed, and
+    /// extensions are a u16 count of type(u16) | len(u16) | value entries.
+    pub fn encode(&self) -> Result<Vec<u8>, HandshakeError> {
+        let mut out = header(KIND_CLIENT_HELLO);
+        put_str(&mut out, &self.device_id)?;
+        put_str(&mut out, &self.public_key_b64)?;
+        out.extend_from_slice(&self.ephemeral_public);
+        out.extend_from_slice(&self.nonce);
+        out.extend_from_slice(&self.timestamp_secs.to_be_bytes());
+        push_capabilities(&mut out, self.capabilities);
+        self.extensions.write(&mut out);
+        out.extend_from_slice(&self.signature);
+        Ok(out)
+    }
+
+    /// Parse and validate the framing; the signature is checked separately with
+    /// [`crate::verify_client_hello`].
+    pub fn decode(bytes: &[u8]) -> Result<Self, HandshakeError> {
+        let mut reader = Reader::new(bytes, KIND_CLIENT_HELLO)?;
+        let hello = Self {
+            device_id: reader.string()?,
+            public_key_b64: reader.string()?,
+            ephemeral_public: reader.array()?,
+            nonce: reader.array()?,
+            timestamp_secs: u64::from_be_bytes(reader.array()?),
+            capabilities: reader.capabilities()?,
+            extensions: reader.extensions()?,
+            signature: reader.array()?,
+        };
+        reader.finish()?;
+        Ok(hello)
+    }
+}
+
+impl ServerHello {
+    /// MAGIC | version | kind | device_id | public_key_b64 | ephemeral_public[32]
+    ///   | client_nonce[32] | server_nonce[32] | client_hello_hash[32] | timestamp(u64)
+    ///   | capabilities[3] | extensions | signature[64]
+    pub fn encode(&self) -> Result<Vec<u8>, HandshakeError> {
+        let mut out = header(KIND_SERVER_HELLO);
+        put_str(&mut out, &self.device_id)?;
+        put_str(&mut out, &self.public_key_b64)?;
+        out.extend_from_slice(&self.ephemeral_public);
+        out.extend_from_slice(&self.client_nonce);
+        out.extend_from_slice(&self.server_nonce);
+        out.extend_from_slice(&self.client_hello_hash);
+        out.extend_from_slice(&self.timestamp_secs.to_be_bytes());
+        push_capabilities(&mut out, self.capabilities);
+        self.extensions.write(&mut out);
+        out.extend_from_slice(&self.signature);
+        Ok(out)
+    }
+
+    /// Parse and validate the framing; the signature is checked separately with
+    /// [`crate::verify_server_hello`].
+    pub fn decode(bytes: &[u8]) -> Result<Self, HandshakeError> {
+        let mut reader = Reader::new(bytes, KIND_SERVER_HELLO)?;
+        let hello = Self {
+            device_id: reader.string()?,
+            public_key_b64: reader.string()?,
+            ephemeral_public: reader.array()?,
+            client_nonce: reader.array()?,
+            server_nonce: reader.array()?,
+            client_hello_hash: reader.array()?,
+            timestamp_secs: u64::from_be_bytes(reader.array()?),
+            capabilities: reader.capabilities()?,
+            extensions: reader.extensions()?,
+            signature: reader.array()?,
+        };
+        reader.finish()?;
+        Ok(hello)
+    }
+}
+
+impl ClientFinished {
+    /// MAGIC | version | kind | verify_data[32] | has_binder(u8) [| psk_id | binder[32]]
+    /// | has_sas_secret(u8) [| sas_secret[32]]
+    pub fn encode(&self) -> Result<Vec<u8>, HandshakeError> {
+        let mut out = header(KIND_CLIENT_FINISHED);
+        out.extend_from_slice(&self.verify_data);
+        put_binder(&mut out, self.psk_binder.as_ref())?;
+        match &self.sas_secret {
+            Some(secret) => {
+                out.push(1);
+                out.extend_from_slice(secret);
+            }
+            None => out.push(0),
+        }
+        Ok(out)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, HandshakeError> {
+        let mut reader = Reader::new(bytes, KIND_CLIENT_FINISHED)?;
+        let finished = Self {
+            verify_data: reader.array()?,
+            psk_binder: reader.binder()?,
+            sas_secret: match reader.array::<1>()? {
+                [0] => None,
+                [1] => Some(reader.array()?),
+                _ => return Err(HandshakeError::InvalidMessage("bad sas secret flag")),
+            },
+        };
+        reader.finish()?;
+        Ok(finished)
+    }
+}
+
+impl ServerFinished {
+    /// [`ClientFinished::encode`] without the SAS secret.
+    pub fn encode(&self) -> Result<Vec<u8>, HandshakeError> {
+        let mut out = header(KIND_SERVER_FINISHED);
+        out.extend_from_slice(&self.verify_data);
+        put_binder(&mut out, self.psk_binder.as_ref())?;
+        Ok(out)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, HandshakeError> {
+        let mut reader = Reader::new(bytes, KIND_SERVER_FINISHED)?;
+        let finished = Self {
+            verify_data: reader.array()?,
+            psk_binder: reader.binder()?,
+        };
+        reader.finish()?;
+        Ok(finished)
+    }
+}
+
+impl Rekey {
+    /// MAGIC | version | kind | epoch(u32) | tag[32]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = header(KIND_REKEY);
+        out.extend_from_slice(&self.epoch.to_be_bytes());
+        out.extend_from_slice(&self.tag);
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, HandshakeError> {
+        let mut reader = Reader::new(bytes, KIND_REKEY)?;
+        let rekey = Self {
+            epoch: u32::from_be_bytes(reader.array()?),
+            tag: reader.array()?,
+        };
+        reader.finish()?;
+        Ok(rekey)
+    }
+}
+
+fn header(kind: u8) -> Vec<u8> {
+    let mut out = Vec::with_capacity(256);
+    out.extend_from_slice(MAGIC_HANDSHAKE);
+    out.push(WIRE_VERSION);
+    out.push(kind);
+    out
+}
+
+fn put_str(out: &mut Vec<u8>, value: &str) -> Result<(), HandshakeError> {
+    let len = u16::try_from(value.len())
+        .map_err(|_| HandshakeError::InvalidMessage("string field too long"))?;
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+fn put_binder(out: &mut Vec<u8>, binder: Option<&PskBinder>) -> Result<(), HandshakeError> {
+    let Some(binder) = binder else {
+        out.push(0);
+        return Ok(());
+    };
+    out.push(1);
+    put_str(out, &binder.psk_id)?;
+    out.extend_from_slice(&binder.binder);
+    Ok(())
+}
+
+struct Reader<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], kind: u8) -> Result<Self, HandshakeError> {
+        if bytes.len() < 6 || &bytes[..4] != MAGIC_HANDSHAKE {
+            return Err(HandshakeError::InvalidMessage(
+                "bad handshake message header",
+            ));
+        }
+        if bytes[4] != WIRE_VERSION {
+            return Err(HandshakeError::InvalidMessage(
+                "unsupported handshake message version",
+            ));
+        }
+        if bytes[5] != kind {
+            return Err(HandshakeError::InvalidMessage(
+                "unexpected handshake message kind",
+            ));
+        }
+        Ok(Self { rest: &bytes[6..] })
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], HandshakeError> {
+        if self.rest.len() < len {
+            return Err(HandshakeError::InvalidMessage(
+                "truncated handshake message",
+            ));
+        }
+        let (head, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], HandshakeError> {
+        Ok(self.take(N)?.try_into().expect("slice len"))
+    }
+
+    fn string(&mut self) -> Result<String, HandshakeError> {
+        let len = u16::from_be_bytes(self.array()?) as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| HandshakeError::InvalidMessage("string field not utf-8"))
+    }
+
+    fn binder(&mut self) -> Result<Option<PskBinder>, HandshakeError> {
+        match self.array::<1>()? {
+            [0] => Ok(None),
+            [1] => Ok(Some(PskBinder {
+                psk_id: self.string()?,
+                binder: self.array()?,
+            })),
+            _ => Err(HandshakeError::InvalidMessage("bad psk binder flag")),
+        }
+    }
+
+    fn capabilities(&mut self) -> Result<HandshakeCapabilities, HandshakeError> {
+        let [encryption, mode, compression] = self.array()?;
+        let supports_encryption = match encryption {
+            0 => false,
+            1 => true,
+            _ => return Err(HandshakeError::InvalidCapabilities),
+        };
+        if compression & !0b11 != 0 {
+            return Err(HandshakeError::InvalidCapabilities);
+        }
+        Ok(HandshakeCapabilities {
+            supports_encryption,
+            preferred_encryption_mode: EncryptionMode::from_u8(mode)?,
+            compression: CompressionSupport {
+                zstd: compression & 0b01 != 0,
+                lz4: compression & 0b10 != 0,
+            },
+        })
+    }
+
+    /// Entries must be in strictly increasing type order, as [`HelloExtensions`] writes
+    /// them, so each extension set has exactly one encoding.
+    fn extensions(&mut self) -> Result<HelloExtensions, HandshakeError> {
+        let count = u16::from_be_bytes(self.array()?) as usize;
+        if count > MAX_HELLO_EXTENSIONS {
+            return Err(HandshakeError::InvalidMessage("too many hello extensions"));
+        }
+        let mut extensions = HelloExtensions::new();
+        let mut previous = None;
+        for _ in 0..count {
+            let kind = u16::from_be_bytes(self.array()?);
+            if previous.is_some_and(|previous| kind <= previous) {
+                return Err(HandshakeError::InvalidMessage(
+                    "hello extensions out of order",
+                ));
+            }
+            previous = Some(kind);
+            let len = u16::from_be_bytes(self.array()?) as usize;
+            extensions.insert(kind, self.take(len)?.to_vec())?;
+        }
+        Ok(extensions)
+    }
+
+    fn finish(self) -> Result<(), HandshakeError> {
+        if !self.rest.is_empty() {
+            return Err(HandshakeError::InvalidMessage(
+                "trailing bytes after handshake message",
+            ));
+        }
+        Ok(())
+    }
+}
@@ -1,16 +1,32 @@
 use handshake::{
-    create_client_hello, create_client_hello_with_capabilities, create_server_hello,
-    create_server_hello_with_capabilities, derive_session_keys, negotiate_encryption,
-    verify_client_hello, verify_server_hello, EncryptionMode, HandshakeCapabilities,
-    HandshakeError, ReplayGuard,
+    accept_client_psk, accept_server_psk, client_psk_binder, create_client_hello,
+    create_client_hello_with_capabilities, create_client_hello_with_extensions,
+    create_server_hello, create_server_hello_with_capabilities,
+    create_server_hello_with_extensions, derive_key_schedule, derive_session_keys,
+    handshake_transcript, key_confirmation_tag, mix_psk_into_keys, negotiate_cipher_suite,
+    negotiate_compression, negotiate_encryption, negotiate_max_chunk_size, negotiate_padding,
+    sas_commitment, server_psk_binder, verify_client_hello, verify_client_hello_pinned,
+    verify_key_confirmation, verify_sas_reveal, verify_server_hello, verify_server_hello_pinned,
+    AttemptAction, CipherSuite, ClientFinished, ClientHello, Clock, CompressionSupport, EarlyData,
+    EncryptionMode, EphemeralKey, FixedClock, GroupSession, HandshakeAttempt,
+    HandshakeCapabilities, HandshakeConfig, HandshakeError, HandshakeInitiator, HandshakeResponder,
+    HelloExtensions, InitiatorState, KeyRotation, KeySchedule, MonotonicFallback,
+    NegotiatedCompression, PaddingScheme, PairingCode, PairingShare, PairingState, PeerTrustState,
+    PinCheck, PreSharedKey, PskProfile, PskProfiles, Rekey, RekeyPolicy, ReplayGuard,
+    ResponderState, ResumptionTicket, RetryPolicy, ServerFinished, ServerHello, ShortAuthString,
+    SkewPolicy, SystemClock, TicketStore, TranscriptHash, TrustStore, TrustedPeer,
+    DEFAULT_REPLAY_GUARD_ENTRIES, DEFAULT_TICKET_LIFETIME_SECS, EXT_CIPHER_SUITES,
+    EXT_MAX_CHUNK_SIZE, EXT_PADDING_SCHEMES, EXT_SAS_COMMITMENT, LABEL_C2S, LABEL_S2C,
+    MAX_EARLY_DATA_LEN, UNSTAMPED,
 };
 use identity::DeviceIdentity;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 #[test]
 fn client_hello_verification_succeeds() {
     let client = DeviceIdentity::generate();
-    let hello = create_client_hello("client-1", &client);
+    let (hello, _) = create_client_hello("client-1", &client);
     let now = hello.timestamp_secs;
     verify_client_hello(&hello, 30, now).expect("valid client hello");
 }
@@ -20,21 +36,22 @@ fn server_hello_verification_succeeds() {
     let client = DeviceIdentity::generate();
     let server = DeviceIdentity::generate();
 
-    let ch = create_client_hello("client-1", &client);
-    let sh = create_server_hello("server-1", &server, &ch);
+    let (ch, _) = create_client_hello("client-1", &client);
+    let (sh, _) = create_server_hello("server-1", &server, &ch);
 
-    verify_server_hello(ch.nonce, &sh, 30, sh.timestamp_secs).expect("valid server hello");
+    verify_server_hello(&ch, &sh, 30, sh.timestamp_secs).expect("valid server hello");
 }
 
 #[test]
 fn client_hello_signature_covers_capabilities() {
     let client = DeviceIdentity::generate();
-    let mut hello = create_client_hello_with_capabilities(
+    let (mut hello, _) = create_client_hello_with_capabilities(
         "client-1",
         &client,
         HandshakeCapabilities {
             supports_encryption: true,
             preferred_encryption_mode: EncryptionMode::Optional,
+            ..HandshakeCapabilities::default()
         },
     );
 
@@ -48,21 +65,22 @@ fn client_hello_signature_covers_capabilities() {
 fn server_hello_signature_covers_capabilities() {
     let client = DeviceIdentity::generate();
     let server = DeviceIdentity::generate();
-    let ch = create_client_hello("client-1", &client);
+    let (ch, _) = create_client_hello("client-1", &client);
 
-    let mut sh = create_server_hello_with_capabilities(
+    let (mut sh, _) = create_server_hello_with_capabilities(
         "server-1",
         &server,
         &ch,
         HandshakeCapabilities {
             supports_encryption: true,
             preferred_encryption_mode: EncryptionMode::Optional,
+            ..HandshakeCapabilities::default()
         },
     );
 
     sh.capabilities.supports_encryption = false;
 
-    let err = verify_server_hello(ch.nonce, &sh, 30, sh.timestamp_secs).expect_err("tamper fails");
+    let err = verify_server_hello(&ch, &sh, 30, sh.timestamp_secs).expect_err("tamper fails");
     assert!(matches!(err, HandshakeError::InvalidSignature));
 }
 
@@ -72,10 +90,12 @@ fn negotiation_optional_falls_back_to_plaintext_when_peer_lacks_support() {
         HandshakeCapabilities {
             supports_encryption: true,
             preferred_encryption_mode: EncryptionMode::Optional,
+            ..HandshakeCapabilities::default()
         },
         HandshakeCapabilities {
             supports_encryption: false,
             preferred_encryption_mode: EncryptionMode::Off,
+            ..HandshakeCapabilities::default()
         },
     )
     .expect("fallback allowed");
@@ -90,10 +110,12 @@ fn negotiation_required_rejects_non_supporting_peer() {
         HandshakeCapabilities {
             supports_encryption: true,
             preferred_encryption_mode: EncryptionMode::Required,
+            ..HandshakeCapabilities::default()
         },
         HandshakeCapabilities {
             supports_encryption: false,
             preferred_encryption_mode: EncryptionMode::Off,
+            ..HandshakeCapabilities::default()
         },
     )
     .expect_err("required should fail closed");
@@ -110,10 +132,12 @@ fn negotiation_enables_optional_when_both_support_it() {
         HandshakeCapabilities {
             supports_encryption: true,
             preferred_encryption_mode: EncryptionMode::Optional,
+            ..HandshakeCapabilities::default()
         },
         HandshakeCapabilities {
             supports_encryption: true,
             preferred_encryption_mode: EncryptionMode::Off,
+            ..HandshakeCapabilities::default()
         },
     )
     .expect("optional succeeds");
@@ -127,37 +151,1524 @@ fn session_keys_are_directional_and_consistent() {
     let client = DeviceIdentity::generate();
     let server = DeviceIdentity::generate();
 
-    let ch = create_client_hello("client-1", &client);
-    let sh = create_server_hello("server-1", &server, &ch);
+    let (ch, client_secret) = create_client_hello("client-1", &client);
+    let (sh, server_secret) = create_server_hello("server-1", &server, &ch);
 
-    let client_keys = derive_session_keys(
-        &ch.public_key_b64,
-        &sh.public_key_b64,
-        ch.nonce,
-        sh.server_nonce,
+    let client_keys = derive_session_keys(&client_secret, &ch, &sh, true).expect("client keys");
+    let server_keys = derive_session_keys(&server_secret, &ch, &sh, false).expect("server keys");
+
+    assert_eq!(client_keys.tx_key, server_keys.rx_key);
+    assert_eq!(client_keys.rx_key, server_keys.tx_key);
+    assert_ne!(client_keys.tx_key, client_keys.rx_key);
+}
+
+#[test]
+fn key_confirmation_passes_both_ways_and_catches_mismatched_derivation() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let (ch, client_secret) = create_client_hello("client-1", &client);
+    let (sh, server_secret) = create_server_hello("server-1", &server, &ch);
+    let transcript = handshake_transcript(&ch, &sh);
+
+    let client_keys = derive_session_keys(&client_secret, &ch, &sh, true).expect("client keys");
+    let server_keys = derive_session_keys(&server_secret, &ch, &sh, false).expect("server keys");
+
+    let client_tag = key_confirmation_tag(&client_keys, &transcript);
+    let server_tag = key_confirmation_tag(&server_keys, &transcript);
+    verify_key_confirmation(&server_keys, &transcript, &client_tag).expect("client -> server");
+    verify_key_confirmation(&client_keys, &transcript, &server_tag).expect("server -> client");
+
+    // A peer that derived from different inputs is caught before any chunk is sent.
+    let skewed =
+        derive_session_keys(&EphemeralKey::generate(), &ch, &sh, false).expect("other keys");
+    assert!(matches!(
+        verify_key_confirmation(&skewed, &transcript, &client_tag),
+        Err(HandshakeError::KeyConfirmationFailed)
+    ));
+    // Same keys but a different view of the exchange also fails.
+    let (other, _) = create_server_hello("server-1", &server, &ch);
+    assert!(matches!(
+        verify_key_confirmation(
+            &server_keys,
+            &handshake_transcript(&ch, &other),
+            &client_tag
+        ),
+        Err(HandshakeError::KeyConfirmationFailed)
+    ));
+}
+
+#[test]
+fn psk_handshake_binds_transcript_and_mixes_secret_into_keys() {
+    let mut profiles = PskProfiles::default();
+    profiles
+        .apply_settings(
+            "psk.lab-net.id = lab-2024\n\
+             psk.lab-net.secret = 000102030405060708090a0b0c0d0e0f\n\
+             psk.lab-net.strict = true\n",
+        )
+        .expect("settings");
+    let profile = profiles.for_network(Some("lab-net"));
+    assert!(profile.strict);
+    let psk = profile.key.clone().expect("psk configured");
+    assert_eq!(psk.id, "lab-2024");
+    assert!(!format!("{psk:?}").contains("0001"));
+
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let (ch, client_secret) = create_client_hello("client-1", &client);
+    let (sh, server_secret) = create_server_hello("server-1", &server, &ch);
+
+    let client_binder = client_psk_binder(&psk, &ch);
+    let accepted = accept_client_psk(&profile, &ch, Some(&client_binder)).expect("server side");
+    assert_eq!(accepted, Some(&psk));
+    let server_binder = server_psk_binder(&psk, &ch, &sh);
+    accept_server_psk(&profile, &ch, &sh, Some(&server_binder)).expect("client side");
+
+    let transcript = handshake_transcript(&ch, &sh);
+    let derive = |is_client| {
+        let secret = if is_client {
+            &client_secret
+        } else {
+            &server_secret
+        };
+        derive_session_keys(secret, &ch, &sh, is_client).expect("keys")
+    };
+    let client_keys = mix_psk_into_keys(&derive(true), &psk, &transcript);
+    let server_keys = mix_psk_into_keys(&derive(false), &psk, &transcript);
+    assert_eq!(client_keys.tx_key, server_keys.rx_key);
+    assert_ne!(client_keys, derive(true));
+    let tag = key_confirmation_tag(&client_keys, &transcript);
+    verify_key_confirmation(&server_keys, &transcript, &tag).expect("confirm");
+
+    // A peer holding a different secret under the same id cannot forge the binder.
+    let wrong = PreSharedKey::new("lab-2024", &[7u8; 32]).expect("psk");
+    assert!(matches!(
+        accept_client_psk(&profile, &ch, Some(&client_psk_binder(&wrong, &ch))),
+        Err(HandshakeError::PskBinderMismatch)
+    ));
+    // A binder is tied to the hello it was computed for.
+    let (other, _) = create_client_hello("client-1", &client);
+    assert!(matches!(
+        accept_client_psk(&profile, &other, Some(&client_binder)),
+        Err(HandshakeError::PskBinderMismatch)
+    ));
+}
+
+#[test]
+fn strict_psk_network_refuses_peers_without_psk() {
+    let psk = PreSharedKey::new("site", &[3u8; 16]).expect("psk");
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let (ch, _) = create_client_hello("client-1", &client);
+    let (sh, _) = create_server_hello("server-1", &server, &ch);
+
+    let strict = PskProfile {
+        key: Some(psk.clone()),
+        strict: true,
+    };
+    assert!(matches!(
+        accept_client_psk(&strict, &ch, None),
+        Err(HandshakeError::PskRequired)
+    ));
+    assert!(matches!(
+        accept_server_psk(&strict, &ch, &sh, None),
+        Err(HandshakeError::PskRequired)
+    ));
+
+    // Non-strict networks fall back to the identity handshake.
+    let relaxed = PskProfile {
+        key: Some(psk),
+        strict: false,
+    };
+    assert_eq!(
+        accept_client_psk(&relaxed, &ch, None).expect("fallback"),
+        None
+    );
+    assert_eq!(
+        PskProfiles::default().for_network(Some("cafe")),
+        PskProfile::default()
+    );
+
+    assert!(matches!(
+        PreSharedKey::new("short", &[1u8; 8]),
+        Err(HandshakeError::InvalidPsk(_))
+    ));
+    assert!(matches!(
+        PskProfiles::default().apply_settings("psk.x.secret = zz00"),
+        Err(HandshakeError::InvalidPsk(_))
+    ));
+}
+
+#[test]
+fn replay_guard_blocks_reused_nonce() {
+    let mut guard = ReplayGuard::new(Duration::from_secs(10));
+    let nonce = [7u8; 32];
+    let now = Instant::now();
+
+    guard.check_and_remember(nonce, now).expect("fresh nonce");
+    assert!(matches!(
+        guard.check_and_remember(nonce, now + Duration::from_secs(1)),
+        Err(HandshakeError::ReplayedNonce)
+    ));
+    guard
+        .check_and_remember(nonce, now + Duration::from_secs(11))
+        .expect("fresh nonce");
+}
+
+#[test]
+fn per_peer_policy_resolves_toward_stricter_requirement() {
+    let own = DeviceIdentity::generate();
+    let mut store = TrustStore::new(EncryptionMode::Optional, EncryptionMode::Required);
+    store
+        .trust(TrustedPeer::verified("laptop", &own.public_key_b64()))
+        .expect("valid peer");
+    let local = HandshakeCapabilities {
+        supports_encryption: true,
+        preferred_encryption_mode: EncryptionMode::Off,
+        ..HandshakeCapabilities::default()
+    };
+    let plaintext_peer = HandshakeCapabilities::default();
+
+    // Own device: Optional policy lets a non-encrypting peer fall back to plaintext.
+    let negotiated = store
+        .negotiate_for_peer(local, plaintext_peer, "laptop", &own.public_key_b64())
+        .expect("optional policy falls back");
+    assert_eq!(negotiated.mode, EncryptionMode::Off);
+    assert!(store
+        .check_frame("laptop", &own.public_key_b64(), negotiated, false)
+        .is_ok());
+
+    // Unknown peer, or a known id presenting another key, gets the Required policy.
+    let stranger = DeviceIdentity::generate();
+    for (id, key) in [
+        ("stranger", stranger.public_key_b64()),
+        ("laptop", stranger.public_key_b64()),
+    ] {
+        let err = store
+            .negotiate_for_peer(local, plaintext_peer, id, &key)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            HandshakeError::EncryptionRequiredButUnsupported
+        ));
+        let off = negotiate_encryption(plaintext_peer, plaintext_peer).unwrap();
+        assert!(matches!(
+            store.check_frame(id, &key, off, false),
+            Err(HandshakeError::PlaintextFrameRejected)
+        ));
+        assert!(store.check_frame(id, &key, off, true).is_ok());
+    }
+
+    // A looser per-peer override never weakens a stricter global preference.
+    store
+        .set_encryption_policy("laptop", Some(EncryptionMode::Off))
+        .unwrap();
+    let strict_local = HandshakeCapabilities {
+        supports_encryption: true,
+        preferred_encryption_mode: EncryptionMode::Required,
+        ..HandshakeCapabilities::default()
+    };
+    let caps = store
+        .capabilities_for(strict_local, "laptop", &own.public_key_b64())
+        .unwrap();
+    assert_eq!(caps.preferred_encryption_mode, EncryptionMode::Required);
+    assert!(matches!(
+        store.set_encryption_policy("stranger", Some(EncryptionMode::Off)),
+        Err(HandshakeError::PeerNotTrusted)
+    ));
+}
+
+#[test]
+fn compression_negotiates_best_shared_codec_and_is_signed() {
+    let caps = |zstd, lz4| HandshakeCapabilities {
+        compression: CompressionSupport { zstd, lz4 },
+        ..HandshakeCapabilities::default()
+    };
+    assert_eq!(
+        negotiate_compression(caps(true, true), caps(true, false)),
+        NegotiatedCompression::Zstd
+    );
+    assert_eq!(
+        negotiate_compression(caps(true, true), caps(false, true)),
+        NegotiatedCompression::Lz4
+    );
+    assert_eq!(
+        negotiate_compression(caps(true, false), caps(false, true)),
+        NegotiatedCompression::None
+    );
+    assert_eq!(NegotiatedCompression::Lz4.as_str(), "lz4");
+
+    let client = DeviceIdentity::generate();
+    let (mut hello, _) =
+        create_client_hello_with_capabilities("client-1", &client, caps(true, true));
+    verify_client_hello(&hello, 30, hello.timestamp_secs).expect("valid");
+    hello.capabilities.compression.zstd = false;
+    let err = verify_client_hello(&hello, 30, hello.timestamp_secs).expect_err("tamper fails");
+    assert!(matches!(err, HandshakeError::InvalidSignature));
+}
+
+#[test]
+fn session_keys_need_an_ephemeral_secret_and_reject_low_order_shares() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let (ch, client_secret) = create_client_hello("client-1", &client);
+    let (sh, server_secret) = create_server_hello("server-1", &server, &ch);
+    assert_ne!(ch.ephemeral_public, sh.ephemeral_public);
+    assert_eq!(ch.ephemeral_public, client_secret.public_bytes());
+
+    let client_keys = derive_session_keys(&client_secret, &ch, &sh, true).expect("client keys");
+    let server_keys = derive_session_keys(&server_secret, &ch, &sh, false).expect("server keys");
+    assert_eq!(client_keys.tx_key, server_keys.rx_key);
+
+    // An observer holding only the public values (with its own secret) gets other keys.
+    let observer = derive_session_keys(&EphemeralKey::generate(), &ch, &sh, true).expect("keys");
+    assert_ne!(observer, client_keys);
+    assert!(!format!("{client_secret:?}").contains("secret:"));
+
+    // Swapping the share invalidates the hello signature...
+    let mut forged = sh.clone();
+    forged.ephemeral_public = EphemeralKey::generate().public_bytes();
+    assert!(matches!(
+        verify_server_hello(&ch, &forged, 30, forged.timestamp_secs),
+        Err(HandshakeError::InvalidSignature)
+    ));
+    // ...and a low-order share is refused outright.
+    forged.ephemeral_public = [0u8; 32];
+    assert!(matches!(
+        derive_session_keys(&client_secret, &ch, &forged, true),
+        Err(HandshakeError::InvalidKeyShare)
+    ));
+}
+
+#[test]
+fn key_schedule_separates_keys_by_label() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let (ch, client_secret) = create_client_hello("client-1", &client);
+    let (sh, server_secret) = create_server_hello("server-1", &server, &ch);
+
+    let client_schedule = derive_key_schedule(&client_secret, &ch, &sh, true).expect("schedule");
+    let server_schedule = derive_key_schedule(&server_secret, &ch, &sh, false).expect("schedule");
+    assert_eq!(
+        client_schedule.session_keys(true),
+        derive_session_keys(&client_secret, &ch, &sh, true).expect("keys")
+    );
+    assert_eq!(
+        client_schedule.rekey_secret(),
+        server_schedule.rekey_secret()
+    );
+
+    let keys = client_schedule.session_keys(true);
+    let rekey = client_schedule.rekey_secret();
+    assert_eq!(keys.tx_key, client_schedule.expand(LABEL_C2S));
+    assert_eq!(keys.rx_key, client_schedule.expand(LABEL_S2C));
+    let secret = keys.to_secret();
+    assert_eq!(
+        (secret.tx_key(), secret.rx_key()),
+        (&keys.tx_key, &keys.rx_key)
+    );
+    assert_eq!(format!("{secret:?}"), "SessionKeySecret(..)");
+    assert_ne!(rekey, keys.tx_key);
+    assert_ne!(rekey, keys.rx_key);
+    assert_ne!(client_schedule.expand(b"p2p/hkdf/v1/other"), rekey);
+
+    // The same secret under another transcript gives an unrelated schedule.
+    let secret = [7u8; 32];
+    assert_ne!(
+        KeySchedule::new(&secret, &[1; 32]).expand(LABEL_C2S),
+        KeySchedule::new(&secret, &[2; 32]).expand(LABEL_C2S)
+    );
+    assert_eq!(format!("{client_schedule:?}"), "KeySchedule(..)");
+}
+
+#[test]
+fn hellos_roundtrip_through_the_wire_encoding() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let caps = HandshakeCapabilities {
+        supports_encryption: true,
+        preferred_encryption_mode: EncryptionMode::Required,
+        compression: CompressionSupport {
+            zstd: true,
+            lz4: false,
+        },
+    };
+    let (ch, _) = create_client_hello_with_capabilities("client-é", &client, caps);
+    let (sh, _) = create_server_hello_with_capabilities("server-1", &server, &ch, caps);
+
+    let ch_bytes = ch.encode().expect("encode");
+    let decoded = ClientHello::decode(&ch_bytes).expect("decode");
+    assert_eq!(decoded, ch);
+    verify_client_hello(&decoded, 30, decoded.timestamp_secs).expect("still verifies");
+    let decoded = ServerHello::decode(&sh.encode().expect("encode")).expect("decode");
+    assert_eq!(decoded, sh);
+    verify_server_hello(&ch, &decoded, 30, decoded.timestamp_secs).expect("verifies");
+
+    assert!(matches!(
+        ServerHello::decode(&ch_bytes),
+        Err(HandshakeError::InvalidMessage(
+            "unexpected handshake message kind"
+        ))
+    ));
+    assert!(matches!(
+        ClientHello::decode(&ch_bytes[..ch_bytes.len() - 1]),
+        Err(HandshakeError::InvalidMessage(
+            "truncated handshake message"
+        ))
+    ));
+    let mut trailing = ch_bytes.clone();
+    trailing.push(0);
+    assert!(matches!(
+        ClientHello::decode(&trailing),
+        Err(HandshakeError::InvalidMessage(
+            "trailing bytes after handshake message"
+        ))
+    ));
+    let mut newer = ch_bytes.clone();
+    newer[4] = 9;
+    assert!(matches!(
+        ClientHello::decode(&newer),
+        Err(HandshakeError::InvalidMessage(
+            "unsupported handshake message version"
+        ))
+    ));
+    // Capability bytes sit right before the (here empty) extensions and the signature.
+    let mut bad_mode = ch_bytes;
+    let mode = bad_mode.len() - 64 - 2 - 2;
+    bad_mode[mode] = 7;
+    assert!(matches!(
+        ClientHello::decode(&bad_mode),
+        Err(HandshakeError::InvalidCapabilities)
+    ));
+}
+
+#[test]
+fn finished_messages_confirm_keys_over_the_transcript() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let (ch, client_secret) = create_client_hello("client-1", &client);
+    let (sh, server_secret) = create_server_hello("server-1", &server, &ch);
+    let client_keys = derive_session_keys(&client_secret, &ch, &sh, true).expect("client keys");
+    let server_keys = derive_session_keys(&server_secret, &ch, &sh, false).expect("server keys");
+
+    let client_finished = ClientFinished::new(&client_keys, &ch, &sh);
+    let received =
+        ClientFinished::decode(&client_finished.encode().expect("encode")).expect("decode");
+    received
+        .verify(&server_keys, &ch, &sh)
+        .expect("server accepts");
+    let server_finished = ServerFinished::new(&server_keys, &ch, &sh, &received);
+    let received =
+        ServerFinished::decode(&server_finished.encode().expect("encode")).expect("decode");
+    received
+        .verify(&client_keys, &ch, &sh, &client_finished)
+        .expect("client accepts");
+
+    // Wrong keys are caught at the Finished exchange.
+    let skewed =
+        derive_session_keys(&EphemeralKey::generate(), &ch, &sh, false).expect("other keys");
+    assert!(matches!(
+        client_finished.verify(&skewed, &ch, &sh),
+        Err(HandshakeError::KeyConfirmationFailed)
+    ));
+    // A client Finished cannot be reflected back as the server's.
+    let reflected = ServerFinished {
+        verify_data: client_finished.verify_data,
+        psk_binder: None,
+    };
+    assert!(matches!(
+        reflected.verify(&client_keys, &ch, &sh, &client_finished),
+        Err(HandshakeError::KeyConfirmationFailed)
+    ));
+    assert!(matches!(
+        ServerFinished::decode(&client_finished.encode().expect("encode")),
+        Err(HandshakeError::InvalidMessage(
+            "unexpected handshake message kind"
+        ))
+    ));
+}
+
+#[test]
+fn key_rotation_moves_both_sides_to_the_same_new_epoch() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let (ch, client_secret) = create_client_hello("client-1", &client);
+    let (sh, server_secret) = create_server_hello("server-1", &server, &ch);
+    let start = Instant::now();
+    let mut sender = KeyRotation::new(
+        derive_key_schedule(&client_secret, &ch, &sh, true).expect("client schedule"),
         true,
+        start,
+    );
+    let mut receiver = KeyRotation::new(
+        derive_key_schedule(&server_secret, &ch, &sh, false).expect("server schedule"),
+        false,
+        start,
     );
+    let initial = sender.keys().clone();
 
-    let server_keys = derive_session_keys(
-        &ch.public_key_b64,
-        &sh.public_key_b64,
-        ch.nonce,
-        sh.server_nonce,
+    let policy = RekeyPolicy {
+        max_chunks: 2,
+        max_age: Duration::from_secs(60),
+    };
+    sender.record_chunk();
+    assert!(!sender.is_due(&policy, start));
+    sender.record_chunk();
+    assert!(sender.is_due(&policy, start));
+    assert!(receiver.is_due(&policy, start + Duration::from_secs(60)));
+
+    let rekey = sender.start_rekey(start).expect("rekey");
+    assert_eq!(rekey.epoch, 1);
+    assert!(!sender.is_due(&policy, start));
+    receiver
+        .apply_rekey(&Rekey::decode(&rekey.encode()).expect("decode"), start)
+        .expect("apply");
+    assert_eq!(receiver.epoch(), 1);
+    assert_eq!(sender.epoch_aad(), receiver.epoch_aad());
+    assert_eq!(sender.keys().tx_key, receiver.keys().rx_key);
+    assert_eq!(sender.keys().rx_key, receiver.keys().tx_key);
+    assert_ne!(sender.keys(), &initial);
+
+    // Replays, skipped epochs and forged tags are refused.
+    assert!(matches!(
+        receiver.apply_rekey(&rekey, start),
+        Err(HandshakeError::RekeyRejected(_))
+    ));
+    let mut forged = sender.start_rekey(start).expect("rekey");
+    forged.tag[0] ^= 1;
+    assert!(matches!(
+        receiver.apply_rekey(&forged, start),
+        Err(HandshakeError::RekeyRejected("tag did not verify"))
+    ));
+    assert_eq!(receiver.epoch(), 1);
+}
+
+#[test]
+fn key_rotation_keeps_the_old_receive_key_briefly_and_settles_crossed_rekeys() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let (ch, client_secret) = create_client_hello("client-1", &client);
+    let (sh, server_secret) = create_server_hello("server-1", &server, &ch);
+    let start = Instant::now();
+    let grace = Duration::from_secs(5);
+    let mut alice = KeyRotation::new(
+        derive_key_schedule(&client_secret, &ch, &sh, true).expect("client schedule"),
+        true,
+        start,
+    )
+    .with_grace(grace);
+    let mut bob = KeyRotation::new(
+        derive_key_schedule(&server_secret, &ch, &sh, false).expect("server schedule"),
         false,
+        start,
+    )
+    .with_grace(grace);
+
+    // Bob sealed a frame under epoch 0 just as Alice rotated: it still opens, briefly.
+    let in_flight_aad = bob.epoch_aad();
+    let in_flight_key = bob.keys().tx_key;
+    let rekey = alice.start_rekey(start).expect("rekey");
+    assert_eq!(
+        alice
+            .rx_key_for(in_flight_aad, start + Duration::from_secs(1))
+            .expect("within grace"),
+        &in_flight_key
+    );
+    assert!(matches!(
+        alice.rx_key_for(in_flight_aad, start + grace),
+        Err(HandshakeError::StaleKeyEpoch(0))
+    ));
+    assert!(alice.rx_key_for(2u32.to_be_bytes(), start).is_err());
+    bob.apply_rekey(&rekey, start).expect("apply");
+    assert_eq!(
+        alice.rx_key_for(bob.epoch_aad(), start).expect("current"),
+        &bob.keys().tx_key
     );
 
+    // Both rotate at once: each gets a rekey for the epoch it is already on and accepts
+    // it without moving again, so the two stay in step.
+    let from_alice = alice.start_rekey(start).expect("alice rekey");
+    let from_bob = bob.start_rekey(start).expect("bob rekey");
+    alice.apply_rekey(&from_bob, start).expect("crossed");
+    bob.apply_rekey(&from_alice, start).expect("crossed");
+    assert_eq!((alice.epoch(), bob.epoch()), (2, 2));
+    assert_eq!(alice.keys().tx_key, bob.keys().rx_key);
+    // The crossed message is only accepted once.
+    assert!(matches!(
+        alice.apply_rekey(&from_bob, start),
+        Err(HandshakeError::RekeyRejected(_))
+    ));
+}
+
+#[test]
+fn pairing_code_authenticates_unknown_peers_and_mixes_into_keys() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let code = PairingCode::new("482-913").expect("code");
+    assert_eq!(code, PairingCode::new("482913").expect("code"));
+    assert!(!format!("{code:?}").contains("482"));
+    assert!(matches!(
+        PairingCode::new("12-34"),
+        Err(HandshakeError::InvalidPairingCode(_))
+    ));
+
+    let (ch, client_secret) = create_client_hello("client-1", &client);
+    let (sh, server_secret) = create_server_hello("server-1", &server, &ch);
+    let (client_pairing, client_share) = PairingState::start(&code, true);
+    let (server_pairing, server_share) = PairingState::start(&code, false);
+    let client_key = client_pairing
+        .finish(&server_share, &ch, &sh)
+        .expect("client pairing");
+    let server_key = server_pairing
+        .finish(&client_share, &ch, &sh)
+        .expect("server pairing");
+    server_key
+        .verify_peer(&client_key.confirmation())
+        .expect("server confirms");
+    client_key
+        .verify_peer(&server_key.confirmation())
+        .expect("client confirms");
+    // A tag cannot be reflected back to its sender.
+    assert!(client_key.verify_peer(&client_key.confirmation()).is_err());
+
+    let client_keys = client_key
+        .mix_into_keys(&derive_session_keys(&client_secret, &ch, &sh, true).expect("client keys"));
+    let server_keys = server_key
+        .mix_into_keys(&derive_session_keys(&server_secret, &ch, &sh, false).expect("server keys"));
     assert_eq!(client_keys.tx_key, server_keys.rx_key);
     assert_eq!(client_keys.rx_key, server_keys.tx_key);
-    assert_ne!(client_keys.tx_key, client_keys.rx_key);
+
+    // A peer that typed a different code fails confirmation.
+    let (wrong, wrong_share) =
+        PairingState::start(&PairingCode::new("482914").expect("code"), false);
+    let wrong_key = wrong.finish(&client_share, &ch, &sh).expect("finish");
+    let (client_pairing, _) = PairingState::start(&code, true);
+    let client_key = client_pairing
+        .finish(&wrong_share, &ch, &sh)
+        .expect("finish");
+    assert!(matches!(
+        client_key.verify_peer(&wrong_key.confirmation()),
+        Err(HandshakeError::PairingFailed)
+    ));
+    // The identity point is not a valid share.
+    let (client_pairing, _) = PairingState::start(&code, true);
+    assert!(matches!(
+        client_pairing.finish(&PairingShare { share: [0u8; 32] }, &ch, &sh),
+        Err(HandshakeError::PairingFailed)
+    ));
 }
 
 #[test]
-fn replay_guard_blocks_reused_nonce() {
-    let mut guard = ReplayGuard::new(Duration::from_secs(10));
-    let nonce = [7u8; 32];
+fn server_hello_signs_the_client_hello_it_answered() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let caps = HandshakeCapabilities {
+        supports_encryption: true,
+        preferred_encryption_mode: EncryptionMode::Optional,
+        ..HandshakeCapabilities::default()
+    };
+    let (ch, _) = create_client_hello_with_capabilities("client-1", &client, caps);
+
+    // An attacker strips the encryption capability on the way to the server.
+    let mut stripped = ch.clone();
+    stripped.capabilities.supports_encryption = false;
+    stripped.capabilities.preferred_encryption_mode = EncryptionMode::Off;
+    let (sh, _) = create_server_hello_with_capabilities("server-1", &server, &stripped, caps);
+    assert!(matches!(
+        verify_server_hello(&ch, &sh, 30, sh.timestamp_secs),
+        Err(HandshakeError::TranscriptMismatch)
+    ));
+    assert_ne!(
+        handshake_transcript(&ch, &sh),
+        handshake_transcript(&stripped, &sh)
+    );
+
+    // The running hash matches what the server signed, and grows with later messages.
+    let (sh, _) = create_server_hello_with_capabilities("server-1", &server, &ch, caps);
+    let mut transcript = TranscriptHash::after_client_hello(&ch);
+    assert_eq!(transcript.current(), sh.client_hello_hash);
+    transcript.add_server_hello(&sh);
+    assert_eq!(transcript.current(), handshake_transcript(&ch, &sh));
+    transcript.add_message(b"finished");
+    assert_ne!(transcript.current(), handshake_transcript(&ch, &sh));
+}
+
+#[test]
+fn hello_extensions_are_signed_and_unknown_types_are_carried() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let mut client_ext = HelloExtensions::new()
+        .with_resumable_transfer()
+        .with_max_chunk_size(256 * 1024);
+    // A feature this build knows nothing about still travels and verifies.
+    client_ext
+        .insert(0x7f00, b"future".to_vec())
+        .expect("insert");
+    let (ch, _) = create_client_hello_with_extensions(
+        "client-1",
+        &client,
+        HandshakeCapabilities::default(),
+        client_ext.clone(),
+    );
+    let decoded = ClientHello::decode(&ch.encode().expect("encode")).expect("decode");
+    assert_eq!(decoded.extensions, client_ext);
+    assert_eq!(decoded.extensions.get(0x7f00), Some(&b"future"[..]));
+    verify_client_hello(&decoded, 30, decoded.timestamp_secs).expect("verifies");
+
+    let (sh, _) = create_server_hello_with_extensions(
+        "server-1",
+        &server,
+        &ch,
+        HandshakeCapabilities::default(),
+        HelloExtensions::new().with_max_chunk_size(64 * 1024),
+    );
+    verify_server_hello(&ch, &sh, 30, sh.timestamp_secs).expect("verifies");
+    assert!(!sh.extensions.supports_resumable_transfer());
+    assert_eq!(
+        negotiate_max_chunk_size(&ch.extensions, &sh.extensions).expect("negotiate"),
+        Some(64 * 1024)
+    );
+    assert_eq!(
+        negotiate_max_chunk_size(&ch.extensions, &HelloExtensions::new()).expect("negotiate"),
+        Some(256 * 1024)
+    );
+
+    // Dropping an extension in flight breaks the signature.
+    let mut stripped = ch.clone();
+    stripped.extensions = HelloExtensions::new().with_max_chunk_size(256 * 1024);
+    assert!(matches!(
+        verify_client_hello(&stripped, 30, stripped.timestamp_secs),
+        Err(HandshakeError::InvalidSignature)
+    ));
+    let mut malformed = HelloExtensions::new();
+    malformed
+        .insert(EXT_MAX_CHUNK_SIZE, vec![1, 2])
+        .expect("insert");
+    assert!(matches!(
+        malformed.max_chunk_size(),
+        Err(HandshakeError::InvalidCapabilities)
+    ));
+}
+
+#[test]
+fn state_machines_run_a_full_handshake_and_fail_closed() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let caps = HandshakeCapabilities {
+        supports_encryption: true,
+        preferred_encryption_mode: EncryptionMode::Required,
+        compression: CompressionSupport {
+            zstd: true,
+            lz4: true,
+        },
+    };
+    let mut client_config = HandshakeConfig::new("client-1");
+    client_config.capabilities = caps;
+    let mut server_config = HandshakeConfig::new("server-1");
+    server_config.capabilities = caps;
+    server_config.extensions = HelloExtensions::new().with_resumable_transfer();
+    let mut guard = ReplayGuard::new(Duration::from_secs(60));
+    let now = Instant::now();
+
+    let (mut initiator, ch) = HandshakeInitiator::start(&client, client_config.clone());
+    let mut responder = HandshakeResponder::new(&server, server_config.clone());
+    let sh = responder
+        .on_client_hello(&ch, &mut guard, now)
+        .expect("server hello");
+    assert_eq!(responder.state(), ResponderState::AwaitingClientFinished);
+    let client_finished = initiator.on_server_hello(&sh).expect("client finished");
+    assert_eq!(initiator.state(), InitiatorState::AwaitingServerFinished);
+    let (server_finished, server_outcome) = responder
+        .on_client_finished(&client_finished)
+        .expect("server finished");
+    let client_outcome = initiator
+        .on_server_finished(&server_finished)
+        .expect("complete");
+    assert_eq!(initiator.state(), InitiatorState::Complete);
+    assert_eq!(responder.state(), ResponderState::Complete);
+
+    assert_eq!(client_outcome.keys.tx_key, server_outcome.keys.rx_key);
+    assert_eq!(client_outcome.keys.rx_key, server_outcome.keys.tx_key);
+    assert_eq!(client_outcome.transcript, server_outcome.transcript);
+    assert_eq!(client_outcome.sas, server_outcome.sas);
+    assert!(client_outcome.encryption.enabled);
+    assert_eq!(client_outcome.compression, NegotiatedCompression::Zstd);
+    assert_eq!(client_outcome.peer_device_id, "server-1");
+    assert_eq!(server_outcome.peer_public_key_b64, client.public_key_b64());
+    assert!(client_outcome.peer_extensions.supports_resumable_transfer());
+
+    // The same hello on another connection is a replay.
+    let mut second = HandshakeResponder::new(&server, server_config.clone());
+    assert!(matches!(
+        second.on_client_hello(&ch, &mut guard, now),
+        Err(HandshakeError::ReplayedNonce)
+    ));
+    assert_eq!(second.state(), ResponderState::Failed);
+
+    // Messages out of order fail the machine for good.
+    let (mut initiator, _) = HandshakeInitiator::start(&client, client_config);
+    assert!(matches!(
+        initiator.on_server_finished(&server_finished),
+        Err(HandshakeError::UnexpectedMessage(_))
+    ));
+    assert_eq!(initiator.state(), InitiatorState::Failed);
+    assert!(initiator.on_server_hello(&sh).is_err());
+}
+
+#[test]
+fn state_machines_apply_pins_policy_and_psk() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
     let now = Instant::now();
+    let run = |client_config: HandshakeConfig, server_config: HandshakeConfig| {
+        let mut guard = ReplayGuard::new(Duration::from_secs(60));
+        let (mut initiator, ch) = HandshakeInitiator::start(&client, client_config);
+        let mut responder = HandshakeResponder::new(&server, server_config);
+        let sh = responder.on_client_hello(&ch, &mut guard, now)?;
+        let client_finished = initiator.on_server_hello(&sh)?;
+        let (server_finished, server_outcome) = responder.on_client_finished(&client_finished)?;
+        let client_outcome = initiator.on_server_finished(&server_finished)?;
+        Ok::<_, HandshakeError>((client_outcome, server_outcome))
+    };
+
+    // The first contact pins the client's key; the next handshake finds it known.
+    let pins = Arc::new(Mutex::new(TrustStore::default()));
+    let server_config = HandshakeConfig::new("server-1").with_trust_store(pins.clone());
+    let (_, outcome) = run(HandshakeConfig::new("client-1"), server_config.clone()).expect("first");
+    assert_eq!(outcome.pin, Some(PinCheck::FirstSeen));
+    let (_, outcome) = run(HandshakeConfig::new("client-1"), server_config.clone()).expect("again");
+    assert_eq!(outcome.pin, Some(PinCheck::Known(PeerTrustState::FirstUse)));
+
+    // A policy that requires encryption refuses a peer that cannot provide it.
+    pins.lock()
+        .expect("pins")
+        .set_encryption_policy("client-1", Some(EncryptionMode::Required))
+        .expect("policy");
+    let mut plaintext = HandshakeConfig::new("client-1");
+    plaintext.capabilities.supports_encryption = false;
+    assert!(matches!(
+        run(plaintext, server_config.clone()),
+        Err(HandshakeError::EncryptionRequiredButUnsupported)
+    ));
+
+    // Another device claiming the pinned id, or a blocked one, is refused.
+    let impostor = DeviceIdentity::generate();
+    let (ch, _) = create_client_hello("client-1", &impostor);
+    let mut responder = HandshakeResponder::new(&server, server_config.clone());
+    let mut guard = ReplayGuard::new(Duration::from_secs(60));
+    assert!(matches!(
+        responder.on_client_hello(&ch, &mut guard, now),
+        Err(HandshakeError::KeyChanged(_))
+    ));
+    pins.lock()
+        .expect("pins")
+        .set_state("client-1", PeerTrustState::Blocked)
+        .expect("block");
+    assert!(matches!(
+        run(HandshakeConfig::new("client-1"), server_config),
+        Err(HandshakeError::PeerBlocked)
+    ));
+
+    // A strict PSK network refuses a client without the key and mixes it in otherwise.
+    let psk = PreSharedKey::new("site", &[3u8; 16]).expect("psk");
+    let strict = PskProfile {
+        key: Some(psk),
+        strict: true,
+    };
+    let server_config = HandshakeConfig::new("server-1").with_psk(strict.clone());
+    assert!(matches!(
+        run(HandshakeConfig::new("client-1"), server_config.clone()),
+        Err(HandshakeError::PskRequired)
+    ));
+    let (plain_client, _) = run(
+        HandshakeConfig::new("client-1"),
+        HandshakeConfig::new("server-1"),
+    )
+    .expect("plain");
+    let (client_outcome, server_outcome) = run(
+        HandshakeConfig::new("client-1").with_psk(strict),
+        server_config,
+    )
+    .expect("psk");
+    assert!(client_outcome.psk_used && server_outcome.psk_used);
+    assert!(!plain_client.psk_used);
+    assert_eq!(client_outcome.keys.tx_key, server_outcome.keys.rx_key);
+    assert_eq!(client_outcome.keys.rx_key, server_outcome.keys.tx_key);
+}
+
+#[test]
+fn pinned_verification_trusts_first_key_and_flags_changes() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let mut pins = TrustStore::default();
+    let (ch, _) = create_client_hello("laptop", &client);
+    let now = ch.timestamp_secs;
+    assert_eq!(
+        verify_client_hello_pinned(&ch, &mut pins, 30, now).expect("first contact"),
+        PinCheck::FirstSeen
+    );
+    let (ch, _) = create_client_hello("laptop", &client);
+    assert_eq!(
+        verify_client_hello_pinned(&ch, &mut pins, 30, now).expect("same key"),
+        PinCheck::Known(PeerTrustState::FirstUse)
+    );
+
+    // Same device id, new key: refused with the details the user needs to decide.
+    let impostor = DeviceIdentity::generate();
+    let (forged, _) = create_client_hello("laptop", &impostor);
+    let Err(HandshakeError::KeyChanged(changed)) =
+        verify_client_hello_pinned(&forged, &mut pins, 30, now)
+    else {
+        panic!("key change not reported");
+    };
+    assert_eq!(changed.pinned_public_key_b64, client.public_key_b64());
+    assert_eq!(changed.presented_public_key_b64, impostor.public_key_b64());
+    assert_eq!(
+        pins.get("laptop").expect("pinned").public_key_b64,
+        client.public_key_b64()
+    );
+    pins.replace_key(&changed, now).expect("valid key");
+    assert!(verify_client_hello_pinned(&forged, &mut pins, 30, now).is_ok());
+
+    // The client side pins servers the same way, and blocked peers fail.
+    let (ch, _) = create_client_hello("laptop", &client);
+    let (sh, _) = create_server_hello("desk", &server, &ch);
+    verify_server_hello_pinned(&ch, &sh, &mut pins, 30, now).expect("first contact");
+    pins.set_state("desk", PeerTrustState::Blocked)
+        .expect("known");
+    assert!(matches!(
+        verify_server_hello_pinned(&ch, &sh, &mut pins, 30, now),
+        Err(HandshakeError::PeerBlocked)
+    ));
+
+    let decoded = TrustStore::decode(&pins.encode()).expect("decode");
+    assert_eq!(decoded, pins);
+    assert!(matches!(
+        TrustStore::decode("peer\tdesk\tkey\tdefault\ttrusted\t0\n"),
+        Err(HandshakeError::InvalidTrustStore(_))
+    ));
+}
+
+#[test]
+fn pins_refuse_control_characters_and_duplicate_ids() {
+    let mut pins = TrustStore::default();
+    // A tab and newline in the id would forge a verified line for another device.
+    let forged = "x\tkey\tdefault\tfirst-use\t0\npeer\tvictim";
+    assert!(matches!(
+        pins.check(forged, "attacker-key", 0),
+        Err(HandshakeError::InvalidPeerId(_))
+    ));
+    assert!(matches!(
+        pins.pin("desk", "key\r", PeerTrustState::Verified, 0),
+        Err(HandshakeError::InvalidPeerId(_))
+    ));
+    assert!(pins.peers().is_empty());
+
+    pins.check("desk", "key", 0).expect("first contact");
+    let mut text = pins.encode();
+    text.push_str("peer\tdesk\tother-key\tdefault\tverified\t0\n");
+    assert!(matches!(
+        TrustStore::decode(&text),
+        Err(HandshakeError::InvalidTrustStore("duplicate device id"))
+    ));
+    // Files written before pins moved into the store still load, as verified peers.
+    let legacy = TrustStore::decode("peer\tdesk\tkey\tdefault\n").expect("legacy line");
+    assert_eq!(
+        legacy.get("desk").expect("peer").state,
+        PeerTrustState::Verified
+    );
+}
+
+#[test]
+fn replay_guard_survives_a_restart_and_compacts_expired_nonces() {
+    let ttl = Duration::from_secs(60);
+    let start = Instant::now();
+    let start_unix = 1_700_000_000;
+    let mut guard = ReplayGuard::new(ttl);
+    guard
+        .check_and_remember([1u8; 32], start)
+        .expect("fresh nonce");
+    guard
+        .check_and_remember([2u8; 32], start + Duration::from_secs(50))
+        .expect("fresh nonce");
+
+    let path = std::env::temp_dir().join(format!("replay-{}.txt", std::process::id()));
+    let later = start + Duration::from_secs(70);
+    guard.save(&path, later, start_unix + 70).expect("save");
+    // The first nonce had expired and was compacted away.
+    assert_eq!(guard.len(), 1);
+
+    // After a restart the remaining nonce is still refused until its ttl runs out.
+    let restarted = Instant::now();
+    let mut reloaded = ReplayGuard::load(&path, ttl, restarted, start_unix + 75).expect("load");
+    std::fs::remove_file(&path).expect("cleanup");
+    assert_eq!(reloaded.len(), 1);
+    assert!(matches!(
+        reloaded.check_and_remember([2u8; 32], restarted),
+        Err(HandshakeError::ReplayedNonce)
+    ));
+    reloaded
+        .check_and_remember([2u8; 32], restarted + Duration::from_secs(36))
+        .expect("expired nonce");
+
+    assert!(ReplayGuard::load(&path, ttl, restarted, start_unix)
+        .expect("missing file")
+        .is_empty());
+    assert!(matches!(
+        ReplayGuard::decode("zz\t1\n", ttl, restarted, start_unix),
+        Err(HandshakeError::InvalidReplayCache(_))
+    ));
+}
+
+#[test]
+fn replay_guard_is_bounded_and_counts_what_it_rejects() {
+    let now = Instant::now();
+    let mut guard = ReplayGuard::new(Duration::from_secs(60)).with_max_entries(2);
+    guard
+        .check_and_remember([1u8; 32], now)
+        .expect("fresh nonce");
+    guard
+        .check_and_remember([2u8; 32], now)
+        .expect("fresh nonce");
+    assert!(matches!(
+        guard.check_and_remember([2u8; 32], now),
+        Err(HandshakeError::ReplayedNonce)
+    ));
+    // A third nonce is refused while the first two are live; neither is forgotten.
+    assert!(matches!(
+        guard.check_and_remember([3u8; 32], now),
+        Err(HandshakeError::ReplayGuardFull)
+    ));
+    assert_eq!(guard.len(), 2);
+    assert!(matches!(
+        guard.check_and_remember([1u8; 32], now + Duration::from_secs(30)),
+        Err(HandshakeError::ReplayedNonce)
+    ));
+
+    // Expiry happens on insert without an explicit expire() call.
+    guard
+        .check_and_remember([4u8; 32], now + Duration::from_secs(61))
+        .expect("room once expired");
+    assert_eq!(guard.len(), 1);
+
+    let stats = guard.take_stats();
+    assert_eq!(stats.accepted, 3);
+    assert_eq!(stats.replays_rejected, 2);
+    assert_eq!(stats.rejected_full, 1);
+    assert_eq!(stats.expired, 2);
+    assert_eq!(stats.counters()[1], ("handshake.replay_rejected", 2));
+    assert_eq!(guard.stats(), Default::default());
+
+    // A saved cache larger than any guard holds is refused, not trimmed.
+    let oversized: String = (0..=DEFAULT_REPLAY_GUARD_ENTRIES as u32)
+        .map(|i| format!("{:064x}\t1000\n", i))
+        .collect();
+    assert!(matches!(
+        ReplayGuard::decode(&oversized, Duration::from_secs(60), now, 1_010),
+        Err(HandshakeError::InvalidReplayCache(_))
+    ));
+}
+
+#[test]
+fn short_authentication_string_matches_only_for_the_same_handshake() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let (ch, _) = create_client_hello("client-1", &client);
+    let (sh, _) = create_server_hello("server-1", &server, &ch);
+    let sas = ShortAuthString::from_transcript(&handshake_transcript(&ch, &sh));
+    assert_eq!(sas.digits.len(), 6);
+    assert!(sas.digits.chars().all(|c| c.is_ascii_digit()));
+    assert_eq!(sas.phrase().split(' ').count(), 4);
+    assert!(sas.to_string().starts_with(&sas.digits[..3]));
+
+    // A relayed handshake (different server hello) yields a different code.
+    let (relayed, _) = create_server_hello("server-1", &server, &ch);
+    assert_ne!(
+        ShortAuthString::from_transcript(&handshake_transcript(&ch, &relayed)),
+        sas
+    );
+    assert_eq!(
+        ShortAuthString::from_transcript(&[0u8; 32]),
+        ShortAuthString::from_transcript(&[0u8; 32])
+    );
+}
+
+#[test]
+fn state_machines_commit_to_the_sas_before_the_server_key_is_known() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let mut guard = ReplayGuard::new(Duration::from_secs(60));
+    let now = Instant::now();
+
+    let (mut initiator, ch) = HandshakeInitiator::start(&client, HandshakeConfig::new("client-1"));
+    let commitment = ch.extensions.get(EXT_SAS_COMMITMENT).expect("committed");
+    assert_eq!(commitment.len(), 32);
+    let mut responder = HandshakeResponder::new(&server, HandshakeConfig::new("server-1"));
+    let sh = responder
+        .on_client_hello(&ch, &mut guard, now)
+        .expect("server hello");
+    let client_finished = initiator.on_server_hello(&sh).expect("client finished");
+    let secret = client_finished.sas_secret.expect("revealed");
+    assert_eq!(commitment, sas_commitment(&ch.ephemeral_public, &secret));
+    verify_sas_reveal(&ch, &secret).expect("reveal matches");
+
+    // A reveal that does not open the commitment fails the handshake.
+    let mut other = HandshakeResponder::new(&server, HandshakeConfig::new("server-1"));
+    let (mut other_initiator, other_ch) =
+        HandshakeInitiator::start(&client, HandshakeConfig::new("client-1"));
+    let other_sh = other
+        .on_client_hello(&other_ch, &mut guard, now)
+        .expect("other server hello");
+    let mut other_finished = other_initiator
+        .on_server_hello(&other_sh)
+        .expect("other client finished");
+    other_finished.sas_secret = Some([0u8; 32]);
+    assert!(matches!(
+        other.on_client_finished(&other_finished),
+        Err(HandshakeError::SasCommitmentMismatch)
+    ));
+
+    let (server_finished, server_outcome) = responder
+        .on_client_finished(&client_finished)
+        .expect("server finished");
+    let client_outcome = initiator
+        .on_server_finished(&server_finished)
+        .expect("complete");
+    assert_eq!(client_outcome.sas, server_outcome.sas);
+    assert_eq!(
+        client_outcome.sas,
+        ShortAuthString::from_revealed(&client_outcome.transcript, &secret)
+    );
+    assert_ne!(
+        client_outcome.sas,
+        ShortAuthString::from_transcript(&client_outcome.transcript)
+    );
+
+    // A hello without a commitment is refused before the server picks its key.
+    let (uncommitted, _) = create_client_hello("client-1", &client);
+    let mut responder = HandshakeResponder::new(&server, HandshakeConfig::new("server-1"));
+    assert!(matches!(
+        responder.on_client_hello(&uncommitted, &mut guard, now),
+        Err(HandshakeError::SasCommitmentMismatch)
+    ));
+}
+
+#[test]
+fn handshake_attempt_backs_off_between_fresh_hellos_and_gives_up() {
+    let policy = RetryPolicy {
+        timeout: Duration::from_secs(2),
+        max_retries: 2,
+        initial_backoff: Duration::from_secs(1),
+        max_backoff: Duration::from_millis(1500),
+    };
+    let start = Instant::now();
+    let at = |secs: f64| start + Duration::from_secs_f64(secs);
+    let mut attempt = HandshakeAttempt::new(policy);
+
+    assert_eq!(attempt.poll(at(0.0)), AttemptAction::SendHello);
+    assert_eq!(
+        attempt.poll(at(0.5)),
+        AttemptAction::Wait(Duration::from_millis(1500))
+    );
+    // Timed out at 2s; backoff 1s before the retry.
+    assert_eq!(
+        attempt.poll(at(2.0)),
+        AttemptAction::Wait(Duration::from_secs(1))
+    );
+    assert_eq!(attempt.poll(at(3.0)), AttemptAction::SendHello);
+    // Second timeout at 5s; the doubled backoff is capped at 1.5s.
+    assert_eq!(
+        attempt.poll(at(5.0)),
+        AttemptAction::Wait(Duration::from_millis(1500))
+    );
+    assert_eq!(attempt.poll(at(6.5)), AttemptAction::SendHello);
+    assert_eq!(attempt.hellos_sent(), 3);
+    assert_eq!(attempt.poll(at(8.5)), AttemptAction::GiveUp);
+    assert_eq!(attempt.poll(at(100.0)), AttemptAction::GiveUp);
+
+    // Each resend is a new hello, so the responder's replay guard accepts it.
+    let client = DeviceIdentity::generate();
+    let mut guard = ReplayGuard::new(Duration::from_secs(60));
+    let mut attempt = HandshakeAttempt::new(RetryPolicy::default());
+    let mut now = start;
+    for _ in 0..2 {
+        let AttemptAction::SendHello = attempt.poll(now) else {
+            panic!("expected a send");
+        };
+        let (hello, _) = create_client_hello("client-1", &client);
+        guard
+            .check_and_remember(hello.nonce, now)
+            .expect("fresh nonce");
+        now += Duration::from_secs(6);
+    }
+    attempt.complete();
+    assert_eq!(attempt.poll(now), AttemptAction::Complete);
+}
+
+#[test]
+fn group_session_keys_each_receiver_separately() {
+    let sender = DeviceIdentity::generate();
+    let receivers: Vec<(&str, DeviceIdentity)> = ["recv-a", "recv-b", "recv-c"]
+        .into_iter()
+        .map(|id| (id, DeviceIdentity::generate()))
+        .collect();
+    let mut guard = ReplayGuard::new(Duration::from_secs(60));
+    let now = Instant::now();
+    let mut group = GroupSession::new(HandshakeConfig::new("sender"));
+
+    let mut responders = Vec::new();
+    for (id, identity) in &receivers {
+        let ch = group.start(&sender, id);
+        let mut responder = HandshakeResponder::new(identity, HandshakeConfig::new(*id));
+        let sh = responder
+            .on_client_hello(&ch, &mut guard, now)
+            .expect("server hello");
+        responders.push((*id, responder, sh));
+    }
+    assert_eq!(group.pending(), vec!["recv-a", "recv-b", "recv-c"]);
+
+    // recv-c answers with recv-b's hello: refused, and only recv-c fails.
+    let misdirected = responders[1].2.clone();
+    assert!(matches!(
+        group.on_server_hello("recv-c", &misdirected),
+        Err(HandshakeError::PeerMismatch)
+    ));
+    assert_eq!(group.failed(), vec!["recv-c"]);
+
+    let mut receiver_keys = Vec::new();
+    for (id, responder, sh) in responders.iter_mut().take(2) {
+        let cf = group.on_server_hello(id, sh).expect("client finished");
+        let (sf, outcome) = responder.on_client_finished(&cf).expect("server finished");
+        group.on_server_finished(id, &sf).expect("ready");
+        receiver_keys.push(outcome.keys);
+    }
+    assert_eq!(group.ready(), vec!["recv-a", "recv-b"]);
+    assert!(!group.is_complete());
+
+    let keys = group.session_keys();
+    assert_eq!(keys.len(), 2);
+    assert_eq!(keys["recv-a"].tx_key, receiver_keys[0].rx_key);
+    assert_eq!(keys["recv-b"].tx_key, receiver_keys[1].rx_key);
+    assert_ne!(keys["recv-a"].tx_key, keys["recv-b"].tx_key);
+    assert_eq!(
+        group.outcome("recv-a").expect("ready").peer_device_id,
+        "recv-a"
+    );
+
+    // A completed receiver takes no further messages; a dropped one leaves the group.
+    let sf = ServerFinished {
+        verify_data: [0u8; 32],
+        psk_binder: None,
+    };
+    assert!(matches!(
+        group.on_server_finished("recv-a", &sf),
+        Err(HandshakeError::UnexpectedMessage(_))
+    ));
+    assert!(group.remove("recv-c"));
+    assert!(group.is_complete());
+}
+
+#[test]
+fn resumption_ticket_carries_single_use_early_data() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let start = 1_700_000_000;
+    let client_clock = Arc::new(FixedClock::new(start));
+    let server_clock = Arc::new(FixedClock::new(start));
+    let server_config = HandshakeConfig::new("server-1").with_clock(server_clock.clone());
+    let mut guard = ReplayGuard::new(Duration::from_secs(60));
+    let mut tickets = TicketStore::new();
+    let now = Instant::now();
+
+    let run = |config: HandshakeConfig,
+               guard: &mut ReplayGuard,
+               tickets: &mut TicketStore,
+               late_by: u64| {
+        server_clock.set(Some(start + late_by));
+        let (mut initiator, ch) = HandshakeInitiator::start(&client, config);
+        let mut responder = HandshakeResponder::new(&server, server_config.clone());
+        let (sh, early) = responder
+            .on_client_hello_with_early_data(&ch, guard, tickets, now)
+            .expect("server hello");
+        let cf = initiator.on_server_hello(&sh).expect("finished");
+        let (sf, server_outcome) = responder.on_client_finished(&cf).expect("finished");
+        let client_outcome = initiator.on_server_finished(&sf).expect("complete");
+        (ch, early, client_outcome, server_outcome)
+    };
+    let with_early = |device_id: &str, ticket: &ResumptionTicket, data: &[u8]| {
+        let mut config = HandshakeConfig::new(device_id).with_clock(client_clock.clone());
+        config.extensions = HelloExtensions::new()
+            .with_early_data(ticket, data)
+            .expect("early data");
+        config
+    };
+
+    // A first handshake leaves both sides with the same ticket.
+    let (ch, early, client_outcome, server_outcome) = run(
+        HandshakeConfig::new("client-1").with_clock(client_clock.clone()),
+        &mut guard,
+        &mut tickets,
+        0,
+    );
+    assert_eq!(early, EarlyData::NotOffered);
+    assert!(!client_outcome.early_data_accepted);
+    let issued = ch.timestamp_secs;
+    let ticket = ResumptionTicket::issue(&client_outcome, issued, DEFAULT_TICKET_LIFETIME_SECS);
+    let stored = ResumptionTicket::issue(&server_outcome, issued, DEFAULT_TICKET_LIFETIME_SECS);
+    assert_eq!(ticket.id, stored.id);
+    assert_eq!(ticket.peer_device_id, "server-1");
+    assert_eq!(stored.peer_device_id, "client-1");
+    assert!(format!("{ticket:?}").contains("<redacted>"));
+
+    // Resuming: the manifest is read before the handshake completes.
+    let manifest = b"manifest: photo.jpg 48213 bytes".to_vec();
+    tickets.insert(stored.clone());
+    let (ch, early, client_outcome, _) = run(
+        with_early("client-1", &ticket, &manifest),
+        &mut guard,
+        &mut tickets,
+        0,
+    );
+    assert_eq!(early, EarlyData::Accepted(manifest.clone()));
+    assert!(client_outcome.early_data_accepted);
+    assert!(tickets.is_empty());
+
+    // The replayed hello is caught by the guard; a fresh hello with the used ticket is
+    // answered without its early data.
+    let mut responder = HandshakeResponder::new(&server, server_config.clone());
+    assert!(matches!(
+        responder.on_client_hello_with_early_data(&ch, &mut guard, &mut tickets, now),
+        Err(HandshakeError::ReplayedNonce)
+    ));
+    let (_, early, client_outcome, _) = run(
+        with_early("client-1", &ticket, &manifest),
+        &mut guard,
+        &mut tickets,
+        0,
+    );
+    assert_eq!(early, EarlyData::Rejected("unknown or already used ticket"));
+    assert!(!client_outcome.early_data_accepted);
+
+    // Early data needs a fresher hello than the handshake does, and a live ticket.
+    tickets.insert(stored.clone());
+    let (_, early, _, _) = run(
+        with_early("client-1", &ticket, &manifest),
+        &mut guard,
+        &mut tickets,
+        20,
+    );
+    assert_eq!(early, EarlyData::Rejected("hello too old for early data"));
+    let mut expired = stored.clone();
+    expired.lifetime_secs = 0;
+    tickets.insert(expired);
+    let (_, early, _, _) = run(
+        with_early("client-1", &ticket, &manifest),
+        &mut guard,
+        &mut tickets,
+        0,
+    );
+    assert_eq!(early, EarlyData::Rejected("ticket expired"));
+
+    // Another device can neither use nor burn the ticket.
+    tickets.insert(stored);
+    let (_, early, _, _) = run(
+        with_early("client-2", &ticket, &manifest),
+        &mut guard,
+        &mut tickets,
+        0,
+    );
+    assert_eq!(early, EarlyData::Rejected("ticket issued to another peer"));
+    assert_eq!(tickets.len(), 1);
+    tickets.expire(issued + DEFAULT_TICKET_LIFETIME_SECS);
+    assert!(tickets.is_empty());
+
+    assert!(HelloExtensions::new()
+        .with_early_data(&ticket, &vec![0; MAX_EARLY_DATA_LEN + 1])
+        .is_err());
+}
+
+#[test]
+fn clock_and_skew_policy_drive_the_state_machines() {
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let client_clock = Arc::new(FixedClock::new(1_000_000));
+    let server_clock = Arc::new(FixedClock::new(1_000_040));
+    let mut guard = ReplayGuard::new(Duration::from_secs(60));
+    let now = Instant::now();
+    let client_config = HandshakeConfig::new("client-1").with_clock(client_clock.clone());
+    let server_config = HandshakeConfig::new("server-1").with_clock(server_clock.clone());
+
+    // Hellos carry the configured time, checked against the other side's clock.
+    let (_, ch) = HandshakeInitiator::start(&client, client_config.clone());
+    assert_eq!(ch.timestamp_secs, 1_000_000);
+    let mut responder = HandshakeResponder::new(&server, server_config.clone());
+    assert!(matches!(
+        responder.on_client_hello(&ch, &mut guard, now),
+        Err(HandshakeError::TimestampSkew)
+    ));
+    let mut responder = HandshakeResponder::new(
+        &server,
+        server_config.clone().with_skew(SkewPolicy::new(60)),
+    );
+    responder
+        .on_client_hello(&ch, &mut guard, now)
+        .expect("within 60s");
+
+    // A device without a clock sends unstamped hellos; a strict peer refuses them and
+    // refuses to check without a clock of its own.
+    client_clock.set(None);
+    let (_, unstamped) = HandshakeInitiator::start(&client, client_config.clone());
+    assert_eq!(unstamped.timestamp_secs, UNSTAMPED);
+    let mut responder = HandshakeResponder::new(&server, server_config.clone());
+    assert!(matches!(
+        responder.on_client_hello(&unstamped, &mut guard, now),
+        Err(HandshakeError::TimestampSkew)
+    ));
+    server_clock.set(None);
+    let (_, ch) = HandshakeInitiator::start(&client, client_config.clone());
+    let mut responder = HandshakeResponder::new(&server, server_config.clone());
+    assert!(matches!(
+        responder.on_client_hello(&ch, &mut guard, now),
+        Err(HandshakeError::ClockUnavailable)
+    ));
+
+    // A lenient pair completes the whole handshake without either clock.
+    let lenient = SkewPolicy {
+        allow_without_clock: true,
+        accept_unstamped: true,
+        ..SkewPolicy::default()
+    };
+    let (mut initiator, ch) = HandshakeInitiator::start(&client, client_config.with_skew(lenient));
+    let mut responder = HandshakeResponder::new(&server, server_config.with_skew(lenient));
+    let sh = responder
+        .on_client_hello(&ch, &mut guard, now)
+        .expect("server hello");
+    let cf = initiator.on_server_hello(&sh).expect("client finished");
+    let (sf, _) = responder.on_client_finished(&cf).expect("server finished");
+    initiator.on_server_finished(&sf).expect("complete");
+
+    // The fallback keeps time from its last reading once the clock is lost.
+    let clock = Arc::new(FixedClock::new(5_000));
+    let fallback = MonotonicFallback::new(clock.clone());
+    assert_eq!(fallback.now_unix(), Some(5_000));
+    clock.set(None);
+    assert!(fallback.now_unix().is_some_and(|secs| secs >= 5_000));
+    let unset = MonotonicFallback::new(Arc::new(FixedClock::unset()));
+    assert_eq!(unset.now_unix(), None);
+    let anchored = MonotonicFallback::anchored(Arc::new(FixedClock::unset()), 7_000);
+    assert!(anchored.now_unix().is_some_and(|secs| secs >= 7_000));
+    assert!(SystemClock.now_unix().is_some());
+}
+
+#[test]
+fn cipher_suite_follows_client_preference_and_defaults_to_chacha() {
+    let none = HelloExtensions::new();
+    let suites = |suites: &[CipherSuite]| HelloExtensions::new().with_cipher_suites(suites);
+    assert_eq!(
+        negotiate_cipher_suite(&none, &none).unwrap(),
+        CipherSuite::ChaCha20Poly1305
+    );
+    assert_eq!(
+        negotiate_cipher_suite(
+            &suites(&[CipherSuite::Aes256Gcm, CipherSuite::ChaCha20Poly1305]),
+            &suites(&CipherSuite::ALL),
+        )
+        .unwrap(),
+        CipherSuite::Aes256Gcm
+    );
+    // A peer that advertises nothing only speaks the original suite.
+    assert!(matches!(
+        negotiate_cipher_suite(&suites(&[CipherSuite::Aes256Gcm]), &none),
+        Err(HandshakeError::NoCommonCipherSuite)
+    ));
+
+    let mut unknown = HelloExtensions::new();
+    unknown.insert(EXT_CIPHER_SUITES, vec![9, 2, 2]).unwrap();
+    assert_eq!(
+        unknown.cipher_suites().unwrap(),
+        [CipherSuite::Aes256Gcm; 2]
+    );
+    unknown.insert(EXT_CIPHER_SUITES, Vec::new()).unwrap();
+    assert!(matches!(
+        unknown.cipher_suites(),
+        Err(HandshakeError::InvalidCapabilities)
+    ));
+
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let mut client_config = HandshakeConfig::new("client-1");
+    client_config.extensions = suites(&[CipherSuite::Aes256Gcm, CipherSuite::ChaCha20Poly1305]);
+    let mut server_config = HandshakeConfig::new("server-1");
+    server_config.extensions = suites(&CipherSuite::ALL);
+    let mut guard = ReplayGuard::new(Duration::from_secs(60));
+
+    let (mut initiator, ch) = HandshakeInitiator::start(&client, client_config);
+    let mut responder = HandshakeResponder::new(&server, server_config);
+    let sh = responder
+        .on_client_hello(&ch, &mut guard, Instant::now())
+        .expect("server hello");
+    let client_finished = initiator.on_server_hello(&sh).expect("client finished");
+    let (server_finished, server_outcome) = responder
+        .on_client_finished(&client_finished)
+        .expect("server finished");
+    let client_outcome = initiator
+        .on_server_finished(&server_finished)
+        .expect("complete");
+    assert_eq!(client_outcome.cipher_suite, CipherSuite::Aes256Gcm);
+    assert_eq!(server_outcome.cipher_suite, CipherSuite::Aes256Gcm);
+}
+
+#[test]
+fn padding_follows_client_preference_and_defaults_to_none() {
+    let none = HelloExtensions::new();
+    let schemes = |schemes: &[PaddingScheme]| HelloExtensions::new().with_padding_schemes(schemes);
+    assert_eq!(
+        negotiate_padding(&none, &none).unwrap(),
+        PaddingScheme::None
+    );
+    assert_eq!(
+        negotiate_padding(
+            &schemes(&PaddingScheme::ALL),
+            &schemes(&[PaddingScheme::Bucket])
+        )
+        .unwrap(),
+        PaddingScheme::Bucket
+    );
+    // Peers that share no scheme, or one that advertises nothing, send unpadded.
+    assert_eq!(
+        negotiate_padding(&schemes(&[PaddingScheme::Padme]), &none).unwrap(),
+        PaddingScheme::None
+    );
+
+    let mut unknown = HelloExtensions::new();
+    unknown.insert(EXT_PADDING_SCHEMES, vec![9, 2]).unwrap();
+    assert_eq!(unknown.padding_schemes().unwrap(), [PaddingScheme::Padme]);
+    unknown.insert(EXT_PADDING_SCHEMES, Vec::new()).unwrap();
+    assert!(matches!(
+        negotiate_padding(&unknown, &none),
+        Err(HandshakeError::InvalidCapabilities)
+    ));
+
+    let client = DeviceIdentity::generate();
+    let server = DeviceIdentity::generate();
+    let mut client_config = HandshakeConfig::new("client-1");
+    client_config.extensions = schemes(&PaddingScheme::ALL);
+    let mut server_config = HandshakeConfig::new("server-1");
+    server_config.extensions = schemes(&[PaddingScheme::Padme, PaddingScheme::None]);
+    let mut guard = ReplayGuard::new(Duration::from_secs(60));
 
-    assert!(guard.check_and_remember(nonce, now));
-    assert!(!guard.check_and_remember(nonce, now + Duration::from_secs(1)));
-    assert!(guard.check_and_remember(nonce, now + Duration::from_secs(11)));
+    let (mut initiator, ch) = HandshakeInitiator::start(&client, client_config);
+    let mut responder = HandshakeResponder::new(&server, server_config);
+    let sh = responder
+        .on_client_hello(&ch, &mut guard, Instant::now())
+        .expect("server hello");
+    let client_finished = initiator.on_server_hello(&sh).expect("client finished");
+    let (server_finished, server_outcome) = responder
+        .on_client_finished(&client_finished)
+        .expect("server finished");
+    let client_outcome = initiator
+        .on_server_finished(&server_finished)
+        .expect("complete");
+    assert_eq!(client_outcome.padding, PaddingScheme::Padme);
+    assert_eq!(server_outcome.padding, PaddingScheme::Padme);
 }
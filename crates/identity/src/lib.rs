@@ -1,10 +1,13 @@
 use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine as _};
+use crypto_envelope::SecretKeyBytes;
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use paths::AppPaths;
 use rand::rngs::OsRng;
 use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::Path;
 use thiserror::Error;
+use zeroize::{Zeroize, Zeroizing};
 
 #[derive(Debug, Error)]
 pub enum IdentityError {
@@ -28,18 +31,39 @@ impl DeviceIdentity {
         Self { signing_key }
     }
 
+    /// Rebuild an identity from its raw 32-byte secret key.
+    pub fn from_secret_key_bytes(mut bytes: [u8; 32]) -> Self {
+        let signing_key = SigningKey::from_bytes(&bytes);
+        bytes.zeroize();
+        Self { signing_key }
+    }
+
     /// Load identity from a 32-byte secret key file.
+    ///
+    /// The bytes read from disk are wiped once the key is built.
     pub fn load(path: impl AsRef<Path>) -> Result<Self, IdentityError> {
-        let bytes = fs::read(path)?;
+        let bytes = Zeroizing::new(fs::read(path)?);
         if bytes.len() != 32 {
             return Err(IdentityError::InvalidKey);
         }
 
         let mut sk_bytes = [0u8; 32];
         sk_bytes.copy_from_slice(&bytes);
-        Ok(Self {
-            signing_key: SigningKey::from_bytes(&sk_bytes),
-        })
+        let identity = Self::from_secret_key_bytes(sk_bytes);
+        sk_bytes.zeroize();
+        Ok(identity)
+    }
+
+    /// Load the identity at the platform key location, generating and saving one on first run.
+    pub fn load_or_generate(paths: &AppPaths) -> Result<Self, IdentityError> {
+        let path = paths.identity_key_file();
+        if path.exists() {
+            return Self::load(path);
+        }
+
+        let identity = Self::generate();
+        identity.save(path)?;
+        Ok(identity)
     }
 
     /// Save identity as a raw 32-byte secret key file with restrictive permissions.
@@ -51,7 +75,7 @@ impl DeviceIdentity {
             fs::create_dir_all(parent)?;
         }
 
-        fs::write(path, self.secret_key_bytes())?;
+        fs::write(path, self.secret_key_bytes().expose())?;
 
         #[cfg(unix)]
         {
@@ -91,8 +115,8 @@ impl DeviceIdentity {
             .join(":")
     }
 
-    fn secret_key_bytes(&self) -> [u8; 32] {
-        self.signing_key.to_bytes()
+    fn secret_key_bytes(&self) -> SecretKeyBytes {
+        SecretKeyBytes::new(self.signing_key.to_bytes())
     }
 }
 
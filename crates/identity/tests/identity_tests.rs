@@ -32,3 +32,15 @@ fn sign_and_verify_roundtrip() {
     let ok = verify_signature(&id.public_key_b64(), msg, &sig).expect("verify");
     assert!(ok);
 }
+
+#[test]
+fn load_or_generate_persists_under_app_paths() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let paths = paths::AppPaths::rooted(dir.path());
+
+    let first = DeviceIdentity::load_or_generate(&paths).expect("generate");
+    assert!(paths.identity_key_file().exists());
+
+    let second = DeviceIdentity::load_or_generate(&paths).expect("load");
+    assert_eq!(first.public_key_b64(), second.public_key_b64());
+}
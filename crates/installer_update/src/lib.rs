@@ -1,3 +1,4 @@
+use sha2::{Digest, Sha256};
 use std::collections::HashSet;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -99,6 +100,60 @@ pub fn evaluate_update(
     })
 }
 
+/// Check a downloaded package against the SHA-256 its manifest pins, before it is installed.
+pub fn verify_package(manifest: &PackageManifest, package: &[u8]) -> Result<(), InstallerError> {
+    let digest: String = Sha256::digest(package).iter().map(|b| format!("{b:02x}")).collect();
+    if !digest.eq_ignore_ascii_case(&manifest.sha256) {
+        return Err(InstallerError::PackageMismatch);
+    }
+    Ok(())
+}
+
+/// A manifest published on the update feed, with its release notes link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeedEntry {
+    pub manifest: PackageManifest,
+    pub changelog_url: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestFeed {
+    entries: Vec<FeedEntry>,
+}
+
+impl ManifestFeed {
+    pub fn new(entries: Vec<FeedEntry>) -> Self {
+        Self { entries }
+    }
+
+    pub fn entries(&self) -> &[FeedEntry] {
+        &self.entries
+    }
+
+    /// Newest entry for `platform` on the current (or a more stable) channel that policy
+    /// allows installing over `current_version`. Invalid entries are skipped.
+    pub fn check(
+        &self,
+        current_version: &str,
+        current_channel: UpdateChannel,
+        platform: &str,
+        policy: &InstallPolicy,
+    ) -> Option<&FeedEntry> {
+        self.entries
+            .iter()
+            .filter(|e| e.manifest.platform == platform)
+            .filter(|e| channel_rank(e.manifest.channel) <= channel_rank(current_channel))
+            .filter(|e| {
+                evaluate_update(current_version, current_channel, &e.manifest, policy)
+                    .map(|d| d.allowed)
+                    .unwrap_or(false)
+            })
+            .filter_map(|e| parse_semver(&e.manifest.version).ok().map(|v| (v, e)))
+            .max_by_key(|(v, _)| *v)
+            .map(|(_, e)| e)
+    }
+}
+
 pub fn rollback_marker(previous_version: &str, failed_version: &str) -> String {
     format!("rollback:{}<-{}", previous_version, failed_version)
 }
@@ -152,6 +207,8 @@ fn parse_semver(v: &str) -> Result<(u64, u64, u64), InstallerError> {
 pub enum InstallerError {
     InvalidManifest(&'static str),
     PolicyViolation(&'static str),
+    /// The downloaded package does not hash to its manifest's sha256.
+    PackageMismatch,
 }
 
 impl std::fmt::Display for InstallerError {
@@ -159,6 +216,7 @@ impl std::fmt::Display for InstallerError {
         match self {
             InstallerError::InvalidManifest(m) => write!(f, "invalid manifest: {m}"),
             InstallerError::PolicyViolation(m) => write!(f, "policy violation: {m}"),
+            InstallerError::PackageMismatch => write!(f, "package does not match manifest sha256"),
         }
     }
 }
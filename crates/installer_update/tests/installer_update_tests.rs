@@ -1,6 +1,6 @@
 use installer_update::{
-    evaluate_update, rollback_marker, validate_manifest, InstallPolicy, PackageManifest,
-    UpdateChannel,
+    evaluate_update, rollback_marker, validate_manifest, verify_package, FeedEntry, InstallPolicy,
+    InstallerError, ManifestFeed, PackageManifest, UpdateChannel,
 };
 
 fn base_manifest() -> PackageManifest {
@@ -54,3 +54,42 @@ fn rollback_marker_is_generated() {
     let marker = rollback_marker("1.1.0", "1.2.0");
     assert_eq!(marker, "rollback:1.1.0<-1.2.0");
 }
+
+#[test]
+fn feed_check_picks_newest_allowed_entry_for_platform_and_channel() {
+    let entry = |version: &str, channel: UpdateChannel, platform: &str| FeedEntry {
+        manifest: PackageManifest {
+            version: version.to_string(),
+            channel,
+            platform: platform.to_string(),
+            ..base_manifest()
+        },
+        changelog_url: format!("https://example.com/changelog/{version}"),
+    };
+    let feed = ManifestFeed::new(vec![
+        entry("1.3.0", UpdateChannel::Stable, "linux-x86_64"),
+        entry("1.4.0", UpdateChannel::Stable, "linux-x86_64"),
+        entry("1.9.0", UpdateChannel::Nightly, "linux-x86_64"),
+        entry("2.0.0", UpdateChannel::Stable, "macos-aarch64"),
+    ]);
+    let policy = InstallPolicy::default();
+
+    let best = feed
+        .check("1.2.0", UpdateChannel::Stable, "linux-x86_64", &policy)
+        .expect("candidate");
+    assert_eq!(best.manifest.version, "1.4.0");
+    assert!(feed
+        .check("1.4.0", UpdateChannel::Stable, "linux-x86_64", &policy)
+        .is_none());
+}
+
+#[test]
+fn package_verification_checks_the_manifest_sha256() {
+    let mut m = base_manifest();
+    m.sha256 = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD".to_string();
+    verify_package(&m, b"abc").expect("matching package");
+    assert_eq!(
+        verify_package(&m, b"abd"),
+        Err(InstallerError::PackageMismatch)
+    );
+}
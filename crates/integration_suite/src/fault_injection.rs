@@ -0,0 +1,218 @@
+use large_file_manager::{assemble_file, verify_integrity};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::fs;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use transfer::{decrypt_chunk_frame, TransferChunkV2};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    BitFlip,
+    Duplicate,
+    Truncate,
+    Reorder,
+}
+
+/// Counts of faults applied to a frame stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    pub bit_flips: u32,
+    pub duplicates: u32,
+    pub truncations: u32,
+    pub reorders: u32,
+}
+
+/// Seeded mangler sitting between two simulated peers.
+#[derive(Debug)]
+pub struct FaultInjector {
+    rng: StdRng,
+    /// Probability (0..=100) that a frame is hit by a fault.
+    fault_percent: u8,
+    stats: FaultStats,
+}
+
+impl FaultInjector {
+    pub fn new(seed: u64, fault_percent: u8) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            fault_percent: fault_percent.min(100),
+            stats: FaultStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> FaultStats {
+        self.stats
+    }
+
+    pub fn apply(&mut self, frames: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+        let mut out = Vec::with_capacity(frames.len());
+        let mut reorder = false;
+
+        for mut frame in frames {
+            if self.rng.gen_range(0..100) >= self.fault_percent {
+                out.push(frame);
+                continue;
+            }
+
+            match self.pick_fault() {
+                FaultKind::BitFlip => {
+                    let flips = self.rng.gen_range(1..=4);
+                    for _ in 0..flips {
+                        let byte = self.rng.gen_range(0..frame.len());
+                        frame[byte] ^= 1 << self.rng.gen_range(0..8);
+                    }
+                    self.stats.bit_flips += 1;
+                    out.push(frame);
+                }
+                FaultKind::Duplicate => {
+                    self.stats.duplicates += 1;
+                    out.push(frame.clone());
+                    out.push(frame);
+                }
+                FaultKind::Truncate => {
+                    let keep = self.rng.gen_range(0..frame.len());
+                    frame.truncate(keep);
+                    self.stats.truncations += 1;
+                    out.push(frame);
+                }
+                FaultKind::Reorder => {
+                    reorder = true;
+                    self.stats.reorders += 1;
+                    out.push(frame);
+                }
+            }
+        }
+
+        if reorder {
+            out.shuffle(&mut self.rng);
+        }
+        out
+    }
+
+    fn pick_fault(&mut self) -> FaultKind {
+        match self.rng.gen_range(0..4) {
+            0 => FaultKind::BitFlip,
+            1 => FaultKind::Duplicate,
+            2 => FaultKind::Truncate,
+            _ => FaultKind::Reorder,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReceiverState {
+    Receiving,
+    /// Every chunk arrived but the assembled file failed verification; buffers were discarded.
+    IntegrityFailed,
+    /// The verified file was written to `path`.
+    Completed {
+        path: PathBuf,
+    },
+}
+
+/// Receiver model that only ever writes a verified file to its final location.
+#[derive(Debug)]
+pub struct SimulatedReceiver {
+    transfer_id: u64,
+    total_chunks: u32,
+    expected_tag: u64,
+    session_key: [u8; 32],
+    output: PathBuf,
+    chunks: BTreeMap<u32, Vec<u8>>,
+    state: ReceiverState,
+    pub rejected_frames: u32,
+    pub duplicate_frames: u32,
+}
+
+impl SimulatedReceiver {
+    pub fn new(
+        transfer_id: u64,
+        total_chunks: u32,
+        expected_tag: u64,
+        session_key: [u8; 32],
+        output: impl AsRef<Path>,
+    ) -> Self {
+        Self {
+            transfer_id,
+            total_chunks,
+            expected_tag,
+            session_key,
+            output: output.as_ref().to_path_buf(),
+            chunks: BTreeMap::new(),
+            state: ReceiverState::Receiving,
+            rejected_frames: 0,
+            duplicate_frames: 0,
+        }
+    }
+
+    pub fn state(&self) -> &ReceiverState {
+        &self.state
+    }
+
+    pub fn missing_chunks(&self) -> Vec<u32> {
+        (0..self.total_chunks)
+            .filter(|i| !self.chunks.contains_key(i))
+            .collect()
+    }
+
+    /// Feed one raw frame; returns `Err` only if handling the frame panicked.
+    pub fn ingest(&mut self, frame: &[u8]) -> Result<(), String> {
+        catch_unwind(AssertUnwindSafe(|| self.ingest_inner(frame)))
+            .map_err(|_| "receiver panicked on hostile frame".to_string())?
+    }
+
+    fn ingest_inner(&mut self, frame: &[u8]) -> Result<(), String> {
+        if matches!(self.state, ReceiverState::Completed { .. }) {
+            self.duplicate_frames += 1;
+            return Ok(());
+        }
+
+        let chunk = match TransferChunkV2::decode(frame)
+            .and_then(|f| decrypt_chunk_frame(&f, &self.session_key))
+        {
+            Ok(chunk)
+                if chunk.transfer_id == self.transfer_id
+                    && chunk.total_chunks == self.total_chunks =>
+            {
+                chunk
+            }
+            _ => {
+                self.rejected_frames += 1;
+                return Ok(());
+            }
+        };
+
+        if self.chunks.contains_key(&chunk.chunk_index) {
+            self.duplicate_frames += 1;
+            return Ok(());
+        }
+        self.chunks.insert(chunk.chunk_index, chunk.payload);
+        self.state = ReceiverState::Receiving;
+
+        if self.chunks.len() == self.total_chunks as usize {
+            self.finalize()?;
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<(), String> {
+        let data = assemble_file(self.total_chunks, &self.chunks).map_err(|e| e.to_string())?;
+        if !verify_integrity(&data, self.expected_tag) {
+            // A corrupted chunk slipped past the per-frame checks; start over.
+            self.chunks.clear();
+            self.state = ReceiverState::IntegrityFailed;
+            return Ok(());
+        }
+
+        let partial = self.output.with_extension("part");
+        fs::write(&partial, &data).map_err(|e| e.to_string())?;
+        fs::rename(&partial, &self.output).map_err(|e| e.to_string())?;
+        self.state = ReceiverState::Completed {
+            path: self.output.clone(),
+        };
+        Ok(())
+    }
+}
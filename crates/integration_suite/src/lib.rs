@@ -1,12 +1,27 @@
+mod fault_injection;
+
+pub use fault_injection::{FaultInjector, FaultKind, FaultStats, ReceiverState, SimulatedReceiver};
+
 use audit_telemetry::{AuditEvent, AuditTelemetry, RetentionPolicy};
-use desktop_ui::{DesktopUiState, DeviceCard, DeviceStatus, TransferItem, TransferState};
-use discovery::Announcement;
+use desktop_ui::{
+    DesktopUiState, DeviceCard, DeviceStatus, OfferState, OutgoingOffer, TransferItem,
+    TransferState,
+};
+use discovery::{Announcement, DiscoveryService, PeerRegistry};
+use handshake::{GroupSession, HandshakeConfig, HandshakeResponder, ReplayGuard};
+use identity::DeviceIdentity;
 use lan_offline::{LanOfflineGuard, LanPolicy};
-use nat_traversal::{decide_route, gather_candidates, NatType, Route};
+use nat_traversal::{
+    decide_route, decide_route_with_guard, gather_candidates, gather_candidates_with_guard,
+    NatType, Route,
+};
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::time::{Duration, Instant};
 use transfer::{
-    decrypt_chunk_frame, encrypt_chunk_frame, Ack, EncryptionFlag, TransferChunk, TransferChunkV2,
+    decrypt_chunk_frame, encrypt_chunk_frame, Ack, CipherSuite, ControlFrame, EncryptionFlag,
+    ReceiveSession, ReceiverProgress, TransferChunk, TransferChunkV2, TransferChunkV2Ref,
     TransferSession,
 };
 
@@ -16,10 +31,13 @@ pub fn wire_discovery_to_ui_and_transfer() -> Result<bool, String> {
         public_key_b64: "PUBKEYBASE64".into(),
         display_name: "Aarav iPhone".into(),
         port: 7777,
+        free_space: None,
+        metadata: None,
     };
 
     // Discovery packet decode path
-    let decoded = Announcement::decode(&ann.encode()).map_err(|e| e.to_string())?;
+    let decoded = Announcement::decode(&ann.encode().map_err(|e| e.to_string())?)
+        .map_err(|e| e.to_string())?;
 
     // LAN policy gate
     let guard = LanOfflineGuard::new(LanPolicy::default());
@@ -48,13 +66,16 @@ pub fn wire_discovery_to_ui_and_transfer() -> Result<bool, String> {
         file_name: "hello.txt".into(),
         progress_percent: 0,
         state: TransferState::InProgress,
+        tags: Vec::new(),
     });
 
     session
         .apply_ack(&Ack {
             transfer_id: 101,
             receiver_id: "peer-a".into(),
+            receiver_epoch: 1,
             next_expected_chunk: session.total_chunks(),
+            sack_bitmap: Vec::new(),
         })
         .map_err(|e| e.to_string())?;
 
@@ -135,13 +156,15 @@ pub fn lifecycle_security_and_telemetry_validation() -> Result<(usize, bool, boo
 pub fn plaintext_and_encrypted_paths_coexist() -> Result<(bool, bool), String> {
     let plaintext_chunk = TransferChunk {
         transfer_id: 501,
+        file_index: 0,
         chunk_index: 0,
         total_chunks: 1,
         payload: b"plaintext-ok".to_vec(),
     };
 
     let decoded_plain =
-        TransferChunk::decode(&plaintext_chunk.encode()).map_err(|e| e.to_string())?;
+        TransferChunk::decode(&plaintext_chunk.encode().map_err(|e| e.to_string())?)
+            .map_err(|e| e.to_string())?;
     let plaintext_ok = decoded_plain == plaintext_chunk;
 
     let session_key = [21u8; 32];
@@ -158,6 +181,7 @@ pub fn required_mode_rejects_plaintext_frame() -> Result<&'static str, String> {
     let plaintext_frame = TransferChunkV2 {
         protocol_version: 2,
         encryption_flag: EncryptionFlag::Plaintext,
+        cipher_suite: CipherSuite::default(),
         transfer_id: 900,
         chunk_index: 0,
         total_chunks: 1,
@@ -176,3 +200,315 @@ pub fn required_mode_rejects_plaintext_frame() -> Result<&'static str, String> {
         Err(format!("unexpected error: {err}"))
     }
 }
+
+pub fn offer_receipts_drive_sender_offer_states() -> Result<Vec<OfferState>, String> {
+    let mut ui = DesktopUiState::new();
+    ui.track_outgoing_offer(OutgoingOffer {
+        transfer_id: 610,
+        target_device_id: "peer-b".into(),
+        file_name: "report.pdf".into(),
+        state: OfferState::Sent,
+    });
+
+    // Receiver emits receipts; seen arrives before the delivery receipt on the wire.
+    let wire = [
+        ControlFrame::OfferSeen { transfer_id: 610 }.encode(),
+        ControlFrame::OfferDelivered { transfer_id: 610 }.encode(),
+    ];
+
+    let mut observed = Vec::new();
+    for bytes in wire {
+        let state = match ControlFrame::decode(&bytes).map_err(|e| e.to_string())? {
+            ControlFrame::OfferDelivered { .. } => OfferState::Delivered,
+            ControlFrame::OfferSeen { .. } => OfferState::Seen,
+            ControlFrame::OfferAccepted { free_space, .. } => {
+                ui.record_peer_free_space("peer-b", free_space);
+                OfferState::Accepted
+            }
+            ControlFrame::Error(_)
+            | ControlFrame::Cancel { .. }
+            | ControlFrame::Abort { .. }
+            | ControlFrame::Heartbeat { .. } => continue,
+        };
+        ui.advance_offer_state(610, state)
+            .map_err(|e| e.to_string())?;
+        observed.push(ui.outgoing_offer(610).ok_or("missing offer")?.state);
+    }
+
+    ui.advance_offer_state(610, OfferState::Accepted)
+        .map_err(|e| e.to_string())?;
+    observed.push(ui.outgoing_offer(610).ok_or("missing offer")?.state);
+
+    Ok(observed)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaultScenarioReport {
+    pub state: ReceiverState,
+    pub faults: FaultStats,
+    pub rejected_frames: u32,
+    pub duplicate_frames: u32,
+    /// Whether the final file (if any) is byte-identical to what was sent.
+    pub output_matches: bool,
+}
+
+/// Send an encrypted transfer through a fault injector for `faulty_rounds` rounds of
+/// retransmitting whatever the receiver is missing, then finish over a clean link.
+pub fn fault_injected_transfer(
+    seed: u64,
+    faulty_rounds: usize,
+    out_dir: &Path,
+) -> Result<FaultScenarioReport, String> {
+    const CLEAN_ROUNDS: usize = 3;
+
+    let data: Vec<u8> = (0..10_000u32).map(|i| (i * 31 % 251) as u8).collect();
+    let session = TransferSession::new(700, data.clone(), 512, ["peer-r".to_string()])
+        .map_err(|e| e.to_string())?;
+    let key = [42u8; 32];
+    let output = out_dir.join(format!("fault-{seed}.bin"));
+
+    let mut injector = FaultInjector::new(seed, 30);
+    let mut receiver = SimulatedReceiver::new(
+        700,
+        session.total_chunks(),
+        large_file_manager::integrity_tag(&data),
+        key,
+        &output,
+    );
+
+    for round in 0..faulty_rounds + CLEAN_ROUNDS {
+        if matches!(receiver.state(), ReceiverState::Completed { .. }) {
+            break;
+        }
+
+        let mut frames = Vec::new();
+        for index in receiver.missing_chunks() {
+            let chunk = session.chunk_for(index).map_err(|e| e.to_string())?;
+            let frame = encrypt_chunk_frame(&chunk, &key).map_err(|e| e.to_string())?;
+            frames.push(frame.encode());
+        }
+        if round < faulty_rounds {
+            frames = injector.apply(frames);
+        }
+
+        for frame in &frames {
+            receiver.ingest(frame)?;
+        }
+    }
+
+    let output_matches = match receiver.state() {
+        ReceiverState::Completed { path } => {
+            std::fs::read(path).map_err(|e| e.to_string())? == data
+        }
+        _ => !output.exists(),
+    };
+
+    Ok(FaultScenarioReport {
+        state: receiver.state().clone(),
+        faults: injector.stats(),
+        rejected_frames: receiver.rejected_frames,
+        duplicate_frames: receiver.duplicate_frames,
+        output_matches,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ipv6OnlyReport {
+    pub discovered_service: SocketAddr,
+    pub route: Route,
+    pub route_trace: Vec<String>,
+    pub received: Vec<u8>,
+}
+
+/// Two peers with only `::1` and link-local addresses: discover over UDP, pass the LAN
+/// guard, plan a route from IPv6 candidates and move an encrypted file.
+pub fn ipv6_only_discovery_route_and_transfer() -> Result<Ipv6OnlyReport, String> {
+    let loopback: SocketAddr = "[::1]:0"
+        .parse()
+        .map_err(|e: std::net::AddrParseError| e.to_string())?;
+    let receiver = DiscoveryService::bind(loopback).map_err(|e| e.to_string())?;
+    let sender = DiscoveryService::bind(loopback).map_err(|e| e.to_string())?;
+    let data_socket = UdpSocket::bind(loopback).map_err(|e| e.to_string())?;
+    data_socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .map_err(|e| e.to_string())?;
+    let data_port = data_socket.local_addr().map_err(|e| e.to_string())?.port();
+
+    let announcement = Announcement {
+        device_id: "peer-v6".into(),
+        public_key_b64: "PUBKEYBASE64".into(),
+        display_name: "IPv6 Laptop".into(),
+        port: data_port,
+        free_space: None,
+        metadata: None,
+    };
+    sender
+        .send_announcement(
+            receiver.local_addr().map_err(|e| e.to_string())?,
+            &announcement,
+        )
+        .map_err(|e| e.to_string())?;
+    let (heard, source) = receiver
+        .recv_announcement(2048)
+        .map_err(|e| e.to_string())?;
+
+    let mut registry = PeerRegistry::new(Duration::from_secs(30));
+    registry.upsert(heard, source, Instant::now());
+    let peer = registry
+        .peers()
+        .into_iter()
+        .find(|p| p.announcement.device_id == "peer-v6")
+        .ok_or("peer not registered")?
+        .service_addr();
+
+    let guard = LanOfflineGuard::new(LanPolicy::default());
+    guard
+        .validate_peer_set([peer].iter())
+        .map_err(|e| e.to_string())?;
+
+    // Reflexive candidates from a global prefix are dropped offline; link-local stays usable.
+    let addr = |s: &str| s.parse::<SocketAddr>().map_err(|e| e.to_string());
+    let local = gather_candidates_with_guard(
+        addr("[fe80::1]:5000")?,
+        Some(addr("[2001:db8::10]:5000")?),
+        None,
+        &guard,
+    );
+    let remote = gather_candidates(peer, Some(addr("[2001:db8::20]:5001")?), None);
+    let plan = decide_route_with_guard(
+        NatType::FullCone,
+        NatType::FullCone,
+        &local,
+        &remote,
+        &guard,
+    );
+
+    let data: Vec<u8> = (0..5_000u32).map(|i| (i % 253) as u8).collect();
+    let session = TransferSession::new(880, data, 1_000, ["peer-v6".to_string()])
+        .map_err(|e| e.to_string())?;
+    let key = [66u8; 32];
+    let link = UdpSocket::bind(loopback).map_err(|e| e.to_string())?;
+    for index in 0..session.total_chunks() {
+        let chunk = session.chunk_for(index).map_err(|e| e.to_string())?;
+        let frame = encrypt_chunk_frame(&chunk, &key).map_err(|e| e.to_string())?;
+        link.send_to(&frame.encode(), peer)
+            .map_err(|e| e.to_string())?;
+    }
+
+    let mut chunks = std::collections::BTreeMap::new();
+    let mut buf = vec![0u8; 4096];
+    while chunks.len() < session.total_chunks() as usize {
+        let (n, _) = data_socket.recv_from(&mut buf).map_err(|e| e.to_string())?;
+        let frame = TransferChunkV2::decode(&buf[..n]).map_err(|e| e.to_string())?;
+        let chunk = decrypt_chunk_frame(&frame, &key).map_err(|e| e.to_string())?;
+        chunks.insert(chunk.chunk_index, chunk.payload);
+    }
+
+    Ok(Ipv6OnlyReport {
+        discovered_service: peer,
+        route: plan.route,
+        route_trace: plan.trace,
+        received: chunks.into_values().flatten().collect(),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupTransferReport {
+    pub progress: Vec<ReceiverProgress>,
+    /// Receivers whose reassembled payload matched the data sent.
+    pub completed: Vec<String>,
+    /// A frame sealed for one receiver failed to open under another's key.
+    pub cross_key_rejected: bool,
+}
+
+/// A sender runs a group handshake with two receivers, keys the fan-out with the
+/// per-receiver session keys and tracks each receiver's progress from its acks. The
+/// second receiver only gets the first half of the chunks.
+pub fn group_handshake_keys_multi_receiver_transfer() -> Result<GroupTransferReport, String> {
+    let data: Vec<u8> = (0..4_000u32).map(|i| (i % 241) as u8).collect();
+    let sender = DeviceIdentity::generate();
+    let receiver_ids = ["peer-b", "peer-c"];
+    let mut guard = ReplayGuard::new(Duration::from_secs(60));
+    let now = Instant::now();
+
+    let mut group = GroupSession::new(HandshakeConfig::new("peer-a"));
+    let mut receivers = Vec::new();
+    for id in receiver_ids {
+        let identity = DeviceIdentity::generate();
+        let client_hello = group.start(&sender, id);
+        let mut responder = HandshakeResponder::new(&identity, HandshakeConfig::new(id));
+        let server_hello = responder
+            .on_client_hello(&client_hello, &mut guard, now)
+            .map_err(|e| e.to_string())?;
+        let client_finished = group
+            .on_server_hello(id, &server_hello)
+            .map_err(|e| e.to_string())?;
+        let (server_finished, outcome) = responder
+            .on_client_finished(&client_finished)
+            .map_err(|e| e.to_string())?;
+        group
+            .on_server_finished(id, &server_finished)
+            .map_err(|e| e.to_string())?;
+        receivers.push((id, outcome.keys.rx_key, ReceiveSession::new(800, id, 1)));
+    }
+    if !group.is_complete() {
+        return Err(format!("handshakes pending: {:?}", group.pending()));
+    }
+
+    let mut session = TransferSession::new(800, data.clone(), 256, receiver_ids.map(String::from))
+        .map_err(|e| e.to_string())?;
+    for id in receiver_ids {
+        let outcome = group.outcome(id).ok_or("handshake outcome missing")?;
+        session
+            .set_receiver_key_with_suite(
+                id,
+                outcome.keys.tx_key,
+                outcome.cipher_suite,
+                outcome.padding,
+            )
+            .map_err(|e| e.to_string())?;
+    }
+
+    let total = session.total_chunks();
+    for (position, (id, key, receiver)) in receivers.iter_mut().enumerate() {
+        let sent = if position == 0 { total } else { total / 2 };
+        for index in 0..sent {
+            let wire = session
+                .encrypted_chunk_for(id, index)
+                .map_err(|e| e.to_string())?
+                .encode();
+            let frame = TransferChunkV2Ref::decode(&wire).map_err(|e| e.to_string())?;
+            receiver
+                .accept_encrypted_frame(&frame, key)
+                .map_err(|e| e.to_string())?;
+        }
+        session
+            .apply_ack(&receiver.ack())
+            .map_err(|e| e.to_string())?;
+    }
+
+    let foreign = session
+        .encrypted_chunk_for("peer-c", 0)
+        .map_err(|e| e.to_string())?
+        .encode();
+    let foreign = TransferChunkV2Ref::decode(&foreign).map_err(|e| e.to_string())?;
+    let cross_key_rejected = ReceiveSession::new(800, "peer-b", 2)
+        .accept_encrypted_frame(&foreign, &receivers[0].1)
+        .is_err();
+
+    let mut completed = Vec::new();
+    for (id, _, receiver) in &mut receivers {
+        if receiver.take_payload().as_ref() == Some(&data) {
+            completed.push(id.to_string());
+        }
+    }
+    let progress = receiver_ids
+        .iter()
+        .map(|id| session.progress_for(id).map_err(|e| e.to_string()))
+        .collect::<Result<_, _>>()?;
+    Ok(GroupTransferReport {
+        progress,
+        completed,
+        cross_key_rejected,
+    })
+}
@@ -1,9 +1,14 @@
+use crypto_envelope::{probe, CryptoEnvelopeError};
+use desktop_ui::OfferState;
 use integration_suite::{
-    e2e_route_for_lan_and_relay, lifecycle_security_and_telemetry_validation,
+    e2e_route_for_lan_and_relay, fault_injected_transfer,
+    group_handshake_keys_multi_receiver_transfer, ipv6_only_discovery_route_and_transfer,
+    lifecycle_security_and_telemetry_validation, offer_receipts_drive_sender_offer_states,
     plaintext_and_encrypted_paths_coexist, required_mode_rejects_plaintext_frame,
-    wire_discovery_to_ui_and_transfer,
+    wire_discovery_to_ui_and_transfer, ReceiverState, SimulatedReceiver,
 };
 use nat_traversal::Route;
+use transfer::{encrypt_chunk_frame, TransferChunk};
 
 #[test]
 fn cross_module_wiring_discovery_to_ui_to_transfer_works() {
@@ -39,3 +44,124 @@ fn required_mode_policy_rejects_plaintext_frame() {
     let status = required_mode_rejects_plaintext_frame().expect("reject plaintext");
     assert_eq!(status, "rejected");
 }
+
+#[test]
+fn offer_receipts_surface_as_monotonic_sender_states() {
+    let states = offer_receipts_drive_sender_offer_states().expect("offer receipts");
+    assert_eq!(
+        states,
+        vec![OfferState::Seen, OfferState::Seen, OfferState::Accepted]
+    );
+}
+
+#[test]
+fn receiver_survives_fault_injection_and_never_writes_corrupt_file() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut total_faults = 0;
+
+    for seed in 0..16 {
+        let report = fault_injected_transfer(seed, 4, dir.path()).expect("no panic");
+        assert!(
+            matches!(report.state, ReceiverState::Completed { .. }),
+            "seed {seed}: {report:?}"
+        );
+        assert!(report.output_matches, "seed {seed}: corrupted output");
+
+        let f = report.faults;
+        total_faults += f.bit_flips + f.duplicates + f.truncations + f.reorders;
+    }
+    assert!(total_faults > 0);
+}
+
+#[test]
+fn hostile_frames_leave_receiver_in_defined_state() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let output = dir.path().join("hostile.bin");
+    let mut receiver = SimulatedReceiver::new(1, 2, 0, [7u8; 32], &output);
+
+    let valid = encrypt_chunk_frame(
+        &TransferChunk {
+            transfer_id: 1,
+            file_index: 0,
+            chunk_index: 0,
+            total_chunks: 2,
+            payload: b"chunk".to_vec(),
+        },
+        &[7u8; 32],
+    )
+    .expect("encrypt")
+    .encode();
+
+    for len in 0..valid.len() {
+        receiver.ingest(&valid[..len]).expect("truncated frame");
+    }
+    for byte in 0..valid.len() {
+        let mut flipped = valid.clone();
+        flipped[byte] ^= 0x80;
+        receiver.ingest(&flipped).expect("flipped frame");
+    }
+    receiver.ingest(b"garbage").expect("garbage frame");
+
+    assert_eq!(receiver.state(), &ReceiverState::Receiving);
+    assert!(!output.exists());
+    assert!(receiver.rejected_frames > 0);
+}
+
+#[test]
+fn ipv6_only_peers_discover_route_and_transfer() {
+    let report = ipv6_only_discovery_route_and_transfer().expect("ipv6-only scenario");
+    assert!(report.discovered_service.ip().is_loopback());
+    assert!(report.discovered_service.is_ipv6());
+    assert_eq!(report.route, Route::Direct);
+    assert_eq!(report.route_trace.len(), 2);
+    assert!(report.route_trace[0].contains("[2001:db8::10]:5000"));
+    let expected: Vec<u8> = (0..5_000u32).map(|i| (i % 253) as u8).collect();
+    assert_eq!(report.received, expected);
+}
+
+#[test]
+fn group_handshake_keys_each_receiver_of_a_fanout() {
+    let report = group_handshake_keys_multi_receiver_transfer().expect("group scenario");
+    assert_eq!(report.completed, vec!["peer-b".to_string()]);
+    assert!(report.cross_key_rejected);
+    let percents: Vec<(&str, u8)> = report
+        .progress
+        .iter()
+        .map(|p| (p.receiver_id.as_str(), p.percent()))
+        .collect();
+    assert_eq!(percents, [("peer-b", 100), ("peer-c", 50)]);
+}
+
+#[test]
+fn tag_verification_has_no_early_exit() {
+    let key = [0x19u8; 32];
+    let nonce = crypto_envelope::derive_nonce(5, 0, crypto_envelope::Direction::SenderToReceiver);
+    let ciphertext =
+        crypto_envelope::encrypt_chunk_with_aad(&key, nonce, &[0xab; 4096], b"hdr").unwrap();
+
+    // A flip in the first byte costs as much as one in the tag: the whole ciphertext is
+    // authenticated before the verdict, and the verdict is the same.
+    let traces: Vec<_> = [0, ciphertext.len() / 2, ciphertext.len() - 1]
+        .into_iter()
+        .map(|at| {
+            let mut tampered = ciphertext.clone();
+            tampered[at] ^= 0x01;
+            probe::decrypt_trace(&key, nonce, &tampered, b"hdr")
+        })
+        .collect();
+    for trace in &traces {
+        assert_eq!(trace.result, Err(CryptoEnvelopeError::TagMismatch));
+        assert_eq!(trace.bytes_authenticated, 4096);
+    }
+    let clean = probe::decrypt_trace(&key, nonce, &ciphertext, b"hdr");
+    assert_eq!(clean.result, Ok(vec![0xab; 4096]));
+    assert_eq!(clean.bytes_authenticated, 4096);
+
+    let tag = [0x5au8; 16];
+    for at in [0, 7, 15] {
+        let mut other = tag;
+        other[at] ^= 0x80;
+        assert_eq!(probe::ct_eq_trace(&tag, &other), (false, 16));
+    }
+    assert_eq!(probe::ct_eq_trace(&tag, &tag), (true, 16));
+}
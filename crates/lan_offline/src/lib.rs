@@ -1,4 +1,4 @@
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LanPolicy {
@@ -6,6 +6,11 @@ pub struct LanPolicy {
     pub allow_link_local: bool,
     pub allow_private: bool,
     pub deny_public: bool,
+    /// Global IPv6 prefixes (address, prefix length) assigned to local interfaces.
+    ///
+    /// IPv6-only LANs often hand out only GUAs, so a peer inside one of these on-link
+    /// prefixes is treated like a private-range peer rather than a public one.
+    pub on_link_ipv6_prefixes: Vec<(Ipv6Addr, u8)>,
 }
 
 impl Default for LanPolicy {
@@ -15,6 +20,7 @@ impl Default for LanPolicy {
             allow_link_local: true,
             allow_private: true,
             deny_public: true,
+            on_link_ipv6_prefixes: Vec::new(),
         }
     }
 }
@@ -57,7 +63,8 @@ impl LanOfflineGuard {
             return PolicyDecision::Allow;
         }
 
-        let ip = addr.ip();
+        // IPv4-mapped IPv6 (`::ffff:a.b.c.d`) from dual-stack sockets is judged as IPv4.
+        let ip = addr.ip().to_canonical();
 
         if ip.is_loopback() {
             return if self.policy.allow_loopback {
@@ -83,6 +90,14 @@ impl LanOfflineGuard {
             };
         }
 
+        if self.is_on_link_global(ip) {
+            return if self.policy.allow_private {
+                PolicyDecision::Allow
+            } else {
+                PolicyDecision::Deny("on-link IPv6 prefix denied")
+            };
+        }
+
         if self.policy.deny_public {
             return PolicyDecision::Deny("public internet address denied in offline mode");
         }
@@ -90,6 +105,21 @@ impl LanOfflineGuard {
         PolicyDecision::Allow
     }
 
+    fn is_on_link_global(&self, ip: IpAddr) -> bool {
+        let IpAddr::V6(v6) = ip else {
+            return false;
+        };
+        self.policy
+            .on_link_ipv6_prefixes
+            .iter()
+            .any(|&(prefix, len)| {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(len.min(128)))
+                    .unwrap_or(0);
+                u128::from(v6) & mask == u128::from(prefix) & mask
+            })
+    }
+
     /// Returns true only when all peers satisfy offline-LAN policy.
     pub fn validate_peer_set<'a>(&self, peers: impl IntoIterator<Item = &'a SocketAddr>) -> Result<(), LanOfflineError> {
         for peer in peers {
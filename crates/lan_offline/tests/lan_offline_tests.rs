@@ -65,3 +65,37 @@ fn deny_private_when_policy_disables_it() {
         PolicyDecision::Deny("private-range denied")
     );
 }
+
+#[test]
+fn ipv6_ula_allowed_and_gua_needs_on_link_prefix() {
+    let ula: SocketAddr = "[fd12:3456:789a::5]:7000".parse().expect("ula");
+    let gua: SocketAddr = "[2001:db8:1:2::5]:7000".parse().expect("gua");
+    let foreign_gua: SocketAddr = "[2001:db8:ffff::5]:7000".parse().expect("gua");
+
+    let guard = LanOfflineGuard::new(LanPolicy::default());
+    assert_eq!(guard.evaluate_peer(ula), PolicyDecision::Allow);
+    assert!(matches!(guard.evaluate_peer(gua), PolicyDecision::Deny(_)));
+
+    let guard = LanOfflineGuard::new(LanPolicy {
+        on_link_ipv6_prefixes: vec![("2001:db8:1:2::".parse().expect("prefix"), 64)],
+        ..LanPolicy::default()
+    });
+    assert_eq!(guard.evaluate_peer(gua), PolicyDecision::Allow);
+    assert!(matches!(
+        guard.evaluate_peer(foreign_gua),
+        PolicyDecision::Deny(_)
+    ));
+}
+
+#[test]
+fn ipv4_mapped_ipv6_is_judged_as_ipv4() {
+    let guard = LanOfflineGuard::new(LanPolicy::default());
+    let mapped_private: SocketAddr = "[::ffff:192.168.1.10]:9000".parse().expect("mapped");
+    let mapped_public: SocketAddr = "[::ffff:8.8.8.8]:53".parse().expect("mapped");
+
+    assert_eq!(guard.evaluate_peer(mapped_private), PolicyDecision::Allow);
+    assert!(matches!(
+        guard.evaluate_peer(mapped_public),
+        PolicyDecision::Deny(_)
+    ));
+}
@@ -0,0 +1,64 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use large_file_manager::{file_tag, integrity_tag, ParallelVerifier, VerifyConfig};
+use std::collections::BTreeMap;
+use std::thread;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const CHUNKS: u32 = 512;
+
+fn sample_chunks() -> BTreeMap<u32, Vec<u8>> {
+    (0..CHUNKS)
+        .map(|i| {
+            let chunk = (0..CHUNK_SIZE).map(|b| (b as u32 ^ i) as u8).collect();
+            (i, chunk)
+        })
+        .collect()
+}
+
+/// Thread counts to compare: powers of two up to the machine's core count.
+///
+/// Throughput should scale close to linearly with the count, since chunks are hashed
+/// independently and nothing is shared between workers.
+fn thread_counts() -> Vec<usize> {
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut counts = vec![1];
+    while counts.last().copied().unwrap_or(1) * 2 <= cores {
+        counts.push(counts.last().copied().unwrap_or(1) * 2);
+    }
+    counts
+}
+
+fn bench_verify_chunks(c: &mut Criterion) {
+    let chunks = sample_chunks();
+    let expected: Vec<u64> = chunks.values().map(|c| integrity_tag(c)).collect();
+
+    let mut group = c.benchmark_group("verify_chunks");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(CHUNK_SIZE as u64 * u64::from(CHUNKS)));
+    for threads in thread_counts() {
+        let verifier = ParallelVerifier::new(VerifyConfig { threads }).expect("pool");
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &verifier, |b, v| {
+            b.iter(|| assert!(v.verify_chunks(&chunks, &expected).is_empty()))
+        });
+    }
+    group.finish();
+}
+
+fn bench_file_tag(c: &mut Criterion) {
+    let data: Vec<u8> = sample_chunks().into_values().flatten().collect();
+    let expected = file_tag(&data, CHUNK_SIZE).expect("tag");
+
+    let mut group = c.benchmark_group("file_tag");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    for threads in thread_counts() {
+        let verifier = ParallelVerifier::new(VerifyConfig { threads }).expect("pool");
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &verifier, |b, v| {
+            b.iter(|| assert!(v.verify_file(&data, CHUNK_SIZE, expected).expect("verify")))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_verify_chunks, bench_file_tag);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+use large_file_manager::{apply_mirror, plan_mirror, DirectorySignature, MirrorOptions};
+use std::process::ExitCode;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let delete_extraneous = args.iter().any(|a| a == "--delete");
+    let apply = args.iter().any(|a| a == "--apply");
+    let paths: Vec<&String> = args.iter().filter(|a| !a.starts_with("--")).collect();
+
+    let [source, destination] = paths.as_slice() else {
+        eprintln!("usage: p2p_mirror <source> <destination> [--delete] [--apply]");
+        return ExitCode::from(2);
+    };
+
+    let result = DirectorySignature::scan(source, CHUNK_SIZE)
+        .and_then(|src| Ok((src, DirectorySignature::scan(destination, CHUNK_SIZE)?)))
+        .and_then(|(src, dst)| plan_mirror(&src, &dst, MirrorOptions { delete_extraneous }));
+    let plan = match result {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("mirror failed: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for action in &plan.actions {
+        println!("{action}");
+    }
+    for path in &plan.extraneous {
+        println!("keep {} (pass --delete to remove)", path.display());
+    }
+    println!("{} bytes to send", plan.bytes_to_send);
+
+    if !apply {
+        println!("dry run: pass --apply to execute");
+        return ExitCode::SUCCESS;
+    }
+    match apply_mirror(&plan, source, destination) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("mirror failed: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,6 +1,16 @@
+mod mirror;
+mod verify;
+
+pub use mirror::{
+    apply_mirror, plan_mirror, DirectorySignature, FileSignature, MirrorAction, MirrorOptions,
+    MirrorPlan,
+};
+pub use verify::{file_tag, ParallelVerifier, VerifyConfig};
+
+use paths::AppPaths;
 use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ChunkIndexEntry {
@@ -157,6 +167,11 @@ impl LargeFileManager {
     }
 }
 
+/// Default checkpoint location for a transfer under the platform data directory.
+pub fn checkpoint_path(paths: &AppPaths, transfer_id: u64) -> PathBuf {
+    paths.checkpoints_dir().join(format!("{transfer_id}.ckpt"))
+}
+
 pub fn assemble_file(total_chunks: u32, chunks: &BTreeMap<u32, Vec<u8>>) -> Result<Vec<u8>, ManagerError> {
     let mut out = Vec::new();
     for i in 0..total_chunks {
@@ -0,0 +1,264 @@
+use crate::{integrity_tag, ManagerError};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Per-chunk signature of one file, relative to the mirrored root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSignature {
+    pub size: u64,
+    pub chunk_tags: Vec<u64>,
+}
+
+/// Signatures for every regular file under a directory, keyed by relative path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectorySignature {
+    pub chunk_size: usize,
+    pub files: BTreeMap<PathBuf, FileSignature>,
+}
+
+impl DirectorySignature {
+    /// Walk `root` and compute chunk signatures. A missing root scans as empty.
+    pub fn scan(root: impl AsRef<Path>, chunk_size: usize) -> Result<Self, ManagerError> {
+        if chunk_size == 0 {
+            return Err(ManagerError::InvalidConfig("chunk_size must be > 0"));
+        }
+
+        let root = root.as_ref();
+        let mut files = BTreeMap::new();
+        if root.is_dir() {
+            scan_dir(root, root, chunk_size, &mut files)?;
+        }
+        Ok(Self { chunk_size, files })
+    }
+}
+
+fn scan_dir(
+    root: &Path,
+    dir: &Path,
+    chunk_size: usize,
+    files: &mut BTreeMap<PathBuf, FileSignature>,
+) -> Result<(), ManagerError> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+        if file_type.is_dir() {
+            scan_dir(root, &path, chunk_size, files)?;
+        } else if file_type.is_file() {
+            let relative = path
+                .strip_prefix(root)
+                .map_err(|_| ManagerError::InvalidState("path outside mirror root"))?
+                .to_path_buf();
+            files.insert(relative, sign_file(&path, chunk_size)?);
+        }
+    }
+    Ok(())
+}
+
+fn sign_file(path: &Path, chunk_size: usize) -> Result<FileSignature, ManagerError> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut chunk_tags = Vec::new();
+    let mut buf = vec![0u8; chunk_size];
+    loop {
+        let n = read_full(&mut file, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        chunk_tags.push(integrity_tag(&buf[..n]));
+    }
+    Ok(FileSignature { size, chunk_tags })
+}
+
+fn read_full(file: &mut File, buf: &mut [u8]) -> Result<usize, ManagerError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = file.read(&mut buf[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MirrorOptions {
+    /// Remove receiver files that do not exist in the source. Off unless explicitly enabled.
+    pub delete_extraneous: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MirrorAction {
+    Create {
+        path: PathBuf,
+        size: u64,
+    },
+    Update {
+        path: PathBuf,
+        size: u64,
+        changed_chunks: Vec<u32>,
+    },
+    Delete {
+        path: PathBuf,
+    },
+}
+
+impl std::fmt::Display for MirrorAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MirrorAction::Create { path, size } => {
+                write!(f, "create {} ({size} bytes)", path.display())
+            }
+            MirrorAction::Update {
+                path,
+                changed_chunks,
+                ..
+            } => write!(
+                f,
+                "update {} ({} changed chunks)",
+                path.display(),
+                changed_chunks.len()
+            ),
+            MirrorAction::Delete { path } => write!(f, "delete {}", path.display()),
+        }
+    }
+}
+
+/// What a mirror run would do; computing it never touches the destination.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MirrorPlan {
+    pub chunk_size: usize,
+    pub actions: Vec<MirrorAction>,
+    /// Destination-only files left in place because deletion was not enabled.
+    pub extraneous: Vec<PathBuf>,
+    pub bytes_to_send: u64,
+}
+
+impl MirrorPlan {
+    pub fn is_noop(&self) -> bool {
+        self.actions.is_empty()
+    }
+}
+
+/// Compare a source signature against the receiver's copy.
+pub fn plan_mirror(
+    source: &DirectorySignature,
+    destination: &DirectorySignature,
+    options: MirrorOptions,
+) -> Result<MirrorPlan, ManagerError> {
+    if source.chunk_size != destination.chunk_size {
+        return Err(ManagerError::InvalidConfig(
+            "signatures use different chunk sizes",
+        ));
+    }
+
+    let chunk_size = source.chunk_size as u64;
+    let mut plan = MirrorPlan {
+        chunk_size: source.chunk_size,
+        ..MirrorPlan::default()
+    };
+
+    for (path, src) in &source.files {
+        match destination.files.get(path) {
+            None => {
+                plan.bytes_to_send += src.size;
+                plan.actions.push(MirrorAction::Create {
+                    path: path.clone(),
+                    size: src.size,
+                });
+            }
+            Some(dst) if dst == src => {}
+            Some(dst) => {
+                let changed_chunks: Vec<u32> = (0..src.chunk_tags.len())
+                    .filter(|&i| dst.chunk_tags.get(i) != Some(&src.chunk_tags[i]))
+                    .map(|i| i as u32)
+                    .collect();
+                plan.bytes_to_send += changed_chunks
+                    .iter()
+                    .map(|&i| chunk_size.min(src.size - u64::from(i) * chunk_size))
+                    .sum::<u64>();
+                plan.actions.push(MirrorAction::Update {
+                    path: path.clone(),
+                    size: src.size,
+                    changed_chunks,
+                });
+            }
+        }
+    }
+
+    for path in destination.files.keys() {
+        if source.files.contains_key(path) {
+            continue;
+        }
+        if options.delete_extraneous {
+            plan.actions
+                .push(MirrorAction::Delete { path: path.clone() });
+        } else {
+            plan.extraneous.push(path.clone());
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Execute a plan from a reachable source directory into `dest_root`.
+///
+/// Updates only rewrite the changed chunks and truncate to the source size.
+pub fn apply_mirror(
+    plan: &MirrorPlan,
+    source_root: impl AsRef<Path>,
+    dest_root: impl AsRef<Path>,
+) -> Result<(), ManagerError> {
+    let source_root = source_root.as_ref();
+    let dest_root = dest_root.as_ref();
+    let chunk_size = plan.chunk_size as u64;
+
+    for action in &plan.actions {
+        match action {
+            MirrorAction::Create { path, .. } => {
+                let dest = confined(dest_root, path)?;
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(confined(source_root, path)?, dest)?;
+            }
+            MirrorAction::Update {
+                path,
+                size,
+                changed_chunks,
+            } => {
+                let mut src = File::open(confined(source_root, path)?)?;
+                let mut dst = OpenOptions::new()
+                    .write(true)
+                    .open(confined(dest_root, path)?)?;
+                let mut buf = vec![0u8; plan.chunk_size];
+                for &index in changed_chunks {
+                    let offset = u64::from(index) * chunk_size;
+                    src.seek(SeekFrom::Start(offset))?;
+                    let n = read_full(&mut src, &mut buf)?;
+                    dst.seek(SeekFrom::Start(offset))?;
+                    dst.write_all(&buf[..n])?;
+                }
+                dst.set_len(*size)?;
+            }
+            MirrorAction::Delete { path } => {
+                fs::remove_file(confined(dest_root, path)?)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Join a relative plan path onto `root`, refusing anything that could escape it.
+fn confined(root: &Path, relative: &Path) -> Result<PathBuf, ManagerError> {
+    if relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return Err(ManagerError::InvalidState("unsafe path in mirror plan"));
+    }
+    Ok(root.join(relative))
+}
@@ -0,0 +1,97 @@
+use crate::{integrity_tag, ManagerError};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VerifyConfig {
+    /// Worker threads; 0 uses one per available core.
+    pub threads: usize,
+}
+
+/// Chunk verification and whole-file tags computed on a dedicated worker pool.
+///
+/// Results are identical to the serial helpers regardless of thread count; only the
+/// wall-clock time changes.
+#[derive(Debug)]
+pub struct ParallelVerifier {
+    pool: ThreadPool,
+}
+
+impl ParallelVerifier {
+    pub fn new(config: VerifyConfig) -> Result<Self, ManagerError> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(config.threads)
+            .thread_name(|i| format!("chunk-verify-{i}"))
+            .build()
+            .map_err(|_| ManagerError::InvalidConfig("verify thread pool could not start"))?;
+        Ok(Self { pool })
+    }
+
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    pub fn chunk_tags(&self, chunks: &[Vec<u8>]) -> Vec<u64> {
+        self.pool
+            .install(|| chunks.par_iter().map(|c| integrity_tag(c)).collect())
+    }
+
+    /// Check every chunk against `expected_tags` (indexed by chunk number).
+    ///
+    /// Returns the indices that are missing or do not match, in ascending order, so
+    /// the caller can re-request exactly those.
+    pub fn verify_chunks(
+        &self,
+        chunks: &BTreeMap<u32, Vec<u8>>,
+        expected_tags: &[u64],
+    ) -> Vec<u32> {
+        self.pool.install(|| {
+            expected_tags
+                .par_iter()
+                .enumerate()
+                .filter_map(|(i, expected)| {
+                    let index = i as u32;
+                    match chunks.get(&index) {
+                        Some(chunk) if integrity_tag(chunk) == *expected => None,
+                        _ => Some(index),
+                    }
+                })
+                .collect()
+        })
+    }
+
+    /// Parallel equivalent of [`file_tag`].
+    pub fn file_tag(&self, data: &[u8], chunk_size: usize) -> Result<u64, ManagerError> {
+        if chunk_size == 0 {
+            return Err(ManagerError::InvalidConfig("chunk_size must be > 0"));
+        }
+        let tags: Vec<u64> = self
+            .pool
+            .install(|| data.par_chunks(chunk_size).map(integrity_tag).collect());
+        Ok(combine_tags(&tags))
+    }
+
+    pub fn verify_file(
+        &self,
+        data: &[u8],
+        chunk_size: usize,
+        expected_tag: u64,
+    ) -> Result<bool, ManagerError> {
+        Ok(self.file_tag(data, chunk_size)? == expected_tag)
+    }
+}
+
+/// Whole-file tag: the integrity tag over each chunk's tag, so chunks hash independently.
+pub fn file_tag(data: &[u8], chunk_size: usize) -> Result<u64, ManagerError> {
+    if chunk_size == 0 {
+        return Err(ManagerError::InvalidConfig("chunk_size must be > 0"));
+    }
+    let tags: Vec<u64> = data.chunks(chunk_size).map(integrity_tag).collect();
+    Ok(combine_tags(&tags))
+}
+
+fn combine_tags(tags: &[u64]) -> u64 {
+    let bytes: Vec<u8> = tags.iter().flat_map(|t| t.to_be_bytes()).collect();
+    integrity_tag(&bytes)
+}
@@ -1,5 +1,7 @@
 use large_file_manager::{
-    assemble_file, integrity_tag, verify_integrity, LargeFileManager, TransferState,
+    apply_mirror, assemble_file, file_tag, integrity_tag, plan_mirror, verify_integrity,
+    DirectorySignature, LargeFileManager, MirrorAction, MirrorOptions, ParallelVerifier,
+    TransferState, VerifyConfig,
 };
 use std::collections::BTreeMap;
 
@@ -62,6 +64,35 @@ fn assemble_and_verify_integrity() {
     assert!(!verify_integrity(&file, tag.wrapping_add(1)));
 }
 
+#[test]
+fn parallel_verification_matches_serial_for_any_thread_count() {
+    let chunks: BTreeMap<u32, Vec<u8>> = (0..97u32)
+        .map(|i| (i, (0..1000u32).map(|b| (b * 31 + i) as u8).collect()))
+        .collect();
+    let expected: Vec<u64> = chunks.values().map(|c| integrity_tag(c)).collect();
+    let data = assemble_file(97, &chunks).expect("assemble");
+    let serial = file_tag(&data, 1000).expect("serial tag");
+
+    for threads in [1, 2, 4, 0] {
+        let verifier = ParallelVerifier::new(VerifyConfig { threads }).expect("pool");
+        if threads > 0 {
+            assert_eq!(verifier.threads(), threads);
+        }
+        let owned: Vec<Vec<u8>> = chunks.values().cloned().collect();
+        assert_eq!(verifier.chunk_tags(&owned), expected);
+        assert!(verifier.verify_chunks(&chunks, &expected).is_empty());
+        assert_eq!(verifier.file_tag(&data, 1000).expect("tag"), serial);
+        assert!(verifier.verify_file(&data, 1000, serial).expect("verify"));
+    }
+
+    let mut damaged = chunks.clone();
+    damaged.get_mut(&40).expect("chunk")[0] ^= 1;
+    damaged.remove(&90);
+    let verifier = ParallelVerifier::new(VerifyConfig { threads: 3 }).expect("pool");
+    assert_eq!(verifier.verify_chunks(&damaged, &expected), vec![40, 90]);
+    assert!(verifier.file_tag(&data, 0).is_err());
+}
+
 #[test]
 fn missing_chunk_fails_assembly() {
     let mut chunks = BTreeMap::new();
@@ -70,3 +101,47 @@ fn missing_chunk_fails_assembly() {
     let err = assemble_file(2, &chunks).expect_err("should fail");
     assert_eq!(err.to_string(), "missing chunk 1");
 }
+
+#[test]
+fn mirror_plans_delta_and_only_deletes_when_enabled() {
+    let src = tempfile::tempdir().expect("src");
+    let dst = tempfile::tempdir().expect("dst");
+    std::fs::create_dir_all(src.path().join("docs")).expect("src dir");
+    std::fs::create_dir_all(dst.path().join("docs")).expect("dst dir");
+    std::fs::write(src.path().join("docs/a.txt"), b"aaaabbbbcccc").expect("src a");
+    std::fs::write(dst.path().join("docs/a.txt"), b"aaaaXXXXcccc!!").expect("dst a");
+    std::fs::write(src.path().join("b.txt"), b"new").expect("src b");
+    std::fs::write(dst.path().join("old.txt"), b"gone").expect("dst old");
+
+    let source = DirectorySignature::scan(src.path(), 4).expect("scan src");
+    let dest = DirectorySignature::scan(dst.path(), 4).expect("scan dst");
+
+    let dry = plan_mirror(&source, &dest, MirrorOptions::default()).expect("plan");
+    assert_eq!(dry.extraneous, vec![std::path::PathBuf::from("old.txt")]);
+    assert!(!dry
+        .actions
+        .iter()
+        .any(|a| matches!(a, MirrorAction::Delete { .. })));
+
+    let plan = plan_mirror(
+        &source,
+        &dest,
+        MirrorOptions {
+            delete_extraneous: true,
+        },
+    )
+    .expect("plan");
+    assert!(plan.actions.contains(&MirrorAction::Update {
+        path: "docs/a.txt".into(),
+        size: 12,
+        changed_chunks: vec![1],
+    }));
+    assert_eq!(plan.bytes_to_send, 3 + 4);
+
+    apply_mirror(&plan, src.path(), dst.path()).expect("apply");
+    let after = DirectorySignature::scan(dst.path(), 4).expect("rescan");
+    assert_eq!(after, source);
+    assert!(plan_mirror(&source, &after, MirrorOptions::default())
+        .expect("replan")
+        .is_noop());
+}
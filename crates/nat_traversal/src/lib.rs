@@ -1,3 +1,19 @@
+mod pairing;
+mod punch;
+mod relay_policy;
+mod stun;
+
+pub use pairing::{
+    CandidatePair, CandidatePairing, CandidateType, CheckState, ConnectivityReport, PairCheck,
+    PairingError, PairingRole, PairingSession,
+};
+pub use punch::{punch_targets, HolePuncher, PunchError, PunchRole, PunchedPair};
+pub use relay_policy::{decide_route_with_relay_policy, MeteredState, RelayDecision, RelayPolicy};
+pub use stun::{
+    gather_candidates_with_stun, query_reflexive_address, BindingRequest, StunConfig, StunError,
+};
+
+use lan_offline::{LanOfflineGuard, PolicyDecision};
 use std::net::SocketAddr;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -16,17 +32,65 @@ pub enum Route {
     Relay,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CandidateKind {
+    StunReflexive,
+    Relay,
+}
+
+/// A candidate dropped by policy before route selection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandidateExclusion {
+    pub kind: CandidateKind,
+    pub candidate: SocketAddr,
+    pub reason: &'static str,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CandidateSet {
     pub local_candidate: SocketAddr,
     pub stun_reflexive_candidate: Option<SocketAddr>,
     pub relay_candidate: Option<SocketAddr>,
+    pub excluded: Vec<CandidateExclusion>,
+}
+
+impl CandidateSet {
+    /// Drop reflexive and relay candidates the offline LAN guard would refuse.
+    pub fn filtered(&self, guard: &LanOfflineGuard) -> CandidateSet {
+        let mut excluded = self.excluded.clone();
+        let mut keep = |kind: CandidateKind, candidate: Option<SocketAddr>| {
+            let addr = candidate?;
+            match guard.evaluate_peer(addr) {
+                PolicyDecision::Allow => Some(addr),
+                PolicyDecision::Deny(reason) => {
+                    excluded.push(CandidateExclusion {
+                        kind,
+                        candidate: addr,
+                        reason,
+                    });
+                    None
+                }
+            }
+        };
+
+        let stun_reflexive_candidate =
+            keep(CandidateKind::StunReflexive, self.stun_reflexive_candidate);
+        let relay_candidate = keep(CandidateKind::Relay, self.relay_candidate);
+        CandidateSet {
+            local_candidate: self.local_candidate,
+            stun_reflexive_candidate,
+            relay_candidate,
+            excluded,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConnectivityPlan {
     pub route: Route,
     pub reason: &'static str,
+    /// Notes on candidates excluded by policy before the route was chosen.
+    pub trace: Vec<String>,
 }
 
 pub fn gather_candidates(
@@ -38,51 +102,89 @@ pub fn gather_candidates(
         local_candidate,
         stun_reflexive_candidate,
         relay_candidate,
+        excluded: Vec::new(),
     }
 }
 
+/// Gather candidates, dropping public reflexive and WAN relay addresses under offline LAN mode.
+pub fn gather_candidates_with_guard(
+    local_candidate: SocketAddr,
+    stun_reflexive_candidate: Option<SocketAddr>,
+    relay_candidate: Option<SocketAddr>,
+    guard: &LanOfflineGuard,
+) -> CandidateSet {
+    gather_candidates(local_candidate, stun_reflexive_candidate, relay_candidate).filtered(guard)
+}
+
+/// Decide a route after applying the offline LAN guard to both peers' candidates.
+pub fn decide_route_with_guard(
+    local_nat: NatType,
+    remote_nat: NatType,
+    local: &CandidateSet,
+    remote: &CandidateSet,
+    guard: &LanOfflineGuard,
+) -> ConnectivityPlan {
+    decide_route(local_nat, remote_nat, &local.filtered(guard), &remote.filtered(guard))
+}
+
 /// Decide direct vs relay route from NAT signals and available candidates.
+///
+/// This is a guess made before any packet is sent; [`CandidatePairing`] checks which
+/// candidate pairs actually work.
 pub fn decide_route(
     local_nat: NatType,
     remote_nat: NatType,
     local: &CandidateSet,
     remote: &CandidateSet,
 ) -> ConnectivityPlan {
-    let both_have_reflexive = local.stun_reflexive_candidate.is_some() && remote.stun_reflexive_candidate.is_some();
+    let (route, reason) = choose_route(local_nat, remote_nat, local, remote);
+    let trace = [("local", local), ("remote", remote)]
+        .iter()
+        .flat_map(|(side, set)| {
+            set.excluded.iter().map(move |e| {
+                let kind = match e.kind {
+                    CandidateKind::StunReflexive => "reflexive",
+                    CandidateKind::Relay => "relay",
+                };
+                format!("excluded {side} {kind} candidate {}: {}", e.candidate, e.reason)
+            })
+        })
+        .collect();
+
+    ConnectivityPlan { route, reason, trace }
+}
+
+fn choose_route(
+    local_nat: NatType,
+    remote_nat: NatType,
+    local: &CandidateSet,
+    remote: &CandidateSet,
+) -> (Route, &'static str) {
+    // A v4 reflexive address cannot reach a v6 one, so on mixed or IPv6-only networks the
+    // pair only counts when both sides share an address family.
+    let both_have_reflexive = match (local.stun_reflexive_candidate, remote.stun_reflexive_candidate) {
+        (Some(l), Some(r)) => l.is_ipv4() == r.is_ipv4(),
+        _ => false,
+    };
     let any_symmetric = matches!(local_nat, NatType::Symmetric) || matches!(remote_nat, NatType::Symmetric);
 
     if any_symmetric {
         if local.relay_candidate.is_some() || remote.relay_candidate.is_some() {
-            return ConnectivityPlan {
-                route: Route::Relay,
-                reason: "symmetric NAT detected; using relay",
-            };
+            return (Route::Relay, "symmetric NAT detected; using relay");
         }
 
-        return ConnectivityPlan {
-            route: Route::Direct,
-            reason: "symmetric NAT detected but relay unavailable; try direct best-effort",
-        };
+        return (Route::Direct, "symmetric NAT detected but relay unavailable; try direct best-effort");
     }
 
     if both_have_reflexive {
-        return ConnectivityPlan {
-            route: Route::Direct,
-            reason: "both peers have reflexive candidates",
-        };
+        return (Route::Direct, "both peers have reflexive candidates");
     }
 
     if local.relay_candidate.is_some() || remote.relay_candidate.is_some() {
-        return ConnectivityPlan {
-            route: Route::Relay,
-            reason: "insufficient direct candidates; fallback to relay",
-        };
+        return (Route::Relay, "insufficient direct candidates; fallback to relay");
     }
 
-    ConnectivityPlan {
-        route: Route::Direct,
-        reason: "default direct route",
-    }
+    (Route::Direct, "default direct route")
 }
 
 pub fn should_attempt_hole_punch(local_nat: NatType, remote_nat: NatType) -> bool {
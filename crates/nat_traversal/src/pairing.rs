@@ -0,0 +1,649 @@
+use crate::punch::{is_transient, keyed_mac, put_addr, read_addr};
+use crate::stun::random_u64;
+use crate::{CandidateSet, Route};
+use hmac::Mac;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8; 4] = b"P2PI";
+const KIND_CHECK: u8 = 1;
+const KIND_CHECK_OK: u8 = 2;
+const KIND_NOMINATE: u8 = 3;
+const KIND_NOMINATE_OK: u8 = 4;
+const MAC_LEN: usize = 32;
+/// Longest packet: both addresses IPv6.
+const MAX_PACKET_LEN: usize = 4 + 1 + 8 + 2 * 19 + MAC_LEN;
+
+/// Where a candidate address came from, which sets its priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CandidateType {
+    Host,
+    /// Learned from a check the peer sent from an address it did not advertise.
+    PeerReflexive,
+    ServerReflexive,
+    Relay,
+}
+
+impl CandidateType {
+    /// RFC 8445 recommended type preference: direct paths first, relays last.
+    fn preference(self) -> u32 {
+        match self {
+            CandidateType::Host => 126,
+            CandidateType::PeerReflexive => 110,
+            CandidateType::ServerReflexive => 100,
+            CandidateType::Relay => 0,
+        }
+    }
+
+    /// RFC 8445 candidate priority for the single component and local preference we use.
+    fn priority(self) -> u32 {
+        (self.preference() << 24) + (65535 << 8) + 255
+    }
+}
+
+/// Which peer picks the pair both end up using. The signaling channel assigns one of each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairingRole {
+    Controlling,
+    Controlled,
+}
+
+/// One local × remote pairing, ordered by `priority`, highest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CandidatePair {
+    pub local: SocketAddr,
+    pub local_type: CandidateType,
+    pub remote: SocketAddr,
+    pub remote_type: CandidateType,
+    /// RFC 8445 pair priority; both peers compute the same value for the same pair.
+    pub priority: u64,
+}
+
+impl CandidatePair {
+    fn new(
+        role: PairingRole,
+        (local, local_type): (SocketAddr, CandidateType),
+        (remote, remote_type): (SocketAddr, CandidateType),
+    ) -> Self {
+        let (g, d) = match role {
+            PairingRole::Controlling => (local_type.priority(), remote_type.priority()),
+            PairingRole::Controlled => (remote_type.priority(), local_type.priority()),
+        };
+        let priority = (u64::from(g.min(d)) << 32) + 2 * u64::from(g.max(d)) + u64::from(g > d);
+        Self {
+            local,
+            local_type,
+            remote,
+            remote_type,
+            priority,
+        }
+    }
+
+    /// Relay when either end is a relay candidate, direct otherwise.
+    pub fn route(&self) -> Route {
+        if self.local_type == CandidateType::Relay || self.remote_type == CandidateType::Relay {
+            Route::Relay
+        } else {
+            Route::Direct
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckState {
+    /// Not started yet.
+    Waiting,
+    InProgress,
+    Succeeded,
+    /// Every retransmission went unanswered.
+    Failed,
+}
+
+/// A pair and how its connectivity check went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairCheck {
+    pub pair: CandidatePair,
+    pub state: CheckState,
+    /// Requests sent, retransmissions included.
+    pub attempts: u32,
+    /// From the first request to the answer, for a succeeded check.
+    pub rtt: Option<Duration>,
+    transaction_id: u64,
+    started: Option<Instant>,
+    next_send: Option<Instant>,
+}
+
+impl PairCheck {
+    fn waiting(pair: CandidatePair) -> Self {
+        Self {
+            pair,
+            state: CheckState::Waiting,
+            attempts: 0,
+            rtt: None,
+            transaction_id: 0,
+            started: None,
+            next_send: None,
+        }
+    }
+}
+
+/// What connectivity checks found, in place of a route guessed from NAT types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectivityReport {
+    /// The pair both peers settled on; `None` when no pair was validated in time.
+    pub nominated: Option<CandidatePair>,
+    /// Every pair, highest priority first.
+    pub checks: Vec<PairCheck>,
+    pub trace: Vec<String>,
+}
+
+impl ConnectivityReport {
+    /// The route the nominated pair takes, if one was nominated.
+    pub fn route(&self) -> Option<Route> {
+        self.nominated.map(|pair| pair.route())
+    }
+}
+
+/// ICE-style connectivity checks over a single UDP socket.
+///
+/// Both peers run [`Self::run`] at about the same time with the same key, one in each
+/// [`PairingRole`]. Each checks its pairs in priority order, a new one every `pacing`,
+/// retransmitting unanswered requests with a doubling timeout. The controlling peer
+/// nominates the best pair once every higher-priority one has failed, and the controlled
+/// peer finishes when it is told which. Every packet carries the pair it belongs to under
+/// an HMAC with the shared key, so only the peer can validate a pair and a packet cannot
+/// be replayed onto another one.
+#[derive(Debug, Clone)]
+pub struct CandidatePairing {
+    key: [u8; 32],
+    role: PairingRole,
+    /// Wait before the first retransmission; doubled after each one.
+    pub initial_rto: Duration,
+    /// Requests per check, the first included.
+    pub max_attempts: u32,
+    /// Gap between starting one check and the next.
+    pub pacing: Duration,
+    /// Give up on nominating a pair after this long.
+    pub timeout: Duration,
+}
+
+impl CandidatePairing {
+    /// `key` is a per-attempt secret both peers got from signaling.
+    pub fn new(key: [u8; 32], role: PairingRole) -> Self {
+        Self {
+            key,
+            role,
+            initial_rto: Duration::from_millis(100),
+            max_attempts: 5,
+            pacing: Duration::from_millis(20),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Pairs of `local` × `remote` candidates in one address family, highest priority first.
+    ///
+    /// Checks are sent from the socket's own address, so a local reflexive candidate is
+    /// replaced by that base and duplicates are pruned. Local relay candidates would need
+    /// an allocation on the relay to send from and are left out.
+    pub fn pairs(&self, local: &CandidateSet, remote: &CandidateSet) -> Vec<CandidatePair> {
+        let base = local.local_candidate;
+        let locals = [
+            Some((base, CandidateType::Host)),
+            local
+                .stun_reflexive_candidate
+                .map(|_| (base, CandidateType::ServerReflexive)),
+        ];
+        let remotes = [
+            Some((remote.local_candidate, CandidateType::Host)),
+            remote
+                .stun_reflexive_candidate
+                .map(|addr| (addr, CandidateType::ServerReflexive)),
+            remote
+                .relay_candidate
+                .map(|addr| (addr, CandidateType::Relay)),
+        ];
+        let mut pairs: Vec<CandidatePair> = Vec::new();
+        for local in locals.into_iter().flatten() {
+            for remote in remotes.into_iter().flatten() {
+                if local.0.is_ipv4() != remote.0.is_ipv4() {
+                    continue;
+                }
+                let pair = CandidatePair::new(self.role, local, remote);
+                match pairs
+                    .iter_mut()
+                    .find(|p| p.local == pair.local && p.remote == pair.remote)
+                {
+                    Some(existing) if existing.priority >= pair.priority => {}
+                    Some(existing) => *existing = pair,
+                    None => pairs.push(pair),
+                }
+            }
+        }
+        pairs.sort_by_key(|pair| std::cmp::Reverse(pair.priority));
+        pairs
+    }
+
+    /// Check every pair from `socket` and agree with the peer on one.
+    ///
+    /// Fails on socket errors and when there is nothing to check; running out of time
+    /// gives a report with no nominated pair, leaving the caller to fall back to a relay.
+    /// The session ends with the report; use [`Self::start`] to keep answering the peer.
+    pub fn run(
+        &self,
+        socket: &UdpSocket,
+        local: &CandidateSet,
+        remote: &CandidateSet,
+    ) -> Result<ConnectivityReport, PairingError> {
+        self.start(socket, local, remote)?.run()
+    }
+
+    /// Set up the checks of `local` × `remote` over `socket` without sending anything yet.
+    ///
+    /// The session answers the peer's checks and nominations for as long as it lives.
+    /// After [`PairingSession::run`] returns, pass it the packets the transfer does not
+    /// recognise, so a peer whose answer was lost can still finish; dropping it closes
+    /// the session.
+    pub fn start<'a>(
+        &'a self,
+        socket: &'a UdpSocket,
+        local: &CandidateSet,
+        remote: &CandidateSet,
+    ) -> Result<PairingSession<'a>, PairingError> {
+        let pairs = self.pairs(local, remote);
+        if pairs.is_empty() {
+            return Err(PairingError::NoPairs);
+        }
+        let own = [
+            Some(local.local_candidate),
+            local.stun_reflexive_candidate,
+            local.relay_candidate,
+        ];
+        Ok(PairingSession {
+            pairing: self,
+            socket,
+            base: local.local_candidate,
+            own: own.into_iter().flatten().collect(),
+            checks: pairs.into_iter().map(PairCheck::waiting).collect(),
+            nomination: None,
+            nominated: None,
+            deadline: Instant::now() + self.timeout,
+            trace: Vec::new(),
+        })
+    }
+
+    fn encode(&self, kind: u8, transaction_id: u64, from: SocketAddr, to: SocketAddr) -> Vec<u8> {
+        // MAGIC | kind(u8) | transaction id(u64 be) | from | to | hmac-sha256(32)
+        let mut out = Vec::with_capacity(MAX_PACKET_LEN);
+        out.extend_from_slice(MAGIC);
+        out.push(kind);
+        out.extend_from_slice(&transaction_id.to_be_bytes());
+        put_addr(&mut out, from);
+        put_addr(&mut out, to);
+        let tag = keyed_mac(&self.key, &out).finalize().into_bytes();
+        out.extend_from_slice(&tag);
+        out
+    }
+
+    fn decode(&self, input: &[u8]) -> Option<Packet> {
+        if input.len() > MAX_PACKET_LEN || input.len() < 13 + MAC_LEN || &input[..4] != MAGIC {
+            return None;
+        }
+        let (body, tag) = input.split_at(input.len() - MAC_LEN);
+        keyed_mac(&self.key, body).verify_slice(tag).ok()?;
+        let (from, rest) = read_addr(&body[13..])?;
+        let (to, rest) = read_addr(rest)?;
+        if !rest.is_empty() {
+            return None;
+        }
+        Some(Packet {
+            kind: body[4],
+            transaction_id: u64::from_be_bytes(body[5..13].try_into().ok()?),
+            from,
+            to,
+        })
+    }
+
+    /// When to resend after `attempts` requests: the initial timeout doubled each time,
+    /// never past `deadline`.
+    fn backoff(&self, now: Instant, attempts: u32, deadline: Instant) -> Instant {
+        let factor = 1u32.checked_shl(attempts).unwrap_or(u32::MAX);
+        now.checked_add(self.initial_rto.saturating_mul(factor))
+            .map_or(deadline, |at| at.min(deadline))
+    }
+}
+
+/// An authenticated packet. `from` and `to` are the pair as its sender saw it.
+struct Packet {
+    kind: u8,
+    transaction_id: u64,
+    from: SocketAddr,
+    to: SocketAddr,
+}
+
+/// The pair the controlling peer is nominating and its retransmission state.
+struct Nomination {
+    check: usize,
+    transaction_id: u64,
+    attempts: u32,
+    next_send: Instant,
+}
+
+/// Checks in progress with one peer; see [`CandidatePairing::start`].
+pub struct PairingSession<'a> {
+    pairing: &'a CandidatePairing,
+    socket: &'a UdpSocket,
+    /// The socket's own address, which every check is sent from.
+    base: SocketAddr,
+    /// Addresses the peer may reach us on: our candidates, plus any mapping its answers
+    /// revealed.
+    own: Vec<SocketAddr>,
+    checks: Vec<PairCheck>,
+    nomination: Option<Nomination>,
+    nominated: Option<CandidatePair>,
+    deadline: Instant,
+    trace: Vec<String>,
+}
+
+impl PairingSession<'_> {
+    /// Run the checks until a pair is nominated or the pairing's timeout passes.
+    pub fn run(&mut self) -> Result<ConnectivityReport, PairingError> {
+        let previous_timeout = self.socket.read_timeout()?;
+        let result = self.check_until_nominated();
+        self.socket.set_read_timeout(previous_timeout)?;
+        let nominated = result?;
+        match nominated {
+            Some(pair) => self.trace.push(format!(
+                "nominated {} -> {} ({:?})",
+                pair.local,
+                pair.remote,
+                pair.route()
+            )),
+            None => self
+                .trace
+                .push("no pair validated before the timeout".to_string()),
+        }
+        Ok(ConnectivityReport {
+            nominated,
+            checks: self.checks.clone(),
+            trace: self.trace.clone(),
+        })
+    }
+
+    /// Answer a pairing packet that arrived after [`Self::run`] returned; `false` when
+    /// `input` is not one, so it belongs to the caller.
+    pub fn handle_packet(
+        &mut self,
+        input: &[u8],
+        source: SocketAddr,
+    ) -> Result<bool, PairingError> {
+        self.handle(input, source, Instant::now())
+    }
+
+    /// The pair the peers settled on, once there is one. On the controlled side a later
+    /// nomination from the peer replaces it.
+    pub fn nominated(&self) -> Option<CandidatePair> {
+        self.nominated
+    }
+
+    fn check_until_nominated(&mut self) -> Result<Option<CandidatePair>, PairingError> {
+        self.deadline = Instant::now() + self.pairing.timeout;
+        let mut next_start = Instant::now();
+        let mut buf = [0u8; 128];
+        loop {
+            let now = Instant::now();
+            if now >= self.deadline {
+                return Ok(None);
+            }
+            if now >= next_start {
+                if let Some(index) = self
+                    .checks
+                    .iter()
+                    .position(|c| c.state == CheckState::Waiting)
+                {
+                    self.start_check(index, now)?;
+                }
+                next_start = now + self.pairing.pacing;
+            }
+            self.retransmit(now)?;
+            if self.pairing.role == PairingRole::Controlling {
+                self.nominate(now)?;
+            }
+
+            let wake = self
+                .checks
+                .iter()
+                .filter_map(|c| c.next_send)
+                .chain(self.nomination.as_ref().map(|n| n.next_send))
+                .chain(Some(next_start))
+                .fold(self.deadline, Instant::min);
+            self.socket.set_read_timeout(Some(
+                wake.saturating_duration_since(now)
+                    .max(Duration::from_millis(1)),
+            ))?;
+            let (n, source) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(err) if is_transient(&err) => continue,
+                Err(err) => return Err(err.into()),
+            };
+            self.handle(&buf[..n], source, Instant::now())?;
+            if self.nominated.is_some() {
+                return Ok(self.nominated);
+            }
+        }
+    }
+
+    fn handle(
+        &mut self,
+        input: &[u8],
+        source: SocketAddr,
+        now: Instant,
+    ) -> Result<bool, PairingError> {
+        let Some(packet) = self.pairing.decode(input) else {
+            return Ok(false);
+        };
+        match packet.kind {
+            // Requests name the address they were sent to; one meant for another of our
+            // addresses, or another session, is not answered.
+            KIND_CHECK if self.own.contains(&packet.to) => {
+                self.send(KIND_CHECK_OK, packet.transaction_id, packet.to, source)?;
+                let index = match self.checks.iter().position(|c| c.pair.remote == source) {
+                    Some(index) => index,
+                    None => self.add_peer_reflexive(source),
+                };
+                // A triggered check: the peer just opened its side towards us.
+                if self.checks[index].state == CheckState::Waiting {
+                    self.start_check(index, now)?;
+                }
+            }
+            KIND_CHECK_OK => {
+                if let Some(check) = self.checks.iter_mut().find(|c| {
+                    c.state == CheckState::InProgress
+                        && c.transaction_id == packet.transaction_id
+                        && c.pair.remote == source
+                        && packet.from == source
+                }) {
+                    check.state = CheckState::Succeeded;
+                    check.next_send = None;
+                    check.rtt = check.started.map(|started| now.duration_since(started));
+                    self.trace.push(format!(
+                        "check {} -> {} succeeded after {} request(s)",
+                        check.pair.local, check.pair.remote, check.attempts
+                    ));
+                    // The address the peer saw us at; it may check that one too.
+                    if !self.own.contains(&packet.to) {
+                        self.own.push(packet.to);
+                    }
+                }
+            }
+            KIND_NOMINATE
+                if self.pairing.role == PairingRole::Controlled
+                    && self.own.contains(&packet.to) =>
+            {
+                // Only a pair our own check validated can be nominated; until then the
+                // peer's retransmissions go unanswered.
+                let Some(pair) = self
+                    .checks
+                    .iter()
+                    .find(|c| c.pair.remote == source && c.state == CheckState::Succeeded)
+                    .map(|c| c.pair)
+                else {
+                    return Ok(true);
+                };
+                self.send(KIND_NOMINATE_OK, packet.transaction_id, packet.to, source)?;
+                self.nominated = Some(pair);
+            }
+            KIND_NOMINATE_OK => {
+                if let Some(nomination) = &self.nomination {
+                    let pair = self.checks[nomination.check].pair;
+                    if nomination.transaction_id == packet.transaction_id
+                        && pair.remote == source
+                        && packet.from == source
+                    {
+                        self.nominated = Some(pair);
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    /// A check from an address the peer did not advertise gets a pair of its own, in
+    /// priority order.
+    fn add_peer_reflexive(&mut self, source: SocketAddr) -> usize {
+        let pair = CandidatePair::new(
+            self.pairing.role,
+            (self.base, CandidateType::Host),
+            (source, CandidateType::PeerReflexive),
+        );
+        let index = self
+            .checks
+            .partition_point(|c| c.pair.priority >= pair.priority);
+        self.checks.insert(index, PairCheck::waiting(pair));
+        if let Some(nomination) = &mut self.nomination {
+            if nomination.check >= index {
+                nomination.check += 1;
+            }
+        }
+        self.trace.push(format!(
+            "check from unadvertised address {source}; added it as peer-reflexive"
+        ));
+        index
+    }
+
+    fn start_check(&mut self, index: usize, now: Instant) -> Result<(), PairingError> {
+        let check = &mut self.checks[index];
+        check.state = CheckState::InProgress;
+        check.transaction_id = random_u64();
+        check.started = Some(now);
+        check.next_send = Some(now);
+        self.retransmit(now)
+    }
+
+    /// Send requests that are due, failing checks that have used up their attempts.
+    fn retransmit(&mut self, now: Instant) -> Result<(), PairingError> {
+        for check in &mut self.checks {
+            if check.state != CheckState::InProgress || check.next_send.is_some_and(|at| at > now) {
+                continue;
+            }
+            if check.attempts == self.pairing.max_attempts {
+                check.state = CheckState::Failed;
+                check.next_send = None;
+                self.trace.push(format!(
+                    "check {} -> {} failed after {} request(s)",
+                    check.pair.local, check.pair.remote, check.attempts
+                ));
+                continue;
+            }
+            self.socket.send_to(
+                &self.pairing.encode(
+                    KIND_CHECK,
+                    check.transaction_id,
+                    check.pair.local,
+                    check.pair.remote,
+                ),
+                check.pair.remote,
+            )?;
+            check.next_send = Some(self.pairing.backoff(now, check.attempts, self.deadline));
+            check.attempts += 1;
+        }
+        Ok(())
+    }
+
+    /// Controlling side: nominate the best succeeded pair once no better one is pending.
+    /// A nomination that goes unanswered fails its pair, and the next best is tried.
+    fn nominate(&mut self, now: Instant) -> Result<(), PairingError> {
+        if let Some(nomination) = &mut self.nomination {
+            if nomination.next_send > now {
+                return Ok(());
+            }
+            let check = nomination.check;
+            if nomination.attempts == self.pairing.max_attempts {
+                self.checks[check].state = CheckState::Failed;
+                self.trace.push(format!(
+                    "nomination of {} -> {} went unanswered",
+                    self.checks[check].pair.local, self.checks[check].pair.remote
+                ));
+                self.nomination = None;
+                return Ok(());
+            }
+            nomination.next_send = self
+                .pairing
+                .backoff(now, nomination.attempts, self.deadline);
+            nomination.attempts += 1;
+            let transaction_id = nomination.transaction_id;
+            let pair = self.checks[check].pair;
+            return self.send(KIND_NOMINATE, transaction_id, pair.local, pair.remote);
+        }
+        let best = self
+            .checks
+            .iter()
+            .position(|c| c.state != CheckState::Failed);
+        if let Some(check) = best.filter(|&i| self.checks[i].state == CheckState::Succeeded) {
+            self.nomination = Some(Nomination {
+                check,
+                transaction_id: random_u64(),
+                attempts: 0,
+                next_send: now,
+            });
+            return self.nominate(now);
+        }
+        Ok(())
+    }
+
+    fn send(
+        &self,
+        kind: u8,
+        transaction_id: u64,
+        from: SocketAddr,
+        to: SocketAddr,
+    ) -> Result<(), PairingError> {
+        self.socket
+            .send_to(&self.pairing.encode(kind, transaction_id, from, to), to)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum PairingError {
+    Io(std::io::Error),
+    /// Neither side has a candidate the other can be checked against.
+    NoPairs,
+}
+
+impl std::fmt::Display for PairingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PairingError::Io(e) => write!(f, "I/O error: {e}"),
+            PairingError::NoPairs => write!(f, "no candidate pairs to check"),
+        }
+    }
+}
+
+impl std::error::Error for PairingError {}
+
+impl From<std::io::Error> for PairingError {
+    fn from(value: std::io::Error) -> Self {
+        PairingError::Io(value)
+    }
+}
@@ -0,0 +1,308 @@
+use crate::stun::random_u64;
+use crate::CandidateSet;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+const MAGIC: &[u8; 4] = b"P2PU";
+const KIND_PROBE: u8 = 1;
+const KIND_ACK: u8 = 2;
+const MAC_LEN: usize = 32;
+/// Acks sent back once a path works, in case the first is lost.
+const FINAL_ACKS: usize = 3;
+/// MAGIC | kind | role | nonce | echo
+const HEADER_LEN: usize = 4 + 1 + 1 + 8 + 8;
+
+/// Which end of the signaling exchange a peer is. The two peers take different roles,
+/// so a peer's own probes reflected back at it are not mistaken for the other's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PunchRole {
+    Initiator,
+    Responder,
+}
+
+impl PunchRole {
+    fn as_u8(self) -> u8 {
+        match self {
+            PunchRole::Initiator => 0,
+            PunchRole::Responder => 1,
+        }
+    }
+}
+
+/// The addresses a punched path runs between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PunchedPair {
+    /// The candidate of ours the peer reached us on.
+    pub local: SocketAddr,
+    /// The peer address its packet came from.
+    pub remote: SocketAddr,
+}
+
+/// Opens a direct UDP path through both peers' NATs by simultaneous open.
+///
+/// Both peers call [`Self::punch`] with the same key and start time, agreed over the
+/// signaling channel. From that moment each sends bursts of probes to every direct
+/// candidate of the other, so each NAT sees outbound traffic to the peer before the peer's
+/// probes arrive. Probes carry an HMAC under the shared key; anything else is ignored, so
+/// a third party on the path cannot pose as the peer. Acks echo a nonce fresh to each
+/// attempt, so only an answer to one of this attempt's probes completes it.
+#[derive(Debug, Clone)]
+pub struct HolePuncher {
+    key: [u8; 32],
+    role: PunchRole,
+    /// Pause between bursts.
+    pub interval: Duration,
+    /// How long after the start time to keep trying.
+    pub timeout: Duration,
+}
+
+impl HolePuncher {
+    /// `key` is a per-attempt secret both peers got from signaling, which also assigned
+    /// each its `role`.
+    pub fn new(key: [u8; 32], role: PunchRole) -> Self {
+        Self {
+            key,
+            role,
+            interval: Duration::from_millis(50),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Punch from `socket`, which should be the one `local` was gathered for, so the
+    /// reflexive mapping the peer aims at is the one being opened.
+    ///
+    /// Returns on the first authenticated packet from the peer that reached one of
+    /// `local`'s candidates, after acking it so a peer still waiting finishes too.
+    pub fn punch(
+        &self,
+        socket: &UdpSocket,
+        local: &CandidateSet,
+        remote: &CandidateSet,
+        start_at: SystemTime,
+    ) -> Result<PunchedPair, PunchError> {
+        let targets = punch_targets(local, remote);
+        if targets.is_empty() {
+            return Err(PunchError::NoCandidates);
+        }
+        if let Ok(wait) = start_at.duration_since(SystemTime::now()) {
+            thread::sleep(wait);
+        }
+
+        let previous_timeout = socket.read_timeout()?;
+        let result = self.run(socket, local, &targets);
+        socket.set_read_timeout(previous_timeout)?;
+        result
+    }
+
+    fn run(
+        &self,
+        socket: &UdpSocket,
+        local: &CandidateSet,
+        targets: &[SocketAddr],
+    ) -> Result<PunchedPair, PunchError> {
+        let nonce = random_u64();
+        let deadline = Instant::now() + self.timeout;
+        let mut next_burst = Instant::now();
+        let mut buf = [0u8; 128];
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(PunchError::TimedOut);
+            }
+            if now >= next_burst {
+                for &target in targets {
+                    socket.send_to(&self.encode(KIND_PROBE, nonce, 0, target), target)?;
+                }
+                next_burst = now + self.interval;
+            }
+
+            socket.set_read_timeout(Some(
+                next_burst
+                    .min(deadline)
+                    .saturating_duration_since(now)
+                    .max(Duration::from_millis(1)),
+            ))?;
+            let (n, source) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(err) if is_transient(&err) => continue,
+                Err(err) => return Err(err.into()),
+            };
+            let Some(packet) = self.decode(&buf[..n]) else {
+                continue;
+            };
+            let reached = packet.target;
+            // An ack must answer this attempt's probes, and either packet must have been
+            // aimed at an address we advertised.
+            if (packet.kind == KIND_ACK && packet.echo != nonce) || !is_candidate(local, reached) {
+                continue;
+            }
+            for _ in 0..FINAL_ACKS {
+                socket.send_to(&self.encode(KIND_ACK, nonce, packet.nonce, source), source)?;
+            }
+            return Ok(PunchedPair {
+                local: reached,
+                remote: source,
+            });
+        }
+    }
+
+    fn encode(&self, kind: u8, nonce: u64, echo: u64, target: SocketAddr) -> Vec<u8> {
+        // MAGIC | kind(u8) | role(u8) | nonce(u64 be) | echo(u64 be) | target | hmac(32)
+        //
+        // `nonce` is the sender's for this attempt; an ack echoes the probe's.
+        let mut out = Vec::with_capacity(HEADER_LEN + 19 + MAC_LEN);
+        out.extend_from_slice(MAGIC);
+        out.push(kind);
+        out.push(self.role.as_u8());
+        out.extend_from_slice(&nonce.to_be_bytes());
+        out.extend_from_slice(&echo.to_be_bytes());
+        put_addr(&mut out, target);
+        let tag = keyed_mac(&self.key, &out).finalize().into_bytes();
+        out.extend_from_slice(&tag);
+        out
+    }
+
+    /// An authenticated probe or ack from the peer's role, or `None` for anything else.
+    fn decode(&self, input: &[u8]) -> Option<Packet> {
+        if input.len() < HEADER_LEN + MAC_LEN
+            || &input[..4] != MAGIC
+            || !matches!(input[4], KIND_PROBE | KIND_ACK)
+            || input[5] == self.role.as_u8()
+        {
+            return None;
+        }
+        let (body, tag) = input.split_at(input.len() - MAC_LEN);
+        keyed_mac(&self.key, body).verify_slice(tag).ok()?;
+        let (target, rest) = read_addr(&body[HEADER_LEN..])?;
+        if !rest.is_empty() {
+            return None;
+        }
+        Some(Packet {
+            kind: body[4],
+            nonce: u64::from_be_bytes(body[6..14].try_into().ok()?),
+            echo: u64::from_be_bytes(body[14..22].try_into().ok()?),
+            target,
+        })
+    }
+}
+
+struct Packet {
+    kind: u8,
+    nonce: u64,
+    echo: u64,
+    target: SocketAddr,
+}
+
+fn is_candidate(set: &CandidateSet, addr: SocketAddr) -> bool {
+    addr == set.local_candidate || set.stun_reflexive_candidate == Some(addr)
+}
+
+/// An ICMP error for an earlier datagram, e.g. to a candidate nobody listens on, surfaces
+/// on the next receive on some platforms; it says nothing about the packets still coming.
+pub(crate) fn is_transient(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionRefused
+    )
+}
+
+/// family(u8: 4 or 6) | ip(4 or 16) | port(u16 be)
+pub(crate) fn put_addr(out: &mut Vec<u8>, addr: SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            out.push(4);
+            out.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            out.push(6);
+            out.extend_from_slice(&ip.octets());
+        }
+    }
+    out.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+/// An address written by [`put_addr`] and what follows it.
+pub(crate) fn read_addr(input: &[u8]) -> Option<(SocketAddr, &[u8])> {
+    let (ip, rest) = match input.split_first()? {
+        (4, rest) if rest.len() >= 6 => {
+            let (ip, rest) = rest.split_at(4);
+            (
+                IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(ip).ok()?)),
+                rest,
+            )
+        }
+        (6, rest) if rest.len() >= 18 => {
+            let (ip, rest) = rest.split_at(16);
+            (
+                IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(ip).ok()?)),
+                rest,
+            )
+        }
+        _ => return None,
+    };
+    let (port, rest) = rest.split_at(2);
+    Some((
+        SocketAddr::new(ip, u16::from_be_bytes([port[0], port[1]])),
+        rest,
+    ))
+}
+
+/// HMAC-SHA256 of `body` under a per-attempt key from signaling.
+pub(crate) fn keyed_mac(key: &[u8; 32], body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(body);
+    mac
+}
+
+/// The peer's direct candidates this side can reach: its local address and its reflexive
+/// one, in the address family of our own socket. Relay candidates are not punched.
+pub fn punch_targets(local: &CandidateSet, remote: &CandidateSet) -> Vec<SocketAddr> {
+    let family_v4 = local.local_candidate.is_ipv4();
+    let mut targets = Vec::new();
+    for candidate in [
+        Some(remote.local_candidate),
+        remote.stun_reflexive_candidate,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        if candidate.is_ipv4() == family_v4 && !targets.contains(&candidate) {
+            targets.push(candidate);
+        }
+    }
+    targets
+}
+
+#[derive(Debug)]
+pub enum PunchError {
+    Io(std::io::Error),
+    /// The peer has no direct candidate in our address family.
+    NoCandidates,
+    /// No authenticated packet arrived before the timeout.
+    TimedOut,
+}
+
+impl std::fmt::Display for PunchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PunchError::Io(e) => write!(f, "I/O error: {e}"),
+            PunchError::NoCandidates => write!(f, "peer has no direct candidates to punch"),
+            PunchError::TimedOut => write!(f, "hole punching timed out"),
+        }
+    }
+}
+
+impl std::error::Error for PunchError {}
+
+impl From<std::io::Error> for PunchError {
+    fn from(value: std::io::Error) -> Self {
+        PunchError::Io(value)
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{
+    decide_route, CandidateExclusion, CandidateKind, CandidateSet, ConnectivityPlan, NatType,
+};
+use std::collections::HashMap;
+
+/// Settings key prefix for per-network relay overrides (`relay_enabled.<network> = false`).
+const NETWORK_OVERRIDE_PREFIX: &str = "relay_enabled.";
+
+/// Whether the active connection costs money per byte.
+///
+/// The manual toggle always wins; the OS hint (e.g. Android's metered flag or a
+/// NetworkManager connection property) fills in when the user has not chosen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MeteredState {
+    pub manual: Option<bool>,
+    pub os_hint: Option<bool>,
+}
+
+impl MeteredState {
+    pub fn is_metered(&self) -> bool {
+        self.manual.or(self.os_hint).unwrap_or(false)
+    }
+
+    fn reason(&self) -> &'static str {
+        if self.manual.is_some() {
+            "metered connection (manual toggle)"
+        } else {
+            "metered connection (OS hint)"
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayPolicy {
+    /// Global `relay_enabled` setting.
+    pub relay_enabled: bool,
+    /// Per-network choices keyed by network id (SSID or interface profile). An override
+    /// is the user's explicit call for that network, so it beats both the global setting
+    /// and metered avoidance.
+    pub network_overrides: HashMap<String, bool>,
+    pub avoid_relay_when_metered: bool,
+    pub metered: MeteredState,
+}
+
+impl Default for RelayPolicy {
+    fn default() -> Self {
+        Self {
+            relay_enabled: true,
+            network_overrides: HashMap::new(),
+            avoid_relay_when_metered: true,
+            metered: MeteredState::default(),
+        }
+    }
+}
+
+/// Outcome of the relay policy, with the rule that produced it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelayDecision {
+    pub allowed: bool,
+    pub reason: &'static str,
+}
+
+impl RelayPolicy {
+    /// Read `relay_enabled`, `relay_enabled.<network>`, `avoid_relay_when_metered` and
+    /// `metered` (`true`, `false` or `auto`) from a settings file.
+    ///
+    /// Unknown keys and unparsable values are ignored.
+    pub fn apply_settings(&mut self, contents: &str) {
+        for line in contents.lines() {
+            let Some((key, value)) = line.trim().split_once('=') else {
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+            if key == "metered" {
+                if value.eq_ignore_ascii_case("auto") {
+                    self.metered.manual = None;
+                } else if let Some(metered) = parse_bool(value) {
+                    self.metered.manual = Some(metered);
+                }
+                continue;
+            }
+            let Some(enabled) = parse_bool(value) else {
+                continue;
+            };
+            match key {
+                "relay_enabled" => self.relay_enabled = enabled,
+                "avoid_relay_when_metered" => self.avoid_relay_when_metered = enabled,
+                _ => {
+                    if let Some(network) = key.strip_prefix(NETWORK_OVERRIDE_PREFIX) {
+                        self.network_overrides.insert(network.to_string(), enabled);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Decide whether relays may be used on `network` (`None` when the network is unidentified).
+    pub fn evaluate(&self, network: Option<&str>) -> RelayDecision {
+        if let Some(&allowed) = network.and_then(|n| self.network_overrides.get(n)) {
+            let reason = if allowed {
+                "relay enabled by network override"
+            } else {
+                "relay disabled by network override"
+            };
+            return RelayDecision { allowed, reason };
+        }
+        if !self.relay_enabled {
+            return RelayDecision {
+                allowed: false,
+                reason: "relay disabled in settings",
+            };
+        }
+        if self.avoid_relay_when_metered && self.metered.is_metered() {
+            return RelayDecision {
+                allowed: false,
+                reason: self.metered.reason(),
+            };
+        }
+        RelayDecision {
+            allowed: true,
+            reason: "relay allowed",
+        }
+    }
+}
+
+/// Decide a route with relay candidates withheld when the relay policy forbids them.
+///
+/// The trace ends with the policy outcome, naming the network it was evaluated for.
+pub fn decide_route_with_relay_policy(
+    local_nat: NatType,
+    remote_nat: NatType,
+    local: &CandidateSet,
+    remote: &CandidateSet,
+    policy: &RelayPolicy,
+    network: Option<&str>,
+) -> ConnectivityPlan {
+    let decision = policy.evaluate(network);
+    let mut plan = if decision.allowed {
+        decide_route(local_nat, remote_nat, local, remote)
+    } else {
+        decide_route(
+            local_nat,
+            remote_nat,
+            &without_relay(local, decision.reason),
+            &without_relay(remote, decision.reason),
+        )
+    };
+    plan.trace.push(format!(
+        "relay policy on network {}: {}",
+        network.unwrap_or("unknown"),
+        decision.reason
+    ));
+    plan
+}
+
+fn without_relay(set: &CandidateSet, reason: &'static str) -> CandidateSet {
+    let mut set = set.clone();
+    if let Some(candidate) = set.relay_candidate.take() {
+        set.excluded.push(CandidateExclusion {
+            kind: CandidateKind::Relay,
+            candidate,
+            reason,
+        });
+    }
+    set
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "on" | "yes" | "1" => Some(true),
+        "false" | "off" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
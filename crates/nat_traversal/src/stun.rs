@@ -0,0 +1,282 @@
+use crate::punch::is_transient;
+use crate::{gather_candidates, CandidateSet};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const HEADER_LEN: usize = 20;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const BINDING_ERROR: u16 = 0x0111;
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const FAMILY_IPV4: u8 = 0x01;
+const FAMILY_IPV6: u8 = 0x02;
+
+/// STUN servers to ask and how hard to try each one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StunConfig {
+    /// Tried in order until one answers.
+    pub servers: Vec<SocketAddr>,
+    /// Requests sent to each server before moving on to the next.
+    pub attempts: u32,
+    /// Wait for the first answer; doubled after each unanswered request, as RFC 5389
+    /// retransmits.
+    pub initial_timeout: Duration,
+}
+
+impl StunConfig {
+    pub fn new(servers: Vec<SocketAddr>) -> Self {
+        Self {
+            servers,
+            attempts: 3,
+            initial_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+/// An RFC 5389 Binding Request; the server answers with the address it saw it come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BindingRequest {
+    pub transaction_id: [u8; 12],
+}
+
+impl BindingRequest {
+    /// A request under a fresh random transaction id.
+    pub fn new() -> Self {
+        let mut transaction_id = [0u8; 12];
+        transaction_id[..8].copy_from_slice(&random_u64().to_be_bytes());
+        transaction_id[8..].copy_from_slice(&random_u64().to_be_bytes()[..4]);
+        Self { transaction_id }
+    }
+
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        // type(u16 be) | length(u16 be) | magic cookie(u32 be) | transaction id(12)
+        let mut out = [0u8; HEADER_LEN];
+        out[..2].copy_from_slice(&BINDING_REQUEST.to_be_bytes());
+        out[4..8].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        out[8..].copy_from_slice(&self.transaction_id);
+        out
+    }
+
+    /// The reflexive address in a Binding success response to this request.
+    ///
+    /// XOR-MAPPED-ADDRESS is preferred over MAPPED-ADDRESS, which some NATs rewrite.
+    pub fn parse_response(&self, input: &[u8]) -> Result<SocketAddr, StunError> {
+        if input.len() < HEADER_LEN
+            || input[0] & 0xc0 != 0
+            || u32::from_be_bytes([input[4], input[5], input[6], input[7]]) != MAGIC_COOKIE
+        {
+            return Err(StunError::InvalidResponse("not a STUN message"));
+        }
+        if input[8..HEADER_LEN] != self.transaction_id {
+            return Err(StunError::InvalidResponse("transaction id mismatch"));
+        }
+        let body_len = u16::from_be_bytes([input[2], input[3]]) as usize;
+        if !body_len.is_multiple_of(4) || input.len() != HEADER_LEN + body_len {
+            return Err(StunError::InvalidResponse("bad message length"));
+        }
+        match u16::from_be_bytes([input[0], input[1]]) {
+            BINDING_SUCCESS => {}
+            BINDING_ERROR => return Err(StunError::ErrorResponse),
+            _ => return Err(StunError::InvalidResponse("not a binding response")),
+        }
+
+        let mut mapped = None;
+        let mut xor_mapped = None;
+        let mut idx = HEADER_LEN;
+        while idx < input.len() {
+            if idx + 4 > input.len() {
+                return Err(StunError::InvalidResponse("truncated attribute"));
+            }
+            let kind = u16::from_be_bytes([input[idx], input[idx + 1]]);
+            let len = u16::from_be_bytes([input[idx + 2], input[idx + 3]]) as usize;
+            let value = input
+                .get(idx + 4..idx + 4 + len)
+                .ok_or(StunError::InvalidResponse("truncated attribute"))?;
+            match kind {
+                ATTR_MAPPED_ADDRESS => mapped = Some(read_address(value, None)?),
+                ATTR_XOR_MAPPED_ADDRESS => {
+                    xor_mapped = Some(read_address(value, Some(&self.transaction_id))?)
+                }
+                _ => {}
+            }
+            // Attribute values are padded to a multiple of four bytes.
+            idx += 4 + len.div_ceil(4) * 4;
+        }
+        xor_mapped
+            .or(mapped)
+            .ok_or(StunError::InvalidResponse("no mapped address"))
+    }
+}
+
+impl Default for BindingRequest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Ask `config`'s servers, over `socket`, for the address the socket is seen from outside.
+///
+/// Asking over the socket transfers will use keeps the NAT mapping the answer describes.
+/// Packets that are not an answer to the pending request are read and dropped, so do this
+/// before the socket carries other traffic. The socket's read timeout is restored after.
+pub fn query_reflexive_address(
+    socket: &UdpSocket,
+    config: &StunConfig,
+) -> Result<SocketAddr, StunError> {
+    let previous_timeout = socket.read_timeout()?;
+    let result = query_servers(socket, config);
+    socket.set_read_timeout(previous_timeout)?;
+    result
+}
+
+/// Gather candidates for `socket`, with the reflexive one filled in from STUN.
+///
+/// When no server answers the set has no reflexive candidate, as if none were configured;
+/// only failing to read the socket's own address is an error.
+pub fn gather_candidates_with_stun(
+    socket: &UdpSocket,
+    config: &StunConfig,
+    relay_candidate: Option<SocketAddr>,
+) -> Result<CandidateSet, StunError> {
+    let local_candidate = socket.local_addr()?;
+    let reflexive = match query_reflexive_address(socket, config) {
+        Ok(addr) => Some(addr),
+        Err(StunError::Io(err)) => return Err(StunError::Io(err)),
+        Err(_) => None,
+    };
+    Ok(gather_candidates(
+        local_candidate,
+        reflexive,
+        relay_candidate,
+    ))
+}
+
+fn query_servers(socket: &UdpSocket, config: &StunConfig) -> Result<SocketAddr, StunError> {
+    let mut last_error = StunError::NoServers;
+    for &server in &config.servers {
+        match query_server(socket, server, config) {
+            Ok(addr) => return Ok(addr),
+            Err(StunError::Io(err)) => return Err(StunError::Io(err)),
+            Err(err) => last_error = err,
+        }
+    }
+    Err(last_error)
+}
+
+fn query_server(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    config: &StunConfig,
+) -> Result<SocketAddr, StunError> {
+    let request = BindingRequest::new();
+    let packet = request.encode();
+    let mut timeout = config.initial_timeout;
+    let mut buf = [0u8; 576];
+    let mut last_error = StunError::Timeout;
+    for _ in 0..config.attempts {
+        socket.send_to(&packet, server)?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            socket.set_read_timeout(Some(left))?;
+            let (n, source) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    break
+                }
+                // An ICMP error for an earlier request; the server may still answer.
+                Err(err) if is_transient(&err) => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if source != server {
+                continue;
+            }
+            match request.parse_response(&buf[..n]) {
+                Ok(addr) => return Ok(addr),
+                // An error response is final; anything unparsable may be a stray packet.
+                Err(StunError::ErrorResponse) => return Err(StunError::ErrorResponse),
+                Err(err) => last_error = err,
+            }
+        }
+        timeout *= 2;
+    }
+    Err(last_error)
+}
+
+fn read_address(value: &[u8], xor_with: Option<&[u8; 12]>) -> Result<SocketAddr, StunError> {
+    // reserved(u8) | family(u8) | port(u16 be) | address(4 or 16)
+    if value.len() < 4 {
+        return Err(StunError::InvalidResponse("truncated address"));
+    }
+    let mut mask = [0u8; 16];
+    if let Some(transaction_id) = xor_with {
+        mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(transaction_id);
+    }
+    let port = u16::from_be_bytes([value[2] ^ mask[0], value[3] ^ mask[1]]);
+    let ip = match (value[1], &value[4..]) {
+        (FAMILY_IPV4, octets) if octets.len() == 4 => {
+            let mut ip = [0u8; 4];
+            for (i, byte) in ip.iter_mut().enumerate() {
+                *byte = octets[i] ^ mask[i];
+            }
+            IpAddr::V4(Ipv4Addr::from(ip))
+        }
+        (FAMILY_IPV6, octets) if octets.len() == 16 => {
+            let mut ip = [0u8; 16];
+            for (i, byte) in ip.iter_mut().enumerate() {
+                *byte = octets[i] ^ mask[i];
+            }
+            IpAddr::V6(Ipv6Addr::from(ip))
+        }
+        _ => return Err(StunError::InvalidResponse("bad address family")),
+    };
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// Random enough for transaction ids, without pulling in an RNG crate.
+pub(crate) fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    hasher.finish()
+}
+
+#[derive(Debug)]
+pub enum StunError {
+    Io(std::io::Error),
+    /// The config lists no servers.
+    NoServers,
+    /// No server answered within the configured attempts.
+    Timeout,
+    /// The server answered the request with a Binding error response.
+    ErrorResponse,
+    InvalidResponse(&'static str),
+}
+
+impl std::fmt::Display for StunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StunError::Io(e) => write!(f, "I/O error: {e}"),
+            StunError::NoServers => write!(f, "no STUN servers configured"),
+            StunError::Timeout => write!(f, "no STUN server answered"),
+            StunError::ErrorResponse => write!(f, "STUN server returned an error response"),
+            StunError::InvalidResponse(msg) => write!(f, "invalid STUN response: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for StunError {}
+
+impl From<std::io::Error> for StunError {
+    fn from(value: std::io::Error) -> Self {
+        StunError::Io(value)
+    }
+}
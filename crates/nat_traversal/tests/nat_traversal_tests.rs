@@ -1,7 +1,14 @@
+use lan_offline::{LanOfflineGuard, LanPolicy};
 use nat_traversal::{
-    decide_route, gather_candidates, should_attempt_hole_punch, NatType, Route,
+    decide_route, decide_route_with_guard, decide_route_with_relay_policy, gather_candidates,
+    gather_candidates_with_guard, gather_candidates_with_stun, punch_targets,
+    should_attempt_hole_punch, BindingRequest, CandidateKind, CandidatePairing, CandidateType,
+    CheckState, HolePuncher, MeteredState, NatType, PairingError, PairingRole, PunchError,
+    PunchRole, RelayPolicy, Route, StunConfig, StunError,
 };
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 fn addr(s: &str) -> SocketAddr {
     s.parse().expect("valid socket addr")
@@ -71,3 +78,421 @@ fn relay_used_when_reflexive_missing_but_relay_present() {
     let plan = decide_route(NatType::Unknown, NatType::Unknown, &a, &b);
     assert_eq!(plan.route, Route::Relay);
 }
+
+#[test]
+fn offline_guard_excludes_public_candidates_and_traces_why() {
+    let guard = LanOfflineGuard::new(LanPolicy::default());
+    let a = gather_candidates_with_guard(
+        addr("192.168.1.10:5000"),
+        Some(addr("203.0.113.10:5000")),
+        Some(addr("198.51.100.1:7000")),
+        &guard,
+    );
+    assert_eq!(a.stun_reflexive_candidate, None);
+    assert_eq!(a.relay_candidate, None);
+    assert_eq!(a.excluded.len(), 2);
+    assert_eq!(a.excluded[1].kind, CandidateKind::Relay);
+
+    // Remote candidates arrive unfiltered and are filtered at decision time.
+    let b = gather_candidates(
+        addr("192.168.1.20:5001"),
+        Some(addr("203.0.113.20:5001")),
+        Some(addr("198.51.100.2:7000")),
+    );
+    let plan = decide_route_with_guard(NatType::Symmetric, NatType::FullCone, &a, &b, &guard);
+    assert_eq!(plan.route, Route::Direct);
+    assert_eq!(plan.trace.len(), 4);
+    assert!(plan.trace[3].contains("remote relay candidate 198.51.100.2:7000"));
+
+    let mut online = guard.clone();
+    online.disable_offline_mode();
+    let plan = decide_route_with_guard(NatType::Symmetric, NatType::FullCone, &b, &b, &online);
+    assert_eq!(plan.route, Route::Relay);
+    assert!(plan.trace.is_empty());
+}
+
+#[test]
+fn ipv6_candidates_route_direct_and_mixed_families_fall_back_to_relay() {
+    let a = gather_candidates(
+        addr("[fe80::1]:5000"),
+        Some(addr("[2001:db8::10]:5000")),
+        Some(addr("[2001:db8:ff::1]:7000")),
+    );
+    let b = gather_candidates(
+        addr("[fe80::2]:5001"),
+        Some(addr("[2001:db8::20]:5001")),
+        None,
+    );
+    let plan = decide_route(NatType::FullCone, NatType::RestrictedCone, &a, &b);
+    assert_eq!(plan.route, Route::Direct);
+
+    let v4_only = gather_candidates(addr("10.0.0.2:5001"), Some(addr("203.0.113.20:5001")), None);
+    let plan = decide_route(NatType::FullCone, NatType::RestrictedCone, &a, &v4_only);
+    assert_eq!(plan.route, Route::Relay);
+}
+
+#[test]
+fn metered_connection_avoids_relay_unless_network_override_allows_it() {
+    let a = gather_candidates(
+        addr("192.168.1.10:5000"),
+        None,
+        Some(addr("198.51.100.1:7000")),
+    );
+    let b = gather_candidates(addr("10.0.0.2:5001"), None, None);
+
+    let mut policy = RelayPolicy {
+        metered: MeteredState {
+            manual: None,
+            os_hint: Some(true),
+        },
+        ..RelayPolicy::default()
+    };
+    let plan = decide_route_with_relay_policy(
+        NatType::Symmetric,
+        NatType::FullCone,
+        &a,
+        &b,
+        &policy,
+        Some("cafe"),
+    );
+    assert_eq!(plan.route, Route::Direct);
+    assert!(plan.trace[0].contains(
+        "excluded local relay candidate 198.51.100.1:7000: metered connection (OS hint)"
+    ));
+    assert_eq!(
+        plan.trace.last().unwrap(),
+        "relay policy on network cafe: metered connection (OS hint)"
+    );
+
+    // The manual toggle beats the OS hint.
+    policy.apply_settings("metered = false\n");
+    let plan = decide_route_with_relay_policy(
+        NatType::Symmetric,
+        NatType::FullCone,
+        &a,
+        &b,
+        &policy,
+        Some("cafe"),
+    );
+    assert_eq!(plan.route, Route::Relay);
+    assert_eq!(plan.trace, ["relay policy on network cafe: relay allowed"]);
+
+    policy.apply_settings(
+        "metered = auto\nrelay_enabled.phone-hotspot = true\nrelay_enabled = false\n",
+    );
+    assert!(policy.metered.is_metered());
+    let hotspot = policy.evaluate(Some("phone-hotspot"));
+    assert!(hotspot.allowed);
+    assert_eq!(hotspot.reason, "relay enabled by network override");
+    assert_eq!(
+        policy.evaluate(Some("office")).reason,
+        "relay disabled in settings"
+    );
+    assert!(!policy.evaluate(None).allowed);
+}
+
+/// A Binding success response to `request` carrying `mapped` as XOR-MAPPED-ADDRESS.
+fn binding_response(request: &[u8], mapped: SocketAddr) -> Vec<u8> {
+    let mut mask = request[4..20].to_vec();
+    let (family, ip) = match mapped.ip() {
+        IpAddr::V4(ip) => (1u8, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (2u8, ip.octets().to_vec()),
+    };
+    mask.truncate(ip.len());
+    let mut value = vec![0, family];
+    value.extend_from_slice(&(mapped.port() ^ 0x2112).to_be_bytes());
+    value.extend(ip.iter().zip(&mask).map(|(b, m)| b ^ m));
+
+    let mut out = vec![0x01, 0x01];
+    out.extend_from_slice(&((4 + value.len()) as u16).to_be_bytes());
+    out.extend_from_slice(&request[4..20]);
+    out.extend_from_slice(&[0x00, 0x20]);
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(&value);
+    out
+}
+
+#[test]
+fn stun_response_is_validated_against_the_request() {
+    let request = BindingRequest::new();
+    let encoded = request.encode();
+    assert_eq!(&encoded[..2], &[0x00, 0x01]);
+    assert_eq!(&encoded[4..8], &[0x21, 0x12, 0xa4, 0x42]);
+
+    let v6 = addr("[2001:db8::1]:40000");
+    assert_eq!(
+        request
+            .parse_response(&binding_response(&encoded, v6))
+            .unwrap(),
+        v6
+    );
+
+    let other = BindingRequest::new().encode();
+    assert!(matches!(
+        request.parse_response(&binding_response(&other, v6)),
+        Err(StunError::InvalidResponse("transaction id mismatch"))
+    ));
+    let mut truncated = binding_response(&encoded, v6);
+    truncated.pop();
+    assert!(request.parse_response(&truncated).is_err());
+
+    let mut error = encoded.to_vec();
+    error[..2].copy_from_slice(&[0x01, 0x11]);
+    assert!(matches!(
+        request.parse_response(&error),
+        Err(StunError::ErrorResponse)
+    ));
+}
+
+#[test]
+fn stun_fills_reflexive_candidate_with_retries() {
+    // One server never answers; the other drops the first request and sends a stray
+    // response before the real one.
+    let silent = UdpSocket::bind("127.0.0.1:0").expect("bind silent server");
+    let server = UdpSocket::bind("127.0.0.1:0").expect("bind server");
+    let server_addr = server.local_addr().expect("server addr");
+    let mapped = addr("203.0.113.7:61000");
+    let handle = thread::spawn(move || {
+        let mut buf = [0u8; 64];
+        server.recv_from(&mut buf).expect("first request");
+        let (n, client) = server.recv_from(&mut buf).expect("retransmitted request");
+        let stray = binding_response(&BindingRequest::new().encode(), addr("198.51.100.1:1"));
+        server.send_to(&stray, client).expect("send stray");
+        server
+            .send_to(&binding_response(&buf[..n], mapped), client)
+            .expect("send response");
+    });
+
+    let socket = UdpSocket::bind("127.0.0.1:0").expect("bind client");
+    let config = StunConfig {
+        attempts: 2,
+        initial_timeout: Duration::from_millis(100),
+        ..StunConfig::new(vec![silent.local_addr().expect("silent addr"), server_addr])
+    };
+    let set = gather_candidates_with_stun(&socket, &config, None).expect("gather");
+    handle.join().expect("server thread");
+    assert_eq!(
+        set.local_candidate,
+        socket.local_addr().expect("local addr")
+    );
+    assert_eq!(set.stun_reflexive_candidate, Some(mapped));
+    assert_eq!(socket.read_timeout().expect("timeout"), None);
+
+    // Without an answer the set simply has no reflexive candidate.
+    let config = StunConfig {
+        attempts: 1,
+        initial_timeout: Duration::from_millis(50),
+        ..StunConfig::new(vec![silent.local_addr().expect("silent addr")])
+    };
+    let set = gather_candidates_with_stun(&socket, &config, None).expect("gather");
+    assert_eq!(set.stun_reflexive_candidate, None);
+}
+
+#[test]
+fn hole_puncher_opens_an_authenticated_path_between_peers() {
+    let a_socket = UdpSocket::bind("127.0.0.1:0").expect("bind a");
+    let b_socket = UdpSocket::bind("127.0.0.1:0").expect("bind b");
+    let a_addr = a_socket.local_addr().expect("a addr");
+    let b_addr = b_socket.local_addr().expect("b addr");
+    // Unreachable reflexive and relay candidates must not get in the way.
+    let a = gather_candidates(
+        a_addr,
+        Some(addr("127.0.0.1:9")),
+        Some(addr("198.51.100.1:7000")),
+    );
+    let b = gather_candidates(b_addr, None, Some(addr("198.51.100.2:7000")));
+    assert_eq!(punch_targets(&a, &b), vec![b_addr]);
+    assert_eq!(punch_targets(&b, &a), vec![a_addr, addr("127.0.0.1:9")]);
+
+    let key = [7u8; 32];
+    let start_at = SystemTime::now() + Duration::from_millis(50);
+    let (a_set, b_set) = (a.clone(), b.clone());
+    let peer = thread::spawn(move || {
+        HolePuncher::new(key, PunchRole::Responder)
+            .punch(&b_socket, &b_set, &a_set, start_at)
+            .expect("b punches")
+    });
+    let pair = HolePuncher::new(key, PunchRole::Initiator)
+        .punch(&a_socket, &a, &b, start_at)
+        .expect("a punches");
+    let peer_pair = peer.join().expect("peer thread");
+    assert_eq!((pair.local, pair.remote), (a_addr, b_addr));
+    assert_eq!((peer_pair.local, peer_pair.remote), (b_addr, a_addr));
+
+    // Probes under another key are ignored until the attempt times out.
+    let a_socket = UdpSocket::bind("127.0.0.1:0").expect("bind a again");
+    let a = gather_candidates(a_socket.local_addr().expect("a addr"), None, None);
+    let c_socket = UdpSocket::bind("127.0.0.1:0").expect("bind c");
+    let c = gather_candidates(c_socket.local_addr().expect("c addr"), None, None);
+    let (a_set, c_set) = (a.clone(), c.clone());
+    let impostor = thread::spawn(move || {
+        let mut puncher = HolePuncher::new([1u8; 32], PunchRole::Responder);
+        puncher.timeout = Duration::from_millis(200);
+        puncher.punch(&c_socket, &c_set, &a_set, SystemTime::now())
+    });
+    let mut puncher = HolePuncher::new(key, PunchRole::Initiator);
+    puncher.timeout = Duration::from_millis(200);
+    assert!(matches!(
+        puncher.punch(&a_socket, &a, &c, SystemTime::now()),
+        Err(PunchError::TimedOut)
+    ));
+    assert!(matches!(
+        impostor.join().expect("impostor thread"),
+        Err(PunchError::TimedOut)
+    ));
+
+    // Probes from a peer in the same role, as a reflection of our own would be, are ignored.
+    let c_socket = UdpSocket::bind("127.0.0.1:0").expect("bind c again");
+    let c = gather_candidates(c_socket.local_addr().expect("c addr"), None, None);
+    let (a_set, c_set) = (a.clone(), c.clone());
+    let same_role = thread::spawn(move || {
+        let mut puncher = HolePuncher::new(key, PunchRole::Initiator);
+        puncher.timeout = Duration::from_millis(200);
+        puncher.punch(&c_socket, &c_set, &a_set, SystemTime::now())
+    });
+    assert!(matches!(
+        puncher.punch(&a_socket, &a, &c, SystemTime::now()),
+        Err(PunchError::TimedOut)
+    ));
+    assert!(matches!(
+        same_role.join().expect("same role thread"),
+        Err(PunchError::TimedOut)
+    ));
+
+    // A packet aimed at an address we did not advertise does not complete our attempt,
+    // though the peer, whose advertised address we did reach, finishes.
+    let c_socket = UdpSocket::bind("127.0.0.1:0").expect("bind c once more");
+    let c = gather_candidates(c_socket.local_addr().expect("c addr"), None, None);
+    let (a_set, c_set) = (a.clone(), c.clone());
+    let peer = thread::spawn(move || {
+        let mut puncher = HolePuncher::new(key, PunchRole::Responder);
+        puncher.timeout = Duration::from_millis(200);
+        puncher.punch(&c_socket, &c_set, &a_set, SystemTime::now())
+    });
+    let misadvertised = gather_candidates(addr("127.0.0.1:9"), None, None);
+    assert!(matches!(
+        puncher.punch(&a_socket, &misadvertised, &c, SystemTime::now()),
+        Err(PunchError::TimedOut)
+    ));
+    assert!(peer.join().expect("peer thread").is_ok());
+
+    let v6_only = gather_candidates(addr("[::1]:5000"), None, None);
+    assert!(matches!(
+        puncher.punch(&a_socket, &a, &v6_only, SystemTime::now()),
+        Err(PunchError::NoCandidates)
+    ));
+}
+
+#[test]
+fn candidate_pairing_checks_pairs_and_nominates_the_best() {
+    let a_socket = UdpSocket::bind("127.0.0.1:0").expect("bind a");
+    let b_socket = UdpSocket::bind("127.0.0.1:0").expect("bind b");
+    let a_addr = a_socket.local_addr().expect("a addr");
+    let b_addr = b_socket.local_addr().expect("b addr");
+    let a = gather_candidates(a_addr, Some(addr("127.0.0.1:9")), None);
+    let b = gather_candidates(b_addr, Some(addr("127.0.0.1:9")), Some(addr("127.0.0.2:9")));
+
+    let key = [3u8; 32];
+    let mut controlling = CandidatePairing::new(key, PairingRole::Controlling);
+    controlling.initial_rto = Duration::from_millis(20);
+    controlling.timeout = Duration::from_secs(2);
+    let mut controlled = CandidatePairing::new(key, PairingRole::Controlled);
+    controlled.initial_rto = Duration::from_millis(20);
+    controlled.timeout = Duration::from_secs(2);
+
+    // The local reflexive candidate folds into its base; host pairs come first, relays last.
+    let pairs = controlling.pairs(&a, &b);
+    let kinds: Vec<_> = pairs.iter().map(|p| (p.local, p.remote_type)).collect();
+    assert_eq!(
+        kinds,
+        [
+            (a_addr, CandidateType::Host),
+            (a_addr, CandidateType::ServerReflexive),
+            (a_addr, CandidateType::Relay),
+        ]
+    );
+    let mirrored = controlled.pairs(&b, &a);
+    assert_eq!(mirrored[0].priority, pairs[0].priority);
+
+    let (a_set, b_set) = (a.clone(), b.clone());
+    let peer = thread::spawn(move || controlled.run(&b_socket, &b_set, &a_set));
+    let report = controlling.run(&a_socket, &a, &b).expect("controlling run");
+    let peer_report = peer.join().expect("peer thread").expect("controlled run");
+
+    let nominated = report.nominated.expect("nominated pair");
+    assert_eq!((nominated.local, nominated.remote), (a_addr, b_addr));
+    assert_eq!(report.route(), Some(Route::Direct));
+    assert_eq!(report.checks[0].state, CheckState::Succeeded);
+    assert!(report.checks[0].rtt.is_some());
+    let peer_nominated = peer_report.nominated.expect("peer nominated pair");
+    assert_eq!(
+        (peer_nominated.local, peer_nominated.remote),
+        (b_addr, a_addr)
+    );
+
+    // With nobody answering, every check fails and nothing is nominated.
+    let mut lonely = CandidatePairing::new(key, PairingRole::Controlling);
+    lonely.initial_rto = Duration::from_millis(10);
+    lonely.max_attempts = 2;
+    lonely.timeout = Duration::from_millis(300);
+    let report = lonely.run(&a_socket, &a, &b).expect("lonely run");
+    assert_eq!(report.route(), None);
+    assert!(report.checks.iter().all(|c| c.state == CheckState::Failed));
+    assert_eq!(
+        report.trace.last().map(String::as_str),
+        Some("no pair validated before the timeout")
+    );
+}
+
+#[test]
+fn candidate_pairing_learns_peer_reflexive_pairs_and_keeps_answering() {
+    let a_socket = UdpSocket::bind("127.0.0.1:0").expect("bind a");
+    let b_socket = UdpSocket::bind("127.0.0.1:0").expect("bind b");
+    let a_addr = a_socket.local_addr().expect("a addr");
+    let b_addr = b_socket.local_addr().expect("b addr");
+    let a = gather_candidates(a_addr, None, None);
+    let b = gather_candidates(b_addr, None, None);
+    // The controlled side only knows an address of `a` that does not answer.
+    let a_claimed = gather_candidates(addr("127.0.0.1:9"), None, None);
+
+    let key = [5u8; 32];
+    let mut controlled = CandidatePairing::new(key, PairingRole::Controlled);
+    controlled.initial_rto = Duration::from_millis(20);
+    controlled.timeout = Duration::from_secs(2);
+    let b_set = b.clone();
+    let peer = thread::spawn(move || {
+        let mut session = controlled.start(&b_socket, &b_set, &a_claimed)?;
+        let report = session.run()?;
+        // Keep answering until the peer goes quiet.
+        b_socket.set_read_timeout(Some(Duration::from_millis(300)))?;
+        let mut buf = [0u8; 128];
+        let mut answered = 0;
+        while let Ok((n, source)) = b_socket.recv_from(&mut buf) {
+            answered += usize::from(session.handle_packet(&buf[..n], source)?);
+        }
+        Ok::<_, PairingError>((report, answered))
+    });
+
+    let mut controlling = CandidatePairing::new(key, PairingRole::Controlling);
+    controlling.initial_rto = Duration::from_millis(20);
+    controlling.timeout = Duration::from_secs(2);
+    let report = controlling.run(&a_socket, &a, &b).expect("controlling run");
+    assert_eq!(report.nominated.map(|pair| pair.remote), Some(b_addr));
+    // A second round after the controlled side returned is still answered.
+    let again = controlling.run(&a_socket, &a, &b).expect("second run");
+    assert_eq!(again.nominated.map(|pair| pair.remote), Some(b_addr));
+
+    let (peer_report, answered) = peer.join().expect("peer thread").expect("controlled");
+    let nominated = peer_report.nominated.expect("peer nominated pair");
+    assert_eq!(nominated.remote, a_addr);
+    assert_eq!(nominated.remote_type, CandidateType::PeerReflexive);
+    assert!(answered >= 2);
+
+    // Long retransmission schedules stay bounded by the timeout instead of overflowing.
+    let mut patient = CandidatePairing::new(key, PairingRole::Controlling);
+    patient.initial_rto = Duration::from_secs(u64::MAX / 4);
+    patient.max_attempts = 64;
+    patient.timeout = Duration::from_millis(100);
+    let report = patient.run(&a_socket, &a, &b).expect("patient run");
+    assert_eq!(report.nominated, None);
+}
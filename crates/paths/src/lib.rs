@@ -0,0 +1,223 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const APP_DIR: &str = "p2p";
+const LEGACY_DIR: &str = ".p2p";
+
+/// Environment variable that relocates config, data and cache under one root.
+pub const ENV_HOME: &str = "P2P_HOME";
+pub const ENV_CONFIG_DIR: &str = "P2P_CONFIG_DIR";
+pub const ENV_DATA_DIR: &str = "P2P_DATA_DIR";
+pub const ENV_CACHE_DIR: &str = "P2P_CACHE_DIR";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Linux,
+    MacOs,
+    Windows,
+}
+
+impl Platform {
+    pub fn current() -> Self {
+        if cfg!(target_os = "windows") {
+            Platform::Windows
+        } else if cfg!(target_os = "macos") {
+            Platform::MacOs
+        } else {
+            Platform::Linux
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppPaths {
+    pub config_dir: PathBuf,
+    pub data_dir: PathBuf,
+    pub cache_dir: PathBuf,
+    /// Ad-hoc location used before platform directories existed (`~/.p2p`).
+    pub legacy_dir: Option<PathBuf>,
+}
+
+impl AppPaths {
+    /// Resolve directories for the running platform from the process environment.
+    pub fn from_env() -> Result<Self, PathsError> {
+        Self::resolve_with(Platform::current(), |key| std::env::var_os(key))
+    }
+
+    /// Resolve directories for `platform`, reading variables through `lookup`.
+    ///
+    /// Precedence: per-directory override, then `P2P_HOME`, then platform convention
+    /// (XDG on Linux, `~/Library` on macOS, `%APPDATA%`/`%LOCALAPPDATA%` on Windows).
+    pub fn resolve_with(
+        platform: Platform,
+        lookup: impl Fn(&str) -> Option<OsString>,
+    ) -> Result<Self, PathsError> {
+        let var = |key: &str| lookup(key).map(PathBuf::from).filter(|p| p.is_absolute());
+
+        let home_var = match platform {
+            Platform::Windows => "USERPROFILE",
+            Platform::Linux | Platform::MacOs => "HOME",
+        };
+        let home = var(home_var);
+        let legacy_dir = home.as_ref().map(|h| h.join(LEGACY_DIR));
+
+        if let Some(root) = var(ENV_HOME) {
+            return Ok(Self {
+                config_dir: var(ENV_CONFIG_DIR).unwrap_or_else(|| root.join("config")),
+                data_dir: var(ENV_DATA_DIR).unwrap_or_else(|| root.join("data")),
+                cache_dir: var(ENV_CACHE_DIR).unwrap_or_else(|| root.join("cache")),
+                legacy_dir,
+            });
+        }
+
+        // Base directory from an env var, falling back to a path under the home directory.
+        let base = |env_key: Option<&str>, under_home: &[&str]| {
+            env_key
+                .and_then(&var)
+                .or_else(|| {
+                    home.as_ref().map(|h| {
+                        under_home
+                            .iter()
+                            .fold(h.clone(), |acc, part| acc.join(part))
+                    })
+                })
+                .ok_or(PathsError::NoHomeDirectory)
+        };
+
+        let (config_base, data_base, cache_dir) = match platform {
+            Platform::Linux => (
+                base(Some("XDG_CONFIG_HOME"), &[".config"])?,
+                base(Some("XDG_DATA_HOME"), &[".local", "share"])?,
+                base(Some("XDG_CACHE_HOME"), &[".cache"])?.join(APP_DIR),
+            ),
+            Platform::MacOs => (
+                base(None, &["Library", "Application Support"])?,
+                base(None, &["Library", "Application Support"])?,
+                base(None, &["Library", "Caches"])?.join(APP_DIR),
+            ),
+            Platform::Windows => {
+                let local = base(Some("LOCALAPPDATA"), &["AppData", "Local"])?;
+                (
+                    base(Some("APPDATA"), &["AppData", "Roaming"])?,
+                    local.clone(),
+                    local.join(APP_DIR).join("cache"),
+                )
+            }
+        };
+
+        Ok(Self {
+            config_dir: var(ENV_CONFIG_DIR).unwrap_or_else(|| config_base.join(APP_DIR)),
+            data_dir: var(ENV_DATA_DIR).unwrap_or_else(|| data_base.join(APP_DIR)),
+            cache_dir: var(ENV_CACHE_DIR).unwrap_or(cache_dir),
+            legacy_dir,
+        })
+    }
+
+    /// Build paths rooted in a single directory (tests, portable installs).
+    pub fn rooted(root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref();
+        Self {
+            config_dir: root.join("config"),
+            data_dir: root.join("data"),
+            cache_dir: root.join("cache"),
+            legacy_dir: None,
+        }
+    }
+
+    pub fn identity_key_file(&self) -> PathBuf {
+        self.data_dir.join("identity.key")
+    }
+
+    pub fn settings_file(&self) -> PathBuf {
+        self.config_dir.join("settings.conf")
+    }
+
+    pub fn trust_store_file(&self) -> PathBuf {
+        self.data_dir.join("trust_store")
+    }
+
+    pub fn checkpoints_dir(&self) -> PathBuf {
+        self.data_dir.join("checkpoints")
+    }
+
+    pub fn audit_dir(&self) -> PathBuf {
+        self.data_dir.join("audit")
+    }
+
+    /// Where profile bundles are exported to and imported from over the local API.
+    pub fn profile_exports_dir(&self) -> PathBuf {
+        self.data_dir.join("exports")
+    }
+
+    pub fn ensure_dirs(&self) -> Result<(), PathsError> {
+        for dir in [&self.config_dir, &self.data_dir, &self.cache_dir] {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(())
+    }
+
+    /// Move files from the legacy `~/.p2p` layout into the resolved directories.
+    ///
+    /// Existing destinations are never overwritten; such entries are reported as skipped.
+    pub fn migrate_legacy(&self) -> Result<MigrationReport, PathsError> {
+        let mut report = MigrationReport::default();
+        let Some(legacy) = self.legacy_dir.as_ref().filter(|d| d.is_dir()) else {
+            return Ok(report);
+        };
+
+        let moves = [
+            (legacy.join("identity.key"), self.identity_key_file()),
+            (legacy.join("settings.conf"), self.settings_file()),
+            (legacy.join("trust_store"), self.trust_store_file()),
+            (legacy.join("checkpoints"), self.checkpoints_dir()),
+            (legacy.join("audit"), self.audit_dir()),
+        ];
+
+        for (from, to) in moves {
+            if !from.exists() {
+                continue;
+            }
+            if to.exists() {
+                report.skipped.push(from);
+                continue;
+            }
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&from, &to)?;
+            report.moved.push((from, to));
+        }
+
+        Ok(report)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub moved: Vec<(PathBuf, PathBuf)>,
+    pub skipped: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathsError {
+    NoHomeDirectory,
+    Io(String),
+}
+
+impl std::fmt::Display for PathsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathsError::NoHomeDirectory => write!(f, "could not determine home directory"),
+            PathsError::Io(m) => write!(f, "io error: {m}"),
+        }
+    }
+}
+
+impl std::error::Error for PathsError {}
+
+impl From<std::io::Error> for PathsError {
+    fn from(value: std::io::Error) -> Self {
+        PathsError::Io(value.to_string())
+    }
+}
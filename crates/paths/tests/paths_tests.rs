@@ -0,0 +1,110 @@
+use paths::{AppPaths, PathsError, Platform};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<OsString> {
+    let map: HashMap<String, OsString> = vars
+        .iter()
+        .map(|(k, v)| (k.to_string(), OsString::from(v)))
+        .collect();
+    move |key| map.get(key).cloned()
+}
+
+#[test]
+fn linux_uses_xdg_dirs_with_home_fallback() {
+    let paths = AppPaths::resolve_with(
+        Platform::Linux,
+        env(&[("HOME", "/home/u"), ("XDG_CONFIG_HOME", "/xdg/config")]),
+    )
+    .expect("resolve");
+
+    assert_eq!(paths.config_dir, PathBuf::from("/xdg/config/p2p"));
+    assert_eq!(paths.data_dir, PathBuf::from("/home/u/.local/share/p2p"));
+    assert_eq!(paths.cache_dir, PathBuf::from("/home/u/.cache/p2p"));
+    assert_eq!(paths.legacy_dir, Some(PathBuf::from("/home/u/.p2p")));
+    assert_eq!(
+        paths.identity_key_file(),
+        PathBuf::from("/home/u/.local/share/p2p/identity.key")
+    );
+}
+
+#[test]
+fn macos_and_windows_use_platform_conventions() {
+    let mac = AppPaths::resolve_with(Platform::MacOs, env(&[("HOME", "/Users/u")])).expect("mac");
+    assert_eq!(
+        mac.config_dir,
+        PathBuf::from("/Users/u/Library/Application Support/p2p")
+    );
+    assert_eq!(mac.cache_dir, PathBuf::from("/Users/u/Library/Caches/p2p"));
+
+    let win = AppPaths::resolve_with(
+        Platform::Windows,
+        env(&[
+            ("USERPROFILE", "/c/Users/u"),
+            ("APPDATA", "/c/Users/u/AppData/Roaming"),
+            ("LOCALAPPDATA", "/c/Users/u/AppData/Local"),
+        ]),
+    )
+    .expect("windows");
+    assert_eq!(
+        win.config_dir,
+        PathBuf::from("/c/Users/u/AppData/Roaming/p2p")
+    );
+    assert_eq!(win.data_dir, PathBuf::from("/c/Users/u/AppData/Local/p2p"));
+    assert_eq!(
+        win.cache_dir,
+        PathBuf::from("/c/Users/u/AppData/Local/p2p/cache")
+    );
+}
+
+#[test]
+fn env_overrides_take_precedence() {
+    let paths = AppPaths::resolve_with(
+        Platform::Linux,
+        env(&[
+            ("HOME", "/home/u"),
+            ("P2P_HOME", "/opt/p2p"),
+            ("P2P_CACHE_DIR", "/tmp/p2p-cache"),
+            ("P2P_DATA_DIR", "relative/ignored"),
+        ]),
+    )
+    .expect("resolve");
+
+    assert_eq!(paths.config_dir, PathBuf::from("/opt/p2p/config"));
+    assert_eq!(paths.data_dir, PathBuf::from("/opt/p2p/data"));
+    assert_eq!(paths.cache_dir, PathBuf::from("/tmp/p2p-cache"));
+}
+
+#[test]
+fn missing_home_is_an_error() {
+    let err = AppPaths::resolve_with(Platform::Linux, env(&[])).expect_err("no home");
+    assert_eq!(err, PathsError::NoHomeDirectory);
+}
+
+#[test]
+fn migrate_legacy_moves_without_overwriting() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let legacy = dir.path().join(".p2p");
+    fs::create_dir_all(legacy.join("checkpoints")).expect("legacy dirs");
+    fs::write(legacy.join("identity.key"), [7u8; 32]).expect("legacy key");
+    fs::write(legacy.join("checkpoints/1.ckpt"), "1\n0\nrunning\n").expect("legacy ckpt");
+    fs::write(legacy.join("settings.conf"), "old").expect("legacy settings");
+
+    let mut paths = AppPaths::rooted(dir.path().join("new"));
+    paths.legacy_dir = Some(legacy.clone());
+    paths.ensure_dirs().expect("ensure");
+    fs::write(paths.settings_file(), "new").expect("current settings");
+
+    let report = paths.migrate_legacy().expect("migrate");
+    assert_eq!(report.moved.len(), 2);
+    assert_eq!(report.skipped, vec![legacy.join("settings.conf")]);
+
+    assert_eq!(fs::read(paths.identity_key_file()).expect("key"), [7u8; 32]);
+    assert!(paths.checkpoints_dir().join("1.ckpt").exists());
+    assert_eq!(
+        fs::read_to_string(paths.settings_file()).expect("settings"),
+        "new"
+    );
+}
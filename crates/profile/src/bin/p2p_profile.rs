@@ -0,0 +1,92 @@
+use paths::AppPaths;
+use profile::{export_profile, import_profile, ConflictPolicy, ExportOptions};
+use std::io::BufRead;
+use std::process::ExitCode;
+
+/// Passphrase source for non-interactive use; otherwise the first line of stdin is read.
+const PASSPHRASE_ENV: &str = "P2P_PROFILE_PASSPHRASE";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let include_history = args.iter().any(|a| a == "--with-history");
+    let replace = args.iter().any(|a| a == "--replace");
+    let positional: Vec<&String> = args.iter().filter(|a| !a.starts_with("--")).collect();
+
+    let [command, bundle] = positional.as_slice() else {
+        eprintln!("usage: p2p_profile export <bundle> [--with-history]");
+        eprintln!("       p2p_profile import <bundle> [--replace]");
+        return ExitCode::from(2);
+    };
+
+    let paths = match AppPaths::from_env() {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("profile failed: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let passphrase = match std::env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) => passphrase,
+        Err(_) => {
+            eprintln!("passphrase:");
+            let mut line = String::new();
+            if std::io::stdin().lock().read_line(&mut line).is_err() {
+                return ExitCode::FAILURE;
+            }
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+    if passphrase.is_empty() {
+        eprintln!("profile failed: empty passphrase");
+        return ExitCode::from(2);
+    }
+
+    match command.as_str() {
+        "export" => {
+            let options = ExportOptions {
+                include_history,
+                ..ExportOptions::default()
+            };
+            match export_profile(&paths, &passphrase, bundle, options) {
+                Ok(()) => {
+                    println!("exported profile to {bundle}");
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("export failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        "import" => {
+            let policy = if replace {
+                ConflictPolicy::Replace
+            } else {
+                ConflictPolicy::KeepExisting
+            };
+            match import_profile(&paths, &passphrase, bundle, policy) {
+                Ok(report) => {
+                    println!("identity: {}", report.identity.as_str());
+                    println!("settings: {}", report.settings.as_str());
+                    println!("history: {}", report.history.as_str());
+                    println!(
+                        "trust store: {} added, {} conflicting",
+                        report.peers_added, report.peer_conflicts
+                    );
+                    if report.peer_conflicts > 0 && !replace {
+                        println!("conflicting entries kept: pass --replace to overwrite");
+                    }
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("import failed: {e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        _ => {
+            eprintln!("unknown command: {command}");
+            ExitCode::from(2)
+        }
+    }
+}
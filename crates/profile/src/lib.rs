@@ -0,0 +1,435 @@
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use handshake::{HandshakeError, TrustStore};
+use identity::DeviceIdentity;
+use paths::AppPaths;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"P2PB";
+const BUNDLE_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// MAGIC + version + iterations + salt + nonce.
+const HEADER_LEN: usize = 4 + 1 + 4 + SALT_LEN + NONCE_LEN;
+/// Floor for PBKDF2 rounds accepted from a bundle header, so a tampered header cannot
+/// make a guessed passphrase cheap to verify.
+const MIN_ITERATIONS: u32 = 1_000;
+/// Ceiling for the same rounds, so a crafted header cannot pin a CPU for minutes
+/// before the passphrase is even checked.
+const MAX_ITERATIONS: u32 = 10_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportOptions {
+    pub include_history: bool,
+    /// PBKDF2-HMAC-SHA256 rounds used to stretch the passphrase.
+    pub iterations: u32,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            include_history: false,
+            iterations: 600_000,
+        }
+    }
+}
+
+/// How an import treats data already present on this device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// Keep local values that differ from the bundle; only fill in what is missing.
+    #[default]
+    KeepExisting,
+    /// Overwrite local values with the bundle's.
+    Replace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemOutcome {
+    /// Nothing on this device, so the bundle's value was written.
+    Imported,
+    /// The local value already matched the bundle.
+    Unchanged,
+    /// A different local value was kept.
+    KeptExisting,
+    /// A different local value was overwritten.
+    Replaced,
+    /// The bundle did not carry this item.
+    Absent,
+}
+
+impl ItemOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ItemOutcome::Imported => "imported",
+            ItemOutcome::Unchanged => "unchanged",
+            ItemOutcome::KeptExisting => "kept_existing",
+            ItemOutcome::Replaced => "replaced",
+            ItemOutcome::Absent => "absent",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImportReport {
+    pub identity: ItemOutcome,
+    pub settings: ItemOutcome,
+    pub history: ItemOutcome,
+    pub peers_added: usize,
+    /// Peers present on both sides with different keys or policies.
+    pub peer_conflicts: usize,
+}
+
+/// Everything needed to move a device to new hardware.
+#[derive(Debug, Clone)]
+pub struct ProfileBundle {
+    pub identity_key: [u8; 32],
+    pub trust_store: TrustStore,
+    pub settings: Option<String>,
+    /// Audit history, only when requested at export time.
+    pub history: Option<Vec<u8>>,
+}
+
+impl ProfileBundle {
+    /// Gather the profile from the platform directories.
+    pub fn collect(paths: &AppPaths, include_history: bool) -> Result<Self, ProfileError> {
+        let key = fs::read(paths.identity_key_file())?;
+        let identity_key: [u8; 32] = key
+            .try_into()
+            .map_err(|_| ProfileError::InvalidBundle("identity key must be 32 bytes"))?;
+        let history_file = paths.audit_dir().join("audit.log");
+
+        Ok(Self {
+            identity_key,
+            trust_store: TrustStore::load(paths.trust_store_file())?,
+            settings: read_optional(&paths.settings_file())?
+                .map(|b| String::from_utf8_lossy(&b).into_owned()),
+            history: if include_history {
+                read_optional(&history_file)?
+            } else {
+                None
+            },
+        })
+    }
+
+    /// Encrypt the bundle under a passphrase-derived key.
+    pub fn seal(&self, passphrase: &str, iterations: u32) -> Result<Vec<u8>, ProfileError> {
+        if iterations < MIN_ITERATIONS {
+            return Err(ProfileError::InvalidConfig("iterations below minimum"));
+        }
+        if iterations > MAX_ITERATIONS {
+            return Err(ProfileError::InvalidConfig("iterations above maximum"));
+        }
+
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut out = Vec::with_capacity(HEADER_LEN + 64);
+        out.extend_from_slice(MAGIC);
+        out.push(BUNDLE_VERSION);
+        out.extend_from_slice(&iterations.to_be_bytes());
+        out.extend_from_slice(&salt);
+        out.extend_from_slice(&nonce);
+
+        let cipher = bundle_cipher(passphrase, &salt, iterations);
+        let ciphertext = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &self.encode(),
+                    aad: &out,
+                },
+            )
+            .map_err(|_| ProfileError::InvalidBundle("encryption failed"))?;
+        out.extend_from_slice(&ciphertext);
+        Ok(out)
+    }
+
+    /// Decrypt a sealed bundle. A wrong passphrase and a tampered bundle are
+    /// indistinguishable and both report [`ProfileError::WrongPassphrase`].
+    pub fn open(bytes: &[u8], passphrase: &str) -> Result<Self, ProfileError> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return Err(ProfileError::InvalidBundle("bad header"));
+        }
+        if bytes[4] != BUNDLE_VERSION {
+            return Err(ProfileError::InvalidBundle("unsupported bundle version"));
+        }
+        let iterations = u32::from_be_bytes(bytes[5..9].try_into().expect("4 bytes"));
+        if iterations < MIN_ITERATIONS {
+            return Err(ProfileError::InvalidBundle("iterations below minimum"));
+        }
+        if iterations > MAX_ITERATIONS {
+            return Err(ProfileError::InvalidBundle("iterations above maximum"));
+        }
+        let salt = &bytes[9..9 + SALT_LEN];
+        let nonce = &bytes[9 + SALT_LEN..HEADER_LEN];
+
+        let plaintext = bundle_cipher(passphrase, salt, iterations)
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: &bytes[HEADER_LEN..],
+                    aad: &bytes[..HEADER_LEN],
+                },
+            )
+            .map_err(|_| ProfileError::WrongPassphrase)?;
+        Self::decode(&plaintext)
+    }
+
+    /// Write the bundle into the platform directories, resolving conflicts per `policy`.
+    pub fn install(
+        &self,
+        paths: &AppPaths,
+        policy: ConflictPolicy,
+    ) -> Result<ImportReport, ProfileError> {
+        paths.ensure_dirs()?;
+
+        let key_file = paths.identity_key_file();
+        let identity = match read_optional(&key_file)? {
+            Some(existing) if existing == self.identity_key => ItemOutcome::Unchanged,
+            existing => {
+                let outcome = resolve(existing.is_some(), policy);
+                if outcome != ItemOutcome::KeptExisting {
+                    DeviceIdentity::from_secret_key_bytes(self.identity_key)
+                        .save(&key_file)
+                        .map_err(|e| ProfileError::Io(e.to_string()))?;
+                }
+                outcome
+            }
+        };
+
+        let fresh_store = !paths.trust_store_file().exists();
+        let mut store = TrustStore::load(paths.trust_store_file())?;
+        let mut peers_added = 0;
+        let mut peer_conflicts = 0;
+        for peer in self.trust_store.peers() {
+            match store.get(&peer.device_id) {
+                None => peers_added += 1,
+                Some(local) if local == peer => continue,
+                Some(_) => {
+                    peer_conflicts += 1;
+                    if policy == ConflictPolicy::KeepExisting {
+                        continue;
+                    }
+                }
+            }
+            store.trust(peer.clone())?;
+        }
+        if fresh_store || policy == ConflictPolicy::Replace {
+            store.trusted_peer_policy = self.trust_store.trusted_peer_policy;
+            store.unknown_peer_policy = self.trust_store.unknown_peer_policy;
+        }
+        store.save(paths.trust_store_file())?;
+
+        let settings = install_item(
+            &paths.settings_file(),
+            self.settings.as_ref().map(String::as_bytes),
+            policy,
+        )?;
+        let history = install_item(
+            &paths.audit_dir().join("audit.log"),
+            self.history.as_deref(),
+            policy,
+        )?;
+
+        Ok(ImportReport {
+            identity,
+            settings,
+            history,
+            peers_added,
+            peer_conflicts,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&self.identity_key);
+        push_section(&mut out, Some(self.trust_store.encode().as_bytes()));
+        push_section(&mut out, self.settings.as_ref().map(String::as_bytes));
+        push_section(&mut out, self.history.as_deref());
+        out
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, ProfileError> {
+        if bytes.len() < 32 {
+            return Err(ProfileError::InvalidBundle("truncated identity key"));
+        }
+        let identity_key: [u8; 32] = bytes[..32].try_into().expect("32 bytes");
+        let mut cursor = 32;
+
+        let trust = read_section(bytes, &mut cursor)?
+            .ok_or(ProfileError::InvalidBundle("missing trust store"))?;
+        let trust_store = TrustStore::decode(
+            std::str::from_utf8(trust)
+                .map_err(|_| ProfileError::InvalidBundle("trust store is not utf-8"))?,
+        )?;
+        let settings = read_section(bytes, &mut cursor)?
+            .map(|s| {
+                String::from_utf8(s.to_vec())
+                    .map_err(|_| ProfileError::InvalidBundle("settings are not utf-8"))
+            })
+            .transpose()?;
+        let history = read_section(bytes, &mut cursor)?.map(<[u8]>::to_vec);
+        if cursor != bytes.len() {
+            return Err(ProfileError::InvalidBundle("trailing bytes"));
+        }
+
+        Ok(Self {
+            identity_key,
+            trust_store,
+            settings,
+            history,
+        })
+    }
+}
+
+/// Collect, seal and write the profile to `out`.
+pub fn export_profile(
+    paths: &AppPaths,
+    passphrase: &str,
+    out: impl AsRef<Path>,
+    options: ExportOptions,
+) -> Result<(), ProfileError> {
+    let sealed = ProfileBundle::collect(paths, options.include_history)?
+        .seal(passphrase, options.iterations)?;
+    fs::write(out, sealed)?;
+    Ok(())
+}
+
+/// Read, open and install a profile bundle from `input`.
+pub fn import_profile(
+    paths: &AppPaths,
+    passphrase: &str,
+    input: impl AsRef<Path>,
+    policy: ConflictPolicy,
+) -> Result<ImportReport, ProfileError> {
+    ProfileBundle::open(&fs::read(input)?, passphrase)?.install(paths, policy)
+}
+
+fn bundle_cipher(passphrase: &str, salt: &[u8], iterations: u32) -> ChaCha20Poly1305 {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+fn resolve(exists: bool, policy: ConflictPolicy) -> ItemOutcome {
+    match (exists, policy) {
+        (false, _) => ItemOutcome::Imported,
+        (true, ConflictPolicy::KeepExisting) => ItemOutcome::KeptExisting,
+        (true, ConflictPolicy::Replace) => ItemOutcome::Replaced,
+    }
+}
+
+fn install_item(
+    path: &Path,
+    value: Option<&[u8]>,
+    policy: ConflictPolicy,
+) -> Result<ItemOutcome, ProfileError> {
+    let Some(value) = value else {
+        return Ok(ItemOutcome::Absent);
+    };
+    let existing = read_optional(path)?;
+    if existing.as_deref() == Some(value) {
+        return Ok(ItemOutcome::Unchanged);
+    }
+
+    let outcome = resolve(existing.is_some(), policy);
+    if outcome != ItemOutcome::KeptExisting {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, value)?;
+    }
+    Ok(outcome)
+}
+
+fn read_optional(path: &Path) -> Result<Option<Vec<u8>>, ProfileError> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn push_section(out: &mut Vec<u8>, section: Option<&[u8]>) {
+    match section {
+        Some(bytes) => {
+            out.push(1);
+            out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+            out.extend_from_slice(bytes);
+        }
+        None => out.push(0),
+    }
+}
+
+fn read_section<'a>(bytes: &'a [u8], cursor: &mut usize) -> Result<Option<&'a [u8]>, ProfileError> {
+    let present = *bytes
+        .get(*cursor)
+        .ok_or(ProfileError::InvalidBundle("truncated section"))?;
+    *cursor += 1;
+    match present {
+        0 => Ok(None),
+        1 => {
+            let len_bytes = bytes
+                .get(*cursor..*cursor + 4)
+                .ok_or(ProfileError::InvalidBundle("truncated section"))?;
+            let len = u32::from_be_bytes(len_bytes.try_into().expect("4 bytes")) as usize;
+            *cursor += 4;
+            let section = bytes
+                .get(*cursor..*cursor + len)
+                .ok_or(ProfileError::InvalidBundle("truncated section"))?;
+            *cursor += len;
+            Ok(Some(section))
+        }
+        _ => Err(ProfileError::InvalidBundle("bad section marker")),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileError {
+    InvalidBundle(&'static str),
+    InvalidConfig(&'static str),
+    WrongPassphrase,
+    TrustStore(String),
+    Io(String),
+}
+
+impl std::fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfileError::InvalidBundle(m) => write!(f, "invalid profile bundle: {m}"),
+            ProfileError::InvalidConfig(m) => write!(f, "invalid config: {m}"),
+            ProfileError::WrongPassphrase => {
+                write!(f, "wrong passphrase or corrupted bundle")
+            }
+            ProfileError::TrustStore(m) => write!(f, "trust store error: {m}"),
+            ProfileError::Io(m) => write!(f, "io error: {m}"),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+impl From<std::io::Error> for ProfileError {
+    fn from(value: std::io::Error) -> Self {
+        ProfileError::Io(value.to_string())
+    }
+}
+
+impl From<paths::PathsError> for ProfileError {
+    fn from(value: paths::PathsError) -> Self {
+        ProfileError::Io(value.to_string())
+    }
+}
+
+impl From<HandshakeError> for ProfileError {
+    fn from(value: HandshakeError) -> Self {
+        ProfileError::TrustStore(value.to_string())
+    }
+}
@@ -0,0 +1,144 @@
+use handshake::{EncryptionMode, TrustStore, TrustedPeer};
+use identity::DeviceIdentity;
+use paths::AppPaths;
+use profile::{
+    export_profile, import_profile, ConflictPolicy, ExportOptions, ItemOutcome, ProfileBundle,
+    ProfileError,
+};
+use std::fs;
+
+const FAST: ExportOptions = ExportOptions {
+    include_history: true,
+    iterations: 1_000,
+};
+
+fn peer(id: &str, policy: Option<EncryptionMode>) -> TrustedPeer {
+    TrustedPeer {
+        encryption_policy: policy,
+        ..TrustedPeer::verified(id, &DeviceIdentity::generate().public_key_b64())
+    }
+}
+
+fn seeded_device(root: &std::path::Path) -> (AppPaths, DeviceIdentity, TrustStore) {
+    let paths = AppPaths::rooted(root);
+    paths.ensure_dirs().unwrap();
+    let identity = DeviceIdentity::load_or_generate(&paths).unwrap();
+    let mut store = TrustStore::new(EncryptionMode::Optional, EncryptionMode::Required);
+    store.trust(peer("phone", None)).unwrap();
+    store
+        .trust(peer("tablet", Some(EncryptionMode::Required)))
+        .unwrap();
+    store.save(paths.trust_store_file()).unwrap();
+    fs::write(paths.settings_file(), "flags.swarm_mode = on\n").unwrap();
+    fs::create_dir_all(paths.audit_dir()).unwrap();
+    fs::write(
+        paths.audit_dir().join("audit.log"),
+        "1|security|peer_trusted|\n",
+    )
+    .unwrap();
+    (paths, identity, store)
+}
+
+#[test]
+fn profile_roundtrips_to_a_fresh_device() {
+    let old = tempfile::tempdir().unwrap();
+    let new = tempfile::tempdir().unwrap();
+    let (old_paths, identity, store) = seeded_device(old.path());
+    let bundle = old.path().join("profile.p2pf");
+
+    export_profile(&old_paths, "correct horse", &bundle, FAST).unwrap();
+    let sealed = fs::read(&bundle).unwrap();
+    assert!(!sealed
+        .windows(b"swarm_mode".len())
+        .any(|w| w == b"swarm_mode"));
+
+    let new_paths = AppPaths::rooted(new.path());
+    let report = import_profile(
+        &new_paths,
+        "correct horse",
+        &bundle,
+        ConflictPolicy::KeepExisting,
+    )
+    .unwrap();
+    assert_eq!(report.identity, ItemOutcome::Imported);
+    assert_eq!(report.settings, ItemOutcome::Imported);
+    assert_eq!(report.history, ItemOutcome::Imported);
+    assert_eq!(report.peers_added, 2);
+
+    let migrated = DeviceIdentity::load(new_paths.identity_key_file()).unwrap();
+    assert_eq!(migrated.public_key_b64(), identity.public_key_b64());
+    assert_eq!(
+        TrustStore::load(new_paths.trust_store_file()).unwrap(),
+        store
+    );
+    assert_eq!(
+        fs::read_to_string(new_paths.settings_file()).unwrap(),
+        "flags.swarm_mode = on\n"
+    );
+
+    assert_eq!(
+        import_profile(&new_paths, "wrong", &bundle, ConflictPolicy::Replace).unwrap_err(),
+        ProfileError::WrongPassphrase
+    );
+    let mut tampered = sealed.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        ProfileBundle::open(&tampered, "correct horse"),
+        Err(ProfileError::WrongPassphrase)
+    )); // A header demanding absurd KDF work is refused before any is done.
+    let mut costly = sealed.clone();
+    costly[5..9].copy_from_slice(&u32::MAX.to_be_bytes());
+    assert_eq!(
+        ProfileBundle::open(&costly, "correct horse").unwrap_err(),
+        ProfileError::InvalidBundle("iterations above maximum")
+    );
+}
+
+#[test]
+fn import_conflicts_keep_local_data_unless_replacing() {
+    let old = tempfile::tempdir().unwrap();
+    let new = tempfile::tempdir().unwrap();
+    let (old_paths, identity, _) = seeded_device(old.path());
+    let bundle = old.path().join("profile.p2pf");
+    export_profile(
+        &old_paths,
+        "pass",
+        &bundle,
+        ExportOptions {
+            include_history: false,
+            ..FAST
+        },
+    )
+    .unwrap();
+
+    let (new_paths, local_identity, mut local_store) = seeded_device(new.path());
+    local_store.trust(peer("desktop", None)).unwrap();
+    local_store.save(new_paths.trust_store_file()).unwrap();
+
+    let report = import_profile(&new_paths, "pass", &bundle, ConflictPolicy::KeepExisting).unwrap();
+    assert_eq!(report.identity, ItemOutcome::KeptExisting);
+    assert_eq!(report.settings, ItemOutcome::Unchanged);
+    assert_eq!(report.history, ItemOutcome::Absent);
+    assert_eq!(report.peers_added, 0);
+    assert_eq!(report.peer_conflicts, 2);
+    assert_eq!(
+        DeviceIdentity::load(new_paths.identity_key_file())
+            .unwrap()
+            .public_key_b64(),
+        local_identity.public_key_b64()
+    );
+
+    let report = import_profile(&new_paths, "pass", &bundle, ConflictPolicy::Replace).unwrap();
+    assert_eq!(report.identity, ItemOutcome::Replaced);
+    assert_eq!(report.peer_conflicts, 2);
+    assert_eq!(
+        DeviceIdentity::load(new_paths.identity_key_file())
+            .unwrap()
+            .public_key_b64(),
+        identity.public_key_b64()
+    );
+    // Peers that exist only locally survive a replacing import.
+    let merged = TrustStore::load(new_paths.trust_store_file()).unwrap();
+    assert!(merged.get("desktop").is_some());
+    assert_eq!(merged.peers().len(), 3);
+}
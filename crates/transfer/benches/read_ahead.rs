@@ -0,0 +1,62 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::thread;
+use std::time::Duration;
+use transfer::{FileSource, ReadAheadConfig, TransferSource};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const CHUNKS: u32 = 64;
+
+/// Reader that charges a fixed round-trip per read call, like a cold network mount.
+struct SlowReader {
+    inner: Cursor<Vec<u8>>,
+    latency: Duration,
+}
+
+impl Read for SlowReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        thread::sleep(self.latency);
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for SlowReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+fn read_all(config: ReadAheadConfig) {
+    let data = vec![7u8; CHUNK_SIZE * CHUNKS as usize];
+    let len = data.len() as u64;
+    let reader = SlowReader {
+        inner: Cursor::new(data),
+        latency: Duration::from_micros(500),
+    };
+    let mut source = FileSource::from_reader(reader, len, CHUNK_SIZE, config).expect("source");
+    for index in 0..CHUNKS {
+        source.read_chunk(index).expect("chunk");
+    }
+}
+
+fn bench_read_ahead(c: &mut Criterion) {
+    let mut group = c.benchmark_group("nfs_like_sequential_read");
+    group.sample_size(10);
+    for prefetch in [0u32, 4, 16] {
+        let config = if prefetch == 0 {
+            ReadAheadConfig::disabled()
+        } else {
+            ReadAheadConfig {
+                prefetch_chunks: prefetch,
+                cache_chunks: prefetch as usize * 2,
+            }
+        };
+        group.bench_with_input(BenchmarkId::from_parameter(prefetch), &config, |b, &cfg| {
+            b.iter(|| read_all(cfg))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_read_ahead);
+criterion_main!(benches);
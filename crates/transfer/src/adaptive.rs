@@ -0,0 +1,100 @@
+use crate::TransferError;
+use std::time::Duration;
+
+/// Bounds and target for [`AdaptiveChunker`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveChunkConfig {
+    /// Where the chunker starts, and the wire chunk size of adaptive sessions.
+    pub min_chunk_size: usize,
+    pub max_chunk_size: usize,
+    /// Round trips at or under this count as success and let the size grow.
+    pub target_rtt: Duration,
+}
+
+impl Default for AdaptiveChunkConfig {
+    fn default() -> Self {
+        Self {
+            min_chunk_size: 16 * 1024,
+            max_chunk_size: 1024 * 1024,
+            target_rtt: Duration::from_millis(50),
+        }
+    }
+}
+
+/// Picks how much data to put on the wire per send from ack round trips and losses.
+///
+/// Starts at the minimum and doubles after every ack within the target RTT, so a
+/// gigabit LAN reaches the maximum within a few acks. Losses, and a smoothed RTT above
+/// twice the target, halve it again for congested Wi-Fi. Sizes stay a power-of-two
+/// multiple of the minimum so they always cover whole wire chunks.
+#[derive(Debug, Clone)]
+pub struct AdaptiveChunker {
+    config: AdaptiveChunkConfig,
+    current: usize,
+    smoothed_rtt: Option<Duration>,
+}
+
+impl AdaptiveChunker {
+    pub fn new(config: AdaptiveChunkConfig) -> Result<Self, TransferError> {
+        if config.min_chunk_size == 0 {
+            return Err(TransferError::InvalidConfig("chunk_size must be > 0"));
+        }
+        if config.max_chunk_size < config.min_chunk_size {
+            return Err(TransferError::InvalidConfig(
+                "max chunk size below min chunk size",
+            ));
+        }
+        if config.target_rtt.is_zero() {
+            return Err(TransferError::InvalidConfig("target rtt must be > 0"));
+        }
+        Ok(Self {
+            config,
+            current: config.min_chunk_size,
+            smoothed_rtt: None,
+        })
+    }
+
+    pub fn config(&self) -> AdaptiveChunkConfig {
+        self.config
+    }
+
+    /// Bytes to send before waiting on the next ack.
+    pub fn chunk_size(&self) -> usize {
+        self.current
+    }
+
+    pub fn smoothed_rtt(&self) -> Option<Duration> {
+        self.smoothed_rtt
+    }
+
+    /// Feed the round trip of an acked send.
+    pub fn on_ack(&mut self, rtt: Duration) {
+        // Same 7/8 smoothing as TCP's SRTT, so one slow ack does not undo a ramp-up.
+        let smoothed = match self.smoothed_rtt {
+            Some(previous) => (previous * 7 + rtt) / 8,
+            None => rtt,
+        };
+        self.smoothed_rtt = Some(smoothed);
+        if smoothed > self.config.target_rtt * 2 {
+            self.shrink();
+        } else if rtt <= self.config.target_rtt {
+            self.grow();
+        }
+    }
+
+    /// A chunk was reported missing or timed out.
+    pub fn on_loss(&mut self) {
+        self.shrink();
+    }
+
+    fn grow(&mut self) {
+        let doubled = self.current.saturating_mul(2);
+        if doubled <= self.config.max_chunk_size {
+            self.current = doubled;
+        }
+    }
+
+    fn shrink(&mut self) {
+        self.current = (self.current / 2).max(self.config.min_chunk_size);
+    }
+}
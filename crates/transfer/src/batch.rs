@@ -0,0 +1,77 @@
+use crate::TransferError;
+
+/// Where one file of a batch sits in the session's chunk space and byte buffer.
+///
+/// Every file starts on a chunk boundary and takes at least one chunk, so an empty
+/// file still travels as one empty chunk and chunk indices stay global to the session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BatchFile {
+    pub(crate) first_chunk: u32,
+    pub(crate) chunk_count: u32,
+    pub(crate) byte_offset: usize,
+    pub(crate) len: usize,
+}
+
+/// Lay out files of the given lengths back to back.
+///
+/// Fails when the chunk count does not fit a `u32` or the bytes do not fit memory,
+/// which lengths read back from a snapshot can claim.
+pub(crate) fn layout(
+    file_lens: &[usize],
+    chunk_size: usize,
+) -> Result<Vec<BatchFile>, TransferError> {
+    let too_many_chunks = || TransferError::InvalidConfig("batch has too many chunks");
+    let mut files = Vec::with_capacity(file_lens.len());
+    let (mut first_chunk, mut byte_offset) = (0u32, 0usize);
+    for &len in file_lens {
+        let chunk_count =
+            u32::try_from(len.div_ceil(chunk_size).max(1)).map_err(|_| too_many_chunks())?;
+        files.push(BatchFile {
+            first_chunk,
+            chunk_count,
+            byte_offset,
+            len,
+        });
+        first_chunk = first_chunk
+            .checked_add(chunk_count)
+            .ok_or_else(too_many_chunks)?;
+        byte_offset = byte_offset
+            .checked_add(len)
+            .ok_or(TransferError::InvalidConfig("batch is too large"))?;
+    }
+    Ok(files)
+}
+
+/// Index of the file holding `chunk_index`; the caller has bounds-checked it.
+pub(crate) fn locate(files: &[BatchFile], chunk_index: u32) -> usize {
+    files
+        .partition_point(|file| file.first_chunk <= chunk_index)
+        .saturating_sub(1)
+}
+
+/// One receiver's progress through one file of a batch.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileProgress {
+    pub file_index: u32,
+    pub acked_chunks: u32,
+    pub total_chunks: u32,
+}
+
+impl FileProgress {
+    pub(crate) fn for_layout(files: &[BatchFile]) -> Vec<Self> {
+        files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| Self {
+                file_index: index as u32,
+                acked_chunks: 0,
+                total_chunks: file.chunk_count,
+            })
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.acked_chunks >= self.total_chunks
+    }
+}
@@ -0,0 +1,294 @@
+use crate::TransferError;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+const ZSTD_LEVEL: i32 = 3;
+const FLAG_ZSTD: u8 = 0b0000_0001;
+
+/// Identifies a shared dictionary by id plus content hash, so peers never decode
+/// with a same-numbered but different dictionary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DictionaryDescriptor {
+    pub id: u32,
+    pub sha256: [u8; 32],
+}
+
+/// Local store of zstd dictionaries available for compression.
+#[derive(Debug, Clone, Default)]
+pub struct DictionaryStore {
+    dictionaries: HashMap<u32, Vec<u8>>,
+}
+
+impl DictionaryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load every `<id>.dict` file in `dir`; other files are ignored.
+    pub fn load_dir(dir: impl AsRef<Path>) -> Result<Self, TransferError> {
+        let mut store = Self::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("dict") {
+                continue;
+            }
+            let Some(id) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u32>().ok())
+            else {
+                continue;
+            };
+            store.insert(id, fs::read(&path)?);
+        }
+        Ok(store)
+    }
+
+    pub fn insert(&mut self, id: u32, dictionary: Vec<u8>) -> DictionaryDescriptor {
+        let descriptor = DictionaryDescriptor {
+            id,
+            sha256: Sha256::digest(&dictionary).into(),
+        };
+        self.dictionaries.insert(id, dictionary);
+        descriptor
+    }
+
+    pub fn get(&self, id: u32) -> Option<&[u8]> {
+        self.dictionaries.get(&id).map(Vec::as_slice)
+    }
+
+    pub fn descriptor(&self, id: u32) -> Option<DictionaryDescriptor> {
+        self.get(id).map(|dictionary| DictionaryDescriptor {
+            id,
+            sha256: Sha256::digest(dictionary).into(),
+        })
+    }
+
+    pub fn descriptors(&self) -> Vec<DictionaryDescriptor> {
+        let mut ids: Vec<u32> = self.dictionaries.keys().copied().collect();
+        ids.sort_unstable();
+        ids.into_iter()
+            .filter_map(|id| self.descriptor(id))
+            .collect()
+    }
+}
+
+/// Compression support a peer advertises during capability exchange.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompressionCapabilities {
+    pub zstd: bool,
+    pub dictionaries: Vec<DictionaryDescriptor>,
+}
+
+impl CompressionCapabilities {
+    pub fn local(store: &DictionaryStore) -> Self {
+        Self {
+            zstd: true,
+            dictionaries: store.descriptors(),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        // flags(u8) | count(u16 be) | (id(u32 be) | sha256[32])*
+        let count = u16::try_from(self.dictionaries.len()).unwrap_or(u16::MAX);
+        let mut out = Vec::with_capacity(1 + 2 + count as usize * 36);
+        out.push(if self.zstd { FLAG_ZSTD } else { 0 });
+        out.extend_from_slice(&count.to_be_bytes());
+        for d in &self.dictionaries[..count as usize] {
+            out.extend_from_slice(&d.id.to_be_bytes());
+            out.extend_from_slice(&d.sha256);
+        }
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, TransferError> {
+        if bytes.len() < 3 {
+            return Err(TransferError::InvalidFrame("bad compression capabilities"));
+        }
+        let count = u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+        if bytes.len() != 3 + count * 36 {
+            return Err(TransferError::InvalidFrame(
+                "invalid dictionary list length",
+            ));
+        }
+
+        let dictionaries = bytes[3..]
+            .chunks_exact(36)
+            .map(|entry| DictionaryDescriptor {
+                id: u32::from_be_bytes(entry[..4].try_into().expect("slice len")),
+                sha256: entry[4..].try_into().expect("slice len"),
+            })
+            .collect();
+
+        Ok(Self {
+            zstd: bytes[0] & FLAG_ZSTD != 0,
+            dictionaries,
+        })
+    }
+
+    fn has_dictionary(&self, descriptor: &DictionaryDescriptor) -> bool {
+        self.dictionaries.contains(descriptor)
+    }
+}
+
+/// Largest payload a compressed chunk may expand to; caps decompression bombs.
+pub const MAX_DECOMPRESSED_CHUNK_LEN: usize = 16 * 1024 * 1024;
+
+/// Per-chunk payload codec, carried in the frame so each chunk can opt out.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompressionCodec {
+    #[default]
+    None,
+    Zstd,
+    Lz4,
+}
+
+impl CompressionCodec {
+    pub(crate) fn as_u8(self) -> u8 {
+        match self {
+            CompressionCodec::None => 0,
+            CompressionCodec::Zstd => 1,
+            CompressionCodec::Lz4 => 2,
+        }
+    }
+
+    pub(crate) fn from_u8(v: u8) -> Result<Self, TransferError> {
+        match v {
+            0 => Ok(CompressionCodec::None),
+            1 => Ok(CompressionCodec::Zstd),
+            2 => Ok(CompressionCodec::Lz4),
+            _ => Err(TransferError::InvalidFrame("unknown compression codec")),
+        }
+    }
+
+    /// Parse a negotiated codec name (`none`, `zstd`, `lz4`) as the handshake reports it.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(CompressionCodec::None),
+            "zstd" => Some(CompressionCodec::Zstd),
+            "lz4" => Some(CompressionCodec::Lz4),
+            _ => None,
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, TransferError> {
+        match self {
+            CompressionCodec::None => Ok(data.to_vec()),
+            CompressionCodec::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL)
+                .map_err(|_| TransferError::Compression("zstd compression failed")),
+            CompressionCodec::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+        }
+    }
+
+    /// Decompress one chunk, refusing to produce more than `max_len` bytes.
+    pub fn decompress(self, data: &[u8], max_len: usize) -> Result<Vec<u8>, TransferError> {
+        let no_dictionaries = DictionaryStore::default();
+        match self {
+            CompressionCodec::None => {
+                decompress_payload(CompressionPlan::None, &no_dictionaries, data, max_len)
+            }
+            CompressionCodec::Zstd => {
+                decompress_payload(CompressionPlan::Zstd, &no_dictionaries, data, max_len)
+            }
+            CompressionCodec::Lz4 => {
+                // lz4_flex prepends the decompressed size as a little-endian u32.
+                let size = data
+                    .get(..4)
+                    .map(|len| u32::from_le_bytes(len.try_into().expect("slice len")) as usize)
+                    .ok_or(TransferError::Compression("lz4 payload truncated"))?;
+                if size > max_len {
+                    return Err(TransferError::Compression("payload exceeds size limit"));
+                }
+                lz4_flex::decompress(&data[4..], size)
+                    .ok()
+                    .filter(|out| out.len() == size)
+                    .ok_or(TransferError::Compression("lz4 decompression failed"))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionPlan {
+    None,
+    Zstd,
+    ZstdDictionary(u32),
+}
+
+/// Choose per-transfer compression against what the peer advertised.
+///
+/// The preferred dictionary is only used when the peer holds the exact same bytes
+/// (id and hash match); otherwise this falls back to plain zstd.
+pub fn select_compression(
+    store: &DictionaryStore,
+    peer: &CompressionCapabilities,
+    preferred_dictionary: Option<u32>,
+) -> CompressionPlan {
+    if !peer.zstd {
+        return CompressionPlan::None;
+    }
+
+    match preferred_dictionary.and_then(|id| store.descriptor(id)) {
+        Some(descriptor) if peer.has_dictionary(&descriptor) => {
+            CompressionPlan::ZstdDictionary(descriptor.id)
+        }
+        _ => CompressionPlan::Zstd,
+    }
+}
+
+pub fn compress_payload(
+    plan: CompressionPlan,
+    store: &DictionaryStore,
+    data: &[u8],
+) -> Result<Vec<u8>, TransferError> {
+    match plan {
+        CompressionPlan::None => Ok(data.to_vec()),
+        CompressionPlan::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL)
+            .map_err(|_| TransferError::Compression("zstd compression failed")),
+        CompressionPlan::ZstdDictionary(id) => {
+            let dictionary = store
+                .get(id)
+                .ok_or(TransferError::Compression("unknown dictionary"))?;
+            zstd::bulk::Compressor::with_dictionary(ZSTD_LEVEL, dictionary)
+                .and_then(|mut c| c.compress(data))
+                .map_err(|_| TransferError::Compression("zstd compression failed"))
+        }
+    }
+}
+
+/// Decompress a payload, refusing to produce more than `max_len` bytes.
+pub fn decompress_payload(
+    plan: CompressionPlan,
+    store: &DictionaryStore,
+    data: &[u8],
+    max_len: usize,
+) -> Result<Vec<u8>, TransferError> {
+    let dictionary: &[u8] = match plan {
+        CompressionPlan::None => {
+            if data.len() > max_len {
+                return Err(TransferError::Compression("payload exceeds size limit"));
+            }
+            return Ok(data.to_vec());
+        }
+        CompressionPlan::Zstd => &[],
+        CompressionPlan::ZstdDictionary(id) => store
+            .get(id)
+            .ok_or(TransferError::Compression("unknown dictionary"))?,
+    };
+    let decoder = zstd::stream::read::Decoder::with_dictionary(data, dictionary)
+        .map_err(|_| TransferError::Compression("zstd decoder init failed"))?;
+
+    let mut out = Vec::new();
+    decoder
+        .take(max_len as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|_| TransferError::Compression("zstd decompression failed"))?;
+    if out.len() > max_len {
+        return Err(TransferError::Compression("payload exceeds size limit"));
+    }
+    Ok(out)
+}
@@ -0,0 +1,321 @@
+use crate::TransferError;
+use discovery::FreeSpaceHint;
+
+pub(crate) const MAGIC_CONTROL: &[u8; 4] = b"P2PC";
+
+const KIND_ERROR: u8 = 1;
+const KIND_OFFER_DELIVERED: u8 = 2;
+const KIND_OFFER_SEEN: u8 = 3;
+const KIND_OFFER_ACCEPTED: u8 = 4;
+const KIND_CANCEL: u8 = 5;
+const KIND_ABORT: u8 = 6;
+const KIND_HEARTBEAT: u8 = 7;
+
+/// Error codes a peer can report about a transfer over the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferErrorCode {
+    /// The offered transfer_id collides with a live or recently finished transfer;
+    /// the sender should re-offer under a freshly allocated id.
+    TransferIdInUse,
+    /// The sender's file changed mid-transfer; the receiver should discard what it has.
+    /// The sender restarts with a fresh offer.
+    SourceModified,
+}
+
+impl TransferErrorCode {
+    fn as_u8(self) -> u8 {
+        match self {
+            TransferErrorCode::TransferIdInUse => 1,
+            TransferErrorCode::SourceModified => 2,
+        }
+    }
+
+    fn from_u8(v: u8) -> Result<Self, TransferError> {
+        match v {
+            1 => Ok(TransferErrorCode::TransferIdInUse),
+            2 => Ok(TransferErrorCode::SourceModified),
+            _ => Err(TransferError::InvalidFrame("unknown error code")),
+        }
+    }
+
+    /// Whether the sender should retry the offer under a new transfer_id.
+    pub fn requires_reoffer(self) -> bool {
+        matches!(
+            self,
+            TransferErrorCode::TransferIdInUse | TransferErrorCode::SourceModified
+        )
+    }
+
+    /// Whether data already received for this transfer must be thrown away.
+    pub fn discards_partial_data(self) -> bool {
+        matches!(self, TransferErrorCode::SourceModified)
+    }
+}
+
+/// Why a transfer was ended before completing, as carried in cancel and abort frames.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    /// The user stopped the transfer.
+    UserRequested,
+    /// The receiving user turned the offer down.
+    Declined,
+    /// The receiver has no room left for the file.
+    InsufficientSpace,
+    /// The peer stopped responding.
+    Timeout,
+    /// The peer sent frames that cannot be processed.
+    ProtocolError,
+    /// The app is exiting.
+    Shutdown,
+}
+
+impl CancelReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CancelReason::UserRequested => "user requested",
+            CancelReason::Declined => "declined",
+            CancelReason::InsufficientSpace => "insufficient space",
+            CancelReason::Timeout => "timeout",
+            CancelReason::ProtocolError => "protocol error",
+            CancelReason::Shutdown => "shutdown",
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            CancelReason::UserRequested => 1,
+            CancelReason::Declined => 2,
+            CancelReason::InsufficientSpace => 3,
+            CancelReason::Timeout => 4,
+            CancelReason::ProtocolError => 5,
+            CancelReason::Shutdown => 6,
+        }
+    }
+
+    fn from_u8(v: u8) -> Result<Self, TransferError> {
+        match v {
+            1 => Ok(CancelReason::UserRequested),
+            2 => Ok(CancelReason::Declined),
+            3 => Ok(CancelReason::InsufficientSpace),
+            4 => Ok(CancelReason::Timeout),
+            5 => Ok(CancelReason::ProtocolError),
+            6 => Ok(CancelReason::Shutdown),
+            _ => Err(TransferError::InvalidFrame("unknown cancel reason")),
+        }
+    }
+}
+
+/// How a transfer ended early.
+///
+/// A cancel is a deliberate stop; an abort means the transfer failed and should be
+/// reported as such.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    Cancelled(CancelReason),
+    Aborted(CancelReason),
+}
+
+impl Termination {
+    pub fn reason(self) -> CancelReason {
+        match self {
+            Termination::Cancelled(reason) | Termination::Aborted(reason) => reason,
+        }
+    }
+
+    /// Frame telling the peer about this termination.
+    pub fn frame(self, transfer_id: u64) -> ControlFrame {
+        match self {
+            Termination::Cancelled(reason) => ControlFrame::Cancel {
+                transfer_id,
+                reason,
+            },
+            Termination::Aborted(reason) => ControlFrame::Abort {
+                transfer_id,
+                reason,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorFrame {
+    pub transfer_id: u64,
+    pub code: TransferErrorCode,
+}
+
+/// Control-plane messages exchanged alongside chunk frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlFrame {
+    Error(ErrorFrame),
+    /// Receiver's device got the offer (sent automatically on receipt).
+    OfferDelivered {
+        transfer_id: u64,
+    },
+    /// The offer was shown to the receiving user.
+    OfferSeen {
+        transfer_id: u64,
+    },
+    /// The receiving user accepted the offer, optionally advertising how much room it has.
+    OfferAccepted {
+        transfer_id: u64,
+        free_space: Option<FreeSpaceHint>,
+    },
+    /// Either side stopped the transfer on purpose; the peer should stop too.
+    Cancel {
+        transfer_id: u64,
+        reason: CancelReason,
+    },
+    /// Either side gave up on the transfer after a failure.
+    Abort {
+        transfer_id: u64,
+        reason: CancelReason,
+    },
+    /// Sent while a side has nothing else to say, e.g. a paused receiver, so the peer
+    /// can tell it from one that has gone silent.
+    Heartbeat {
+        transfer_id: u64,
+    },
+}
+
+impl ControlFrame {
+    pub fn transfer_id(&self) -> u64 {
+        match self {
+            ControlFrame::Error(frame) => frame.transfer_id,
+            ControlFrame::OfferDelivered { transfer_id }
+            | ControlFrame::OfferSeen { transfer_id }
+            | ControlFrame::OfferAccepted { transfer_id, .. }
+            | ControlFrame::Cancel { transfer_id, .. }
+            | ControlFrame::Abort { transfer_id, .. }
+            | ControlFrame::Heartbeat { transfer_id } => *transfer_id,
+        }
+    }
+
+    /// The termination a cancel or abort frame announces; `None` for other frames.
+    pub fn termination(&self) -> Option<Termination> {
+        match self {
+            ControlFrame::Cancel { reason, .. } => Some(Termination::Cancelled(*reason)),
+            ControlFrame::Abort { reason, .. } => Some(Termination::Aborted(*reason)),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        // MAGIC | kind(u8) | transfer_id(u64 be) | kind-specific body
+        // OfferAccepted carries an optional free-space bucket(u8); no byte means no hint.
+        // Cancel and Abort carry a reason(u8).
+        let mut out = Vec::with_capacity(4 + 1 + 8 + 1);
+        out.extend_from_slice(MAGIC_CONTROL);
+        match self {
+            ControlFrame::Error(frame) => {
+                out.push(KIND_ERROR);
+                out.extend_from_slice(&frame.transfer_id.to_be_bytes());
+                out.push(frame.code.as_u8());
+            }
+            ControlFrame::OfferDelivered { transfer_id } => {
+                out.push(KIND_OFFER_DELIVERED);
+                out.extend_from_slice(&transfer_id.to_be_bytes());
+            }
+            ControlFrame::OfferSeen { transfer_id } => {
+                out.push(KIND_OFFER_SEEN);
+                out.extend_from_slice(&transfer_id.to_be_bytes());
+            }
+            ControlFrame::OfferAccepted {
+                transfer_id,
+                free_space,
+            } => {
+                out.push(KIND_OFFER_ACCEPTED);
+                out.extend_from_slice(&transfer_id.to_be_bytes());
+                if let Some(hint) = free_space {
+                    out.push(hint.bucket());
+                }
+            }
+            ControlFrame::Cancel {
+                transfer_id,
+                reason,
+            } => {
+                out.push(KIND_CANCEL);
+                out.extend_from_slice(&transfer_id.to_be_bytes());
+                out.push(reason.as_u8());
+            }
+            ControlFrame::Abort {
+                transfer_id,
+                reason,
+            } => {
+                out.push(KIND_ABORT);
+                out.extend_from_slice(&transfer_id.to_be_bytes());
+                out.push(reason.as_u8());
+            }
+            ControlFrame::Heartbeat { transfer_id } => {
+                out.push(KIND_HEARTBEAT);
+                out.extend_from_slice(&transfer_id.to_be_bytes());
+            }
+        }
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, TransferError> {
+        if bytes.len() < 13 || &bytes[..4] != MAGIC_CONTROL {
+            return Err(TransferError::InvalidFrame("bad control header"));
+        }
+
+        let kind = bytes[4];
+        let transfer_id = u64::from_be_bytes(bytes[5..13].try_into().expect("slice len"));
+        let body = &bytes[13..];
+
+        match kind {
+            KIND_ERROR => {
+                if body.len() != 1 {
+                    return Err(TransferError::InvalidFrame("invalid control body length"));
+                }
+                Ok(ControlFrame::Error(ErrorFrame {
+                    transfer_id,
+                    code: TransferErrorCode::from_u8(body[0])?,
+                }))
+            }
+            KIND_OFFER_DELIVERED | KIND_OFFER_SEEN | KIND_HEARTBEAT => {
+                if !body.is_empty() {
+                    return Err(TransferError::InvalidFrame("invalid control body length"));
+                }
+                Ok(match kind {
+                    KIND_OFFER_DELIVERED => ControlFrame::OfferDelivered { transfer_id },
+                    KIND_OFFER_SEEN => ControlFrame::OfferSeen { transfer_id },
+                    _ => ControlFrame::Heartbeat { transfer_id },
+                })
+            }
+            KIND_OFFER_ACCEPTED => {
+                let free_space = match body {
+                    [] => None,
+                    [bucket] => Some(
+                        FreeSpaceHint::from_bucket(*bucket)
+                            .ok_or(TransferError::InvalidFrame("unknown free space bucket"))?,
+                    ),
+                    _ => return Err(TransferError::InvalidFrame("invalid control body length")),
+                };
+                Ok(ControlFrame::OfferAccepted {
+                    transfer_id,
+                    free_space,
+                })
+            }
+            KIND_CANCEL | KIND_ABORT => {
+                let [reason] = body else {
+                    return Err(TransferError::InvalidFrame("invalid control body length"));
+                };
+                let reason = CancelReason::from_u8(*reason)?;
+                Ok(if kind == KIND_CANCEL {
+                    ControlFrame::Cancel {
+                        transfer_id,
+                        reason,
+                    }
+                } else {
+                    ControlFrame::Abort {
+                        transfer_id,
+                        reason,
+                    }
+                })
+            }
+            _ => Err(TransferError::InvalidFrame("unknown control kind")),
+        }
+    }
+}
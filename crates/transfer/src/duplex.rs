@@ -0,0 +1,239 @@
+use crate::{
+    decrypt_chunk_frame_ref, Ack, CipherSuite, CompressionCodec, Direction, PaddingScheme,
+    PreparedChunk, ReceiveSession, TransferChunkV2Ref, TransferError, TransferIdRegistry,
+    TransferSession,
+};
+use crypto_envelope::SessionKeySecret;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+
+/// Which end of the secure session this peer is; fixes its sending nonce direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionRole {
+    Initiator,
+    Responder,
+}
+
+impl SessionRole {
+    pub fn send_direction(self) -> Direction {
+        match self {
+            SessionRole::Initiator => Direction::SenderToReceiver,
+            SessionRole::Responder => Direction::ReceiverToSender,
+        }
+    }
+
+    pub fn recv_direction(self) -> Direction {
+        match self {
+            SessionRole::Initiator => Direction::ReceiverToSender,
+            SessionRole::Responder => Direction::SenderToReceiver,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Outgoing {
+    session: TransferSession,
+    next_chunk: u32,
+}
+
+/// Both directions of transfers between one pair of peers over a single secure session.
+///
+/// Outgoing and incoming transfers live in separate id registries, so the two peers may
+/// pick the same transfer id at once; frames carry the role's nonce direction.
+#[derive(Debug)]
+pub struct DuplexSession {
+    role: SessionRole,
+    local_id: String,
+    peer_id: String,
+    epoch: u64,
+    keys: SessionKeySecret,
+    compression: CompressionCodec,
+    cipher_suite: CipherSuite,
+    outgoing_ids: TransferIdRegistry,
+    incoming_ids: TransferIdRegistry,
+    outgoing: BTreeMap<u64, Outgoing>,
+    incoming: HashMap<u64, ReceiveSession>,
+}
+
+impl DuplexSession {
+    pub fn new(
+        role: SessionRole,
+        local_id: impl Into<String>,
+        peer_id: impl Into<String>,
+        tx_key: [u8; 32],
+        rx_key: [u8; 32],
+    ) -> Self {
+        Self {
+            role,
+            local_id: local_id.into(),
+            peer_id: peer_id.into(),
+            epoch: crate::new_receiver_epoch(),
+            keys: SessionKeySecret::new(tx_key, rx_key),
+            compression: CompressionCodec::None,
+            cipher_suite: CipherSuite::default(),
+            outgoing_ids: TransferIdRegistry::default(),
+            incoming_ids: TransferIdRegistry::default(),
+            outgoing: BTreeMap::new(),
+            incoming: HashMap::new(),
+        }
+    }
+
+    pub fn role(&self) -> SessionRole {
+        self.role
+    }
+
+    /// Compress outgoing chunks with the codec negotiated in the handshake; incoming
+    /// frames may use only that codec or none.
+    pub fn set_compression(&mut self, codec: CompressionCodec) {
+        self.compression = codec;
+    }
+
+    /// Encrypt with the cipher suite negotiated in the handshake; incoming frames must
+    /// use the same one.
+    pub fn set_cipher_suite(&mut self, suite: CipherSuite) {
+        self.cipher_suite = suite;
+    }
+
+    /// Queue data for the peer and return the allocated outgoing transfer id.
+    pub fn start_outgoing(
+        &mut self,
+        data: Vec<u8>,
+        chunk_size: usize,
+    ) -> Result<u64, TransferError> {
+        let transfer_id = self.outgoing_ids.allocate();
+        self.queue_outgoing(transfer_id, data, chunk_size)?;
+        Ok(transfer_id)
+    }
+
+    /// Queue data back to the peer under the id of a transfer it sent us, e.g. a
+    /// thumbnail of what arrived.
+    ///
+    /// The reply is an ordinary outgoing transfer: sealed under this end's tx key in its
+    /// role's nonce direction, and received by the peer as an incoming transfer with the
+    /// same id as the one it sent.
+    pub fn reply(
+        &mut self,
+        transfer_id: u64,
+        data: Vec<u8>,
+        chunk_size: usize,
+    ) -> Result<(), TransferError> {
+        self.outgoing_ids.register(transfer_id)?;
+        self.queue_outgoing(transfer_id, data, chunk_size)
+    }
+
+    fn queue_outgoing(
+        &mut self,
+        transfer_id: u64,
+        data: Vec<u8>,
+        chunk_size: usize,
+    ) -> Result<(), TransferError> {
+        let session =
+            match TransferSession::new(transfer_id, data, chunk_size, [self.peer_id.clone()]) {
+                Ok(session) => session,
+                Err(e) => {
+                    self.outgoing_ids.release(transfer_id);
+                    return Err(e);
+                }
+            };
+        self.outgoing.insert(
+            transfer_id,
+            Outgoing {
+                session,
+                next_chunk: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Encode the next unsent chunk of any outgoing transfer, or `None` when all are sent.
+    pub fn next_outgoing_frame(&mut self) -> Result<Option<Vec<u8>>, TransferError> {
+        let Some(out) = self
+            .outgoing
+            .values_mut()
+            .find(|o| o.session.termination().is_none() && o.next_chunk < o.session.total_chunks())
+        else {
+            return Ok(None);
+        };
+
+        let chunk = out.session.chunk_for(out.next_chunk)?;
+        out.session.mark_chunk_sent(out.next_chunk);
+        out.next_chunk += 1;
+        let frame = PreparedChunk::new(
+            Cow::Owned(chunk),
+            &mut out.session.nonces,
+            self.role.send_direction(),
+            true,
+            self.compression,
+            self.cipher_suite,
+            PaddingScheme::None,
+        )?
+        .seal(self.keys.tx_key())?;
+        Ok(Some(frame.encode()))
+    }
+
+    /// Accept a frame from the peer and return the cumulative ack for its transfer.
+    ///
+    /// A replay of a frame already accepted fails with [`TransferError::DuplicateChunk`]
+    /// without being decrypted again.
+    pub fn receive_frame(&mut self, bytes: &[u8]) -> Result<Ack, TransferError> {
+        let frame = TransferChunkV2Ref::decode(bytes)?;
+        let codec = frame.compression()?;
+        if codec != CompressionCodec::None && codec != self.compression {
+            return Err(TransferError::Compression("codec was not negotiated"));
+        }
+        if let Some(incoming) = self.incoming.get(&frame.transfer_id) {
+            incoming.check_replay(frame.chunk_index, &frame.nonce)?;
+        }
+        let chunk = decrypt_chunk_frame_ref(
+            &frame,
+            self.keys.rx_key(),
+            self.role.recv_direction(),
+            self.cipher_suite,
+        )?;
+
+        if !self.incoming.contains_key(&chunk.transfer_id) {
+            self.incoming_ids.register(chunk.transfer_id)?;
+            self.incoming.insert(
+                chunk.transfer_id,
+                ReceiveSession::new(chunk.transfer_id, self.local_id.clone(), self.epoch),
+            );
+        }
+
+        let incoming = self
+            .incoming
+            .get_mut(&chunk.transfer_id)
+            .expect("incoming transfer registered above");
+        incoming.accept_frame_chunk(frame.nonce, chunk)?;
+        Ok(incoming.ack())
+    }
+
+    /// Apply the peer's ack to one of our outgoing transfers.
+    pub fn apply_ack(&mut self, ack: &Ack) -> Result<(), TransferError> {
+        let out = self
+            .outgoing
+            .get_mut(&ack.transfer_id)
+            .ok_or(TransferError::WrongTransfer)?;
+        out.session.apply_ack(ack)
+    }
+
+    pub fn outgoing_complete(&self, transfer_id: u64) -> bool {
+        self.outgoing
+            .get(&transfer_id)
+            .is_some_and(|o| o.session.all_complete())
+    }
+
+    /// Remove a fully received incoming transfer and return its payload.
+    pub fn take_incoming(&mut self, transfer_id: u64) -> Option<Vec<u8>> {
+        let payload = self.incoming.get_mut(&transfer_id)?.take_payload()?;
+        self.incoming.remove(&transfer_id);
+        self.incoming_ids.release(transfer_id);
+        Some(payload)
+    }
+
+    /// Drop a finished outgoing transfer, keeping its id reserved against late frames.
+    pub fn finish_outgoing(&mut self, transfer_id: u64) -> Option<TransferSession> {
+        let out = self.outgoing.remove(&transfer_id)?;
+        self.outgoing_ids.release(transfer_id);
+        Some(out.session)
+    }
+}
@@ -0,0 +1,262 @@
+use crate::{TransferChunk, TransferError, TransferSession};
+use std::collections::{HashMap, VecDeque};
+
+/// Settings key prefix for per-peer weights (`peer_weight.<device_id> = 4`).
+const WEIGHT_PREFIX: &str = "peer_weight.";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FairnessConfig {
+    /// Bytes credited to a weight-1 peer on each round.
+    pub quantum_bytes: u64,
+    pub default_weight: u32,
+    /// Per-peer overrides; a peer with weight 2 gets twice the share of a weight-1 peer.
+    pub peer_weights: HashMap<String, u32>,
+}
+
+impl Default for FairnessConfig {
+    fn default() -> Self {
+        Self {
+            quantum_bytes: 64 * 1024,
+            default_weight: 1,
+            peer_weights: HashMap::new(),
+        }
+    }
+}
+
+impl FairnessConfig {
+    /// Read `peer_weight.<device_id> = <n>` lines from a settings file.
+    ///
+    /// Other keys, zero weights and unparsable values are ignored.
+    pub fn apply_settings(&mut self, contents: &str) {
+        for line in contents.lines() {
+            let Some((key, value)) = line.trim().split_once('=') else {
+                continue;
+            };
+            let Some(peer_id) = key.trim().strip_prefix(WEIGHT_PREFIX) else {
+                continue;
+            };
+            match value.trim().parse::<u32>() {
+                Ok(weight) if weight > 0 => {
+                    self.peer_weights.insert(peer_id.to_string(), weight);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn weight_for(&self, peer_id: &str) -> u32 {
+        self.peer_weights
+            .get(peer_id)
+            .copied()
+            .unwrap_or(self.default_weight)
+    }
+}
+
+#[derive(Debug)]
+struct PeerSession {
+    session: TransferSession,
+    next_chunk: u32,
+}
+
+#[derive(Debug)]
+struct PeerQueue {
+    peer_id: String,
+    deficit: u64,
+    sessions: VecDeque<PeerSession>,
+    /// Chunk fetched for the deficit check but not yet sent.
+    head: Option<TransferChunk>,
+    bytes_sent: u64,
+}
+
+impl PeerQueue {
+    fn has_pending(&self) -> bool {
+        self.head.is_some()
+            || self
+                .sessions
+                .iter()
+                .any(|s| s.next_chunk < s.session.total_chunks())
+    }
+
+    /// Fill `head` from the next session with work, rotating sessions round-robin.
+    ///
+    /// A chunk that cannot be read stays next in its session, to be tried again on a
+    /// later call rather than skipped.
+    fn load_head(&mut self) -> Option<&TransferChunk> {
+        if self.head.is_none() {
+            for _ in 0..self.sessions.len() {
+                let mut entry = self.sessions.pop_front()?;
+                let chunk = if entry.next_chunk < entry.session.total_chunks() {
+                    let chunk = entry.session.chunk_for(entry.next_chunk).ok();
+                    if chunk.is_some() {
+                        entry.next_chunk += 1;
+                    }
+                    chunk
+                } else {
+                    None
+                };
+                self.sessions.push_back(entry);
+                if chunk.is_some() {
+                    self.head = chunk;
+                    break;
+                }
+            }
+        }
+        self.head.as_ref()
+    }
+}
+
+/// Deficit round robin across peers, so one peer with many or large transfers cannot
+/// starve the others.
+///
+/// Sits underneath the global rate limiter: whatever budget the limiter grants is
+/// spent by pulling chunks from here, and each peer receives bytes in proportion to
+/// its weight while it has work queued.
+#[derive(Debug)]
+pub struct FairScheduler {
+    config: FairnessConfig,
+    peers: Vec<PeerQueue>,
+    cursor: usize,
+    /// Whether the peer at `cursor` has already been credited its quantum this round.
+    turn_started: bool,
+}
+
+impl FairScheduler {
+    pub fn new(config: FairnessConfig) -> Result<Self, TransferError> {
+        if config.quantum_bytes == 0 {
+            return Err(TransferError::InvalidConfig("quantum_bytes must be > 0"));
+        }
+        if config.default_weight == 0 {
+            return Err(TransferError::InvalidConfig("default_weight must be > 0"));
+        }
+        Ok(Self {
+            config,
+            peers: Vec::new(),
+            cursor: 0,
+            turn_started: false,
+        })
+    }
+
+    /// Queue a session to be sent to `peer_id`.
+    pub fn add_session(
+        &mut self,
+        peer_id: &str,
+        session: TransferSession,
+    ) -> Result<(), TransferError> {
+        if self.peers.iter().any(|p| {
+            p.sessions
+                .iter()
+                .any(|s| s.session.transfer_id() == session.transfer_id())
+        }) {
+            return Err(TransferError::TransferIdInUse);
+        }
+
+        let next_chunk = session.min_resume_point();
+        let entry = PeerSession {
+            session,
+            next_chunk,
+        };
+        match self.peers.iter_mut().find(|p| p.peer_id == peer_id) {
+            Some(peer) => peer.sessions.push_back(entry),
+            None => self.peers.push(PeerQueue {
+                peer_id: peer_id.to_string(),
+                deficit: 0,
+                sessions: VecDeque::from([entry]),
+                head: None,
+                bytes_sent: 0,
+            }),
+        }
+        Ok(())
+    }
+
+    pub fn remove_session(&mut self, transfer_id: u64) -> Option<TransferSession> {
+        for peer in &mut self.peers {
+            if let Some(idx) = peer
+                .sessions
+                .iter()
+                .position(|s| s.session.transfer_id() == transfer_id)
+            {
+                if peer
+                    .head
+                    .as_ref()
+                    .is_some_and(|c| c.transfer_id == transfer_id)
+                {
+                    peer.head = None;
+                }
+                return peer.sessions.remove(idx).map(|s| s.session);
+            }
+        }
+        None
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.peers.iter().any(PeerQueue::has_pending)
+    }
+
+    /// Payload bytes handed out for `peer_id` so far.
+    pub fn bytes_sent(&self, peer_id: &str) -> u64 {
+        self.peers
+            .iter()
+            .find(|p| p.peer_id == peer_id)
+            .map_or(0, |p| p.bytes_sent)
+    }
+
+    /// Yield the next chunk and the peer it is for, or `None` when no queue has a chunk
+    /// ready: every one is drained, or the chunks left could not be read this time.
+    pub fn next_chunk(&mut self) -> Option<(String, TransferChunk)> {
+        if !self.has_pending() {
+            return None;
+        }
+
+        // Peers visited in a row without a chunk; a full round of them ends the call.
+        let mut idle = 0;
+        loop {
+            let quantum = self.config.quantum_bytes
+                * u64::from(self.config.weight_for(&self.peers[self.cursor].peer_id));
+            let peer = &mut self.peers[self.cursor];
+
+            let Some(head_len) = peer.load_head().map(|c| c.payload.len() as u64) else {
+                // Idle peers keep no credit, otherwise they could burst after waking up.
+                peer.deficit = 0;
+                idle += 1;
+                if idle == self.peers.len() {
+                    return None;
+                }
+                self.advance();
+                continue;
+            };
+            idle = 0;
+
+            if !self.turn_started {
+                peer.deficit += quantum;
+                self.turn_started = true;
+            }
+
+            if head_len > peer.deficit {
+                self.advance();
+                continue;
+            }
+
+            peer.deficit -= head_len;
+            peer.bytes_sent += head_len;
+            let chunk = peer.head.take()?;
+            if let Some(entry) = peer
+                .sessions
+                .iter()
+                .find(|entry| entry.session.transfer_id() == chunk.transfer_id)
+            {
+                entry.session.mark_chunk_sent(chunk.chunk_index);
+            }
+            let peer_id = peer.peer_id.clone();
+            if !peer.has_pending() {
+                peer.deficit = 0;
+                self.advance();
+            }
+            return Some((peer_id, chunk));
+        }
+    }
+
+    fn advance(&mut self) {
+        self.cursor = (self.cursor + 1) % self.peers.len();
+        self.turn_started = false;
+    }
+}
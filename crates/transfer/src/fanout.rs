@@ -0,0 +1,60 @@
+use crate::{CipherSuite, PaddingScheme, TransferError};
+use crypto_envelope::SecretKeyBytes;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Session keys of a transfer's receivers, each from its own handshake.
+///
+/// Frame nonces derive from the chunk header alone, so every receiver of the same
+/// chunk gets the same nonce; a key may therefore belong to one receiver only, which
+/// keeps each (key, nonce) pair unique per destination. Each key is kept with the
+/// cipher suite and padding negotiated in the same handshake, and wiped when removed
+/// or dropped.
+#[derive(Clone, Default)]
+pub(crate) struct ReceiverKeys {
+    keys: BTreeMap<String, (SecretKeyBytes, CipherSuite, PaddingScheme)>,
+}
+
+impl fmt::Debug for ReceiverKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.keys.keys()).finish()
+    }
+}
+
+impl ReceiverKeys {
+    pub(crate) fn insert(
+        &mut self,
+        receiver_id: &str,
+        key: [u8; 32],
+        suite: CipherSuite,
+        padding: PaddingScheme,
+    ) -> Result<(), TransferError> {
+        let key = SecretKeyBytes::new(key);
+        if self
+            .keys
+            .iter()
+            .any(|(id, (existing, ..))| id != receiver_id && *existing == key)
+        {
+            return Err(TransferError::InvalidConfig(
+                "session key already used by another receiver",
+            ));
+        }
+        self.keys
+            .insert(receiver_id.to_string(), (key, suite, padding));
+        Ok(())
+    }
+
+    pub(crate) fn remove(&mut self, receiver_id: &str) -> bool {
+        self.keys.remove(receiver_id).is_some()
+    }
+
+    pub(crate) fn get(
+        &self,
+        receiver_id: &str,
+    ) -> Result<(&[u8; 32], CipherSuite, PaddingScheme), TransferError> {
+        self.keys
+            .get(receiver_id)
+            .map(|(key, suite, padding)| (key.expose(), *suite, *padding))
+            .ok_or(TransferError::MissingSessionKey)
+    }
+}
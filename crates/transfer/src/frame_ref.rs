@@ -0,0 +1,232 @@
+use crate::{
+    CompressionCodec, EncryptionFlag, TransferChunk, TransferChunkV2, TransferError, CHUNK_AAD_LEN,
+    CHUNK_DIGEST_LEN, CHUNK_FILE_INDEX_LEN, CHUNK_V1_HEADER_LEN, CHUNK_V2_HEADER_LEN, MAGIC_V1,
+    MAGIC_V2, PROTOCOL_VERSION_V2,
+};
+use crypto_envelope::{ChunkAad, CipherSuite};
+use sha2::{Digest, Sha256};
+
+/// A v1 frame decoded in place: the payload borrows the input instead of being copied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferChunkRef<'a> {
+    pub transfer_id: u64,
+    pub chunk_index: u32,
+    pub total_chunks: u32,
+    pub payload: &'a [u8],
+}
+
+impl<'a> TransferChunkRef<'a> {
+    pub fn decode(bytes: &'a [u8]) -> Result<Self, TransferError> {
+        if bytes.len() < CHUNK_V1_HEADER_LEN || &bytes[..4] != MAGIC_V1 {
+            return Err(TransferError::InvalidFrame("bad header"));
+        }
+
+        let transfer_id = u64::from_be_bytes(bytes[4..12].try_into().expect("slice len"));
+        let chunk_index = u32::from_be_bytes(bytes[12..16].try_into().expect("slice len"));
+        let total_chunks = u32::from_be_bytes(bytes[16..20].try_into().expect("slice len"));
+        let payload_len = u32::from_be_bytes(bytes[20..24].try_into().expect("slice len")) as usize;
+
+        if bytes.len() != CHUNK_V1_HEADER_LEN + payload_len {
+            return Err(TransferError::InvalidFrame("invalid payload length"));
+        }
+        if total_chunks == 0 || chunk_index >= total_chunks {
+            return Err(TransferError::InvalidFrame("invalid chunk bounds"));
+        }
+
+        Ok(Self {
+            transfer_id,
+            chunk_index,
+            total_chunks,
+            payload: &bytes[CHUNK_V1_HEADER_LEN..],
+        })
+    }
+
+    pub fn into_owned(self) -> TransferChunk {
+        TransferChunk {
+            transfer_id: self.transfer_id,
+            file_index: 0,
+            chunk_index: self.chunk_index,
+            total_chunks: self.total_chunks,
+            payload: self.payload.to_vec(),
+        }
+    }
+}
+
+/// A v2 frame decoded in place; AAD and payload borrow the input.
+///
+/// Pair with [`crate::decrypt_chunk_frame_ref`] so a received frame is decrypted
+/// straight out of the read buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferChunkV2Ref<'a> {
+    pub protocol_version: u8,
+    pub encryption_flag: EncryptionFlag,
+    pub cipher_suite: CipherSuite,
+    pub transfer_id: u64,
+    pub chunk_index: u32,
+    pub total_chunks: u32,
+    pub nonce: [u8; 12],
+    pub aad: &'a [u8],
+    pub payload: &'a [u8],
+}
+
+impl<'a> TransferChunkV2Ref<'a> {
+    pub fn decode(bytes: &'a [u8]) -> Result<Self, TransferError> {
+        if bytes.len() < CHUNK_V2_HEADER_LEN || &bytes[..4] != MAGIC_V2 {
+            return Err(TransferError::InvalidFrame("bad v2 header"));
+        }
+
+        let protocol_version = bytes[4];
+        let (encryption_flag, cipher_suite) = EncryptionFlag::from_u8(bytes[5])?;
+        let transfer_id = u64::from_be_bytes(bytes[6..14].try_into().expect("slice len"));
+        let chunk_index = u32::from_be_bytes(bytes[14..18].try_into().expect("slice len"));
+        let total_chunks = u32::from_be_bytes(bytes[18..22].try_into().expect("slice len"));
+
+        if protocol_version != PROTOCOL_VERSION_V2 {
+            return Err(TransferError::InvalidFrame("unsupported protocol version"));
+        }
+        if total_chunks == 0 || chunk_index >= total_chunks {
+            return Err(TransferError::InvalidFrame("invalid chunk bounds"));
+        }
+
+        let mut nonce = [0u8; 12];
+        nonce.copy_from_slice(&bytes[22..34]);
+
+        let aad_len = u16::from_be_bytes(bytes[34..36].try_into().expect("slice len")) as usize;
+        let payload_len = u32::from_be_bytes(bytes[36..40].try_into().expect("slice len")) as usize;
+
+        let expected_len = CHUNK_V2_HEADER_LEN + aad_len + payload_len;
+        if bytes.len() != expected_len {
+            return Err(TransferError::InvalidFrame("invalid payload length"));
+        }
+
+        let aad_start = CHUNK_V2_HEADER_LEN;
+        let payload_start = aad_start + aad_len;
+
+        let frame = Self {
+            protocol_version,
+            encryption_flag,
+            cipher_suite,
+            transfer_id,
+            chunk_index,
+            total_chunks,
+            nonce,
+            aad: &bytes[aad_start..payload_start],
+            payload: &bytes[payload_start..],
+        };
+        // Encrypted payloads are checked after decryption, against the plaintext.
+        if frame.encryption_flag == EncryptionFlag::Plaintext {
+            if frame.compression()? != CompressionCodec::None {
+                return Err(TransferError::InvalidFrame(
+                    "plaintext frames are not compressed",
+                ));
+            }
+            frame.verify_digest(frame.payload)?;
+        }
+        Ok(frame)
+    }
+
+    pub fn into_owned(self) -> TransferChunkV2 {
+        TransferChunkV2 {
+            protocol_version: self.protocol_version,
+            encryption_flag: self.encryption_flag,
+            cipher_suite: self.cipher_suite,
+            transfer_id: self.transfer_id,
+            chunk_index: self.chunk_index,
+            total_chunks: self.total_chunks,
+            nonce: self.nonce,
+            aad: self.aad.to_vec(),
+            payload: self.payload.to_vec(),
+        }
+    }
+
+    /// The AAD parsed as an [`crypto_envelope::AadBuilder`] header; `None` for frames
+    /// from before it, which carry the bare header layout.
+    pub fn structured_aad(&self) -> Option<ChunkAad> {
+        ChunkAad::parse(self.aad).ok()
+    }
+
+    /// Plaintext digest carried in the AAD, if the sender included one.
+    pub fn chunk_digest(&self) -> Option<[u8; CHUNK_DIGEST_LEN]> {
+        if let Some(aad) = self.structured_aad() {
+            return aad.chunk_digest;
+        }
+        let with_digest = CHUNK_AAD_LEN + CHUNK_DIGEST_LEN;
+        if self.aad.len() != with_digest && self.aad.len() != with_digest + CHUNK_FILE_INDEX_LEN {
+            return None;
+        }
+        self.aad[self.aad.len() - CHUNK_DIGEST_LEN..]
+            .try_into()
+            .ok()
+    }
+
+    /// Codec the payload was compressed with before sealing. It is read from the AAD,
+    /// which the tag covers, so only frames that decrypt can be trusted to name it;
+    /// frames without a structured AAD are never compressed.
+    pub fn compression(&self) -> Result<CompressionCodec, TransferError> {
+        self.structured_aad()
+            .map_or(Ok(CompressionCodec::None), |aad| {
+                CompressionCodec::from_u8(aad.compression)
+            })
+    }
+
+    /// File within a batch, from the AAD; frames without one belong to file 0.
+    pub fn file_index(&self) -> u32 {
+        if let Some(aad) = self.structured_aad() {
+            return aad.file_index;
+        }
+        let with_index = CHUNK_AAD_LEN + CHUNK_FILE_INDEX_LEN;
+        if self.aad.len() != with_index && self.aad.len() != with_index + CHUNK_DIGEST_LEN {
+            return 0;
+        }
+        u32::from_be_bytes(
+            self.aad[CHUNK_AAD_LEN..with_index]
+                .try_into()
+                .expect("slice len"),
+        )
+    }
+
+    /// The AAD must repeat the frame header, and the digest, if any, match `plaintext`.
+    pub(crate) fn verify_digest(&self, plaintext: &[u8]) -> Result<(), TransferError> {
+        let header_mismatch = TransferError::InvalidFrame("aad does not match frame header");
+        let digest = match self.structured_aad() {
+            Some(aad) => {
+                if (
+                    aad.protocol_version,
+                    aad.cipher_suite,
+                    aad.transfer_id,
+                    aad.chunk_index,
+                    aad.total_chunks,
+                ) != (
+                    self.protocol_version,
+                    self.cipher_suite,
+                    self.transfer_id,
+                    self.chunk_index,
+                    self.total_chunks,
+                ) {
+                    return Err(header_mismatch);
+                }
+                aad.chunk_digest
+            }
+            None => {
+                let Some(digest) = self.chunk_digest() else {
+                    return Ok(());
+                };
+                let mut header = Vec::with_capacity(CHUNK_AAD_LEN);
+                header.extend_from_slice(&self.transfer_id.to_be_bytes());
+                header.extend_from_slice(&self.chunk_index.to_be_bytes());
+                header.extend_from_slice(&self.total_chunks.to_be_bytes());
+                if self.aad[..CHUNK_AAD_LEN] != header[..] {
+                    return Err(header_mismatch);
+                }
+                Some(digest)
+            }
+        };
+        let Some(digest) = digest else {
+            return Ok(());
+        };
+        if <[u8; CHUNK_DIGEST_LEN]>::from(Sha256::digest(plaintext)) != digest {
+            return Err(TransferError::ChunkDigestMismatch);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,482 @@
+use crate::{
+    check_chunk_nonce, chunk_nonce, CipherSuite, CompressionCodec, Direction, EncryptionFlag,
+    TransferChunk, TransferError, CHUNK_DIGEST_LEN, MAX_DECOMPRESSED_CHUNK_LEN,
+};
+use crypto_envelope::{decrypt_with_suite, derive_transfer_key, encrypt_with_suite};
+use sha2::{Digest, Sha256};
+
+pub(crate) const MAGIC_V3: &[u8; 4] = b"P2P3";
+const PROTOCOL_VERSION_V3: u8 = 3;
+/// MAGIC | version | flags | transfer_id | chunk_index | total_chunks | nonce |
+/// extensions_len(u16) | payload_len(u32)
+const HEADER_LEN: usize = 4 + 1 + 1 + 8 + 4 + 4 + 12 + 2 + 4;
+/// extensions_len(u16) | payload_len(u32) at the start of an encrypted frame's plaintext.
+const SEALED_HEADER_LEN: usize = 2 + 4;
+/// type(u8) | len(u16) ahead of each extension value.
+const EXTENSION_HEADER_LEN: usize = 1 + 2;
+
+const EXT_CHECKSUM: u8 = 1;
+const EXT_COMPRESSION: u8 = 2;
+const EXT_FILE_INDEX: u8 = 3;
+const EXT_TIMESTAMP: u8 = 4;
+const EXT_PADDING: u8 = 5;
+/// Extension types from here up must be understood; a receiver that does not know one
+/// rejects the frame instead of skipping it.
+const EXT_CRITICAL: u8 = 0x80;
+
+/// One type-length-value field of a v3 frame.
+///
+/// New metadata gets a new extension type rather than a new frame magic. Unknown
+/// non-critical types are kept as [`FrameExtension::Unknown`] and otherwise ignored.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameExtension {
+    /// SHA-256 of the uncompressed plaintext payload.
+    Checksum([u8; CHUNK_DIGEST_LEN]),
+    /// Codec applied to the plaintext before encryption.
+    Compression(CompressionCodec),
+    /// File within a batch transfer; absent means file 0.
+    FileIndex(u32),
+    /// Sender's clock when the frame was built, in milliseconds since the Unix epoch.
+    TimestampMs(u64),
+    /// This many filler bytes, to hide the real payload size.
+    Padding(u16),
+    Unknown {
+        kind: u8,
+        value: Vec<u8>,
+    },
+}
+
+impl FrameExtension {
+    fn kind(&self) -> u8 {
+        match self {
+            FrameExtension::Checksum(_) => EXT_CHECKSUM,
+            FrameExtension::Compression(_) => EXT_COMPRESSION,
+            FrameExtension::FileIndex(_) => EXT_FILE_INDEX,
+            FrameExtension::TimestampMs(_) => EXT_TIMESTAMP,
+            FrameExtension::Padding(_) => EXT_PADDING,
+            FrameExtension::Unknown { kind, .. } => *kind,
+        }
+    }
+
+    fn value(&self) -> Vec<u8> {
+        match self {
+            FrameExtension::Checksum(digest) => digest.to_vec(),
+            FrameExtension::Compression(codec) => vec![codec.as_u8()],
+            FrameExtension::FileIndex(index) => index.to_be_bytes().to_vec(),
+            FrameExtension::TimestampMs(ms) => ms.to_be_bytes().to_vec(),
+            FrameExtension::Padding(len) => vec![0; *len as usize],
+            FrameExtension::Unknown { value, .. } => value.clone(),
+        }
+    }
+
+    fn decode(kind: u8, value: &[u8]) -> Result<Self, TransferError> {
+        let bad_len = TransferError::InvalidFrame("invalid extension length");
+        Ok(match kind {
+            EXT_CHECKSUM => FrameExtension::Checksum(value.try_into().map_err(|_| bad_len)?),
+            EXT_COMPRESSION => {
+                let [codec] = value else {
+                    return Err(bad_len);
+                };
+                FrameExtension::Compression(CompressionCodec::from_u8(*codec)?)
+            }
+            EXT_FILE_INDEX => FrameExtension::FileIndex(u32::from_be_bytes(
+                value.try_into().map_err(|_| bad_len)?,
+            )),
+            EXT_TIMESTAMP => FrameExtension::TimestampMs(u64::from_be_bytes(
+                value.try_into().map_err(|_| bad_len)?,
+            )),
+            EXT_PADDING => FrameExtension::Padding(value.len() as u16),
+            kind if kind >= EXT_CRITICAL => {
+                return Err(TransferError::InvalidFrame("unknown critical extension"))
+            }
+            kind => FrameExtension::Unknown {
+                kind,
+                value: value.to_vec(),
+            },
+        })
+    }
+}
+
+/// Chunk frame whose metadata travels as TLV extensions after a fixed header.
+///
+/// Encrypted frames carry no extensions in the clear: the extensions and the payload
+/// length are sealed together with the payload, so padding hides the real size and
+/// nothing but the authenticated header can be read or altered in transit.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferChunkV3 {
+    pub encryption_flag: EncryptionFlag,
+    /// Sent in the flags byte like v2's; covered by the AAD of encrypted frames.
+    pub cipher_suite: CipherSuite,
+    pub transfer_id: u64,
+    pub chunk_index: u32,
+    pub total_chunks: u32,
+    pub nonce: [u8; 12],
+    /// Always empty on encrypted frames; theirs are inside `payload`.
+    pub extensions: Vec<FrameExtension>,
+    pub payload: Vec<u8>,
+}
+
+impl TransferChunkV3 {
+    pub fn encode(&self) -> Result<Vec<u8>, TransferError> {
+        let payload_len = u32::try_from(self.payload.len())
+            .map_err(|_| TransferError::InvalidFrame("payload too large"))?;
+        let mut out = self.authenticated_bytes()?;
+        // payload_len sits between extensions_len and the extensions.
+        out.splice(HEADER_LEN - 4..HEADER_LEN - 4, payload_len.to_be_bytes());
+        out.extend_from_slice(&self.payload);
+        Ok(out)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, TransferError> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC_V3 {
+            return Err(TransferError::InvalidFrame("bad v3 header"));
+        }
+        if bytes[4] != PROTOCOL_VERSION_V3 {
+            return Err(TransferError::InvalidFrame("unsupported protocol version"));
+        }
+        let (encryption_flag, cipher_suite) = EncryptionFlag::from_u8(bytes[5])?;
+        let transfer_id = u64::from_be_bytes(bytes[6..14].try_into().expect("slice len"));
+        let chunk_index = u32::from_be_bytes(bytes[14..18].try_into().expect("slice len"));
+        let total_chunks = u32::from_be_bytes(bytes[18..22].try_into().expect("slice len"));
+        if total_chunks == 0 || chunk_index >= total_chunks {
+            return Err(TransferError::InvalidFrame("invalid chunk bounds"));
+        }
+        let mut nonce = [0u8; 12];
+        nonce.copy_from_slice(&bytes[22..34]);
+        let extensions_len =
+            u16::from_be_bytes(bytes[34..36].try_into().expect("slice len")) as usize;
+        let payload_len = u32::from_be_bytes(bytes[36..40].try_into().expect("slice len")) as usize;
+        if bytes.len() != HEADER_LEN + extensions_len + payload_len {
+            return Err(TransferError::InvalidFrame("invalid payload length"));
+        }
+        if encryption_flag == EncryptionFlag::Encrypted && extensions_len != 0 {
+            return Err(TransferError::InvalidFrame(
+                "encrypted frames carry sealed extensions",
+            ));
+        }
+
+        let payload_start = HEADER_LEN + extensions_len;
+        let frame = Self {
+            encryption_flag,
+            cipher_suite,
+            transfer_id,
+            chunk_index,
+            total_chunks,
+            nonce,
+            extensions: decode_extensions(&bytes[HEADER_LEN..payload_start])?,
+            payload: bytes[payload_start..].to_vec(),
+        };
+        // Encrypted payloads are checked after decryption, against the plaintext.
+        if frame.encryption_flag == EncryptionFlag::Plaintext {
+            if frame.compression() != CompressionCodec::None {
+                return Err(TransferError::InvalidFrame(
+                    "plaintext frames are not compressed",
+                ));
+            }
+            verify_checksum(&frame.extensions, &frame.payload)?;
+        }
+        Ok(frame)
+    }
+
+    pub fn checksum(&self) -> Option<[u8; CHUNK_DIGEST_LEN]> {
+        checksum_of(&self.extensions)
+    }
+
+    pub fn compression(&self) -> CompressionCodec {
+        compression_of(&self.extensions)
+    }
+
+    pub fn file_index(&self) -> u32 {
+        file_index_of(&self.extensions)
+    }
+
+    pub fn timestamp_ms(&self) -> Option<u64> {
+        self.extensions.iter().find_map(|ext| match ext {
+            FrameExtension::TimestampMs(ms) => Some(*ms),
+            _ => None,
+        })
+    }
+
+    /// Everything but the payload and its length: the AAD of encrypted frames. The
+    /// ciphertext length is left out since it is only known after sealing.
+    fn authenticated_bytes(&self) -> Result<Vec<u8>, TransferError> {
+        let (extensions_len, extensions) = encode_extensions(&self.extensions)?;
+
+        let mut out = Vec::with_capacity(HEADER_LEN + extensions.len() + self.payload.len());
+        out.extend_from_slice(MAGIC_V3);
+        out.push(PROTOCOL_VERSION_V3);
+        out.push(self.encryption_flag.as_u8(self.cipher_suite));
+        out.extend_from_slice(&self.transfer_id.to_be_bytes());
+        out.extend_from_slice(&self.chunk_index.to_be_bytes());
+        out.extend_from_slice(&self.total_chunks.to_be_bytes());
+        out.extend_from_slice(&self.nonce);
+        out.extend_from_slice(&extensions_len.to_be_bytes());
+        out.extend_from_slice(&extensions);
+        Ok(out)
+    }
+}
+
+fn encode_extensions(extensions: &[FrameExtension]) -> Result<(u16, Vec<u8>), TransferError> {
+    check_unique(extensions)?;
+    let mut out = Vec::new();
+    for ext in extensions {
+        let value = ext.value();
+        let len = u16::try_from(value.len())
+            .map_err(|_| TransferError::InvalidFrame("extension too large"))?;
+        out.push(ext.kind());
+        out.extend_from_slice(&len.to_be_bytes());
+        out.extend_from_slice(&value);
+    }
+    let len = u16::try_from(out.len())
+        .map_err(|_| TransferError::InvalidFrame("extensions too large"))?;
+    Ok((len, out))
+}
+
+/// Plaintext of an encrypted frame: extensions_len(u16) | payload_len(u32) |
+/// extensions | payload.
+fn encode_sealed(extensions: &[FrameExtension], payload: &[u8]) -> Result<Vec<u8>, TransferError> {
+    let (extensions_len, extensions) = encode_extensions(extensions)?;
+    let payload_len = u32::try_from(payload.len())
+        .map_err(|_| TransferError::InvalidFrame("payload too large"))?;
+    let mut out = Vec::with_capacity(SEALED_HEADER_LEN + extensions.len() + payload.len());
+    out.extend_from_slice(&extensions_len.to_be_bytes());
+    out.extend_from_slice(&payload_len.to_be_bytes());
+    out.extend_from_slice(&extensions);
+    out.extend_from_slice(payload);
+    Ok(out)
+}
+
+fn decode_sealed(mut sealed: Vec<u8>) -> Result<(Vec<FrameExtension>, Vec<u8>), TransferError> {
+    if sealed.len() < SEALED_HEADER_LEN {
+        return Err(TransferError::InvalidFrame("truncated sealed header"));
+    }
+    let extensions_len = u16::from_be_bytes(sealed[..2].try_into().expect("slice len")) as usize;
+    let payload_len = u32::from_be_bytes(sealed[2..6].try_into().expect("slice len")) as usize;
+    let payload_start = SEALED_HEADER_LEN + extensions_len;
+    if sealed.len() != payload_start + payload_len {
+        return Err(TransferError::InvalidFrame("invalid payload length"));
+    }
+    let extensions = decode_extensions(&sealed[SEALED_HEADER_LEN..payload_start])?;
+    sealed.drain(..payload_start);
+    Ok((extensions, sealed))
+}
+
+fn checksum_of(extensions: &[FrameExtension]) -> Option<[u8; CHUNK_DIGEST_LEN]> {
+    extensions.iter().find_map(|ext| match ext {
+        FrameExtension::Checksum(digest) => Some(*digest),
+        _ => None,
+    })
+}
+
+fn compression_of(extensions: &[FrameExtension]) -> CompressionCodec {
+    extensions
+        .iter()
+        .find_map(|ext| match ext {
+            FrameExtension::Compression(codec) => Some(*codec),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+fn file_index_of(extensions: &[FrameExtension]) -> u32 {
+    extensions
+        .iter()
+        .find_map(|ext| match ext {
+            FrameExtension::FileIndex(index) => Some(*index),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+fn verify_checksum(extensions: &[FrameExtension], plaintext: &[u8]) -> Result<(), TransferError> {
+    match checksum_of(extensions) {
+        Some(digest) if <[u8; CHUNK_DIGEST_LEN]>::from(Sha256::digest(plaintext)) != digest => {
+            Err(TransferError::ChunkDigestMismatch)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn decode_extensions(mut bytes: &[u8]) -> Result<Vec<FrameExtension>, TransferError> {
+    let mut extensions = Vec::new();
+    while !bytes.is_empty() {
+        if bytes.len() < EXTENSION_HEADER_LEN {
+            return Err(TransferError::InvalidFrame("truncated extension"));
+        }
+        let kind = bytes[0];
+        let len = u16::from_be_bytes(bytes[1..3].try_into().expect("slice len")) as usize;
+        let end = EXTENSION_HEADER_LEN + len;
+        if bytes.len() < end {
+            return Err(TransferError::InvalidFrame("truncated extension"));
+        }
+        extensions.push(FrameExtension::decode(
+            kind,
+            &bytes[EXTENSION_HEADER_LEN..end],
+        )?);
+        bytes = &bytes[end..];
+    }
+    check_unique(&extensions)?;
+    Ok(extensions)
+}
+
+/// Each known extension may appear once; padding and unknown types may repeat.
+fn check_unique(extensions: &[FrameExtension]) -> Result<(), TransferError> {
+    for (i, ext) in extensions.iter().enumerate() {
+        if matches!(
+            ext,
+            FrameExtension::Unknown { .. } | FrameExtension::Padding(_)
+        ) {
+            continue;
+        }
+        if extensions[..i].iter().any(|seen| seen.kind() == ext.kind()) {
+            return Err(TransferError::InvalidFrame("duplicate frame extension"));
+        }
+    }
+    Ok(())
+}
+
+/// Unencrypted v3 frame with the payload checksum, the file index when not 0, and
+/// any `extra` extensions such as a timestamp or padding.
+pub fn plaintext_chunk_frame_v3(
+    chunk: &TransferChunk,
+    extra: impl IntoIterator<Item = FrameExtension>,
+) -> TransferChunkV3 {
+    let mut extensions = chunk_extensions(chunk, CompressionCodec::None);
+    extensions.extend(extra);
+    TransferChunkV3 {
+        encryption_flag: EncryptionFlag::Plaintext,
+        cipher_suite: CipherSuite::default(),
+        transfer_id: chunk.transfer_id,
+        chunk_index: chunk.chunk_index,
+        total_chunks: chunk.total_chunks,
+        nonce: [0u8; 12],
+        extensions,
+        payload: chunk.payload.clone(),
+    }
+}
+
+/// Compress with `codec` when that shrinks the chunk, then encrypt it as a v3 frame.
+///
+/// All extensions, `extra` included, are sealed with the payload; the header is bound
+/// in as AAD.
+pub fn encrypt_chunk_frame_v3(
+    chunk: &TransferChunk,
+    session_tx_key: &[u8; 32],
+    direction: Direction,
+    codec: CompressionCodec,
+    extra: impl IntoIterator<Item = FrameExtension>,
+) -> Result<TransferChunkV3, TransferError> {
+    encrypt_chunk_frame_v3_with_suite(
+        chunk,
+        session_tx_key,
+        direction,
+        CipherSuite::default(),
+        codec,
+        extra,
+    )
+}
+
+/// [`encrypt_chunk_frame_v3`] under the cipher suite negotiated in the handshake.
+pub fn encrypt_chunk_frame_v3_with_suite(
+    chunk: &TransferChunk,
+    session_tx_key: &[u8; 32],
+    direction: Direction,
+    suite: CipherSuite,
+    codec: CompressionCodec,
+    extra: impl IntoIterator<Item = FrameExtension>,
+) -> Result<TransferChunkV3, TransferError> {
+    let compressed = match codec {
+        CompressionCodec::None => None,
+        codec => Some(codec.compress(&chunk.payload)?)
+            .filter(|compressed| compressed.len() < chunk.payload.len()),
+    };
+    let (codec, plaintext) = match &compressed {
+        Some(compressed) => (codec, compressed.as_slice()),
+        None => (CompressionCodec::None, chunk.payload.as_slice()),
+    };
+
+    let mut extensions = chunk_extensions(chunk, codec);
+    extensions.extend(extra);
+    let mut frame = TransferChunkV3 {
+        encryption_flag: EncryptionFlag::Encrypted,
+        cipher_suite: suite,
+        transfer_id: chunk.transfer_id,
+        chunk_index: chunk.chunk_index,
+        total_chunks: chunk.total_chunks,
+        nonce: chunk_nonce(chunk.transfer_id, chunk.chunk_index, direction)?,
+        extensions: Vec::new(),
+        payload: Vec::new(),
+    };
+    let sealed = encode_sealed(&extensions, plaintext)?;
+    let aad = frame.authenticated_bytes()?;
+    let key = derive_transfer_key(session_tx_key, chunk.transfer_id);
+    frame.payload = encrypt_with_suite(suite, key.expose(), frame.nonce, &sealed, &aad)
+        .map_err(|_| TransferError::Crypto("failed to encrypt chunk payload"))?;
+    Ok(frame)
+}
+
+/// Decrypt a v3 frame sent in `direction` under the negotiated `suite`, decompress it,
+/// and check its checksum.
+pub fn decrypt_chunk_frame_v3(
+    frame: &TransferChunkV3,
+    session_rx_key: &[u8; 32],
+    direction: Direction,
+    suite: CipherSuite,
+) -> Result<TransferChunk, TransferError> {
+    if frame.encryption_flag != EncryptionFlag::Encrypted {
+        return Err(TransferError::InvalidFrame("expected encrypted frame"));
+    }
+    if frame.cipher_suite != suite {
+        return Err(TransferError::Crypto("cipher suite was not negotiated"));
+    }
+    if !frame.extensions.is_empty() {
+        return Err(TransferError::InvalidFrame(
+            "encrypted frames carry sealed extensions",
+        ));
+    }
+    check_chunk_nonce(
+        &frame.nonce,
+        frame.transfer_id,
+        frame.chunk_index,
+        direction,
+    )?;
+    let aad = frame.authenticated_bytes()?;
+    let key = derive_transfer_key(session_rx_key, frame.transfer_id);
+    let sealed = decrypt_with_suite(
+        frame.cipher_suite,
+        key.expose(),
+        frame.nonce,
+        &frame.payload,
+        &aad,
+    )
+    .map_err(|_| TransferError::Crypto("failed to decrypt chunk payload"))?;
+    let (extensions, plaintext) = decode_sealed(sealed)?;
+    let plaintext = match compression_of(&extensions) {
+        CompressionCodec::None => plaintext,
+        codec => codec.decompress(&plaintext, MAX_DECOMPRESSED_CHUNK_LEN)?,
+    };
+    verify_checksum(&extensions, &plaintext)?;
+
+    Ok(TransferChunk {
+        transfer_id: frame.transfer_id,
+        file_index: file_index_of(&extensions),
+        chunk_index: frame.chunk_index,
+        total_chunks: frame.total_chunks,
+        payload: plaintext,
+    })
+}
+
+fn chunk_extensions(chunk: &TransferChunk, codec: CompressionCodec) -> Vec<FrameExtension> {
+    let mut extensions = vec![FrameExtension::Checksum(
+        Sha256::digest(&chunk.payload).into(),
+    )];
+    if codec != CompressionCodec::None {
+        extensions.push(FrameExtension::Compression(codec));
+    }
+    if chunk.file_index != 0 {
+        extensions.push(FrameExtension::FileIndex(chunk.file_index));
+    }
+    extensions
+}
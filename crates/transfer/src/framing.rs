@@ -0,0 +1,199 @@
+use crate::TransferError;
+use std::io::{ErrorKind, Read, Write};
+
+/// Distinct from every datagram magic, so a resync never locks onto the start of the
+/// frame being carried rather than its stream header.
+const MAGIC_STREAM: &[u8; 4] = b"P2PT";
+/// MAGIC | len(u32) | !len(u32): the complement lets a reader tell a real header from
+/// garbage that happens to contain the magic.
+const HEADER_LEN: usize = 4 + 4 + 4;
+const READ_BUF_LEN: usize = 64 * 1024;
+
+/// Largest frame accepted by default; larger lengths are treated as garbage.
+pub const DEFAULT_MAX_STREAM_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// Length-prefix one frame for a byte stream.
+pub fn encode_stream_frame(frame: &[u8]) -> Result<Vec<u8>, TransferError> {
+    let len = u32::try_from(frame.len())
+        .map_err(|_| TransferError::InvalidFrame("frame too large for stream"))?;
+    let mut out = Vec::with_capacity(HEADER_LEN + frame.len());
+    out.extend_from_slice(MAGIC_STREAM);
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(&(!len).to_be_bytes());
+    out.extend_from_slice(frame);
+    Ok(out)
+}
+
+/// Incremental decoder for length-prefixed frames, independent of any I/O.
+///
+/// Feed it whatever the socket returned with [`Self::push`]; [`Self::next_frame`] yields
+/// complete frames and skips bytes that do not start a valid header.
+#[derive(Debug)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    max_frame_len: usize,
+    skipped: u64,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_STREAM_FRAME_LEN)
+    }
+}
+
+impl FrameDecoder {
+    pub fn new(max_frame_len: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            max_frame_len,
+            skipped: 0,
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        loop {
+            self.resync();
+            if self.buf.len() < HEADER_LEN {
+                return None;
+            }
+            let len = u32::from_be_bytes(self.buf[4..8].try_into().expect("slice len"));
+            let check = u32::from_be_bytes(self.buf[8..12].try_into().expect("slice len"));
+            if check != !len || len as usize > self.max_frame_len {
+                // Not a real header: drop the magic's first byte and search again.
+                self.skip(1);
+                continue;
+            }
+            let end = HEADER_LEN + len as usize;
+            if self.buf.len() < end {
+                return None;
+            }
+            let frame = self.buf[HEADER_LEN..end].to_vec();
+            self.buf.drain(..end);
+            return Some(frame);
+        }
+    }
+
+    /// Bytes discarded while resynchronising, for diagnostics.
+    pub fn skipped_bytes(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Bytes held that do not yet form a complete frame.
+    pub fn buffered_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Drop bytes up to the next possible magic. A partial magic at the end is kept,
+    /// since the rest of it may still arrive.
+    fn resync(&mut self) {
+        let start = (0..self.buf.len())
+            .find(|&i| {
+                let tail = &self.buf[i..];
+                let n = tail.len().min(MAGIC_STREAM.len());
+                tail[..n] == MAGIC_STREAM[..n]
+            })
+            .unwrap_or(self.buf.len());
+        self.skip(start);
+    }
+
+    fn skip(&mut self, n: usize) {
+        self.buf.drain(..n);
+        self.skipped += n as u64;
+    }
+}
+
+/// Reads whole frames off a byte stream such as a `TcpStream`.
+#[derive(Debug)]
+pub struct FrameReader<R> {
+    inner: R,
+    decoder: FrameDecoder,
+    read_buf: Box<[u8]>,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_decoder(inner, FrameDecoder::default())
+    }
+
+    pub fn with_decoder(inner: R, decoder: FrameDecoder) -> Self {
+        Self {
+            inner,
+            decoder,
+            read_buf: vec![0u8; READ_BUF_LEN].into_boxed_slice(),
+        }
+    }
+
+    /// Next frame, or `None` once the stream ends cleanly between frames.
+    ///
+    /// A stream that ends inside a frame is an error; trailing garbage is not.
+    pub fn read_frame(&mut self) -> Result<Option<Vec<u8>>, TransferError> {
+        loop {
+            if let Some(frame) = self.decoder.next_frame() {
+                return Ok(Some(frame));
+            }
+            let n = match self.inner.read(&mut self.read_buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if n == 0 {
+                return if self.decoder.buffered_len() == 0 {
+                    Ok(None)
+                } else if self.decoder.buf.starts_with(MAGIC_STREAM) {
+                    Err(TransferError::InvalidFrame("stream ended mid-frame"))
+                } else {
+                    let rest = self.decoder.buffered_len();
+                    self.decoder.skip(rest);
+                    Ok(None)
+                };
+            }
+            self.decoder.push(&self.read_buf[..n]);
+        }
+    }
+
+    pub fn skipped_bytes(&self) -> u64 {
+        self.decoder.skipped_bytes()
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+/// Writes length-prefixed frames to a byte stream.
+#[derive(Debug)]
+pub struct FrameWriter<W> {
+    inner: W,
+}
+
+impl<W: Write> FrameWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    pub fn write_frame(&mut self, frame: &[u8]) -> Result<(), TransferError> {
+        self.inner.write_all(&encode_stream_frame(frame)?)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), TransferError> {
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
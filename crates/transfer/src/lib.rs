@@ -1,56 +1,166 @@
-use crypto_envelope::{decrypt_chunk, derive_nonce, encrypt_chunk, Direction};
-use std::collections::HashMap;
+mod adaptive;
+mod batch;
+mod compression;
+mod control;
+mod duplex;
+mod fairness;
+mod fanout;
+mod frame_ref;
+mod frame_v3;
+mod framing;
+mod manifest;
+mod observer;
+mod outbound;
+mod rate_limit;
+mod receipt;
+mod receive;
+mod scheduler;
+mod selective_ack;
+#[cfg(feature = "serde")]
+mod serde_support;
+mod session_params;
+mod snapshot;
+mod source;
+mod tags;
+#[cfg(feature = "tokio")]
+mod tokio_driver;
+mod transfer_id;
+mod window;
+
+pub use adaptive::{AdaptiveChunkConfig, AdaptiveChunker};
+pub use batch::FileProgress;
+pub use compression::{
+    compress_payload, decompress_payload, select_compression, CompressionCapabilities,
+    CompressionCodec, CompressionPlan, DictionaryDescriptor, DictionaryStore,
+    MAX_DECOMPRESSED_CHUNK_LEN,
+};
+pub use control::{CancelReason, ControlFrame, ErrorFrame, Termination, TransferErrorCode};
+pub use crypto_envelope::{AadBuilder, ChunkAad, CipherSuite, Direction, PaddingScheme};
+pub use duplex::{DuplexSession, SessionRole};
+pub use fairness::{FairScheduler, FairnessConfig};
+pub use frame_ref::{TransferChunkRef, TransferChunkV2Ref};
+pub use frame_v3::{
+    decrypt_chunk_frame_v3, encrypt_chunk_frame_v3, encrypt_chunk_frame_v3_with_suite,
+    plaintext_chunk_frame_v3, FrameExtension, TransferChunkV3,
+};
+pub use framing::{
+    encode_stream_frame, FrameDecoder, FrameReader, FrameWriter, DEFAULT_MAX_STREAM_FRAME_LEN,
+};
+pub use manifest::{open_manifest, seal_manifest, SignedTransferManifest, TransferManifest};
+pub use observer::TransferObserver;
+pub use outbound::{
+    outbound_queue, OutboundReceiver, OutboundSender, QueueStats, SendChunk, SendPermit,
+};
+pub use rate_limit::{RateLimit, RateLimiter};
+pub use receipt::CompletionReceipt;
+pub use receive::{ChunkReceipt, ReceiveSession};
+pub use scheduler::{Lane, SchedulerConfig, TransferScheduler};
+pub use selective_ack::{SelectiveAck, MAX_SACK_SPAN};
+pub use session_params::SessionParams;
+pub use snapshot::{ReceiverSnapshot, SnapshotSource, TransferSnapshot};
+pub use source::{
+    FileSource, MemorySource, ReadAheadConfig, ReadAheadStats, SourceSnapshot, TransferSource,
+    DEFAULT_CHANGE_CHECK_READS,
+};
+pub use tags::{normalize_tags, MAX_TAGS, MAX_TAG_LEN};
+#[cfg(feature = "tokio")]
+pub use tokio_driver::{receive_transfer, send_transfer, DriverConfig};
+pub use transfer_id::TransferIdRegistry;
+pub use window::{SendWindow, DEFAULT_WINDOW_CHUNKS};
+
+use batch::BatchFile;
+use bytes::BufMut;
+use crypto_envelope::{
+    decrypt_with_suite, derive_transfer_key, encrypt_chunks_with_suite, nonce_with_epoch,
+    try_derive_nonce, unpad, BatchChunk, EncryptStream, NonceSequencer,
+};
+use fanout::ReceiverKeys;
+use observer::Observers;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 const MAGIC_V1: &[u8; 4] = b"P2PF";
 const MAGIC_V2: &[u8; 4] = b"P2PE";
+const PROTOCOL_VERSION_V2: u8 = 2;
+/// MAGIC | transfer_id | chunk_index | total_chunks | payload_len(u32)
+const CHUNK_V1_HEADER_LEN: usize = 4 + 8 + 4 + 4 + 4;
+/// MAGIC | version | flags | transfer_id | chunk_index | total_chunks | nonce |
+/// aad_len(u16) | payload_len(u32)
+const CHUNK_V2_HEADER_LEN: usize = 4 + 1 + 1 + 8 + 4 + 4 + 12 + 2 + 4;
 
+/// Length of the base AAD frames had before [`AadBuilder`]: transfer_id | chunk_index |
+/// total_chunks. Still read, never written.
+const CHUNK_AAD_LEN: usize = 8 + 4 + 4;
+/// File index appended to that base AAD for files after the first in a batch.
+const CHUNK_FILE_INDEX_LEN: usize = 4;
+/// SHA-256 of the plaintext payload, appended to the AAD when a frame carries one.
+pub const CHUNK_DIGEST_LEN: usize = 32;
+/// Largest file [`TransferSession::for_upload`] buffers in memory; bigger files belong
+/// in a [`FileSource`] session.
+pub const MAX_UPLOAD_BYTES: u64 = 256 * 1024 * 1024;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TransferChunk {
     pub transfer_id: u64,
+    /// File within a batch transfer; 0 for single-file transfers. v1 frames cannot
+    /// carry it, so batches need v2.
+    pub file_index: u32,
     pub chunk_index: u32,
     pub total_chunks: u32,
     pub payload: Vec<u8>,
 }
 
 impl TransferChunk {
-    pub fn encode(&self) -> Vec<u8> {
-        let payload_len = u32::try_from(self.payload.len()).unwrap_or(u32::MAX);
-        let mut out = Vec::with_capacity(4 + 8 + 4 + 4 + 4 + payload_len as usize);
-        out.extend_from_slice(MAGIC_V1);
-        out.extend_from_slice(&self.transfer_id.to_be_bytes());
-        out.extend_from_slice(&self.chunk_index.to_be_bytes());
-        out.extend_from_slice(&self.total_chunks.to_be_bytes());
-        out.extend_from_slice(&payload_len.to_be_bytes());
-        out.extend_from_slice(&self.payload[..payload_len as usize]);
-        out
+    pub fn encode(&self) -> Result<Vec<u8>, TransferError> {
+        let mut out = Vec::with_capacity(self.encoded_len());
+        self.encode_to(&mut out)?;
+        Ok(out)
     }
 
-    pub fn decode(bytes: &[u8]) -> Result<Self, TransferError> {
-        if bytes.len() < 24 || &bytes[..4] != MAGIC_V1 {
-            return Err(TransferError::InvalidFrame("bad header"));
-        }
+    /// Exact size of [`Self::encode`]'s output, for sizing a reusable buffer.
+    pub fn encoded_len(&self) -> usize {
+        CHUNK_V1_HEADER_LEN + self.payload.len().min(u32::MAX as usize)
+    }
 
-        let transfer_id = u64::from_be_bytes(bytes[4..12].try_into().expect("slice len"));
-        let chunk_index = u32::from_be_bytes(bytes[12..16].try_into().expect("slice len"));
-        let total_chunks = u32::from_be_bytes(bytes[16..20].try_into().expect("slice len"));
-        let payload_len = u32::from_be_bytes(bytes[20..24].try_into().expect("slice len")) as usize;
+    /// Encode into the front of `out` and return the frame length.
+    pub fn encode_into(&self, mut out: &mut [u8]) -> Result<usize, TransferError> {
+        self.encode_to(&mut out)?;
+        Ok(self.encoded_len())
+    }
 
-        if bytes.len() != 24 + payload_len {
-            return Err(TransferError::InvalidFrame("invalid payload length"));
+    /// Append the frame to `out` without an intermediate allocation.
+    pub fn encode_to(&self, out: &mut impl BufMut) -> Result<(), TransferError> {
+        if self.file_index != 0 {
+            return Err(TransferError::InvalidFrame(
+                "v1 frames cannot carry a file index",
+            ));
         }
-        if total_chunks == 0 || chunk_index >= total_chunks {
-            return Err(TransferError::InvalidFrame("invalid chunk bounds"));
+        if out.remaining_mut() < self.encoded_len() {
+            return Err(TransferError::InvalidFrame("buffer too small for frame"));
         }
+        let payload_len = u32::try_from(self.payload.len()).unwrap_or(u32::MAX);
+        out.put_slice(MAGIC_V1);
+        out.put_u64(self.transfer_id);
+        out.put_u32(self.chunk_index);
+        out.put_u32(self.total_chunks);
+        out.put_u32(payload_len);
+        out.put_slice(&self.payload[..payload_len as usize]);
+        Ok(())
+    }
 
-        Ok(Self {
-            transfer_id,
-            chunk_index,
-            total_chunks,
-            payload: bytes[24..].to_vec(),
-        })
+    pub fn decode(bytes: &[u8]) -> Result<Self, TransferError> {
+        TransferChunkRef::decode(bytes).map(TransferChunkRef::into_owned)
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EncryptionFlag {
     Plaintext,
@@ -58,26 +168,32 @@ pub enum EncryptionFlag {
 }
 
 impl EncryptionFlag {
-    fn as_u8(self) -> u8 {
+    /// 0 for plaintext, else the id of the suite the payload is sealed with.
+    pub(crate) fn as_u8(self, suite: CipherSuite) -> u8 {
         match self {
             EncryptionFlag::Plaintext => 0,
-            EncryptionFlag::Encrypted => 1,
+            EncryptionFlag::Encrypted => suite.as_u8(),
         }
     }
 
-    fn from_u8(v: u8) -> Result<Self, TransferError> {
+    pub(crate) fn from_u8(v: u8) -> Result<(Self, CipherSuite), TransferError> {
         match v {
-            0 => Ok(EncryptionFlag::Plaintext),
-            1 => Ok(EncryptionFlag::Encrypted),
-            _ => Err(TransferError::InvalidFrame("invalid encryption flag")),
+            0 => Ok((EncryptionFlag::Plaintext, CipherSuite::default())),
+            v => CipherSuite::from_u8(v)
+                .map(|suite| (EncryptionFlag::Encrypted, suite))
+                .ok_or(TransferError::InvalidFrame("unsupported cipher suite")),
         }
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TransferChunkV2 {
     pub protocol_version: u8,
     pub encryption_flag: EncryptionFlag,
+    /// AEAD of an encrypted payload, sent in place of the encryption flag's "on" value;
+    /// meaningless for plaintext frames.
+    pub cipher_suite: CipherSuite,
     pub transfer_id: u64,
     pub chunk_index: u32,
     pub total_chunks: u32,
@@ -88,128 +204,472 @@ pub struct TransferChunkV2 {
 
 impl TransferChunkV2 {
     pub fn encode(&self) -> Vec<u8> {
-        let aad_len = u16::try_from(self.aad.len()).unwrap_or(u16::MAX);
-        let payload_len = u32::try_from(self.payload.len()).unwrap_or(u32::MAX);
-
-        let mut out = Vec::with_capacity(
-            4 + 1 + 1 + 8 + 4 + 4 + 12 + 2 + 4 + aad_len as usize + payload_len as usize,
-        );
-        out.extend_from_slice(MAGIC_V2);
-        out.push(self.protocol_version);
-        out.push(self.encryption_flag.as_u8());
-        out.extend_from_slice(&self.transfer_id.to_be_bytes());
-        out.extend_from_slice(&self.chunk_index.to_be_bytes());
-        out.extend_from_slice(&self.total_chunks.to_be_bytes());
-        out.extend_from_slice(&self.nonce);
-        out.extend_from_slice(&aad_len.to_be_bytes());
-        out.extend_from_slice(&payload_len.to_be_bytes());
-        out.extend_from_slice(&self.aad[..aad_len as usize]);
-        out.extend_from_slice(&self.payload[..payload_len as usize]);
+        let mut out = Vec::with_capacity(self.encoded_len());
+        self.write(&mut out);
         out
     }
 
-    pub fn decode(bytes: &[u8]) -> Result<Self, TransferError> {
-        let min_header = 4 + 1 + 1 + 8 + 4 + 4 + 12 + 2 + 4;
-        if bytes.len() < min_header || &bytes[..4] != MAGIC_V2 {
-            return Err(TransferError::InvalidFrame("bad v2 header"));
-        }
+    /// Exact size of [`Self::encode`]'s output, for sizing a reusable buffer.
+    pub fn encoded_len(&self) -> usize {
+        CHUNK_V2_HEADER_LEN
+            + self.aad.len().min(u16::MAX as usize)
+            + self.payload.len().min(u32::MAX as usize)
+    }
 
-        let protocol_version = bytes[4];
-        let encryption_flag = EncryptionFlag::from_u8(bytes[5])?;
-        let transfer_id = u64::from_be_bytes(bytes[6..14].try_into().expect("slice len"));
-        let chunk_index = u32::from_be_bytes(bytes[14..18].try_into().expect("slice len"));
-        let total_chunks = u32::from_be_bytes(bytes[18..22].try_into().expect("slice len"));
+    /// Encode into the front of `out` and return the frame length.
+    pub fn encode_into(&self, mut out: &mut [u8]) -> Result<usize, TransferError> {
+        self.encode_to(&mut out)?;
+        Ok(self.encoded_len())
+    }
 
-        if protocol_version != 2 {
-            return Err(TransferError::InvalidFrame("unsupported protocol version"));
-        }
-        if total_chunks == 0 || chunk_index >= total_chunks {
-            return Err(TransferError::InvalidFrame("invalid chunk bounds"));
+    /// Append the frame to `out` without an intermediate allocation.
+    pub fn encode_to(&self, out: &mut impl BufMut) -> Result<(), TransferError> {
+        if out.remaining_mut() < self.encoded_len() {
+            return Err(TransferError::InvalidFrame("buffer too small for frame"));
         }
+        self.write(out);
+        Ok(())
+    }
 
-        let mut nonce = [0u8; 12];
-        nonce.copy_from_slice(&bytes[22..34]);
+    pub fn decode(bytes: &[u8]) -> Result<Self, TransferError> {
+        TransferChunkV2Ref::decode(bytes).map(TransferChunkV2Ref::into_owned)
+    }
+
+    /// Plaintext digest carried in the AAD, if the sender included one.
+    pub fn chunk_digest(&self) -> Option<[u8; CHUNK_DIGEST_LEN]> {
+        self.borrowed().chunk_digest()
+    }
 
-        let aad_len = u16::from_be_bytes(bytes[34..36].try_into().expect("slice len")) as usize;
-        let payload_len = u32::from_be_bytes(bytes[36..40].try_into().expect("slice len")) as usize;
+    /// File within a batch, from the AAD; frames without one belong to file 0.
+    pub fn file_index(&self) -> u32 {
+        self.borrowed().file_index()
+    }
+
+    /// Codec the payload was compressed with before sealing, from the AAD.
+    pub fn compression(&self) -> Result<CompressionCodec, TransferError> {
+        self.borrowed().compression()
+    }
 
-        let expected_len = min_header + aad_len + payload_len;
-        if bytes.len() != expected_len {
-            return Err(TransferError::InvalidFrame("invalid payload length"));
+    fn borrowed(&self) -> TransferChunkV2Ref<'_> {
+        TransferChunkV2Ref {
+            protocol_version: self.protocol_version,
+            encryption_flag: self.encryption_flag,
+            cipher_suite: self.cipher_suite,
+            transfer_id: self.transfer_id,
+            chunk_index: self.chunk_index,
+            total_chunks: self.total_chunks,
+            nonce: self.nonce,
+            aad: &self.aad,
+            payload: &self.payload,
         }
+    }
+
+    fn write(&self, out: &mut impl BufMut) {
+        let aad_len = u16::try_from(self.aad.len()).unwrap_or(u16::MAX);
+        let payload_len = u32::try_from(self.payload.len()).unwrap_or(u32::MAX);
+        out.put_slice(MAGIC_V2);
+        out.put_u8(self.protocol_version);
+        out.put_u8(self.encryption_flag.as_u8(self.cipher_suite));
+        out.put_u64(self.transfer_id);
+        out.put_u32(self.chunk_index);
+        out.put_u32(self.total_chunks);
+        out.put_slice(&self.nonce);
+        out.put_u16(aad_len);
+        out.put_u32(payload_len);
+        out.put_slice(&self.aad[..aad_len as usize]);
+        out.put_slice(&self.payload[..payload_len as usize]);
+    }
+}
+
+/// Unencrypted v2 frame, optionally with a payload digest so corruption is caught
+/// at the chunk level even without a session key.
+pub fn plaintext_chunk_frame(chunk: &TransferChunk, with_digest: bool) -> TransferChunkV2 {
+    let aad = if with_digest {
+        transfer_chunk_aad_with_digest(chunk)
+    } else {
+        transfer_chunk_aad(chunk)
+    };
+    TransferChunkV2 {
+        protocol_version: PROTOCOL_VERSION_V2,
+        encryption_flag: EncryptionFlag::Plaintext,
+        cipher_suite: CipherSuite::default(),
+        transfer_id: chunk.transfer_id,
+        chunk_index: chunk.chunk_index,
+        total_chunks: chunk.total_chunks,
+        nonce: [0u8; 12],
+        aad,
+        payload: chunk.payload.clone(),
+    }
+}
+
+/// Payloads are sealed under [`derive_transfer_key`] of the session key and the chunk's
+/// transfer id, never under the session key itself.
+pub fn encrypt_chunk_frame(
+    chunk: &TransferChunk,
+    session_tx_key: &[u8; 32],
+) -> Result<TransferChunkV2, TransferError> {
+    encrypt_chunk_frame_directional(chunk, session_tx_key, Direction::SenderToReceiver)
+}
+
+/// Encrypt with the nonce space of `direction`, so both peers of a full-duplex session
+/// can send under the same transfer id without sharing nonces.
+pub fn encrypt_chunk_frame_directional(
+    chunk: &TransferChunk,
+    session_tx_key: &[u8; 32],
+    direction: Direction,
+) -> Result<TransferChunkV2, TransferError> {
+    PreparedChunk::new(
+        Cow::Borrowed(chunk),
+        &mut NonceSequencer::new(),
+        direction,
+        false,
+        CompressionCodec::None,
+        CipherSuite::default(),
+        PaddingScheme::None,
+    )?
+    .seal(session_tx_key)
+}
+
+/// Encrypt with the plaintext digest in the AAD, which the cipher tag then covers.
+///
+/// Stripping the digest from such a frame breaks the tag, so a receiver cannot be
+/// downgraded to an unchecked frame.
+pub fn encrypt_chunk_frame_with_digest(
+    chunk: &TransferChunk,
+    session_tx_key: &[u8; 32],
+    direction: Direction,
+) -> Result<TransferChunkV2, TransferError> {
+    PreparedChunk::new(
+        Cow::Borrowed(chunk),
+        &mut NonceSequencer::new(),
+        direction,
+        true,
+        CompressionCodec::None,
+        CipherSuite::default(),
+        PaddingScheme::None,
+    )?
+    .seal(session_tx_key)
+}
+
+/// [`encrypt_chunk_frame_with_digest`] that takes the chunk and encrypts its payload in
+/// place, so a large chunk read from a source is never held twice.
+pub fn encrypt_chunk_frame_in_place(
+    chunk: TransferChunk,
+    session_tx_key: &[u8; 32],
+    direction: Direction,
+) -> Result<TransferChunkV2, TransferError> {
+    encrypt_chunk_frame_with_suite(chunk, session_tx_key, direction, CipherSuite::default())
+}
+
+/// [`encrypt_chunk_frame_in_place`] under the cipher suite negotiated in the handshake;
+/// the frame names the suite so the receiver decrypts with the same one.
+pub fn encrypt_chunk_frame_with_suite(
+    chunk: TransferChunk,
+    session_tx_key: &[u8; 32],
+    direction: Direction,
+    suite: CipherSuite,
+) -> Result<TransferChunkV2, TransferError> {
+    PreparedChunk::new(
+        Cow::Owned(chunk),
+        &mut NonceSequencer::new(),
+        direction,
+        true,
+        CompressionCodec::None,
+        suite,
+        PaddingScheme::None,
+    )?
+    .seal(session_tx_key)
+}
 
-        let aad_start = min_header;
-        let payload_start = aad_start + aad_len;
+/// [`encrypt_chunk_frame_with_suite`] that first pads the payload under the negotiated
+/// `padding`, so the frame length hides the chunk's exact size. The AAD marks the
+/// frame as padded, and the receiver strips the padding after decrypting.
+///
+/// A padded frame leaves the plaintext digest out of the AAD: the AAD travels in the
+/// clear, and the hash of a known chunk would give it away whatever its padded length.
+/// The tag still authenticates the plaintext.
+pub fn encrypt_chunk_frame_padded(
+    chunk: TransferChunk,
+    session_tx_key: &[u8; 32],
+    direction: Direction,
+    suite: CipherSuite,
+    padding: PaddingScheme,
+) -> Result<TransferChunkV2, TransferError> {
+    PreparedChunk::new(
+        Cow::Owned(chunk),
+        &mut NonceSequencer::new(),
+        direction,
+        true,
+        CompressionCodec::None,
+        suite,
+        padding,
+    )?
+    .seal(session_tx_key)
+}
 
+/// Compress the payload with the negotiated `codec`, then encrypt it.
+///
+/// Chunks that do not shrink (media, archives) go out uncompressed. The codec used is
+/// recorded in the AAD, so the tag covers it and it cannot be flipped in transit, and
+/// compressed frames always carry the plaintext digest, so a corrupted stream cannot
+/// produce wrong bytes unnoticed.
+pub fn encrypt_chunk_frame_compressed(
+    chunk: &TransferChunk,
+    session_tx_key: &[u8; 32],
+    direction: Direction,
+    codec: CompressionCodec,
+) -> Result<TransferChunkV2, TransferError> {
+    PreparedChunk::new(
+        Cow::Borrowed(chunk),
+        &mut NonceSequencer::new(),
+        direction,
+        true,
+        codec,
+        CipherSuite::default(),
+        PaddingScheme::None,
+    )?
+    .seal(session_tx_key)
+}
+
+/// A chunk compressed, padded and given its nonce and AAD, waiting to be encrypted.
+pub(crate) struct PreparedChunk {
+    transfer_id: u64,
+    chunk_index: u32,
+    total_chunks: u32,
+    suite: CipherSuite,
+    nonce: [u8; 12],
+    aad: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl PreparedChunk {
+    /// The nonce is the one `nonces` gives the chunk's index in its current epoch.
+    pub(crate) fn new(
+        chunk: Cow<'_, TransferChunk>,
+        nonces: &mut NonceSequencer,
+        direction: Direction,
+        with_digest: bool,
+        codec: CompressionCodec,
+        suite: CipherSuite,
+        padding: PaddingScheme,
+    ) -> Result<Self, TransferError> {
+        let nonce = nonces
+            .nonce_for(chunk.transfer_id, direction, chunk.chunk_index)
+            .map_err(|_| TransferError::Crypto("chunk index exceeds the nonce space"))?;
+        let compressed = match codec {
+            CompressionCodec::None => None,
+            codec => Some(codec.compress(&chunk.payload)?)
+                .filter(|compressed| compressed.len() < chunk.payload.len()),
+        };
+        let compression = match compressed {
+            Some(_) => codec,
+            None => CompressionCodec::None,
+        };
+        let with_digest = with_digest && padding == PaddingScheme::None;
+        let aad = chunk_aad(&chunk, suite, with_digest).with_compression(compression.as_u8());
+        let aad = match padding {
+            PaddingScheme::None => aad.build(),
+            _ => aad.with_padded_payload().build(),
+        };
+        let (transfer_id, chunk_index, total_chunks) =
+            (chunk.transfer_id, chunk.chunk_index, chunk.total_chunks);
+        let mut payload = match compressed {
+            Some(compressed) => compressed,
+            None => chunk.into_owned().payload,
+        };
+        padding.pad(&mut payload);
         Ok(Self {
-            protocol_version,
-            encryption_flag,
             transfer_id,
             chunk_index,
             total_chunks,
+            suite,
             nonce,
-            aad: bytes[aad_start..payload_start].to_vec(),
-            payload: bytes[payload_start..].to_vec(),
+            aad,
+            payload,
         })
     }
-}
 
-pub fn encrypt_chunk_frame(
-    chunk: &TransferChunk,
-    session_tx_key: &[u8; 32],
-) -> Result<TransferChunkV2, TransferError> {
-    let nonce = derive_nonce(
-        chunk.transfer_id,
-        chunk.chunk_index,
-        Direction::SenderToReceiver,
-    );
-    let aad = transfer_chunk_aad(chunk);
-    let ciphertext = encrypt_chunk(session_tx_key, nonce, &chunk.payload)
-        .map_err(|_| TransferError::Crypto("failed to encrypt chunk payload"))?;
+    /// Encrypt the payload in place under the transfer key of `session_tx_key`.
+    pub(crate) fn seal(
+        mut self,
+        session_tx_key: &[u8; 32],
+    ) -> Result<TransferChunkV2, TransferError> {
+        let key = derive_transfer_key(session_tx_key, self.transfer_id);
+        let mut stream = EncryptStream::new(self.suite, key.expose(), self.nonce, &self.aad);
+        stream
+            .update(&mut self.payload)
+            .map_err(|_| TransferError::Crypto("failed to encrypt chunk payload"))?;
+        self.payload.extend_from_slice(&stream.finalize());
+        Ok(self.into_frame())
+    }
 
-    Ok(TransferChunkV2 {
-        protocol_version: 2,
-        encryption_flag: EncryptionFlag::Encrypted,
-        transfer_id: chunk.transfer_id,
-        chunk_index: chunk.chunk_index,
-        total_chunks: chunk.total_chunks,
-        nonce,
-        aad,
-        payload: ciphertext,
-    })
+    /// The frame, once `payload` holds the ciphertext and tag.
+    fn into_frame(self) -> TransferChunkV2 {
+        TransferChunkV2 {
+            protocol_version: PROTOCOL_VERSION_V2,
+            encryption_flag: EncryptionFlag::Encrypted,
+            cipher_suite: self.suite,
+            transfer_id: self.transfer_id,
+            chunk_index: self.chunk_index,
+            total_chunks: self.total_chunks,
+            nonce: self.nonce,
+            aad: self.aad,
+            payload: self.payload,
+        }
+    }
 }
 
+/// Decrypt a sender-to-receiver frame under the default cipher suite.
 pub fn decrypt_chunk_frame(
     frame: &TransferChunkV2,
     session_rx_key: &[u8; 32],
+) -> Result<TransferChunk, TransferError> {
+    decrypt_chunk_frame_directional(
+        frame,
+        session_rx_key,
+        Direction::SenderToReceiver,
+        CipherSuite::default(),
+    )
+}
+
+/// Decrypt a frame sent in `direction` under the negotiated `suite`; frames from the
+/// other direction or naming another suite are rejected.
+pub fn decrypt_chunk_frame_directional(
+    frame: &TransferChunkV2,
+    session_rx_key: &[u8; 32],
+    direction: Direction,
+    suite: CipherSuite,
+) -> Result<TransferChunk, TransferError> {
+    decrypt_chunk_frame_ref(&frame.borrowed(), session_rx_key, direction, suite)
+}
+
+/// [`decrypt_chunk_frame_directional`] for a frame decoded in place, so the ciphertext
+/// is never copied out of the receive buffer.
+pub fn decrypt_chunk_frame_ref(
+    frame: &TransferChunkV2Ref<'_>,
+    session_rx_key: &[u8; 32],
+    direction: Direction,
+    suite: CipherSuite,
 ) -> Result<TransferChunk, TransferError> {
     if frame.encryption_flag != EncryptionFlag::Encrypted {
         return Err(TransferError::InvalidFrame("expected encrypted frame"));
     }
+    // The frame names its suite in the clear; only the one the handshake settled on is
+    // used, so a sender cannot steer the receiver to a weaker one.
+    if frame.cipher_suite != suite {
+        return Err(TransferError::Crypto("cipher suite was not negotiated"));
+    }
+    // The nonce is derived from the header, so a tampered header cannot reuse it.
+    check_chunk_nonce(
+        &frame.nonce,
+        frame.transfer_id,
+        frame.chunk_index,
+        direction,
+    )?;
 
-    let plaintext = decrypt_chunk(session_rx_key, frame.nonce, &frame.payload)
-        .map_err(|_| TransferError::Crypto("failed to decrypt chunk payload"))?;
+    // Old-layout frames without a digest were sealed before the AAD was bound in, and
+    // every old-layout frame before keys were scoped to a transfer.
+    let structured = frame.structured_aad();
+    let cipher_aad = if structured.is_some() || frame.chunk_digest().is_some() {
+        frame.aad
+    } else {
+        &[]
+    };
+    let transfer_key = derive_transfer_key(session_rx_key, frame.transfer_id);
+    let key = if structured.is_some() {
+        transfer_key.expose()
+    } else {
+        session_rx_key
+    };
+    let mut plaintext = decrypt_with_suite(
+        frame.cipher_suite,
+        key,
+        frame.nonce,
+        frame.payload,
+        cipher_aad,
+    )
+    .map_err(|_| TransferError::Crypto("failed to decrypt chunk payload"))?;
+    if structured.is_some_and(|aad| aad.padded) {
+        unpad(&mut plaintext)
+            .map_err(|_| TransferError::InvalidFrame("malformed payload padding"))?;
+    }
+    // The codec comes from the AAD the tag just verified, not from the cleartext header.
+    let plaintext = match frame.compression()? {
+        CompressionCodec::None => plaintext,
+        codec => codec.decompress(&plaintext, MAX_DECOMPRESSED_CHUNK_LEN)?,
+    };
+    frame.verify_digest(&plaintext)?;
 
     Ok(TransferChunk {
         transfer_id: frame.transfer_id,
+        file_index: frame.file_index(),
         chunk_index: frame.chunk_index,
         total_chunks: frame.total_chunks,
         payload: plaintext,
     })
 }
 
+/// Nonce of a chunk frame; a chunk index past the three bytes the nonce holds would
+/// repeat an earlier chunk's nonce, so it is refused.
+pub(crate) fn chunk_nonce(
+    transfer_id: u64,
+    chunk_index: u32,
+    direction: Direction,
+) -> Result<[u8; 12], TransferError> {
+    try_derive_nonce(transfer_id, chunk_index, direction)
+        .map_err(|_| TransferError::Crypto("chunk index exceeds the nonce space"))
+}
+
+/// Check that `nonce` is the nonce of this chunk in the rekey epoch its last byte
+/// names; the epoch is the only part not derived from the frame header.
+pub(crate) fn check_chunk_nonce(
+    nonce: &[u8; 12],
+    transfer_id: u64,
+    chunk_index: u32,
+    direction: Direction,
+) -> Result<(), TransferError> {
+    let epoch = u32::from(nonce[11] >> 2);
+    let expected = nonce_with_epoch(transfer_id, epoch, chunk_index, direction)
+        .map_err(|_| TransferError::Crypto("chunk index exceeds the nonce space"))?;
+    if *nonce != expected {
+        return Err(TransferError::InvalidFrame(
+            "nonce does not match frame header",
+        ));
+    }
+    Ok(())
+}
+
+/// The chunk's v2 header as an [`AadBuilder`] AAD, for the default cipher suite.
 pub fn transfer_chunk_aad(chunk: &TransferChunk) -> Vec<u8> {
-    let mut aad = Vec::with_capacity(8 + 4 + 4);
-    aad.extend_from_slice(&chunk.transfer_id.to_be_bytes());
-    aad.extend_from_slice(&chunk.chunk_index.to_be_bytes());
-    aad.extend_from_slice(&chunk.total_chunks.to_be_bytes());
-    aad
+    chunk_aad(chunk, CipherSuite::default(), false).build()
+}
+
+/// [`transfer_chunk_aad`] with the SHA-256 of the plaintext payload.
+pub fn transfer_chunk_aad_with_digest(chunk: &TransferChunk) -> Vec<u8> {
+    chunk_aad(chunk, CipherSuite::default(), true).build()
+}
+
+fn chunk_aad(chunk: &TransferChunk, suite: CipherSuite, with_digest: bool) -> AadBuilder {
+    let aad = AadBuilder::new(
+        PROTOCOL_VERSION_V2,
+        chunk.transfer_id,
+        chunk.chunk_index,
+        chunk.total_chunks,
+    )
+    .with_cipher_suite(suite)
+    .with_file_index(chunk.file_index);
+    if with_digest {
+        aad.with_chunk_digest(Sha256::digest(&chunk.payload).into())
+    } else {
+        aad
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum VersionedTransferChunk {
     V1(TransferChunk),
     V2(TransferChunkV2),
+    V3(TransferChunkV3),
 }
 
 impl VersionedTransferChunk {
@@ -222,24 +682,52 @@ impl VersionedTransferChunk {
             Ok(VersionedTransferChunk::V1(TransferChunk::decode(bytes)?))
         } else if &bytes[..4] == MAGIC_V2 {
             Ok(VersionedTransferChunk::V2(TransferChunkV2::decode(bytes)?))
+        } else if &bytes[..4] == frame_v3::MAGIC_V3 {
+            Ok(VersionedTransferChunk::V3(TransferChunkV3::decode(bytes)?))
         } else {
             Err(TransferError::InvalidFrame("bad header"))
         }
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Ack {
     pub transfer_id: u64,
     pub receiver_id: String,
+    /// Session token of the receiver process that produced this ack.
+    pub receiver_epoch: u64,
     pub next_expected_chunk: u32,
+    /// Chunks already held past the cumulative point, LSB first: bit `i` set means chunk
+    /// `next_expected_chunk + i` has arrived, the same layout a [`SelectiveAck`] uses on
+    /// the wire. Bit 0 is always clear. Empty for a purely cumulative ack.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sack_bitmap: Vec<u8>,
+}
+
+impl Ack {
+    /// Chunks the bitmap reports as received, ascending.
+    pub fn sacked_chunks(&self) -> impl Iterator<Item = u32> + '_ {
+        selective_ack::received_in_bitmap(self.next_expected_chunk, &self.sack_bitmap)
+    }
+}
+
+/// Generate a fresh receiver session token.
+///
+/// A receiver picks one per process instance and stamps it on every ack, so the sender can
+/// tell acks of a restarted receiver apart from stale in-flight ones without trusting clocks.
+pub fn new_receiver_epoch() -> u64 {
+    rand::random()
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ReceiverProgress {
     pub receiver_id: String,
     pub acked_up_to_exclusive: u32,
     pub total_chunks: u32,
+    /// One entry per file; a single-file transfer has exactly one.
+    pub files: Vec<FileProgress>,
 }
 
 impl ReceiverProgress {
@@ -254,6 +742,49 @@ impl ReceiverProgress {
     pub fn is_complete(&self) -> bool {
         self.acked_up_to_exclusive >= self.total_chunks
     }
+
+    fn advance(&mut self, next_expected: u32, layout: &[BatchFile]) {
+        if next_expected <= self.acked_up_to_exclusive {
+            return;
+        }
+        self.acked_up_to_exclusive = next_expected;
+        for (file, entry) in layout.iter().zip(&mut self.files) {
+            entry.acked_chunks = next_expected
+                .saturating_sub(file.first_chunk)
+                .min(file.chunk_count);
+        }
+    }
+}
+
+/// Where a session's chunk payloads come from.
+///
+/// Clones of a streaming session share one source, so chunks are still read lazily
+/// and the file is opened once.
+#[derive(Clone)]
+enum SessionData {
+    Memory(Vec<u8>),
+    Stream {
+        source: Arc<Mutex<Box<dyn TransferSource + Send>>>,
+        len: u64,
+    },
+}
+
+impl fmt::Debug for SessionData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionData::Memory(data) => f.debug_tuple("Memory").field(&data.len()).finish(),
+            SessionData::Stream { len, .. } => f.debug_struct("Stream").field("len", len).finish(),
+        }
+    }
+}
+
+impl SessionData {
+    fn len(&self) -> u64 {
+        match self {
+            SessionData::Memory(data) => data.len() as u64,
+            SessionData::Stream { len, .. } => *len,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -261,8 +792,32 @@ pub struct TransferSession {
     transfer_id: u64,
     total_chunks: u32,
     chunk_size: usize,
-    data: Vec<u8>,
+    data: SessionData,
     receivers: HashMap<String, ReceiverProgress>,
+    receiver_epochs: HashMap<String, u64>,
+    /// Per-receiver holes from the latest selective ack.
+    holes: HashMap<String, BTreeSet<u32>>,
+    params: Option<SessionParams>,
+    tags: Vec<String>,
+    /// Chunks not yet supplied, for sessions whose data arrives through [`Self::put_chunk`].
+    missing: BTreeSet<u32>,
+    /// Chunk and byte ranges of each file; a single entry unless built as a batch.
+    layout: Vec<BatchFile>,
+    /// Set once either side cancels or aborts; no chunks are served or acks taken after.
+    terminated: Option<Termination>,
+    keys: ReceiverKeys,
+    /// The file behind sessions from [`Self::open_file`], recorded for snapshots.
+    source_file: Option<SnapshotSource>,
+    observers: Observers,
+    /// When each receiver was last heard from: its latest ack or heartbeat, or the
+    /// session's creation before either.
+    last_activity: HashMap<String, Instant>,
+    /// Send sizing for sessions from [`Self::new_adaptive`].
+    chunker: Option<AdaptiveChunker>,
+    /// Nonce domain for encrypted chunks; receiver-to-sender for replies.
+    direction: Direction,
+    /// Nonces of the encrypted chunks sent so far, and the rekey epoch they are in.
+    nonces: NonceSequencer,
 }
 
 impl TransferSession {
@@ -275,60 +830,457 @@ impl TransferSession {
         if chunk_size == 0 {
             return Err(TransferError::InvalidConfig("chunk_size must be > 0"));
         }
+        let layout = batch::layout(&[data.len()], chunk_size)?;
+        Ok(Self::with_layout(
+            transfer_id,
+            SessionData::Memory(data),
+            chunk_size,
+            layout,
+            receiver_ids,
+        ))
+    }
 
-        let total_chunks = if data.is_empty() {
-            1
-        } else {
-            data.len().div_ceil(chunk_size) as u32
-        };
+    /// Session whose send size follows [`AdaptiveChunker`] instead of staying fixed.
+    ///
+    /// Chunks on the wire are `config.min_chunk_size`, so indices, acks and resume work
+    /// as usual; the sending loop sends [`Self::chunks_per_send`] of them back to back
+    /// and reports round trips and losses through [`Self::record_ack_rtt`] and
+    /// [`Self::record_loss`].
+    pub fn new_adaptive(
+        transfer_id: u64,
+        data: Vec<u8>,
+        config: AdaptiveChunkConfig,
+        receiver_ids: impl IntoIterator<Item = String>,
+    ) -> Result<Self, TransferError> {
+        let chunker = AdaptiveChunker::new(config)?;
+        let mut session = Self::new(transfer_id, data, config.min_chunk_size, receiver_ids)?;
+        session.chunker = Some(chunker);
+        Ok(session)
+    }
 
+    /// Several files under one transfer id, e.g. a folder of photos sent together.
+    ///
+    /// Chunk indices run across the whole batch, so acks, windows and resume work as for
+    /// one file; each chunk names its file in [`TransferChunk::file_index`].
+    pub fn new_batch(
+        transfer_id: u64,
+        files: impl IntoIterator<Item = Vec<u8>>,
+        chunk_size: usize,
+        receiver_ids: impl IntoIterator<Item = String>,
+    ) -> Result<Self, TransferError> {
+        if chunk_size == 0 {
+            return Err(TransferError::InvalidConfig("chunk_size must be > 0"));
+        }
+        let files: Vec<Vec<u8>> = files.into_iter().collect();
+        if files.is_empty() {
+            return Err(TransferError::InvalidConfig("batch has no files"));
+        }
+        let lens: Vec<usize> = files.iter().map(Vec::len).collect();
+        let layout = batch::layout(&lens, chunk_size)?;
+        Ok(Self::with_layout(
+            transfer_id,
+            SessionData::Memory(files.concat()),
+            chunk_size,
+            layout,
+            receiver_ids,
+        ))
+    }
+
+    fn with_layout(
+        transfer_id: u64,
+        data: SessionData,
+        chunk_size: usize,
+        layout: Vec<BatchFile>,
+        receiver_ids: impl IntoIterator<Item = String>,
+    ) -> Self {
+        let total_chunks = layout
+            .last()
+            .map_or(1, |file| file.first_chunk + file.chunk_count);
+        let files = FileProgress::for_layout(&layout);
+
+        let created_at = Instant::now();
         let mut receivers = HashMap::new();
+        let mut last_activity = HashMap::new();
         for id in receiver_ids {
+            last_activity.insert(id.clone(), created_at);
             receivers.insert(
                 id.clone(),
                 ReceiverProgress {
                     receiver_id: id,
                     acked_up_to_exclusive: 0,
                     total_chunks,
+                    files: files.clone(),
                 },
             );
         }
 
-        Ok(Self {
+        Self {
             transfer_id,
             total_chunks,
             chunk_size,
             data,
             receivers,
-        })
+            receiver_epochs: HashMap::new(),
+            holes: HashMap::new(),
+            params: None,
+            tags: Vec::new(),
+            missing: BTreeSet::new(),
+            layout,
+            terminated: None,
+            keys: ReceiverKeys::default(),
+            source_file: None,
+            observers: Observers::default(),
+            last_activity,
+            chunker: None,
+            direction: Direction::SenderToReceiver,
+            nonces: NonceSequencer::new(),
+        }
     }
 
-    pub fn chunk_for(&self, chunk_index: u32) -> Result<TransferChunk, TransferError> {
+    /// Session that reads chunks from `source` on demand instead of holding the file.
+    ///
+    /// The chunk size is the source's. Use this for anything large; [`Self::new`] keeps
+    /// the whole payload in memory.
+    pub fn from_source(
+        transfer_id: u64,
+        source: impl TransferSource + Send + 'static,
+        receiver_ids: impl IntoIterator<Item = String>,
+    ) -> Result<Self, TransferError> {
+        let (len, chunk_size) = (source.len(), source.chunk_size());
+        if chunk_size == 0 {
+            return Err(TransferError::InvalidConfig("chunk_size must be > 0"));
+        }
+        let layout = vec![BatchFile {
+            first_chunk: 0,
+            chunk_count: source.total_chunks().max(1),
+            byte_offset: 0,
+            len: usize::try_from(len).unwrap_or(usize::MAX),
+        }];
+        let data = SessionData::Stream {
+            source: Arc::new(Mutex::new(Box::new(source))),
+            len,
+        };
+        Ok(Self::with_layout(
+            transfer_id,
+            data,
+            chunk_size,
+            layout,
+            receiver_ids,
+        ))
+    }
+
+    /// Stream `path` through a read-ahead [`FileSource`] with change detection.
+    pub fn open_file(
+        transfer_id: u64,
+        path: impl AsRef<Path>,
+        chunk_size: usize,
+        receiver_ids: impl IntoIterator<Item = String>,
+    ) -> Result<Self, TransferError> {
+        let source = FileSource::open(&path, chunk_size, ReadAheadConfig::default())?;
+        let source_file = source.snapshot().map(|state| SnapshotSource {
+            path: path.as_ref().to_path_buf(),
+            state,
+        });
+        let mut session = Self::from_source(transfer_id, source, receiver_ids)?;
+        session.source_file = source_file;
+        Ok(session)
+    }
+
+    /// Session for a file whose bytes are uploaded piecewise rather than known up front.
+    ///
+    /// Every chunk starts missing; [`Self::chunk_for`] refuses a chunk until it has been
+    /// supplied, so nothing is sent from the zero-filled placeholder. The whole file is
+    /// held in memory, so `total_bytes` is capped at [`MAX_UPLOAD_BYTES`].
+    pub fn for_upload(
+        transfer_id: u64,
+        total_bytes: u64,
+        chunk_size: usize,
+        receiver_ids: impl IntoIterator<Item = String>,
+    ) -> Result<Self, TransferError> {
+        if total_bytes > MAX_UPLOAD_BYTES {
+            return Err(TransferError::InvalidConfig(
+                "upload too large to buffer in memory",
+            ));
+        }
+        let len = usize::try_from(total_bytes)
+            .map_err(|_| TransferError::InvalidConfig("upload too large for this platform"))?;
+        let mut session = Self::new(transfer_id, vec![0; len], chunk_size, receiver_ids)?;
+        session.missing = (0..session.total_chunks).collect();
+        Ok(session)
+    }
+
+    /// Store one uploaded chunk.
+    ///
+    /// The payload must be exactly the chunk's length: `chunk_size` except for the last.
+    /// Re-sending a stored chunk is accepted when the bytes match, so retries are safe;
+    /// different bytes are [`TransferError::DuplicateChunk`] and leave it unchanged.
+    pub fn put_chunk(&mut self, chunk_index: u32, payload: &[u8]) -> Result<(), TransferError> {
+        self.ensure_live()?;
         if chunk_index >= self.total_chunks {
             return Err(TransferError::ChunkOutOfRange);
         }
+        let SessionData::Memory(data) = &mut self.data else {
+            return Err(TransferError::InvalidConfig(
+                "cannot upload into a streaming session",
+            ));
+        };
+        let (start, end) = chunk_bytes(&self.layout, chunk_index, self.chunk_size);
+        if payload.len() != end - start {
+            return Err(TransferError::InvalidFrame(
+                "chunk length does not match session",
+            ));
+        }
+        if !self.missing.contains(&chunk_index) {
+            return if data[start..end] == *payload {
+                Ok(())
+            } else {
+                Err(TransferError::DuplicateChunk)
+            };
+        }
+        data[start..end].copy_from_slice(payload);
+        self.missing.remove(&chunk_index);
+        Ok(())
+    }
 
-        let start = chunk_index as usize * self.chunk_size;
-        let end = ((chunk_index as usize + 1) * self.chunk_size).min(self.data.len());
+    /// Chunks still awaiting upload, ascending; empty once the data is complete.
+    pub fn missing_chunks(&self) -> Vec<u32> {
+        self.missing.iter().copied().collect()
+    }
 
-        let payload = if self.data.is_empty() {
-            Vec::new()
-        } else {
-            self.data[start..end].to_vec()
+    pub fn chunk_for(&self, chunk_index: u32) -> Result<TransferChunk, TransferError> {
+        self.ensure_live()?;
+        if chunk_index >= self.total_chunks {
+            return Err(TransferError::ChunkOutOfRange);
+        }
+        if self.missing.contains(&chunk_index) {
+            return Err(TransferError::ChunkNotUploaded);
+        }
+
+        let payload = match &self.data {
+            SessionData::Memory(data) => {
+                let (start, end) = chunk_bytes(&self.layout, chunk_index, self.chunk_size);
+                data[start..end].to_vec()
+            }
+            SessionData::Stream { len: 0, .. } => Vec::new(),
+            SessionData::Stream { source, .. } => source
+                .lock()
+                .map_err(|_| TransferError::Io("chunk source lock poisoned".to_string()))?
+                .read_chunk(chunk_index)?,
         };
 
         Ok(TransferChunk {
             transfer_id: self.transfer_id,
+            file_index: batch::locate(&self.layout, chunk_index) as u32,
             chunk_index,
             total_chunks: self.total_chunks,
             payload,
         })
     }
 
+    /// Bind the session key negotiated with one receiver, for encrypted fan-out.
+    ///
+    /// Each receiver needs its own key: reusing one across receivers is refused, since
+    /// every destination sees the same nonce for a chunk.
+    pub fn set_receiver_key(
+        &mut self,
+        receiver_id: &str,
+        key: [u8; 32],
+    ) -> Result<(), TransferError> {
+        self.set_receiver_key_with_suite(
+            receiver_id,
+            key,
+            CipherSuite::default(),
+            PaddingScheme::None,
+        )
+    }
+
+    /// [`Self::set_receiver_key`] for a receiver whose handshake settled on `suite` and
+    /// `padding`; every frame for that receiver is sealed and padded accordingly.
+    pub fn set_receiver_key_with_suite(
+        &mut self,
+        receiver_id: &str,
+        key: [u8; 32],
+        suite: CipherSuite,
+        padding: PaddingScheme,
+    ) -> Result<(), TransferError> {
+        if !self.receivers.contains_key(receiver_id) {
+            return Err(TransferError::UnknownReceiver);
+        }
+        self.keys.insert(receiver_id, key, suite, padding)
+    }
+
+    pub fn remove_receiver_key(&mut self, receiver_id: &str) -> bool {
+        self.keys.remove(receiver_id)
+    }
+
+    /// One chunk encrypted under `receiver_id`'s key, with the plaintext digest bound in
+    /// unless the receiver's frames are padded.
+    pub fn encrypted_chunk_for(
+        &mut self,
+        receiver_id: &str,
+        chunk_index: u32,
+    ) -> Result<TransferChunkV2, TransferError> {
+        if !self.receivers.contains_key(receiver_id) {
+            return Err(TransferError::UnknownReceiver);
+        }
+        let chunk = self.chunk_for(chunk_index)?;
+        let (key, suite, padding) = self.keys.get(receiver_id)?;
+        PreparedChunk::new(
+            Cow::Owned(chunk),
+            &mut self.nonces,
+            self.direction,
+            true,
+            CompressionCodec::None,
+            suite,
+            padding,
+        )?
+        .seal(key)
+    }
+
+    /// A run of chunks encrypted under `receiver_id`'s key, as [`Self::encrypted_chunk_for`]
+    /// would one by one, but with the transfer key derived and the cipher keyed once.
+    pub fn encrypted_chunks_for(
+        &mut self,
+        receiver_id: &str,
+        chunk_indices: Range<u32>,
+    ) -> Result<Vec<TransferChunkV2>, TransferError> {
+        if !self.receivers.contains_key(receiver_id) {
+            return Err(TransferError::UnknownReceiver);
+        }
+        let chunks = chunk_indices
+            .map(|index| self.chunk_for(index))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (key, suite, padding) = self.keys.get(receiver_id)?;
+        let prepared = chunks
+            .into_iter()
+            .map(|chunk| {
+                PreparedChunk::new(
+                    Cow::Owned(chunk),
+                    &mut self.nonces,
+                    self.direction,
+                    true,
+                    CompressionCodec::None,
+                    suite,
+                    padding,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let transfer_key = derive_transfer_key(key, self.transfer_id);
+        let sealed = encrypt_chunks_with_suite(
+            suite,
+            &transfer_key,
+            prepared.iter().map(|chunk| BatchChunk {
+                nonce: chunk.nonce,
+                plaintext: &chunk.payload,
+                aad: &chunk.aad,
+            }),
+        )
+        .map_err(|_| TransferError::Crypto("failed to encrypt chunk payload"))?;
+        Ok(prepared
+            .into_iter()
+            .zip(sealed)
+            .map(|(mut chunk, ciphertext)| {
+                chunk.payload = ciphertext;
+                chunk.into_frame()
+            })
+            .collect())
+    }
+
+    /// One chunk encrypted for every receiver, ordered by receiver id.
+    ///
+    /// The chunk is read once. Fails without producing any frame if a receiver has no
+    /// key yet.
+    pub fn encrypted_fanout(
+        &mut self,
+        chunk_index: u32,
+    ) -> Result<Vec<(String, TransferChunkV2)>, TransferError> {
+        let mut receiver_ids: Vec<&String> = self.receivers.keys().collect();
+        receiver_ids.sort();
+        let keys = receiver_ids
+            .iter()
+            .map(|id| self.keys.get(id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut chunk = Some(self.chunk_for(chunk_index)?);
+        let last = receiver_ids.len().saturating_sub(1);
+        receiver_ids
+            .into_iter()
+            .zip(keys)
+            .enumerate()
+            .map(|(position, (id, (key, suite, padding)))| {
+                // The last receiver's frame takes the chunk itself.
+                let chunk = if position == last {
+                    Cow::Owned(chunk.take().expect("taken once"))
+                } else {
+                    Cow::Borrowed(chunk.as_ref().expect("taken last"))
+                };
+                let frame = PreparedChunk::new(
+                    chunk,
+                    &mut self.nonces,
+                    self.direction,
+                    true,
+                    CompressionCodec::None,
+                    suite,
+                    padding,
+                )?
+                .seal(key)?;
+                Ok((id.clone(), frame))
+            })
+            .collect()
+    }
+
+    /// Receiving side for a reply to this transfer from `receiver_id`, such as a
+    /// thumbnail, built with [`ReceiveSession::reply`] on the other end.
+    ///
+    /// `local_id` is this sender's id as the reply addresses it.
+    pub fn reply_receiver(&self, local_id: impl Into<String>, epoch: u64) -> ReceiveSession {
+        ReceiveSession::new(self.transfer_id, local_id, epoch)
+            .with_direction(Direction::ReceiverToSender)
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    pub(crate) fn with_direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Rekey epoch the nonces of encrypted chunks are drawn from.
+    pub fn nonce_epoch(&self) -> u32 {
+        self.nonces.epoch(self.transfer_id, self.direction)
+    }
+
+    /// Move encrypted chunks to the next nonce epoch, whose counters start over.
+    ///
+    /// Only call this alongside new receiver keys: a chunk resent under the old key in
+    /// the new epoch gets a nonce of its own, but the counters of the old epoch are
+    /// never handed out again.
+    pub fn rekey_nonces(&mut self) -> Result<u32, TransferError> {
+        self.nonces
+            .rekey(self.transfer_id, self.direction)
+            .map_err(|_| TransferError::Crypto("nonce epochs exhausted"))
+    }
+
+    pub fn file_count(&self) -> u32 {
+        self.layout.len() as u32
+    }
+
+    /// Global chunk indices of one file in the batch.
+    pub fn file_chunks(&self, file_index: u32) -> Result<Range<u32>, TransferError> {
+        let file = self
+            .layout
+            .get(file_index as usize)
+            .ok_or(TransferError::InvalidConfig("file index out of range"))?;
+        Ok(file.first_chunk..file.first_chunk + file.chunk_count)
+    }
+
     pub fn apply_ack(&mut self, ack: &Ack) -> Result<(), TransferError> {
         if ack.transfer_id != self.transfer_id {
             return Err(TransferError::WrongTransfer);
         }
+        self.ensure_live()?;
+        let all_were_complete = self.all_complete();
 
         let receiver = self
             .receivers
@@ -338,12 +1290,99 @@ impl TransferSession {
         if ack.next_expected_chunk > self.total_chunks {
             return Err(TransferError::AckOutOfRange);
         }
+        if ack.sack_bitmap.len() > (MAX_SACK_SPAN / 8) as usize {
+            return Err(TransferError::InvalidFrame("sack span too large"));
+        }
+        let sacked: Vec<u32> = ack.sacked_chunks().collect();
+        if sacked.last().is_some_and(|&last| last >= self.total_chunks) {
+            return Err(TransferError::AckOutOfRange);
+        }
+
+        // The first ack pins the receiver epoch unless one was bound explicitly.
+        let epoch = *self
+            .receiver_epochs
+            .entry(ack.receiver_id.clone())
+            .or_insert(ack.receiver_epoch);
+        if epoch != ack.receiver_epoch {
+            return Err(TransferError::StaleAck);
+        }
 
         // Monotonic forward-only checkpointing for resume safety.
-        if ack.next_expected_chunk > receiver.acked_up_to_exclusive {
-            receiver.acked_up_to_exclusive = ack.next_expected_chunk;
+        let was_complete = receiver.is_complete();
+        receiver.advance(ack.next_expected_chunk, &self.layout);
+        let acked = receiver.acked_up_to_exclusive;
+        if let Some(&highest) = sacked.last() {
+            // Like a selective ack, the bitmap replaces the receiver's holes.
+            let holes = (acked..highest)
+                .filter(|index| sacked.binary_search(index).is_err())
+                .collect();
+            self.holes.insert(ack.receiver_id.clone(), holes);
+        } else if let Some(holes) = self.holes.get_mut(&ack.receiver_id) {
+            holes.retain(|&index| index >= acked);
+        }
+
+        self.last_activity
+            .insert(ack.receiver_id.clone(), Instant::now());
+        self.notify_ack(&ack.receiver_id, was_complete, all_were_complete);
+        Ok(())
+    }
+
+    /// Advance the checkpoint from a selective ack and remember the holes it reports.
+    ///
+    /// Each report replaces the receiver's previous holes, since it describes everything
+    /// that receiver holds.
+    pub fn apply_selective_ack(&mut self, sack: &SelectiveAck) -> Result<(), TransferError> {
+        if sack.received_up_to_exclusive > self.total_chunks {
+            return Err(TransferError::AckOutOfRange);
+        }
+        if sack.missing.iter().any(|&index| {
+            index < sack.next_expected_chunk || index >= sack.received_up_to_exclusive
+        }) {
+            return Err(TransferError::InvalidFrame(
+                "missing chunk outside sack span",
+            ));
+        }
+        self.apply_ack(&sack.cumulative())?;
+
+        let acked = self.receivers[&sack.receiver_id].acked_up_to_exclusive;
+        let holes = sack
+            .missing
+            .iter()
+            .copied()
+            .filter(|&index| index >= acked)
+            .collect();
+        self.holes.insert(sack.receiver_id.clone(), holes);
+        Ok(())
+    }
+
+    /// Chunks the receiver reported missing behind its furthest received chunk, ascending,
+    /// from a selective ack or an ack's bitmap.
+    ///
+    /// Resending these fills the holes without rewinding to the contiguous checkpoint.
+    pub fn chunks_to_retransmit(&self, receiver_id: &str) -> Result<Vec<u32>, TransferError> {
+        if !self.receivers.contains_key(receiver_id) {
+            return Err(TransferError::UnknownReceiver);
         }
+        Ok(self
+            .holes
+            .get(receiver_id)
+            .map(|holes| holes.iter().copied().collect())
+            .unwrap_or_default())
+    }
 
+    /// Bind the session token a receiver announced when it (re)joined the transfer.
+    ///
+    /// Acks carrying any other epoch are rejected afterwards, so late acks from a previous
+    /// receiver process can no longer move its progress.
+    pub fn bind_receiver_epoch(
+        &mut self,
+        receiver_id: &str,
+        epoch: u64,
+    ) -> Result<(), TransferError> {
+        if !self.receivers.contains_key(receiver_id) {
+            return Err(TransferError::UnknownReceiver);
+        }
+        self.receiver_epochs.insert(receiver_id.to_string(), epoch);
         Ok(())
     }
 
@@ -362,6 +1401,155 @@ impl TransferSession {
             .ok_or(TransferError::UnknownReceiver)
     }
 
+    /// Stop the transfer and return the frame telling receivers why. If the transfer
+    /// already ended, the frame repeats the original termination.
+    pub fn cancel(&mut self, reason: CancelReason) -> ControlFrame {
+        self.terminate(Termination::Cancelled(reason))
+    }
+
+    /// Give up on the transfer after a failure and return the frame to send.
+    pub fn abort(&mut self, reason: CancelReason) -> ControlFrame {
+        self.terminate(Termination::Aborted(reason))
+    }
+
+    /// Apply a cancel or abort frame from a receiver. The first termination sticks;
+    /// a later one is accepted but does not replace it.
+    pub fn apply_termination(
+        &mut self,
+        frame: &ControlFrame,
+    ) -> Result<Termination, TransferError> {
+        if frame.transfer_id() != self.transfer_id {
+            return Err(TransferError::WrongTransfer);
+        }
+        let termination = frame
+            .termination()
+            .ok_or(TransferError::InvalidFrame("not a cancel or abort frame"))?;
+        Ok(*self.terminated.get_or_insert(termination))
+    }
+
+    /// How the transfer ended, if it was cancelled or aborted.
+    pub fn termination(&self) -> Option<Termination> {
+        self.terminated
+    }
+
+    /// Note a heartbeat from `receiver_id`, keeping it from being reported as stalled.
+    pub fn apply_heartbeat(
+        &mut self,
+        receiver_id: &str,
+        frame: &ControlFrame,
+    ) -> Result<(), TransferError> {
+        let ControlFrame::Heartbeat { transfer_id } = frame else {
+            return Err(TransferError::InvalidFrame("not a heartbeat frame"));
+        };
+        if *transfer_id != self.transfer_id {
+            return Err(TransferError::WrongTransfer);
+        }
+        self.ensure_live()?;
+        let last = self
+            .last_activity
+            .get_mut(receiver_id)
+            .ok_or(TransferError::UnknownReceiver)?;
+        *last = Instant::now();
+        Ok(())
+    }
+
+    pub fn last_activity(&self, receiver_id: &str) -> Result<Instant, TransferError> {
+        self.last_activity
+            .get(receiver_id)
+            .copied()
+            .ok_or(TransferError::UnknownReceiver)
+    }
+
+    /// Whether an unfinished receiver has sent nothing for longer than `timeout`.
+    pub fn is_stalled(
+        &self,
+        receiver_id: &str,
+        timeout: Duration,
+        now: Instant,
+    ) -> Result<bool, TransferError> {
+        let last = self.last_activity(receiver_id)?;
+        Ok(!self.receivers[receiver_id].is_complete()
+            && now.saturating_duration_since(last) > timeout)
+    }
+
+    /// Receivers [`Self::is_stalled`] reports, sorted, for surfacing in the UI.
+    pub fn stalled_receivers(&self, timeout: Duration, now: Instant) -> Vec<String> {
+        let mut stalled: Vec<String> = self
+            .receivers
+            .keys()
+            .filter(|id| self.is_stalled(id, timeout, now) == Ok(true))
+            .cloned()
+            .collect();
+        stalled.sort();
+        stalled
+    }
+
+    /// Subscribe to this session's lifecycle events.
+    pub fn add_observer(&mut self, observer: Arc<dyn TransferObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Bytes the sending loop should put on the wire before the next ack: the
+    /// chunker's current size for adaptive sessions, one chunk otherwise.
+    pub fn effective_chunk_size(&self) -> usize {
+        self.chunker
+            .as_ref()
+            .map_or(self.chunk_size, AdaptiveChunker::chunk_size)
+    }
+
+    /// Wire chunks making up [`Self::effective_chunk_size`]; at least one.
+    pub fn chunks_per_send(&self) -> u32 {
+        (self.effective_chunk_size() / self.chunk_size).max(1) as u32
+    }
+
+    /// Feed an ack's round trip to the adaptive chunker; no-op for fixed sessions.
+    pub fn record_ack_rtt(&mut self, rtt: Duration) {
+        if let Some(chunker) = &mut self.chunker {
+            chunker.on_ack(rtt);
+        }
+    }
+
+    /// Report a lost or timed-out chunk to the adaptive chunker; no-op for fixed sessions.
+    pub fn record_loss(&mut self) {
+        if let Some(chunker) = &mut self.chunker {
+            chunker.on_loss();
+        }
+    }
+
+    /// Report that `chunk_index` went out. The crate's schedulers and duplex sessions
+    /// call this; loops that send from [`Self::chunk_for`] directly call it themselves.
+    pub fn mark_chunk_sent(&self, chunk_index: u32) {
+        self.observers
+            .each(|observer| observer.on_chunk_sent(self.transfer_id, chunk_index));
+    }
+
+    fn notify_ack(&self, receiver_id: &str, was_complete: bool, all_were_complete: bool) {
+        let progress = &self.receivers[receiver_id];
+        self.observers
+            .each(|observer| observer.on_ack(self.transfer_id, progress));
+        if !was_complete && progress.is_complete() {
+            self.observers
+                .each(|observer| observer.on_receiver_complete(self.transfer_id, receiver_id));
+        }
+        if !all_were_complete && self.all_complete() {
+            self.observers
+                .each(|observer| observer.on_all_complete(self.transfer_id));
+        }
+    }
+
+    fn terminate(&mut self, termination: Termination) -> ControlFrame {
+        self.terminated
+            .get_or_insert(termination)
+            .frame(self.transfer_id)
+    }
+
+    fn ensure_live(&self) -> Result<(), TransferError> {
+        match self.terminated {
+            Some(termination) => Err(TransferError::Terminated(termination)),
+            None => Ok(()),
+        }
+    }
+
     pub fn all_complete(&self) -> bool {
         self.receivers.values().all(ReceiverProgress::is_complete)
     }
@@ -369,17 +1557,282 @@ impl TransferSession {
     pub fn total_chunks(&self) -> u32 {
         self.total_chunks
     }
+
+    pub fn transfer_id(&self) -> u64 {
+        self.transfer_id
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.data.len()
+    }
+
+    /// Manifest to send ahead of chunk 0. Hashes every chunk, so a streaming session
+    /// reads its whole file once here.
+    pub fn manifest(
+        &self,
+        file_name: &str,
+        mime_type: &str,
+    ) -> Result<TransferManifest, TransferError> {
+        if self.layout.len() > 1 {
+            return Err(TransferError::InvalidConfig(
+                "a manifest describes one file, not a batch",
+            ));
+        }
+        let mut hasher = Sha256::new();
+        for index in 0..self.total_chunks {
+            hasher.update(&self.chunk_for(index)?.payload);
+        }
+        let manifest = TransferManifest {
+            transfer_id: self.transfer_id,
+            file_name: file_name.to_string(),
+            size_bytes: self.total_bytes(),
+            mime_type: mime_type.to_string(),
+            chunk_size: u32::try_from(self.chunk_size)
+                .map_err(|_| TransferError::InvalidConfig("chunk_size exceeds u32"))?,
+            total_chunks: self.total_chunks,
+            file_sha256: hasher.finalize().into(),
+        };
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    /// Lowest acked chunk across receivers: where a shared send loop has to restart.
+    pub fn min_resume_point(&self) -> u32 {
+        self.receivers
+            .values()
+            .map(|r| r.acked_up_to_exclusive)
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Record what was negotiated for this session once the handshake completes.
+    pub fn set_session_params(&mut self, params: SessionParams) {
+        self.params = Some(params);
+    }
+
+    pub fn session_params(&self) -> Option<&SessionParams> {
+        self.params.as_ref()
+    }
+
+    /// Label the session; tags are normalized with [`normalize_tags`].
+    pub fn set_tags<S: AsRef<str>>(
+        &mut self,
+        tags: impl IntoIterator<Item = S>,
+    ) -> Result<(), TransferError> {
+        self.tags = normalize_tags(tags)?;
+        Ok(())
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        let tag = tag.trim().to_ascii_lowercase();
+        self.tags.contains(&tag)
+    }
+
+    /// `key=value` lines describing progress and, once known, the negotiated parameters
+    /// (prefixed `session.`), for attaching to bug reports.
+    pub fn stats_blob(&self) -> String {
+        let complete = self.receivers.values().filter(|r| r.is_complete()).count();
+        let mut out = format!(
+            "transfer_id={}\ntotal_chunks={}\ntotal_bytes={}\nreceivers_complete={}/{}\n",
+            self.transfer_id,
+            self.total_chunks,
+            self.total_bytes(),
+            complete,
+            self.receivers.len()
+        );
+        if self.layout.len() > 1 {
+            out.push_str(&format!("files={}\n", self.layout.len()));
+        }
+        if !self.tags.is_empty() {
+            out.push_str(&format!("tags={}\n", self.tags.join(",")));
+        }
+        for (key, value) in self.params.iter().flat_map(SessionParams::fields) {
+            out.push_str(&format!("session.{key}={value}\n"));
+        }
+        out
+    }
+
+    /// Capture what is needed to resume every receiver after a restart.
+    pub fn snapshot(&self) -> Result<TransferSnapshot, TransferError> {
+        self.ensure_live()?;
+        let mut receivers: Vec<ReceiverSnapshot> = self
+            .receivers
+            .values()
+            .map(|progress| ReceiverSnapshot {
+                receiver_id: progress.receiver_id.clone(),
+                acked_up_to_exclusive: progress.acked_up_to_exclusive,
+                epoch: self.receiver_epochs.get(&progress.receiver_id).copied(),
+            })
+            .collect();
+        receivers.sort_by(|a, b| a.receiver_id.cmp(&b.receiver_id));
+        Ok(TransferSnapshot {
+            transfer_id: self.transfer_id,
+            chunk_size: self.chunk_size,
+            file_lens: self.layout.iter().map(|file| file.len as u64).collect(),
+            source: self.source_file.clone(),
+            missing: self.missing_chunks(),
+            tags: self.tags.clone(),
+            receivers,
+        })
+    }
+
+    /// Reopen a file-backed session from its snapshot.
+    ///
+    /// Fails with `SourceModified` if the file is no longer the one that was being sent,
+    /// since acked chunks would then describe other bytes.
+    pub fn restore(snapshot: &TransferSnapshot) -> Result<Self, TransferError> {
+        let source = snapshot
+            .source
+            .as_ref()
+            .ok_or(TransferError::InvalidConfig(
+                "snapshot has no source file; restore it with its data",
+            ))?;
+        let session = Self::open_file(
+            snapshot.transfer_id,
+            &source.path,
+            snapshot.chunk_size,
+            snapshot.receivers.iter().map(|r| r.receiver_id.clone()),
+        )?;
+        if session.source_file.as_ref().map(|file| file.state) != Some(source.state) {
+            return Err(TransferError::SourceModified);
+        }
+        session.apply_snapshot(snapshot)
+    }
+
+    /// Rebuild an in-memory session from its snapshot and the same payload, with a
+    /// batch's files concatenated in order.
+    pub fn restore_with_data(
+        snapshot: &TransferSnapshot,
+        data: Vec<u8>,
+    ) -> Result<Self, TransferError> {
+        if snapshot.chunk_size == 0 {
+            return Err(TransferError::InvalidConfig("chunk_size must be > 0"));
+        }
+        // Lengths come from the snapshot, so a sum that overflows is a mismatch too.
+        let total = snapshot
+            .file_lens
+            .iter()
+            .try_fold(0u64, |total, &len| total.checked_add(len));
+        if snapshot.file_lens.is_empty() || total != Some(data.len() as u64) {
+            return Err(TransferError::InvalidConfig(
+                "data does not match the snapshot",
+            ));
+        }
+        // Each length is at most the total, which is `data.len()`.
+        let lens: Vec<usize> = snapshot.file_lens.iter().map(|&len| len as usize).collect();
+        let session = Self::with_layout(
+            snapshot.transfer_id,
+            SessionData::Memory(data),
+            snapshot.chunk_size,
+            batch::layout(&lens, snapshot.chunk_size)?,
+            snapshot.receivers.iter().map(|r| r.receiver_id.clone()),
+        );
+        session.apply_snapshot(snapshot)
+    }
+
+    fn apply_snapshot(mut self, snapshot: &TransferSnapshot) -> Result<Self, TransferError> {
+        if snapshot.file_lens.len() != self.layout.len() {
+            return Err(TransferError::InvalidConfig(
+                "data does not match the snapshot",
+            ));
+        }
+        for receiver in &snapshot.receivers {
+            if receiver.acked_up_to_exclusive > self.total_chunks {
+                return Err(TransferError::AckOutOfRange);
+            }
+            let progress = self
+                .receivers
+                .get_mut(&receiver.receiver_id)
+                .expect("session built from the snapshot's receivers");
+            progress.advance(receiver.acked_up_to_exclusive, &self.layout);
+            if let Some(epoch) = receiver.epoch {
+                self.receiver_epochs
+                    .insert(receiver.receiver_id.clone(), epoch);
+            }
+        }
+        if snapshot
+            .missing
+            .iter()
+            .any(|&index| index >= self.total_chunks)
+        {
+            return Err(TransferError::ChunkOutOfRange);
+        }
+        self.missing = snapshot.missing.iter().copied().collect();
+        self.set_tags(&snapshot.tags)?;
+        Ok(self)
+    }
+
+    /// Move a not-yet-started session to a new id after the receiver rejected the offer.
+    pub fn reassign_transfer_id(&mut self, transfer_id: u64) -> Result<(), TransferError> {
+        if self.receivers.values().any(|r| r.acked_up_to_exclusive > 0) {
+            return Err(TransferError::InvalidConfig(
+                "cannot reassign id of a transfer in progress",
+            ));
+        }
+        self.transfer_id = transfer_id;
+        Ok(())
+    }
+}
+
+/// Byte range of a memory session's chunk within its file.
+fn chunk_bytes(layout: &[BatchFile], chunk_index: u32, chunk_size: usize) -> (usize, usize) {
+    let file = &layout[batch::locate(layout, chunk_index)];
+    let start = file.byte_offset + (chunk_index - file.first_chunk) as usize * chunk_size;
+    (start, (start + chunk_size).min(file.byte_offset + file.len))
 }
 
+// `Deserialize` is implemented in `serde_support`: the derive cannot produce
+// `&'static str` messages from borrowed input.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TransferError {
     InvalidFrame(&'static str),
     InvalidConfig(&'static str),
     ChunkOutOfRange,
+    /// The chunk belongs to an upload session and has not been supplied yet.
+    ChunkNotUploaded,
+    InvalidTag(&'static str),
+    /// A chunk's payload does not hash to the digest its frame carries.
+    ChunkDigestMismatch,
+    /// A frame for a chunk already accepted under the same nonce: a replay or a
+    /// retransmission after a lost ack.
+    DuplicateChunk,
+    /// The received file does not match what its manifest announced.
+    ManifestMismatch(&'static str),
+    ManifestSignatureInvalid,
+    /// A completion receipt failed to verify.
+    ReceiptInvalid(&'static str),
     WrongTransfer,
     UnknownReceiver,
     AckOutOfRange,
+    StaleAck,
+    TransferIdInUse,
+    Backpressure,
+    QueueClosed,
+    Compression(&'static str),
     Crypto(&'static str),
+    /// The file being sent changed after its manifest was built.
+    SourceModified,
+    /// The transfer was cancelled or aborted, by either side.
+    Terminated(Termination),
+    /// No session key has been bound for the receiver.
+    MissingSessionKey,
+    Io(String),
+}
+
+impl TransferError {
+    /// Code to report to the peer in an error frame, for errors the peer must act on.
+    pub fn wire_code(&self) -> Option<TransferErrorCode> {
+        match self {
+            TransferError::TransferIdInUse => Some(TransferErrorCode::TransferIdInUse),
+            TransferError::SourceModified => Some(TransferErrorCode::SourceModified),
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Display for TransferError {
@@ -388,12 +1841,39 @@ impl std::fmt::Display for TransferError {
             TransferError::InvalidFrame(m) => write!(f, "invalid frame: {m}"),
             TransferError::InvalidConfig(m) => write!(f, "invalid config: {m}"),
             TransferError::ChunkOutOfRange => write!(f, "chunk index out of range"),
+            TransferError::ChunkNotUploaded => write!(f, "chunk not uploaded yet"),
+            TransferError::InvalidTag(m) => write!(f, "invalid tag: {m}"),
+            TransferError::ChunkDigestMismatch => write!(f, "chunk digest mismatch"),
+            TransferError::DuplicateChunk => write!(f, "chunk already received"),
+            TransferError::ManifestMismatch(m) => write!(f, "manifest mismatch: {m}"),
+            TransferError::ManifestSignatureInvalid => write!(f, "manifest signature invalid"),
+            TransferError::ReceiptInvalid(m) => write!(f, "completion receipt invalid: {m}"),
             TransferError::WrongTransfer => write!(f, "ack for wrong transfer"),
             TransferError::UnknownReceiver => write!(f, "unknown receiver"),
             TransferError::AckOutOfRange => write!(f, "ack next_expected_chunk out of range"),
+            TransferError::StaleAck => write!(f, "ack from stale receiver session"),
+            TransferError::TransferIdInUse => write!(f, "transfer id already in use"),
+            TransferError::Backpressure => write!(f, "outbound queue is full"),
+            TransferError::QueueClosed => write!(f, "outbound queue closed"),
+            TransferError::Compression(m) => write!(f, "compression error: {m}"),
             TransferError::Crypto(m) => write!(f, "crypto error: {m}"),
+            TransferError::SourceModified => write!(f, "source file modified during transfer"),
+            TransferError::Terminated(Termination::Cancelled(reason)) => {
+                write!(f, "transfer cancelled: {}", reason.as_str())
+            }
+            TransferError::Terminated(Termination::Aborted(reason)) => {
+                write!(f, "transfer aborted: {}", reason.as_str())
+            }
+            TransferError::MissingSessionKey => write!(f, "no session key for receiver"),
+            TransferError::Io(m) => write!(f, "io error: {m}"),
         }
     }
 }
 
 impl std::error::Error for TransferError {}
+
+impl From<std::io::Error> for TransferError {
+    fn from(value: std::io::Error) -> Self {
+        TransferError::Io(value.to_string())
+    }
+}
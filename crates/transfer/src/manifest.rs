@@ -0,0 +1,285 @@
+use crate::{CipherSuite, TransferError};
+use crypto_envelope::{decrypt_with_suite, encrypt_with_suite, manifest_nonce};
+use identity::{verify_signature, DeviceIdentity};
+use sha2::{Digest, Sha256};
+
+const MAGIC_MANIFEST: &[u8; 4] = b"P2PM";
+const MAGIC_SIGNED_MANIFEST: &[u8; 4] = b"P2PN";
+const MAGIC_SEALED_MANIFEST: &[u8; 4] = b"P2PK";
+/// MAGIC | cipher_suite(u8) | transfer_id(u64)
+const SEALED_MANIFEST_HEADER_LEN: usize = 4 + 1 + 8;
+const SIGNATURE_CONTEXT: &[u8] = b"p2p/transfer-manifest/v1";
+const SIGNATURE_LEN: usize = 64;
+
+/// What is being sent, announced before chunk 0 so the receiver can name, size-check
+/// and verify the file without trusting the chunks alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferManifest {
+    pub transfer_id: u64,
+    pub file_name: String,
+    pub size_bytes: u64,
+    pub mime_type: String,
+    pub chunk_size: u32,
+    pub total_chunks: u32,
+    /// SHA-256 of the whole file.
+    pub file_sha256: [u8; 32],
+}
+
+impl TransferManifest {
+    /// Manifest for an in-memory payload, chunked the way [`crate::TransferSession::new`]
+    /// chunks it.
+    pub fn for_payload(
+        transfer_id: u64,
+        file_name: &str,
+        mime_type: &str,
+        data: &[u8],
+        chunk_size: usize,
+    ) -> Result<Self, TransferError> {
+        let chunk_size = u32::try_from(chunk_size)
+            .ok()
+            .filter(|&size| size > 0)
+            .ok_or(TransferError::InvalidConfig(
+                "chunk_size must be 1..=u32::MAX",
+            ))?;
+        let manifest = Self {
+            transfer_id,
+            file_name: file_name.to_string(),
+            size_bytes: data.len() as u64,
+            mime_type: mime_type.to_string(),
+            chunk_size,
+            total_chunks: expected_chunks(data.len() as u64, chunk_size),
+            file_sha256: Sha256::digest(data).into(),
+        };
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    /// Check the fields agree with each other; run on both ends of the wire.
+    pub fn validate(&self) -> Result<(), TransferError> {
+        if self.file_name.is_empty() {
+            return Err(TransferError::InvalidFrame("manifest file name is empty"));
+        }
+        if self.file_name.contains(['/', '\\', '\0'])
+            || matches!(self.file_name.as_str(), "." | "..")
+        {
+            return Err(TransferError::InvalidFrame(
+                "manifest file name is not a plain name",
+            ));
+        }
+        if self.chunk_size == 0 {
+            return Err(TransferError::InvalidFrame("manifest chunk size is zero"));
+        }
+        if self.total_chunks != expected_chunks(self.size_bytes, self.chunk_size) {
+            return Err(TransferError::InvalidFrame(
+                "manifest chunk count does not match size",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check a reassembled payload against the announced size and hash.
+    pub fn verify_payload(&self, payload: &[u8]) -> Result<(), TransferError> {
+        if payload.len() as u64 != self.size_bytes {
+            return Err(TransferError::ManifestMismatch("payload size differs"));
+        }
+        if <[u8; 32]>::from(Sha256::digest(payload)) != self.file_sha256 {
+            return Err(TransferError::ManifestMismatch("payload hash differs"));
+        }
+        Ok(())
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, TransferError> {
+        // MAGIC | transfer_id(u64) | size(u64) | chunk_size(u32) | total_chunks(u32)
+        //   | sha256[32] | name_len(u16) | name | mime_len(u16) | mime
+        let name = self.file_name.as_bytes();
+        let mime = self.mime_type.as_bytes();
+        if name.len() > u16::MAX as usize || mime.len() > u16::MAX as usize {
+            return Err(TransferError::InvalidFrame("manifest string too long"));
+        }
+        self.validate()?;
+
+        let mut out = Vec::with_capacity(64 + name.len() + mime.len());
+        out.extend_from_slice(MAGIC_MANIFEST);
+        out.extend_from_slice(&self.transfer_id.to_be_bytes());
+        out.extend_from_slice(&self.size_bytes.to_be_bytes());
+        out.extend_from_slice(&self.chunk_size.to_be_bytes());
+        out.extend_from_slice(&self.total_chunks.to_be_bytes());
+        out.extend_from_slice(&self.file_sha256);
+        out.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        out.extend_from_slice(name);
+        out.extend_from_slice(&(mime.len() as u16).to_be_bytes());
+        out.extend_from_slice(mime);
+        Ok(out)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, TransferError> {
+        if bytes.len() < 62 || &bytes[..4] != MAGIC_MANIFEST {
+            return Err(TransferError::InvalidFrame("bad manifest header"));
+        }
+        let transfer_id = u64::from_be_bytes(bytes[4..12].try_into().expect("slice len"));
+        let size_bytes = u64::from_be_bytes(bytes[12..20].try_into().expect("slice len"));
+        let chunk_size = u32::from_be_bytes(bytes[20..24].try_into().expect("slice len"));
+        let total_chunks = u32::from_be_bytes(bytes[24..28].try_into().expect("slice len"));
+        let file_sha256: [u8; 32] = bytes[28..60].try_into().expect("slice len");
+
+        let mut rest = &bytes[60..];
+        let file_name = read_string(&mut rest, "manifest file name not utf-8")?;
+        let mime_type = read_string(&mut rest, "manifest mime type not utf-8")?;
+        if !rest.is_empty() {
+            return Err(TransferError::InvalidFrame("invalid manifest length"));
+        }
+
+        let manifest = Self {
+            transfer_id,
+            file_name,
+            size_bytes,
+            mime_type,
+            chunk_size,
+            total_chunks,
+            file_sha256,
+        };
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    /// Sign with the sender's device identity so a relay cannot rewrite the metadata.
+    pub fn sign(&self, identity: &DeviceIdentity) -> Result<SignedTransferManifest, TransferError> {
+        let signature = identity.sign(&signing_bytes(&self.encode()?));
+        Ok(SignedTransferManifest {
+            manifest: self.clone(),
+            signature,
+        })
+    }
+}
+
+/// A manifest with the sender's Ed25519 signature over its encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedTransferManifest {
+    pub manifest: TransferManifest,
+    pub signature: [u8; 64],
+}
+
+impl SignedTransferManifest {
+    /// Check the signature against the sender's public key from the handshake.
+    pub fn verify(&self, public_key_b64: &str) -> Result<&TransferManifest, TransferError> {
+        let message = signing_bytes(&self.manifest.encode()?);
+        match verify_signature(public_key_b64, &message, &self.signature) {
+            Ok(true) => Ok(&self.manifest),
+            Ok(false) => Err(TransferError::ManifestSignatureInvalid),
+            Err(_) => Err(TransferError::InvalidConfig("invalid sender public key")),
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, TransferError> {
+        // MAGIC | manifest_len(u32) | manifest | signature[64]
+        let manifest = self.manifest.encode()?;
+        let mut out = Vec::with_capacity(8 + manifest.len() + SIGNATURE_LEN);
+        out.extend_from_slice(MAGIC_SIGNED_MANIFEST);
+        out.extend_from_slice(&(manifest.len() as u32).to_be_bytes());
+        out.extend_from_slice(&manifest);
+        out.extend_from_slice(&self.signature);
+        Ok(out)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, TransferError> {
+        if bytes.len() < 8 || &bytes[..4] != MAGIC_SIGNED_MANIFEST {
+            return Err(TransferError::InvalidFrame("bad signed manifest header"));
+        }
+        let len = u32::from_be_bytes(bytes[4..8].try_into().expect("slice len")) as usize;
+        if bytes.len() - 8 != len.saturating_add(SIGNATURE_LEN) {
+            return Err(TransferError::InvalidFrame(
+                "invalid signed manifest length",
+            ));
+        }
+        let manifest = TransferManifest::decode(&bytes[8..8 + len])?;
+        let signature = bytes[8 + len..].try_into().expect("slice len");
+        Ok(Self {
+            manifest,
+            signature,
+        })
+    }
+}
+
+/// Encrypt `manifest` under the session key, so file names and sizes do not cross the
+/// wire in the clear when the chunks themselves are encrypted.
+///
+/// Only the transfer id and the suite stay readable, in a header the tag covers. The
+/// nonce comes from [`manifest_nonce`], a domain no chunk nonce reaches.
+pub fn seal_manifest(
+    manifest: &TransferManifest,
+    session_tx_key: &[u8; 32],
+    suite: CipherSuite,
+) -> Result<Vec<u8>, TransferError> {
+    // MAGIC | cipher_suite(u8) | transfer_id(u64) | ciphertext
+    let mut out = Vec::with_capacity(SEALED_MANIFEST_HEADER_LEN + 128);
+    out.extend_from_slice(MAGIC_SEALED_MANIFEST);
+    out.push(suite.as_u8());
+    out.extend_from_slice(&manifest.transfer_id.to_be_bytes());
+    let ciphertext = encrypt_with_suite(
+        suite,
+        session_tx_key,
+        manifest_nonce(manifest.transfer_id),
+        &manifest.encode()?,
+        &out,
+    )
+    .map_err(|_| TransferError::Crypto("failed to encrypt manifest"))?;
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+/// Decrypt and validate what [`seal_manifest`] produced.
+pub fn open_manifest(
+    bytes: &[u8],
+    session_rx_key: &[u8; 32],
+) -> Result<TransferManifest, TransferError> {
+    if bytes.len() < SEALED_MANIFEST_HEADER_LEN || &bytes[..4] != MAGIC_SEALED_MANIFEST {
+        return Err(TransferError::InvalidFrame("bad sealed manifest header"));
+    }
+    let suite = CipherSuite::from_u8(bytes[4])
+        .ok_or(TransferError::InvalidFrame("unsupported cipher suite"))?;
+    let transfer_id = u64::from_be_bytes(bytes[5..13].try_into().expect("slice len"));
+    let (header, ciphertext) = bytes.split_at(SEALED_MANIFEST_HEADER_LEN);
+    let plaintext = decrypt_with_suite(
+        suite,
+        session_rx_key,
+        manifest_nonce(transfer_id),
+        ciphertext,
+        header,
+    )
+    .map_err(|_| TransferError::Crypto("failed to decrypt manifest"))?;
+    let manifest = TransferManifest::decode(&plaintext)?;
+    if manifest.transfer_id != transfer_id {
+        return Err(TransferError::InvalidFrame(
+            "sealed manifest transfer id mismatch",
+        ));
+    }
+    Ok(manifest)
+}
+
+/// Chunk count for `size` bytes; an empty file still travels as one empty chunk.
+fn expected_chunks(size: u64, chunk_size: u32) -> u32 {
+    size.div_ceil(u64::from(chunk_size))
+        .clamp(1, u64::from(u32::MAX)) as u32
+}
+
+fn signing_bytes(encoded: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(SIGNATURE_CONTEXT.len() + encoded.len());
+    out.extend_from_slice(SIGNATURE_CONTEXT);
+    out.extend_from_slice(encoded);
+    out
+}
+
+fn read_string(rest: &mut &[u8], what: &'static str) -> Result<String, TransferError> {
+    if rest.len() < 2 {
+        return Err(TransferError::InvalidFrame("invalid manifest length"));
+    }
+    let len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+    if rest.len() < 2 + len {
+        return Err(TransferError::InvalidFrame("invalid manifest length"));
+    }
+    let value = std::str::from_utf8(&rest[2..2 + len])
+        .map_err(|_| TransferError::InvalidFrame(what))?
+        .to_string();
+    *rest = &rest[2 + len..];
+    Ok(value)
+}
@@ -0,0 +1,43 @@
+use crate::ReceiverProgress;
+use std::fmt;
+use std::sync::Arc;
+
+/// Lifecycle callbacks from a [`crate::TransferSession`], for UI and telemetry.
+///
+/// Callbacks run synchronously on the thread driving the session, so they should only
+/// record or forward the event. Every method defaults to doing nothing.
+pub trait TransferObserver: Send + Sync {
+    /// A chunk was handed out for sending.
+    fn on_chunk_sent(&self, _transfer_id: u64, _chunk_index: u32) {}
+
+    /// An ack was applied; `progress` is the receiver's state afterwards.
+    fn on_ack(&self, _transfer_id: u64, _progress: &ReceiverProgress) {}
+
+    /// A receiver acked the last chunk. Fires once per receiver.
+    fn on_receiver_complete(&self, _transfer_id: u64, _receiver_id: &str) {}
+
+    /// Every receiver has the whole transfer. Fires once.
+    fn on_all_complete(&self, _transfer_id: u64) {}
+}
+
+/// Observers of one session; clones of the session share them.
+#[derive(Clone, Default)]
+pub(crate) struct Observers(Vec<Arc<dyn TransferObserver>>);
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
+
+impl Observers {
+    pub(crate) fn push(&mut self, observer: Arc<dyn TransferObserver>) {
+        self.0.push(observer);
+    }
+
+    pub(crate) fn each(&self, mut notify: impl FnMut(&dyn TransferObserver)) {
+        for observer in &self.0 {
+            notify(observer.as_ref());
+        }
+    }
+}
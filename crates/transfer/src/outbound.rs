@@ -0,0 +1,255 @@
+use crate::{TransferChunk, TransferError};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// Queue depth and stall telemetry for one outbound queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    pub depth: usize,
+    pub max_depth: usize,
+    pub enqueued: u64,
+    pub dequeued: u64,
+    /// Number of times the sender had to wait for the transport to drain.
+    pub stalls: u64,
+    pub total_stall: Duration,
+}
+
+#[derive(Debug)]
+struct Shared {
+    queue: VecDeque<TransferChunk>,
+    reserved: usize,
+    capacity: usize,
+    closed: bool,
+    send_waker: Option<Waker>,
+    recv_waker: Option<Waker>,
+    stalled_since: Option<Instant>,
+    stats: QueueStats,
+}
+
+impl Shared {
+    fn has_room(&self) -> bool {
+        self.queue.len() + self.reserved < self.capacity
+    }
+
+    fn end_stall(&mut self) {
+        if let Some(since) = self.stalled_since.take() {
+            self.stats.total_stall += since.elapsed();
+        }
+    }
+}
+
+/// Create a bounded per-session queue between the chunk producer and the transport.
+pub fn outbound_queue(
+    capacity: usize,
+) -> Result<(OutboundSender, OutboundReceiver), TransferError> {
+    if capacity == 0 {
+        return Err(TransferError::InvalidConfig("queue capacity must be > 0"));
+    }
+
+    let shared = Arc::new(Mutex::new(Shared {
+        queue: VecDeque::with_capacity(capacity),
+        reserved: 0,
+        capacity,
+        closed: false,
+        send_waker: None,
+        recv_waker: None,
+        stalled_since: None,
+        stats: QueueStats::default(),
+    }));
+
+    Ok((
+        OutboundSender {
+            shared: Arc::clone(&shared),
+        },
+        OutboundReceiver { shared },
+    ))
+}
+
+fn lock(shared: &Mutex<Shared>) -> MutexGuard<'_, Shared> {
+    shared
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Producer half: the sending loop enqueues chunks here.
+#[derive(Debug)]
+pub struct OutboundSender {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl OutboundSender {
+    /// Ready once a slot is free; registers the waker and records a stall otherwise.
+    pub fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), TransferError>> {
+        let mut shared = lock(&self.shared);
+        if shared.closed {
+            return Poll::Ready(Err(TransferError::QueueClosed));
+        }
+        if shared.has_room() {
+            shared.end_stall();
+            return Poll::Ready(Ok(()));
+        }
+
+        if shared.stalled_since.is_none() {
+            shared.stalled_since = Some(Instant::now());
+            shared.stats.stalls += 1;
+        }
+        shared.send_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Reserve a slot without waiting, failing with `Backpressure` when the queue is full.
+    pub fn try_reserve(&self) -> Result<SendPermit<'_>, TransferError> {
+        let mut shared = lock(&self.shared);
+        if shared.closed {
+            return Err(TransferError::QueueClosed);
+        }
+        if !shared.has_room() {
+            return Err(TransferError::Backpressure);
+        }
+        shared.reserved += 1;
+        Ok(SendPermit {
+            sender: self,
+            used: false,
+        })
+    }
+
+    /// Enqueue a chunk, waiting for the transport to drain when the queue is full.
+    pub fn send_chunk(&self, chunk: TransferChunk) -> SendChunk<'_> {
+        SendChunk {
+            sender: self,
+            chunk: Some(chunk),
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        lock(&self.shared).stats
+    }
+
+    fn push(&self, chunk: TransferChunk) {
+        let mut shared = lock(&self.shared);
+        shared.reserved -= 1;
+        shared.queue.push_back(chunk);
+        shared.stats.enqueued += 1;
+        shared.stats.depth = shared.queue.len();
+        shared.stats.max_depth = shared.stats.max_depth.max(shared.stats.depth);
+        if let Some(waker) = shared.recv_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for OutboundSender {
+    fn drop(&mut self) {
+        let mut shared = lock(&self.shared);
+        shared.closed = true;
+        if let Some(waker) = shared.recv_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A reserved queue slot; dropping it unused returns the slot.
+#[derive(Debug)]
+pub struct SendPermit<'a> {
+    sender: &'a OutboundSender,
+    used: bool,
+}
+
+impl SendPermit<'_> {
+    pub fn send(mut self, chunk: TransferChunk) {
+        self.used = true;
+        self.sender.push(chunk);
+    }
+}
+
+impl Drop for SendPermit<'_> {
+    fn drop(&mut self) {
+        if !self.used {
+            lock(&self.sender.shared).reserved -= 1;
+        }
+    }
+}
+
+/// Future returned by [`OutboundSender::send_chunk`].
+#[derive(Debug)]
+pub struct SendChunk<'a> {
+    sender: &'a OutboundSender,
+    chunk: Option<TransferChunk>,
+}
+
+impl Future for SendChunk<'_> {
+    type Output = Result<(), TransferError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            match self.sender.poll_ready(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Ready(Ok(())) => {}
+            }
+
+            // Another permit holder may have taken the slot between the two locks.
+            if let Ok(permit) = self.sender.try_reserve() {
+                let chunk = self
+                    .chunk
+                    .take()
+                    .expect("SendChunk polled after completion");
+                permit.send(chunk);
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+/// Consumer half: the transport drains chunks from here.
+#[derive(Debug)]
+pub struct OutboundReceiver {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl OutboundReceiver {
+    pub fn try_recv(&self) -> Option<TransferChunk> {
+        pop_front(&mut lock(&self.shared))
+    }
+
+    /// Ready with the next chunk, or `None` once the sender is gone and the queue is drained.
+    pub fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<TransferChunk>> {
+        let mut shared = lock(&self.shared);
+        if let Some(chunk) = pop_front(&mut shared) {
+            return Poll::Ready(Some(chunk));
+        }
+        if shared.closed {
+            return Poll::Ready(None);
+        }
+        shared.recv_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        lock(&self.shared).stats
+    }
+}
+
+fn pop_front(shared: &mut Shared) -> Option<TransferChunk> {
+    let chunk = shared.queue.pop_front()?;
+    shared.stats.dequeued += 1;
+    shared.stats.depth = shared.queue.len();
+    if let Some(waker) = shared.send_waker.take() {
+        waker.wake();
+    }
+    Some(chunk)
+}
+
+impl Drop for OutboundReceiver {
+    fn drop(&mut self) {
+        let mut shared = lock(&self.shared);
+        shared.closed = true;
+        if let Some(waker) = shared.send_waker.take() {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use crate::TransferError;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Sustained throughput plus how far above it a sender may briefly burst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub bytes_per_sec: u64,
+    pub burst_bytes: u64,
+}
+
+impl RateLimit {
+    pub fn new(bytes_per_sec: u64, burst_bytes: u64) -> Result<Self, TransferError> {
+        if bytes_per_sec == 0 || burst_bytes == 0 {
+            return Err(TransferError::InvalidConfig(
+                "rate limit and burst must be > 0",
+            ));
+        }
+        Ok(Self {
+            bytes_per_sec,
+            burst_bytes,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    limit: RateLimit,
+    /// May go negative: a chunk larger than the burst is let through from a full
+    /// bucket and the debt delays whatever follows.
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst_bytes as f64,
+            refilled_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.bytes_per_sec as f64)
+            .min(self.limit.burst_bytes as f64);
+        self.refilled_at = now;
+    }
+
+    /// Time until `bytes` may be sent; zero when it may go now.
+    fn wait_for(&self, bytes: u64) -> Duration {
+        let needed = bytes.min(self.limit.burst_bytes) as f64;
+        if self.tokens >= needed {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((needed - self.tokens) / self.limit.bytes_per_sec as f64)
+    }
+}
+
+/// Token buckets the sending loop consults before emitting each chunk.
+///
+/// A chunk goes out only when both the global budget (all transfers together) and its
+/// own transfer's budget allow it; either may be unlimited.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    global: Option<Bucket>,
+    transfers: HashMap<u64, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(global: Option<RateLimit>, now: Instant) -> Self {
+        Self {
+            global: global.map(|limit| Bucket::new(limit, now)),
+            transfers: HashMap::new(),
+        }
+    }
+
+    pub fn set_global_limit(&mut self, limit: Option<RateLimit>, now: Instant) {
+        self.global = limit.map(|limit| Bucket::new(limit, now));
+    }
+
+    /// Cap one transfer, e.g. a background sync; `None` lifts its cap.
+    pub fn set_transfer_limit(&mut self, transfer_id: u64, limit: Option<RateLimit>, now: Instant) {
+        match limit {
+            Some(limit) => {
+                self.transfers.insert(transfer_id, Bucket::new(limit, now));
+            }
+            None => {
+                self.transfers.remove(&transfer_id);
+            }
+        }
+    }
+
+    /// Take budget for a `bytes`-long chunk of `transfer_id`.
+    ///
+    /// On `Err` nothing is taken and the value is how long to wait before asking again.
+    pub fn try_acquire(
+        &mut self,
+        transfer_id: u64,
+        bytes: u64,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let mut wait = Duration::ZERO;
+        for bucket in self.buckets_for(transfer_id) {
+            bucket.refill(now);
+            wait = wait.max(bucket.wait_for(bytes));
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        for bucket in self.buckets_for(transfer_id) {
+            bucket.tokens -= bytes as f64;
+        }
+        Ok(())
+    }
+
+    /// Block the calling thread until the chunk may be sent, then take its budget.
+    pub fn acquire(&mut self, transfer_id: u64, bytes: u64) {
+        while let Err(wait) = self.try_acquire(transfer_id, bytes, Instant::now()) {
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Forget a finished transfer's budget.
+    pub fn remove_transfer(&mut self, transfer_id: u64) {
+        self.transfers.remove(&transfer_id);
+    }
+
+    fn buckets_for(&mut self, transfer_id: u64) -> impl Iterator<Item = &mut Bucket> {
+        self.global
+            .iter_mut()
+            .chain(self.transfers.get_mut(&transfer_id))
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{TransferError, TransferManifest};
+use identity::{verify_signature, DeviceIdentity};
+
+const MAGIC_RECEIPT: &[u8; 4] = b"P2PR";
+const SIGNATURE_CONTEXT: &[u8] = b"p2p/completion-receipt/v1";
+/// MAGIC | transfer_id(u64) | total_chunks(u32) | file_sha256[32]
+const BODY_LEN: usize = 4 + 8 + 4 + 32;
+const SIGNATURE_LEN: usize = 64;
+
+/// The receiver's signed statement that it holds the whole file.
+///
+/// Sent after the last chunk; the sender keeps it as proof of delivery.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletionReceipt {
+    pub transfer_id: u64,
+    pub total_chunks: u32,
+    /// SHA-256 of the reassembled payload.
+    pub file_sha256: [u8; 32],
+    pub signature: [u8; 64],
+}
+
+impl CompletionReceipt {
+    pub fn sign(
+        transfer_id: u64,
+        total_chunks: u32,
+        file_sha256: [u8; 32],
+        identity: &DeviceIdentity,
+    ) -> Self {
+        let mut receipt = Self {
+            transfer_id,
+            total_chunks,
+            file_sha256,
+            signature: [0; 64],
+        };
+        receipt.signature = identity.sign(&receipt.signing_bytes());
+        receipt
+    }
+
+    /// Check the receipt against the receiver's public key from the handshake and the
+    /// manifest the file was sent under.
+    pub fn verify(
+        &self,
+        receiver_public_key_b64: &str,
+        manifest: &TransferManifest,
+    ) -> Result<(), TransferError> {
+        match verify_signature(
+            receiver_public_key_b64,
+            &self.signing_bytes(),
+            &self.signature,
+        ) {
+            Ok(true) => {}
+            Ok(false) => return Err(TransferError::ReceiptInvalid("bad signature")),
+            Err(_) => return Err(TransferError::InvalidConfig("invalid receiver public key")),
+        }
+        if self.transfer_id != manifest.transfer_id {
+            return Err(TransferError::ReceiptInvalid(
+                "receipt for another transfer",
+            ));
+        }
+        if self.total_chunks != manifest.total_chunks || self.file_sha256 != manifest.file_sha256 {
+            return Err(TransferError::ReceiptInvalid(
+                "received file differs from manifest",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(BODY_LEN + SIGNATURE_LEN);
+        out.extend_from_slice(&self.body());
+        out.extend_from_slice(&self.signature);
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, TransferError> {
+        if bytes.len() != BODY_LEN + SIGNATURE_LEN || &bytes[..4] != MAGIC_RECEIPT {
+            return Err(TransferError::InvalidFrame("bad completion receipt"));
+        }
+        Ok(Self {
+            transfer_id: u64::from_be_bytes(bytes[4..12].try_into().expect("slice len")),
+            total_chunks: u32::from_be_bytes(bytes[12..16].try_into().expect("slice len")),
+            file_sha256: bytes[16..48].try_into().expect("slice len"),
+            signature: bytes[BODY_LEN..].try_into().expect("slice len"),
+        })
+    }
+
+    fn body(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(BODY_LEN);
+        out.extend_from_slice(MAGIC_RECEIPT);
+        out.extend_from_slice(&self.transfer_id.to_be_bytes());
+        out.extend_from_slice(&self.total_chunks.to_be_bytes());
+        out.extend_from_slice(&self.file_sha256);
+        out
+    }
+
+    fn signing_bytes(&self) -> Vec<u8> {
+        let mut out = SIGNATURE_CONTEXT.to_vec();
+        out.extend_from_slice(&self.body());
+        out
+    }
+}
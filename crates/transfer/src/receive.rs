@@ -0,0 +1,358 @@
+use crate::{
+    decrypt_chunk_frame_ref, selective_ack, Ack, CancelReason, CipherSuite, CompletionReceipt,
+    ControlFrame, Direction, SelectiveAck, Termination, TransferChunk, TransferChunkV2Ref,
+    TransferError, TransferManifest, TransferSession,
+};
+use identity::DeviceIdentity;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkReceipt {
+    Stored,
+    /// Already held; the copy was dropped. The sender is retransmitting or the network
+    /// duplicated the frame, and the fresh ack should settle it.
+    Duplicate,
+}
+
+/// Receiver side of one transfer: reassembles chunks and produces acks.
+///
+/// The chunk count is learned from the first chunk and must not change afterwards.
+/// Batches are reassembled per file with [`Self::take_files`].
+#[derive(Debug, Clone)]
+pub struct ReceiveSession {
+    transfer_id: u64,
+    receiver_id: String,
+    epoch: u64,
+    total_chunks: Option<u32>,
+    /// Payloads by chunk index, with the file each belongs to.
+    chunks: BTreeMap<u32, (u32, Vec<u8>)>,
+    next_expected: u32,
+    duplicates: u64,
+    terminated: Option<Termination>,
+    /// (chunk index, nonce) of every frame accepted, kept after the payload is taken so
+    /// late replays are still refused.
+    accepted_frames: HashSet<(u32, [u8; 12])>,
+    /// Nonce domain of the frames this session accepts.
+    direction: Direction,
+    /// The only suite [`Self::accept_encrypted_frame`] decrypts with.
+    cipher_suite: CipherSuite,
+}
+
+impl ReceiveSession {
+    /// `epoch` is this receiver process's token from [`crate::new_receiver_epoch`].
+    pub fn new(transfer_id: u64, receiver_id: impl Into<String>, epoch: u64) -> Self {
+        Self {
+            transfer_id,
+            receiver_id: receiver_id.into(),
+            epoch,
+            total_chunks: None,
+            chunks: BTreeMap::new(),
+            next_expected: 0,
+            duplicates: 0,
+            terminated: None,
+            accepted_frames: HashSet::new(),
+            direction: Direction::SenderToReceiver,
+            cipher_suite: CipherSuite::default(),
+        }
+    }
+
+    /// Accept encrypted frames only under the cipher suite negotiated in the handshake.
+    pub fn with_cipher_suite(mut self, suite: CipherSuite) -> Self {
+        self.cipher_suite = suite;
+        self
+    }
+
+    /// Session for a transfer announced by `manifest`: chunks must match its chunk count
+    /// from the first one on.
+    pub fn from_manifest(
+        manifest: &TransferManifest,
+        receiver_id: impl Into<String>,
+        epoch: u64,
+    ) -> Self {
+        let mut session = Self::new(manifest.transfer_id, receiver_id, epoch);
+        session.total_chunks = Some(manifest.total_chunks);
+        session
+    }
+
+    pub fn accept_chunk(&mut self, chunk: TransferChunk) -> Result<ChunkReceipt, TransferError> {
+        if chunk.transfer_id != self.transfer_id {
+            return Err(TransferError::WrongTransfer);
+        }
+        if let Some(termination) = self.terminated {
+            return Err(TransferError::Terminated(termination));
+        }
+        // Recorded only once the chunk is stored, so a bad first frame cannot pin it.
+        let total = self.total_chunks.unwrap_or(chunk.total_chunks);
+        if chunk.total_chunks != total {
+            return Err(TransferError::InvalidFrame(
+                "total_chunks changed mid-transfer",
+            ));
+        }
+        if chunk.chunk_index >= total {
+            return Err(TransferError::ChunkOutOfRange);
+        }
+        if self.chunks.contains_key(&chunk.chunk_index) {
+            self.duplicates += 1;
+            return Ok(ChunkReceipt::Duplicate);
+        }
+
+        self.check_file_order(chunk.chunk_index, chunk.file_index)?;
+
+        self.total_chunks = Some(total);
+        self.chunks
+            .insert(chunk.chunk_index, (chunk.file_index, chunk.payload));
+        while self.chunks.contains_key(&self.next_expected) {
+            self.next_expected += 1;
+        }
+        Ok(ChunkReceipt::Stored)
+    }
+
+    /// Refuse a frame whose chunk index and nonce were already accepted, before spending
+    /// a decryption on it.
+    pub fn check_replay(&self, chunk_index: u32, nonce: &[u8; 12]) -> Result<(), TransferError> {
+        if self.accepted_frames.contains(&(chunk_index, *nonce)) {
+            return Err(TransferError::DuplicateChunk);
+        }
+        Ok(())
+    }
+
+    /// [`Self::accept_chunk`] for a chunk taken off a frame carrying `nonce`: a replay of
+    /// an accepted frame fails with [`TransferError::DuplicateChunk`] instead of being
+    /// processed again.
+    pub fn accept_frame_chunk(
+        &mut self,
+        nonce: [u8; 12],
+        chunk: TransferChunk,
+    ) -> Result<ChunkReceipt, TransferError> {
+        if chunk.transfer_id != self.transfer_id {
+            return Err(TransferError::WrongTransfer);
+        }
+        self.check_replay(chunk.chunk_index, &nonce)?;
+        let chunk_index = chunk.chunk_index;
+        let receipt = self.accept_chunk(chunk)?;
+        if receipt == ChunkReceipt::Stored {
+            self.accepted_frames.insert((chunk_index, nonce));
+        }
+        Ok(receipt)
+    }
+
+    /// Check, decrypt and store one encrypted frame, in this session's direction.
+    pub fn accept_encrypted_frame(
+        &mut self,
+        frame: &TransferChunkV2Ref<'_>,
+        key: &[u8; 32],
+    ) -> Result<ChunkReceipt, TransferError> {
+        if frame.transfer_id != self.transfer_id {
+            return Err(TransferError::WrongTransfer);
+        }
+        self.check_replay(frame.chunk_index, &frame.nonce)?;
+        let chunk = decrypt_chunk_frame_ref(frame, key, self.direction, self.cipher_suite)?;
+        self.accept_frame_chunk(frame.nonce, chunk)
+    }
+
+    /// Session for sending `data` back to the sender under this transfer's id, e.g. a
+    /// thumbnail of what arrived.
+    ///
+    /// Seal its chunks under the key this end receives the transfer with: the
+    /// receiver-to-sender nonce domain keeps them apart from the forward chunks under that
+    /// key. `sender_id` names the original sender, who receives with
+    /// [`TransferSession::reply_receiver`]. Peers sharing a
+    /// [`DuplexSession`](crate::DuplexSession) reply with
+    /// [`DuplexSession::reply`](crate::DuplexSession::reply) instead.
+    pub fn reply(
+        &self,
+        sender_id: impl Into<String>,
+        data: Vec<u8>,
+        chunk_size: usize,
+    ) -> Result<TransferSession, TransferError> {
+        if let Some(termination) = self.terminated {
+            return Err(TransferError::Terminated(termination));
+        }
+        Ok(
+            TransferSession::new(self.transfer_id, data, chunk_size, [sender_id.into()])?
+                .with_direction(Direction::ReceiverToSender),
+        )
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    pub(crate) fn with_direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Cumulative ack: every chunk below `next_expected_chunk` has arrived. Chunks held
+    /// beyond that are reported in its bitmap.
+    pub fn ack(&self) -> Ack {
+        Ack {
+            transfer_id: self.transfer_id,
+            receiver_id: self.receiver_id.clone(),
+            receiver_epoch: self.epoch,
+            next_expected_chunk: self.next_expected,
+            sack_bitmap: selective_ack::sack_bitmap(
+                self.next_expected,
+                self.chunks
+                    .range(self.next_expected..)
+                    .map(|(&index, _)| index),
+            ),
+        }
+    }
+
+    /// Ack that also lists the holes behind the furthest chunk received.
+    pub fn selective_ack(&self) -> SelectiveAck {
+        SelectiveAck::from_received(
+            self.transfer_id,
+            &self.receiver_id,
+            self.epoch,
+            self.chunks.keys().copied(),
+        )
+    }
+
+    /// Stop receiving and return the frame telling the sender why. Buffered chunks are
+    /// released.
+    pub fn cancel(&mut self, reason: CancelReason) -> ControlFrame {
+        self.terminate(Termination::Cancelled(reason))
+            .frame(self.transfer_id)
+    }
+
+    /// Give up on the transfer after a failure and return the frame to send.
+    pub fn abort(&mut self, reason: CancelReason) -> ControlFrame {
+        self.terminate(Termination::Aborted(reason))
+            .frame(self.transfer_id)
+    }
+
+    /// Apply a cancel or abort frame from the sender, releasing buffered chunks. The
+    /// first termination sticks.
+    pub fn apply_termination(
+        &mut self,
+        frame: &ControlFrame,
+    ) -> Result<Termination, TransferError> {
+        if frame.transfer_id() != self.transfer_id {
+            return Err(TransferError::WrongTransfer);
+        }
+        let termination = frame
+            .termination()
+            .ok_or(TransferError::InvalidFrame("not a cancel or abort frame"))?;
+        Ok(self.terminate(termination))
+    }
+
+    /// Frame telling the sender this receiver is still there while it sends no acks.
+    pub fn heartbeat(&self) -> ControlFrame {
+        ControlFrame::Heartbeat {
+            transfer_id: self.transfer_id,
+        }
+    }
+
+    pub fn termination(&self) -> Option<Termination> {
+        self.terminated
+    }
+
+    pub fn transfer_id(&self) -> u64 {
+        self.transfer_id
+    }
+
+    /// `None` until the first chunk arrives.
+    pub fn total_chunks(&self) -> Option<u32> {
+        self.total_chunks
+    }
+
+    pub fn received_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.terminated.is_none()
+            && self
+                .total_chunks
+                .is_some_and(|total| self.next_expected >= total)
+    }
+
+    /// The reassembled payload once every chunk has arrived; the buffered chunks are
+    /// released. Returns `None` (keeping the chunks) while incomplete.
+    pub fn take_payload(&mut self) -> Option<Vec<u8>> {
+        if !self.is_complete() {
+            return None;
+        }
+        Some(
+            std::mem::take(&mut self.chunks)
+                .into_values()
+                .flat_map(|(_, payload)| payload)
+                .collect(),
+        )
+    }
+
+    /// Signed proof for the sender that every chunk arrived; call before taking the
+    /// payload, since it hashes the buffered chunks.
+    pub fn completion_receipt(
+        &self,
+        identity: &DeviceIdentity,
+    ) -> Result<CompletionReceipt, TransferError> {
+        let total_chunks = self
+            .total_chunks
+            .filter(|_| self.is_complete())
+            .ok_or(TransferError::InvalidConfig("transfer is not complete"))?;
+        let mut hasher = Sha256::new();
+        for (_, payload) in self.chunks.values() {
+            hasher.update(payload);
+        }
+        Ok(CompletionReceipt::sign(
+            self.transfer_id,
+            total_chunks,
+            hasher.finalize().into(),
+            identity,
+        ))
+    }
+
+    /// Like [`Self::take_payload`], but split into the files of a batch, in order.
+    pub fn take_files(&mut self) -> Option<Vec<Vec<u8>>> {
+        if !self.is_complete() {
+            return None;
+        }
+        let mut files: Vec<Vec<u8>> = Vec::new();
+        for (file_index, payload) in std::mem::take(&mut self.chunks).into_values() {
+            if files.len() <= file_index as usize {
+                files.push(Vec::new());
+            }
+            files
+                .last_mut()
+                .expect("file pushed above")
+                .extend_from_slice(&payload);
+        }
+        Some(files)
+    }
+
+    fn terminate(&mut self, termination: Termination) -> Termination {
+        if self.terminated.is_none() {
+            self.terminated = Some(termination);
+            self.chunks.clear();
+        }
+        self.terminated.expect("set above")
+    }
+
+    /// Files occupy consecutive chunk runs starting at file 0, so a chunk's file index
+    /// must sit between its neighbours' and step by at most one between adjacent chunks.
+    fn check_file_order(&self, chunk_index: u32, file_index: u32) -> Result<(), TransferError> {
+        let out_of_order = TransferError::InvalidFrame("file index out of order");
+        if chunk_index == 0 && file_index != 0 {
+            return Err(out_of_order);
+        }
+        if let Some((&prev, &(prev_file, _))) = self.chunks.range(..chunk_index).next_back() {
+            if prev_file > file_index || (prev + 1 == chunk_index && file_index - prev_file > 1) {
+                return Err(out_of_order);
+            }
+        }
+        if let Some((&next, &(next_file, _))) = self.chunks.range(chunk_index + 1..).next() {
+            if next_file < file_index || (chunk_index + 1 == next && next_file - file_index > 1) {
+                return Err(out_of_order);
+            }
+        }
+        Ok(())
+    }
+}
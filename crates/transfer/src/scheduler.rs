@@ -0,0 +1,219 @@
+use crate::{TransferChunk, TransferError, TransferSession};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedulerConfig {
+    /// Transfers of at most this many bytes go to the fast lane.
+    pub small_transfer_threshold: u64,
+    /// Share of send slots (percent) reserved for the fast lane while both lanes have work.
+    /// Must stay below 100 so bulk transfers always progress.
+    pub fast_lane_percent: u8,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            small_transfer_threshold: 1024 * 1024,
+            fast_lane_percent: 25,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    /// Small or interactive transfers the user is waiting on.
+    Fast,
+    /// Large or background transfers.
+    Bulk,
+}
+
+#[derive(Debug)]
+struct ScheduledSession {
+    session: TransferSession,
+    next_chunk: u32,
+    lane: Lane,
+}
+
+impl ScheduledSession {
+    fn has_pending(&self) -> bool {
+        self.next_chunk < self.session.total_chunks()
+    }
+}
+
+/// Interleaves chunks of concurrent sessions so small transfers are not stuck
+/// behind large ones.
+#[derive(Debug)]
+pub struct TransferScheduler {
+    config: SchedulerConfig,
+    sessions: Vec<ScheduledSession>,
+    cursor_fast: usize,
+    cursor_bulk: usize,
+    contended_picks: u64,
+    contended_fast_picks: u64,
+}
+
+impl TransferScheduler {
+    pub fn new(config: SchedulerConfig) -> Result<Self, TransferError> {
+        if config.fast_lane_percent >= 100 {
+            return Err(TransferError::InvalidConfig(
+                "fast_lane_percent must be < 100",
+            ));
+        }
+        Ok(Self {
+            config,
+            sessions: Vec::new(),
+            cursor_fast: 0,
+            cursor_bulk: 0,
+            contended_picks: 0,
+            contended_fast_picks: 0,
+        })
+    }
+
+    /// Add a session, starting at the lowest resume point across its receivers.
+    ///
+    /// The lane follows the transfer's size; use [`Self::add_session_in_lane`] when the
+    /// caller knows better (a large file the user is watching, a small background sync).
+    pub fn add_session(&mut self, session: TransferSession) -> Result<Lane, TransferError> {
+        let lane = if session.total_bytes() <= self.config.small_transfer_threshold {
+            Lane::Fast
+        } else {
+            Lane::Bulk
+        };
+        self.add_session_in_lane(session, lane)?;
+        Ok(lane)
+    }
+
+    /// Add a session with an explicit priority, regardless of its size.
+    pub fn add_session_in_lane(
+        &mut self,
+        session: TransferSession,
+        lane: Lane,
+    ) -> Result<(), TransferError> {
+        if self.position(session.transfer_id()).is_some() {
+            return Err(TransferError::TransferIdInUse);
+        }
+        let next_chunk = session.min_resume_point();
+        self.sessions.push(ScheduledSession {
+            session,
+            next_chunk,
+            lane,
+        });
+        Ok(())
+    }
+
+    /// Move a scheduled transfer to another lane, e.g. when the user opens or
+    /// backgrounds it. Returns `false` for unknown transfers.
+    pub fn set_lane(&mut self, transfer_id: u64, lane: Lane) -> bool {
+        match self.position(transfer_id) {
+            Some(idx) => {
+                self.sessions[idx].lane = lane;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove_session(&mut self, transfer_id: u64) -> Option<TransferSession> {
+        let idx = self.position(transfer_id)?;
+        Some(self.sessions.remove(idx).session)
+    }
+
+    pub fn session_mut(&mut self, transfer_id: u64) -> Option<&mut TransferSession> {
+        let idx = self.position(transfer_id)?;
+        Some(&mut self.sessions[idx].session)
+    }
+
+    pub fn lane_of(&self, transfer_id: u64) -> Option<Lane> {
+        self.position(transfer_id)
+            .map(|idx| self.sessions[idx].lane)
+    }
+
+    /// Scheduled transfers carrying `tag`, in scheduling order.
+    pub fn transfer_ids_tagged(&self, tag: &str) -> Vec<u64> {
+        self.sessions
+            .iter()
+            .filter(|s| s.session.has_tag(tag))
+            .map(|s| s.session.transfer_id())
+            .collect()
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.sessions.iter().any(ScheduledSession::has_pending)
+    }
+
+    /// Yield the next chunk to put on the wire, or `None` when no session has one ready.
+    ///
+    /// A session whose next chunk cannot be read (an upload still missing it, a failing
+    /// source) keeps its place and is passed over until the next call.
+    pub fn next_chunk(&mut self) -> Option<TransferChunk> {
+        let mut stalled = vec![false; self.sessions.len()];
+        loop {
+            let fast_pending = self.lane_has_pending(Lane::Fast, &stalled);
+            let bulk_pending = self.lane_has_pending(Lane::Bulk, &stalled);
+
+            let contended = fast_pending && bulk_pending;
+            let lane = match (fast_pending, bulk_pending) {
+                (false, false) => return None,
+                (true, false) => Lane::Fast,
+                (false, true) => Lane::Bulk,
+                (true, true) => {
+                    if self.contended_fast_picks * 100 <= self.contended_picks * self.fast_share() {
+                        Lane::Fast
+                    } else {
+                        Lane::Bulk
+                    }
+                }
+            };
+
+            let idx = self.pick_round_robin(lane, &stalled)?;
+            let entry = &mut self.sessions[idx];
+            let Ok(chunk) = entry.session.chunk_for(entry.next_chunk) else {
+                stalled[idx] = true;
+                continue;
+            };
+            entry.session.mark_chunk_sent(entry.next_chunk);
+            entry.next_chunk += 1;
+            if contended {
+                self.contended_picks += 1;
+                if lane == Lane::Fast {
+                    self.contended_fast_picks += 1;
+                }
+            }
+            return Some(chunk);
+        }
+    }
+
+    fn fast_share(&self) -> u64 {
+        u64::from(self.config.fast_lane_percent)
+    }
+
+    fn lane_has_pending(&self, lane: Lane, stalled: &[bool]) -> bool {
+        self.sessions
+            .iter()
+            .zip(stalled)
+            .any(|(s, &stalled)| !stalled && s.lane == lane && s.has_pending())
+    }
+
+    fn pick_round_robin(&mut self, lane: Lane, stalled: &[bool]) -> Option<usize> {
+        let len = self.sessions.len();
+        let cursor = match lane {
+            Lane::Fast => &mut self.cursor_fast,
+            Lane::Bulk => &mut self.cursor_bulk,
+        };
+
+        for offset in 0..len {
+            let idx = (*cursor + offset) % len;
+            let entry = &self.sessions[idx];
+            if !stalled[idx] && entry.lane == lane && entry.has_pending() {
+                *cursor = idx + 1;
+                return Some(idx);
+            }
+        }
+        None
+    }
+
+    fn position(&self, transfer_id: u64) -> Option<usize> {
+        self.sessions
+            .iter()
+            .position(|s| s.session.transfer_id() == transfer_id)
+    }
+}
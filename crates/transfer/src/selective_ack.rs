@@ -0,0 +1,193 @@
+use crate::{Ack, TransferError};
+
+pub(crate) const MAGIC_SACK: &[u8; 4] = b"P2PS";
+
+/// Widest span a selective ack may describe past the cumulative point (8 KiB bitmap).
+pub const MAX_SACK_SPAN: u32 = 65_536;
+
+/// Receiver report of holes beyond the contiguous prefix.
+///
+/// Everything below `next_expected_chunk` has arrived, as with [`Ack`]. In
+/// `[next_expected_chunk, received_up_to_exclusive)` the chunks listed in `missing`
+/// are absent and the rest have arrived, so the sender can refill just the holes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectiveAck {
+    pub transfer_id: u64,
+    pub receiver_id: String,
+    pub receiver_epoch: u64,
+    pub next_expected_chunk: u32,
+    pub received_up_to_exclusive: u32,
+    /// Ascending chunk indices inside the reported span.
+    pub missing: Vec<u32>,
+}
+
+impl SelectiveAck {
+    /// Build a report from the set of chunk indices the receiver holds.
+    pub fn from_received(
+        transfer_id: u64,
+        receiver_id: &str,
+        receiver_epoch: u64,
+        received: impl IntoIterator<Item = u32>,
+    ) -> Self {
+        let mut received: Vec<u32> = received.into_iter().collect();
+        received.sort_unstable();
+        received.dedup();
+
+        let mut next_expected_chunk = 0;
+        for &index in &received {
+            if index != next_expected_chunk {
+                break;
+            }
+            next_expected_chunk += 1;
+        }
+        let received_up_to_exclusive = received
+            .last()
+            .map_or(0, |&last| last + 1)
+            .max(next_expected_chunk);
+        let missing = (next_expected_chunk..received_up_to_exclusive)
+            .filter(|index| received.binary_search(index).is_err())
+            .collect();
+
+        Self {
+            transfer_id,
+            receiver_id: receiver_id.to_string(),
+            receiver_epoch,
+            next_expected_chunk,
+            received_up_to_exclusive,
+            missing,
+        }
+    }
+
+    /// The cumulative part alone, for peers that only track contiguous progress.
+    pub fn cumulative(&self) -> Ack {
+        Ack {
+            transfer_id: self.transfer_id,
+            receiver_id: self.receiver_id.clone(),
+            receiver_epoch: self.receiver_epoch,
+            next_expected_chunk: self.next_expected_chunk,
+            sack_bitmap: Vec::new(),
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, TransferError> {
+        // MAGIC | transfer_id(u64) | epoch(u64) | next_expected(u32) | span(u32)
+        //   | receiver_id_len(u16) | receiver_id | received bitmap (ceil(span/8) bytes, same
+        //   layout as `Ack::sack_bitmap`)
+        let span = self.span()?;
+        let receiver_id = self.receiver_id.as_bytes();
+        if receiver_id.len() > u16::MAX as usize {
+            return Err(TransferError::InvalidFrame("receiver id too long"));
+        }
+        if self.missing.iter().any(|&index| {
+            index < self.next_expected_chunk || index >= self.received_up_to_exclusive
+        }) {
+            return Err(TransferError::InvalidFrame(
+                "missing chunk outside sack span",
+            ));
+        }
+        let mut bitmap = vec![0u8; span.div_ceil(8) as usize];
+        for bit in 0..span {
+            bitmap[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+        for &index in &self.missing {
+            let bit = index - self.next_expected_chunk;
+            bitmap[(bit / 8) as usize] &= !(1 << (bit % 8));
+        }
+
+        let mut out = Vec::with_capacity(30 + receiver_id.len() + bitmap.len());
+        out.extend_from_slice(MAGIC_SACK);
+        out.extend_from_slice(&self.transfer_id.to_be_bytes());
+        out.extend_from_slice(&self.receiver_epoch.to_be_bytes());
+        out.extend_from_slice(&self.next_expected_chunk.to_be_bytes());
+        out.extend_from_slice(&span.to_be_bytes());
+        out.extend_from_slice(&(receiver_id.len() as u16).to_be_bytes());
+        out.extend_from_slice(receiver_id);
+        out.extend_from_slice(&bitmap);
+        Ok(out)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, TransferError> {
+        if bytes.len() < 30 || &bytes[..4] != MAGIC_SACK {
+            return Err(TransferError::InvalidFrame("bad sack header"));
+        }
+        let transfer_id = u64::from_be_bytes(bytes[4..12].try_into().expect("slice len"));
+        let receiver_epoch = u64::from_be_bytes(bytes[12..20].try_into().expect("slice len"));
+        let next_expected_chunk = u32::from_be_bytes(bytes[20..24].try_into().expect("slice len"));
+        let span = u32::from_be_bytes(bytes[24..28].try_into().expect("slice len"));
+        let id_len = u16::from_be_bytes(bytes[28..30].try_into().expect("slice len")) as usize;
+        if span > MAX_SACK_SPAN {
+            return Err(TransferError::InvalidFrame("sack span too large"));
+        }
+        let received_up_to_exclusive = next_expected_chunk
+            .checked_add(span)
+            .ok_or(TransferError::InvalidFrame("sack span overflows"))?;
+
+        let rest = &bytes[30..];
+        if rest.len() != id_len + span.div_ceil(8) as usize {
+            return Err(TransferError::InvalidFrame("invalid sack length"));
+        }
+        let receiver_id = std::str::from_utf8(&rest[..id_len])
+            .map_err(|_| TransferError::InvalidFrame("receiver id not utf-8"))?
+            .to_string();
+        let bitmap = &rest[id_len..];
+        let missing = (0..span)
+            .filter(|&bit| !bit_is_set(bitmap, bit))
+            .map(|bit| next_expected_chunk + bit)
+            .collect();
+
+        Ok(Self {
+            transfer_id,
+            receiver_id,
+            receiver_epoch,
+            next_expected_chunk,
+            received_up_to_exclusive,
+            missing,
+        })
+    }
+
+    fn span(&self) -> Result<u32, TransferError> {
+        let span = self
+            .received_up_to_exclusive
+            .checked_sub(self.next_expected_chunk)
+            .ok_or(TransferError::InvalidFrame("sack span is negative"))?;
+        if span > MAX_SACK_SPAN {
+            return Err(TransferError::InvalidFrame("sack span too large"));
+        }
+        Ok(span)
+    }
+}
+
+/// [`Ack::sack_bitmap`] for the chunks held past `next_expected`. Chunks more than
+/// [`MAX_SACK_SPAN`] past it are left out; they are reported once the gap closes.
+pub(crate) fn sack_bitmap(next_expected: u32, held: impl IntoIterator<Item = u32>) -> Vec<u8> {
+    let mut bitmap = Vec::new();
+    for index in held {
+        let Some(bit) = index.checked_sub(next_expected) else {
+            continue;
+        };
+        if bit >= MAX_SACK_SPAN {
+            continue;
+        }
+        let byte = (bit / 8) as usize;
+        if bitmap.len() <= byte {
+            bitmap.resize(byte + 1, 0);
+        }
+        bitmap[byte] |= 1 << (bit % 8);
+    }
+    bitmap
+}
+
+/// Chunks an ack or selective-ack bitmap reports as received, ascending. Bit `i` stands
+/// for chunk `next_expected + i`.
+pub(crate) fn received_in_bitmap(
+    next_expected: u32,
+    bitmap: &[u8],
+) -> impl Iterator<Item = u32> + '_ {
+    (0..bitmap.len() as u32 * 8)
+        .filter(move |&bit| bit_is_set(bitmap, bit))
+        .map(move |bit| next_expected.saturating_add(bit))
+}
+
+fn bit_is_set(bitmap: &[u8], bit: u32) -> bool {
+    bitmap[(bit / 8) as usize] & (1 << (bit % 8)) != 0
+}
@@ -0,0 +1,94 @@
+use crate::{Termination, TransferError};
+use serde::{Deserialize, Deserializer};
+use std::sync::Mutex;
+
+/// Distinct error messages kept for deserialized errors; further unknown messages map
+/// to [`UNKNOWN_MESSAGE`] so hostile input cannot grow memory without bound.
+const MAX_INTERNED_MESSAGES: usize = 256;
+const UNKNOWN_MESSAGE: &str = "unrecognized error message";
+
+static INTERNED: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+/// [`TransferError`] with owned messages, as read back from JSON.
+///
+/// The error's messages are `&'static str`; this is the shape serde can fill before
+/// they are interned.
+#[derive(Deserialize)]
+#[serde(rename = "TransferError")]
+pub(crate) enum TransferErrorRepr {
+    InvalidFrame(String),
+    InvalidConfig(String),
+    ChunkOutOfRange,
+    ChunkNotUploaded,
+    InvalidTag(String),
+    ChunkDigestMismatch,
+    DuplicateChunk,
+    ManifestMismatch(String),
+    ManifestSignatureInvalid,
+    ReceiptInvalid(String),
+    WrongTransfer,
+    UnknownReceiver,
+    AckOutOfRange,
+    StaleAck,
+    TransferIdInUse,
+    Backpressure,
+    QueueClosed,
+    Compression(String),
+    Crypto(String),
+    SourceModified,
+    Terminated(Termination),
+    MissingSessionKey,
+    Io(String),
+}
+
+impl From<TransferErrorRepr> for TransferError {
+    fn from(repr: TransferErrorRepr) -> Self {
+        use TransferErrorRepr as R;
+        match repr {
+            R::InvalidFrame(m) => TransferError::InvalidFrame(intern(m)),
+            R::InvalidConfig(m) => TransferError::InvalidConfig(intern(m)),
+            R::ChunkOutOfRange => TransferError::ChunkOutOfRange,
+            R::ChunkNotUploaded => TransferError::ChunkNotUploaded,
+            R::InvalidTag(m) => TransferError::InvalidTag(intern(m)),
+            R::ChunkDigestMismatch => TransferError::ChunkDigestMismatch,
+            R::DuplicateChunk => TransferError::DuplicateChunk,
+            R::ManifestMismatch(m) => TransferError::ManifestMismatch(intern(m)),
+            R::ManifestSignatureInvalid => TransferError::ManifestSignatureInvalid,
+            R::ReceiptInvalid(m) => TransferError::ReceiptInvalid(intern(m)),
+            R::WrongTransfer => TransferError::WrongTransfer,
+            R::UnknownReceiver => TransferError::UnknownReceiver,
+            R::AckOutOfRange => TransferError::AckOutOfRange,
+            R::StaleAck => TransferError::StaleAck,
+            R::TransferIdInUse => TransferError::TransferIdInUse,
+            R::Backpressure => TransferError::Backpressure,
+            R::QueueClosed => TransferError::QueueClosed,
+            R::Compression(m) => TransferError::Compression(intern(m)),
+            R::Crypto(m) => TransferError::Crypto(intern(m)),
+            R::SourceModified => TransferError::SourceModified,
+            R::Terminated(t) => TransferError::Terminated(t),
+            R::MissingSessionKey => TransferError::MissingSessionKey,
+            R::Io(m) => TransferError::Io(m),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TransferError {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        TransferErrorRepr::deserialize(deserializer).map(Self::from)
+    }
+}
+
+fn intern(message: String) -> &'static str {
+    let Ok(mut interned) = INTERNED.lock() else {
+        return UNKNOWN_MESSAGE;
+    };
+    if let Some(existing) = interned.iter().find(|m| **m == message) {
+        return existing;
+    }
+    if interned.len() >= MAX_INTERNED_MESSAGES {
+        return UNKNOWN_MESSAGE;
+    }
+    let leaked: &'static str = Box::leak(message.into_boxed_str());
+    interned.push(leaked);
+    leaked
+}
@@ -0,0 +1,43 @@
+use crate::CompressionPlan;
+
+/// What the two peers agreed on for one session, recorded for debugging.
+///
+/// Values negotiated outside this crate (encryption mode, cipher, route) are kept as
+/// their wire/display names so the record can be logged and served without the
+/// crates that produced them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionParams {
+    pub protocol_version: u8,
+    pub encryption_mode: String,
+    /// `"none"` when the session runs in plaintext.
+    pub cipher: String,
+    pub chunk_size: u32,
+    pub compression: CompressionPlan,
+    pub route: String,
+}
+
+impl SessionParams {
+    /// Name/value pairs in a stable order, shared by the stats blob, audit metadata and
+    /// API responses so they never disagree.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("protocol_version", self.protocol_version.to_string()),
+            ("encryption_mode", self.encryption_mode.clone()),
+            ("cipher", self.cipher.clone()),
+            ("chunk_size", self.chunk_size.to_string()),
+            ("compression", self.compression.describe()),
+            ("route", self.route.clone()),
+        ]
+    }
+}
+
+impl CompressionPlan {
+    /// Short label: `none`, `zstd` or `zstd-dict:<id>`.
+    pub fn describe(&self) -> String {
+        match self {
+            CompressionPlan::None => "none".to_string(),
+            CompressionPlan::Zstd => "zstd".to_string(),
+            CompressionPlan::ZstdDictionary(id) => format!("zstd-dict:{id}"),
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{SourceSnapshot, TransferError};
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+const SNAPSHOT_HEADER: &str = "p2p-transfer-snapshot/1";
+
+/// The file a snapshotted session streams from, and how it looked at the time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotSource {
+    pub path: PathBuf,
+    pub state: SourceSnapshot,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceiverSnapshot {
+    pub receiver_id: String,
+    pub acked_up_to_exclusive: u32,
+    /// Epoch bound for the receiver, so acks from before the restart stay stale.
+    pub epoch: Option<u64>,
+}
+
+/// Sender-side state of a [`crate::TransferSession`], enough to resume every receiver
+/// after a restart.
+///
+/// The payload is not included: file-backed sessions record their path, in-memory
+/// sessions need their data handed back. Session keys and negotiated parameters are
+/// not included either and are bound again after the new handshakes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferSnapshot {
+    pub transfer_id: u64,
+    pub chunk_size: usize,
+    /// Length of each file; one entry unless the session is a batch.
+    pub file_lens: Vec<u64>,
+    pub source: Option<SnapshotSource>,
+    /// Chunks an upload session was still waiting for.
+    pub missing: Vec<u32>,
+    pub tags: Vec<String>,
+    pub receivers: Vec<ReceiverSnapshot>,
+}
+
+impl TransferSnapshot {
+    /// `key=value` lines under a version header; receiver ids go last on their line so
+    /// they may contain commas.
+    pub fn encode(&self) -> Result<String, TransferError> {
+        let mut out = format!(
+            "{SNAPSHOT_HEADER}\ntransfer_id={}\nchunk_size={}\nfiles={}\n",
+            self.transfer_id,
+            self.chunk_size,
+            join(&self.file_lens)
+        );
+        if let Some(source) = &self.source {
+            let path = source
+                .path
+                .to_str()
+                .filter(|path| !path.contains('\n'))
+                .ok_or(TransferError::InvalidConfig(
+                    "source path cannot be stored in a snapshot",
+                ))?;
+            out.push_str(&format!("source={path}\nsource_len={}\n", source.state.len));
+            if let Some(modified) = source.state.modified {
+                let nanos = modified
+                    .duration_since(UNIX_EPOCH)
+                    .map_err(|_| TransferError::InvalidConfig("source mtime before 1970"))?
+                    .as_nanos();
+                out.push_str(&format!("source_modified_ns={nanos}\n"));
+            }
+            if let Some(inode) = source.state.inode {
+                out.push_str(&format!("source_inode={inode}\n"));
+            }
+        }
+        if !self.missing.is_empty() {
+            out.push_str(&format!("missing={}\n", join(&self.missing)));
+        }
+        if !self.tags.is_empty() {
+            out.push_str(&format!("tags={}\n", self.tags.join(",")));
+        }
+        for receiver in &self.receivers {
+            if receiver.receiver_id.contains('\n') {
+                return Err(TransferError::InvalidConfig(
+                    "receiver id cannot be stored in a snapshot",
+                ));
+            }
+            let epoch = receiver
+                .epoch
+                .map_or_else(|| "-".to_string(), |epoch| epoch.to_string());
+            out.push_str(&format!(
+                "receiver={},{epoch},{}\n",
+                receiver.acked_up_to_exclusive, receiver.receiver_id
+            ));
+        }
+        Ok(out)
+    }
+
+    pub fn decode(text: &str) -> Result<Self, TransferError> {
+        let invalid = TransferError::InvalidFrame("invalid snapshot");
+        let mut lines = text.lines();
+        if lines.next() != Some(SNAPSHOT_HEADER) {
+            return Err(TransferError::InvalidFrame("unknown snapshot version"));
+        }
+
+        let (mut transfer_id, mut chunk_size, mut file_lens) = (None, None, None);
+        let (mut path, mut source_len, mut modified, mut inode) = (None, None, None, None);
+        let mut snapshot = Self {
+            transfer_id: 0,
+            chunk_size: 0,
+            file_lens: Vec::new(),
+            source: None,
+            missing: Vec::new(),
+            tags: Vec::new(),
+            receivers: Vec::new(),
+        };
+        for line in lines {
+            let (key, value) = line.split_once('=').ok_or(invalid.clone())?;
+            match key {
+                "transfer_id" => transfer_id = Some(parse(value)?),
+                "chunk_size" => chunk_size = Some(parse(value)?),
+                "files" => file_lens = Some(parse_list(value)?),
+                "source" => path = Some(PathBuf::from(value)),
+                "source_len" => source_len = Some(parse(value)?),
+                "source_modified_ns" => {
+                    let nanos: u128 = parse(value)?;
+                    let secs = u64::try_from(nanos / 1_000_000_000).map_err(|_| invalid.clone())?;
+                    modified =
+                        Some(UNIX_EPOCH + Duration::new(secs, (nanos % 1_000_000_000) as u32));
+                }
+                "source_inode" => inode = Some(parse(value)?),
+                "missing" => snapshot.missing = parse_list(value)?,
+                "tags" => snapshot.tags = value.split(',').map(str::to_string).collect(),
+                "receiver" => {
+                    let mut fields = value.splitn(3, ',');
+                    let (Some(acked), Some(epoch), Some(receiver_id)) =
+                        (fields.next(), fields.next(), fields.next())
+                    else {
+                        return Err(invalid);
+                    };
+                    snapshot.receivers.push(ReceiverSnapshot {
+                        receiver_id: receiver_id.to_string(),
+                        acked_up_to_exclusive: parse(acked)?,
+                        epoch: if epoch == "-" {
+                            None
+                        } else {
+                            Some(parse(epoch)?)
+                        },
+                    });
+                }
+                // Unknown keys are skipped so snapshots from newer versions still load.
+                _ => {}
+            }
+        }
+
+        snapshot.transfer_id = transfer_id.ok_or(invalid.clone())?;
+        snapshot.chunk_size = chunk_size.ok_or(invalid.clone())?;
+        snapshot.file_lens = file_lens.ok_or(invalid.clone())?;
+        snapshot.source = match (path, source_len) {
+            (Some(path), Some(len)) => Some(SnapshotSource {
+                path,
+                state: SourceSnapshot {
+                    len,
+                    modified,
+                    inode,
+                },
+            }),
+            (None, None) => None,
+            _ => return Err(invalid),
+        };
+        Ok(snapshot)
+    }
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(T::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T, TransferError> {
+    value
+        .parse()
+        .map_err(|_| TransferError::InvalidFrame("invalid snapshot"))
+}
+
+fn parse_list<T: std::str::FromStr>(value: &str) -> Result<Vec<T>, TransferError> {
+    value.split(',').map(parse).collect()
+}
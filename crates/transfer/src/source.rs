@@ -0,0 +1,325 @@
+use crate::TransferError;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Random-access provider of chunk payloads for a sending session.
+pub trait TransferSource {
+    fn len(&self) -> u64;
+
+    fn chunk_size(&self) -> usize;
+
+    fn read_chunk(&mut self, chunk_index: u32) -> Result<Vec<u8>, TransferError>;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn total_chunks(&self) -> u32 {
+        self.len().div_ceil(self.chunk_size() as u64) as u32
+    }
+
+    /// Fail with `SourceModified` if the data changed since the transfer was set up.
+    ///
+    /// Sources that cannot change (or cannot tell) report unchanged.
+    fn check_unchanged(&mut self) -> Result<(), TransferError> {
+        Ok(())
+    }
+}
+
+/// Size, mtime and inode of a file, taken when its manifest is built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceSnapshot {
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    /// `None` on platforms without inode numbers.
+    pub inode: Option<u64>,
+}
+
+impl SourceSnapshot {
+    pub fn capture(path: impl AsRef<Path>) -> Result<Self, TransferError> {
+        let metadata = fs::metadata(path)?;
+        #[cfg(unix)]
+        let inode = {
+            use std::os::unix::fs::MetadataExt;
+            Some(metadata.ino())
+        };
+        #[cfg(not(unix))]
+        let inode = None;
+        Ok(Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            inode,
+        })
+    }
+}
+
+/// How often `FileSource` re-stats its file, counted in underlying reads.
+pub const DEFAULT_CHANGE_CHECK_READS: u32 = 8;
+
+#[derive(Debug)]
+struct ChangeWatch {
+    path: PathBuf,
+    snapshot: SourceSnapshot,
+    check_every_reads: u32,
+    reads_since_check: u32,
+}
+
+/// In-memory source for small payloads.
+#[derive(Debug, Clone)]
+pub struct MemorySource {
+    data: Vec<u8>,
+    chunk_size: usize,
+}
+
+impl MemorySource {
+    pub fn new(data: Vec<u8>, chunk_size: usize) -> Result<Self, TransferError> {
+        if chunk_size == 0 {
+            return Err(TransferError::InvalidConfig("chunk_size must be > 0"));
+        }
+        Ok(Self { data, chunk_size })
+    }
+}
+
+impl TransferSource for MemorySource {
+    fn len(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    fn read_chunk(&mut self, chunk_index: u32) -> Result<Vec<u8>, TransferError> {
+        if chunk_index >= self.total_chunks() {
+            return Err(TransferError::ChunkOutOfRange);
+        }
+        let start = chunk_index as usize * self.chunk_size;
+        let end = (start + self.chunk_size).min(self.data.len());
+        Ok(self.data[start..end].to_vec())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadAheadConfig {
+    /// Chunks fetched past the requested one on a cache miss (0 disables read-ahead).
+    pub prefetch_chunks: u32,
+    /// Maximum chunks held in the cache; oldest entries are evicted first.
+    pub cache_chunks: usize,
+}
+
+impl Default for ReadAheadConfig {
+    fn default() -> Self {
+        Self {
+            prefetch_chunks: 8,
+            cache_chunks: 32,
+        }
+    }
+}
+
+impl ReadAheadConfig {
+    pub fn disabled() -> Self {
+        Self {
+            prefetch_chunks: 0,
+            cache_chunks: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadAheadStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Chunks loaded ahead of being requested.
+    pub prefetched: u64,
+    pub evictions: u64,
+    /// Read calls issued against the underlying reader.
+    pub reads: u64,
+}
+
+/// File-backed source that turns sequential chunk requests into larger reads.
+///
+/// On a miss the requested chunk and up to `prefetch_chunks` following chunks are
+/// read with a single call, which is what matters on high-latency mounts.
+#[derive(Debug)]
+pub struct FileSource<R = File> {
+    reader: R,
+    len: u64,
+    chunk_size: usize,
+    config: ReadAheadConfig,
+    cache: BTreeMap<u32, Vec<u8>>,
+    order: VecDeque<u32>,
+    stats: ReadAheadStats,
+    watch: Option<ChangeWatch>,
+}
+
+impl FileSource<File> {
+    /// Open `path`, snapshotting it so later modification is detected.
+    pub fn open(
+        path: impl AsRef<Path>,
+        chunk_size: usize,
+        config: ReadAheadConfig,
+    ) -> Result<Self, TransferError> {
+        let path = path.as_ref();
+        let snapshot = SourceSnapshot::capture(path)?;
+        let file = File::open(path)?;
+        let mut source = Self::from_reader(file, snapshot.len, chunk_size, config)?;
+        source.watch = Some(ChangeWatch {
+            path: path.to_path_buf(),
+            snapshot,
+            check_every_reads: DEFAULT_CHANGE_CHECK_READS,
+            reads_since_check: 0,
+        });
+        Ok(source)
+    }
+
+    /// Re-check the file every `reads` underlying reads (minimum 1).
+    pub fn with_change_check_interval(mut self, reads: u32) -> Self {
+        if let Some(watch) = &mut self.watch {
+            watch.check_every_reads = reads.max(1);
+        }
+        self
+    }
+
+    pub fn snapshot(&self) -> Option<SourceSnapshot> {
+        self.watch.as_ref().map(|w| w.snapshot)
+    }
+
+    /// Start over against the file's current contents after `SourceModified`.
+    ///
+    /// Takes a fresh snapshot and drops cached chunks; the transfer itself has to be
+    /// re-offered, since the manifest the receiver holds no longer matches.
+    pub fn restart(&mut self) -> Result<SourceSnapshot, TransferError> {
+        let watch = self
+            .watch
+            .as_mut()
+            .ok_or(TransferError::InvalidConfig("source has no path to reopen"))?;
+        let snapshot = SourceSnapshot::capture(&watch.path)?;
+        self.reader = File::open(&watch.path)?;
+        watch.snapshot = snapshot;
+        watch.reads_since_check = 0;
+        self.len = snapshot.len;
+        self.cache.clear();
+        self.order.clear();
+        Ok(snapshot)
+    }
+}
+
+impl<R: Read + Seek> FileSource<R> {
+    pub fn from_reader(
+        reader: R,
+        len: u64,
+        chunk_size: usize,
+        config: ReadAheadConfig,
+    ) -> Result<Self, TransferError> {
+        if chunk_size == 0 {
+            return Err(TransferError::InvalidConfig("chunk_size must be > 0"));
+        }
+        if config.prefetch_chunks > 0 && config.cache_chunks <= config.prefetch_chunks as usize {
+            return Err(TransferError::InvalidConfig(
+                "cache_chunks must exceed prefetch_chunks",
+            ));
+        }
+        Ok(Self {
+            reader,
+            len,
+            chunk_size,
+            config,
+            cache: BTreeMap::new(),
+            order: VecDeque::new(),
+            stats: ReadAheadStats::default(),
+            watch: None,
+        })
+    }
+
+    pub fn stats(&self) -> ReadAheadStats {
+        self.stats
+    }
+
+    fn load_range(&mut self, first: u32, count: u32) -> Result<Vec<u8>, TransferError> {
+        if let Some(watch) = &mut self.watch {
+            watch.reads_since_check += 1;
+            if watch.reads_since_check >= watch.check_every_reads {
+                self.check_watch()?;
+            }
+        }
+
+        let offset = u64::from(first) * self.chunk_size as u64;
+        let end = (offset + u64::from(count) * self.chunk_size as u64).min(self.len);
+        let mut buf = vec![0u8; (end - offset) as usize];
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.reader.read_exact(&mut buf)?;
+        self.stats.reads += 1;
+
+        let mut chunks = buf.chunks(self.chunk_size);
+        let requested = chunks.next().map(<[u8]>::to_vec).unwrap_or_default();
+        for (index, chunk) in (first + 1..).zip(chunks) {
+            self.insert(index, chunk.to_vec());
+            self.stats.prefetched += 1;
+        }
+        Ok(requested)
+    }
+
+    fn check_watch(&mut self) -> Result<(), TransferError> {
+        let Some(watch) = &mut self.watch else {
+            return Ok(());
+        };
+        watch.reads_since_check = 0;
+        // A vanished file counts as modified too.
+        let current = SourceSnapshot::capture(&watch.path).ok();
+        if current != Some(watch.snapshot) {
+            self.cache.clear();
+            self.order.clear();
+            return Err(TransferError::SourceModified);
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, index: u32, chunk: Vec<u8>) {
+        if self.cache.insert(index, chunk).is_some() {
+            return;
+        }
+        self.order.push_back(index);
+        while self.cache.len() > self.config.cache_chunks {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if self.cache.remove(&oldest).is_some() {
+                self.stats.evictions += 1;
+            }
+        }
+    }
+}
+
+impl<R: Read + Seek> TransferSource for FileSource<R> {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    fn read_chunk(&mut self, chunk_index: u32) -> Result<Vec<u8>, TransferError> {
+        let total = self.total_chunks();
+        if chunk_index >= total {
+            return Err(TransferError::ChunkOutOfRange);
+        }
+
+        if let Some(chunk) = self.cache.remove(&chunk_index) {
+            self.order.retain(|&i| i != chunk_index);
+            self.stats.hits += 1;
+            return Ok(chunk);
+        }
+
+        self.stats.misses += 1;
+        let count = (self.config.prefetch_chunks + 1).min(total - chunk_index);
+        self.load_range(chunk_index, count)
+    }
+
+    fn check_unchanged(&mut self) -> Result<(), TransferError> {
+        self.check_watch()
+    }
+}
@@ -0,0 +1,42 @@
+use crate::TransferError;
+
+pub const MAX_TAGS: usize = 8;
+pub const MAX_TAG_LEN: usize = 32;
+
+/// Canonical form of user-supplied transfer labels.
+///
+/// Tags are trimmed and lowercased so `Backup` and `backup ` filter together, and
+/// duplicates are dropped keeping first-seen order. Only ASCII letters, digits, `-`,
+/// `_` and `.` are allowed, which keeps tags safe to join with commas in audit
+/// metadata and stats blobs.
+pub fn normalize_tags<I, S>(tags: I) -> Result<Vec<String>, TransferError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut out: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.as_ref().trim().to_ascii_lowercase();
+        if tag.is_empty() {
+            return Err(TransferError::InvalidTag("empty tag"));
+        }
+        if tag.len() > MAX_TAG_LEN {
+            return Err(TransferError::InvalidTag("tag longer than 32 characters"));
+        }
+        if !tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            return Err(TransferError::InvalidTag(
+                "tag contains unsupported characters",
+            ));
+        }
+        if !out.contains(&tag) {
+            out.push(tag);
+        }
+    }
+    if out.len() > MAX_TAGS {
+        return Err(TransferError::InvalidTag("more than 8 tags"));
+    }
+    Ok(out)
+}
@@ -0,0 +1,267 @@
+use crate::control::MAGIC_CONTROL;
+use crate::selective_ack::MAGIC_SACK;
+use crate::{
+    decrypt_chunk_frame_ref, encode_stream_frame, new_receiver_epoch, plaintext_chunk_frame,
+    CancelReason, CipherSuite, CompressionCodec, ControlFrame, Direction, EncryptionFlag,
+    FrameDecoder, PaddingScheme, PreparedChunk, ReceiveSession, SelectiveAck, SendWindow,
+    Termination, TransferChunk, TransferChunkV2Ref, TransferError, TransferSession,
+    DEFAULT_WINDOW_CHUNKS,
+};
+use std::borrow::Cow;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::timeout;
+
+const READ_BUF_LEN: usize = 64 * 1024;
+
+/// Settings shared by both ends of a driven transfer.
+#[derive(Debug, Clone)]
+pub struct DriverConfig {
+    /// Session key from the handshake; chunks go out as plaintext v2 frames with a
+    /// digest when `None`. Both ends must agree: an encrypting receiver refuses
+    /// plaintext chunks.
+    pub session_key: Option<[u8; 32]>,
+    /// Suite the handshake settled on, for the chunks this end sends; received frames
+    /// naming another are refused.
+    pub cipher_suite: CipherSuite,
+    /// Padding the handshake settled on for the chunks this end sends.
+    pub padding: PaddingScheme,
+    pub window_chunks: u32,
+    /// How long the sender waits for an ack before resending what is in flight.
+    pub ack_timeout: Duration,
+    /// Consecutive timeouts before the transfer is aborted. The receiver gives up
+    /// after the same total silence.
+    pub max_retries: u32,
+}
+
+impl Default for DriverConfig {
+    fn default() -> Self {
+        Self {
+            session_key: None,
+            cipher_suite: CipherSuite::default(),
+            padding: PaddingScheme::None,
+            window_chunks: DEFAULT_WINDOW_CHUNKS,
+            ack_timeout: Duration::from_secs(5),
+            max_retries: 3,
+        }
+    }
+}
+
+/// Send `session` to `receiver_id` over `stream` until every chunk is acked.
+///
+/// Chunks go out windowed; selective acks release them and queue holes for resending,
+/// and a silent receiver gets the in-flight chunks again before the transfer is
+/// aborted with [`CancelReason::Timeout`]. A cancel or abort from the receiver ends
+/// the transfer with [`TransferError::Terminated`].
+pub async fn send_transfer<S>(
+    stream: &mut S,
+    session: &mut TransferSession,
+    receiver_id: &str,
+    config: &DriverConfig,
+) -> Result<(), TransferError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut window =
+        SendWindow::for_session(session, [receiver_id.to_string()], config.window_chunks)?;
+    let mut frames = FrameSource::new();
+    let mut retries = 0;
+
+    while !window.is_complete(receiver_id)? {
+        let sendable = window.next_sendable_chunks(receiver_id)?;
+        for &index in &sendable {
+            let chunk = session.chunk_for(index)?;
+            let frame = match &config.session_key {
+                Some(key) => PreparedChunk::new(
+                    Cow::Owned(chunk),
+                    &mut session.nonces,
+                    Direction::SenderToReceiver,
+                    false,
+                    CompressionCodec::None,
+                    config.cipher_suite,
+                    config.padding,
+                )?
+                .seal(key)?,
+                None => plaintext_chunk_frame(&chunk, true),
+            };
+            write_frame(stream, &frame.encode()).await?;
+            session.mark_chunk_sent(index);
+        }
+        if !sendable.is_empty() {
+            stream.flush().await?;
+        }
+
+        let frame = match timeout(config.ack_timeout, frames.read_frame(stream)).await {
+            Ok(frame) => frame?.ok_or(TransferError::Io("peer closed the stream".to_string()))?,
+            Err(_) if retries < config.max_retries => {
+                retries += 1;
+                window.mark_in_flight_lost(receiver_id)?;
+                continue;
+            }
+            Err(_) => {
+                let abort = session.abort(CancelReason::Timeout);
+                // Best effort: the receiver may be gone, and the timeout is the error.
+                let _ = write_frame(stream, &abort.encode()).await;
+                let _ = stream.flush().await;
+                return Err(TransferError::Terminated(Termination::Aborted(
+                    CancelReason::Timeout,
+                )));
+            }
+        };
+
+        match frame.get(..4) {
+            Some(magic) if magic == MAGIC_SACK => {
+                let sack = SelectiveAck::decode(&frame)?;
+                if sack.transfer_id != session.transfer_id() {
+                    return Err(TransferError::WrongTransfer);
+                }
+                session.apply_selective_ack(&sack)?;
+                window.on_selective_ack(&sack)?;
+            }
+            Some(magic) if magic == MAGIC_CONTROL => {
+                let control = ControlFrame::decode(&frame)?;
+                match control {
+                    ControlFrame::Heartbeat { .. } => {
+                        session.apply_heartbeat(receiver_id, &control)?
+                    }
+                    ControlFrame::Cancel { .. } | ControlFrame::Abort { .. } => {
+                        let termination = session.apply_termination(&control)?;
+                        return Err(TransferError::Terminated(termination));
+                    }
+                    // Offer and error frames belong to the layer above.
+                    _ => {}
+                }
+            }
+            _ => {
+                return Err(TransferError::InvalidFrame(
+                    "unexpected frame from receiver",
+                ))
+            }
+        }
+        retries = 0;
+    }
+    Ok(())
+}
+
+/// Receive one transfer from `stream` and return its completed session, from which
+/// the caller takes the payload or files.
+///
+/// Every chunk is answered with a selective ack, including replays of chunks already
+/// held, which are not processed again. Silence longer than the sender's
+/// whole retry budget aborts with [`CancelReason::Timeout`].
+pub async fn receive_transfer<S>(
+    stream: &mut S,
+    receiver_id: &str,
+    config: &DriverConfig,
+) -> Result<ReceiveSession, TransferError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let idle_limit = config.ack_timeout * (config.max_retries + 1);
+    let epoch = new_receiver_epoch();
+    let mut frames = FrameSource::new();
+    let mut session: Option<ReceiveSession> = None;
+
+    loop {
+        let Ok(frame) = timeout(idle_limit, frames.read_frame(stream)).await else {
+            if let Some(session) = &mut session {
+                let abort = session.abort(CancelReason::Timeout);
+                let _ = write_frame(stream, &abort.encode()).await;
+                let _ = stream.flush().await;
+            }
+            return Err(TransferError::Terminated(Termination::Aborted(
+                CancelReason::Timeout,
+            )));
+        };
+        let frame = frame?.ok_or(TransferError::Io("peer closed the stream".to_string()))?;
+
+        if frame.starts_with(MAGIC_CONTROL) {
+            let control = ControlFrame::decode(&frame)?;
+            let termination = match (&mut session, control.termination()) {
+                (Some(session), Some(_)) => session.apply_termination(&control)?,
+                (None, Some(termination)) => termination,
+                _ => continue,
+            };
+            return Err(TransferError::Terminated(termination));
+        }
+
+        let chunk_frame = TransferChunkV2Ref::decode(&frame)?;
+        if let Some(receiving) = &session {
+            // A resend after a lost ack: answer it again instead of reprocessing it.
+            if receiving
+                .check_replay(chunk_frame.chunk_index, &chunk_frame.nonce)
+                .is_err()
+            {
+                write_frame(stream, &receiving.selective_ack().encode()?).await?;
+                stream.flush().await?;
+                continue;
+            }
+        }
+        let chunk = match (&config.session_key, chunk_frame.encryption_flag) {
+            (Some(key), _) => decrypt_chunk_frame_ref(
+                &chunk_frame,
+                key,
+                Direction::SenderToReceiver,
+                config.cipher_suite,
+            )?,
+            (None, EncryptionFlag::Plaintext) => TransferChunk {
+                transfer_id: chunk_frame.transfer_id,
+                file_index: chunk_frame.file_index(),
+                chunk_index: chunk_frame.chunk_index,
+                total_chunks: chunk_frame.total_chunks,
+                payload: chunk_frame.payload.to_vec(),
+            },
+            (None, EncryptionFlag::Encrypted) => return Err(TransferError::MissingSessionKey),
+        };
+
+        let receiving = session
+            .get_or_insert_with(|| ReceiveSession::new(chunk.transfer_id, receiver_id, epoch));
+        receiving.accept_frame_chunk(chunk_frame.nonce, chunk)?;
+        write_frame(stream, &receiving.selective_ack().encode()?).await?;
+        stream.flush().await?;
+        if receiving.is_complete() {
+            return Ok(session.expect("session created above"));
+        }
+    }
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    frame: &[u8],
+) -> Result<(), TransferError> {
+    stream.write_all(&encode_stream_frame(frame)?).await?;
+    Ok(())
+}
+
+/// Decoder plus the read buffer it is fed from, kept for the whole transfer.
+struct FrameSource {
+    decoder: FrameDecoder,
+    buf: Box<[u8]>,
+}
+
+impl FrameSource {
+    fn new() -> Self {
+        Self {
+            decoder: FrameDecoder::default(),
+            buf: vec![0u8; READ_BUF_LEN].into_boxed_slice(),
+        }
+    }
+
+    /// Next frame, or `None` once the stream ends. Safe to cancel: bytes already read
+    /// stay in the decoder.
+    async fn read_frame<R: AsyncRead + Unpin>(
+        &mut self,
+        stream: &mut R,
+    ) -> Result<Option<Vec<u8>>, TransferError> {
+        loop {
+            if let Some(frame) = self.decoder.next_frame() {
+                return Ok(Some(frame));
+            }
+            let n = stream.read(&mut self.buf).await?;
+            if n == 0 {
+                return Ok(None);
+            }
+            self.decoder.push(&self.buf[..n]);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use crate::TransferError;
+use std::collections::{HashSet, VecDeque};
+
+/// Allocates and tracks transfer ids so concurrent transfers never share one.
+///
+/// Ids that finished recently stay reserved for `history_limit` releases, because late
+/// frames of the old transfer may still be in flight.
+#[derive(Debug, Clone)]
+pub struct TransferIdRegistry {
+    active: HashSet<u64>,
+    recent: VecDeque<u64>,
+    history_limit: usize,
+}
+
+impl TransferIdRegistry {
+    pub fn new(history_limit: usize) -> Self {
+        Self {
+            active: HashSet::new(),
+            recent: VecDeque::new(),
+            history_limit,
+        }
+    }
+
+    /// Pick a random non-zero id that is neither live nor in recent history and mark it live.
+    pub fn allocate(&mut self) -> u64 {
+        loop {
+            let candidate: u64 = rand::random();
+            if candidate != 0 && !self.is_reserved(candidate) {
+                self.active.insert(candidate);
+                return candidate;
+            }
+        }
+    }
+
+    /// Register an id chosen by a remote sender (receiver side of an offer).
+    pub fn register(&mut self, transfer_id: u64) -> Result<(), TransferError> {
+        if self.is_reserved(transfer_id) {
+            return Err(TransferError::TransferIdInUse);
+        }
+        self.active.insert(transfer_id);
+        Ok(())
+    }
+
+    /// Mark a transfer finished; its id moves into the bounded recent history.
+    pub fn release(&mut self, transfer_id: u64) {
+        if !self.active.remove(&transfer_id) {
+            return;
+        }
+        self.recent.push_back(transfer_id);
+        while self.recent.len() > self.history_limit {
+            self.recent.pop_front();
+        }
+    }
+
+    pub fn is_active(&self, transfer_id: u64) -> bool {
+        self.active.contains(&transfer_id)
+    }
+
+    fn is_reserved(&self, transfer_id: u64) -> bool {
+        self.active.contains(&transfer_id) || self.recent.contains(&transfer_id)
+    }
+}
+
+impl Default for TransferIdRegistry {
+    fn default() -> Self {
+        Self::new(256)
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{Ack, SelectiveAck, TransferError, TransferSession};
+use std::collections::{BTreeSet, HashMap};
+
+pub const DEFAULT_WINDOW_CHUNKS: u32 = 32;
+
+#[derive(Debug, Clone, Default)]
+struct ReceiverWindow {
+    /// Everything below this is acknowledged.
+    acked: u32,
+    /// Lowest chunk never sent yet.
+    next_new: u32,
+    in_flight: BTreeSet<u32>,
+    /// Chunks known lost, resent ahead of new data.
+    retransmit: BTreeSet<u32>,
+}
+
+/// Per-receiver flow control: at most `window_chunks` chunks unacknowledged at once.
+///
+/// Callers ask for [`Self::next_sendable_chunks`], send what it returns, and feed acks
+/// back in; the window reopens as acks arrive. Lost chunks are resent before new ones.
+#[derive(Debug, Clone)]
+pub struct SendWindow {
+    window_chunks: u32,
+    total_chunks: u32,
+    receivers: HashMap<String, ReceiverWindow>,
+}
+
+impl SendWindow {
+    pub fn new(
+        total_chunks: u32,
+        window_chunks: u32,
+        receiver_ids: impl IntoIterator<Item = String>,
+    ) -> Result<Self, TransferError> {
+        if window_chunks == 0 {
+            return Err(TransferError::InvalidConfig("window_chunks must be > 0"));
+        }
+        Ok(Self {
+            window_chunks,
+            total_chunks,
+            receivers: receiver_ids
+                .into_iter()
+                .map(|id| (id, ReceiverWindow::default()))
+                .collect(),
+        })
+    }
+
+    /// Window over a session's receivers, each starting at its resume checkpoint.
+    pub fn for_session(
+        session: &TransferSession,
+        receiver_ids: impl IntoIterator<Item = String>,
+        window_chunks: u32,
+    ) -> Result<Self, TransferError> {
+        let mut window = Self::new(session.total_chunks(), window_chunks, [])?;
+        for id in receiver_ids {
+            let resume = session.resume_from_for_receiver(&id)?;
+            let state = ReceiverWindow {
+                acked: resume,
+                next_new: resume,
+                ..ReceiverWindow::default()
+            };
+            window.receivers.insert(id, state);
+        }
+        Ok(window)
+    }
+
+    pub fn window_chunks(&self) -> u32 {
+        self.window_chunks
+    }
+
+    /// Chunks to put on the wire now for `receiver_id`, marked in flight.
+    ///
+    /// Returns an empty list while the window is full or everything has been sent.
+    pub fn next_sendable_chunks(&mut self, receiver_id: &str) -> Result<Vec<u32>, TransferError> {
+        let total = self.total_chunks;
+        let capacity = self.window_chunks as usize;
+        let state = self.receiver_mut(receiver_id)?;
+        let mut out = Vec::new();
+        while state.in_flight.len() < capacity {
+            let next = match state.retransmit.pop_first() {
+                Some(index) => index,
+                None if state.next_new < total => {
+                    state.next_new += 1;
+                    state.next_new - 1
+                }
+                None => break,
+            };
+            state.in_flight.insert(next);
+            out.push(next);
+        }
+        Ok(out)
+    }
+
+    pub fn on_ack(&mut self, ack: &Ack) -> Result<(), TransferError> {
+        if ack.next_expected_chunk > self.total_chunks {
+            return Err(TransferError::AckOutOfRange);
+        }
+        let state = self.receiver_mut(&ack.receiver_id)?;
+        state.advance(ack.next_expected_chunk);
+        Ok(())
+    }
+
+    /// Release chunks a selective ack confirms and queue its holes for resending.
+    pub fn on_selective_ack(&mut self, sack: &SelectiveAck) -> Result<(), TransferError> {
+        if sack.received_up_to_exclusive > self.total_chunks {
+            return Err(TransferError::AckOutOfRange);
+        }
+        let state = self.receiver_mut(&sack.receiver_id)?;
+        state.advance(sack.next_expected_chunk);
+        let missing: BTreeSet<u32> = sack.missing.iter().copied().collect();
+        let span = sack.next_expected_chunk..sack.received_up_to_exclusive;
+        state
+            .in_flight
+            .retain(|index| !span.contains(index) || missing.contains(index));
+        for index in missing {
+            if state.in_flight.remove(&index) || index < state.next_new {
+                state.retransmit.insert(index);
+            }
+        }
+        Ok(())
+    }
+
+    /// Give up on an in-flight chunk (e.g. after a timeout) and queue it for resending.
+    pub fn mark_lost(&mut self, receiver_id: &str, chunk_index: u32) -> Result<(), TransferError> {
+        let state = self.receiver_mut(receiver_id)?;
+        if state.in_flight.remove(&chunk_index) {
+            state.retransmit.insert(chunk_index);
+        }
+        Ok(())
+    }
+
+    /// Queue every in-flight chunk for resending, e.g. when no ack came back in time.
+    pub fn mark_in_flight_lost(&mut self, receiver_id: &str) -> Result<(), TransferError> {
+        let state = self.receiver_mut(receiver_id)?;
+        let lost = std::mem::take(&mut state.in_flight);
+        state.retransmit.extend(lost);
+        Ok(())
+    }
+
+    pub fn in_flight(&self, receiver_id: &str) -> Result<usize, TransferError> {
+        self.receivers
+            .get(receiver_id)
+            .map(|state| state.in_flight.len())
+            .ok_or(TransferError::UnknownReceiver)
+    }
+
+    pub fn is_complete(&self, receiver_id: &str) -> Result<bool, TransferError> {
+        self.receivers
+            .get(receiver_id)
+            .map(|state| state.acked >= self.total_chunks)
+            .ok_or(TransferError::UnknownReceiver)
+    }
+
+    fn receiver_mut(&mut self, receiver_id: &str) -> Result<&mut ReceiverWindow, TransferError> {
+        self.receivers
+            .get_mut(receiver_id)
+            .ok_or(TransferError::UnknownReceiver)
+    }
+}
+
+impl ReceiverWindow {
+    fn advance(&mut self, next_expected: u32) {
+        if next_expected <= self.acked {
+            return;
+        }
+        self.acked = next_expected;
+        self.next_new = self.next_new.max(next_expected);
+        self.in_flight.retain(|&index| index >= next_expected);
+        self.retransmit.retain(|&index| index >= next_expected);
+    }
+}
@@ -1,18 +1,42 @@
+use discovery::FreeSpaceHint;
+use sha2::Digest;
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
 use transfer::{
-    decrypt_chunk_frame, encrypt_chunk_frame, transfer_chunk_aad, Ack, EncryptionFlag,
-    TransferChunk, TransferChunkV2, TransferSession, VersionedTransferChunk,
+    compress_payload, decompress_payload, decrypt_chunk_frame, decrypt_chunk_frame_directional,
+    decrypt_chunk_frame_ref, decrypt_chunk_frame_v3, encode_stream_frame, encrypt_chunk_frame,
+    encrypt_chunk_frame_compressed, encrypt_chunk_frame_in_place, encrypt_chunk_frame_padded,
+    encrypt_chunk_frame_v3, encrypt_chunk_frame_v3_with_suite, encrypt_chunk_frame_with_digest,
+    encrypt_chunk_frame_with_suite, new_receiver_epoch, normalize_tags, open_manifest,
+    outbound_queue, plaintext_chunk_frame, plaintext_chunk_frame_v3, seal_manifest,
+    select_compression, transfer_chunk_aad, AadBuilder, Ack, AdaptiveChunkConfig, AdaptiveChunker,
+    CancelReason, ChunkAad, ChunkReceipt, CipherSuite, CompletionReceipt, CompressionCapabilities,
+    CompressionCodec, CompressionPlan, ControlFrame, DictionaryStore, Direction, DuplexSession,
+    EncryptionFlag, ErrorFrame, FairScheduler, FairnessConfig, FileSource, FrameDecoder,
+    FrameExtension, FrameReader, FrameWriter, Lane, MemorySource, PaddingScheme, RateLimit,
+    RateLimiter, ReadAheadConfig, ReceiveSession, SchedulerConfig, SelectiveAck, SendWindow,
+    SessionParams, SessionRole, SignedTransferManifest, Termination, TransferChunk,
+    TransferChunkRef, TransferChunkV2, TransferChunkV2Ref, TransferChunkV3, TransferError,
+    TransferErrorCode, TransferIdRegistry, TransferManifest, TransferObserver, TransferScheduler,
+    TransferSession, TransferSnapshot, TransferSource, VersionedTransferChunk, MAX_SACK_SPAN,
+    MAX_UPLOAD_BYTES,
 };
+#[cfg(feature = "tokio")]
+use transfer::{receive_transfer, send_transfer, DriverConfig};
 
 #[test]
 fn chunk_frame_roundtrip() {
     let chunk = TransferChunk {
         transfer_id: 42,
+        file_index: 0,
         chunk_index: 0,
         total_chunks: 2,
         payload: b"hello".to_vec(),
     };
 
-    let decoded = TransferChunk::decode(&chunk.encode()).expect("decode chunk");
+    let decoded =
+        TransferChunk::decode(&chunk.encode().expect("encode chunk")).expect("decode chunk");
     assert_eq!(decoded, chunk);
 }
 
@@ -20,15 +44,18 @@ fn chunk_frame_roundtrip() {
 fn versioned_decoder_accepts_v1_and_v2() {
     let v1 = TransferChunk {
         transfer_id: 1,
+        file_index: 0,
         chunk_index: 0,
         total_chunks: 1,
         payload: b"v1".to_vec(),
     }
-    .encode();
+    .encode()
+    .expect("encode v1");
 
     let v2 = TransferChunkV2 {
         protocol_version: 2,
         encryption_flag: EncryptionFlag::Encrypted,
+        cipher_suite: CipherSuite::default(),
         transfer_id: 2,
         chunk_index: 0,
         total_chunks: 1,
@@ -53,6 +80,7 @@ fn v2_frame_roundtrip_with_metadata() {
     let chunk = TransferChunkV2 {
         protocol_version: 2,
         encryption_flag: EncryptionFlag::Encrypted,
+        cipher_suite: CipherSuite::default(),
         transfer_id: 91,
         chunk_index: 3,
         total_chunks: 10,
@@ -70,6 +98,7 @@ fn encrypt_adapter_wraps_chunk_and_decrypt_adapter_recovers_payload() {
     let key = [13u8; 32];
     let chunk = TransferChunk {
         transfer_id: 77,
+        file_index: 0,
         chunk_index: 2,
         total_chunks: 5,
         payload: b"payload-for-e4".to_vec(),
@@ -91,6 +120,7 @@ fn decrypt_adapter_fails_with_wrong_key() {
     let bad_key = [2u8; 32];
     let chunk = TransferChunk {
         transfer_id: 9,
+        file_index: 0,
         chunk_index: 0,
         total_chunks: 1,
         payload: b"secret".to_vec(),
@@ -104,6 +134,62 @@ fn decrypt_adapter_fails_with_wrong_key() {
     );
 }
 
+#[test]
+fn decrypt_adapter_rejects_tampered_chunk_index() {
+    let key = [1u8; 32];
+    let chunk = TransferChunk {
+        transfer_id: 9,
+        file_index: 0,
+        chunk_index: 1,
+        total_chunks: 4,
+        payload: b"secret".to_vec(),
+    };
+
+    let mut frame = encrypt_chunk_frame(&chunk, &key).expect("encrypt");
+    frame.chunk_index = 2;
+    let err = decrypt_chunk_frame(&frame, &key).expect_err("moved chunk must fail");
+    assert!(matches!(err, TransferError::InvalidFrame(_)));
+}
+
+#[test]
+fn chunk_digest_in_aad_detects_corruption_in_plaintext_and_encrypted_frames() {
+    let chunk = TransferChunk {
+        transfer_id: 12,
+        file_index: 0,
+        chunk_index: 3,
+        total_chunks: 5,
+        payload: b"payload bytes".to_vec(),
+    };
+
+    let frame = plaintext_chunk_frame(&chunk, true);
+    assert!(frame.chunk_digest().is_some());
+    let mut wire = frame.encode();
+    assert_eq!(TransferChunkV2::decode(&wire).expect("intact"), frame);
+    let last = wire.len() - 1;
+    wire[last] ^= 0x01;
+    assert_eq!(
+        TransferChunkV2::decode(&wire).expect_err("corrupted"),
+        TransferError::ChunkDigestMismatch
+    );
+    // Frames without a digest keep decoding as before.
+    let mut legacy = plaintext_chunk_frame(&chunk, false).encode();
+    legacy[last - 32] ^= 0x01;
+    assert!(TransferChunkV2::decode(&legacy).is_ok());
+
+    let key = [5u8; 32];
+    let sealed =
+        encrypt_chunk_frame_with_digest(&chunk, &key, Direction::SenderToReceiver).expect("seal");
+    let decoded = TransferChunkV2::decode(&sealed.encode()).expect("decode");
+    assert_eq!(decrypt_chunk_frame(&decoded, &key).expect("open"), chunk);
+
+    let mut stripped = decoded.clone();
+    stripped.aad.truncate(16);
+    assert!(decrypt_chunk_frame(&stripped, &key).is_err());
+    let mut swapped = decoded;
+    swapped.aad[20] ^= 0xff;
+    assert!(decrypt_chunk_frame(&swapped, &key).is_err());
+}
+
 #[test]
 fn session_creates_expected_total_chunks() {
     let data = vec![1u8; 10];
@@ -121,7 +207,9 @@ fn resume_checkpoint_moves_forward_per_receiver() {
         .apply_ack(&Ack {
             transfer_id: 11,
             receiver_id: "r1".to_string(),
+            receiver_epoch: 1,
             next_expected_chunk: 2,
+            sack_bitmap: Vec::new(),
         })
         .expect("ack 1");
 
@@ -129,7 +217,9 @@ fn resume_checkpoint_moves_forward_per_receiver() {
         .apply_ack(&Ack {
             transfer_id: 11,
             receiver_id: "r1".to_string(),
+            receiver_epoch: 1,
             next_expected_chunk: 1,
+            sack_bitmap: Vec::new(),
         })
         .expect("stale ack ignored monotonic");
 
@@ -154,7 +244,9 @@ fn multi_receiver_completion_tracks_independently() {
         .apply_ack(&Ack {
             transfer_id: 77,
             receiver_id: "a".to_string(),
+            receiver_epoch: 1,
             next_expected_chunk: 2,
+            sack_bitmap: Vec::new(),
         })
         .expect("ack a done");
 
@@ -164,13 +256,166 @@ fn multi_receiver_completion_tracks_independently() {
         .apply_ack(&Ack {
             transfer_id: 77,
             receiver_id: "b".to_string(),
+            receiver_epoch: 1,
             next_expected_chunk: 2,
+            sack_bitmap: Vec::new(),
         })
         .expect("ack b done");
 
     assert!(session.all_complete());
 }
 
+#[test]
+fn upload_session_tracks_missing_chunks_and_withholds_them() {
+    let mut session =
+        TransferSession::for_upload(9, 10, 4, vec!["r1".to_string()]).expect("session");
+    assert_eq!(session.missing_chunks(), vec![0, 1, 2]);
+    assert_eq!(
+        session.chunk_for(1).expect_err("not uploaded"),
+        TransferError::ChunkNotUploaded
+    );
+
+    session.put_chunk(2, b"ij").expect("short last chunk");
+    session.put_chunk(0, b"abcd").expect("first chunk");
+    assert!(matches!(
+        session.put_chunk(1, b"efg"),
+        Err(TransferError::InvalidFrame(_))
+    ));
+    assert_eq!(
+        session.put_chunk(3, b"zz"),
+        Err(TransferError::ChunkOutOfRange)
+    );
+    assert_eq!(session.missing_chunks(), vec![1]);
+
+    session.put_chunk(1, b"efgh").expect("middle chunk");
+    assert!(session.missing_chunks().is_empty());
+    assert_eq!(session.chunk_for(1).expect("chunk").payload, b"efgh");
+    assert_eq!(session.chunk_for(2).expect("chunk").payload, b"ij");
+
+    // A retry with the same bytes is fine; different bytes never replace a chunk.
+    session.put_chunk(1, b"efgh").expect("retry");
+    assert_eq!(
+        session.put_chunk(1, b"EFGH"),
+        Err(TransferError::DuplicateChunk)
+    );
+    assert_eq!(session.chunk_for(1).expect("chunk").payload, b"efgh");
+
+    assert!(matches!(
+        TransferSession::for_upload(10, MAX_UPLOAD_BYTES + 1, 4, vec!["r1".to_string()]),
+        Err(TransferError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn selective_ack_reports_holes_for_retransmit_without_rewinding() {
+    let mut session =
+        TransferSession::new(3, vec![0u8; 40], 4, vec!["r1".to_string()]).expect("session");
+
+    // Receiver holds 0..3, 4, 6, 7 (lost 3 and 5).
+    let sack = SelectiveAck::from_received(3, "r1", 11, [0, 1, 2, 4, 6, 7]);
+    assert_eq!(sack.next_expected_chunk, 3);
+    assert_eq!(sack.received_up_to_exclusive, 8);
+    assert_eq!(sack.missing, vec![3, 5]);
+    let decoded = SelectiveAck::decode(&sack.encode().expect("encode")).expect("decode");
+    assert_eq!(decoded, sack);
+
+    session.apply_selective_ack(&decoded).expect("apply");
+    assert_eq!(session.resume_from_for_receiver("r1").expect("resume"), 3);
+    assert_eq!(
+        session.chunks_to_retransmit("r1").expect("holes"),
+        vec![3, 5]
+    );
+
+    // Filling chunk 3 moves the cumulative point past it; only 5 remains.
+    session
+        .apply_ack(&Ack {
+            transfer_id: 3,
+            receiver_id: "r1".into(),
+            receiver_epoch: 11,
+            next_expected_chunk: 5,
+            sack_bitmap: Vec::new(),
+        })
+        .expect("ack");
+    assert_eq!(session.chunks_to_retransmit("r1").expect("holes"), vec![5]);
+
+    let beyond = SelectiveAck::from_received(3, "r1", 11, [0, 1, 2, 3, 4, 10]);
+    assert_eq!(
+        session.apply_selective_ack(&beyond),
+        Err(TransferError::AckOutOfRange)
+    );
+    let stale = SelectiveAck::from_received(3, "r1", 12, [0, 1, 2, 3, 4, 6]);
+    assert_eq!(
+        session.apply_selective_ack(&stale),
+        Err(TransferError::StaleAck)
+    );
+    assert_eq!(
+        session.chunks_to_retransmit("nobody"),
+        Err(TransferError::UnknownReceiver)
+    );
+    assert!(SelectiveAck::decode(b"P2PSshort").is_err());
+}
+
+#[test]
+fn send_window_caps_in_flight_chunks_and_reopens_on_ack() {
+    let mut session =
+        TransferSession::new(4, vec![0u8; 40], 4, vec!["r1".to_string()]).expect("session");
+    session
+        .apply_ack(&Ack {
+            transfer_id: 4,
+            receiver_id: "r1".into(),
+            receiver_epoch: 1,
+            next_expected_chunk: 2,
+            sack_bitmap: Vec::new(),
+        })
+        .expect("ack");
+    let mut window = SendWindow::for_session(&session, ["r1".to_string()], 3).expect("window");
+
+    assert_eq!(
+        window.next_sendable_chunks("r1").expect("send"),
+        vec![2, 3, 4]
+    );
+    assert!(window.next_sendable_chunks("r1").expect("full").is_empty());
+    assert_eq!(window.in_flight("r1").expect("in flight"), 3);
+
+    let ack = |next| Ack {
+        transfer_id: 4,
+        receiver_id: "r1".into(),
+        receiver_epoch: 1,
+        next_expected_chunk: next,
+        sack_bitmap: Vec::new(),
+    };
+    window.on_ack(&ack(3)).expect("ack");
+    assert_eq!(window.next_sendable_chunks("r1").expect("send"), vec![5]);
+
+    // Chunk 4 was lost; 3 and 5 arrived. The hole goes out ahead of new data.
+    let sack = SelectiveAck::from_received(4, "r1", 1, [0, 1, 2, 3, 5]);
+    window.on_selective_ack(&sack).expect("sack");
+    assert_eq!(window.in_flight("r1").expect("in flight"), 0);
+    assert_eq!(
+        window.next_sendable_chunks("r1").expect("send"),
+        vec![4, 6, 7]
+    );
+
+    window.mark_lost("r1", 7).expect("lost");
+    window.on_ack(&ack(7)).expect("ack");
+    assert_eq!(
+        window.next_sendable_chunks("r1").expect("send"),
+        vec![7, 8, 9]
+    );
+    window.on_ack(&ack(10)).expect("ack");
+    assert!(window.is_complete("r1").expect("complete"));
+    assert!(window
+        .next_sendable_chunks("r1")
+        .expect("drained")
+        .is_empty());
+
+    assert_eq!(window.on_ack(&ack(11)), Err(TransferError::AckOutOfRange));
+    assert!(matches!(
+        SendWindow::new(10, 0, ["r1".to_string()]),
+        Err(TransferError::InvalidConfig(_))
+    ));
+}
+
 #[test]
 fn invalid_ack_out_of_range_fails() {
     let mut session = TransferSession::new(99, vec![1u8; 5], 2, ["r".to_string()]).expect("new");
@@ -178,8 +423,2514 @@ fn invalid_ack_out_of_range_fails() {
         .apply_ack(&Ack {
             transfer_id: 99,
             receiver_id: "r".to_string(),
+            receiver_epoch: 1,
             next_expected_chunk: 10,
+            sack_bitmap: Vec::new(),
         })
         .expect_err("should reject out-of-range ack");
     assert_eq!(err.to_string(), "ack next_expected_chunk out of range");
 }
+
+#[test]
+fn ack_from_stale_receiver_epoch_is_rejected() {
+    let mut session = TransferSession::new(12, vec![1u8; 12], 4, ["r".to_string()]).expect("new");
+    let old_epoch = new_receiver_epoch();
+    let new_epoch = old_epoch.wrapping_add(1);
+
+    session
+        .apply_ack(&Ack {
+            transfer_id: 12,
+            receiver_id: "r".to_string(),
+            receiver_epoch: old_epoch,
+            next_expected_chunk: 1,
+            sack_bitmap: Vec::new(),
+        })
+        .expect("first ack pins epoch");
+
+    // Receiver restarts and announces a new session token.
+    session.bind_receiver_epoch("r", new_epoch).expect("rebind");
+
+    let err = session
+        .apply_ack(&Ack {
+            transfer_id: 12,
+            receiver_id: "r".to_string(),
+            receiver_epoch: old_epoch,
+            next_expected_chunk: 3,
+            sack_bitmap: Vec::new(),
+        })
+        .expect_err("stale ack must be rejected");
+    assert_eq!(err, TransferError::StaleAck);
+    assert_eq!(
+        session.resume_from_for_receiver("r").expect("checkpoint"),
+        1
+    );
+
+    session
+        .apply_ack(&Ack {
+            transfer_id: 12,
+            receiver_id: "r".to_string(),
+            receiver_epoch: new_epoch,
+            next_expected_chunk: 2,
+            sack_bitmap: Vec::new(),
+        })
+        .expect("current epoch accepted");
+    assert_eq!(
+        session.resume_from_for_receiver("r").expect("checkpoint"),
+        2
+    );
+}
+
+#[test]
+fn transfer_id_registry_rejects_live_and_recent_ids() {
+    let mut registry = TransferIdRegistry::new(2);
+    let allocated = registry.allocate();
+    assert!(registry.is_active(allocated));
+    assert_ne!(allocated, 0);
+
+    registry.register(500).expect("fresh offer id");
+    assert_eq!(registry.register(500), Err(TransferError::TransferIdInUse));
+
+    registry.release(500);
+    assert_eq!(registry.register(500), Err(TransferError::TransferIdInUse));
+
+    // History is bounded; old ids become reusable again.
+    registry.register(501).expect("501");
+    registry.release(501);
+    registry.register(502).expect("502");
+    registry.release(502);
+    registry.register(500).expect("500 aged out of history");
+}
+
+#[test]
+fn collision_error_frame_tells_sender_to_reoffer() {
+    let frame = ControlFrame::Error(ErrorFrame {
+        transfer_id: 77,
+        code: TransferErrorCode::TransferIdInUse,
+    });
+    let decoded = ControlFrame::decode(&frame.encode()).expect("decode control");
+    assert_eq!(decoded, frame);
+
+    assert!(matches!(
+        decoded,
+        ControlFrame::Error(ErrorFrame { code, .. }) if code.requires_reoffer()
+    ));
+
+    let mut session = TransferSession::new(77, vec![1u8; 4], 4, ["r".to_string()]).expect("new");
+    let mut registry = TransferIdRegistry::default();
+    let fresh = registry.allocate();
+    session.reassign_transfer_id(fresh).expect("not started");
+    assert_eq!(session.transfer_id(), fresh);
+    assert_eq!(session.chunk_for(0).expect("chunk").transfer_id, fresh);
+}
+
+#[test]
+fn negotiated_session_params_appear_in_stats_blob() {
+    let mut session = TransferSession::new(12, vec![0u8; 10], 4, ["r".to_string()]).expect("new");
+    assert!(session.session_params().is_none());
+    assert!(!session.stats_blob().contains("session."));
+
+    session.set_session_params(SessionParams {
+        protocol_version: 2,
+        encryption_mode: "required".to_string(),
+        cipher: "chacha20poly1305".to_string(),
+        chunk_size: 4,
+        compression: CompressionPlan::ZstdDictionary(7),
+        route: "direct".to_string(),
+    });
+    let blob = session.stats_blob();
+    assert!(blob
+        .starts_with("transfer_id=12\ntotal_chunks=3\ntotal_bytes=10\nreceivers_complete=0/1\n"));
+    assert!(blob.ends_with(
+        "session.protocol_version=2\nsession.encryption_mode=required\nsession.cipher=chacha20poly1305\nsession.chunk_size=4\nsession.compression=zstd-dict:7\nsession.route=direct\n"
+    ));
+}
+
+#[test]
+fn offer_accepted_frame_carries_optional_free_space_bucket() {
+    let hint = FreeSpaceHint::from_free_bytes(3 * 1024 * 1024 * 1024);
+    let frame = ControlFrame::OfferAccepted {
+        transfer_id: 91,
+        free_space: Some(hint),
+    };
+    let encoded = frame.encode();
+    assert_eq!(ControlFrame::decode(&encoded).expect("decode"), frame);
+    assert_eq!(frame.transfer_id(), 91);
+
+    let bare = ControlFrame::OfferAccepted {
+        transfer_id: 91,
+        free_space: None,
+    };
+    assert_eq!(bare.encode().len(), encoded.len() - 1);
+    assert_eq!(ControlFrame::decode(&bare.encode()).expect("decode"), bare);
+
+    let mut bad = encoded;
+    *bad.last_mut().unwrap() = FreeSpaceHint::BUCKETS;
+    assert!(matches!(
+        ControlFrame::decode(&bad),
+        Err(TransferError::InvalidFrame(_))
+    ));
+}
+
+#[test]
+fn outbound_queue_applies_backpressure_and_records_stalls() {
+    let session = TransferSession::new(13, vec![9u8; 16], 4, ["r".to_string()]).expect("new");
+    let (tx, rx) = outbound_queue(2).expect("queue");
+    let mut cx = Context::from_waker(Waker::noop());
+
+    tx.try_reserve()
+        .expect("slot 0")
+        .send(session.chunk_for(0).expect("c0"));
+    let permit = tx.try_reserve().expect("slot 1");
+    assert_eq!(
+        tx.try_reserve().expect_err("full"),
+        TransferError::Backpressure
+    );
+    drop(permit);
+
+    let mut send = pin!(tx.send_chunk(session.chunk_for(1).expect("c1")));
+    assert!(send.as_mut().poll(&mut cx).is_ready());
+
+    let mut blocked = pin!(tx.send_chunk(session.chunk_for(2).expect("c2")));
+    assert!(blocked.as_mut().poll(&mut cx).is_pending());
+    assert_eq!(tx.stats().stalls, 1);
+
+    assert_eq!(rx.try_recv().expect("drain").chunk_index, 0);
+    assert!(matches!(
+        blocked.as_mut().poll(&mut cx),
+        Poll::Ready(Ok(()))
+    ));
+
+    let stats = rx.stats();
+    assert_eq!(stats.depth, 2);
+    assert_eq!(stats.max_depth, 2);
+    assert_eq!(stats.enqueued, 3);
+    assert_eq!(stats.dequeued, 1);
+}
+
+#[test]
+fn outbound_queue_reports_closed_transport() {
+    let (tx, rx) = outbound_queue(1).expect("queue");
+    drop(rx);
+    let mut cx = Context::from_waker(Waker::noop());
+    assert!(matches!(
+        tx.poll_ready(&mut cx),
+        Poll::Ready(Err(TransferError::QueueClosed))
+    ));
+}
+
+fn sample_dictionary() -> Vec<u8> {
+    br#"{"id":0,"name":"","email":"","created_at":"2024-01-01T00:00:00Z","tags":[]}"#.repeat(8)
+}
+
+#[test]
+fn dictionary_compression_negotiates_and_roundtrips() {
+    let mut sender_store = DictionaryStore::new();
+    sender_store.insert(7, sample_dictionary());
+    let mut receiver_store = DictionaryStore::new();
+    receiver_store.insert(7, sample_dictionary());
+
+    let advertised = CompressionCapabilities::local(&receiver_store);
+    let peer = CompressionCapabilities::decode(&advertised.encode()).expect("decode caps");
+    assert_eq!(peer, advertised);
+
+    let plan = select_compression(&sender_store, &peer, Some(7));
+    assert_eq!(plan, CompressionPlan::ZstdDictionary(7));
+
+    let payload = br#"{"id":42,"name":"ana","email":"ana@example.com","created_at":"2024-05-01T10:00:00Z","tags":["x"]}"#;
+    let compressed = compress_payload(plan, &sender_store, payload).expect("compress");
+    let restored =
+        decompress_payload(plan, &receiver_store, &compressed, payload.len()).expect("decompress");
+    assert_eq!(restored, payload);
+
+    assert!(decompress_payload(plan, &receiver_store, &compressed, 4).is_err());
+}
+
+#[test]
+fn dictionary_selection_falls_back_when_peer_lacks_matching_dictionary() {
+    let mut store = DictionaryStore::new();
+    store.insert(7, sample_dictionary());
+
+    let mut mismatched = DictionaryStore::new();
+    mismatched.insert(7, b"different-bytes".to_vec());
+    let peer = CompressionCapabilities::local(&mismatched);
+    assert_eq!(
+        select_compression(&store, &peer, Some(7)),
+        CompressionPlan::Zstd
+    );
+
+    let no_zstd = CompressionCapabilities::default();
+    assert_eq!(
+        select_compression(&store, &no_zstd, Some(7)),
+        CompressionPlan::None
+    );
+}
+
+#[test]
+fn transfer_tags_are_normalized_and_follow_session_through_scheduler() {
+    assert_eq!(
+        normalize_tags([" Backup", "project-X", "backup"]).expect("tags"),
+        vec!["backup".to_string(), "project-x".to_string()]
+    );
+    assert!(matches!(
+        normalize_tags(["a,b"]),
+        Err(TransferError::InvalidTag(_))
+    ));
+    assert!(matches!(
+        normalize_tags((0..9).map(|i| format!("t{i}"))),
+        Err(TransferError::InvalidTag(_))
+    ));
+
+    let mut tagged = TransferSession::new(1, vec![0u8; 8], 4, ["r".to_string()]).expect("session");
+    tagged.set_tags(["Backup"]).expect("tags");
+    assert!(tagged.has_tag("BACKUP"));
+    assert!(tagged.stats_blob().contains("tags=backup\n"));
+    let untagged = TransferSession::new(2, vec![0u8; 8], 4, ["r".to_string()]).expect("session");
+    assert!(!untagged.stats_blob().contains("tags="));
+
+    let mut scheduler = TransferScheduler::new(SchedulerConfig::default()).expect("scheduler");
+    scheduler.add_session(tagged).expect("add");
+    scheduler.add_session(untagged).expect("add");
+    assert_eq!(scheduler.transfer_ids_tagged("backup"), vec![1]);
+    assert!(scheduler.transfer_ids_tagged("project-x").is_empty());
+}
+
+#[test]
+fn small_transfers_use_fast_lane_without_starving_bulk() {
+    let mut scheduler = TransferScheduler::new(SchedulerConfig {
+        small_transfer_threshold: 16,
+        fast_lane_percent: 50,
+    })
+    .expect("scheduler");
+
+    let big = TransferSession::new(1, vec![0u8; 400], 4, ["r".to_string()]).expect("big");
+    assert_eq!(scheduler.add_session(big).expect("add"), Lane::Bulk);
+    for _ in 0..5 {
+        assert_eq!(scheduler.next_chunk().expect("bulk").transfer_id, 1);
+    }
+
+    let small = TransferSession::new(2, vec![1u8; 16], 4, ["r".to_string()]).expect("small");
+    assert_eq!(scheduler.add_session(small).expect("add"), Lane::Fast);
+
+    let mut order = Vec::new();
+    while let Some(chunk) = scheduler.next_chunk() {
+        order.push(chunk.transfer_id);
+    }
+
+    // The 4-chunk small transfer finishes within 8 picks even though 95 bulk chunks remain.
+    let last_small = order.iter().rposition(|id| *id == 2).expect("small sent");
+    assert!(
+        last_small < 8,
+        "small transfer finished at pick {last_small}"
+    );
+    let bulk_while_contended = order[..=last_small].iter().filter(|id| **id == 1).count();
+    assert!(bulk_while_contended >= 3);
+    assert_eq!(order.iter().filter(|id| **id == 1).count(), 95);
+    assert!(!scheduler.has_pending());
+}
+
+#[test]
+fn explicit_priority_overrides_size_and_can_change_mid_transfer() {
+    let mut scheduler = TransferScheduler::new(SchedulerConfig {
+        small_transfer_threshold: 16,
+        fast_lane_percent: 75,
+    })
+    .expect("scheduler");
+    let watched = TransferSession::new(1, vec![0u8; 400], 4, ["r".to_string()]).expect("big");
+    let sync = TransferSession::new(2, vec![1u8; 400], 4, ["r".to_string()]).expect("sync");
+    scheduler
+        .add_session_in_lane(watched, Lane::Fast)
+        .expect("add");
+    scheduler.add_session(sync).expect("add");
+    assert_eq!(scheduler.lane_of(1), Some(Lane::Fast));
+    assert!(scheduler
+        .add_session_in_lane(
+            TransferSession::new(1, vec![0u8; 4], 4, []).expect("dup"),
+            Lane::Bulk
+        )
+        .is_err());
+
+    let picks: Vec<u64> = (0..8)
+        .map(|_| scheduler.next_chunk().expect("chunk").transfer_id)
+        .collect();
+    assert_eq!(picks.iter().filter(|id| **id == 1).count(), 6);
+
+    // The user backgrounds transfer 1 and opens transfer 2.
+    assert!(scheduler.set_lane(1, Lane::Bulk));
+    assert!(scheduler.set_lane(2, Lane::Fast));
+    assert!(!scheduler.set_lane(99, Lane::Fast));
+    let picks: Vec<u64> = (0..8)
+        .map(|_| scheduler.next_chunk().expect("chunk").transfer_id)
+        .collect();
+    assert_eq!(picks.iter().filter(|id| **id == 2).count(), 6);
+
+    // An upload missing its next chunk is passed over, not the end of scheduling.
+    let mut upload = TransferSession::for_upload(3, 12, 4, ["r".to_string()]).expect("upload");
+    upload.put_chunk(0, &[3u8; 4]).expect("chunk 0");
+    scheduler
+        .add_session_in_lane(upload, Lane::Fast)
+        .expect("add");
+    let picks: Vec<(u64, u32)> = std::iter::from_fn(|| scheduler.next_chunk())
+        .map(|chunk| (chunk.transfer_id, chunk.chunk_index))
+        .collect();
+    assert!(picks.contains(&(3, 0)));
+    assert_eq!(picks.iter().filter(|(id, _)| *id != 3).count(), 200 - 16);
+    assert!(scheduler.has_pending());
+    scheduler
+        .session_mut(3)
+        .expect("still scheduled")
+        .put_chunk(1, &[3u8; 4])
+        .expect("chunk 1");
+    assert_eq!(
+        scheduler
+            .next_chunk()
+            .map(|chunk| (chunk.transfer_id, chunk.chunk_index)),
+        Some((3, 1))
+    );
+}
+
+#[test]
+fn scheduler_rejects_full_fast_lane_share() {
+    let err = TransferScheduler::new(SchedulerConfig {
+        small_transfer_threshold: 16,
+        fast_lane_percent: 100,
+    })
+    .expect_err("bulk would starve");
+    assert!(matches!(err, TransferError::InvalidConfig(_)));
+}
+
+#[test]
+fn fair_scheduler_shares_bandwidth_by_peer_weight_under_contention() {
+    let mut config = FairnessConfig {
+        quantum_bytes: 1000,
+        ..FairnessConfig::default()
+    };
+    config.apply_settings("theme = dark\npeer_weight.heavy = 2\npeer_weight.bad = 0\n");
+    assert_eq!(config.weight_for("heavy"), 2);
+    assert_eq!(config.weight_for("bad"), 1);
+
+    let mut scheduler = FairScheduler::new(config).expect("scheduler");
+    // The greedy peer queues four large transfers; the others one each.
+    for id in 1..=4u64 {
+        let session = TransferSession::new(id, vec![0u8; 200_000], 700, ["greedy".to_string()])
+            .expect("session");
+        scheduler.add_session("greedy", session).expect("add");
+    }
+    let quiet =
+        TransferSession::new(10, vec![1u8; 200_000], 500, ["quiet".to_string()]).expect("session");
+    scheduler.add_session("quiet", quiet).expect("add");
+    let heavy =
+        TransferSession::new(20, vec![2u8; 400_000], 900, ["heavy".to_string()]).expect("session");
+    scheduler.add_session("heavy", heavy).expect("add");
+    let dup = TransferSession::new(20, vec![2u8; 10], 900, ["x".to_string()]).expect("session");
+    assert!(matches!(
+        scheduler.add_session("x", dup),
+        Err(TransferError::TransferIdInUse)
+    ));
+
+    let mut sent: std::collections::HashMap<String, u64> = Default::default();
+    for _ in 0..600 {
+        let (peer, chunk) = scheduler.next_chunk().expect("contended");
+        *sent.entry(peer).or_default() += chunk.payload.len() as u64;
+    }
+    let greedy = sent["greedy"] as f64;
+    let quiet = sent["quiet"] as f64;
+    let heavy = sent["heavy"] as f64;
+    assert!(
+        (greedy / quiet - 1.0).abs() < 0.1,
+        "greedy {greedy} quiet {quiet}"
+    );
+    assert!(
+        (heavy / quiet - 2.0).abs() < 0.2,
+        "heavy {heavy} quiet {quiet}"
+    );
+    assert_eq!(scheduler.bytes_sent("quiet"), sent["quiet"]);
+
+    let mut remaining = 0u64;
+    while let Some((_, chunk)) = scheduler.next_chunk() {
+        remaining += chunk.payload.len() as u64;
+    }
+    assert_eq!(
+        sent.values().sum::<u64>() + remaining,
+        4 * 200_000 + 200_000 + 400_000
+    );
+    assert!(!scheduler.has_pending());
+
+    // A chunk not uploaded yet holds its session back without spinning or being skipped.
+    let mut upload =
+        TransferSession::for_upload(30, 300, 100, ["late".to_string()]).expect("upload");
+    upload.put_chunk(0, &[0u8; 100]).expect("chunk 0");
+    upload.put_chunk(2, &[2u8; 100]).expect("chunk 2");
+    scheduler.add_session("late", upload).expect("add upload");
+    let (_, first) = scheduler.next_chunk().expect("uploaded chunk");
+    assert_eq!(first.chunk_index, 0);
+    assert!(scheduler.next_chunk().is_none());
+    assert!(scheduler.has_pending());
+    let mut upload = scheduler.remove_session(30).expect("still queued");
+    upload.put_chunk(1, &[1u8; 100]).expect("chunk 1");
+    // Re-added, it resumes from its last acknowledged chunk.
+    scheduler.add_session("late", upload).expect("re-add");
+    let order: Vec<u32> = std::iter::from_fn(|| scheduler.next_chunk())
+        .map(|(_, chunk)| chunk.chunk_index)
+        .collect();
+    assert_eq!(order, [0, 1, 2]);
+}
+
+#[test]
+fn file_source_read_ahead_serves_sequential_chunks_from_cache() {
+    let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("source.bin");
+    std::fs::write(&path, &data).expect("write source");
+
+    let config = ReadAheadConfig {
+        prefetch_chunks: 3,
+        cache_chunks: 8,
+    };
+    let mut file = FileSource::open(&path, 1000, config).expect("open");
+    let mut memory = MemorySource::new(data, 1000).expect("memory");
+    assert_eq!(file.total_chunks(), 10);
+
+    for index in 0..file.total_chunks() {
+        assert_eq!(
+            file.read_chunk(index).expect("file chunk"),
+            memory.read_chunk(index).expect("memory chunk")
+        );
+    }
+
+    let stats = file.stats();
+    assert_eq!(stats.misses, 3);
+    assert_eq!(stats.hits, 7);
+    assert_eq!(stats.reads, 3);
+    assert!(file.read_chunk(10).is_err());
+}
+
+#[test]
+fn streaming_session_reads_chunks_lazily_from_disk() {
+    let data: Vec<u8> = (0..10_500u32).map(|i| (i % 253) as u8).collect();
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("big.bin");
+    std::fs::write(&path, &data).expect("write source");
+
+    let streaming =
+        TransferSession::open_file(7, &path, 1000, ["r1".to_string()]).expect("open session");
+    let in_memory = TransferSession::new(7, data, 1000, ["r1".to_string()]).expect("session");
+    assert_eq!(streaming.total_chunks(), 11);
+    assert_eq!(streaming.total_bytes(), 10_500);
+    for index in [0, 5, 10, 3] {
+        assert_eq!(
+            streaming.chunk_for(index).expect("stream chunk"),
+            in_memory.chunk_for(index).expect("memory chunk")
+        );
+    }
+    assert_eq!(
+        streaming.chunk_for(11).expect_err("past end"),
+        TransferError::ChunkOutOfRange
+    );
+
+    // Reads happen at chunk_for time, so a rewrite after opening is caught.
+    let source = FileSource::open(&path, 1000, ReadAheadConfig::disabled())
+        .expect("open")
+        .with_change_check_interval(1);
+    let watched = TransferSession::from_source(9, source, ["r1".to_string()]).expect("session");
+    let clone = watched.clone();
+    std::fs::write(&path, vec![0u8; 20]).expect("rewrite");
+    assert_eq!(
+        clone.chunk_for(2).expect_err("modified"),
+        TransferError::SourceModified
+    );
+
+    let empty = TransferSession::from_source(
+        8,
+        MemorySource::new(Vec::new(), 16).expect("memory"),
+        ["r1".to_string()],
+    )
+    .expect("empty session");
+    assert_eq!(empty.total_chunks(), 1);
+    assert!(empty.chunk_for(0).expect("empty chunk").payload.is_empty());
+}
+
+#[test]
+fn modified_source_fails_transfer_and_tells_receiver_to_restart() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("source.bin");
+    std::fs::write(&path, vec![3u8; 4000]).expect("write source");
+
+    let mut file = FileSource::open(&path, 1000, ReadAheadConfig::disabled())
+        .expect("open")
+        .with_change_check_interval(2);
+    assert_eq!(file.snapshot().expect("snapshot").len, 4000);
+    file.check_unchanged().expect("unchanged");
+    file.read_chunk(0).expect("first read is not checked");
+
+    std::fs::write(&path, vec![4u8; 4500]).expect("rewrite source");
+    let err = file.read_chunk(1).expect_err("second read re-checks");
+    assert_eq!(err, TransferError::SourceModified);
+    assert_eq!(file.check_unchanged(), Err(TransferError::SourceModified));
+
+    let code = err.wire_code().expect("reported to receiver");
+    let frame = ControlFrame::Error(ErrorFrame {
+        transfer_id: 5,
+        code,
+    });
+    match ControlFrame::decode(&frame.encode()).expect("decode") {
+        ControlFrame::Error(ErrorFrame { code, .. }) => {
+            assert_eq!(code, TransferErrorCode::SourceModified);
+            assert!(code.requires_reoffer());
+            assert!(code.discards_partial_data());
+        }
+        other => panic!("unexpected frame {other:?}"),
+    }
+
+    let snapshot = file.restart().expect("restart");
+    assert_eq!(snapshot.len, 4500);
+    assert_eq!(file.total_chunks(), 5);
+    assert_eq!(file.read_chunk(4).expect("tail"), vec![4u8; 500]);
+    file.check_unchanged().expect("fresh snapshot");
+
+    let mut memory = MemorySource::new(vec![1u8; 10], 4).expect("memory");
+    assert!(memory.check_unchanged().is_ok());
+}
+
+#[test]
+fn file_source_without_read_ahead_reads_each_chunk() {
+    let data = vec![1u8; 4096];
+    let len = data.len() as u64;
+    let mut source = FileSource::from_reader(
+        std::io::Cursor::new(data),
+        len,
+        1024,
+        ReadAheadConfig::disabled(),
+    )
+    .expect("source");
+
+    for index in [0, 1, 1, 3] {
+        assert_eq!(source.read_chunk(index).expect("chunk").len(), 1024);
+    }
+    assert_eq!(source.stats().reads, 4);
+    assert_eq!(source.stats().hits, 0);
+
+    let bad = ReadAheadConfig {
+        prefetch_chunks: 4,
+        cache_chunks: 4,
+    };
+    assert!(FileSource::from_reader(std::io::Cursor::new(vec![0u8; 8]), 8, 4, bad).is_err());
+}
+
+enum DuplexMsg {
+    Frame(Vec<u8>),
+    Ack(Ack),
+}
+
+fn run_duplex_peer(
+    mut session: DuplexSession,
+    outgoing: Vec<u8>,
+    tx: std::sync::mpsc::Sender<DuplexMsg>,
+    rx: std::sync::mpsc::Receiver<DuplexMsg>,
+) -> Vec<u8> {
+    let id = session.start_outgoing(outgoing, 16 * 1024).expect("start");
+    let mut received = None;
+    let mut incoming_id = None;
+
+    while received.is_none() || !session.outgoing_complete(id) {
+        if let Some(frame) = session.next_outgoing_frame().expect("frame") {
+            tx.send(DuplexMsg::Frame(frame)).expect("send frame");
+        }
+        while let Ok(msg) = rx.try_recv() {
+            match msg {
+                DuplexMsg::Frame(bytes) => {
+                    let ack = session.receive_frame(&bytes).expect("receive");
+                    incoming_id = Some(ack.transfer_id);
+                    // The peer may already be done and gone; late acks are harmless.
+                    let _ = tx.send(DuplexMsg::Ack(ack));
+                }
+                DuplexMsg::Ack(ack) => session.apply_ack(&ack).expect("ack"),
+            }
+        }
+        if received.is_none() {
+            received = incoming_id.and_then(|id| session.take_incoming(id));
+        }
+        std::thread::yield_now();
+    }
+    received.expect("incoming payload")
+}
+
+#[test]
+fn duplex_session_exchanges_large_files_both_ways_concurrently() {
+    // One key for both directions: only the role's nonce direction keeps frames apart.
+    let key = [9u8; 32];
+    let a = DuplexSession::new(SessionRole::Initiator, "alice", "bob", key, key);
+    let b = DuplexSession::new(SessionRole::Responder, "bob", "alice", key, key);
+
+    let a_data: Vec<u8> = (0..2_000_000u32).map(|i| (i % 253) as u8).collect();
+    let b_data: Vec<u8> = (0..1_500_000u32).map(|i| (i % 241) as u8).collect();
+
+    let (a_tx, b_rx) = std::sync::mpsc::channel();
+    let (b_tx, a_rx) = std::sync::mpsc::channel();
+    let a_out = a_data.clone();
+    let b_out = b_data.clone();
+    let alice = std::thread::spawn(move || run_duplex_peer(a, a_out, a_tx, a_rx));
+    let bob = std::thread::spawn(move || run_duplex_peer(b, b_out, b_tx, b_rx));
+
+    assert_eq!(alice.join().expect("alice"), b_data);
+    assert_eq!(bob.join().expect("bob"), a_data);
+}
+
+#[test]
+fn duplex_session_rejects_reflected_frames() {
+    let key = [3u8; 32];
+    let mut a = DuplexSession::new(SessionRole::Initiator, "alice", "bob", key, key);
+    a.start_outgoing(b"ping".to_vec(), 2).expect("start");
+
+    let frame = a.next_outgoing_frame().expect("frame").expect("some");
+    assert!(matches!(
+        a.receive_frame(&frame),
+        Err(TransferError::InvalidFrame(_))
+    ));
+}
+
+#[test]
+fn receive_session_reassembles_out_of_order_chunks_and_acks_the_prefix() {
+    let data: Vec<u8> = (0..10u8).collect();
+    let sender = TransferSession::new(11, data.clone(), 3, ["bob".to_string()]).expect("session");
+    let epoch = new_receiver_epoch();
+    let mut receiver = ReceiveSession::new(11, "bob", epoch);
+
+    for index in [0, 2, 3] {
+        let chunk = sender.chunk_for(index).expect("chunk");
+        assert_eq!(receiver.accept_chunk(chunk), Ok(ChunkReceipt::Stored));
+    }
+    let ack = receiver.ack();
+    assert_eq!(ack.next_expected_chunk, 1);
+    assert_eq!(ack.receiver_epoch, epoch);
+    assert_eq!(receiver.selective_ack().missing, vec![1]);
+    assert!(receiver.take_payload().is_none());
+
+    let duplicate = sender.chunk_for(2).expect("chunk");
+    assert_eq!(
+        receiver.accept_chunk(duplicate),
+        Ok(ChunkReceipt::Duplicate)
+    );
+    assert_eq!(receiver.duplicates(), 1);
+
+    receiver
+        .accept_chunk(sender.chunk_for(1).expect("chunk"))
+        .expect("accept");
+    assert_eq!(receiver.ack().next_expected_chunk, 4);
+    assert!(receiver.is_complete());
+    assert_eq!(receiver.take_payload(), Some(data));
+}
+
+#[test]
+fn receive_session_rejects_foreign_and_inconsistent_chunks() {
+    let mut receiver = ReceiveSession::new(5, "bob", 1);
+    let chunk = |transfer_id, chunk_index, total_chunks| TransferChunk {
+        transfer_id,
+        file_index: 0,
+        chunk_index,
+        total_chunks,
+        payload: vec![0],
+    };
+
+    assert_eq!(
+        receiver.accept_chunk(chunk(6, 0, 2)),
+        Err(TransferError::WrongTransfer)
+    );
+    // A rejected first chunk does not fix the chunk count for the ones after it.
+    assert_eq!(
+        receiver.accept_chunk(chunk(5, 7, 3)),
+        Err(TransferError::ChunkOutOfRange)
+    );
+    receiver.accept_chunk(chunk(5, 0, 2)).expect("accept");
+    assert!(matches!(
+        receiver.accept_chunk(chunk(5, 1, 3)),
+        Err(TransferError::InvalidFrame(_))
+    ));
+    assert_eq!(
+        receiver.accept_chunk(chunk(5, 2, 2)),
+        Err(TransferError::ChunkOutOfRange)
+    );
+}
+
+#[test]
+fn manifest_roundtrips_and_verifies_the_reassembled_file() {
+    let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+    let sender = TransferSession::new(21, data.clone(), 64, ["bob".to_string()]).expect("session");
+    let manifest = sender
+        .manifest("notes.txt", "text/plain")
+        .expect("manifest");
+    assert_eq!(manifest.total_chunks, 16);
+    assert_eq!(
+        manifest,
+        TransferManifest::for_payload(21, "notes.txt", "text/plain", &data, 64).expect("manifest")
+    );
+
+    let decoded = TransferManifest::decode(&manifest.encode().expect("encode")).expect("decode");
+    assert_eq!(decoded, manifest);
+
+    let mut receiver = ReceiveSession::from_manifest(&decoded, "bob", 1);
+    let mut foreign = sender.chunk_for(0).expect("chunk");
+    foreign.total_chunks = 17;
+    assert!(receiver.accept_chunk(foreign).is_err());
+    for index in 0..sender.total_chunks() {
+        receiver
+            .accept_chunk(sender.chunk_for(index).expect("chunk"))
+            .expect("accept");
+    }
+    let payload = receiver.take_payload().expect("complete");
+    decoded.verify_payload(&payload).expect("matches manifest");
+
+    let mut tampered = payload;
+    tampered[0] ^= 1;
+    assert!(matches!(
+        decoded.verify_payload(&tampered),
+        Err(TransferError::ManifestMismatch(_))
+    ));
+}
+
+#[test]
+fn manifest_rejects_inconsistent_fields_and_path_names() {
+    let mut manifest =
+        TransferManifest::for_payload(1, "a.bin", "application/octet-stream", &[0; 10], 4)
+            .expect("manifest");
+    assert_eq!(manifest.total_chunks, 3);
+
+    manifest.total_chunks = 2;
+    assert!(matches!(
+        manifest.encode(),
+        Err(TransferError::InvalidFrame(_))
+    ));
+
+    manifest.total_chunks = 3;
+    manifest.file_name = "../etc/passwd".to_string();
+    assert!(matches!(
+        manifest.validate(),
+        Err(TransferError::InvalidFrame(_))
+    ));
+}
+
+#[test]
+fn signed_manifest_detects_tampering_and_wrong_signer() {
+    let sender = identity::DeviceIdentity::generate();
+    let other = identity::DeviceIdentity::generate();
+    let manifest =
+        TransferManifest::for_payload(9, "photo.jpg", "image/jpeg", b"jpeg", 2).expect("manifest");
+    let signed = manifest.sign(&sender).expect("sign");
+
+    let decoded =
+        SignedTransferManifest::decode(&signed.encode().expect("encode")).expect("decode");
+    assert_eq!(
+        decoded.verify(&sender.public_key_b64()).expect("valid"),
+        &manifest
+    );
+    assert_eq!(
+        decoded.verify(&other.public_key_b64()),
+        Err(TransferError::ManifestSignatureInvalid)
+    );
+
+    let mut renamed = decoded;
+    renamed.manifest.file_name = "invoice.exe".to_string();
+    assert_eq!(
+        renamed.verify(&sender.public_key_b64()),
+        Err(TransferError::ManifestSignatureInvalid)
+    );
+}
+
+#[test]
+fn batch_session_sends_several_files_under_one_transfer_id() {
+    let files = vec![b"first photo".to_vec(), Vec::new(), vec![7u8; 10]];
+    let key = [4u8; 32];
+    let mut sender =
+        TransferSession::new_batch(31, files.clone(), 4, ["bob".to_string()]).expect("batch");
+    assert_eq!(sender.file_count(), 3);
+    assert_eq!(sender.total_chunks(), 3 + 1 + 3);
+    assert_eq!(sender.file_chunks(1), Ok(3..4));
+
+    let mut receiver = ReceiveSession::new(31, "bob", 5);
+    for index in (0..sender.total_chunks()).rev() {
+        let chunk = sender.chunk_for(index).expect("chunk");
+        let frame = encrypt_chunk_frame_with_digest(&chunk, &key, Direction::SenderToReceiver)
+            .expect("encrypt");
+        let decoded = TransferChunkV2::decode(&frame.encode()).expect("decode");
+        assert_eq!(decoded.file_index(), chunk.file_index);
+        let chunk = decrypt_chunk_frame(&decoded, &key).expect("decrypt");
+        receiver.accept_chunk(chunk).expect("accept");
+    }
+    assert_eq!(receiver.take_files(), Some(files));
+
+    sender
+        .apply_ack(&Ack {
+            transfer_id: 31,
+            receiver_id: "bob".to_string(),
+            receiver_epoch: 5,
+            next_expected_chunk: 4,
+            sack_bitmap: Vec::new(),
+        })
+        .expect("ack");
+    let progress = sender.progress_for("bob").expect("progress");
+    let done: Vec<bool> = progress.files.iter().map(|f| f.is_complete()).collect();
+    assert_eq!(done, vec![true, true, false]);
+    assert_eq!(progress.files[2].acked_chunks, 0);
+    assert!(sender.stats_blob().contains("files=3\n"));
+}
+
+#[test]
+fn batch_frames_keep_single_file_wire_format_and_reject_misplaced_files() {
+    let sender = TransferSession::new_batch(8, vec![vec![1; 3], vec![2; 3]], 4, []).expect("batch");
+    let first = sender.chunk_for(0).expect("chunk");
+    let second = sender.chunk_for(1).expect("chunk");
+    let file_index = |chunk| ChunkAad::parse(&transfer_chunk_aad(chunk)).map(|aad| aad.file_index);
+    assert_eq!(file_index(&first), Ok(0));
+    assert_eq!(file_index(&second), Ok(1));
+    assert!(first.encode().is_ok());
+    assert!(matches!(
+        second.encode(),
+        Err(TransferError::InvalidFrame(_))
+    ));
+
+    let mut receiver = ReceiveSession::new(8, "bob", 1);
+    let mut misplaced = first.clone();
+    misplaced.file_index = 1;
+    assert!(matches!(
+        receiver.accept_chunk(misplaced),
+        Err(TransferError::InvalidFrame(_))
+    ));
+    receiver.accept_chunk(second).expect("accept");
+    receiver.accept_chunk(first).expect("accept");
+    assert_eq!(receiver.take_files(), Some(vec![vec![1; 3], vec![2; 3]]));
+}
+
+#[test]
+fn compressed_frames_roundtrip_and_skip_incompressible_chunks() {
+    let key = [6u8; 32];
+    let log: Vec<u8> = b"INFO transfer progressing nicely\n".repeat(200);
+    let noise: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+
+    for codec in [CompressionCodec::Zstd, CompressionCodec::Lz4] {
+        let chunk = TransferChunk {
+            transfer_id: 12,
+            file_index: 0,
+            chunk_index: 0,
+            total_chunks: 2,
+            payload: log.clone(),
+        };
+        let frame =
+            encrypt_chunk_frame_compressed(&chunk, &key, Direction::SenderToReceiver, codec)
+                .expect("encrypt");
+        assert_eq!(frame.compression(), Ok(codec));
+        assert!(frame.payload.len() < log.len() / 4);
+        let decoded = TransferChunkV2::decode(&frame.encode()).expect("decode");
+        assert_eq!(decrypt_chunk_frame(&decoded, &key).expect("decrypt"), chunk);
+
+        let noisy = TransferChunk {
+            chunk_index: 1,
+            payload: noise.clone(),
+            ..chunk
+        };
+        let frame =
+            encrypt_chunk_frame_compressed(&noisy, &key, Direction::SenderToReceiver, codec)
+                .expect("encrypt");
+        assert_eq!(frame.compression(), Ok(CompressionCodec::None));
+        assert_eq!(decrypt_chunk_frame(&frame, &key).expect("decrypt"), noisy);
+    }
+}
+
+#[test]
+fn codec_id_is_covered_by_the_tag_and_limited_to_the_negotiated_one() {
+    let key = [6u8; 32];
+    let chunk = TransferChunk {
+        transfer_id: 12,
+        file_index: 0,
+        chunk_index: 0,
+        total_chunks: 1,
+        payload: vec![b'a'; 1024],
+    };
+    let frame = encrypt_chunk_frame_compressed(
+        &chunk,
+        &key,
+        Direction::SenderToReceiver,
+        CompressionCodec::Lz4,
+    )
+    .expect("encrypt");
+    // The codec sits in the AAD, so rewriting it breaks the tag before anything is
+    // decompressed.
+    let mut flipped = frame.clone();
+    // Codec id 1 is zstd, in the high nibble of the AAD flags byte.
+    flipped.aad[3] = (flipped.aad[3] & 0x0f) | 1 << 4;
+    assert_eq!(flipped.compression(), Ok(CompressionCodec::Zstd));
+    assert!(matches!(
+        decrypt_chunk_frame(&flipped, &key),
+        Err(TransferError::Crypto(_))
+    ));
+    // The cleartext flags byte no longer carries a codec at all.
+    let mut bytes = frame.encode();
+    bytes[5] |= 1 << 4;
+    assert!(TransferChunkV2::decode(&bytes).is_err());
+
+    // A duplex peer only decompresses with the codec the handshake settled on.
+    let mut sender = DuplexSession::new(SessionRole::Initiator, "a", "b", key, key);
+    let mut receiver = DuplexSession::new(SessionRole::Responder, "b", "a", key, key);
+    sender.set_compression(CompressionCodec::Lz4);
+    sender
+        .start_outgoing(vec![b'a'; 1024], 1024)
+        .expect("start");
+    let bytes = sender
+        .next_outgoing_frame()
+        .expect("encode")
+        .expect("frame");
+    assert_eq!(
+        receiver.receive_frame(&bytes),
+        Err(TransferError::Compression("codec was not negotiated"))
+    );
+    receiver.set_compression(CompressionCodec::Lz4);
+    assert!(receiver.receive_frame(&bytes).is_ok());
+
+    // Likewise only the negotiated cipher suite.
+    sender.set_cipher_suite(CipherSuite::Aes256Gcm);
+    sender.start_outgoing(vec![b'b'; 64], 64).expect("start");
+    let bytes = sender
+        .next_outgoing_frame()
+        .expect("encode")
+        .expect("frame");
+    assert_eq!(
+        receiver.receive_frame(&bytes),
+        Err(TransferError::Crypto("cipher suite was not negotiated"))
+    );
+    receiver.set_cipher_suite(CipherSuite::Aes256Gcm);
+    assert!(receiver.receive_frame(&bytes).is_ok());
+}
+
+/// Hands out at most one byte per read, like a congested socket.
+struct Trickle(std::io::Cursor<Vec<u8>>);
+
+impl std::io::Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(1);
+        self.0.read(&mut buf[..n])
+    }
+}
+
+#[test]
+fn frame_reader_reassembles_partial_reads_and_resyncs_past_garbage() {
+    let frames = [b"first".to_vec(), Vec::new(), vec![9u8; 3000]];
+    let mut stream = b"junkP2P".to_vec();
+    let first = encode_stream_frame(&frames[0]).expect("frame");
+    // The stream header must not share a magic with the datagram frames it carries.
+    assert_eq!(&first[..4], b"P2PT");
+    stream.extend(first);
+    // A fake header whose length check fails must not swallow the next frame.
+    stream.extend(b"P2PT\0\0\0\x05\0\0\0\0");
+    stream.extend(encode_stream_frame(&frames[1]).expect("frame"));
+    stream.extend(b"\xff\xfe");
+    stream.extend(encode_stream_frame(&frames[2]).expect("frame"));
+
+    let mut reader = FrameReader::new(Trickle(std::io::Cursor::new(stream)));
+    for expected in &frames {
+        assert_eq!(reader.read_frame().expect("read").as_ref(), Some(expected));
+    }
+    assert_eq!(reader.read_frame().expect("eof"), None);
+    assert_eq!(reader.skipped_bytes(), 7 + 12 + 2);
+
+    let mut truncated = encode_stream_frame(b"cut short").expect("frame");
+    truncated.truncate(15);
+    let mut reader = FrameReader::new(truncated.as_slice());
+    assert!(matches!(
+        reader.read_frame(),
+        Err(TransferError::InvalidFrame(_))
+    ));
+
+    let mut decoder = FrameDecoder::new(4);
+    decoder.push(&encode_stream_frame(b"too long").expect("frame"));
+    decoder.push(&encode_stream_frame(b"ok").expect("frame"));
+    assert_eq!(decoder.next_frame(), Some(b"ok".to_vec()));
+}
+
+#[test]
+fn chunk_frames_cross_a_tcp_stream_intact() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("addr");
+    let session =
+        TransferSession::new(3, vec![5u8; 100_000], 8192, ["bob".to_string()]).expect("session");
+    let total = session.total_chunks();
+
+    let sender = std::thread::spawn(move || {
+        let stream = std::net::TcpStream::connect(addr).expect("connect");
+        let mut writer = FrameWriter::new(std::io::BufWriter::new(stream));
+        for index in 0..total {
+            let chunk = session.chunk_for(index).expect("chunk");
+            let frame = plaintext_chunk_frame(&chunk, true).encode();
+            writer.write_frame(&frame).expect("write");
+        }
+        writer.flush().expect("flush");
+    });
+
+    let (stream, _) = listener.accept().expect("accept");
+    let mut reader = FrameReader::new(stream);
+    let mut receiver = ReceiveSession::new(3, "bob", 1);
+    while let Some(bytes) = reader.read_frame().expect("read") {
+        let frame = TransferChunkV2::decode(&bytes).expect("decode");
+        receiver
+            .accept_chunk(TransferChunk {
+                transfer_id: frame.transfer_id,
+                file_index: frame.file_index(),
+                chunk_index: frame.chunk_index,
+                total_chunks: frame.total_chunks,
+                payload: frame.payload,
+            })
+            .expect("accept");
+    }
+    sender.join().expect("sender");
+    assert_eq!(receiver.take_payload(), Some(vec![5u8; 100_000]));
+}
+
+#[test]
+fn rate_limiter_enforces_transfer_and_global_budgets() {
+    let start = std::time::Instant::now();
+    let ms = std::time::Duration::from_millis;
+    let global = RateLimit::new(10_000, 4_000).expect("limit");
+    let mut limiter = RateLimiter::new(Some(global), start);
+    limiter.set_transfer_limit(1, Some(RateLimit::new(1_000, 1_000).expect("limit")), start);
+
+    // Transfer 1 spends its own burst; transfer 2 only answers to the global budget.
+    assert_eq!(limiter.try_acquire(1, 1_000, start), Ok(()));
+    assert_eq!(limiter.try_acquire(1, 500, start), Err(ms(500)));
+    assert_eq!(limiter.try_acquire(2, 3_000, start), Ok(()));
+    assert_eq!(limiter.try_acquire(2, 1_000, start), Err(ms(100)));
+
+    // A refused chunk takes nothing, so the retry after the wait succeeds.
+    assert_eq!(limiter.try_acquire(1, 500, start + ms(500)), Ok(()));
+
+    // A chunk bigger than the burst still goes out once the bucket is full; the debt
+    // delays the next one.
+    limiter.set_transfer_limit(1, None, start);
+    limiter.set_global_limit(Some(global), start);
+    assert_eq!(limiter.try_acquire(1, 8_000, start), Ok(()));
+    assert_eq!(limiter.try_acquire(1, 1_000, start + ms(100)), Err(ms(400)));
+
+    assert!(RateLimit::new(0, 1).is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn protocol_types_roundtrip_through_json() {
+    let chunk = TransferChunk {
+        transfer_id: 7,
+        file_index: 1,
+        chunk_index: 2,
+        total_chunks: 3,
+        payload: b"abc".to_vec(),
+    };
+    let json = serde_json::to_string(&chunk).expect("serialize");
+    assert_eq!(
+        serde_json::from_str::<TransferChunk>(&json).expect("parse"),
+        chunk
+    );
+
+    let frame = encrypt_chunk_frame(&chunk, &[1u8; 32]).expect("encrypt");
+    let json = serde_json::to_string(&frame).expect("serialize");
+    assert_eq!(
+        serde_json::from_str::<TransferChunkV2>(&json).expect("parse"),
+        frame
+    );
+
+    let session = TransferSession::new(7, vec![0; 10], 4, ["bob".to_string()]).expect("session");
+    let progress = session.progress_for("bob").expect("progress");
+    let json = serde_json::to_string(&progress).expect("serialize");
+    assert!(json.contains("\"files\":[{\"file_index\":0"));
+    assert_eq!(
+        serde_json::from_str::<transfer::ReceiverProgress>(&json).expect("parse"),
+        progress
+    );
+
+    for err in [
+        TransferError::InvalidFrame("bad v2 header"),
+        TransferError::StaleAck,
+        TransferError::Io("disk full".to_string()),
+    ] {
+        let json = serde_json::to_string(&err).expect("serialize");
+        assert_eq!(
+            serde_json::from_str::<TransferError>(&json).expect("parse"),
+            err
+        );
+    }
+    let ack: Ack = serde_json::from_str(
+        r#"{"transfer_id":7,"receiver_id":"bob","receiver_epoch":1,"next_expected_chunk":2}"#,
+    )
+    .expect("parse ack");
+    assert_eq!(ack.next_expected_chunk, 2);
+}
+
+#[test]
+fn cancel_and_abort_frames_roundtrip_with_reason() {
+    for frame in [
+        ControlFrame::Cancel {
+            transfer_id: 77,
+            reason: CancelReason::UserRequested,
+        },
+        ControlFrame::Abort {
+            transfer_id: 77,
+            reason: CancelReason::InsufficientSpace,
+        },
+    ] {
+        let decoded = ControlFrame::decode(&frame.encode()).expect("decode");
+        assert_eq!(decoded, frame);
+        assert_eq!(decoded.transfer_id(), 77);
+    }
+    assert_eq!(
+        ControlFrame::OfferSeen { transfer_id: 77 }.termination(),
+        None
+    );
+
+    let mut bad = ControlFrame::Cancel {
+        transfer_id: 77,
+        reason: CancelReason::Timeout,
+    }
+    .encode();
+    *bad.last_mut().expect("reason byte") = 0xee;
+    assert_eq!(
+        ControlFrame::decode(&bad),
+        Err(TransferError::InvalidFrame("unknown cancel reason"))
+    );
+    bad.pop();
+    assert_eq!(
+        ControlFrame::decode(&bad),
+        Err(TransferError::InvalidFrame("invalid control body length"))
+    );
+}
+
+#[test]
+fn sender_cancel_crosses_the_wire_and_stops_both_sides() {
+    let mut sender =
+        TransferSession::new(78, vec![5; 30], 10, ["bob".to_string()]).expect("session");
+    let mut receiver = ReceiveSession::new(78, "bob", 1);
+    receiver
+        .accept_chunk(sender.chunk_for(0).expect("chunk"))
+        .expect("accept");
+
+    let wire = sender.cancel(CancelReason::UserRequested).encode();
+    let cancelled = Termination::Cancelled(CancelReason::UserRequested);
+    assert_eq!(sender.termination(), Some(cancelled));
+    assert_eq!(
+        sender.chunk_for(1),
+        Err(TransferError::Terminated(cancelled))
+    );
+
+    let frame = ControlFrame::decode(&wire).expect("decode");
+    assert_eq!(receiver.apply_termination(&frame), Ok(cancelled));
+    assert_eq!(receiver.received_chunks(), 0);
+    assert!(!receiver.is_complete());
+    assert_eq!(receiver.take_payload(), None);
+    let late = TransferChunk {
+        transfer_id: 78,
+        file_index: 0,
+        chunk_index: 1,
+        total_chunks: 3,
+        payload: vec![5; 10],
+    };
+    assert_eq!(
+        receiver.accept_chunk(late),
+        Err(TransferError::Terminated(cancelled))
+    );
+    assert_eq!(
+        TransferError::Terminated(cancelled).to_string(),
+        "transfer cancelled: user requested"
+    );
+}
+
+#[test]
+fn receiver_abort_stops_sender_and_first_termination_sticks() {
+    let mut sender =
+        TransferSession::new(79, vec![1; 20], 10, ["bob".to_string()]).expect("session");
+    let mut receiver = ReceiveSession::new(79, "bob", 1);
+
+    let frame = receiver.abort(CancelReason::InsufficientSpace);
+    let aborted = Termination::Aborted(CancelReason::InsufficientSpace);
+    assert_eq!(sender.apply_termination(&frame), Ok(aborted));
+    assert_eq!(
+        sender.apply_ack(&Ack {
+            transfer_id: 79,
+            receiver_id: "bob".to_string(),
+            receiver_epoch: 1,
+            next_expected_chunk: 1,
+            sack_bitmap: Vec::new(),
+        }),
+        Err(TransferError::Terminated(aborted))
+    );
+
+    // A later local cancel does not rewrite how the transfer ended.
+    assert_eq!(sender.cancel(CancelReason::UserRequested), frame);
+    assert_eq!(sender.termination(), Some(aborted));
+
+    let other = ControlFrame::Cancel {
+        transfer_id: 80,
+        reason: CancelReason::Shutdown,
+    };
+    assert_eq!(
+        sender.apply_termination(&other),
+        Err(TransferError::WrongTransfer)
+    );
+    assert_eq!(
+        sender.apply_termination(&ControlFrame::OfferSeen { transfer_id: 79 }),
+        Err(TransferError::InvalidFrame("not a cancel or abort frame"))
+    );
+}
+
+#[test]
+fn v3_frames_carry_extensions_and_decode_alongside_v1_v2() {
+    let chunk = TransferChunk {
+        transfer_id: 90,
+        file_index: 2,
+        chunk_index: 4,
+        total_chunks: 9,
+        payload: b"v3 payload".to_vec(),
+    };
+    let frame = plaintext_chunk_frame_v3(
+        &chunk,
+        [
+            FrameExtension::TimestampMs(1_700_000_000_000),
+            FrameExtension::Padding(7),
+            FrameExtension::Unknown {
+                kind: 0x40,
+                value: vec![1, 2, 3],
+            },
+        ],
+    );
+    let encoded = frame.encode().expect("encode v3");
+    let VersionedTransferChunk::V3(decoded) =
+        VersionedTransferChunk::decode(&encoded).expect("decode v3")
+    else {
+        panic!("expected v3 frame");
+    };
+    assert_eq!(decoded, frame);
+    assert_eq!(decoded.file_index(), 2);
+    assert_eq!(decoded.timestamp_ms(), Some(1_700_000_000_000));
+    assert_eq!(decoded.compression(), CompressionCodec::None);
+    assert!(decoded.checksum().is_some());
+
+    let mut corrupted = encoded.clone();
+    *corrupted.last_mut().expect("payload") ^= 1;
+    assert_eq!(
+        TransferChunkV3::decode(&corrupted),
+        Err(TransferError::ChunkDigestMismatch)
+    );
+
+    let mut critical = frame.clone();
+    critical.extensions.push(FrameExtension::Unknown {
+        kind: 0x90,
+        value: Vec::new(),
+    });
+    assert_eq!(
+        TransferChunkV3::decode(&critical.encode().expect("encode")),
+        Err(TransferError::InvalidFrame("unknown critical extension"))
+    );
+
+    let mut duplicated = frame;
+    duplicated.extensions.push(FrameExtension::TimestampMs(1));
+    assert_eq!(
+        duplicated.encode(),
+        Err(TransferError::InvalidFrame("duplicate frame extension"))
+    );
+}
+
+#[test]
+fn encrypted_v3_frames_authenticate_their_extensions() {
+    let key = [9u8; 32];
+    let chunk = TransferChunk {
+        transfer_id: 91,
+        file_index: 0,
+        chunk_index: 0,
+        total_chunks: 1,
+        payload: vec![b'a'; 4096],
+    };
+    let frame = encrypt_chunk_frame_v3(
+        &chunk,
+        &key,
+        Direction::SenderToReceiver,
+        CompressionCodec::Zstd,
+        [FrameExtension::TimestampMs(5)],
+    )
+    .expect("encrypt");
+    // Extensions travel sealed; nothing but the header shows.
+    assert!(frame.extensions.is_empty());
+    assert!(frame.payload.len() < chunk.payload.len());
+    let padded = encrypt_chunk_frame_v3(
+        &chunk,
+        &key,
+        Direction::SenderToReceiver,
+        CompressionCodec::Zstd,
+        [FrameExtension::TimestampMs(5), FrameExtension::Padding(64)],
+    )
+    .expect("encrypt");
+    assert!(padded.extensions.is_empty());
+    assert_eq!(padded.payload.len(), frame.payload.len() + 3 + 64);
+
+    let wire = TransferChunkV3::decode(&frame.encode().expect("encode")).expect("decode");
+    assert_eq!(
+        decrypt_chunk_frame_v3(
+            &wire,
+            &key,
+            Direction::SenderToReceiver,
+            CipherSuite::ChaCha20Poly1305
+        )
+        .expect("decrypt"),
+        chunk
+    );
+    assert!(matches!(
+        decrypt_chunk_frame_v3(
+            &wire,
+            &key,
+            Direction::ReceiverToSender,
+            CipherSuite::ChaCha20Poly1305
+        ),
+        Err(TransferError::InvalidFrame(_))
+    ));
+
+    let mut injected = wire.clone();
+    injected.extensions.push(FrameExtension::FileIndex(3));
+    assert!(matches!(
+        TransferChunkV3::decode(&injected.encode().expect("encode")),
+        Err(TransferError::InvalidFrame(_))
+    ));
+    assert!(matches!(
+        decrypt_chunk_frame_v3(
+            &injected,
+            &key,
+            Direction::SenderToReceiver,
+            CipherSuite::ChaCha20Poly1305
+        ),
+        Err(TransferError::InvalidFrame(_))
+    ));
+
+    let mut tampered = wire;
+    tampered.total_chunks = 2;
+    assert_eq!(
+        decrypt_chunk_frame_v3(
+            &tampered,
+            &key,
+            Direction::SenderToReceiver,
+            CipherSuite::ChaCha20Poly1305
+        ),
+        Err(TransferError::Crypto("failed to decrypt chunk payload"))
+    );
+}
+
+#[test]
+fn chunks_encode_into_caller_buffers_without_changing_the_wire() {
+    let chunk = TransferChunk {
+        transfer_id: 92,
+        file_index: 0,
+        chunk_index: 1,
+        total_chunks: 3,
+        payload: b"reuse me".to_vec(),
+    };
+    let v1 = chunk.encode().expect("encode v1");
+    let mut buf = [0u8; 64];
+    let len = chunk.encode_into(&mut buf).expect("encode_into");
+    assert_eq!(len, chunk.encoded_len());
+    assert_eq!(&buf[..len], &v1[..]);
+    assert_eq!(
+        chunk.encode_into(&mut buf[..len - 1]),
+        Err(TransferError::InvalidFrame("buffer too small for frame"))
+    );
+
+    let frame = encrypt_chunk_frame_with_digest(&chunk, &[3u8; 32], Direction::SenderToReceiver)
+        .expect("encrypt");
+    let mut out = b"prefix".to_vec();
+    frame.encode_to(&mut out).expect("encode_to");
+    assert_eq!(&out[6..], &frame.encode()[..]);
+    assert_eq!(out.len() - 6, frame.encoded_len());
+}
+
+#[test]
+fn borrowed_decode_points_into_the_input_frame() {
+    let chunk = TransferChunk {
+        transfer_id: 93,
+        file_index: 0,
+        chunk_index: 0,
+        total_chunks: 1,
+        payload: b"in place".to_vec(),
+    };
+    let v1 = chunk.encode().expect("encode v1");
+    let borrowed = TransferChunkRef::decode(&v1).expect("decode v1");
+    assert_eq!(borrowed.payload, b"in place");
+    assert!(v1.as_ptr_range().contains(&borrowed.payload.as_ptr()));
+    assert_eq!(borrowed.into_owned(), chunk);
+
+    let key = [4u8; 32];
+    let wire = encrypt_chunk_frame_with_digest(&chunk, &key, Direction::SenderToReceiver)
+        .expect("encrypt")
+        .encode();
+    let frame = TransferChunkV2Ref::decode(&wire).expect("decode v2");
+    assert!(wire.as_ptr_range().contains(&frame.payload.as_ptr()));
+    assert!(frame.chunk_digest().is_some());
+    assert_eq!(
+        frame.into_owned(),
+        TransferChunkV2::decode(&wire).expect("owned decode")
+    );
+    assert_eq!(
+        decrypt_chunk_frame_ref(
+            &frame,
+            &key,
+            Direction::SenderToReceiver,
+            CipherSuite::ChaCha20Poly1305
+        )
+        .expect("decrypt"),
+        chunk
+    );
+
+    let mut corrupted = plaintext_chunk_frame(&chunk, true).encode();
+    *corrupted.last_mut().expect("payload") ^= 1;
+    assert_eq!(
+        TransferChunkV2Ref::decode(&corrupted),
+        Err(TransferError::ChunkDigestMismatch)
+    );
+}
+
+#[test]
+fn fanout_encrypts_each_chunk_under_its_receivers_key() {
+    let mut session = TransferSession::new(
+        94,
+        b"shared with two peers".to_vec(),
+        8,
+        ["bob".to_string(), "carol".to_string()],
+    )
+    .expect("session");
+    let (bob_key, carol_key) = ([0xb0u8; 32], [0xc0u8; 32]);
+    session.set_receiver_key("bob", bob_key).expect("bob key");
+    assert_eq!(
+        session.encrypted_fanout(0),
+        Err(TransferError::MissingSessionKey)
+    );
+    assert_eq!(
+        session.set_receiver_key("carol", bob_key),
+        Err(TransferError::InvalidConfig(
+            "session key already used by another receiver"
+        ))
+    );
+    session
+        .set_receiver_key("carol", carol_key)
+        .expect("carol key");
+    assert_eq!(
+        session.set_receiver_key("mallory", [1; 32]),
+        Err(TransferError::UnknownReceiver)
+    );
+
+    let frames = session.encrypted_fanout(1).expect("fanout");
+    let ids: Vec<&str> = frames.iter().map(|(id, _)| id.as_str()).collect();
+    assert_eq!(ids, ["bob", "carol"]);
+    assert_ne!(frames[0].1.payload, frames[1].1.payload);
+
+    let expected = session.chunk_for(1).expect("chunk");
+    for ((_, frame), key) in frames.iter().zip([bob_key, carol_key]) {
+        let wire = TransferChunkV2::decode(&frame.encode()).expect("decode");
+        assert_eq!(decrypt_chunk_frame(&wire, &key).expect("decrypt"), expected);
+    }
+    assert!(decrypt_chunk_frame(&frames[0].1, &carol_key).is_err());
+    assert_eq!(
+        session.encrypted_chunk_for("carol", 1).expect("single"),
+        frames[1].1
+    );
+    let run = session
+        .encrypted_chunks_for("carol", 0..session.total_chunks())
+        .expect("run");
+    assert_eq!(run.len() as u32, session.total_chunks());
+    assert_eq!(run[1], frames[1].1);
+    for (index, frame) in (0..).zip(&run) {
+        assert_eq!(
+            *frame,
+            session.encrypted_chunk_for("carol", index).expect("single")
+        );
+    }
+    assert_eq!(
+        session.encrypted_chunks_for("carol", 0..session.total_chunks() + 1),
+        Err(TransferError::ChunkOutOfRange)
+    );
+    assert!(!format!("{session:?}").contains("192, 192"));
+
+    // After a rekey the same chunk goes out under a nonce of the new epoch, which the
+    // receiver still matches against the frame header.
+    assert_eq!(session.nonce_epoch(), 0);
+    assert_eq!(session.rekey_nonces(), Ok(1));
+    let rekeyed = session.encrypted_chunk_for("carol", 1).expect("rekeyed");
+    assert_ne!(rekeyed.nonce, frames[1].1.nonce);
+    assert_eq!(rekeyed.nonce[11] >> 2, 1);
+    assert_eq!(
+        decrypt_chunk_frame(&rekeyed, &carol_key)
+            .expect("decrypt")
+            .payload,
+        decrypt_chunk_frame(&frames[1].1, &carol_key)
+            .expect("decrypt")
+            .payload
+    );
+    let mut forged = rekeyed.clone();
+    forged.nonce[11] = 2 << 2 | 0x01;
+    assert_eq!(
+        decrypt_chunk_frame(&forged, &carol_key),
+        Err(TransferError::Crypto("failed to decrypt chunk payload"))
+    );
+    forged.nonce[0] ^= 1;
+    assert_eq!(
+        decrypt_chunk_frame(&forged, &carol_key),
+        Err(TransferError::InvalidFrame(
+            "nonce does not match frame header"
+        ))
+    );
+
+    assert!(session.remove_receiver_key("bob"));
+    assert_eq!(
+        session.encrypted_chunk_for("bob", 1),
+        Err(TransferError::MissingSessionKey)
+    );
+}
+
+#[test]
+fn snapshot_restores_every_receivers_progress() {
+    let data: Vec<u8> = (0..50u8).collect();
+    let mut session = TransferSession::new(
+        95,
+        data.clone(),
+        10,
+        ["bob".to_string(), "carol, jr".to_string()],
+    )
+    .expect("session");
+    session.set_tags(["photos"]).expect("tags");
+    session.bind_receiver_epoch("bob", 7).expect("bind");
+    session
+        .apply_ack(&Ack {
+            transfer_id: 95,
+            receiver_id: "bob".to_string(),
+            receiver_epoch: 7,
+            next_expected_chunk: 3,
+            sack_bitmap: Vec::new(),
+        })
+        .expect("ack");
+
+    let text = session
+        .snapshot()
+        .expect("snapshot")
+        .encode()
+        .expect("encode");
+    let snapshot = TransferSnapshot::decode(&text).expect("decode");
+    assert_eq!(snapshot.receivers[1].receiver_id, "carol, jr");
+
+    let mut restored = TransferSession::restore_with_data(&snapshot, data).expect("restore");
+    assert_eq!(restored.resume_from_for_receiver("bob"), Ok(3));
+    assert_eq!(restored.resume_from_for_receiver("carol, jr"), Ok(0));
+    assert!(restored.has_tag("photos"));
+    assert_eq!(restored.stats_blob(), session.stats_blob());
+    assert_eq!(
+        restored.apply_ack(&Ack {
+            transfer_id: 95,
+            receiver_id: "bob".to_string(),
+            receiver_epoch: 1,
+            next_expected_chunk: 4,
+            sack_bitmap: Vec::new(),
+        }),
+        Err(TransferError::StaleAck)
+    );
+
+    assert_eq!(
+        TransferSession::restore(&snapshot).map(|_| ()),
+        Err(TransferError::InvalidConfig(
+            "snapshot has no source file; restore it with its data"
+        ))
+    );
+    assert_eq!(
+        TransferSession::restore_with_data(&snapshot, vec![0; 49]).map(|_| ()),
+        Err(TransferError::InvalidConfig(
+            "data does not match the snapshot"
+        ))
+    );
+    // Lengths that only add up to the data by wrapping around are refused.
+    let mut wrapping = snapshot.clone();
+    wrapping.file_lens = vec![u64::MAX, 50];
+    assert_eq!(
+        TransferSession::restore_with_data(&wrapping, vec![0; 49]).map(|_| ()),
+        Err(TransferError::InvalidConfig(
+            "data does not match the snapshot"
+        ))
+    );
+    assert_eq!(
+        TransferSnapshot::decode("p2p-transfer-snapshot/9\n"),
+        Err(TransferError::InvalidFrame("unknown snapshot version"))
+    );
+}
+
+#[test]
+fn file_backed_snapshot_reopens_the_file_and_detects_changes() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("video.bin");
+    std::fs::write(&path, vec![7u8; 100]).expect("write");
+
+    let mut session = TransferSession::open_file(96, &path, 32, ["bob".to_string()]).expect("open");
+    session
+        .apply_ack(&Ack {
+            transfer_id: 96,
+            receiver_id: "bob".to_string(),
+            receiver_epoch: 1,
+            next_expected_chunk: 2,
+            sack_bitmap: Vec::new(),
+        })
+        .expect("ack");
+    let text = session
+        .snapshot()
+        .expect("snapshot")
+        .encode()
+        .expect("encode");
+    drop(session);
+
+    let snapshot = TransferSnapshot::decode(&text).expect("decode");
+    let restored = TransferSession::restore(&snapshot).expect("restore");
+    assert_eq!(restored.resume_from_for_receiver("bob"), Ok(2));
+    assert_eq!(restored.chunk_for(3).expect("chunk").payload, vec![7u8; 4]);
+
+    std::fs::write(&path, vec![8u8; 120]).expect("rewrite");
+    assert_eq!(
+        TransferSession::restore(&snapshot).map(|_| ()),
+        Err(TransferError::SourceModified)
+    );
+}
+
+#[test]
+fn ack_bitmap_limits_retransmission_to_real_holes() {
+    let mut sender =
+        TransferSession::new(97, vec![3; 80], 10, ["bob".to_string()]).expect("session");
+    let mut receiver = ReceiveSession::new(97, "bob", 1);
+    for index in [0, 2, 3, 5] {
+        receiver
+            .accept_chunk(sender.chunk_for(index).expect("chunk"))
+            .expect("accept");
+    }
+
+    let ack = receiver.ack();
+    assert_eq!(ack.next_expected_chunk, 1);
+    assert_eq!(ack.sack_bitmap, vec![0b10110]);
+    assert_eq!(ack.sacked_chunks().collect::<Vec<_>>(), [2, 3, 5]);
+    // A selective ack for the same state carries the very same bitmap.
+    let sack = receiver.selective_ack().encode().expect("encode");
+    assert!(sack.ends_with(&ack.sack_bitmap));
+    sender.apply_ack(&ack).expect("apply");
+    assert_eq!(sender.chunks_to_retransmit("bob"), Ok(vec![1, 4]));
+
+    receiver
+        .accept_chunk(sender.chunk_for(1).expect("chunk"))
+        .expect("accept");
+    sender.apply_ack(&receiver.ack()).expect("apply");
+    assert_eq!(sender.resume_from_for_receiver("bob"), Ok(4));
+    assert_eq!(sender.chunks_to_retransmit("bob"), Ok(vec![4]));
+
+    let mut beyond = receiver.ack();
+    beyond.sack_bitmap = vec![0, 0b100];
+    assert_eq!(sender.apply_ack(&beyond), Err(TransferError::AckOutOfRange));
+    beyond.sack_bitmap = vec![0; (MAX_SACK_SPAN / 8) as usize + 1];
+    assert_eq!(
+        sender.apply_ack(&beyond),
+        Err(TransferError::InvalidFrame("sack span too large"))
+    );
+}
+
+#[derive(Default)]
+struct RecordingObserver {
+    events: std::sync::Mutex<Vec<String>>,
+}
+
+impl RecordingObserver {
+    fn record(&self, event: String) {
+        self.events.lock().expect("events").push(event);
+    }
+}
+
+impl TransferObserver for RecordingObserver {
+    fn on_chunk_sent(&self, transfer_id: u64, chunk_index: u32) {
+        self.record(format!("sent {transfer_id}/{chunk_index}"));
+    }
+
+    fn on_ack(&self, _transfer_id: u64, progress: &transfer::ReceiverProgress) {
+        self.record(format!(
+            "ack {} {}",
+            progress.receiver_id, progress.acked_up_to_exclusive
+        ));
+    }
+
+    fn on_receiver_complete(&self, _transfer_id: u64, receiver_id: &str) {
+        self.record(format!("done {receiver_id}"));
+    }
+
+    fn on_all_complete(&self, transfer_id: u64) {
+        self.record(format!("all done {transfer_id}"));
+    }
+}
+
+#[test]
+fn observers_hear_sends_acks_and_completion_once() {
+    let observer = std::sync::Arc::new(RecordingObserver::default());
+    let mut session = TransferSession::new(
+        98,
+        vec![1; 20],
+        10,
+        ["bob".to_string(), "carol".to_string()],
+    )
+    .expect("session");
+    session.add_observer(observer.clone());
+
+    let mut scheduler = TransferScheduler::new(SchedulerConfig::default()).expect("scheduler");
+    scheduler.add_session(session).expect("add");
+    while scheduler.next_chunk().is_some() {}
+
+    let session = scheduler.session_mut(98).expect("session");
+    for (receiver, next) in [("bob", 2), ("carol", 1), ("bob", 2), ("carol", 2)] {
+        session
+            .apply_ack(&Ack {
+                transfer_id: 98,
+                receiver_id: receiver.to_string(),
+                receiver_epoch: 1,
+                next_expected_chunk: next,
+                sack_bitmap: Vec::new(),
+            })
+            .expect("ack");
+    }
+
+    assert_eq!(
+        *observer.events.lock().expect("events"),
+        [
+            "sent 98/0",
+            "sent 98/1",
+            "ack bob 2",
+            "done bob",
+            "ack carol 1",
+            "ack bob 2",
+            "ack carol 2",
+            "done carol",
+            "all done 98",
+        ]
+    );
+}
+
+#[test]
+fn completion_receipt_proves_the_receiver_holds_the_file() {
+    let receiver_identity = identity::DeviceIdentity::generate();
+    let data = b"signed delivery".to_vec();
+    let sender = TransferSession::new(99, data.clone(), 4, ["bob".to_string()]).expect("session");
+    let manifest =
+        TransferManifest::for_payload(99, "note.txt", "text/plain", &data, 4).expect("manifest");
+
+    let mut receiver = ReceiveSession::from_manifest(&manifest, "bob", 1);
+    assert_eq!(
+        receiver.completion_receipt(&receiver_identity),
+        Err(TransferError::InvalidConfig("transfer is not complete"))
+    );
+    for index in 0..sender.total_chunks() {
+        receiver
+            .accept_chunk(sender.chunk_for(index).expect("chunk"))
+            .expect("accept");
+    }
+    let receipt = receiver
+        .completion_receipt(&receiver_identity)
+        .expect("receipt");
+    assert_eq!(receiver.take_payload(), Some(data));
+
+    let wire = CompletionReceipt::decode(&receipt.encode()).expect("decode");
+    let receiver_key = receiver_identity.public_key_b64();
+    assert_eq!(wire.verify(&receiver_key, &manifest), Ok(()));
+    assert_eq!(
+        wire.verify(
+            &identity::DeviceIdentity::generate().public_key_b64(),
+            &manifest
+        ),
+        Err(TransferError::ReceiptInvalid("bad signature"))
+    );
+
+    let other =
+        TransferManifest::for_payload(99, "note.txt", "text/plain", b"other", 4).expect("manifest");
+    assert_eq!(
+        wire.verify(&receiver_key, &other),
+        Err(TransferError::ReceiptInvalid(
+            "received file differs from manifest"
+        ))
+    );
+    let mut forged = wire;
+    forged.file_sha256 = other.file_sha256;
+    assert_eq!(
+        forged.verify(&receiver_key, &other),
+        Err(TransferError::ReceiptInvalid("bad signature"))
+    );
+}
+
+#[test]
+fn silent_receivers_are_reported_stalled_until_they_check_in() {
+    let timeout = std::time::Duration::from_secs(30);
+    let mut sender = TransferSession::new(
+        98,
+        vec![4; 20],
+        10,
+        ["alice".to_string(), "bob".to_string()],
+    )
+    .expect("session");
+    let alice = ReceiveSession::new(98, "alice", 2);
+    let mut bob = ReceiveSession::new(98, "bob", 2);
+
+    let heartbeat = alice.heartbeat();
+    assert_eq!(
+        ControlFrame::decode(&heartbeat.encode()),
+        Ok(heartbeat.clone())
+    );
+    let start = sender.last_activity("alice").expect("known receiver");
+    assert!(!sender.is_stalled("alice", timeout, start).expect("stalled"));
+    assert_eq!(
+        sender.stalled_receivers(timeout, start + timeout * 2),
+        ["alice", "bob"]
+    );
+
+    sender
+        .apply_heartbeat("alice", &heartbeat)
+        .expect("heartbeat");
+    let later = sender.last_activity("alice").expect("known receiver");
+    assert!(later >= start);
+    assert!(!sender
+        .is_stalled("alice", timeout, later + timeout)
+        .expect("stalled"));
+    assert!(sender
+        .is_stalled("alice", timeout, later + timeout * 2)
+        .expect("stalled"));
+
+    for index in 0..2 {
+        bob.accept_chunk(sender.chunk_for(index).expect("chunk"))
+            .expect("accept");
+    }
+    sender.apply_ack(&bob.ack()).expect("apply");
+    let far = sender.last_activity("bob").expect("known receiver") + timeout * 10;
+    assert!(!sender.is_stalled("bob", timeout, far).expect("stalled"));
+    assert_eq!(sender.stalled_receivers(timeout, far), ["alice"]);
+
+    assert_eq!(
+        sender.apply_heartbeat("carol", &heartbeat),
+        Err(TransferError::UnknownReceiver)
+    );
+    assert_eq!(
+        sender.apply_heartbeat("alice", &ControlFrame::Heartbeat { transfer_id: 99 }),
+        Err(TransferError::WrongTransfer)
+    );
+    assert_eq!(
+        sender.is_stalled("carol", timeout, far),
+        Err(TransferError::UnknownReceiver)
+    );
+}
+
+#[test]
+fn adaptive_chunker_grows_on_fast_acks_and_backs_off_on_loss() {
+    let ms = std::time::Duration::from_millis;
+    let config = AdaptiveChunkConfig {
+        min_chunk_size: 4,
+        max_chunk_size: 32,
+        target_rtt: ms(50),
+    };
+    let mut session = TransferSession::new_adaptive(120, vec![1; 100], config, ["bob".to_string()])
+        .expect("session");
+    assert_eq!(session.total_chunks(), 25);
+    assert_eq!(session.effective_chunk_size(), 4);
+    assert_eq!(session.chunks_per_send(), 1);
+
+    for expected in [8, 16, 32, 32] {
+        session.record_ack_rtt(ms(10));
+        assert_eq!(session.effective_chunk_size(), expected);
+    }
+    assert_eq!(session.chunks_per_send(), 8);
+    session.record_loss();
+    assert_eq!(session.effective_chunk_size(), 16);
+
+    let mut chunker = AdaptiveChunker::new(config).expect("chunker");
+    chunker.on_ack(ms(10));
+    chunker.on_ack(ms(10));
+    assert_eq!(chunker.chunk_size(), 16);
+    // One slow ack is smoothed away; a run of them shrinks the size.
+    chunker.on_ack(ms(300));
+    assert_eq!(chunker.chunk_size(), 16);
+    for _ in 0..8 {
+        chunker.on_ack(ms(300));
+    }
+    assert_eq!(chunker.chunk_size(), 4);
+
+    let mut fixed = TransferSession::new(121, vec![1; 100], 10, ["bob".to_string()]).expect("s");
+    fixed.record_ack_rtt(ms(1));
+    assert_eq!(fixed.effective_chunk_size(), 10);
+    assert_eq!(fixed.chunks_per_send(), 1);
+
+    assert_eq!(
+        AdaptiveChunker::new(AdaptiveChunkConfig {
+            max_chunk_size: 2,
+            ..config
+        })
+        .err(),
+        Some(TransferError::InvalidConfig(
+            "max chunk size below min chunk size"
+        ))
+    );
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_driver_runs_an_encrypted_transfer_end_to_end() {
+    let config = DriverConfig {
+        session_key: Some([9; 32]),
+        window_chunks: 4,
+        ..DriverConfig::default()
+    };
+    let data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+    let mut session =
+        TransferSession::new(130, data.clone(), 256, ["bob".to_string()]).expect("session");
+    let (mut sender_end, mut receiver_end) = tokio::io::duplex(4096);
+
+    let (sent, received) = tokio::join!(
+        send_transfer(&mut sender_end, &mut session, "bob", &config),
+        receive_transfer(&mut receiver_end, "bob", &config),
+    );
+    sent.expect("send");
+    let mut received = received.expect("receive");
+    assert!(session.all_complete());
+    assert_eq!(received.transfer_id(), 130);
+    assert_eq!(received.take_payload(), Some(data));
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_driver_aborts_on_a_silent_receiver_and_honours_cancel() {
+    let config = DriverConfig {
+        ack_timeout: std::time::Duration::from_millis(20),
+        max_retries: 2,
+        ..DriverConfig::default()
+    };
+    let mut session =
+        TransferSession::new(131, vec![1; 100], 10, ["bob".to_string()]).expect("session");
+    let (mut sender_end, _silent) = tokio::io::duplex(1 << 16);
+    assert_eq!(
+        send_transfer(&mut sender_end, &mut session, "bob", &config).await,
+        Err(TransferError::Terminated(Termination::Aborted(
+            CancelReason::Timeout
+        )))
+    );
+
+    let mut session =
+        TransferSession::new(132, vec![1; 100], 10, ["bob".to_string()]).expect("session");
+    let (mut sender_end, mut receiver_end) = tokio::io::duplex(1 << 16);
+    let cancel = ControlFrame::Cancel {
+        transfer_id: 132,
+        reason: CancelReason::Declined,
+    };
+    tokio::io::AsyncWriteExt::write_all(
+        &mut receiver_end,
+        &encode_stream_frame(&cancel.encode()).expect("frame"),
+    )
+    .await
+    .expect("write");
+    assert_eq!(
+        send_transfer(&mut sender_end, &mut session, "bob", &config).await,
+        Err(TransferError::Terminated(Termination::Cancelled(
+            CancelReason::Declined
+        )))
+    );
+    assert_eq!(
+        session.termination(),
+        Some(Termination::Cancelled(CancelReason::Declined))
+    );
+}
+
+#[test]
+fn replayed_frames_are_refused_with_duplicate_chunk() {
+    let key = [4u8; 32];
+    let mut a = DuplexSession::new(SessionRole::Initiator, "alice", "bob", key, key);
+    let mut b = DuplexSession::new(SessionRole::Responder, "bob", "alice", key, key);
+    let transfer_id = a.start_outgoing(b"abcdef".to_vec(), 2).expect("start");
+
+    let first = a.next_outgoing_frame().expect("frame").expect("some");
+    assert_eq!(
+        b.receive_frame(&first).expect("accept").next_expected_chunk,
+        1
+    );
+    assert_eq!(b.receive_frame(&first), Err(TransferError::DuplicateChunk));
+    while let Some(frame) = a.next_outgoing_frame().expect("frame") {
+        b.receive_frame(&frame).expect("accept");
+    }
+    assert_eq!(b.receive_frame(&first), Err(TransferError::DuplicateChunk));
+    assert_eq!(b.take_incoming(transfer_id), Some(b"abcdef".to_vec()));
+
+    let sender = TransferSession::new(140, vec![5; 6], 2, ["bob".to_string()]).expect("session");
+    let mut receiver = ReceiveSession::new(140, "bob", 1);
+    let frame = encrypt_chunk_frame(&sender.chunk_for(0).expect("chunk"), &key).expect("frame");
+    let chunk = decrypt_chunk_frame(&frame, &key).expect("decrypt");
+    assert_eq!(
+        receiver.accept_frame_chunk(frame.nonce, chunk.clone()),
+        Ok(ChunkReceipt::Stored)
+    );
+    assert_eq!(
+        receiver.check_replay(0, &frame.nonce),
+        Err(TransferError::DuplicateChunk)
+    );
+    assert_eq!(
+        receiver.accept_frame_chunk(frame.nonce, chunk.clone()),
+        Err(TransferError::DuplicateChunk)
+    );
+    // Without a frame nonce the chunk is still only counted as a duplicate.
+    assert_eq!(receiver.accept_chunk(chunk), Ok(ChunkReceipt::Duplicate));
+    assert_eq!(receiver.received_chunks(), 1);
+}
+
+#[test]
+fn receiver_replies_under_the_same_transfer_and_key() {
+    let key = [6u8; 32];
+    let mut sender =
+        TransferSession::new(150, vec![1; 8], 4, ["bob".to_string()]).expect("session");
+    sender.set_receiver_key("bob", key).expect("key");
+    let mut receiver = ReceiveSession::new(150, "bob", 1);
+    for index in 0..2 {
+        let frame = sender.encrypted_chunk_for("bob", index).expect("frame");
+        receiver
+            .accept_encrypted_frame(
+                &TransferChunkV2Ref::decode(&frame.encode()).expect("decode"),
+                &key,
+            )
+            .expect("accept");
+    }
+    assert!(receiver.is_complete());
+
+    let thumbnail = b"tiny preview".to_vec();
+    let mut reply = receiver
+        .reply("alice", thumbnail.clone(), 5)
+        .expect("reply");
+    reply.set_receiver_key("alice", key).expect("key");
+    assert_eq!(reply.transfer_id(), 150);
+    assert_eq!(reply.direction(), Direction::ReceiverToSender);
+
+    let mut back = sender.reply_receiver("alice", 2);
+    let forward = sender
+        .encrypted_chunk_for("bob", 0)
+        .expect("frame")
+        .encode();
+    // A forward frame reflected back does not pass as part of the reply.
+    assert!(matches!(
+        back.accept_encrypted_frame(&TransferChunkV2Ref::decode(&forward).expect("decode"), &key),
+        Err(TransferError::InvalidFrame(_))
+    ));
+    for index in 0..reply.total_chunks() {
+        let frame = reply
+            .encrypted_chunk_for("alice", index)
+            .expect("frame")
+            .encode();
+        assert_eq!(
+            back.accept_encrypted_frame(&TransferChunkV2Ref::decode(&frame).expect("decode"), &key),
+            Ok(ChunkReceipt::Stored)
+        );
+    }
+    reply.apply_ack(&back.ack()).expect("ack");
+    assert!(reply.all_complete());
+    assert_eq!(back.take_payload(), Some(thumbnail.clone()));
+
+    // Over a duplex session the reply is an outgoing transfer reusing the incoming id.
+    let mut alice = DuplexSession::new(SessionRole::Initiator, "alice", "bob", [1; 32], [2; 32]);
+    let mut bob = DuplexSession::new(SessionRole::Responder, "bob", "alice", [2; 32], [1; 32]);
+    let id = alice.start_outgoing(vec![3; 8], 4).expect("start");
+    while let Some(frame) = alice.next_outgoing_frame().expect("frame") {
+        alice
+            .apply_ack(&bob.receive_frame(&frame).expect("receive"))
+            .expect("ack");
+    }
+    assert_eq!(bob.take_incoming(id), Some(vec![3; 8]));
+    bob.reply(id, thumbnail.clone(), 5).expect("reply");
+    assert_eq!(
+        bob.reply(id, Vec::new(), 5),
+        Err(TransferError::TransferIdInUse)
+    );
+    while let Some(frame) = bob.next_outgoing_frame().expect("frame") {
+        bob.apply_ack(&alice.receive_frame(&frame).expect("receive"))
+            .expect("ack");
+    }
+    assert!(bob.outgoing_complete(id));
+    assert_eq!(alice.take_incoming(id), Some(thumbnail));
+}
+
+#[test]
+fn streamed_chunks_are_sealed_in_place_like_the_copying_path() {
+    let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    let source = MemorySource::new(data.clone(), 4_096).expect("source");
+    let mut session =
+        TransferSession::from_source(160, source, ["bob".to_string()]).expect("session");
+    let key = [0x5au8; 32];
+    session.set_receiver_key("bob", key).expect("key");
+
+    for index in 0..session.total_chunks() {
+        let chunk = session.chunk_for(index).expect("chunk");
+        let copied = encrypt_chunk_frame_with_digest(&chunk, &key, Direction::SenderToReceiver)
+            .expect("copying");
+        let frame = session.encrypted_chunk_for("bob", index).expect("in place");
+        assert_eq!(frame, copied);
+        assert_eq!(
+            encrypt_chunk_frame_in_place(chunk.clone(), &key, Direction::SenderToReceiver)
+                .expect("in place"),
+            copied
+        );
+        assert_eq!(decrypt_chunk_frame(&frame, &key).expect("decrypt"), chunk);
+    }
+}
+
+#[test]
+fn frames_name_their_cipher_suite_and_decrypt_with_it() {
+    let key = [0x5au8; 32];
+    let chunk = TransferChunk {
+        transfer_id: 141,
+        file_index: 0,
+        chunk_index: 1,
+        total_chunks: 3,
+        payload: b"sealed with the negotiated suite".to_vec(),
+    };
+    let dir = Direction::SenderToReceiver;
+
+    let aes = encrypt_chunk_frame_with_suite(chunk.clone(), &key, dir, CipherSuite::Aes256Gcm)
+        .expect("aes frame");
+    let chacha = encrypt_chunk_frame_in_place(chunk.clone(), &key, dir).expect("chacha frame");
+    assert_eq!(chacha.cipher_suite, CipherSuite::ChaCha20Poly1305);
+    assert_ne!(aes.payload, chacha.payload);
+    let wire = aes.encode();
+    assert_eq!(wire[5] & 0x0f, CipherSuite::Aes256Gcm.as_u8());
+    let decoded = TransferChunkV2::decode(&wire).expect("decode");
+    assert_eq!(decoded.cipher_suite, CipherSuite::Aes256Gcm);
+    assert_eq!(
+        decrypt_chunk_frame_directional(&decoded, &key, dir, CipherSuite::Aes256Gcm)
+            .expect("decrypt"),
+        chunk
+    );
+    // Only the negotiated suite is accepted, whatever the frame names.
+    assert_eq!(
+        decrypt_chunk_frame(&decoded, &key),
+        Err(TransferError::Crypto("cipher suite was not negotiated"))
+    );
+    // Encrypted frames from before suites were named carry 1, which is ChaCha20-Poly1305.
+    assert_eq!(chacha.encode()[5] & 0x0f, 1);
+
+    let mut unknown = wire.clone();
+    unknown[5] = unknown[5] & 0xf0 | 0x0f;
+    assert_eq!(
+        TransferChunkV2::decode(&unknown),
+        Err(TransferError::InvalidFrame("unsupported cipher suite"))
+    );
+    // Relabelling the suite does not make the payload open under the other cipher.
+    let mut relabelled = decoded.clone();
+    relabelled.cipher_suite = CipherSuite::ChaCha20Poly1305;
+    assert_eq!(
+        decrypt_chunk_frame(&relabelled, &key),
+        Err(TransferError::Crypto("failed to decrypt chunk payload"))
+    );
+
+    let v3 = encrypt_chunk_frame_v3_with_suite(
+        &chunk,
+        &key,
+        dir,
+        CipherSuite::Aes256Gcm,
+        CompressionCodec::None,
+        [],
+    )
+    .expect("v3 frame");
+    let v3 = TransferChunkV3::decode(&v3.encode().expect("encode")).expect("decode v3");
+    assert_eq!(v3.cipher_suite, CipherSuite::Aes256Gcm);
+    assert_eq!(
+        decrypt_chunk_frame_v3(&v3, &key, dir, CipherSuite::Aes256Gcm).expect("decrypt v3"),
+        chunk
+    );
+    assert_eq!(
+        decrypt_chunk_frame_v3(&v3, &key, dir, CipherSuite::ChaCha20Poly1305),
+        Err(TransferError::Crypto("cipher suite was not negotiated"))
+    );
+    let mut relabelled = v3;
+    relabelled.cipher_suite = CipherSuite::ChaCha20Poly1305;
+    assert!(decrypt_chunk_frame_v3(&relabelled, &key, dir, CipherSuite::ChaCha20Poly1305).is_err());
+
+    let mut session = TransferSession::new(
+        141,
+        b"two receivers, two suites".to_vec(),
+        8,
+        ["bob".to_string(), "carol".to_string()],
+    )
+    .expect("session");
+    session
+        .set_receiver_key("bob", [0xb0; 32])
+        .expect("bob key");
+    session
+        .set_receiver_key_with_suite(
+            "carol",
+            [0xc0; 32],
+            CipherSuite::Aes256Gcm,
+            PaddingScheme::Bucket,
+        )
+        .expect("carol key");
+    let frames = session.encrypted_fanout(0).expect("fanout");
+    assert_eq!(frames[0].1.cipher_suite, CipherSuite::ChaCha20Poly1305);
+    assert_eq!(frames[1].1.cipher_suite, CipherSuite::Aes256Gcm);
+    // Carol's handshake settled on padding, so her frames hide the chunk size.
+    assert_eq!(
+        frames[1].1.payload.len(),
+        crypto_envelope::MIN_PADDING_BUCKET + crypto_envelope::TAG_LEN
+    );
+    assert_eq!(
+        session
+            .encrypted_chunk_for("carol", 0)
+            .expect("carol frame"),
+        frames[1].1
+    );
+    assert_eq!(
+        decrypt_chunk_frame_directional(&frames[1].1, &[0xc0; 32], dir, CipherSuite::Aes256Gcm)
+            .expect("carol decrypts"),
+        session.chunk_for(0).expect("chunk")
+    );
+}
+
+#[test]
+fn chunk_indices_past_the_nonce_space_are_not_sealed() {
+    let key = [0x24u8; 32];
+    let chunk = |chunk_index| TransferChunk {
+        transfer_id: 142,
+        file_index: 0,
+        chunk_index,
+        total_chunks: 1 << 25,
+        payload: b"far chunk".to_vec(),
+    };
+    let last = chunk((1 << 24) - 1);
+    let frame = encrypt_chunk_frame(&last, &key).expect("last index that fits");
+    assert_eq!(decrypt_chunk_frame(&frame, &key).expect("decrypt"), last);
+
+    let overflow = TransferError::Crypto("chunk index exceeds the nonce space");
+    assert_eq!(
+        encrypt_chunk_frame(&chunk(1 << 24), &key),
+        Err(overflow.clone())
+    );
+    assert_eq!(
+        encrypt_chunk_frame_v3(
+            &chunk(1 << 24),
+            &key,
+            Direction::SenderToReceiver,
+            CompressionCodec::None,
+            [],
+        ),
+        Err(overflow.clone())
+    );
+    // A frame relabelled with an index that wraps onto the same nonce is refused too.
+    let mut wrapped = frame;
+    wrapped.chunk_index += 1 << 24;
+    assert_eq!(decrypt_chunk_frame(&wrapped, &key), Err(overflow));
+}
+
+#[test]
+fn structured_aad_binds_the_header_and_old_layouts_still_open() {
+    let key = [0x3cu8; 32];
+    let chunk = TransferChunk {
+        transfer_id: 143,
+        file_index: 2,
+        chunk_index: 4,
+        total_chunks: 9,
+        payload: b"header bound into the tag".to_vec(),
+    };
+    let frame = encrypt_chunk_frame(&chunk, &key).expect("frame");
+    let aad = ChunkAad::parse(&frame.aad).expect("structured aad");
+    assert_eq!(
+        aad,
+        *AadBuilder::new(2, 143, 4, 9).with_file_index(2).fields()
+    );
+    assert_eq!(decrypt_chunk_frame(&frame, &key).expect("decrypt"), chunk);
+
+    // The AAD is authenticated even without a digest, and must repeat the header.
+    let mut forged = frame.clone();
+    forged.aad = AadBuilder::new(2, 143, 4, 9).build();
+    assert!(matches!(
+        decrypt_chunk_frame(&forged, &key),
+        Err(TransferError::Crypto(_))
+    ));
+    let mut relabelled = frame;
+    relabelled.total_chunks = 10;
+    assert_eq!(
+        decrypt_chunk_frame(&relabelled, &key),
+        Err(TransferError::InvalidFrame(
+            "aad does not match frame header"
+        ))
+    );
+
+    // Frames sealed with the bare header layout keep opening.
+    let nonce = crypto_envelope::derive_nonce(143, 4, Direction::SenderToReceiver);
+    let mut header = Vec::new();
+    header.extend_from_slice(&143u64.to_be_bytes());
+    header.extend_from_slice(&4u32.to_be_bytes());
+    header.extend_from_slice(&9u32.to_be_bytes());
+    header.extend_from_slice(&2u32.to_be_bytes());
+    let old_frame = |aad: Vec<u8>, payload| TransferChunkV2 {
+        protocol_version: 2,
+        encryption_flag: EncryptionFlag::Encrypted,
+        cipher_suite: CipherSuite::default(),
+        transfer_id: 143,
+        chunk_index: 4,
+        total_chunks: 9,
+        nonce,
+        aad,
+        payload,
+    };
+    let unbound = old_frame(
+        header.clone(),
+        crypto_envelope::encrypt_chunk(&key, nonce, &chunk.payload).expect("seal"),
+    );
+    assert_eq!(
+        decrypt_chunk_frame(&unbound, &key).expect("old frame"),
+        chunk
+    );
+    let mut with_digest = header;
+    with_digest.extend_from_slice(&sha2::Sha256::digest(&chunk.payload));
+    let bound = old_frame(
+        with_digest.clone(),
+        crypto_envelope::encrypt_chunk_with_aad(&key, nonce, &chunk.payload, &with_digest)
+            .expect("seal"),
+    );
+    assert_eq!(
+        decrypt_chunk_frame(&bound, &key).expect("old digest frame"),
+        chunk
+    );
+}
+
+#[test]
+fn chunks_are_sealed_under_a_key_scoped_to_their_transfer() {
+    let key = [0x5au8; 32];
+    let chunk = |transfer_id| TransferChunk {
+        transfer_id,
+        file_index: 0,
+        chunk_index: 0,
+        total_chunks: 1,
+        payload: b"same nonce counter, different transfer".to_vec(),
+    };
+    let frame = encrypt_chunk_frame(&chunk(7), &key).expect("frame");
+    let transfer_key = crypto_envelope::derive_transfer_key(&key, 7);
+    assert_eq!(
+        crypto_envelope::decrypt_chunk_with_aad(
+            transfer_key.expose(),
+            frame.nonce,
+            &frame.payload,
+            &frame.aad
+        )
+        .expect("open under the transfer key"),
+        chunk(7).payload
+    );
+    assert!(
+        crypto_envelope::decrypt_chunk_with_aad(&key, frame.nonce, &frame.payload, &frame.aad)
+            .is_err()
+    );
+
+    // Another transfer under the same session seals with an unrelated key.
+    assert_ne!(transfer_key, crypto_envelope::derive_transfer_key(&key, 8));
+    let v3 = encrypt_chunk_frame_v3(
+        &chunk(8),
+        &key,
+        Direction::SenderToReceiver,
+        CompressionCodec::None,
+        [],
+    )
+    .expect("v3");
+    assert_eq!(
+        decrypt_chunk_frame_v3(
+            &v3,
+            &key,
+            Direction::SenderToReceiver,
+            CipherSuite::ChaCha20Poly1305
+        )
+        .expect("v3 open"),
+        chunk(8)
+    );
+}
+
+#[test]
+fn padded_frames_hide_the_chunk_size_and_strip_on_decrypt() {
+    let key = [0x2eu8; 32];
+    let chunk = |len| TransferChunk {
+        transfer_id: 61,
+        file_index: 0,
+        chunk_index: 1,
+        total_chunks: 2,
+        payload: vec![0x41; len],
+    };
+    let seal = |len, padding| {
+        encrypt_chunk_frame_padded(
+            chunk(len),
+            &key,
+            Direction::SenderToReceiver,
+            CipherSuite::default(),
+            padding,
+        )
+        .expect("frame")
+    };
+
+    // Chunks of nearby sizes leave in frames of one size.
+    let (short, long) = (
+        seal(700, PaddingScheme::Bucket),
+        seal(900, PaddingScheme::Bucket),
+    );
+    assert_eq!(short.payload.len(), 1024 + crypto_envelope::TAG_LEN);
+    assert_eq!(short.payload.len(), long.payload.len());
+    let aad = ChunkAad::parse(&short.aad).expect("aad");
+    assert!(aad.padded);
+    // The cleartext AAD must not carry a hash that identifies the content.
+    assert_eq!(aad.chunk_digest, None);
+    assert_eq!(decrypt_chunk_frame(&short, &key).expect("open"), chunk(700));
+
+    let padme = seal(5000, PaddingScheme::Padme);
+    assert_eq!(
+        padme.payload.len(),
+        PaddingScheme::Padme.padded_len(5000) + crypto_envelope::TAG_LEN
+    );
+    assert_eq!(
+        decrypt_chunk_frame(&padme, &key).expect("open"),
+        chunk(5000)
+    );
+    let plain = seal(5000, PaddingScheme::None);
+    assert!(!ChunkAad::parse(&plain.aad).expect("aad").padded);
+    assert_eq!(plain.payload.len(), 5000 + crypto_envelope::TAG_LEN);
+
+    // The padded flag is authenticated, so it cannot be cleared to leak the filler.
+    let mut unflagged = short;
+    unflagged.aad = AadBuilder::new(2, 61, 1, 2).build();
+    assert!(matches!(
+        decrypt_chunk_frame(&unflagged, &key),
+        Err(TransferError::Crypto(_))
+    ));
+}
+
+#[test]
+fn sealed_manifest_hides_metadata_and_binds_its_header() {
+    let key = [0x4du8; 32];
+    let manifest =
+        TransferManifest::for_payload(77, "quarterly-report.pdf", "application/pdf", &[9; 300], 64)
+            .expect("manifest");
+    let sealed = seal_manifest(&manifest, &key, CipherSuite::Aes256Gcm).expect("seal");
+    assert!(!sealed
+        .windows(b"quarterly".len())
+        .any(|window| window == b"quarterly"));
+    assert_eq!(open_manifest(&sealed, &key).expect("open"), manifest);
+
+    assert_eq!(
+        open_manifest(&sealed, &[0x4e; 32]),
+        Err(TransferError::Crypto("failed to decrypt manifest"))
+    );
+    // The transfer id picks the nonce and is authenticated, so it cannot be swapped.
+    let mut relabelled = sealed.clone();
+    relabelled[12] ^= 1;
+    assert!(matches!(
+        open_manifest(&relabelled, &key),
+        Err(TransferError::Crypto(_))
+    ));
+    assert_eq!(
+        open_manifest(&sealed[..10], &key),
+        Err(TransferError::InvalidFrame("bad sealed manifest header"))
+    );
+    // A chunk frame on the same stream is never taken for a sealed manifest.
+    let chunk = TransferChunk {
+        transfer_id: 77,
+        chunk_index: 0,
+        total_chunks: 1,
+        file_index: 0,
+        payload: vec![9; 64],
+    };
+    let frame = encrypt_chunk_frame(&chunk, &key).expect("encrypt").encode();
+    assert_eq!(
+        open_manifest(&frame, &key),
+        Err(TransferError::InvalidFrame("bad sealed manifest header"))
+    );
+}